@@ -13,7 +13,7 @@ const loadBalanceHistory = () => {
         queryStr += '&sim=1';
     }
 
-    const url = '/api/balance_history' + queryStr;
+    const url = 'api/balance_history' + queryStr;
 
     fetch(url)
         .then(response => response.json())
@@ -8,7 +8,7 @@ const loadCurrentBalances = () => {
         queryStr += '&sim=1';
     }
 
-    const url = '/api/balance_history' + queryStr;
+    const url = 'api/balance_history' + queryStr;
 
     fetch(url)
         .then(response => response.json())
@@ -41,7 +41,7 @@ const renderBalances = (json) => {
         // Hide 0 balance (and small balance under hide option is enabled)
         const balanceThreshold = hideSmallBalances ? 0.1 : 0;
         if (totalBalance > balanceThreshold) {
-            labels.push(balance['symbol'] + '(' + balance['name'] + ')');
+            labels.push(balance['symbol'] + '(' + balance['name'] + ') ' + balance['availableDisplay']);
             totalBalances.push(totalBalance);
             totalBalanceSum += totalBalance;
         }
@@ -14,6 +14,20 @@ use trade;
 --      |               |           |     |
 --      -----stamp-------------------------
 --
+-- myorder_state_change refers myorder and stamp
+--
+-- pending_approval refers market and stamp
+--
+-- recommendation refers market and stamp
+--
+-- currency_tag refers currency
+--
+-- sync_cursor refers market
+--
+-- scrape_run refers stamp
+--
+-- alert_state
+--
 -- next_id
 
 CREATE TABLE currency
@@ -22,13 +36,17 @@ CREATE TABLE currency
     -- currency unit, ex. BTC, ETH, ...
     symbol VARCHAR(8) NOT NULL,
     -- ex. Bitcoin, Ether, ...
-    name VARCHAR(32) NOT NULL
+    name VARCHAR(32) NOT NULL,
+    -- FALSE if delisted from the exchange
+    active BOOLEAN NOT NULL DEFAULT TRUE
 );
 
 CREATE TABLE stamp
 (
     stamp_id INTEGER NOT NULL PRIMARY KEY,
-    stamp TIMESTAMP NOT NULL
+    stamp TIMESTAMP NOT NULL,
+    -- scraper, import, replay, coincheck or other
+    source VARCHAR(16) NOT NULL DEFAULT 'scraper'
 );
 
 CREATE TABLE balance
@@ -36,8 +54,8 @@ CREATE TABLE balance
     balance_id INTEGER NOT NULL PRIMARY KEY,
     currency_id INTEGER NOT NULL,
     stamp_id INTEGER NOT NULL,
-    available FLOAT NOT NULL,
-    pending FLOAT NOT NULL,
+    available DOUBLE NOT NULL,
+    pending DOUBLE NOT NULL,
 
     FOREIGN KEY (currency_id) REFERENCES currency(currency_id) ON UPDATE CASCADE,
     FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
@@ -58,8 +76,10 @@ CREATE TABLE price
     price_id INTEGER NOT NULL PRIMARY KEY,
     market_id INTEGER NOT NULL,
     stamp_id INTEGER NOT NULL,
-    price FLOAT NOT NULL,
+    price DOUBLE NOT NULL,
 
+    -- a market has at most one price per stamp
+    UNIQUE price_market_stamp (market_id, stamp_id),
     FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE,
     FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
 );
@@ -70,8 +90,8 @@ CREATE TABLE orderbook
     market_id INTEGER NOT NULL,
     stamp_id INTEGER NOT NULL,
     side VARCHAR(4) NOT NULL,
-    price FLOAT NOT NULL,
-    volume FLOAT NOT NULL,
+    price DOUBLE NOT NULL,
+    volume DOUBLE NOT NULL,
 
     FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE,
     FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
@@ -85,18 +105,133 @@ CREATE TABLE myorder
     market_id INTEGER NOT NULL,
     created_stamp_id INTEGER NOT NULL,
     modified_stamp_id INTEGER NOT NULL,
-    price FLOAT NOT NULL,
-    base_quantity FLOAT NOT NULL,
-    quote_quantity FLOAT NOT NULL,
+    price DOUBLE NOT NULL,
+    base_quantity DOUBLE NOT NULL,
+    quote_quantity DOUBLE NOT NULL,
     order_type VARCHAR(16) NOT NULL,
     side VARCHAR(4) NOT NULL,
     state VARCHAR(16) NOT NULL,
 
+    UNIQUE (transaction_id),
     FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE,
     FOREIGN KEY (created_stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE,
     FOREIGN KEY (modified_stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
 );
 
+CREATE TABLE myorder_state_change
+(
+    myorder_id INTEGER NOT NULL,
+    old_state VARCHAR(16) NOT NULL,
+    new_state VARCHAR(16) NOT NULL,
+    -- stamp at which the change was found
+    stamp_id INTEGER NOT NULL,
+
+    -- state never goes back, so an order enters each state at most once
+    PRIMARY KEY (myorder_id, new_state),
+    FOREIGN KEY (myorder_id) REFERENCES myorder(myorder_id) ON UPDATE CASCADE,
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE pending_approval
+(
+    pending_approval_id INTEGER NOT NULL PRIMARY KEY,
+    market_id INTEGER NOT NULL,
+    -- stamp at which the order was recommended
+    stamp_id INTEGER NOT NULL,
+    -- the order is not executed after this time
+    expiry TIMESTAMP NOT NULL,
+    price DOUBLE NOT NULL,
+    base_quantity DOUBLE NOT NULL,
+    quote_quantity DOUBLE NOT NULL,
+    -- change of quote balance when filled, including fee
+    expected_net_quote DOUBLE NOT NULL,
+    order_type VARCHAR(16) NOT NULL,
+    side VARCHAR(4) NOT NULL,
+    -- reasons of the recommendation
+    recommendation VARCHAR(1024) NOT NULL,
+    state VARCHAR(16) NOT NULL,
+    -- market price when approved, used to check price drift before execution
+    approved_price DOUBLE,
+
+    FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE,
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE recommendation
+(
+    recommendation_id INTEGER NOT NULL PRIMARY KEY,
+    stamp_id INTEGER NOT NULL,
+    market_id INTEGER NOT NULL,
+    -- rule which made the recommendation. NULL for the aggregated outcome of rules
+    rule_name VARCHAR(64),
+    recommendation_type VARCHAR(16) NOT NULL,
+    -- score of the rule, or weighted mean of scores for the aggregated outcome. NULL if undefined
+    score DOUBLE,
+    reason TEXT NOT NULL,
+
+    INDEX recommendation_market_stamp (market_id, stamp_id),
+    FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE,
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE currency_tag
+(
+    currency_id INTEGER NOT NULL,
+    -- category for reporting, ex. stablecoin, major, ...
+    tag VARCHAR(32) NOT NULL,
+
+    PRIMARY KEY (currency_id, tag),
+    FOREIGN KEY (currency_id) REFERENCES currency(currency_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE sync_cursor
+(
+    market_id INTEGER NOT NULL,
+    -- kind of synced data, ex. myorder
+    kind VARCHAR(16) NOT NULL,
+    -- data created before this are persisted
+    synced_until TIMESTAMP NOT NULL,
+
+    PRIMARY KEY (market_id, kind),
+    FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE scrape_run
+(
+    stamp_id INTEGER NOT NULL PRIMARY KEY,
+    started_at TIMESTAMP NOT NULL,
+    finished_at TIMESTAMP NOT NULL,
+    -- FALSE if the section was disabled, failed, or fell short of STAGE_SUCCESS_THRESHOLD
+    currency_fetched BOOLEAN NOT NULL,
+    -- fetched rows, including those skipped on saving
+    currency_rows INTEGER NOT NULL,
+    -- failed fetches
+    currency_errors INTEGER NOT NULL,
+    balance_fetched BOOLEAN NOT NULL,
+    balance_rows INTEGER NOT NULL,
+    balance_errors INTEGER NOT NULL,
+    price_fetched BOOLEAN NOT NULL,
+    price_rows INTEGER NOT NULL,
+    price_errors INTEGER NOT NULL,
+    orderbook_fetched BOOLEAN NOT NULL,
+    orderbook_rows INTEGER NOT NULL,
+    orderbook_errors INTEGER NOT NULL,
+    myorder_fetched BOOLEAN NOT NULL,
+    myorder_rows INTEGER NOT NULL,
+    myorder_errors INTEGER NOT NULL,
+
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE alert_state
+(
+    -- name of the alert rule
+    alert_name VARCHAR(64) NOT NULL PRIMARY KEY,
+    -- TRUE while the condition holds
+    firing BOOLEAN NOT NULL,
+    last_notified TIMESTAMP NULL
+);
+
 CREATE TABLE next_id
 (
     currency INTEGER NOT NULL,
@@ -105,11 +240,13 @@ CREATE TABLE next_id
     market INTEGER NOT NULL,
     price INTEGER NOT NULL,
     orderbook INTEGER NOT NULL,
-    myorder INTEGER NOT NULL
+    myorder INTEGER NOT NULL,
+    pending_approval INTEGER NOT NULL,
+    recommendation INTEGER NOT NULL
 );
 
 -- First ids
-INSERT INTO next_id VALUES (0, 0, 0, 0, 0, 0, 0);
+INSERT INTO next_id VALUES (0, 0, 0, 0, 0, 0, 0, 0, 0);
 
 DROP USER IF EXISTS autotrader;
 CREATE USER IF NOT EXISTS autotrader IDENTIFIED BY 'autotrader';
@@ -0,0 +1,11 @@
+-- Apply to databases created before transaction_id became unique.
+-- Remove duplicated transactions beforehand, keeping the oldest row.
+use trade;
+
+DELETE m1
+FROM myorder m1
+INNER JOIN myorder m2
+    ON m1.transaction_id = m2.transaction_id
+    AND m1.myorder_id > m2.myorder_id;
+
+ALTER TABLE myorder ADD UNIQUE (transaction_id);
@@ -0,0 +1,24 @@
+-- Apply to databases created before orders required approval.
+use trade;
+
+CREATE TABLE pending_approval
+(
+    pending_approval_id INTEGER NOT NULL PRIMARY KEY,
+    market_id INTEGER NOT NULL,
+    stamp_id INTEGER NOT NULL,
+    expiry TIMESTAMP NOT NULL,
+    price FLOAT NOT NULL,
+    base_quantity FLOAT NOT NULL,
+    quote_quantity FLOAT NOT NULL,
+    expected_net_quote FLOAT NOT NULL,
+    order_type VARCHAR(16) NOT NULL,
+    side VARCHAR(4) NOT NULL,
+    recommendation VARCHAR(1024) NOT NULL,
+    state VARCHAR(16) NOT NULL,
+    approved_price FLOAT,
+
+    FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE,
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+ALTER TABLE next_id ADD pending_approval INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- Apply to databases created before delisted currencies were tracked.
+use trade;
+
+ALTER TABLE currency ADD active BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,9 @@
+-- Apply to databases created before portfolio alerts.
+use trade;
+
+CREATE TABLE alert_state
+(
+    alert_name VARCHAR(64) NOT NULL PRIMARY KEY,
+    firing BOOLEAN NOT NULL,
+    last_notified TIMESTAMP NULL
+);
@@ -0,0 +1,4 @@
+-- Apply to databases created before stamps recorded their source.
+use trade;
+
+ALTER TABLE stamp ADD source VARCHAR(16) NOT NULL DEFAULT 'scraper';
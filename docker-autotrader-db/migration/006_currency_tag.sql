@@ -0,0 +1,11 @@
+-- Apply to databases created before currency tags.
+use trade;
+
+CREATE TABLE currency_tag
+(
+    currency_id INTEGER NOT NULL,
+    tag VARCHAR(32) NOT NULL,
+
+    PRIMARY KEY (currency_id, tag),
+    FOREIGN KEY (currency_id) REFERENCES currency(currency_id) ON UPDATE CASCADE
+);
@@ -0,0 +1,12 @@
+-- Apply to databases created before resumable myorder sync.
+use trade;
+
+CREATE TABLE sync_cursor
+(
+    market_id INTEGER NOT NULL,
+    kind VARCHAR(16) NOT NULL,
+    synced_until TIMESTAMP NOT NULL,
+
+    PRIMARY KEY (market_id, kind),
+    FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE
+);
@@ -0,0 +1,19 @@
+-- Apply to simulation databases created before stop orders.
+use sim;
+
+CREATE TABLE stop_order
+(
+    stop_order_id INTEGER NOT NULL PRIMARY KEY,
+    market_id INTEGER NOT NULL,
+    side VARCHAR(4) NOT NULL,
+    order_type VARCHAR(16) NOT NULL,
+    base_quantity FLOAT NOT NULL,
+    quote_quantity FLOAT NOT NULL,
+    -- limit price, or expected fill price of stop market orders
+    price FLOAT NOT NULL,
+    trigger_price FLOAT NOT NULL,
+    -- change of quote balance when filled, including fee
+    expected_net_quote FLOAT NOT NULL,
+    -- the order is not triggered after this time
+    expiry TIMESTAMP NOT NULL
+);
@@ -0,0 +1,37 @@
+-- Apply to simulation databases created before simulated orders were saved.
+use sim;
+
+-- orders placed by the simulation
+CREATE TABLE myorder
+(
+    myorder_id INTEGER NOT NULL PRIMARY KEY,
+    -- sim-<stamp_id>-<n>
+    transaction_id VARCHAR(64) NOT NULL,
+    market_id INTEGER NOT NULL,
+    created_stamp_id INTEGER NOT NULL,
+    modified_stamp_id INTEGER NOT NULL,
+    price FLOAT NOT NULL,
+    base_quantity FLOAT NOT NULL,
+    quote_quantity FLOAT NOT NULL,
+    order_type VARCHAR(16) NOT NULL,
+    side VARCHAR(4) NOT NULL,
+    state VARCHAR(16) NOT NULL,
+
+    UNIQUE (transaction_id)
+);
+
+-- only myorder is used in simulation
+CREATE TABLE next_id
+(
+    currency INTEGER NOT NULL,
+    stamp INTEGER NOT NULL,
+    balance INTEGER NOT NULL,
+    market INTEGER NOT NULL,
+    price INTEGER NOT NULL,
+    orderbook INTEGER NOT NULL,
+    myorder INTEGER NOT NULL,
+    pending_approval INTEGER NOT NULL
+);
+
+-- First ids
+INSERT INTO next_id VALUES (0, 0, 0, 0, 0, 0, 0, 0);
@@ -0,0 +1,15 @@
+-- Apply to simulation databases created before balance ids were allocated by next_id.
+use sim;
+
+-- lock preventing speculator runs from overlapping. holder is NULL while released
+CREATE TABLE speculator_lock
+(
+    lock_id INTEGER NOT NULL PRIMARY KEY,
+    holder VARCHAR(64) NULL,
+    acquired TIMESTAMP NULL
+);
+
+INSERT INTO speculator_lock VALUES (0, NULL, NULL);
+
+-- continue after balances added by max(balance_id) + 1
+UPDATE next_id SET balance = (SELECT COALESCE(MAX(balance_id), -1) + 1 FROM balance);
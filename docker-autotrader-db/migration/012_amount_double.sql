@@ -0,0 +1,43 @@
+-- Apply to databases created before amounts were DOUBLE. FLOAT loses satoshi-scale amounts.
+use trade;
+
+ALTER TABLE balance
+    MODIFY available DOUBLE NOT NULL,
+    MODIFY pending DOUBLE NOT NULL;
+
+ALTER TABLE price
+    MODIFY price DOUBLE NOT NULL;
+
+ALTER TABLE orderbook
+    MODIFY price DOUBLE NOT NULL,
+    MODIFY volume DOUBLE NOT NULL;
+
+ALTER TABLE myorder
+    MODIFY price DOUBLE NOT NULL,
+    MODIFY base_quantity DOUBLE NOT NULL,
+    MODIFY quote_quantity DOUBLE NOT NULL;
+
+ALTER TABLE pending_approval
+    MODIFY price DOUBLE NOT NULL,
+    MODIFY base_quantity DOUBLE NOT NULL,
+    MODIFY quote_quantity DOUBLE NOT NULL,
+    MODIFY expected_net_quote DOUBLE NOT NULL,
+    MODIFY approved_price DOUBLE;
+
+use sim;
+
+ALTER TABLE balance
+    MODIFY available DOUBLE NOT NULL,
+    MODIFY pending DOUBLE NOT NULL;
+
+ALTER TABLE stop_order
+    MODIFY base_quantity DOUBLE NOT NULL,
+    MODIFY quote_quantity DOUBLE NOT NULL,
+    MODIFY price DOUBLE NOT NULL,
+    MODIFY trigger_price DOUBLE NOT NULL,
+    MODIFY expected_net_quote DOUBLE NOT NULL;
+
+ALTER TABLE myorder
+    MODIFY price DOUBLE NOT NULL,
+    MODIFY base_quantity DOUBLE NOT NULL,
+    MODIFY quote_quantity DOUBLE NOT NULL;
@@ -0,0 +1,12 @@
+-- Apply to databases created before a market had at most one price per stamp.
+-- Remove duplicated prices beforehand, keeping the oldest row.
+use trade;
+
+DELETE p1
+FROM price p1
+INNER JOIN price p2
+    ON p1.market_id = p2.market_id
+    AND p1.stamp_id = p2.stamp_id
+    AND p1.price_id > p2.price_id;
+
+ALTER TABLE price ADD UNIQUE price_market_stamp (market_id, stamp_id);
@@ -0,0 +1,21 @@
+-- Apply to databases created before recommendations were persisted.
+use trade;
+
+CREATE TABLE recommendation
+(
+    recommendation_id INTEGER NOT NULL PRIMARY KEY,
+    stamp_id INTEGER NOT NULL,
+    market_id INTEGER NOT NULL,
+    -- rule which made the recommendation. NULL for the aggregated outcome of rules
+    rule_name VARCHAR(64),
+    recommendation_type VARCHAR(16) NOT NULL,
+    -- score of the rule, or weighted mean of scores for the aggregated outcome. NULL if undefined
+    score DOUBLE,
+    reason TEXT NOT NULL,
+
+    INDEX recommendation_market_stamp (market_id, stamp_id),
+    FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE,
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+ALTER TABLE next_id ADD recommendation INTEGER NOT NULL DEFAULT 0;
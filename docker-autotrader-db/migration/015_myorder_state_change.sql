@@ -0,0 +1,30 @@
+-- Apply to databases created before state changes of orders were recorded.
+use trade;
+
+CREATE TABLE myorder_state_change
+(
+    myorder_id INTEGER NOT NULL,
+    old_state VARCHAR(16) NOT NULL,
+    new_state VARCHAR(16) NOT NULL,
+    -- stamp at which the change was found
+    stamp_id INTEGER NOT NULL,
+
+    -- state never goes back, so an order enters each state at most once
+    PRIMARY KEY (myorder_id, new_state),
+    FOREIGN KEY (myorder_id) REFERENCES myorder(myorder_id) ON UPDATE CASCADE,
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+use sim;
+
+-- state changes of orders placed by the simulation. stamp_id refers stamps of trade DB
+CREATE TABLE myorder_state_change
+(
+    myorder_id INTEGER NOT NULL,
+    old_state VARCHAR(16) NOT NULL,
+    new_state VARCHAR(16) NOT NULL,
+    stamp_id INTEGER NOT NULL,
+
+    PRIMARY KEY (myorder_id, new_state),
+    FOREIGN KEY (myorder_id) REFERENCES myorder(myorder_id) ON UPDATE CASCADE
+);
@@ -0,0 +1,131 @@
+use database::model::Amount;
+use speculator::evaluation::forward_return;
+
+/// Message if `total_value` is below `threshold`
+pub fn portfolio_below(fiat: &str, total_value: f64, threshold: f64) -> Option<String> {
+    if total_value < threshold {
+        Some(format!(
+            "Portfolio value {:.2} {} is below {:.2} {}",
+            total_value, fiat, threshold, fiat
+        ))
+    } else {
+        None
+    }
+}
+
+/// Message if price moves from `old_price` to `latest_price` more than `threshold_percent` in either direction.
+/// No message if `old_price` is not positive, since the change is undefined
+pub fn price_change(
+    market: &str,
+    old_price: Amount,
+    latest_price: Amount,
+    window_min: i64,
+    threshold_percent: f64,
+) -> Option<String> {
+    let change_percent = forward_return(old_price, latest_price)? * 100.0;
+    if change_percent.abs() > threshold_percent {
+        Some(format!(
+            "{} moved {:+.2}% in {} minutes ({} -> {})",
+            market, change_percent, window_min, old_price, latest_price
+        ))
+    } else {
+        None
+    }
+}
+
+/// Balance of a currency at two stamps, with its rate to fiat at the latest one
+#[derive(Debug, Clone, PartialEq)]
+pub struct BalanceChange {
+    pub symbol: String,
+    /// Sum of available and pending. 0 if the currency had no balance
+    pub previous: f64,
+    pub latest: f64,
+    /// `None` if the currency can't be valued in fiat
+    pub rate: Option<f64>,
+}
+
+/// Message listing currencies whose balance increased by more than `threshold` valued in `fiat`.
+/// Currencies without rate are ignored
+pub fn balance_increase(fiat: &str, changes: &[BalanceChange], threshold: f64) -> Option<String> {
+    let increases = changes
+        .iter()
+        .filter_map(|change| {
+            let value = (change.latest - change.previous) * change.rate?;
+            if value > threshold {
+                Some(format!(
+                    "{} +{} ({:.2} {})",
+                    change.symbol,
+                    change.latest - change.previous,
+                    value,
+                    fiat
+                ))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if increases.is_empty() {
+        None
+    } else {
+        Some(format!("Balance increased: {}", increases.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_portfolio_below() {
+        assert!(portfolio_below("USDT", 999.0, 1000.0).is_some());
+        assert!(portfolio_below("USDT", 1000.0, 1000.0).is_none());
+        assert_eq!(
+            Some("Portfolio value 500.00 USDT is below 1000.00 USDT".to_string()),
+            portfolio_below("USDT", 500.0, 1000.0)
+        );
+    }
+
+    #[test]
+    fn test_price_change() {
+        // Both directions
+        assert!(price_change("BTC-USDT", 100.0, 106.0, 60, 5.0).is_some());
+        assert!(price_change("BTC-USDT", 100.0, 94.0, 60, 5.0).is_some());
+
+        assert!(price_change("BTC-USDT", 100.0, 104.0, 60, 5.0).is_none());
+        assert!(price_change("BTC-USDT", 0.0, 104.0, 60, 5.0).is_none());
+    }
+
+    fn change(symbol: &str, previous: f64, latest: f64, rate: Option<f64>) -> BalanceChange {
+        BalanceChange {
+            symbol: symbol.into(),
+            previous,
+            latest,
+            rate,
+        }
+    }
+
+    #[test]
+    fn test_balance_increase() {
+        let changes = vec![
+            change("BTC", 0.0, 0.01, Some(30000.0)),
+            change("ETH", 1.0, 1.01, Some(2000.0)),
+            change("USDT", 100.0, 50.0, Some(1.0)),
+            change("XYZ", 0.0, 1e6, None),
+        ];
+
+        let message = balance_increase("USDT", &changes, 100.0).unwrap();
+
+        assert!(message.contains("BTC"));
+        assert!(!message.contains("ETH"));
+        assert!(!message.contains("USDT +"));
+        assert!(!message.contains("XYZ"));
+    }
+
+    #[test]
+    fn test_balance_increase_none() {
+        let changes = vec![change("USDT", 100.0, 150.0, Some(1.0))];
+
+        assert_eq!(None, balance_increase("USDT", &changes, 100.0));
+    }
+}
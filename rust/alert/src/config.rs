@@ -0,0 +1,167 @@
+use anyhow::{ensure, Result};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::io::Read;
+
+/// Default of `cooldownMin`
+const DEFAULT_COOLDOWN_MIN: i64 = 60;
+
+fn default_cooldown_min() -> i64 {
+    DEFAULT_COOLDOWN_MIN
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertConfig {
+    pub alerts: Vec<AlertRule>,
+}
+
+impl AlertConfig {
+    /// Deserialize JSON from `reader`, then validate names and thresholds of all alerts
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let config: Self = serde_json::from_reader(reader)?;
+
+        let mut names = HashSet::new();
+        for alert in config.alerts.iter() {
+            ensure!(
+                names.insert(alert.name.as_str()),
+                "Duplicated alert name: {}",
+                alert.name
+            );
+            ensure!(
+                !alert.name.is_empty() && alert.name.len() <= 64,
+                "Alert name must be 1 to 64 bytes: {}",
+                alert.name
+            );
+            ensure!(
+                alert.cooldown_min >= 0,
+                "Negative cooldown of alert {}",
+                alert.name
+            );
+            alert.condition.validate()?;
+        }
+
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertRule {
+    /// Unique name, which also identifies cooldown state in DB
+    pub name: String,
+    /// Minimum minutes between notifications while the condition keeps holding
+    #[serde(default = "default_cooldown_min")]
+    pub cooldown_min: i64,
+    pub condition: AlertCondition,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum AlertCondition {
+    /// Total value of balances in `fiat` falls below `threshold`
+    #[serde(rename_all = "camelCase")]
+    PortfolioBelow { fiat: String, threshold: f64 },
+    /// Price of `market` like `BTC-USDT` moves more than `threshold_percent` in either direction within `window_min`
+    #[serde(rename_all = "camelCase")]
+    PriceChange {
+        market: String,
+        window_min: i64,
+        threshold_percent: f64,
+    },
+    /// Balance of any currency increases by more than `threshold` valued in `fiat`
+    /// between the latest two balance stamps, e.g. by an unexpected deposit
+    #[serde(rename_all = "camelCase")]
+    BalanceIncrease { fiat: String, threshold: f64 },
+}
+
+impl AlertCondition {
+    fn validate(&self) -> Result<()> {
+        match self {
+            AlertCondition::PortfolioBelow { threshold, .. } => {
+                ensure!(*threshold > 0.0, "Portfolio threshold must be positive")
+            }
+            AlertCondition::PriceChange {
+                market,
+                window_min,
+                threshold_percent,
+            } => {
+                ensure!(market.split('-').count() == 2, "Invalid market: {}", market);
+                ensure!(*window_min > 0, "Price change window must be positive");
+                ensure!(
+                    *threshold_percent > 0.0,
+                    "Price change threshold must be positive"
+                );
+            }
+            AlertCondition::BalanceIncrease { threshold, .. } => {
+                ensure!(
+                    *threshold > 0.0,
+                    "Balance increase threshold must be positive"
+                )
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_reader() {
+        let json = r#"{
+            "alerts": [
+                {
+                    "name": "floor",
+                    "cooldownMin": 30,
+                    "condition": { "type": "portfolioBelow", "fiat": "USDT", "threshold": 1000.0 }
+                },
+                {
+                    "name": "btcMove",
+                    "condition": {
+                        "type": "priceChange",
+                        "market": "BTC-USDT",
+                        "windowMin": 60,
+                        "thresholdPercent": 5.0
+                    }
+                }
+            ]
+        }"#;
+
+        let config = AlertConfig::from_reader(json.as_bytes()).unwrap();
+
+        assert_eq!(2, config.alerts.len());
+        assert_eq!(30, config.alerts[0].cooldown_min);
+        assert_eq!(DEFAULT_COOLDOWN_MIN, config.alerts[1].cooldown_min);
+        assert_eq!(
+            AlertCondition::PriceChange {
+                market: "BTC-USDT".into(),
+                window_min: 60,
+                threshold_percent: 5.0
+            },
+            config.alerts[1].condition
+        );
+    }
+
+    #[test]
+    fn test_from_reader_invalid() {
+        let duplicated = r#"{
+            "alerts": [
+                { "name": "a", "condition": { "type": "balanceIncrease", "fiat": "USDT", "threshold": 1.0 } },
+                { "name": "a", "condition": { "type": "balanceIncrease", "fiat": "USDT", "threshold": 1.0 } }
+            ]
+        }"#;
+        assert!(AlertConfig::from_reader(duplicated.as_bytes()).is_err());
+
+        let invalid_market = r#"{
+            "alerts": [
+                {
+                    "name": "a",
+                    "condition": { "type": "priceChange", "market": "BTCUSDT", "windowMin": 60, "thresholdPercent": 5.0 }
+                }
+            ]
+        }"#;
+        assert!(AlertConfig::from_reader(invalid_market.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use chrono::{Duration, NaiveDateTime};
+use database::model::AlertState;
+
+/// Decide whether to notify an alert, and its next state.
+///
+/// An alert notifies when its condition starts holding, then again every `cooldown` while it keeps holding.
+/// Once the condition clears, the alert is re-armed and notifies immediately when it holds next time.
+/// # Returns
+/// (whether to notify, next state). The state should be saved only after the notification succeeds,
+/// so that failed notifications are retried
+pub fn next_state(
+    alert_name: &str,
+    state: Option<&AlertState>,
+    triggered: bool,
+    now: NaiveDateTime,
+    cooldown: Duration,
+) -> (bool, AlertState) {
+    let last_notified = state.and_then(|state| state.last_notified);
+
+    if !triggered {
+        let state = AlertState {
+            alert_name: alert_name.to_string(),
+            firing: false,
+            last_notified,
+        };
+        return (false, state);
+    }
+
+    let notify = match state {
+        Some(state) if state.firing => match state.last_notified {
+            Some(last_notified) => now - last_notified >= cooldown,
+            None => true,
+        },
+        _ => true,
+    };
+
+    let state = AlertState {
+        alert_name: alert_name.to_string(),
+        firing: true,
+        last_notified: if notify { Some(now) } else { last_notified },
+    };
+    (notify, state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn minute(minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2021, 1, 1).and_hms(0, minute, 0)
+    }
+
+    /// Run evaluations at each (minute, triggered), then return minutes of notifications
+    fn notified_minutes(evaluations: &[(u32, bool)], cooldown: Duration) -> Vec<u32> {
+        let mut state = None;
+        let mut notified = vec![];
+        for &(m, triggered) in evaluations.iter() {
+            let (notify, next) =
+                next_state("alert", state.as_ref(), triggered, minute(m), cooldown);
+            if notify {
+                notified.push(m);
+            }
+            state = Some(next);
+        }
+        notified
+    }
+
+    #[test]
+    fn test_next_state_cooldown() {
+        let evaluations = [(0, true), (5, true), (10, true), (15, true), (20, true)];
+
+        // Reminded every 10 minutes while the condition holds
+        assert_eq!(
+            vec![0, 10, 20],
+            notified_minutes(&evaluations, Duration::minutes(10))
+        );
+    }
+
+    #[test]
+    fn test_next_state_rearm() {
+        let evaluations = [(0, true), (5, false), (6, true), (7, true), (8, false)];
+
+        // Cleared condition re-arms the alert within the cooldown
+        assert_eq!(
+            vec![0, 6],
+            notified_minutes(&evaluations, Duration::minutes(10))
+        );
+    }
+
+    #[test]
+    fn test_next_state_cleared() {
+        let (notify, state) = next_state("alert", None, false, minute(0), Duration::minutes(10));
+        assert!(!notify);
+        assert!(!state.firing);
+        assert_eq!(None, state.last_notified);
+
+        let firing = AlertState {
+            alert_name: "alert".into(),
+            firing: true,
+            last_notified: Some(minute(0)),
+        };
+        let (notify, state) = next_state(
+            "alert",
+            Some(&firing),
+            false,
+            minute(1),
+            Duration::minutes(10),
+        );
+        assert!(!notify);
+        assert!(!state.firing);
+        // The last notification is kept
+        assert_eq!(Some(minute(0)), state.last_notified);
+    }
+
+    #[test]
+    fn test_next_state_failed_notification_is_retried() {
+        let (notify, _) = next_state("alert", None, true, minute(0), Duration::minutes(10));
+        assert!(notify);
+
+        // The state is not saved because the notification failed, so the next run notifies again
+        let (notify, state) = next_state("alert", None, true, minute(1), Duration::minutes(10));
+        assert!(notify);
+        assert_eq!(Some(minute(1)), state.last_notified);
+    }
+}
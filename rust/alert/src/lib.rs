@@ -0,0 +1,237 @@
+use anyhow::{anyhow, Result};
+use apply::Apply;
+use chrono::{Duration, NaiveDateTime};
+use config::{AlertCondition, AlertConfig, AlertRule};
+use database::exchange::{ExchangeGraph, FromPrices};
+use database::logic::*;
+use database::model::*;
+use database::schema;
+use diesel::prelude::*;
+use itertools::Itertools;
+use notifier::Notifier;
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+#[macro_use]
+extern crate log;
+
+pub mod condition;
+mod config;
+mod cooldown;
+mod notifier;
+
+/// Evaluate alerts of `ALERT_JSON` on the latest stamp, then notify triggered ones.
+/// Intended to run periodically, e.g. by cron after the scraper
+pub fn run() -> Result<()> {
+    info!(
+        "Alert started at {} with {}",
+        chrono::Local::now(),
+        common::build_info()
+    );
+
+    let ret = batch();
+
+    info!("Alert finished at {}", chrono::Local::now());
+
+    ret
+}
+
+fn batch() -> Result<()> {
+    let config = env::var("ALERT_JSON")?
+        .apply(File::open)?
+        .apply(AlertConfig::from_reader)?;
+    let url = env::var("DATABASE_URL")?;
+    let conn = Conn::establish(&url)?;
+    let notifier = notifier::notifier_from_env();
+
+    let now = chrono::Utc::now().naive_utc();
+
+    for rule in config.alerts.iter() {
+        // An alert failing to evaluate must not prevent the others
+        if let Err(e) = process_alert(&conn, notifier.as_ref(), rule, now) {
+            error!("Failed to process alert {}: {}", rule.name, e);
+        }
+    }
+
+    Ok(())
+}
+
+fn process_alert(
+    conn: &Conn,
+    notifier: &dyn Notifier,
+    rule: &AlertRule,
+    now: NaiveDateTime,
+) -> Result<()> {
+    let message = evaluate_condition(conn, &rule.condition)?;
+    let state = get_alert_state(conn, &rule.name)?;
+
+    let (notify, next_state) = cooldown::next_state(
+        &rule.name,
+        state.as_ref(),
+        message.is_some(),
+        now,
+        Duration::minutes(rule.cooldown_min),
+    );
+
+    if let (true, Some(message)) = (notify, message) {
+        notifier.notify(&rule.name, &message)?;
+        info!("Notified alert {}", rule.name);
+    } else if state.as_ref() == Some(&next_state) {
+        return Ok(());
+    }
+
+    // Saved after the notification, so that a failed notification is retried on the next run
+    save_alert_state(conn, &next_state)
+}
+
+fn evaluate_condition(conn: &Conn, condition: &AlertCondition) -> Result<Option<String>> {
+    let currencies = list_currencies(conn)?;
+
+    match condition {
+        AlertCondition::PortfolioBelow { fiat, threshold } => {
+            let fiat_id = currency_id_of(&currencies, fiat)?;
+            let stamps = latest_balance_stamps(conn, 1)?;
+            let stamp = stamps.first().ok_or(anyhow!("No balance"))?;
+            let graph = construct_exchange_graph(conn, stamp.stamp_id)?;
+
+            let total = load_balances(conn, stamp.stamp_id)?
+                .into_iter()
+                .filter_map(|(currency_id, amount)| {
+                    let rate = graph.rate_between(currency_id, fiat_id)?;
+                    Some(amount * rate)
+                })
+                .sum::<f64>();
+
+            Ok(condition::portfolio_below(fiat, total, *threshold))
+        }
+        AlertCondition::PriceChange {
+            market,
+            window_min,
+            threshold_percent,
+        } => {
+            let (base, quote) = market
+                .split('-')
+                .collect_tuple()
+                .ok_or(anyhow!("Invalid market: {}", market))?;
+            let base_id = currency_id_of(&currencies, base)?;
+            let quote_id = currency_id_of(&currencies, quote)?;
+            let market_id = list_markets(conn)?
+                .by_base_quote_id(base_id, quote_id)
+                .map(|m| m.market_id)
+                .ok_or(anyhow!("No market {}", market))?;
+
+            let latest = match latest_price(conn, market_id, None)? {
+                Some(latest) => latest,
+                None => return Ok(None),
+            };
+            let window_begin = latest.0 - Duration::minutes(*window_min);
+            let old = match latest_price(conn, market_id, Some(window_begin))? {
+                Some(old) => old,
+                None => return Ok(None),
+            };
+
+            Ok(condition::price_change(
+                market,
+                old.1,
+                latest.1,
+                *window_min,
+                *threshold_percent,
+            ))
+        }
+        AlertCondition::BalanceIncrease { fiat, threshold } => {
+            let fiat_id = currency_id_of(&currencies, fiat)?;
+            let stamps = latest_balance_stamps(conn, 2)?;
+            let (latest, previous) = match stamps.as_slice() {
+                [latest, previous] => (latest, previous),
+                _ => return Ok(None),
+            };
+            let graph = construct_exchange_graph(conn, latest.stamp_id)?;
+
+            let latest_balances = load_balances(conn, latest.stamp_id)?;
+            let previous_balances = load_balances(conn, previous.stamp_id)?;
+
+            let changes = latest_balances
+                .keys()
+                .chain(previous_balances.keys())
+                .unique()
+                .filter_map(|&currency_id| {
+                    let symbol = currencies.by_id(currency_id)?.symbol.clone();
+                    Some(condition::BalanceChange {
+                        symbol,
+                        previous: previous_balances.get(&currency_id).copied().unwrap_or(0.0),
+                        latest: latest_balances.get(&currency_id).copied().unwrap_or(0.0),
+                        rate: graph.rate_between(currency_id, fiat_id),
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            Ok(condition::balance_increase(fiat, &changes, *threshold))
+        }
+    }
+}
+
+fn currency_id_of(currencies: &CurrencyCollection, symbol: &str) -> Result<CurrencyId> {
+    currencies
+        .by_symbol(symbol)
+        .map(|c| c.currency_id)
+        .ok_or(anyhow!("Unknown currency {}", symbol))
+}
+
+/// The latest `count` stamps having balances, in descending order of timestamp
+fn latest_balance_stamps(conn: &Conn, count: i64) -> Result<Vec<Stamp>> {
+    use schema::*;
+
+    stamp::table
+        .filter(stamp::stamp_id.eq_any(balance::table.select(balance::stamp_id).distinct()))
+        .order(stamp::timestamp.desc())
+        .limit(count)
+        .load::<Stamp>(conn)?
+        .apply(Ok)
+}
+
+/// Sum of available and pending of each currency at `stamp_id`
+fn load_balances(conn: &Conn, stamp_id: StampId) -> Result<HashMap<CurrencyId, f64>> {
+    schema::balance::table
+        .filter(schema::balance::stamp_id.eq(stamp_id))
+        .load::<Balance>(conn)?
+        .into_iter()
+        .map(|b| (b.currency_id, b.available + b.pending))
+        .collect::<HashMap<_, _>>()
+        .apply(Ok)
+}
+
+/// The latest price of `market_id` at or before `until`, or the latest one at all if `until` is `None`
+fn latest_price(
+    conn: &Conn,
+    market_id: MarketId,
+    until: Option<NaiveDateTime>,
+) -> Result<Option<(NaiveDateTime, Amount)>> {
+    use schema::*;
+
+    let query = price::table
+        .inner_join(stamp::table.on(price::stamp_id.eq(stamp::stamp_id)))
+        .filter(price::market_id.eq(market_id))
+        .select((stamp::timestamp, price::amount))
+        .order(stamp::timestamp.desc())
+        .into_boxed();
+    let query = match until {
+        Some(until) => query.filter(stamp::timestamp.le(until)),
+        None => query,
+    };
+
+    query
+        .first::<(NaiveDateTime, Amount)>(conn)
+        .optional()?
+        .apply(Ok)
+}
+
+fn construct_exchange_graph(conn: &Conn, stamp_id: StampId) -> Result<ExchangeGraph<CurrencyId>> {
+    use schema::*;
+
+    let prices = price::table
+        .inner_join(market::table.on(price::market_id.eq(market::market_id)))
+        .filter(price::stamp_id.eq(stamp_id))
+        .load::<(Price, Market)>(conn)?;
+
+    Ok(ExchangeGraph::from_prices(&prices))
+}
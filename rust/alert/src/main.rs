@@ -0,0 +1,12 @@
+#[macro_use]
+extern crate log;
+
+fn main() {
+    dotenv::dotenv().ok();
+
+    env_logger::init();
+
+    if let Err(e) = alert::run() {
+        error!("{}", e);
+    }
+}
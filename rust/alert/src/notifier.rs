@@ -0,0 +1,60 @@
+use anyhow::{ensure, Result};
+use reqwest::blocking::Client;
+use reqwest::header::CONTENT_TYPE;
+use std::env;
+
+/// Destination of alert notifications
+pub trait Notifier {
+    fn notify(&self, title: &str, message: &str) -> Result<()>;
+}
+
+/// Write notifications to the log
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn notify(&self, title: &str, message: &str) -> Result<()> {
+        warn!("[{}] {}", title, message);
+        Ok(())
+    }
+}
+
+/// POST notifications to a webhook as JSON like `{"title": "...", "message": "..."}`
+pub struct WebhookNotifier {
+    client: Client,
+    url: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            client: Client::new(),
+            url: url.into(),
+        }
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(&self, title: &str, message: &str) -> Result<()> {
+        let body = serde_json::json!({ "title": title, "message": message }).to_string();
+        let response = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()?;
+        ensure!(
+            response.status().is_success(),
+            "Webhook responded {}",
+            response.status()
+        );
+        Ok(())
+    }
+}
+
+/// Webhook notifier if `ALERT_WEBHOOK_URL` is set, otherwise log notifier
+pub fn notifier_from_env() -> Box<dyn Notifier> {
+    match env::var("ALERT_WEBHOOK_URL") {
+        Ok(url) if !url.is_empty() => Box::new(WebhookNotifier::new(url)),
+        _ => Box::new(LogNotifier),
+    }
+}
@@ -0,0 +1,83 @@
+/// Totals at or below this are regarded as an empty portfolio, whose shares are undefined
+pub const MIN_TOTAL_VALUE: f64 = 1e-9;
+
+/// Share of each of `values` in their total, in the same order as `values`.
+/// Values must be in the same currency.
+/// # Returns
+/// `None` if the total is not above `MIN_TOTAL_VALUE`
+pub fn shares<T>(values: &[(T, f64)]) -> Option<Vec<f64>> {
+    let total = values.iter().map(|(_, value)| value).sum::<f64>();
+    if total <= MIN_TOTAL_VALUE {
+        return None;
+    }
+
+    values
+        .iter()
+        .map(|(_, value)| value / total)
+        .collect::<Vec<_>>()
+        .into()
+}
+
+/// Share of `item` in `values`. 0 if `item` is not in `values`
+/// # Returns
+/// `None` if the total is not above `MIN_TOTAL_VALUE`
+pub fn share_of<T: PartialEq>(values: &[(T, f64)], item: &T) -> Option<f64> {
+    let shares = shares(values)?;
+    values
+        .iter()
+        .zip(shares)
+        .filter(|((t, _), _)| t == item)
+        .map(|(_, share)| share)
+        .sum::<f64>()
+        .into()
+}
+
+/// Largest value of an item which can be bought,
+/// so that its share stays within `cap` after exchanging other items of the same total value for it.
+/// 0 if the share already reaches `cap`
+pub fn max_buy_value(item_value: f64, total_value: f64, cap: f64) -> f64 {
+    (cap * total_value - item_value).max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_shares() {
+        let values = vec![("BTC", 60.0), ("ETH", 30.0), ("USDT", 10.0)];
+
+        let shares = shares(&values).unwrap();
+
+        assert_eq!(3, shares.len());
+        assert_approx_eq!(0.6, shares[0]);
+        assert_approx_eq!(0.3, shares[1]);
+        assert_approx_eq!(0.1, shares[2]);
+    }
+
+    #[test]
+    fn test_shares_empty_portfolio() {
+        assert_eq!(None, shares::<&str>(&[]));
+        assert_eq!(None, shares(&[("BTC", 0.0)]));
+        assert_eq!(None, shares(&[("BTC", 1e-12), ("USDT", 1e-12)]));
+    }
+
+    #[test]
+    fn test_share_of() {
+        let values = vec![("BTC", 5.0), ("USDT", 95.0)];
+
+        assert_approx_eq!(0.05, share_of(&values, &"BTC").unwrap());
+        assert_eq!(Some(0.0), share_of(&values, &"DOGE"));
+        assert_eq!(None, share_of(&[("BTC", 0.0)], &"BTC"));
+    }
+
+    #[test]
+    fn test_max_buy_value() {
+        // 5% of 1000 is 50, and 20 is held already
+        assert_approx_eq!(30.0, max_buy_value(20.0, 1000.0, 0.05));
+        assert_eq!(0.0, max_buy_value(50.0, 1000.0, 0.05));
+        assert_eq!(0.0, max_buy_value(80.0, 1000.0, 0.05));
+        assert_eq!(0.0, max_buy_value(0.0, 0.0, 0.05));
+    }
+}
@@ -0,0 +1,463 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::sync::RwLock;
+
+/// Cycle in exchange graph whose rate product is not 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct CycleReport<T> {
+    /// Currencies along the cycle. The first currency is also the last one implicitly
+    pub currencies: Vec<T>,
+    /// Product of exchange rates along the cycle
+    pub rate_product: f64,
+}
+
+/// Exchange rates between currencies.
+///
+/// Rates between currencies without direct market are derived through the fewest intermediate currencies,
+/// so that a cross rate doesn't depend on a long chain of markets.
+/// Derived rates are memoized behind `RwLock`,
+/// so the graph can be shared among threads (e.g. `Arc<ExchangeGraph<T>>`).
+pub struct ExchangeGraph<T> {
+    rates: HashMap<(T, T), f64>,
+    direct_relations: HashMap<T, Vec<T>>,
+    derived_rates: RwLock<HashMap<(T, T), Option<f64>>>,
+}
+
+impl<T> ExchangeGraph<T> {
+    pub fn from_rates(rates: impl IntoIterator<Item = (T, T, f64)>) -> Self
+    where
+        T: Copy + Eq + Hash,
+    {
+        let mut rate_map = HashMap::new();
+        let mut direct_relations = HashMap::new();
+
+        for (base, quote, rate) in rates.into_iter() {
+            // Register relationships bi-directionally
+            rate_map.insert((base, quote), rate);
+            rate_map.insert((quote, base), 1.0 / rate);
+
+            direct_relations.entry(base).or_insert(vec![]).push(quote);
+            direct_relations.entry(quote).or_insert(vec![]).push(base);
+        }
+
+        Self {
+            rates: rate_map,
+            direct_relations,
+            derived_rates: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn rate_between(&self, base: T, quote: T) -> Option<f64>
+    where
+        T: Copy + Eq + Hash,
+    {
+        if let Some(rate) = self.rate_inner(base, quote) {
+            return Some(rate);
+        }
+
+        // Poisoned cache is still consistent because entries are inserted atomically
+        if let Some(&rate) = self
+            .derived_rates
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&(base, quote))
+        {
+            return rate;
+        }
+
+        // Computing outside of the lock may duplicate work between threads, but the results are identical
+        let rate = self.rate_between_inner(base, quote);
+        self.derived_rates
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert((base, quote), rate);
+
+        rate
+    }
+
+    /// Rate along a path with the fewest intermediate currencies, found by breadth-first search.
+    /// Among paths of the same length, the one through earlier registered markets is chosen
+    fn rate_between_inner(&self, base: T, quote: T) -> Option<f64>
+    where
+        T: Copy + Eq + Hash,
+    {
+        if let Some(rate) = self.rate_inner(base, quote) {
+            return Some(rate);
+        }
+
+        // Rates from base to currencies already reached
+        let mut reached_rates = HashMap::new();
+        reached_rates.insert(base, 1.0);
+        let mut queue = VecDeque::new();
+        queue.push_back(base);
+
+        while let Some(current) = queue.pop_front() {
+            let current_rate = reached_rates[&current];
+            for &next in self.direct_relations.get(&current).into_iter().flatten() {
+                if reached_rates.contains_key(&next) {
+                    continue;
+                }
+
+                let rate = current_rate * self.rates[&(current, next)];
+                if next == quote {
+                    return Some(rate);
+                }
+                reached_rates.insert(next, rate);
+                queue.push_back(next);
+            }
+        }
+
+        None
+    }
+
+    /// Enumerate simple cycles up to `max_len` currencies,
+    /// then return cycles whose rate product deviates from 1.0 more than `threshold`.
+    ///
+    /// Each cycle is reported once regardless of its start point or direction.
+    pub fn find_inconsistent_cycles(&self, max_len: usize, threshold: f64) -> Vec<CycleReport<T>>
+    where
+        T: Copy + Eq + Hash,
+    {
+        // Number currencies to enumerate each cycle only once
+        let nodes = self.direct_relations.keys().copied().collect::<Vec<_>>();
+        let indices = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, &node)| (node, i))
+            .collect::<HashMap<_, _>>();
+
+        let mut reports = vec![];
+        for (start_index, &start) in nodes.iter().enumerate() {
+            let mut path = vec![start];
+            self.find_cycles_inner(
+                start_index,
+                &indices,
+                &mut path,
+                max_len,
+                threshold,
+                &mut reports,
+            );
+        }
+
+        reports
+    }
+
+    fn find_cycles_inner(
+        &self,
+        start_index: usize,
+        indices: &HashMap<T, usize>,
+        path: &mut Vec<T>,
+        max_len: usize,
+        threshold: f64,
+        reports: &mut Vec<CycleReport<T>>,
+    ) where
+        T: Copy + Eq + Hash,
+    {
+        let start = path[0];
+        let last = *path.last().unwrap();
+
+        let neighbors = match self.direct_relations.get(&last) {
+            Some(neighbors) => neighbors.iter().copied().collect::<HashSet<_>>(),
+            None => return,
+        };
+
+        for next in neighbors.into_iter() {
+            // Close cycle. Skip the reversed one by comparing second and last currencies
+            if next == start {
+                if path.len() >= 3 && indices[&path[1]] < indices[&last] {
+                    let rate_product = path
+                        .iter()
+                        .zip(path.iter().skip(1).chain(std::iter::once(&start)))
+                        .map(|(&from, &to)| self.rates[&(from, to)])
+                        .product::<f64>();
+                    if (rate_product - 1.0).abs() > threshold {
+                        reports.push(CycleReport {
+                            currencies: path.clone(),
+                            rate_product,
+                        });
+                    }
+                }
+                continue;
+            }
+
+            // Cycle starts from the currency which has the smallest index
+            let is_candidate = indices[&next] > start_index && !path.contains(&next);
+            if is_candidate && path.len() < max_len {
+                path.push(next);
+                self.find_cycles_inner(start_index, indices, path, max_len, threshold, reports);
+                path.pop();
+            }
+        }
+    }
+
+    fn rate_inner(&self, base: T, quote: T) -> Option<f64>
+    where
+        T: Copy + Eq + Hash,
+    {
+        if base == quote {
+            Some(1.0)
+        } else if let Some(&rate) = self.rates.get(&(base, quote)) {
+            Some(rate)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExchangeGraph;
+    use assert_approx_eq::assert_approx_eq;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_rate_between_neighbor() {
+        let rates = vec![("a", "b", 10.0)];
+
+        let graph = ExchangeGraph::from_rates(rates);
+
+        let forward_rate = graph.rate_between("a", "b");
+        let backward_rate = graph.rate_between("b", "a");
+
+        assert_eq!(Some(10.0), forward_rate);
+        assert_eq!(Some(0.1), backward_rate);
+    }
+
+    #[test]
+    fn test_rate_between_neighbors() {
+        let rates = vec![("a", "b", 10.0), ("a", "c", 2.0)];
+
+        let graph = ExchangeGraph::from_rates(rates);
+
+        let forward_rate = graph.rate_between("a", "b");
+        let backward_rate = graph.rate_between("b", "a");
+        assert_eq!(Some(10.0), forward_rate);
+        assert_eq!(Some(0.1), backward_rate);
+
+        let forward_rate = graph.rate_between("a", "c");
+        let backward_rate = graph.rate_between("c", "a");
+        assert_eq!(Some(2.0), forward_rate);
+        assert_eq!(Some(0.5), backward_rate);
+    }
+
+    #[test]
+    fn test_rate_between_equivalent() {
+        let rates = vec![("a", "b", 10.0)];
+
+        let graph = ExchangeGraph::from_rates(rates);
+
+        assert_eq!(Some(1.0), graph.rate_between("a", "a"));
+    }
+
+    #[test]
+    fn test_rate_between_empty() {
+        let graph = ExchangeGraph::from_rates(std::iter::empty());
+
+        assert_eq!(None, graph.rate_between("a", "b"));
+    }
+
+    #[test]
+    fn test_rate_between_bridge3() {
+        let rates = vec![("a", "b", 10.0), ("b", "c", 2.0)]; // 1a=10b, 1b=2c
+
+        let graph = ExchangeGraph::from_rates(rates);
+        let forward_rate = graph.rate_between("a", "c");
+        let backward_rate = graph.rate_between("c", "a");
+
+        assert_eq!(Some(20.0), forward_rate);
+        assert_eq!(Some(0.05), backward_rate);
+    }
+
+    #[test]
+    fn test_rate_between_bridge3_with_inverse() {
+        let rates = vec![("a", "b", 10.0), ("c", "b", 0.5)]; // 1a=10b, 1b=2c
+
+        let graph = ExchangeGraph::from_rates(rates);
+        let forward_rate = graph.rate_between("a", "c");
+        let backward_rate = graph.rate_between("c", "a");
+
+        assert_eq!(Some(20.0), forward_rate);
+        assert_eq!(Some(0.05), backward_rate);
+    }
+
+    #[test]
+    fn test_rate_between_bridge3_start_from_center() {
+        let rates = vec![("b", "a", 0.1), ("b", "c", 2.0)]; // 1a=10b, 1b=2c
+
+        let graph = ExchangeGraph::from_rates(rates);
+
+        let forward_rate = graph.rate_between("a", "c");
+        let backward_rate = graph.rate_between("c", "a");
+
+        assert_eq!(Some(20.0), forward_rate);
+        assert_eq!(Some(0.05), backward_rate);
+    }
+
+    #[test]
+    fn test_rate_between_bridge4() {
+        let rates = vec![("a", "b", 10.0), ("b", "c", 2.0), ("c", "d", 4.0)];
+
+        let graph = ExchangeGraph::from_rates(rates);
+        let forward_rate = graph.rate_between("a", "d");
+        let backward_rate = graph.rate_between("d", "a");
+
+        assert_eq!(Some(80.0), forward_rate);
+        assert_eq!(Some(0.0125), backward_rate);
+    }
+
+    #[test]
+    fn test_rate_between_prefers_direct_rate() {
+        // a-x-y-c is registered before a-c
+        let rates = vec![
+            ("a", "x", 2.0),
+            ("x", "y", 3.0),
+            ("y", "c", 5.0),
+            ("a", "c", 7.0),
+        ];
+
+        let graph = ExchangeGraph::from_rates(rates);
+
+        assert_eq!(Some(7.0), graph.rate_between("a", "c"));
+        assert_eq!(Some(1.0 / 7.0), graph.rate_between("c", "a"));
+    }
+
+    #[test]
+    fn test_rate_between_prefers_fewest_hops() {
+        // a-x-y-c (3 hops) is registered before a-b-c (2 hops)
+        let rates = vec![
+            ("a", "x", 2.0),
+            ("x", "y", 3.0),
+            ("y", "c", 5.0),
+            ("a", "b", 4.0),
+            ("b", "c", 6.0),
+        ];
+
+        let graph = ExchangeGraph::from_rates(rates);
+
+        assert_eq!(Some(24.0), graph.rate_between("a", "c"));
+        assert_approx_eq!(1.0 / 24.0, graph.rate_between("c", "a").unwrap());
+        // Memoized rate is the same
+        assert_eq!(Some(24.0), graph.rate_between("a", "c"));
+        // Through x, since a-x-y is the only 2-hop path to y
+        assert_eq!(Some(6.0), graph.rate_between("a", "y"));
+    }
+
+    #[test]
+    fn test_rate_between_isolated_clusters() {
+        let rates = vec![("a", "b", 10.0), ("b", "c", 2.0), ("foo", "bar", 4.0)];
+
+        let graph = ExchangeGraph::from_rates(rates);
+
+        let rate = graph.rate_between("a", "foo");
+
+        assert_eq!(None, rate);
+    }
+
+    #[test]
+    fn test_rate_between_memoized() {
+        let rates = vec![("a", "b", 10.0), ("b", "c", 2.0), ("foo", "bar", 4.0)];
+
+        let graph = ExchangeGraph::from_rates(rates);
+
+        // Second lookups hit the cache and must return the same rates
+        for _ in 0..2 {
+            assert_eq!(Some(20.0), graph.rate_between("a", "c"));
+            assert_eq!(None, graph.rate_between("a", "foo"));
+        }
+    }
+
+    #[test]
+    fn test_rate_between_concurrent() {
+        let rates = vec![
+            ("a", "b", 10.0),
+            ("b", "c", 2.0),
+            ("c", "d", 4.0),
+            ("e", "d", 0.5),
+            ("foo", "bar", 4.0),
+        ];
+        let currencies = ["a", "b", "c", "d", "e", "foo", "bar", "unknown"];
+        let pairs = currencies
+            .iter()
+            .flat_map(|&base| currencies.iter().map(move |&quote| (base, quote)))
+            .collect::<Vec<_>>();
+
+        let baseline = {
+            let graph = ExchangeGraph::from_rates(rates.clone());
+            pairs
+                .iter()
+                .map(|&(base, quote)| graph.rate_between(base, quote))
+                .collect::<Vec<_>>()
+        };
+
+        let graph = Arc::new(ExchangeGraph::from_rates(rates));
+        let pairs = Arc::new(pairs);
+        let handles = (0..8)
+            .map(|_| {
+                let graph = Arc::clone(&graph);
+                let pairs = Arc::clone(&pairs);
+                std::thread::spawn(move || {
+                    (0..100)
+                        .map(|_| {
+                            pairs
+                                .iter()
+                                .map(|&(base, quote)| graph.rate_between(base, quote))
+                                .collect::<Vec<_>>()
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles.into_iter() {
+            for rates in handle.join().unwrap().into_iter() {
+                assert_eq!(baseline, rates);
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_inconsistent_cycles() {
+        let rates = vec![
+            // Consistent triangle: 1a=10b, 1b=2c, 1a=20c
+            ("a", "b", 10.0),
+            ("b", "c", 2.0),
+            ("a", "c", 20.0),
+            // Inconsistent triangle: 1x=10y, 1y=2z, but 1x=20000z
+            ("x", "y", 10.0),
+            ("y", "z", 2.0),
+            ("x", "z", 20000.0),
+        ];
+
+        let graph = ExchangeGraph::from_rates(rates);
+        let reports = graph.find_inconsistent_cycles(4, 0.01);
+
+        assert_eq!(1, reports.len());
+        let report = &reports[0];
+        let mut currencies = report.currencies.clone();
+        currencies.sort();
+        assert_eq!(vec!["x", "y", "z"], currencies);
+
+        let expected = if report.rate_product > 1.0 {
+            1000.0
+        } else {
+            0.001
+        };
+        assert_approx_eq!(expected, report.rate_product);
+    }
+
+    #[test]
+    fn test_find_inconsistent_cycles_max_len() {
+        // Inconsistent square: a->b->c->d->a product is 2
+        let rates = vec![
+            ("a", "b", 2.0),
+            ("b", "c", 1.0),
+            ("c", "d", 1.0),
+            ("d", "a", 1.0),
+        ];
+
+        let graph = ExchangeGraph::from_rates(rates);
+
+        assert!(graph.find_inconsistent_cycles(3, 0.01).is_empty());
+        assert_eq!(1, graph.find_inconsistent_cycles(4, 0.01).len());
+    }
+}
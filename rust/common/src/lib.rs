@@ -0,0 +1,47 @@
+use std::fmt::{self, Display, Formatter};
+
+pub mod allocation;
+pub mod exchange_graph;
+
+/// Information of the build, embedded at compile time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildInfo {
+    /// Version of the workspace crates
+    pub version: &'static str,
+    /// Short commit hash with `-dirty` suffix if tracked files are modified. `unknown` if built without git
+    pub git_hash: &'static str,
+    /// UTC, formatted as `%Y-%m-%dT%H:%M:%SZ`
+    pub build_timestamp: &'static str,
+}
+
+impl Display for BuildInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "version {} (git {}, built at {})",
+            self.version, self.git_hash, self.build_timestamp
+        )
+    }
+}
+
+pub const fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: env!("BUILD_GIT_HASH"),
+        build_timestamp: env!("BUILD_TIMESTAMP"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_info() {
+        let info = build_info();
+
+        assert!(!info.version.is_empty());
+        assert!(!info.git_hash.is_empty());
+        assert!(!info.build_timestamp.is_empty());
+    }
+}
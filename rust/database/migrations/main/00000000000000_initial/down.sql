@@ -0,0 +1,12 @@
+DROP TABLE IF EXISTS next_id;
+DROP TABLE IF EXISTS alert_state;
+DROP TABLE IF EXISTS sync_cursor;
+DROP TABLE IF EXISTS currency_tag;
+DROP TABLE IF EXISTS pending_approval;
+DROP TABLE IF EXISTS myorder;
+DROP TABLE IF EXISTS orderbook;
+DROP TABLE IF EXISTS price;
+DROP TABLE IF EXISTS market;
+DROP TABLE IF EXISTS balance;
+DROP TABLE IF EXISTS stamp;
+DROP TABLE IF EXISTS currency;
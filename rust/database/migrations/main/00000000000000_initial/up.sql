@@ -0,0 +1,160 @@
+-- Tables of trade DB. Existing tables created by docker-autotrader-db are kept as they are
+
+CREATE TABLE IF NOT EXISTS currency
+(
+    currency_id INTEGER NOT NULL PRIMARY KEY,
+    -- currency unit, ex. BTC, ETH, ...
+    symbol VARCHAR(8) NOT NULL,
+    -- ex. Bitcoin, Ether, ...
+    name VARCHAR(32) NOT NULL,
+    -- FALSE if delisted from the exchange
+    active BOOLEAN NOT NULL DEFAULT TRUE
+);
+
+CREATE TABLE IF NOT EXISTS stamp
+(
+    stamp_id INTEGER NOT NULL PRIMARY KEY,
+    stamp TIMESTAMP NOT NULL,
+    -- scraper, import, replay, coincheck or other
+    source VARCHAR(16) NOT NULL DEFAULT 'scraper'
+);
+
+CREATE TABLE IF NOT EXISTS balance
+(
+    balance_id INTEGER NOT NULL PRIMARY KEY,
+    currency_id INTEGER NOT NULL,
+    stamp_id INTEGER NOT NULL,
+    available FLOAT NOT NULL,
+    pending FLOAT NOT NULL,
+
+    FOREIGN KEY (currency_id) REFERENCES currency(currency_id) ON UPDATE CASCADE,
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS market
+(
+    market_id INTEGER NOT NULL PRIMARY KEY,
+    base_id INTEGER NOT NULL,
+    quote_id INTEGER NOT NULL,
+
+    FOREIGN KEY (base_id) REFERENCES currency(currency_id) ON UPDATE CASCADE,
+    FOREIGN KEY (quote_id) REFERENCES currency(currency_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS price
+(
+    price_id INTEGER NOT NULL PRIMARY KEY,
+    market_id INTEGER NOT NULL,
+    stamp_id INTEGER NOT NULL,
+    price FLOAT NOT NULL,
+
+    FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE,
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS orderbook
+(
+    orderbook_id INTEGER NOT NULL PRIMARY KEY,
+    market_id INTEGER NOT NULL,
+    stamp_id INTEGER NOT NULL,
+    side VARCHAR(4) NOT NULL,
+    price FLOAT NOT NULL,
+    volume FLOAT NOT NULL,
+
+    FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE,
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS myorder
+(
+    myorder_id INTEGER NOT NULL PRIMARY KEY,
+    -- order id on remote trading service
+    transaction_id VARCHAR(64) NOT NULL,
+    market_id INTEGER NOT NULL,
+    created_stamp_id INTEGER NOT NULL,
+    modified_stamp_id INTEGER NOT NULL,
+    price FLOAT NOT NULL,
+    base_quantity FLOAT NOT NULL,
+    quote_quantity FLOAT NOT NULL,
+    order_type VARCHAR(16) NOT NULL,
+    side VARCHAR(4) NOT NULL,
+    state VARCHAR(16) NOT NULL,
+
+    UNIQUE (transaction_id),
+    FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE,
+    FOREIGN KEY (created_stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE,
+    FOREIGN KEY (modified_stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS pending_approval
+(
+    pending_approval_id INTEGER NOT NULL PRIMARY KEY,
+    market_id INTEGER NOT NULL,
+    -- stamp at which the order was recommended
+    stamp_id INTEGER NOT NULL,
+    -- the order is not executed after this time
+    expiry TIMESTAMP NOT NULL,
+    price FLOAT NOT NULL,
+    base_quantity FLOAT NOT NULL,
+    quote_quantity FLOAT NOT NULL,
+    -- change of quote balance when filled, including fee
+    expected_net_quote FLOAT NOT NULL,
+    order_type VARCHAR(16) NOT NULL,
+    side VARCHAR(4) NOT NULL,
+    -- reasons of the recommendation
+    recommendation VARCHAR(1024) NOT NULL,
+    state VARCHAR(16) NOT NULL,
+    -- market price when approved, used to check price drift before execution
+    approved_price FLOAT,
+
+    FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE,
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS currency_tag
+(
+    currency_id INTEGER NOT NULL,
+    -- category for reporting, ex. stablecoin, major, ...
+    tag VARCHAR(32) NOT NULL,
+
+    PRIMARY KEY (currency_id, tag),
+    FOREIGN KEY (currency_id) REFERENCES currency(currency_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS sync_cursor
+(
+    market_id INTEGER NOT NULL,
+    -- kind of synced data, ex. myorder
+    kind VARCHAR(16) NOT NULL,
+    -- data created before this are persisted
+    synced_until TIMESTAMP NOT NULL,
+
+    PRIMARY KEY (market_id, kind),
+    FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS alert_state
+(
+    -- name of the alert rule
+    alert_name VARCHAR(64) NOT NULL PRIMARY KEY,
+    -- TRUE while the condition holds
+    firing BOOLEAN NOT NULL,
+    last_notified TIMESTAMP NULL
+);
+
+CREATE TABLE IF NOT EXISTS next_id
+(
+    currency INTEGER NOT NULL,
+    stamp INTEGER NOT NULL,
+    balance INTEGER NOT NULL,
+    market INTEGER NOT NULL,
+    price INTEGER NOT NULL,
+    orderbook INTEGER NOT NULL,
+    myorder INTEGER NOT NULL,
+    pending_approval INTEGER NOT NULL
+);
+
+-- First ids. Kept if the table is already seeded
+INSERT INTO next_id
+SELECT 0, 0, 0, 0, 0, 0, 0, 0 FROM DUAL
+WHERE NOT EXISTS (SELECT * FROM next_id);
@@ -0,0 +1,27 @@
+-- Kept if created by docker-autotrader-db
+CREATE TABLE IF NOT EXISTS scrape_run
+(
+    stamp_id INTEGER NOT NULL PRIMARY KEY,
+    started_at TIMESTAMP NOT NULL,
+    finished_at TIMESTAMP NOT NULL,
+    -- FALSE if the section was disabled, failed, or fell short of STAGE_SUCCESS_THRESHOLD
+    currency_fetched BOOLEAN NOT NULL,
+    -- fetched rows, including those skipped on saving
+    currency_rows INTEGER NOT NULL,
+    -- failed fetches
+    currency_errors INTEGER NOT NULL,
+    balance_fetched BOOLEAN NOT NULL,
+    balance_rows INTEGER NOT NULL,
+    balance_errors INTEGER NOT NULL,
+    price_fetched BOOLEAN NOT NULL,
+    price_rows INTEGER NOT NULL,
+    price_errors INTEGER NOT NULL,
+    orderbook_fetched BOOLEAN NOT NULL,
+    orderbook_rows INTEGER NOT NULL,
+    orderbook_errors INTEGER NOT NULL,
+    myorder_fetched BOOLEAN NOT NULL,
+    myorder_rows INTEGER NOT NULL,
+    myorder_errors INTEGER NOT NULL,
+
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
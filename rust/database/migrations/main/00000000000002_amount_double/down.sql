@@ -0,0 +1,22 @@
+ALTER TABLE balance
+    MODIFY available FLOAT NOT NULL,
+    MODIFY pending FLOAT NOT NULL;
+
+ALTER TABLE price
+    MODIFY price FLOAT NOT NULL;
+
+ALTER TABLE orderbook
+    MODIFY price FLOAT NOT NULL,
+    MODIFY volume FLOAT NOT NULL;
+
+ALTER TABLE myorder
+    MODIFY price FLOAT NOT NULL,
+    MODIFY base_quantity FLOAT NOT NULL,
+    MODIFY quote_quantity FLOAT NOT NULL;
+
+ALTER TABLE pending_approval
+    MODIFY price FLOAT NOT NULL,
+    MODIFY base_quantity FLOAT NOT NULL,
+    MODIFY quote_quantity FLOAT NOT NULL,
+    MODIFY expected_net_quote FLOAT NOT NULL,
+    MODIFY approved_price FLOAT;
@@ -0,0 +1,23 @@
+-- FLOAT loses satoshi-scale amounts
+ALTER TABLE balance
+    MODIFY available DOUBLE NOT NULL,
+    MODIFY pending DOUBLE NOT NULL;
+
+ALTER TABLE price
+    MODIFY price DOUBLE NOT NULL;
+
+ALTER TABLE orderbook
+    MODIFY price DOUBLE NOT NULL,
+    MODIFY volume DOUBLE NOT NULL;
+
+ALTER TABLE myorder
+    MODIFY price DOUBLE NOT NULL,
+    MODIFY base_quantity DOUBLE NOT NULL,
+    MODIFY quote_quantity DOUBLE NOT NULL;
+
+ALTER TABLE pending_approval
+    MODIFY price DOUBLE NOT NULL,
+    MODIFY base_quantity DOUBLE NOT NULL,
+    MODIFY quote_quantity DOUBLE NOT NULL,
+    MODIFY expected_net_quote DOUBLE NOT NULL,
+    MODIFY approved_price DOUBLE;
@@ -0,0 +1 @@
+ALTER TABLE price DROP INDEX price_market_stamp;
@@ -0,0 +1,24 @@
+-- A market has at most one price per stamp. Duplicated prices are removed, keeping the oldest row
+DELETE p1
+FROM price p1
+INNER JOIN price p2
+    ON p1.market_id = p2.market_id
+    AND p1.stamp_id = p2.stamp_id
+    AND p1.price_id > p2.price_id;
+
+-- Kept if created by docker-autotrader-db
+SET @price_market_stamp_exists = (
+    SELECT COUNT(*)
+    FROM information_schema.statistics
+    WHERE table_schema = DATABASE()
+        AND table_name = 'price'
+        AND index_name = 'price_market_stamp'
+);
+SET @price_market_stamp_ddl = IF(
+    @price_market_stamp_exists = 0,
+    'ALTER TABLE price ADD UNIQUE price_market_stamp (market_id, stamp_id)',
+    'DO 0'
+);
+PREPARE price_market_stamp_stmt FROM @price_market_stamp_ddl;
+EXECUTE price_market_stamp_stmt;
+DEALLOCATE PREPARE price_market_stamp_stmt;
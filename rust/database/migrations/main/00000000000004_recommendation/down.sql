@@ -0,0 +1,2 @@
+ALTER TABLE next_id DROP COLUMN recommendation;
+DROP TABLE IF EXISTS recommendation;
@@ -0,0 +1,33 @@
+-- Kept if created by docker-autotrader-db
+CREATE TABLE IF NOT EXISTS recommendation
+(
+    recommendation_id INTEGER NOT NULL PRIMARY KEY,
+    stamp_id INTEGER NOT NULL,
+    market_id INTEGER NOT NULL,
+    -- rule which made the recommendation. NULL for the aggregated outcome of rules
+    rule_name VARCHAR(64),
+    recommendation_type VARCHAR(16) NOT NULL,
+    -- score of the rule, or weighted mean of scores for the aggregated outcome. NULL if undefined
+    score DOUBLE,
+    reason TEXT NOT NULL,
+
+    INDEX recommendation_market_stamp (market_id, stamp_id),
+    FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE,
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+SET @next_recommendation_exists = (
+    SELECT COUNT(*)
+    FROM information_schema.columns
+    WHERE table_schema = DATABASE()
+        AND table_name = 'next_id'
+        AND column_name = 'recommendation'
+);
+SET @next_recommendation_ddl = IF(
+    @next_recommendation_exists = 0,
+    'ALTER TABLE next_id ADD recommendation INTEGER NOT NULL DEFAULT 0',
+    'DO 0'
+);
+PREPARE next_recommendation_stmt FROM @next_recommendation_ddl;
+EXECUTE next_recommendation_stmt;
+DEALLOCATE PREPARE next_recommendation_stmt;
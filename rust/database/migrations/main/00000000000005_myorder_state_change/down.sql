@@ -0,0 +1 @@
+DROP TABLE IF EXISTS myorder_state_change;
@@ -0,0 +1,23 @@
+DROP TABLE IF EXISTS next_id;
+DROP TABLE IF EXISTS alert_state;
+DROP TABLE IF EXISTS scrape_run;
+DROP TABLE IF EXISTS sync_cursor;
+DROP TABLE IF EXISTS currency_tag;
+DROP TABLE IF EXISTS recommendation;
+DROP TABLE IF EXISTS pending_approval;
+DROP TABLE IF EXISTS myorder_state_change;
+DROP TABLE IF EXISTS myorder;
+DROP TABLE IF EXISTS orderbook;
+DROP TABLE IF EXISTS price;
+DROP TABLE IF EXISTS market;
+DROP TABLE IF EXISTS balance;
+DROP TABLE IF EXISTS stamp;
+DROP TABLE IF EXISTS currency;
+
+DROP TYPE IF EXISTS sync_kind;
+DROP TYPE IF EXISTS recommendation_type;
+DROP TYPE IF EXISTS stamp_source;
+DROP TYPE IF EXISTS approval_state;
+DROP TYPE IF EXISTS order_state;
+DROP TYPE IF EXISTS order_type;
+DROP TYPE IF EXISTS order_side;
@@ -0,0 +1,226 @@
+-- Tables of trade DB on PostgreSQL, same as the latest schema of MySQL.
+-- Enum columns are of enum types, named by PgType of custom_sql_type.rs
+
+CREATE TYPE order_side AS ENUM ('buy', 'sell');
+CREATE TYPE order_type AS ENUM ('limit', 'market', 'stop_limit', 'stop_market');
+CREATE TYPE order_state AS ENUM ('opened', 'filled', 'cancelled', 'error');
+CREATE TYPE approval_state AS ENUM ('pending', 'approved', 'executed', 'expired', 'drifted');
+CREATE TYPE stamp_source AS ENUM ('scraper', 'import', 'replay', 'coincheck', 'other');
+CREATE TYPE recommendation_type AS ENUM ('buy', 'sell', 'pending', 'neutral');
+CREATE TYPE sync_kind AS ENUM ('myorder');
+
+CREATE TABLE currency
+(
+    currency_id INTEGER NOT NULL PRIMARY KEY,
+    -- currency unit, ex. BTC, ETH, ...
+    symbol VARCHAR(8) NOT NULL,
+    -- ex. Bitcoin, Ether, ...
+    name VARCHAR(32) NOT NULL,
+    -- FALSE if delisted from the exchange
+    active BOOLEAN NOT NULL DEFAULT TRUE
+);
+
+CREATE TABLE stamp
+(
+    stamp_id INTEGER NOT NULL PRIMARY KEY,
+    stamp TIMESTAMP NOT NULL,
+    source stamp_source NOT NULL DEFAULT 'scraper'
+);
+
+CREATE TABLE balance
+(
+    balance_id INTEGER NOT NULL PRIMARY KEY,
+    currency_id INTEGER NOT NULL,
+    stamp_id INTEGER NOT NULL,
+    available DOUBLE PRECISION NOT NULL,
+    pending DOUBLE PRECISION NOT NULL,
+
+    FOREIGN KEY (currency_id) REFERENCES currency(currency_id) ON UPDATE CASCADE,
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE market
+(
+    market_id INTEGER NOT NULL PRIMARY KEY,
+    base_id INTEGER NOT NULL,
+    quote_id INTEGER NOT NULL,
+
+    FOREIGN KEY (base_id) REFERENCES currency(currency_id) ON UPDATE CASCADE,
+    FOREIGN KEY (quote_id) REFERENCES currency(currency_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE price
+(
+    price_id INTEGER NOT NULL PRIMARY KEY,
+    market_id INTEGER NOT NULL,
+    stamp_id INTEGER NOT NULL,
+    price DOUBLE PRECISION NOT NULL,
+
+    CONSTRAINT price_market_stamp UNIQUE (market_id, stamp_id),
+    FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE,
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE orderbook
+(
+    orderbook_id INTEGER NOT NULL PRIMARY KEY,
+    market_id INTEGER NOT NULL,
+    stamp_id INTEGER NOT NULL,
+    side order_side NOT NULL,
+    price DOUBLE PRECISION NOT NULL,
+    volume DOUBLE PRECISION NOT NULL,
+
+    FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE,
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE myorder
+(
+    myorder_id INTEGER NOT NULL PRIMARY KEY,
+    -- order id on remote trading service
+    transaction_id VARCHAR(64) NOT NULL,
+    market_id INTEGER NOT NULL,
+    created_stamp_id INTEGER NOT NULL,
+    modified_stamp_id INTEGER NOT NULL,
+    price DOUBLE PRECISION NOT NULL,
+    base_quantity DOUBLE PRECISION NOT NULL,
+    quote_quantity DOUBLE PRECISION NOT NULL,
+    order_type order_type NOT NULL,
+    side order_side NOT NULL,
+    state order_state NOT NULL,
+
+    UNIQUE (transaction_id),
+    FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE,
+    FOREIGN KEY (created_stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE,
+    FOREIGN KEY (modified_stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE myorder_state_change
+(
+    myorder_id INTEGER NOT NULL,
+    old_state order_state NOT NULL,
+    new_state order_state NOT NULL,
+    -- stamp at which the change was found
+    stamp_id INTEGER NOT NULL,
+
+    -- state never goes back, so an order enters each state at most once
+    PRIMARY KEY (myorder_id, new_state),
+    FOREIGN KEY (myorder_id) REFERENCES myorder(myorder_id) ON UPDATE CASCADE,
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE pending_approval
+(
+    pending_approval_id INTEGER NOT NULL PRIMARY KEY,
+    market_id INTEGER NOT NULL,
+    -- stamp at which the order was recommended
+    stamp_id INTEGER NOT NULL,
+    -- the order is not executed after this time
+    expiry TIMESTAMP NOT NULL,
+    price DOUBLE PRECISION NOT NULL,
+    base_quantity DOUBLE PRECISION NOT NULL,
+    quote_quantity DOUBLE PRECISION NOT NULL,
+    -- change of quote balance when filled, including fee
+    expected_net_quote DOUBLE PRECISION NOT NULL,
+    order_type order_type NOT NULL,
+    side order_side NOT NULL,
+    -- reasons of the recommendation
+    recommendation VARCHAR(1024) NOT NULL,
+    state approval_state NOT NULL,
+    -- market price when approved, used to check price drift before execution
+    approved_price DOUBLE PRECISION,
+
+    FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE,
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE recommendation
+(
+    recommendation_id INTEGER NOT NULL PRIMARY KEY,
+    stamp_id INTEGER NOT NULL,
+    market_id INTEGER NOT NULL,
+    -- rule which made the recommendation. NULL for the aggregated outcome of rules
+    rule_name VARCHAR(64),
+    recommendation_type recommendation_type NOT NULL,
+    -- score of the rule, or weighted mean of scores for the aggregated outcome. NULL if undefined
+    score DOUBLE PRECISION,
+    reason TEXT NOT NULL,
+
+    FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE,
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+CREATE INDEX recommendation_market_stamp ON recommendation (market_id, stamp_id);
+
+CREATE TABLE currency_tag
+(
+    currency_id INTEGER NOT NULL,
+    -- category for reporting, ex. stablecoin, major, ...
+    tag VARCHAR(32) NOT NULL,
+
+    PRIMARY KEY (currency_id, tag),
+    FOREIGN KEY (currency_id) REFERENCES currency(currency_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE sync_cursor
+(
+    market_id INTEGER NOT NULL,
+    kind sync_kind NOT NULL,
+    -- data created before this are persisted
+    synced_until TIMESTAMP NOT NULL,
+
+    PRIMARY KEY (market_id, kind),
+    FOREIGN KEY (market_id) REFERENCES market(market_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE scrape_run
+(
+    stamp_id INTEGER NOT NULL PRIMARY KEY,
+    started_at TIMESTAMP NOT NULL,
+    finished_at TIMESTAMP NOT NULL,
+    -- FALSE if the section was disabled, failed, or fell short of STAGE_SUCCESS_THRESHOLD
+    currency_fetched BOOLEAN NOT NULL,
+    -- fetched rows, including those skipped on saving
+    currency_rows INTEGER NOT NULL,
+    -- failed fetches
+    currency_errors INTEGER NOT NULL,
+    balance_fetched BOOLEAN NOT NULL,
+    balance_rows INTEGER NOT NULL,
+    balance_errors INTEGER NOT NULL,
+    price_fetched BOOLEAN NOT NULL,
+    price_rows INTEGER NOT NULL,
+    price_errors INTEGER NOT NULL,
+    orderbook_fetched BOOLEAN NOT NULL,
+    orderbook_rows INTEGER NOT NULL,
+    orderbook_errors INTEGER NOT NULL,
+    myorder_fetched BOOLEAN NOT NULL,
+    myorder_rows INTEGER NOT NULL,
+    myorder_errors INTEGER NOT NULL,
+
+    FOREIGN KEY (stamp_id) REFERENCES stamp(stamp_id) ON UPDATE CASCADE
+);
+
+CREATE TABLE alert_state
+(
+    -- name of the alert rule
+    alert_name VARCHAR(64) NOT NULL PRIMARY KEY,
+    -- TRUE while the condition holds
+    firing BOOLEAN NOT NULL,
+    last_notified TIMESTAMP NULL
+);
+
+CREATE TABLE next_id
+(
+    currency INTEGER NOT NULL,
+    stamp INTEGER NOT NULL,
+    balance INTEGER NOT NULL,
+    market INTEGER NOT NULL,
+    price INTEGER NOT NULL,
+    orderbook INTEGER NOT NULL,
+    myorder INTEGER NOT NULL,
+    pending_approval INTEGER NOT NULL,
+    recommendation INTEGER NOT NULL DEFAULT 0
+);
+
+-- First ids
+INSERT INTO next_id VALUES (0, 0, 0, 0, 0, 0, 0, 0, 0);
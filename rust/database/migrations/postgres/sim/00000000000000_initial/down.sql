@@ -0,0 +1,10 @@
+DROP TABLE IF EXISTS next_id;
+DROP TABLE IF EXISTS speculator_lock;
+DROP TABLE IF EXISTS myorder_state_change;
+DROP TABLE IF EXISTS myorder;
+DROP TABLE IF EXISTS stop_order;
+DROP TABLE IF EXISTS balance;
+
+DROP TYPE IF EXISTS order_state;
+DROP TYPE IF EXISTS order_type;
+DROP TYPE IF EXISTS order_side;
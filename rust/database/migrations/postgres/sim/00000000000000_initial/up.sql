@@ -0,0 +1,90 @@
+-- Tables of simulation DB on PostgreSQL, same as the latest schema of MySQL.
+-- Only enum types used by these tables are created
+
+CREATE TYPE order_side AS ENUM ('buy', 'sell');
+CREATE TYPE order_type AS ENUM ('limit', 'market', 'stop_limit', 'stop_market');
+CREATE TYPE order_state AS ENUM ('opened', 'filled', 'cancelled', 'error');
+
+CREATE TABLE balance
+(
+    balance_id INTEGER NOT NULL PRIMARY KEY,
+    currency_id INTEGER NOT NULL,
+    stamp_id INTEGER NOT NULL,
+    available DOUBLE PRECISION NOT NULL,
+    pending DOUBLE PRECISION NOT NULL
+);
+
+-- stop orders held until triggered or expired
+CREATE TABLE stop_order
+(
+    stop_order_id INTEGER NOT NULL PRIMARY KEY,
+    market_id INTEGER NOT NULL,
+    side order_side NOT NULL,
+    order_type order_type NOT NULL,
+    base_quantity DOUBLE PRECISION NOT NULL,
+    quote_quantity DOUBLE PRECISION NOT NULL,
+    -- limit price, or expected fill price of stop market orders
+    price DOUBLE PRECISION NOT NULL,
+    trigger_price DOUBLE PRECISION NOT NULL,
+    -- change of quote balance when filled, including fee
+    expected_net_quote DOUBLE PRECISION NOT NULL,
+    -- the order is not triggered after this time
+    expiry TIMESTAMP NOT NULL
+);
+
+-- orders placed by the simulation
+CREATE TABLE myorder
+(
+    myorder_id INTEGER NOT NULL PRIMARY KEY,
+    -- sim-<stamp_id>-<n>
+    transaction_id VARCHAR(64) NOT NULL,
+    market_id INTEGER NOT NULL,
+    created_stamp_id INTEGER NOT NULL,
+    modified_stamp_id INTEGER NOT NULL,
+    price DOUBLE PRECISION NOT NULL,
+    base_quantity DOUBLE PRECISION NOT NULL,
+    quote_quantity DOUBLE PRECISION NOT NULL,
+    order_type order_type NOT NULL,
+    side order_side NOT NULL,
+    state order_state NOT NULL,
+
+    UNIQUE (transaction_id)
+);
+
+-- state changes of orders placed by the simulation. stamp_id refers stamps of trade DB
+CREATE TABLE myorder_state_change
+(
+    myorder_id INTEGER NOT NULL,
+    old_state order_state NOT NULL,
+    new_state order_state NOT NULL,
+    stamp_id INTEGER NOT NULL,
+
+    PRIMARY KEY (myorder_id, new_state),
+    FOREIGN KEY (myorder_id) REFERENCES myorder(myorder_id) ON UPDATE CASCADE
+);
+
+-- lock preventing speculator runs from overlapping. holder is NULL while released
+CREATE TABLE speculator_lock
+(
+    lock_id INTEGER NOT NULL PRIMARY KEY,
+    holder VARCHAR(64) NULL,
+    acquired TIMESTAMP NULL
+);
+
+INSERT INTO speculator_lock VALUES (0, NULL, NULL);
+
+-- only balance and myorder are used in simulation
+CREATE TABLE next_id
+(
+    currency INTEGER NOT NULL,
+    stamp INTEGER NOT NULL,
+    balance INTEGER NOT NULL,
+    market INTEGER NOT NULL,
+    price INTEGER NOT NULL,
+    orderbook INTEGER NOT NULL,
+    myorder INTEGER NOT NULL,
+    pending_approval INTEGER NOT NULL
+);
+
+-- First ids
+INSERT INTO next_id VALUES (0, 0, 0, 0, 0, 0, 0, 0);
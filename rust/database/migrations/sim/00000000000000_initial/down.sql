@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS next_id;
+DROP TABLE IF EXISTS speculator_lock;
+DROP TABLE IF EXISTS myorder;
+DROP TABLE IF EXISTS stop_order;
+DROP TABLE IF EXISTS balance;
@@ -0,0 +1,77 @@
+-- Tables of simulation DB. Existing tables created by docker-autotrader-db are kept as they are
+
+CREATE TABLE IF NOT EXISTS balance
+(
+    balance_id INTEGER NOT NULL PRIMARY KEY,
+    currency_id INTEGER NOT NULL,
+    stamp_id INTEGER NOT NULL,
+    available FLOAT NOT NULL,
+    pending FLOAT NOT NULL
+);
+
+-- stop orders held until triggered or expired
+CREATE TABLE IF NOT EXISTS stop_order
+(
+    stop_order_id INTEGER NOT NULL PRIMARY KEY,
+    market_id INTEGER NOT NULL,
+    side VARCHAR(4) NOT NULL,
+    order_type VARCHAR(16) NOT NULL,
+    base_quantity FLOAT NOT NULL,
+    quote_quantity FLOAT NOT NULL,
+    -- limit price, or expected fill price of stop market orders
+    price FLOAT NOT NULL,
+    trigger_price FLOAT NOT NULL,
+    -- change of quote balance when filled, including fee
+    expected_net_quote FLOAT NOT NULL,
+    -- the order is not triggered after this time
+    expiry TIMESTAMP NOT NULL
+);
+
+-- orders placed by the simulation
+CREATE TABLE IF NOT EXISTS myorder
+(
+    myorder_id INTEGER NOT NULL PRIMARY KEY,
+    -- sim-<stamp_id>-<n>
+    transaction_id VARCHAR(64) NOT NULL,
+    market_id INTEGER NOT NULL,
+    created_stamp_id INTEGER NOT NULL,
+    modified_stamp_id INTEGER NOT NULL,
+    price FLOAT NOT NULL,
+    base_quantity FLOAT NOT NULL,
+    quote_quantity FLOAT NOT NULL,
+    order_type VARCHAR(16) NOT NULL,
+    side VARCHAR(4) NOT NULL,
+    state VARCHAR(16) NOT NULL,
+
+    UNIQUE (transaction_id)
+);
+
+-- lock preventing speculator runs from overlapping. holder is NULL while released
+CREATE TABLE IF NOT EXISTS speculator_lock
+(
+    lock_id INTEGER NOT NULL PRIMARY KEY,
+    holder VARCHAR(64) NULL,
+    acquired TIMESTAMP NULL
+);
+
+INSERT INTO speculator_lock
+SELECT 0, NULL, NULL FROM DUAL
+WHERE NOT EXISTS (SELECT * FROM speculator_lock);
+
+-- only balance and myorder are used in simulation
+CREATE TABLE IF NOT EXISTS next_id
+(
+    currency INTEGER NOT NULL,
+    stamp INTEGER NOT NULL,
+    balance INTEGER NOT NULL,
+    market INTEGER NOT NULL,
+    price INTEGER NOT NULL,
+    orderbook INTEGER NOT NULL,
+    myorder INTEGER NOT NULL,
+    pending_approval INTEGER NOT NULL
+);
+
+-- First ids. Kept if the table is already seeded
+INSERT INTO next_id
+SELECT 0, 0, 0, 0, 0, 0, 0, 0 FROM DUAL
+WHERE NOT EXISTS (SELECT * FROM next_id);
@@ -0,0 +1,15 @@
+ALTER TABLE balance
+    MODIFY available FLOAT NOT NULL,
+    MODIFY pending FLOAT NOT NULL;
+
+ALTER TABLE stop_order
+    MODIFY base_quantity FLOAT NOT NULL,
+    MODIFY quote_quantity FLOAT NOT NULL,
+    MODIFY price FLOAT NOT NULL,
+    MODIFY trigger_price FLOAT NOT NULL,
+    MODIFY expected_net_quote FLOAT NOT NULL;
+
+ALTER TABLE myorder
+    MODIFY price FLOAT NOT NULL,
+    MODIFY base_quantity FLOAT NOT NULL,
+    MODIFY quote_quantity FLOAT NOT NULL;
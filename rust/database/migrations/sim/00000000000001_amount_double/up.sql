@@ -0,0 +1,16 @@
+-- FLOAT loses satoshi-scale amounts
+ALTER TABLE balance
+    MODIFY available DOUBLE NOT NULL,
+    MODIFY pending DOUBLE NOT NULL;
+
+ALTER TABLE stop_order
+    MODIFY base_quantity DOUBLE NOT NULL,
+    MODIFY quote_quantity DOUBLE NOT NULL,
+    MODIFY price DOUBLE NOT NULL,
+    MODIFY trigger_price DOUBLE NOT NULL,
+    MODIFY expected_net_quote DOUBLE NOT NULL;
+
+ALTER TABLE myorder
+    MODIFY price DOUBLE NOT NULL,
+    MODIFY base_quantity DOUBLE NOT NULL,
+    MODIFY quote_quantity DOUBLE NOT NULL;
@@ -0,0 +1,12 @@
+-- Kept if created by docker-autotrader-db
+-- state changes of orders placed by the simulation. stamp_id refers stamps of trade DB
+CREATE TABLE IF NOT EXISTS myorder_state_change
+(
+    myorder_id INTEGER NOT NULL,
+    old_state VARCHAR(16) NOT NULL,
+    new_state VARCHAR(16) NOT NULL,
+    stamp_id INTEGER NOT NULL,
+
+    PRIMARY KEY (myorder_id, new_state),
+    FOREIGN KEY (myorder_id) REFERENCES myorder(myorder_id) ON UPDATE CASCADE
+);
@@ -0,0 +1,75 @@
+//! Fill empty main DB (and sim DB if `SIM_DATABASE_URL` is set) with synthetic data.
+//! See `settings/demo/demo.env` for usage.
+use database::demo::{seed_demo_data, DemoSetting};
+use database::diesel::prelude::*;
+use database::logic::Conn;
+use database::model::*;
+use database::schema;
+use std::env;
+use std::error::Error;
+use std::str::FromStr;
+#[macro_use]
+extern crate log;
+
+fn main() {
+    dotenv::dotenv().ok();
+
+    env_logger::init();
+
+    if let Err(e) = run() {
+        error!("{}", e);
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let url = env::var("DATABASE_URL")?;
+    let conn = Conn::establish(&url)?;
+
+    let mut setting = DemoSetting::ending_at(chrono::Utc::now().naive_utc());
+    if let Ok(seed) = env::var("DEMO_SEED") {
+        setting.seed = u64::from_str(&seed)?;
+    }
+    if let Ok(days) = env::var("DEMO_DAYS") {
+        setting.days = i64::from_str(&days)?;
+    }
+
+    info!("Seeding demo data: {:?}", setting);
+    let report = seed_demo_data(&conn, &setting)?;
+    info!(
+        "Seeded {} stamps, {} prices, {} orderbooks, {} balances, {} tags",
+        report.stamps, report.prices, report.orderbooks, report.balances, report.tags
+    );
+
+    if let Ok(sim_url) = env::var("SIM_DATABASE_URL") {
+        let sim_conn = Conn::establish(&sim_url)?;
+        seed_sim_balances(&conn, &sim_conn)?;
+    }
+
+    Ok(())
+}
+
+/// Copy balances at the second latest stamp to sim DB,
+/// so that the first run of the speculator simulates the latest stamp instead of only syncing balances
+fn seed_sim_balances(conn: &Conn, sim_conn: &Conn) -> Result<(), Box<dyn Error>> {
+    let sim_balance_count = schema::balance::table.count().get_result::<i64>(sim_conn)?;
+    if sim_balance_count > 0 {
+        warn!("Sim DB already has balances, so they are kept");
+        return Ok(());
+    }
+
+    let stamp_id = schema::stamp::table
+        .select(schema::stamp::stamp_id)
+        .order(schema::stamp::timestamp.desc())
+        .offset(1)
+        .first::<StampId>(conn)?;
+    let balances = schema::balance::table
+        .filter(schema::balance::stamp_id.eq(stamp_id))
+        .load::<Balance>(conn)?;
+
+    database::diesel::insert_into(schema::balance::table)
+        .values(&balances)
+        .execute(sim_conn)?;
+    info!("Seeded {} balances of sim DB", balances.len());
+
+    Ok(())
+}
@@ -1,7 +1,11 @@
+//! Column types shared by the backends.
+//! Enums are stored as snake_case strings like `stop_limit`,
+//! in VARCHAR columns on MySQL and in enum types named by `PgType` on PostgreSQL
 pub use chrono::NaiveDateTime;
 use diesel_derive_enum::DbEnum;
 use serde::{Deserialize, Serialize};
 use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
 
 macro_rules! id_type {
     ($wrapper:tt, $inner:tt) => {
@@ -33,14 +37,21 @@ id_type!(MarketId, i32);
 id_type!(PriceId, i32);
 id_type!(OrderbookId, i32);
 id_type!(MyorderId, i32);
+id_type!(PendingApprovalId, i32);
+id_type!(StopOrderId, i32);
+id_type!(RecommendationId, i32);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DbEnum, Serialize, Deserialize)]
+#[PgType = "order_side"]
 pub enum OrderSide {
+    #[serde(alias = "buy")]
     Buy,
+    #[serde(alias = "sell")]
     Sell,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DbEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DbEnum, Serialize, Deserialize)]
+#[PgType = "order_type"]
 pub enum OrderType {
     Limit,
     Market,
@@ -48,10 +59,174 @@ pub enum OrderType {
     StopMarket,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DbEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DbEnum, Serialize, Deserialize)]
+#[PgType = "order_state"]
 pub enum OrderState {
     Opened,
     Filled,
     Cancelled,
     Error,
 }
+
+impl OrderState {
+    /// Order state only moves toward higher precedence.
+    /// Filled, Cancelled, and Error are final states.
+    pub const fn precedence(self) -> u8 {
+        match self {
+            OrderState::Opened => 0,
+            OrderState::Filled | OrderState::Cancelled | OrderState::Error => 1,
+        }
+    }
+
+    /// Return `true` if an order in this state can move to `next`
+    pub const fn can_transit_to(self, next: OrderState) -> bool {
+        self.precedence() < next.precedence()
+    }
+}
+
+/// State of an order waiting for manual approval
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DbEnum, Serialize, Deserialize)]
+#[PgType = "approval_state"]
+pub enum ApprovalState {
+    Pending,
+    Approved,
+    /// Approved, then executed
+    Executed,
+    /// Not executed before expiry
+    Expired,
+    /// Approved, but market price moved too much before execution
+    Drifted,
+}
+
+/// Producer of a stamp
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DbEnum, Serialize, Deserialize)]
+#[PgType = "stamp_source"]
+#[serde(rename_all = "snake_case")]
+pub enum StampSource {
+    /// Scraped from the exchange
+    Scraper,
+    /// Imported or backfilled from history
+    Import,
+    /// Replayed from the scraper's spool
+    Replay,
+    /// Written by coincheck
+    Coincheck,
+    Other,
+}
+
+impl StampSource {
+    pub const ALL: [StampSource; 5] = [
+        StampSource::Scraper,
+        StampSource::Import,
+        StampSource::Replay,
+        StampSource::Coincheck,
+        StampSource::Other,
+    ];
+
+    /// Same as the value stored in DB
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            StampSource::Scraper => "scraper",
+            StampSource::Import => "import",
+            StampSource::Replay => "replay",
+            StampSource::Coincheck => "coincheck",
+            StampSource::Other => "other",
+        }
+    }
+}
+
+impl Display for StampSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+impl FromStr for StampSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        StampSource::ALL
+            .iter()
+            .copied()
+            .find(|source| source.as_str() == s)
+            .ok_or_else(|| format!("Unknown stamp source: {}", s))
+    }
+}
+
+/// Direction recommended by a speculator rule, or by the aggregation of rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DbEnum, Serialize, Deserialize)]
+#[PgType = "recommendation_type"]
+pub enum RecommendationType {
+    Buy,
+    Sell,
+    /// Should not do any trade
+    Pending,
+    /// Leave determination to other rules
+    Neutral,
+}
+
+/// Kind of data synced from the exchange window by window. See `sync_cursor` table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DbEnum)]
+#[PgType = "sync_kind"]
+pub enum SyncKind {
+    /// Order history of the account
+    Myorder,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OrderState::{self, *};
+    use super::StampSource;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_order_state_transition() {
+        let table: [(OrderState, OrderState, bool); 16] = [
+            (Opened, Opened, false),
+            (Opened, Filled, true),
+            (Opened, Cancelled, true),
+            (Opened, Error, true),
+            (Filled, Opened, false),
+            (Filled, Filled, false),
+            (Filled, Cancelled, false),
+            (Filled, Error, false),
+            (Cancelled, Opened, false),
+            (Cancelled, Filled, false),
+            (Cancelled, Cancelled, false),
+            (Cancelled, Error, false),
+            (Error, Opened, false),
+            (Error, Filled, false),
+            (Error, Cancelled, false),
+            (Error, Error, false),
+        ];
+
+        for &(current, next, expected) in table.iter() {
+            assert_eq!(
+                expected,
+                current.can_transit_to(next),
+                "{:?}->{:?}",
+                current,
+                next
+            );
+        }
+    }
+
+    #[test]
+    fn test_stamp_source_mapping() {
+        let table = [
+            (StampSource::Scraper, "scraper"),
+            (StampSource::Import, "import"),
+            (StampSource::Replay, "replay"),
+            (StampSource::Coincheck, "coincheck"),
+            (StampSource::Other, "other"),
+        ];
+
+        assert_eq!(StampSource::ALL.len(), table.len());
+        for &(source, s) in table.iter() {
+            assert_eq!(s, source.as_str());
+            assert_eq!(s, source.to_string());
+            assert_eq!(Ok(source), StampSource::from_str(s));
+        }
+        assert!(StampSource::from_str("Scraper").is_err());
+    }
+}
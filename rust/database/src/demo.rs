@@ -0,0 +1,430 @@
+//! Synthetic data to try the stack without exchange API keys.
+//!
+//! Data are written through the logic functions, so DB contents look like the scraper's.
+use crate::error::{Error, LogicError, Result};
+use crate::logic::*;
+use crate::model::*;
+use crate::schema::*;
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use std::f64::consts::PI;
+
+/// Deterministic random number generator (SplitMix64).
+/// The same seed always produces the same sequence regardless of platform and dependency versions
+#[derive(Debug, Clone)]
+pub struct DemoRng {
+    state: u64,
+}
+
+impl DemoRng {
+    pub const fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Standard normal distribution by Box-Muller transform
+    pub fn next_normal(&mut self) -> f64 {
+        // (0, 1] to avoid ln(0)
+        let u1 = 1.0 - self.next_f64();
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+    }
+}
+
+/// Prices following geometric brownian motion from `initial`.
+/// `drift` and `volatility` are of log return per step.
+/// # Returns
+/// `steps` prices. The first one is `initial`
+pub fn gbm_path(
+    rng: &mut DemoRng,
+    initial: f64,
+    drift: f64,
+    volatility: f64,
+    steps: usize,
+) -> Vec<f64> {
+    let mut price = initial;
+    (0..steps)
+        .map(|i| {
+            if i > 0 {
+                let log_return = drift - volatility.powi(2) / 2.0 + volatility * rng.next_normal();
+                price *= log_return.exp();
+            }
+            price
+        })
+        .collect()
+}
+
+/// Orderbook levels around `price` as (side, price, volume).
+/// Bids are below and asks are above `price`, each level `tick_ratio` apart.
+/// Volumes are around `volume`, and grow with distance from `price`
+pub fn orderbook_around(
+    rng: &mut DemoRng,
+    price: f64,
+    levels: usize,
+    tick_ratio: f64,
+    volume: f64,
+) -> Vec<(OrderSide, Amount, Amount)> {
+    let mut orderbook = Vec::with_capacity(levels * 2);
+    for i in 1..=levels {
+        let distance = tick_ratio * i as f64;
+        for &(side, level_price) in [
+            (OrderSide::Buy, price * (1.0 - distance)),
+            (OrderSide::Sell, price * (1.0 + distance)),
+        ]
+        .iter()
+        {
+            let level_volume = volume * i as f64 * (0.5 + rng.next_f64());
+            orderbook.push((side, level_price, level_volume));
+        }
+    }
+    orderbook
+}
+
+/// A currency of demo data, priced in the fiat currency
+struct DemoCurrency {
+    symbol: &'static str,
+    name: &'static str,
+    /// Initial price in fiat
+    price: f64,
+    /// Standard deviation of daily log return
+    daily_volatility: f64,
+    /// Initial holding
+    holding: f64,
+    /// Typical volume of an orderbook level in this currency
+    level_volume: f64,
+    /// Reporting group
+    tag: &'static str,
+}
+
+/// The first one is the fiat currency
+const DEMO_CURRENCIES: &[DemoCurrency] = &[
+    DemoCurrency {
+        symbol: "USDT",
+        name: "Tether",
+        price: 1.0,
+        daily_volatility: 0.0,
+        holding: 5000.0,
+        level_volume: 1000.0,
+        tag: "stablecoin",
+    },
+    DemoCurrency {
+        symbol: "BTC",
+        name: "Bitcoin",
+        price: 30000.0,
+        daily_volatility: 0.04,
+        holding: 0.1,
+        level_volume: 0.05,
+        tag: "major",
+    },
+    DemoCurrency {
+        symbol: "ETH",
+        name: "Ethereum",
+        price: 2000.0,
+        daily_volatility: 0.05,
+        holding: 1.5,
+        level_volume: 0.5,
+        tag: "major",
+    },
+    DemoCurrency {
+        symbol: "LTC",
+        name: "Litecoin",
+        price: 150.0,
+        daily_volatility: 0.06,
+        holding: 10.0,
+        level_volume: 5.0,
+        tag: "alt",
+    },
+];
+
+/// (base, quote) symbols of demo markets
+const DEMO_MARKETS: &[(&str, &str)] = &[
+    ("BTC", "USDT"),
+    ("ETH", "USDT"),
+    ("LTC", "USDT"),
+    ("ETH", "BTC"),
+    ("LTC", "BTC"),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DemoSetting {
+    pub seed: u64,
+    pub days: i64,
+    /// Interval of stamps, like the scraper's cadence
+    pub interval: Duration,
+    /// Timestamp of the last stamp
+    pub end: NaiveDateTime,
+    /// Only the last `orderbook_stamps` stamps have orderbooks, since orderbooks are large
+    pub orderbook_stamps: usize,
+    pub orderbook_levels: usize,
+    /// Balances are added every `balance_every` stamps, and at the last two stamps
+    pub balance_every: usize,
+}
+
+impl DemoSetting {
+    /// 3 weeks of 5 minutes cadence until `end`
+    pub fn ending_at(end: NaiveDateTime) -> Self {
+        Self {
+            seed: 42,
+            days: 21,
+            interval: Duration::minutes(5),
+            end,
+            orderbook_stamps: 12,
+            orderbook_levels: 20,
+            balance_every: 12,
+        }
+    }
+
+    fn steps(&self) -> usize {
+        (Duration::days(self.days).num_seconds() / self.interval.num_seconds()) as usize + 1
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DemoReport {
+    pub stamps: usize,
+    pub prices: usize,
+    pub orderbooks: usize,
+    pub balances: usize,
+    pub tags: usize,
+}
+
+/// Fill an empty DB with synthetic currencies, tags, markets, prices, orderbooks and balances.
+/// The same `setting` always produces the same data.
+/// # Returns
+/// `Err(e)` if DB already has currencies or stamps
+pub fn seed_demo_data(conn: &Conn, setting: &DemoSetting) -> Result<DemoReport> {
+    let has_stamp = stamp::table
+        .select(stamp::stamp_id)
+        .first::<StampId>(conn)
+        .optional()?
+        .is_some();
+    if has_stamp || !list_currencies(conn)?.currencies().is_empty() {
+        return Err(LogicError::NotEmpty.into());
+    }
+
+    let mut rng = DemoRng::new(setting.seed);
+    let steps = setting.steps();
+    let step_ratio = setting.interval.num_seconds() as f64 / Duration::days(1).num_seconds() as f64;
+
+    let currencies = DEMO_CURRENCIES
+        .iter()
+        .map(|c| add_currency(conn, c.symbol.into(), c.name.into()))
+        .collect::<Result<Vec<_>>>()?;
+    for (currency, c) in currencies.iter().zip(DEMO_CURRENCIES.iter()) {
+        add_currency_tag(conn, currency.currency_id, c.tag)?;
+    }
+    // Fiat prices of each currency at each step
+    let fiat_paths = DEMO_CURRENCIES
+        .iter()
+        .map(|c| {
+            let volatility = c.daily_volatility * step_ratio.sqrt();
+            gbm_path(&mut rng, c.price, 0.0, volatility, steps)
+        })
+        .collect::<Vec<_>>();
+
+    let index_of = |symbol: &str| {
+        DEMO_CURRENCIES
+            .iter()
+            .position(|c| c.symbol == symbol)
+            .expect("Demo market refers a demo currency")
+    };
+    let markets = DEMO_MARKETS
+        .iter()
+        .map(|&(base, quote)| {
+            let (base, quote) = (index_of(base), index_of(quote));
+            let market = add_market(
+                conn,
+                currencies[base].currency_id,
+                currencies[quote].currency_id,
+            )?;
+            Ok((market, base, quote))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut holdings = DEMO_CURRENCIES
+        .iter()
+        .map(|c| c.holding)
+        .collect::<Vec<_>>();
+    let mut report = DemoReport {
+        tags: currencies.len(),
+        ..DemoReport::default()
+    };
+
+    for step in 0..steps {
+        let timestamp = setting.end - setting.interval * (steps - 1 - step) as i32;
+
+        conn.transaction::<_, Error, _>(|| {
+            let stamp = add_stamp_from(conn, timestamp, StampSource::Other)?;
+            report.stamps += 1;
+
+            for (market, base, quote) in markets.iter() {
+                // Cross rates are consistent with fiat prices, so that exchange graphs have no arbitrage
+                let price = fiat_paths[*base][step] / fiat_paths[*quote][step];
+                add_price(conn, market.market_id, stamp.stamp_id, price)?;
+                report.prices += 1;
+
+                if step + setting.orderbook_stamps >= steps {
+                    let levels = orderbook_around(
+                        &mut rng,
+                        price,
+                        setting.orderbook_levels,
+                        0.001,
+                        DEMO_CURRENCIES[*base].level_volume,
+                    );
+                    report.orderbooks +=
+                        add_orderbooks_bulk(conn, market.market_id, stamp.stamp_id, &levels)?.len();
+                }
+            }
+
+            if step % setting.balance_every == 0 || step + 2 >= steps {
+                // Occasionally sell a part of a currency for fiat, like a manual trade
+                if rng.next_f64() < 0.1 {
+                    let i = 1 + (rng.next_u64() % (holdings.len() as u64 - 1)) as usize;
+                    let sold = holdings[i] * 0.05;
+                    holdings[i] -= sold;
+                    holdings[0] += sold * fiat_paths[i][step];
+                }
+                for (currency, &holding) in currencies.iter().zip(holdings.iter()) {
+                    add_balance(conn, currency.currency_id, stamp.stamp_id, holding, 0.0)?;
+                    report.balances += 1;
+                }
+            }
+
+            Ok(())
+        })?;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_demo_rng_deterministic() {
+        let sequence = |seed| {
+            let mut rng = DemoRng::new(seed);
+            (0..100).map(|_| rng.next_u64()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(sequence(1), sequence(1));
+        assert_ne!(sequence(1), sequence(2));
+        // First output of SplitMix64 with seed 0
+        assert_eq!(0xe220_a839_7b1d_cdaf, DemoRng::new(0).next_u64());
+    }
+
+    #[test]
+    fn test_demo_rng_distribution() {
+        let mut rng = DemoRng::new(7);
+        let n = 100_000;
+
+        let uniforms = (0..n).map(|_| rng.next_f64()).collect::<Vec<_>>();
+        assert!(uniforms.iter().all(|&u| (0.0..1.0).contains(&u)));
+
+        let normals = (0..n).map(|_| rng.next_normal()).collect::<Vec<_>>();
+        let mean = normals.iter().sum::<f64>() / n as f64;
+        let variance = normals.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n as f64;
+        assert!(mean.abs() < 0.02, "{}", mean);
+        assert!((variance - 1.0).abs() < 0.02, "{}", variance);
+    }
+
+    #[test]
+    fn test_gbm_path() {
+        let volatility = 0.01;
+        let path = gbm_path(&mut DemoRng::new(3), 100.0, 0.0, volatility, 10_001);
+
+        assert_eq!(10_001, path.len());
+        assert_eq!(100.0, path[0]);
+        assert!(path.iter().all(|&p| p > 0.0));
+        assert_eq!(
+            path,
+            gbm_path(&mut DemoRng::new(3), 100.0, 0.0, volatility, 10_001)
+        );
+
+        // Standard deviation of log returns matches the volatility
+        let log_returns = path
+            .windows(2)
+            .map(|w| (w[1] / w[0]).ln())
+            .collect::<Vec<_>>();
+        let n = log_returns.len() as f64;
+        let mean = log_returns.iter().sum::<f64>() / n;
+        let sd = (log_returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / n).sqrt();
+        assert!((sd - volatility).abs() < volatility * 0.05, "{}", sd);
+    }
+
+    #[test]
+    fn test_orderbook_around() {
+        let orderbook = orderbook_around(&mut DemoRng::new(0), 100.0, 5, 0.001, 2.0);
+
+        assert_eq!(10, orderbook.len());
+        let bids = orderbook
+            .iter()
+            .filter(|(side, _, _)| *side == OrderSide::Buy)
+            .collect::<Vec<_>>();
+        let asks = orderbook
+            .iter()
+            .filter(|(side, _, _)| *side == OrderSide::Sell)
+            .collect::<Vec<_>>();
+        assert!(bids.iter().all(|(_, price, _)| *price < 100.0));
+        assert!(asks.iter().all(|(_, price, _)| *price > 100.0));
+        // The best levels come first
+        assert!(bids.windows(2).all(|w| w[0].1 > w[1].1));
+        assert!(asks.windows(2).all(|w| w[0].1 < w[1].1));
+        assert!(orderbook.iter().all(|(_, _, volume)| *volume > 0.0));
+    }
+
+    #[test]
+    fn test_demo_setting_steps() {
+        let end = chrono::NaiveDate::from_ymd(2021, 1, 22).and_hms(0, 0, 0);
+        let mut setting = DemoSetting::ending_at(end);
+        setting.days = 1;
+
+        // Both ends are included
+        assert_eq!(289, setting.steps());
+    }
+
+    /// Requires `TEST_DATABASE_URL` of an empty DB. Changes are rolled back
+    #[test]
+    #[ignore]
+    fn test_seed_demo_data() {
+        let url = std::env::var("TEST_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let end = chrono::NaiveDate::from_ymd(2037, 1, 2).and_hms(0, 0, 0);
+            let mut setting = DemoSetting::ending_at(end);
+            setting.days = 1;
+
+            let report = seed_demo_data(&conn, &setting)?;
+
+            assert_eq!(289, report.stamps);
+            assert_eq!(DEMO_CURRENCIES.len(), list_currency_tags(&conn)?.len());
+            assert_eq!(289 * DEMO_MARKETS.len(), report.prices);
+            assert_eq!(
+                setting.orderbook_stamps * DEMO_MARKETS.len() * setting.orderbook_levels * 2,
+                report.orderbooks
+            );
+            assert_eq!(
+                Some(end),
+                get_latest_stamp(&conn, false)?.map(|s| s.timestamp)
+            );
+            // Seeding twice is denied
+            assert!(seed_demo_data(&conn, &setting).is_err());
+
+            Ok(())
+        });
+    }
+}
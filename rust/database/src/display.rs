@@ -0,0 +1,97 @@
+use crate::model::Currency;
+
+/// Decimal places used when neither metadata nor exchange rate is available
+pub const DEFAULT_PRECISION: usize = 8;
+
+/// Maximum decimal places derived from exchange rate
+const MAX_PRECISION: usize = 8;
+
+/// Fiat value of the smallest displayed unit, used by the heuristic
+const SMALLEST_UNIT_VALUE: f64 = 0.001;
+
+/// Decimal places of well-known currencies.
+/// NOTE: This substitutes currency metadata which is not stored in DB yet.
+fn known_precision(symbol: &str) -> Option<usize> {
+    match symbol {
+        "BTC" | "ETH" | "LTC" | "BCH" => Some(8),
+        "USDT" | "USDC" | "TUSD" => Some(6),
+        "XRP" | "XLM" => Some(6),
+        "DOGE" => Some(2),
+        _ => None,
+    }
+}
+
+/// Derive decimal places so that the smallest displayed unit is worth about 0.001 in fiat.
+/// e.g. 8 for 30000 (BTC/USDT), 4 for 2, 0 for 0.0001
+pub fn precision_from_rate(fiat_rate: f64) -> Option<usize> {
+    if !fiat_rate.is_finite() || fiat_rate <= 0.0 {
+        return None;
+    }
+
+    let precision = (fiat_rate / SMALLEST_UNIT_VALUE).log10().ceil();
+    Some(precision.max(0.0).min(MAX_PRECISION as f64) as usize)
+}
+
+/// Decimal places to display amounts of `currency`.
+/// Known precision is preferred, then the one derived from `fiat_rate`.
+pub fn display_precision(currency: &Currency, fiat_rate: Option<f64>) -> usize {
+    known_precision(&currency.symbol)
+        .or_else(|| fiat_rate.and_then(precision_from_rate))
+        .unwrap_or(DEFAULT_PRECISION)
+}
+
+pub fn format_amount(currency: &Currency, amount: f64) -> String {
+    format_amount_with_rate(currency, amount, None)
+}
+
+/// Same as `format_amount`, but unknown currency's precision is derived from `fiat_rate`
+pub fn format_amount_with_rate(currency: &Currency, amount: f64, fiat_rate: Option<f64>) -> String {
+    let precision = display_precision(currency, fiat_rate);
+    format!("{:.*}", precision, amount)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_sql_type::CurrencyId;
+
+    fn currency(symbol: &str) -> Currency {
+        Currency::new(CurrencyId::new(0), symbol.into(), symbol.into())
+    }
+
+    #[test]
+    fn test_precision_from_rate() {
+        assert_eq!(Some(8), precision_from_rate(30000.0));
+        assert_eq!(Some(5), precision_from_rate(100.0));
+        assert_eq!(Some(4), precision_from_rate(2.0));
+        assert_eq!(Some(2), precision_from_rate(0.05));
+        assert_eq!(Some(0), precision_from_rate(0.0001));
+
+        assert_eq!(None, precision_from_rate(0.0));
+        assert_eq!(None, precision_from_rate(-1.0));
+        assert_eq!(None, precision_from_rate(f64::NAN));
+    }
+
+    #[test]
+    fn test_format_amount_known() {
+        assert_eq!("0.12345678", format_amount(&currency("BTC"), 0.123456789));
+        assert_eq!("12.345679", format_amount(&currency("USDT"), 12.3456789));
+        assert_eq!("1234.57", format_amount(&currency("DOGE"), 1234.5678));
+    }
+
+    #[test]
+    fn test_format_amount_unknown() {
+        // Derived from rate
+        assert_eq!(
+            "12.34568",
+            format_amount_with_rate(&currency("FOO"), 12.3456789, Some(20.0))
+        );
+        // Known precision is preferred to rate
+        assert_eq!(
+            "0.00000001",
+            format_amount_with_rate(&currency("BTC"), 0.00000001, Some(0.01))
+        );
+        // Fallback
+        assert_eq!("1.00000000", format_amount(&currency("FOO"), 1.0));
+    }
+}
@@ -8,6 +8,33 @@ pub enum LogicError {
     DuplicatedCurrency,
     #[error("DuplicatedMarket")]
     DuplicatedMarket,
+    #[error("DuplicatedPrice")]
+    DuplicatedPrice,
+    #[error("DuplicatedRecommendation")]
+    DuplicatedRecommendation,
+    #[error("Approval not found")]
+    ApprovalNotFound,
+    #[error("Approval is not pending")]
+    ApprovalNotPending,
+    #[error("Approval expired")]
+    ApprovalExpired,
+    #[error("DB is not empty")]
+    NotEmpty,
+    #[error("Invalid tag")]
+    InvalidTag,
+}
+
+/// Failure to resolve a `BASE-QUOTE` pair to currencies and a market
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum SymbolResolutionError {
+    #[error("Malformed market pair: {0:?}")]
+    MalformedPair(String),
+    #[error("Unknown base currency: {0}")]
+    UnknownBaseCurrency(String),
+    #[error("Unknown quote currency: {0}")]
+    UnknownQuoteCurrency(String),
+    #[error("Unknown market: {0}")]
+    UnknownMarket(String),
 }
 
 #[derive(Debug, Error)]
@@ -16,6 +43,8 @@ pub enum Error {
     Db(diesel::result::Error),
     #[error("Logic error: {0}")]
     Logic(LogicError),
+    #[error("Migration error: {0}")]
+    Migration(diesel_migrations::RunMigrationsError),
 }
 
 impl From<diesel::result::Error> for Error {
@@ -24,6 +53,12 @@ impl From<diesel::result::Error> for Error {
     }
 }
 
+impl From<diesel_migrations::RunMigrationsError> for Error {
+    fn from(e: diesel_migrations::RunMigrationsError) -> Self {
+        Error::Migration(e)
+    }
+}
+
 impl From<LogicError> for Error {
     fn from(e: LogicError) -> Self {
         Error::Logic(e)
@@ -0,0 +1,78 @@
+use crate::model::{Amount, CurrencyId, Market, Price};
+use apply::Apply;
+pub use common::exchange_graph::{CycleReport, ExchangeGraph};
+
+/// Construct an exchange graph of currencies from prices of markets
+pub trait FromPrices {
+    fn from_prices(prices: &[(Price, Market)]) -> Self;
+}
+
+impl FromPrices for ExchangeGraph<CurrencyId> {
+    /// Each price is a rate from base to quote of its market.
+    /// Prices which are not positive finite numbers are ignored, since their inverse rates are meaningless
+    fn from_prices(prices: &[(Price, Market)]) -> Self {
+        prices
+            .iter()
+            .filter(|(p, _)| p.amount.is_finite() && p.amount > 0.0)
+            .map(|(p, m)| (m.base_id, m.quote_id, p.amount))
+            .apply(ExchangeGraph::from_rates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{MarketId, PriceId, StampId};
+
+    const BTC: CurrencyId = CurrencyId::new(1);
+    const ETH: CurrencyId = CurrencyId::new(2);
+    const USDT: CurrencyId = CurrencyId::new(3);
+
+    fn price(
+        market_id: i32,
+        base_id: CurrencyId,
+        quote_id: CurrencyId,
+        amount: Amount,
+    ) -> (Price, Market) {
+        let market_id = MarketId::new(market_id);
+        let price = Price::new(
+            PriceId::new(market_id.inner()),
+            market_id,
+            StampId::new(1),
+            amount,
+        );
+        (price, Market::new(market_id, base_id, quote_id))
+    }
+
+    #[test]
+    fn test_from_prices() {
+        let prices = vec![price(1, BTC, USDT, 40000.0), price(2, ETH, BTC, 0.0625)];
+        let graph = ExchangeGraph::from_prices(&prices);
+
+        assert_eq!(Some(40000.0), graph.rate_between(BTC, USDT));
+        assert_eq!(Some(1.0 / 40000.0), graph.rate_between(USDT, BTC));
+        assert_eq!(Some(2500.0), graph.rate_between(ETH, USDT));
+    }
+
+    #[test]
+    fn test_from_prices_ignores_invalid_price() {
+        let prices = vec![
+            price(1, BTC, USDT, 0.0),
+            price(2, ETH, USDT, Amount::NAN),
+            price(3, ETH, BTC, 0.0625),
+        ];
+        let graph = ExchangeGraph::from_prices(&prices);
+
+        assert_eq!(None, graph.rate_between(BTC, USDT));
+        assert_eq!(None, graph.rate_between(USDT, ETH));
+        assert_eq!(Some(16.0), graph.rate_between(BTC, ETH));
+    }
+
+    #[test]
+    fn test_from_prices_empty() {
+        let graph = ExchangeGraph::from_prices(&[]);
+
+        assert_eq!(Some(1.0), graph.rate_between(BTC, BTC));
+        assert_eq!(None, graph.rate_between(BTC, USDT));
+    }
+}
@@ -2,9 +2,18 @@
 pub extern crate diesel;
 #[macro_use]
 extern crate diesel_derive_newtype;
+#[macro_use]
+extern crate diesel_migrations;
 
 pub mod custom_sql_type;
+pub mod demo;
+pub mod display;
 pub mod error;
+pub mod exchange;
 pub mod logic;
+pub mod migration;
 pub mod model;
+pub mod order;
 pub mod schema;
+
+pub use migration::{run_migrations, run_sim_migrations};
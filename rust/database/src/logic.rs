@@ -1,19 +1,54 @@
-use crate::error::{Error, LogicError, Result};
+use crate::error::{Error, LogicError, Result, SymbolResolutionError};
 use crate::model::*;
+use crate::order::ExchangeOrder;
 use crate::schema::*;
 use apply::Apply;
 use chrono::NaiveDateTime;
 use diesel::expression::dsl::exists;
 use diesel::prelude::*;
+use std::collections::{HashMap, HashSet};
 
+#[cfg(not(any(feature = "mysql", feature = "postgres")))]
+compile_error!("Either feature mysql or postgres of database crate is required");
+
+/// Connection of the backend selected by features. PostgreSQL takes precedence if both are enabled
+#[cfg(feature = "postgres")]
+pub type Conn = diesel::pg::PgConnection;
+#[cfg(all(feature = "mysql", not(feature = "postgres")))]
 pub type Conn = diesel::mysql::MysqlConnection;
 
+pub type Backend = <Conn as Connection>::Backend;
+
+/// Insert `$values` into `$table`, or replace the row having the same primary key `$key`.
+/// PostgreSQL lacks `REPLACE`, so the row is updated by `ON CONFLICT` instead,
+/// which requires `AsChangeset` of `$values` excluding `$key`
+#[cfg(not(feature = "postgres"))]
+macro_rules! replace_row {
+    ($table:expr, $key:expr, $values:expr) => {
+        diesel::replace_into($table).values($values)
+    };
+}
+#[cfg(feature = "postgres")]
+macro_rules! replace_row {
+    ($table:expr, $key:expr, $values:expr) => {
+        diesel::insert_into($table)
+            .values($values)
+            .on_conflict($key)
+            .do_update()
+            .set($values)
+    };
+}
+
 #[derive(Debug, Clone)]
 pub struct CurrencyCollection {
     currencies: Vec<Currency>,
 }
 
 impl CurrencyCollection {
+    pub fn new(currencies: Vec<Currency>) -> Self {
+        Self { currencies }
+    }
+
     pub fn currencies(&self) -> &[Currency] {
         self.currencies.as_slice()
     }
@@ -24,9 +59,35 @@ impl CurrencyCollection {
             .find(|c| c.currency_id == currency_id)
     }
 
+    /// Active currency of `symbol`. Use `by_symbol_including_inactive` to resolve currencies of historical data
     pub fn by_symbol<S: AsRef<str>>(&self, symbol: S) -> Option<&Currency> {
+        self.by_symbol_including_inactive(symbol)
+            .filter(|c| c.active)
+    }
+
+    pub fn by_symbol_including_inactive<S: AsRef<str>>(&self, symbol: S) -> Option<&Currency> {
         self.currencies.iter().find(|c| c.symbol == symbol.as_ref())
     }
+
+    /// Collection of active currencies only
+    pub fn active(&self) -> CurrencyCollection {
+        let currencies = self
+            .currencies
+            .iter()
+            .filter(|c| c.active)
+            .cloned()
+            .collect();
+        CurrencyCollection { currencies }
+    }
+
+    /// Symbols of active currencies, or of all currencies if `include_inactive`
+    pub fn symbols(&self, include_inactive: bool) -> Vec<String> {
+        self.currencies
+            .iter()
+            .filter(|c| include_inactive || c.active)
+            .map(|c| c.symbol.clone())
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -35,6 +96,10 @@ pub struct MarketCollection {
 }
 
 impl MarketCollection {
+    pub fn new(markets: Vec<Market>) -> Self {
+        Self { markets }
+    }
+
     pub fn markets(&self) -> &[Market] {
         self.markets.as_slice()
     }
@@ -56,6 +121,62 @@ impl MarketCollection {
     }
 }
 
+/// Split `BASE-QUOTE` into (base, quote)
+pub fn parse_market_pair(pair: &str) -> std::result::Result<(&str, &str), SymbolResolutionError> {
+    let mut symbols = pair.trim().split('-');
+    match (symbols.next(), symbols.next(), symbols.next()) {
+        (Some(base), Some(quote), None) if !base.is_empty() && !quote.is_empty() => {
+            Ok((base, quote))
+        }
+        _ => Err(SymbolResolutionError::MalformedPair(pair.to_string())),
+    }
+}
+
+/// Resolve `BASE-QUOTE` to its currencies and market.
+/// Currencies are resolved regardless of activity. Pass `CurrencyCollection::active` to exclude delisted ones
+pub fn resolve_market_symbol(
+    pair: &str,
+    currencies: &CurrencyCollection,
+    markets: &MarketCollection,
+) -> std::result::Result<(Currency, Currency, Market), SymbolResolutionError> {
+    let (base_symbol, quote_symbol) = parse_market_pair(pair)?;
+    let base = currencies
+        .by_symbol_including_inactive(base_symbol)
+        .ok_or_else(|| SymbolResolutionError::UnknownBaseCurrency(base_symbol.to_string()))?;
+    let quote = currencies
+        .by_symbol_including_inactive(quote_symbol)
+        .ok_or_else(|| SymbolResolutionError::UnknownQuoteCurrency(quote_symbol.to_string()))?;
+    let market = markets
+        .by_base_quote_id(base.currency_id, quote.currency_id)
+        .ok_or_else(|| SymbolResolutionError::UnknownMarket(pair.trim().to_string()))?;
+
+    Ok((base.clone(), quote.clone(), market.clone()))
+}
+
+/// Resolve `BASE-QUOTE` pairs separated by `:` by `resolve_market_symbol`. Empty pairs are ignored.
+/// # Returns
+/// Resolved markets, and errors of the other pairs. Both are in the order of `s`
+pub fn resolve_market_symbols(
+    s: &str,
+    currencies: &CurrencyCollection,
+    markets: &MarketCollection,
+) -> (
+    Vec<(Currency, Currency, Market)>,
+    Vec<SymbolResolutionError>,
+) {
+    let mut resolved = vec![];
+    let mut errors = vec![];
+
+    for pair in s.split(':').filter(|pair| !pair.trim().is_empty()) {
+        match resolve_market_symbol(pair, currencies, markets) {
+            Ok(market) => resolved.push(market),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    (resolved, errors)
+}
+
 pub fn list_currencies(conn: &Conn) -> Result<CurrencyCollection> {
     currency::table
         .load(conn)
@@ -96,7 +217,146 @@ pub fn add_currency(conn: &Conn, symbol: String, name: String) -> Result<Currenc
     Ok(currency)
 }
 
+/// Currencies whose `active` flag changes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CurrencyActivityChange {
+    pub deactivated: Vec<Currency>,
+    pub reactivated: Vec<Currency>,
+}
+
+/// Plan to deactivate currencies absent from `listed_symbols` and reactivate the ones listed again.
+/// Nothing changes if `listed_symbols` is empty, since an empty listing is more likely a broken response than delisting of everything.
+pub fn plan_currency_activity<S: AsRef<str>>(
+    currencies: &[Currency],
+    listed_symbols: &[S],
+) -> CurrencyActivityChange {
+    if listed_symbols.is_empty() {
+        return CurrencyActivityChange::default();
+    }
+
+    let is_listed = |c: &Currency| listed_symbols.iter().any(|s| s.as_ref() == c.symbol);
+    CurrencyActivityChange {
+        deactivated: currencies
+            .iter()
+            .filter(|c| c.active && !is_listed(c))
+            .cloned()
+            .collect(),
+        reactivated: currencies
+            .iter()
+            .filter(|c| !c.active && is_listed(c))
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Update `active` flag of currencies by the exchange's current listing.
+/// See `plan_currency_activity` for details.
+pub fn update_currency_activity<S: AsRef<str>>(
+    conn: &Conn,
+    listed_symbols: &[S],
+) -> Result<CurrencyActivityChange> {
+    let currencies = currency::table.load::<Currency>(conn)?;
+    let change = plan_currency_activity(&currencies, listed_symbols);
+
+    conn.transaction::<(), Error, _>(|| {
+        for (currencies, active) in vec![(&change.deactivated, false), (&change.reactivated, true)]
+        {
+            let ids = currencies.iter().map(|c| c.currency_id).collect::<Vec<_>>();
+            if !ids.is_empty() {
+                currency::table
+                    .filter(currency::currency_id.eq_any(ids))
+                    .apply(diesel::update)
+                    .set(currency::active.eq(active))
+                    .execute(conn)?;
+            }
+        }
+        Ok(())
+    })?;
+
+    Ok(change)
+}
+
+/// Group of currencies without tags
+pub const UNTAGGED: &str = "untagged";
+/// Length limit of `currency_tag.tag`
+const MAX_TAG_LEN: usize = 32;
+
+/// Tags are 1 to 32 characters of lowercase letters, digits, `_` and `-`. `untagged` is reserved
+pub fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag.len() <= MAX_TAG_LEN
+        && tag != UNTAGGED
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+/// Tag a currency. Adding an existing tag does nothing
+pub fn add_currency_tag(conn: &Conn, currency_id: CurrencyId, tag: &str) -> Result<()> {
+    if !is_valid_tag(tag) {
+        return Err(LogicError::InvalidTag.into());
+    }
+
+    let currency_tag = CurrencyTag {
+        currency_id,
+        tag: tag.into(),
+    };
+    // The row has only the primary key, so nothing is left to replace
+    #[cfg(not(feature = "postgres"))]
+    diesel::replace_into(currency_tag::table)
+        .values(currency_tag)
+        .execute(conn)?;
+    #[cfg(feature = "postgres")]
+    diesel::insert_into(currency_tag::table)
+        .values(currency_tag)
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    Ok(())
+}
+
+/// # Returns
+/// `Ok(false)` if the currency doesn't have `tag`
+pub fn remove_currency_tag(conn: &Conn, currency_id: CurrencyId, tag: &str) -> Result<bool> {
+    let removed = currency_tag::table
+        .filter(currency_tag::currency_id.eq(currency_id))
+        .filter(currency_tag::tag.eq(tag))
+        .apply(diesel::delete)
+        .execute(conn)?;
+    Ok(removed > 0)
+}
+
+/// All tags, ordered by currency and tag
+pub fn list_currency_tags(conn: &Conn) -> Result<Vec<CurrencyTag>> {
+    currency_tag::table
+        .order((currency_tag::currency_id.asc(), currency_tag::tag.asc()))
+        .load(conn)
+        .map_err(Into::into)
+}
+
+/// The tag each currency is grouped by.
+/// A currency having several tags is grouped by its alphabetically first tag only,
+/// so that a value is never counted in more than one group.
+/// Currencies absent from the result belong to `UNTAGGED`
+pub fn primary_currency_tags(tags: &[CurrencyTag]) -> HashMap<CurrencyId, String> {
+    let mut primary_tags = HashMap::<CurrencyId, String>::new();
+    for tag in tags.iter() {
+        match primary_tags.get(&tag.currency_id) {
+            Some(primary) if *primary <= tag.tag => {}
+            _ => {
+                primary_tags.insert(tag.currency_id, tag.tag.clone());
+            }
+        }
+    }
+    primary_tags
+}
+
+/// Add a stamp of the scraper
 pub fn add_stamp(conn: &Conn, timestamp: NaiveDateTime) -> Result<Stamp> {
+    add_stamp_from(conn, timestamp, StampSource::Scraper)
+}
+
+/// Add a stamp produced by `source`
+pub fn add_stamp_from(conn: &Conn, timestamp: NaiveDateTime, source: StampSource) -> Result<Stamp> {
     // Deny non latest timestamp.
     // This system allow only to add newer data
     let latest_stamp = stamp::table
@@ -110,7 +370,7 @@ pub fn add_stamp(conn: &Conn, timestamp: NaiveDateTime) -> Result<Stamp> {
     }
 
     let stamp_id = next_id::table.select(next_id::stamp).first(conn)?;
-    let stamp = Stamp::new(stamp_id, timestamp);
+    let stamp = Stamp::with_source(stamp_id, timestamp, source);
 
     conn.transaction::<(), Error, _>(|| {
         next_id::table
@@ -136,27 +396,57 @@ pub fn add_balance(
     available: Amount,
     pending: Amount,
 ) -> Result<Balance> {
-    let balance_id = next_id::table.select(next_id::balance).first(conn)?;
+    let balance = add_balances_bulk(conn, stamp_id, &[(currency_id, available, pending)])?
+        .pop()
+        .expect("One balance is added");
 
-    let balance = Balance::new(balance_id, currency_id, stamp_id, available, pending);
+    Ok(balance)
+}
 
-    conn.transaction::<(), Error, _>(|| {
-        // Update next id
+/// Add balances of a stamp at once.
+/// `items` are (currency, available, pending), and are inserted by one statement.
+///
+/// A contiguous id range is reserved by one `next_id` update in the same transaction,
+/// so no id is consumed if the insert fails.
+pub fn add_balances_bulk(
+    conn: &Conn,
+    stamp_id: StampId,
+    items: &[(CurrencyId, Amount, Amount)],
+) -> Result<Vec<Balance>> {
+    if items.is_empty() {
+        return Ok(vec![]);
+    }
+
+    conn.transaction::<_, Error, _>(|| {
+        // Lock the row so that concurrent writers don't reserve the same range
+        let first_id: BalanceId = next_id::table
+            .select(next_id::balance)
+            .for_update()
+            .first(conn)?;
+
+        let balances = items
+            .iter()
+            .enumerate()
+            .map(|(i, &(currency_id, available, pending))| {
+                let balance_id = BalanceId::new(first_id.inner() + i as i32);
+                Balance::new(balance_id, currency_id, stamp_id, available, pending)
+            })
+            .collect::<Vec<_>>();
+
+        // Reserve ids
         next_id::table
             .apply(diesel::update)
-            .set(next_id::balance.eq(next_id::balance + 1))
+            .set(next_id::balance.eq(next_id::balance + items.len() as i32))
             .execute(conn)?;
 
-        // Add balance
+        // Add balances
         balance::table
             .apply(diesel::insert_into)
-            .values(&balance)
+            .values(&balances)
             .execute(conn)?;
 
-        Ok(())
-    })?;
-
-    Ok(balance)
+        Ok(balances)
+    })
 }
 
 pub fn list_markets(conn: &Conn) -> Result<MarketCollection> {
@@ -204,33 +494,106 @@ pub fn add_market(
     Ok(market)
 }
 
+/// # Returns
+/// `Err(LogicError::DuplicatedPrice)` if the market already has a price at `stamp_id`
 pub fn add_price(
     conn: &Conn,
     market_id: MarketId,
     stamp_id: StampId,
     amount: Amount,
 ) -> Result<Price> {
-    let price_id = next_id::table.select(next_id::price).first(conn)?;
+    let price = add_prices_bulk(conn, stamp_id, &[(market_id, amount)])?
+        .pop()
+        .expect("One price is added");
+
+    Ok(price)
+}
 
-    let price = Price::new(price_id, market_id, stamp_id, amount);
+/// Add prices of a stamp at once.
+/// `items` are (market, price), and are inserted by one statement.
+///
+/// A contiguous id range is reserved by one `next_id` update in the same transaction,
+/// so no id is consumed if the insert fails.
+///
+/// A market has at most one price per stamp.
+/// `Err(LogicError::DuplicatedPrice)` is returned and nothing is added
+/// if any of the markets already has a price at `stamp_id`, or is repeated in `items`.
+pub fn add_prices_bulk(
+    conn: &Conn,
+    stamp_id: StampId,
+    items: &[(MarketId, Amount)],
+) -> Result<Vec<Price>> {
+    if items.is_empty() {
+        return Ok(vec![]);
+    }
 
-    conn.transaction::<(), Error, _>(|| {
-        // Update next id
+    let market_ids = items
+        .iter()
+        .map(|&(market_id, _)| market_id)
+        .collect::<Vec<_>>();
+    let already_exists = price::table
+        .filter(price::stamp_id.eq(stamp_id))
+        .filter(price::market_id.eq_any(&market_ids))
+        .apply(exists)
+        .apply(diesel::select)
+        .get_result(conn)?;
+    let repeated = market_ids.iter().collect::<HashSet<_>>().len() < market_ids.len();
+    if already_exists || repeated {
+        return Err(LogicError::DuplicatedPrice.into());
+    }
+
+    let ret = conn.transaction::<_, Error, _>(|| {
+        // Lock the row so that concurrent writers don't reserve the same range
+        let first_id: PriceId = next_id::table
+            .select(next_id::price)
+            .for_update()
+            .first(conn)?;
+
+        let prices = items
+            .iter()
+            .enumerate()
+            .map(|(i, &(market_id, amount))| {
+                Price::new(
+                    PriceId::new(first_id.inner() + i as i32),
+                    market_id,
+                    stamp_id,
+                    amount,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // Reserve ids
         next_id::table
             .apply(diesel::update)
-            .set(next_id::price.eq(next_id::price + 1))
+            .set(next_id::price.eq(next_id::price + items.len() as i32))
             .execute(conn)?;
 
-        // Add price
+        // Add prices
         price::table
             .apply(diesel::insert_into)
-            .values(&price)
+            .values(&prices)
             .execute(conn)?;
 
-        Ok(())
-    })?;
+        Ok(prices)
+    });
 
-    Ok(price)
+    // Unique index on (market_id, stamp_id) rejects a concurrent writer passing the check above
+    match ret {
+        Err(Error::Db(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        ))) => Err(LogicError::DuplicatedPrice.into()),
+        ret => ret,
+    }
+}
+
+/// Markets having a price at `stamp_id`
+pub fn list_priced_market_ids(conn: &Conn, stamp_id: StampId) -> Result<Vec<MarketId>> {
+    price::table
+        .filter(price::stamp_id.eq(stamp_id))
+        .select(price::market_id)
+        .load(conn)
+        .map_err(Into::into)
 }
 
 pub fn add_orderbook(
@@ -241,36 +604,123 @@ pub fn add_orderbook(
     price: Amount,
     volume: Amount,
 ) -> Result<Orderbook> {
-    let orderbook_id = next_id::table.select(next_id::orderbook).first(conn)?;
+    let orderbook = add_orderbooks_bulk(conn, market_id, stamp_id, &[(side, price, volume)])?
+        .pop()
+        .expect("One orderbook is added");
 
-    let orderbook = Orderbook {
-        orderbook_id,
-        market_id,
-        stamp_id,
-        side,
-        price,
-        volume,
-    };
+    Ok(orderbook)
+}
 
-    conn.transaction::<(), Error, _>(|| {
-        // Update next id
+/// Add orderbook levels of a market at once.
+/// `items` are (side, price, volume), and are inserted by one statement.
+///
+/// A contiguous id range is reserved by one `next_id` update in the same transaction,
+/// so no id is consumed if the insert fails.
+pub fn add_orderbooks_bulk(
+    conn: &Conn,
+    market_id: MarketId,
+    stamp_id: StampId,
+    items: &[(OrderSide, Amount, Amount)],
+) -> Result<Vec<Orderbook>> {
+    if items.is_empty() {
+        return Ok(vec![]);
+    }
+
+    conn.transaction::<_, Error, _>(|| {
+        // Lock the row so that concurrent writers don't reserve the same range
+        let first_id: OrderbookId = next_id::table
+            .select(next_id::orderbook)
+            .for_update()
+            .first(conn)?;
+
+        let orderbooks = items
+            .iter()
+            .enumerate()
+            .map(|(i, &(side, price, volume))| Orderbook {
+                orderbook_id: OrderbookId::new(first_id.inner() + i as i32),
+                market_id,
+                stamp_id,
+                side,
+                price,
+                volume,
+            })
+            .collect::<Vec<_>>();
+
+        // Reserve ids
         next_id::table
             .apply(diesel::update)
-            .set(next_id::orderbook.eq(next_id::orderbook + 1))
+            .set(next_id::orderbook.eq(next_id::orderbook + items.len() as i32))
             .execute(conn)?;
 
-        // Add orderbook
+        // Add orderbooks
         orderbook::table
             .apply(diesel::insert_into)
-            .values(&orderbook)
+            .values(&orderbooks)
             .execute(conn)?;
 
-        Ok(())
-    })?;
+        Ok(orderbooks)
+    })
+}
 
-    Ok(orderbook)
+/// Conditions of `list_myorders`. `None` fields don't filter orders
+#[derive(Debug, Clone, Default)]
+pub struct MyorderFilter {
+    pub market_id: Option<MarketId>,
+    pub state: Option<OrderState>,
+    /// Orders created or modified between these stamps (inclusive)
+    pub stamp_range: Option<(StampId, StampId)>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// List myorders matching `filter`, ordered by modified stamp, then by myorder id
+pub fn list_myorders(conn: &Conn, filter: MyorderFilter) -> Result<Vec<MyOrder>> {
+    let mut query = myorder::table.into_boxed();
+
+    if let Some(market_id) = filter.market_id {
+        query = query.filter(myorder::market_id.eq(market_id));
+    }
+    if let Some(state) = filter.state {
+        query = query.filter(myorder::state.eq(state));
+    }
+    if let Some((first, last)) = filter.stamp_range {
+        query = query.filter(
+            myorder::created_stamp_id
+                .between(first, last)
+                .or(myorder::modified_stamp_id.between(first, last)),
+        );
+    }
+    // MySQL doesn't accept OFFSET without LIMIT
+    match (filter.limit, filter.offset) {
+        (Some(limit), offset) => query = query.limit(limit).offset(offset.unwrap_or(0)),
+        (None, Some(offset)) => query = query.limit(i64::MAX).offset(offset),
+        (None, None) => {}
+    }
+
+    query
+        .order((myorder::modified_stamp_id.asc(), myorder::myorder_id.asc()))
+        .load::<MyOrder>(conn)
+        .map_err(Into::into)
+}
+
+/// Result of `add_or_update_myorder`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MyorderUpsert {
+    /// New order was added
+    Inserted,
+    /// State of existing order was updated
+    Updated,
+    /// Existing order already has the same state
+    Unchanged,
+    /// Given state is older than the stored one, so it was ignored
+    IgnoredStale,
 }
 
+/// Add order, or update state of the order which has the same `transaction_id`.
+///
+/// Order state never goes back, e.g. filled order is never updated to opened
+/// even if outdated order info is given.
+/// Each update of the state is recorded in `myorder_state_change` together.
 pub fn add_or_update_myorder(
     conn: &Conn,
     transaction_id: String,
@@ -282,60 +732,2520 @@ pub fn add_or_update_myorder(
     order_type: OrderType,
     side: OrderSide,
     state: OrderState,
-) -> Result<()> {
-    let already_exists = myorder::table
-        .filter(myorder::transaction_id.eq(&transaction_id))
-        .apply(exists)
-        .apply(diesel::select)
-        .get_result(conn)?;
+) -> Result<MyorderUpsert> {
+    let inserted = conn.transaction::<(), Error, _>(|| {
+        // Lock the row so that concurrent writers don't reserve the same id
+        let myorder_id = next_id::table
+            .select(next_id::myorder)
+            .for_update()
+            .first(conn)?;
 
-    if already_exists {
-        // If order state changed, update order state.
-        // Otherwise, nothing executed.
-        return myorder::table
-            .filter(myorder::transaction_id.eq(&transaction_id))
-            .filter(myorder::state.ne(state))
+        let myorder = MyOrder {
+            myorder_id,
+            transaction_id: transaction_id.clone(),
+            market_id,
+            created_stamp_id: now_stamp_id,
+            modified_stamp_id: now_stamp_id,
+            price,
+            base_quantity,
+            quote_quantity,
+            order_type,
+            side,
+            state,
+        };
+
+        // Reserve id
+        next_id::table
             .apply(diesel::update)
-            .set((
-                myorder::modified_stamp_id.eq(now_stamp_id),
-                myorder::state.eq(&state),
-            ))
-            .execute(conn)
-            .map(|_| ())
-            .map_err(Into::into);
+            .set(next_id::myorder.eq(next_id::myorder + 1))
+            .execute(conn)?;
+
+        // Add order. Fails if the transaction id already exists
+        myorder::table
+            .apply(diesel::insert_into)
+            .values(&myorder)
+            .execute(conn)?;
+
+        Ok(())
+    });
+
+    // Only the unique key on transaction_id means the order is recorded already, e.g. by a concurrent writer
+    match inserted {
+        Ok(()) => Ok(MyorderUpsert::Inserted),
+        Err(Error::Db(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            info,
+        ))) if is_transaction_id_violation(info.as_ref()) => {
+            update_myorder_state(conn, &transaction_id, now_stamp_id, state)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether a unique violation of `myorder` is of `transaction_id`.
+/// PostgreSQL names the key `myorder_transaction_id_key`,
+/// while MySQL reports it only in the message like `Duplicate entry 'x' for key 'myorder.transaction_id'`
+fn is_transaction_id_violation(info: &dyn diesel::result::DatabaseErrorInformation) -> bool {
+    match info.constraint_name() {
+        Some(constraint_name) => constraint_name == "myorder_transaction_id_key",
+        None => info.message().ends_with("transaction_id'"),
+    }
+}
+
+/// `add_or_update_myorder` of `order` reported by the exchange for `market_id`
+pub fn add_or_update_exchange_order(
+    conn: &Conn,
+    order: ExchangeOrder,
+    market_id: MarketId,
+    now_stamp_id: StampId,
+) -> Result<MyorderUpsert> {
+    add_or_update_myorder(
+        conn,
+        order.transaction_id,
+        market_id,
+        now_stamp_id,
+        order.price,
+        order.base_quantity,
+        order.quote_quantity,
+        order.order_type,
+        order.side,
+        order.state,
+    )
+}
+
+fn update_myorder_state(
+    conn: &Conn,
+    transaction_id: &str,
+    now_stamp_id: StampId,
+    state: OrderState,
+) -> Result<MyorderUpsert> {
+    loop {
+        let (myorder_id, current_state) = myorder::table
+            .filter(myorder::transaction_id.eq(transaction_id))
+            .select((myorder::myorder_id, myorder::state))
+            .first::<(MyorderId, OrderState)>(conn)?;
+
+        if current_state == state {
+            return Ok(MyorderUpsert::Unchanged);
+        }
+        if !current_state.can_transit_to(state) {
+            return Ok(MyorderUpsert::IgnoredStale);
+        }
+
+        let updated = conn.transaction::<_, Error, _>(|| {
+            // Update only if no one changed the state since it was read
+            let updated_count = myorder::table
+                .filter(myorder::transaction_id.eq(transaction_id))
+                .filter(myorder::state.eq(current_state))
+                .apply(diesel::update)
+                .set((
+                    myorder::modified_stamp_id.eq(now_stamp_id),
+                    myorder::state.eq(state),
+                ))
+                .execute(conn)?;
+            if updated_count == 0 {
+                return Ok(false);
+            }
+
+            let change = MyorderStateChange {
+                myorder_id,
+                old_state: current_state,
+                new_state: state,
+                stamp_id: now_stamp_id,
+            };
+            myorder_state_change::table
+                .apply(diesel::insert_into)
+                .values(&change)
+                .execute(conn)?;
+
+            Ok(true)
+        })?;
+
+        if updated {
+            return Ok(MyorderUpsert::Updated);
+        }
     }
+}
+
+/// State changes of orders of `market_id` found at `since_stamp` or later, with the timestamps of their stamps.
+/// Sorted by stamp, then by order
+pub fn load_order_state_changes(
+    conn: &Conn,
+    market_id: MarketId,
+    since_stamp: StampId,
+) -> Result<Vec<(MyorderStateChange, NaiveDateTime)>> {
+    let myorder_ids = myorder::table
+        .filter(myorder::market_id.eq(market_id))
+        .select(myorder::myorder_id);
+
+    myorder_state_change::table
+        .inner_join(stamp::table)
+        .filter(myorder_state_change::myorder_id.eq_any(myorder_ids))
+        .filter(myorder_state_change::stamp_id.ge(since_stamp))
+        .select((myorder_state_change::all_columns, stamp::timestamp))
+        .order((
+            myorder_state_change::stamp_id.asc(),
+            myorder_state_change::myorder_id.asc(),
+        ))
+        .load(conn)
+        .map_err(Into::into)
+}
 
-    let myorder_id = next_id::table.select(next_id::myorder).first(conn)?;
+/// Add an order waiting for manual approval
+pub fn add_pending_approval(
+    conn: &Conn,
+    market_id: MarketId,
+    stamp_id: StampId,
+    expiry: NaiveDateTime,
+    price: Amount,
+    base_quantity: Amount,
+    quote_quantity: Amount,
+    expected_net_quote: Amount,
+    order_type: OrderType,
+    side: OrderSide,
+    recommendation: String,
+) -> Result<PendingApproval> {
+    let pending_approval_id = next_id::table
+        .select(next_id::pending_approval)
+        .first(conn)?;
 
-    let myorder = MyOrder {
-        myorder_id,
-        transaction_id,
+    let approval = PendingApproval {
+        pending_approval_id,
         market_id,
-        created_stamp_id: now_stamp_id,
-        modified_stamp_id: now_stamp_id,
+        stamp_id,
+        expiry,
         price,
         base_quantity,
         quote_quantity,
+        expected_net_quote,
         order_type,
         side,
-        state,
+        recommendation,
+        state: ApprovalState::Pending,
+        approved_price: None,
     };
 
     conn.transaction::<(), Error, _>(|| {
         // Update next id
         next_id::table
             .apply(diesel::update)
-            .set(next_id::myorder.eq(next_id::myorder + 1))
+            .set(next_id::pending_approval.eq(next_id::pending_approval + 1))
             .execute(conn)?;
 
-        // Add order
-        myorder::table
+        pending_approval::table
             .apply(diesel::insert_into)
-            .values(&myorder)
+            .values(&approval)
             .execute(conn)?;
 
         Ok(())
     })?;
 
+    Ok(approval)
+}
+
+/// List approvals in `state`, ordered by id
+pub fn list_pending_approvals(conn: &Conn, state: ApprovalState) -> Result<Vec<PendingApproval>> {
+    pending_approval::table
+        .filter(pending_approval::state.eq(state))
+        .order(pending_approval::pending_approval_id.asc())
+        .load::<PendingApproval>(conn)
+        .map_err(Into::into)
+}
+
+/// Approve a pending order, recording the market price at approval.
+/// An order past its expiry becomes `Expired` instead.
+pub fn approve_pending_approval(
+    conn: &Conn,
+    pending_approval_id: PendingApprovalId,
+    approved_price: Amount,
+    now: NaiveDateTime,
+) -> Result<PendingApproval> {
+    let approval = pending_approval::table
+        .find(pending_approval_id)
+        .first::<PendingApproval>(conn)
+        .optional()?
+        .ok_or(LogicError::ApprovalNotFound)?;
+
+    if approval.state != ApprovalState::Pending {
+        return Err(LogicError::ApprovalNotPending.into());
+    }
+    if is_approval_expired(&approval, now) {
+        update_pending_approval_state(conn, pending_approval_id, ApprovalState::Expired)?;
+        return Err(LogicError::ApprovalExpired.into());
+    }
+
+    // Update only if no one changed the state since it was read
+    let updated_count = pending_approval::table
+        .find(pending_approval_id)
+        .filter(pending_approval::state.eq(ApprovalState::Pending))
+        .apply(diesel::update)
+        .set((
+            pending_approval::state.eq(ApprovalState::Approved),
+            pending_approval::approved_price.eq(Some(approved_price)),
+        ))
+        .execute(conn)?;
+    if updated_count == 0 {
+        return Err(LogicError::ApprovalNotPending.into());
+    }
+
+    Ok(PendingApproval {
+        state: ApprovalState::Approved,
+        approved_price: Some(approved_price),
+        ..approval
+    })
+}
+
+pub fn update_pending_approval_state(
+    conn: &Conn,
+    pending_approval_id: PendingApprovalId,
+    state: ApprovalState,
+) -> Result<()> {
+    pending_approval::table
+        .find(pending_approval_id)
+        .apply(diesel::update)
+        .set(pending_approval::state.eq(state))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Reason not to execute an approved order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ApprovalRejection {
+    Expired,
+    /// Relative change of market price since approval exceeds the limit
+    Drifted {
+        drift: Amount,
+    },
+}
+
+pub fn is_approval_expired(approval: &PendingApproval, now: NaiveDateTime) -> bool {
+    approval.expiry < now
+}
+
+/// Check that an approved order can still be executed at `now`.
+/// `max_drift` is the allowed relative change of market price from `approved_price` to `current_price`.
+///
+/// An order without approved price is regarded as drifted infinitely.
+pub fn check_approved_order(
+    approval: &PendingApproval,
+    now: NaiveDateTime,
+    current_price: Amount,
+    max_drift: Amount,
+) -> std::result::Result<(), ApprovalRejection> {
+    if is_approval_expired(approval, now) {
+        return Err(ApprovalRejection::Expired);
+    }
+
+    let drift = match approval.approved_price {
+        Some(approved_price) if approved_price > 0.0 => {
+            (current_price - approved_price).abs() / approved_price
+        }
+        _ => Amount::INFINITY,
+    };
+    if drift > max_drift {
+        return Err(ApprovalRejection::Drifted { drift });
+    }
+
     Ok(())
 }
+
+/// Add recommendations of a stamp at once.
+/// `items` are (market, rule name, recommendation type, score, reason). Rule name is `None` for the aggregated outcome of rules.
+///
+/// A contiguous id range is reserved by one `next_id` update in the same transaction,
+/// so no id is consumed if the insert fails.
+///
+/// Recommendations of a market are recorded once per stamp.
+/// `Err(LogicError::DuplicatedRecommendation)` is returned and nothing is added
+/// if any of the markets already has recommendations at `stamp_id`.
+pub fn add_recommendations_bulk(
+    conn: &Conn,
+    stamp_id: StampId,
+    items: &[(
+        MarketId,
+        Option<String>,
+        RecommendationType,
+        Option<f64>,
+        String,
+    )],
+) -> Result<Vec<RecommendationRecord>> {
+    if items.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let market_ids = items
+        .iter()
+        .map(|(market_id, ..)| *market_id)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    let already_exists = recommendation::table
+        .filter(recommendation::stamp_id.eq(stamp_id))
+        .filter(recommendation::market_id.eq_any(market_ids))
+        .apply(exists)
+        .apply(diesel::select)
+        .get_result(conn)?;
+    if already_exists {
+        return Err(LogicError::DuplicatedRecommendation.into());
+    }
+
+    conn.transaction::<_, Error, _>(|| {
+        // Lock the row so that concurrent writers don't reserve the same range
+        let first_id: RecommendationId = next_id::table
+            .select(next_id::recommendation)
+            .for_update()
+            .first(conn)?;
+
+        let recommendations = items
+            .iter()
+            .enumerate()
+            .map(
+                |(i, (market_id, rule_name, recommendation_type, score, reason))| {
+                    RecommendationRecord {
+                        recommendation_id: RecommendationId::new(first_id.inner() + i as i32),
+                        stamp_id,
+                        market_id: *market_id,
+                        rule_name: rule_name.clone(),
+                        recommendation_type: *recommendation_type,
+                        score: *score,
+                        reason: reason.clone(),
+                    }
+                },
+            )
+            .collect::<Vec<_>>();
+
+        // Reserve ids
+        next_id::table
+            .apply(diesel::update)
+            .set(next_id::recommendation.eq(next_id::recommendation + items.len() as i32))
+            .execute(conn)?;
+
+        // Add recommendations
+        recommendation::table
+            .apply(diesel::insert_into)
+            .values(&recommendations)
+            .execute(conn)?;
+
+        Ok(recommendations)
+    })
+}
+
+/// List recommendations recorded between `since` and `until`, of `market_id` if given.
+/// # Returns
+/// Each recommendation with the timestamp of its stamp and the price of its market at the stamp,
+/// in ascending order of timestamp. Price is `None` if the market has no price at the stamp
+pub fn list_recommendations(
+    conn: &Conn,
+    market_id: Option<MarketId>,
+    since: NaiveDateTime,
+    until: NaiveDateTime,
+) -> Result<Vec<(RecommendationRecord, NaiveDateTime, Option<Amount>)>> {
+    let mut query = recommendation::table
+        .inner_join(stamp::table)
+        .left_join(
+            price::table.on(price::market_id
+                .eq(recommendation::market_id)
+                .and(price::stamp_id.eq(recommendation::stamp_id))),
+        )
+        .filter(stamp::timestamp.between(since, until))
+        .select((
+            recommendation::all_columns,
+            stamp::timestamp,
+            price::amount.nullable(),
+        ))
+        .order((
+            stamp::timestamp.asc(),
+            recommendation::recommendation_id.asc(),
+        ))
+        .into_boxed();
+    if let Some(market_id) = market_id {
+        query = query.filter(recommendation::market_id.eq(market_id));
+    }
+
+    query.load(conn).map_err(Into::into)
+}
+
+/// Id of the only row of `speculator_lock`
+const SPECULATOR_LOCK_ID: i32 = 0;
+
+/// Take the lock of speculator runs as `holder`.
+/// A lock acquired before `stale_before` is taken over, since its run is regarded as crashed.
+/// # Returns
+/// `None` if the lock is taken, or `Some(lock)` held by another run
+pub fn try_lock_speculator(
+    conn: &Conn,
+    holder: &str,
+    now: NaiveDateTime,
+    stale_before: NaiveDateTime,
+) -> Result<Option<SpeculatorLock>> {
+    // One conditional update, so that concurrent runs can't both take the lock
+    let updated = speculator_lock::table
+        .find(SPECULATOR_LOCK_ID)
+        .filter(
+            speculator_lock::holder
+                .is_null()
+                .or(speculator_lock::acquired.lt(stale_before)),
+        )
+        .apply(diesel::update)
+        .set((
+            speculator_lock::holder.eq(holder),
+            speculator_lock::acquired.eq(now),
+        ))
+        .execute(conn)?;
+
+    if updated > 0 {
+        Ok(None)
+    } else {
+        speculator_lock::table
+            .find(SPECULATOR_LOCK_ID)
+            .first(conn)
+            .map(Some)
+            .map_err(Into::into)
+    }
+}
+
+/// Release the lock of speculator runs if `holder` holds it
+pub fn unlock_speculator(conn: &Conn, holder: &str) -> Result<()> {
+    speculator_lock::table
+        .find(SPECULATOR_LOCK_ID)
+        .filter(speculator_lock::holder.eq(holder))
+        .apply(diesel::update)
+        .set((
+            speculator_lock::holder.eq(None::<String>),
+            speculator_lock::acquired.eq(None::<NaiveDateTime>),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+pub fn get_alert_state(conn: &Conn, alert_name: &str) -> Result<Option<AlertState>> {
+    alert_state::table
+        .find(alert_name)
+        .first(conn)
+        .optional()
+        .map_err(Into::into)
+}
+
+/// Insert or replace state of `state.alert_name`
+pub fn save_alert_state(conn: &Conn, state: &AlertState) -> Result<()> {
+    replace_row!(alert_state::table, alert_state::alert_name, state).execute(conn)?;
+    Ok(())
+}
+
+/// Insert or replace the run of `run.stamp_id`
+pub fn record_scrape_run(conn: &Conn, run: &ScrapeRun) -> Result<()> {
+    replace_row!(scrape_run::table, scrape_run::stamp_id, run).execute(conn)?;
+    Ok(())
+}
+
+pub fn load_scrape_run(conn: &Conn, stamp_id: StampId) -> Result<Option<ScrapeRun>> {
+    scrape_run::table
+        .find(stamp_id)
+        .first(conn)
+        .optional()
+        .map_err(Into::into)
+}
+
+/// Runs of `stamp_ids`. Stamps not created by the scraper, or scraped before runs were recorded, have no run
+pub fn list_scrape_runs(conn: &Conn, stamp_ids: &[StampId]) -> Result<Vec<ScrapeRun>> {
+    scrape_run::table
+        .filter(scrape_run::stamp_id.eq_any(stamp_ids.to_vec()))
+        .load(conn)
+        .map_err(Into::into)
+}
+
+pub fn get_sync_cursor(
+    conn: &Conn,
+    market_id: MarketId,
+    kind: SyncKind,
+) -> Result<Option<SyncCursor>> {
+    sync_cursor::table
+        .find((market_id, kind))
+        .first(conn)
+        .optional()
+        .map_err(Into::into)
+}
+
+/// Insert or replace the cursor of `cursor.market_id` and `cursor.kind`.
+/// Call it in the same transaction as the data synced until `cursor.synced_until`
+pub fn save_sync_cursor(conn: &Conn, cursor: &SyncCursor) -> Result<()> {
+    replace_row!(
+        sync_cursor::table,
+        (sync_cursor::market_id, sync_cursor::kind),
+        cursor
+    )
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Merge of a stamp into an older one
+#[derive(Debug, Clone, PartialEq)]
+pub struct StampMerge {
+    /// Newer stamp, which is deleted after merge
+    pub from: Stamp,
+    /// Older stamp, which takes over rows of `from`
+    pub into: Stamp,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StampMergeReport {
+    pub merges: Vec<StampMerge>,
+    /// Count of rows removed from the older stamp because the newer stamp has the same currency/market
+    pub replaced_rows: usize,
+    /// Count of rows re-pointed to the older stamp
+    pub moved_rows: usize,
+}
+
+/// Group stamps closer than `window` to the oldest stamp of each group,
+/// then plan merging the rest of each group into its oldest stamp.
+///
+/// Merges of the same group are ordered from older to newer.
+pub fn plan_stamp_merges(stamps: &[Stamp], window: chrono::Duration) -> Vec<StampMerge> {
+    let mut sorted = stamps.to_vec();
+    sorted.sort_by_key(|s| s.timestamp);
+
+    let mut merges = vec![];
+    let mut head: Option<Stamp> = None;
+    for stamp in sorted.into_iter() {
+        match head.as_ref() {
+            Some(h) if stamp.timestamp - h.timestamp < window => merges.push(StampMerge {
+                from: stamp,
+                into: h.clone(),
+            }),
+            _ => head = Some(stamp),
+        }
+    }
+
+    merges
+}
+
+/// Find stamps created closer than `window` by overlapping scraper runs, then merge them into the older stamp.
+/// When both stamps have a row of the same currency/market, the newer row is kept.
+///
+/// If `dry_run` is `true`, only planned merges are reported and nothing is written.
+pub fn merge_adjacent_stamps(
+    conn: &Conn,
+    window: chrono::Duration,
+    dry_run: bool,
+) -> Result<StampMergeReport> {
+    let stamps = stamp::table.load::<Stamp>(conn)?;
+    let merges = plan_stamp_merges(&stamps, window);
+
+    let mut report = StampMergeReport::default();
+    if dry_run {
+        report.merges = merges;
+        return Ok(report);
+    }
+
+    for merge in merges.into_iter() {
+        let (replaced_rows, moved_rows) = conn.transaction::<_, Error, _>(|| {
+            let from = merge.from.stamp_id;
+            let into = merge.into.stamp_id;
+            let mut replaced_rows = 0;
+            let mut moved_rows = 0;
+
+            // Balance
+            let currency_ids = balance::table
+                .filter(balance::stamp_id.eq(from))
+                .select(balance::currency_id)
+                .load::<CurrencyId>(conn)?;
+            replaced_rows += balance::table
+                .filter(balance::stamp_id.eq(into))
+                .filter(balance::currency_id.eq_any(currency_ids))
+                .apply(diesel::delete)
+                .execute(conn)?;
+            moved_rows += balance::table
+                .filter(balance::stamp_id.eq(from))
+                .apply(diesel::update)
+                .set(balance::stamp_id.eq(into))
+                .execute(conn)?;
+
+            // Price
+            let market_ids = price::table
+                .filter(price::stamp_id.eq(from))
+                .select(price::market_id)
+                .load::<MarketId>(conn)?;
+            replaced_rows += price::table
+                .filter(price::stamp_id.eq(into))
+                .filter(price::market_id.eq_any(market_ids))
+                .apply(diesel::delete)
+                .execute(conn)?;
+            moved_rows += price::table
+                .filter(price::stamp_id.eq(from))
+                .apply(diesel::update)
+                .set(price::stamp_id.eq(into))
+                .execute(conn)?;
+
+            // Orderbook
+            let market_ids = orderbook::table
+                .filter(orderbook::stamp_id.eq(from))
+                .select(orderbook::market_id)
+                .distinct()
+                .load::<MarketId>(conn)?;
+            replaced_rows += orderbook::table
+                .filter(orderbook::stamp_id.eq(into))
+                .filter(orderbook::market_id.eq_any(market_ids))
+                .apply(diesel::delete)
+                .execute(conn)?;
+            moved_rows += orderbook::table
+                .filter(orderbook::stamp_id.eq(from))
+                .apply(diesel::update)
+                .set(orderbook::stamp_id.eq(into))
+                .execute(conn)?;
+
+            // Myorder
+            moved_rows += myorder::table
+                .filter(myorder::created_stamp_id.eq(from))
+                .apply(diesel::update)
+                .set(myorder::created_stamp_id.eq(into))
+                .execute(conn)?;
+            moved_rows += myorder::table
+                .filter(myorder::modified_stamp_id.eq(from))
+                .apply(diesel::update)
+                .set(myorder::modified_stamp_id.eq(into))
+                .execute(conn)?;
+
+            moved_rows += myorder_state_change::table
+                .filter(myorder_state_change::stamp_id.eq(from))
+                .apply(diesel::update)
+                .set(myorder_state_change::stamp_id.eq(into))
+                .execute(conn)?;
+
+            // Pending approval
+            moved_rows += pending_approval::table
+                .filter(pending_approval::stamp_id.eq(from))
+                .apply(diesel::update)
+                .set(pending_approval::stamp_id.eq(into))
+                .execute(conn)?;
+
+            // Recommendation
+            moved_rows += recommendation::table
+                .filter(recommendation::stamp_id.eq(from))
+                .apply(diesel::update)
+                .set(recommendation::stamp_id.eq(into))
+                .execute(conn)?;
+
+            // Rows of the merged run are now in the older stamp, whose run is kept
+            scrape_run::table
+                .find(from)
+                .apply(diesel::delete)
+                .execute(conn)?;
+
+            // No row refers the stamp anymore
+            stamp::table
+                .find(from)
+                .apply(diesel::delete)
+                .execute(conn)?;
+
+            Ok((replaced_rows, moved_rows))
+        })?;
+
+        report.replaced_rows += replaced_rows;
+        report.moved_rows += moved_rows;
+        report.merges.push(merge);
+    }
+
+    Ok(report)
+}
+
+/// Get the stamp nearest to `timestamp` within `tolerance`
+pub fn get_stamp_near(
+    conn: &Conn,
+    timestamp: NaiveDateTime,
+    tolerance: chrono::Duration,
+) -> Result<Option<Stamp>> {
+    let stamps = stamp::table
+        .filter(stamp::timestamp.between(timestamp - tolerance, timestamp + tolerance))
+        .order(stamp::timestamp.asc())
+        .load::<Stamp>(conn)?;
+
+    Ok(nearest_stamp(&stamps, timestamp, tolerance).cloned())
+}
+
+/// Find the stamp closest to `target` among `stamps` sorted in ascending order of timestamp.
+/// # Returns
+/// `None` if no stamp is within `tolerance` from `target`
+pub fn nearest_stamp(
+    stamps: &[Stamp],
+    target: NaiveDateTime,
+    tolerance: chrono::Duration,
+) -> Option<&Stamp> {
+    let index = stamps.partition_point(|s| s.timestamp < target);
+    let before = index.checked_sub(1).and_then(|i| stamps.get(i));
+    let after = stamps.get(index);
+
+    let distance = |s: &Stamp| (s.timestamp - target).num_milliseconds().abs();
+    let nearest = match (before, after) {
+        (Some(b), Some(a)) if distance(a) < distance(b) => a,
+        (Some(b), _) => b,
+        (None, Some(a)) => a,
+        (None, None) => return None,
+    };
+
+    if distance(nearest) <= tolerance.num_milliseconds() {
+        Some(nearest)
+    } else {
+        None
+    }
+}
+
+/// The latest stamp among `stamps` sorted in descending order of timestamp.
+///
+/// If `ignore_newer_imports` is `true`, imported stamps newer than the latest scraper stamp are skipped,
+/// so that a backfill in progress does not look like fresh data.
+/// Imported stamps are not skipped if no scraper stamp exists.
+pub fn select_latest_stamp(stamps: &[Stamp], ignore_newer_imports: bool) -> Option<&Stamp> {
+    let latest_scraper = stamps
+        .iter()
+        .find(|s| s.source == StampSource::Scraper)
+        .map(|s| s.timestamp);
+
+    stamps.iter().find(|s| {
+        let newer_import = s.source == StampSource::Import
+            && latest_scraper.map_or(false, |latest| s.timestamp > latest);
+        !(ignore_newer_imports && newer_import)
+    })
+}
+
+/// The latest stamp. See `select_latest_stamp` about `ignore_newer_imports`
+pub fn get_latest_stamp(conn: &Conn, ignore_newer_imports: bool) -> Result<Option<Stamp>> {
+    let latest_scraper = stamp::table
+        .filter(stamp::source.eq(StampSource::Scraper))
+        .order(stamp::timestamp.desc())
+        .first::<Stamp>(conn)
+        .optional()?;
+
+    // Only stamps since the latest scraper stamp matter
+    let mut query = stamp::table.order(stamp::timestamp.desc()).into_boxed();
+    query = match latest_scraper.as_ref() {
+        Some(latest_scraper) => query.filter(stamp::timestamp.ge(latest_scraper.timestamp)),
+        None => query.limit(1),
+    };
+    let stamps = query.load::<Stamp>(conn)?;
+
+    Ok(select_latest_stamp(&stamps, ignore_newer_imports).cloned())
+}
+
+/// Median gap between consecutive `timestamps` sorted in ascending order.
+/// Median is used so that a few outages of the scraper don't affect the result.
+/// # Returns
+/// `None` if fewer than 2 timestamps are given
+pub fn median_interval(timestamps: &[NaiveDateTime]) -> Option<chrono::Duration> {
+    let mut gaps = timestamps
+        .windows(2)
+        .map(|w| w[1] - w[0])
+        .collect::<Vec<_>>();
+    gaps.sort();
+
+    let n = gaps.len();
+    match n {
+        0 => None,
+        n if n % 2 == 1 => Some(gaps[n / 2]),
+        n => Some((gaps[n / 2 - 1] + gaps[n / 2]) / 2),
+    }
+}
+
+/// Estimate the scraper's cadence from the latest `sample_count` stamps
+pub fn estimate_stamp_cadence(conn: &Conn, sample_count: i64) -> Result<Option<chrono::Duration>> {
+    let mut timestamps = stamp::table
+        .select(stamp::timestamp)
+        .order(stamp::timestamp.desc())
+        .limit(sample_count)
+        .load::<NaiveDateTime>(conn)?;
+    timestamps.reverse();
+
+    Ok(median_interval(&timestamps))
+}
+
+/// Rows deleted per statement by pruning, so that each transaction locks tables only briefly
+pub const PRUNE_BATCH_SIZE: i64 = 10_000;
+
+/// Delete orderbooks of stamps older than `cutoff`, by `PRUNE_BATCH_SIZE` rows per transaction.
+/// # Returns
+/// Count of deleted rows
+pub fn prune_orderbooks_older_than(conn: &Conn, cutoff: NaiveDateTime) -> Result<usize> {
+    prune_orderbooks(conn, cutoff, PRUNE_BATCH_SIZE)
+}
+
+/// Delete prices of stamps older than `cutoff`, by `PRUNE_BATCH_SIZE` rows per transaction.
+///
+/// Balance history can't be valued at the pruned stamps anymore.
+/// # Returns
+/// Count of deleted rows
+pub fn prune_prices_older_than(conn: &Conn, cutoff: NaiveDateTime) -> Result<usize> {
+    prune_prices(conn, cutoff, PRUNE_BATCH_SIZE)
+}
+
+/// Delete stamps older than `cutoff` which no row refers, by `PRUNE_BATCH_SIZE` stamps per transaction.
+/// # Returns
+/// Count of deleted stamps
+pub fn prune_unreferenced_stamps_older_than(conn: &Conn, cutoff: NaiveDateTime) -> Result<usize> {
+    prune_unreferenced_stamps(conn, cutoff, PRUNE_BATCH_SIZE)
+}
+
+/// Repeat `prune_batch` until it deletes fewer rows than `batch_size`
+fn prune_in_batches<F>(conn: &Conn, batch_size: i64, prune_batch: F) -> Result<usize>
+where
+    F: Fn() -> Result<usize>,
+{
+    let mut pruned = 0;
+    loop {
+        let count = conn.transaction::<_, Error, _>(&prune_batch)?;
+        pruned += count;
+        if (count as i64) < batch_size {
+            return Ok(pruned);
+        }
+    }
+}
+
+fn prune_orderbooks(conn: &Conn, cutoff: NaiveDateTime, batch_size: i64) -> Result<usize> {
+    prune_in_batches(conn, batch_size, || {
+        // MySQL doesn't support LIMIT in a subquery of DELETE, so ids are loaded first
+        let orderbook_ids = orderbook::table
+            .inner_join(stamp::table)
+            .filter(stamp::timestamp.lt(cutoff))
+            .select(orderbook::orderbook_id)
+            .limit(batch_size)
+            .load::<OrderbookId>(conn)?;
+        if orderbook_ids.is_empty() {
+            return Ok(0);
+        }
+
+        orderbook::table
+            .filter(orderbook::orderbook_id.eq_any(orderbook_ids))
+            .apply(diesel::delete)
+            .execute(conn)
+            .map_err(Into::into)
+    })
+}
+
+fn prune_prices(conn: &Conn, cutoff: NaiveDateTime, batch_size: i64) -> Result<usize> {
+    prune_in_batches(conn, batch_size, || {
+        let price_ids = price::table
+            .inner_join(stamp::table)
+            .filter(stamp::timestamp.lt(cutoff))
+            .select(price::price_id)
+            .limit(batch_size)
+            .load::<PriceId>(conn)?;
+        if price_ids.is_empty() {
+            return Ok(0);
+        }
+
+        price::table
+            .filter(price::price_id.eq_any(price_ids))
+            .apply(diesel::delete)
+            .execute(conn)
+            .map_err(Into::into)
+    })
+}
+
+fn prune_unreferenced_stamps(conn: &Conn, cutoff: NaiveDateTime, batch_size: i64) -> Result<usize> {
+    let mut pruned = 0;
+    let mut last_stamp_id = None;
+
+    // Referred stamps are kept, so candidates are paged by stamp id instead of repeating until none is deleted
+    loop {
+        let mut query = stamp::table
+            .filter(stamp::timestamp.lt(cutoff))
+            .select(stamp::stamp_id)
+            .order(stamp::stamp_id.asc())
+            .limit(batch_size)
+            .into_boxed();
+        if let Some(last_stamp_id) = last_stamp_id {
+            query = query.filter(stamp::stamp_id.gt(last_stamp_id));
+        }
+        let candidates = query.load::<StampId>(conn)?;
+        last_stamp_id = match candidates.last() {
+            Some(stamp_id) => Some(*stamp_id),
+            None => return Ok(pruned),
+        };
+
+        pruned += conn.transaction::<_, Error, _>(|| {
+            let referred = referred_stamp_ids(conn, &candidates)?;
+            let unreferred = candidates
+                .iter()
+                .filter(|stamp_id| !referred.contains(*stamp_id))
+                .copied()
+                .collect::<Vec<_>>();
+            if unreferred.is_empty() {
+                return Ok(0);
+            }
+
+            // Runs don't keep stamps, since they only describe rows of the stamps
+            scrape_run::table
+                .filter(scrape_run::stamp_id.eq_any(unreferred.clone()))
+                .apply(diesel::delete)
+                .execute(conn)?;
+            stamp::table
+                .filter(stamp::stamp_id.eq_any(unreferred))
+                .apply(diesel::delete)
+                .execute(conn)
+                .map_err(Into::into)
+        })?;
+    }
+}
+
+/// Stamps among `stamp_ids` which any row refers
+fn referred_stamp_ids(conn: &Conn, stamp_ids: &[StampId]) -> Result<HashSet<StampId>> {
+    let mut referred = HashSet::new();
+
+    referred.extend(
+        balance::table
+            .filter(balance::stamp_id.eq_any(stamp_ids.to_vec()))
+            .select(balance::stamp_id)
+            .distinct()
+            .load::<StampId>(conn)?,
+    );
+    referred.extend(
+        price::table
+            .filter(price::stamp_id.eq_any(stamp_ids.to_vec()))
+            .select(price::stamp_id)
+            .distinct()
+            .load::<StampId>(conn)?,
+    );
+    referred.extend(
+        orderbook::table
+            .filter(orderbook::stamp_id.eq_any(stamp_ids.to_vec()))
+            .select(orderbook::stamp_id)
+            .distinct()
+            .load::<StampId>(conn)?,
+    );
+    referred.extend(
+        myorder::table
+            .filter(myorder::created_stamp_id.eq_any(stamp_ids.to_vec()))
+            .select(myorder::created_stamp_id)
+            .distinct()
+            .load::<StampId>(conn)?,
+    );
+    referred.extend(
+        myorder::table
+            .filter(myorder::modified_stamp_id.eq_any(stamp_ids.to_vec()))
+            .select(myorder::modified_stamp_id)
+            .distinct()
+            .load::<StampId>(conn)?,
+    );
+    referred.extend(
+        myorder_state_change::table
+            .filter(myorder_state_change::stamp_id.eq_any(stamp_ids.to_vec()))
+            .select(myorder_state_change::stamp_id)
+            .distinct()
+            .load::<StampId>(conn)?,
+    );
+    referred.extend(
+        pending_approval::table
+            .filter(pending_approval::stamp_id.eq_any(stamp_ids.to_vec()))
+            .select(pending_approval::stamp_id)
+            .distinct()
+            .load::<StampId>(conn)?,
+    );
+    referred.extend(
+        recommendation::table
+            .filter(recommendation::stamp_id.eq_any(stamp_ids.to_vec()))
+            .select(recommendation::stamp_id)
+            .distinct()
+            .load::<StampId>(conn)?,
+    );
+
+    Ok(referred)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Add a stamp older than existing ones, which `add_stamp` denies
+    fn add_past_stamp(conn: &Conn, timestamp: NaiveDateTime) -> Result<Stamp> {
+        let stamp_id = next_id::table.select(next_id::stamp).first(conn)?;
+        let stamp = Stamp::with_source(stamp_id, timestamp, StampSource::Import);
+
+        next_id::table
+            .apply(diesel::update)
+            .set(next_id::stamp.eq(next_id::stamp + 1))
+            .execute(conn)?;
+        stamp::table
+            .apply(diesel::insert_into)
+            .values(&stamp)
+            .execute(conn)?;
+
+        Ok(stamp)
+    }
+
+    fn stamp(id: i32, hour: u32, minute: u32, second: u32) -> Stamp {
+        let timestamp = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(hour, minute, second);
+        Stamp::new(StampId::new(id), timestamp)
+    }
+
+    fn currency(id: i32, symbol: &str, active: bool) -> Currency {
+        let mut currency = Currency::new(CurrencyId::new(id), symbol.into(), symbol.into());
+        currency.active = active;
+        currency
+    }
+
+    fn symbols(currencies: &[Currency]) -> Vec<&str> {
+        currencies.iter().map(|c| c.symbol.as_str()).collect()
+    }
+
+    fn collections() -> (CurrencyCollection, MarketCollection) {
+        let currencies = CurrencyCollection {
+            currencies: vec![
+                currency(0, "BTC", true),
+                currency(1, "USDT", true),
+                currency(2, "ETH", true),
+                currency(3, "XYZ", false),
+            ],
+        };
+        let market = |id, base, quote| {
+            Market::new(
+                MarketId::new(id),
+                CurrencyId::new(base),
+                CurrencyId::new(quote),
+            )
+        };
+        let markets = MarketCollection {
+            markets: vec![market(0, 0, 1), market(1, 2, 0), market(2, 3, 0)],
+        };
+        (currencies, markets)
+    }
+
+    #[test]
+    fn test_resolve_market_symbols() {
+        let (currencies, markets) = collections();
+
+        let (resolved, errors) = resolve_market_symbols("BTC-USDT:ETH-BTC", &currencies, &markets);
+
+        let ids = resolved
+            .iter()
+            .map(|(base, quote, market)| {
+                (
+                    base.symbol.as_str(),
+                    quote.symbol.as_str(),
+                    market.market_id.inner(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vec![("BTC", "USDT", 0), ("ETH", "BTC", 1)], ids);
+        assert!(errors.is_empty());
+
+        // Empty pairs, e.g. of a trailing separator, are not errors
+        let (resolved, errors) = resolve_market_symbols(":BTC-USDT:", &currencies, &markets);
+        assert_eq!(1, resolved.len());
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_market_symbols_errors() {
+        let (currencies, markets) = collections();
+
+        let (resolved, errors) = resolve_market_symbols(
+            "BTC-USTD:BTC-USDT:DOGE-BTC:BTCUSDT:USDT-BTC:BTC-USDT-ETH:-BTC",
+            &currencies,
+            &markets,
+        );
+
+        assert_eq!(1, resolved.len());
+        assert_eq!(
+            vec![
+                SymbolResolutionError::UnknownQuoteCurrency("USTD".into()),
+                SymbolResolutionError::UnknownBaseCurrency("DOGE".into()),
+                SymbolResolutionError::MalformedPair("BTCUSDT".into()),
+                SymbolResolutionError::UnknownMarket("USDT-BTC".into()),
+                SymbolResolutionError::MalformedPair("BTC-USDT-ETH".into()),
+                SymbolResolutionError::MalformedPair("-BTC".into()),
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_resolve_market_symbol_inactive() {
+        let (currencies, markets) = collections();
+
+        assert!(resolve_market_symbol("XYZ-BTC", &currencies, &markets).is_ok());
+        assert_eq!(
+            Err(SymbolResolutionError::UnknownBaseCurrency("XYZ".into())),
+            resolve_market_symbol("XYZ-BTC", &currencies.active(), &markets)
+        );
+    }
+
+    #[test]
+    fn test_plan_currency_activity() {
+        let currencies = vec![
+            currency(0, "BTC", true),
+            currency(1, "ETH", true),
+            currency(2, "XYZ", false),
+            currency(3, "ABC", false),
+        ];
+
+        let change = plan_currency_activity(&currencies, &["BTC", "XYZ", "NEW"]);
+
+        assert_eq!(vec!["ETH"], symbols(&change.deactivated));
+        assert_eq!(vec!["XYZ"], symbols(&change.reactivated));
+    }
+
+    #[test]
+    fn test_plan_currency_activity_unchanged() {
+        let currencies = vec![currency(0, "BTC", true), currency(1, "XYZ", false)];
+
+        let change = plan_currency_activity(&currencies, &["BTC"]);
+        assert_eq!(CurrencyActivityChange::default(), change);
+
+        // Empty listing doesn't delist everything
+        let change = plan_currency_activity::<&str>(&currencies, &[]);
+        assert_eq!(CurrencyActivityChange::default(), change);
+    }
+
+    #[test]
+    fn test_currency_collection_excludes_inactive() {
+        let collection = CurrencyCollection {
+            currencies: vec![
+                currency(0, "BTC", true),
+                currency(1, "USD", false),
+                currency(2, "USDT", true),
+            ],
+        };
+
+        assert_eq!(vec!["BTC", "USDT"], collection.symbols(false));
+        assert_eq!(vec!["BTC", "USD", "USDT"], collection.symbols(true));
+        assert_eq!(None, collection.by_symbol("USD"));
+        assert_eq!(
+            Some(CurrencyId::new(1)),
+            collection
+                .by_symbol_including_inactive("USD")
+                .map(|c| c.currency_id)
+        );
+    }
+
+    #[test]
+    fn test_is_valid_tag() {
+        assert!(is_valid_tag("stablecoin"));
+        assert!(is_valid_tag("layer-1_2"));
+        assert!(!is_valid_tag(""));
+        assert!(!is_valid_tag("Major"));
+        assert!(!is_valid_tag("two words"));
+        assert!(!is_valid_tag(&"a".repeat(33)));
+        assert!(!is_valid_tag(UNTAGGED));
+    }
+
+    #[test]
+    fn test_primary_currency_tags() {
+        let tag = |id, tag: &str| CurrencyTag {
+            currency_id: CurrencyId::new(id),
+            tag: tag.into(),
+        };
+        let tags = vec![
+            tag(1, "major"),
+            tag(2, "stablecoin"),
+            tag(2, "fiat-backed"),
+            tag(1, "layer1"),
+        ];
+
+        let primary_tags = primary_currency_tags(&tags);
+
+        // The alphabetically first tag wins regardless of order
+        assert_eq!(2, primary_tags.len());
+        assert_eq!("layer1", primary_tags[&CurrencyId::new(1)]);
+        assert_eq!("fiat-backed", primary_tags[&CurrencyId::new(2)]);
+        let mut reversed = tags;
+        reversed.reverse();
+        assert_eq!(primary_tags, primary_currency_tags(&reversed));
+    }
+
+    #[test]
+    fn test_plan_stamp_merges() {
+        let stamps = vec![
+            stamp(0, 0, 0, 0),
+            stamp(1, 0, 0, 10),
+            stamp(2, 0, 5, 0),
+            // Overlapping runs can insert stamps in any id order
+            stamp(4, 0, 10, 20),
+            stamp(3, 0, 10, 5),
+            stamp(5, 0, 10, 50),
+        ];
+
+        let merges = plan_stamp_merges(&stamps, chrono::Duration::seconds(30));
+
+        let pairs = merges
+            .iter()
+            .map(|m| (m.from.stamp_id.inner(), m.into.stamp_id.inner()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(1, 0), (4, 3)], pairs);
+    }
+
+    #[test]
+    fn test_plan_stamp_merges_chain() {
+        // Every stamp is merged into the oldest one of the group
+        let stamps = vec![stamp(0, 0, 0, 0), stamp(1, 0, 0, 10), stamp(2, 0, 0, 20)];
+
+        let merges = plan_stamp_merges(&stamps, chrono::Duration::seconds(30));
+
+        let pairs = merges
+            .iter()
+            .map(|m| (m.from.stamp_id.inner(), m.into.stamp_id.inner()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(1, 0), (2, 0)], pairs);
+    }
+
+    #[test]
+    fn test_plan_stamp_merges_empty() {
+        assert!(plan_stamp_merges(&[], chrono::Duration::seconds(30)).is_empty());
+    }
+
+    fn timestamps(minutes: &[i64]) -> Vec<NaiveDateTime> {
+        let origin = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 0, 0);
+        minutes
+            .iter()
+            .map(|&m| origin + chrono::Duration::minutes(m))
+            .collect()
+    }
+
+    #[test]
+    fn test_median_interval_regular() {
+        let timestamps = timestamps(&[0, 5, 10, 15, 20]);
+
+        assert_eq!(
+            Some(chrono::Duration::minutes(5)),
+            median_interval(&timestamps)
+        );
+    }
+
+    #[test]
+    fn test_median_interval_irregular() {
+        // Gaps: 4, 6, 5, 7 -> median of even count is the mean of 5 and 6
+        let timestamps = timestamps(&[0, 4, 10, 15, 22]);
+
+        assert_eq!(
+            Some(chrono::Duration::seconds(330)),
+            median_interval(&timestamps)
+        );
+    }
+
+    #[test]
+    fn test_median_interval_gappy() {
+        // An outage of 2 hours does not affect the cadence
+        let timestamps = timestamps(&[0, 5, 10, 130, 135, 140]);
+
+        assert_eq!(
+            Some(chrono::Duration::minutes(5)),
+            median_interval(&timestamps)
+        );
+    }
+
+    #[test]
+    fn test_median_interval_insufficient() {
+        assert_eq!(None, median_interval(&[]));
+        assert_eq!(None, median_interval(&timestamps(&[0])));
+    }
+
+    #[test]
+    fn test_nearest_stamp() {
+        let stamps = vec![stamp(0, 0, 0, 0), stamp(1, 0, 5, 0), stamp(2, 0, 20, 0)];
+        let at =
+            |minute, second| chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(0, minute, second);
+        let tolerance = chrono::Duration::minutes(2);
+        let nearest_id =
+            |target| nearest_stamp(&stamps, target, tolerance).map(|s| s.stamp_id.inner());
+
+        assert_eq!(Some(0), nearest_id(at(0, 0)));
+        assert_eq!(Some(1), nearest_id(at(4, 0)));
+        assert_eq!(Some(1), nearest_id(at(6, 59)));
+        assert_eq!(Some(2), nearest_id(at(18, 0)));
+        // Same distance prefers the older stamp
+        let tolerance = chrono::Duration::minutes(10);
+        assert_eq!(
+            Some(1),
+            nearest_stamp(&stamps, at(12, 30), tolerance).map(|s| s.stamp_id.inner())
+        );
+
+        // Out of tolerance
+        assert_eq!(None, nearest_id(at(12, 30)));
+        assert_eq!(None, nearest_id(at(23, 0)));
+        assert_eq!(None, nearest_stamp(&[], at(0, 0), tolerance));
+    }
+
+    #[test]
+    fn test_select_latest_stamp() {
+        let sourced = |id, minute, source| Stamp {
+            source,
+            ..stamp(id, 0, minute, 0)
+        };
+        // Descending order of timestamp
+        let stamps = vec![
+            sourced(4, 40, StampSource::Import),
+            sourced(3, 30, StampSource::Import),
+            sourced(2, 20, StampSource::Replay),
+            sourced(1, 10, StampSource::Scraper),
+            sourced(0, 0, StampSource::Import),
+        ];
+        let latest_id = |stamps: &[Stamp], ignore| {
+            select_latest_stamp(stamps, ignore).map(|s| s.stamp_id.inner())
+        };
+
+        assert_eq!(Some(4), latest_id(&stamps, false));
+        // Imports newer than the latest scraper stamp are skipped, but replayed ones are not
+        assert_eq!(Some(2), latest_id(&stamps, true));
+        assert_eq!(Some(1), latest_id(&stamps[3..], true));
+        // Imports older than the latest scraper stamp are kept
+        assert_eq!(Some(0), latest_id(&stamps[4..], true));
+        // Without scraper stamps, imports are the latest anyway
+        assert_eq!(Some(4), latest_id(&stamps[..3], true));
+        assert_eq!(None, latest_id(&[], true));
+    }
+
+    fn approval(expiry: NaiveDateTime, approved_price: Option<Amount>) -> PendingApproval {
+        PendingApproval {
+            pending_approval_id: PendingApprovalId::new(0),
+            market_id: MarketId::new(0),
+            stamp_id: StampId::new(0),
+            expiry,
+            price: 100.0,
+            base_quantity: 1.0,
+            quote_quantity: 100.0,
+            expected_net_quote: -100.1,
+            order_type: OrderType::Limit,
+            side: OrderSide::Buy,
+            recommendation: String::new(),
+            state: ApprovalState::Approved,
+            approved_price,
+        }
+    }
+
+    #[test]
+    fn test_check_approved_order_expiry() {
+        let expiry = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 5, 0);
+        let approval = approval(expiry, Some(100.0));
+
+        let before = expiry - chrono::Duration::seconds(1);
+        assert_eq!(Ok(()), check_approved_order(&approval, before, 100.0, 0.01));
+        // Expiry itself is still executable
+        assert_eq!(Ok(()), check_approved_order(&approval, expiry, 100.0, 0.01));
+
+        let after = expiry + chrono::Duration::seconds(1);
+        assert_eq!(
+            Err(ApprovalRejection::Expired),
+            check_approved_order(&approval, after, 100.0, 0.01)
+        );
+    }
+
+    #[test]
+    fn test_check_approved_order_drift() {
+        let expiry = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 5, 0);
+        let now = expiry - chrono::Duration::minutes(1);
+        let approval = approval(expiry, Some(100.0));
+
+        assert_eq!(Ok(()), check_approved_order(&approval, now, 100.5, 0.01));
+        assert_eq!(Ok(()), check_approved_order(&approval, now, 99.5, 0.01));
+
+        match check_approved_order(&approval, now, 102.0, 0.01) {
+            Err(ApprovalRejection::Drifted { drift }) => assert!((drift - 0.02).abs() < 1e-6),
+            other => panic!("Unexpected {:?}", other),
+        }
+        match check_approved_order(&approval, now, 97.0, 0.01) {
+            Err(ApprovalRejection::Drifted { drift }) => assert!((drift - 0.03).abs() < 1e-6),
+            other => panic!("Unexpected {:?}", other),
+        }
+
+        // Expiry is checked first
+        let after = expiry + chrono::Duration::seconds(1);
+        assert_eq!(
+            Err(ApprovalRejection::Expired),
+            check_approved_order(&approval, after, 200.0, 0.01)
+        );
+    }
+
+    #[test]
+    fn test_check_approved_order_without_approved_price() {
+        let expiry = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 5, 0);
+        let now = expiry - chrono::Duration::minutes(1);
+
+        let rejection = check_approved_order(&approval(expiry, None), now, 100.0, 0.01);
+
+        assert!(matches!(rejection, Err(ApprovalRejection::Drifted { .. })));
+    }
+
+    /// Requires `TEST_DATABASE_URL`. Changes are rolled back
+    #[test]
+    #[ignore]
+    fn test_list_myorders_deterministic() {
+        let url = std::env::var("TEST_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let base = add_currency(&conn, "TESTBASE".into(), "Test base".into())?;
+            let quote = add_currency(&conn, "TESTQUOTE".into(), "Test quote".into())?;
+            let market = add_market(&conn, base.currency_id, quote.currency_id)?;
+            let other_market = add_market(&conn, quote.currency_id, base.currency_id)?;
+
+            let timestamp = |hour| chrono::NaiveDate::from_ymd(2037, 1, 1).and_hms(hour, 0, 0);
+            let add_order = |market_id, stamp_id, id: &str, state| {
+                add_or_update_myorder(
+                    &conn,
+                    id.into(),
+                    market_id,
+                    stamp_id,
+                    1.0,
+                    1.0,
+                    1.0,
+                    OrderType::Limit,
+                    OrderSide::Buy,
+                    state,
+                )
+            };
+
+            let stamp1 = add_stamp(&conn, timestamp(0))?;
+            let stamp2 = add_stamp(&conn, timestamp(1))?;
+            // Orders modified at the same stamp are ordered by id
+            add_order(
+                market.market_id,
+                stamp2.stamp_id,
+                "test-c",
+                OrderState::Opened,
+            )?;
+            add_order(
+                market.market_id,
+                stamp1.stamp_id,
+                "test-a",
+                OrderState::Opened,
+            )?;
+            add_order(
+                market.market_id,
+                stamp1.stamp_id,
+                "test-b",
+                OrderState::Opened,
+            )?;
+            add_order(
+                market.market_id,
+                stamp2.stamp_id,
+                "test-a",
+                OrderState::Filled,
+            )?;
+
+            let filter = MyorderFilter {
+                market_id: Some(market.market_id),
+                ..Default::default()
+            };
+            let ids = |orders: Vec<MyOrder>| {
+                orders
+                    .into_iter()
+                    .map(|order| order.transaction_id)
+                    .collect::<Vec<_>>()
+            };
+
+            let expected = vec!["test-b", "test-c", "test-a"];
+            assert_eq!(expected, ids(list_myorders(&conn, filter.clone())?));
+            assert_eq!(expected, ids(list_myorders(&conn, filter.clone())?));
+
+            // Unrelated rows don't change the order
+            let stamp3 = add_stamp(&conn, timestamp(2))?;
+            add_order(
+                other_market.market_id,
+                stamp1.stamp_id,
+                "test-d",
+                OrderState::Opened,
+            )?;
+            add_order(
+                other_market.market_id,
+                stamp3.stamp_id,
+                "test-e",
+                OrderState::Opened,
+            )?;
+            assert_eq!(expected, ids(list_myorders(&conn, filter.clone())?));
+
+            // Paging keeps the order
+            let page = MyorderFilter {
+                limit: Some(2),
+                offset: Some(1),
+                ..filter.clone()
+            };
+            assert_eq!(vec!["test-c", "test-a"], ids(list_myorders(&conn, page)?));
+
+            let opened = MyorderFilter {
+                state: Some(OrderState::Opened),
+                ..filter
+            };
+            assert_eq!(vec!["test-b", "test-c"], ids(list_myorders(&conn, opened)?));
+
+            Ok(())
+        });
+    }
+
+    /// Requires `TEST_DATABASE_URL`. Changes are rolled back
+    #[test]
+    #[ignore]
+    fn test_myorder_state_change() {
+        let url = std::env::var("TEST_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let base = add_currency(&conn, "STATEB".into(), "State base".into())?;
+            let quote = add_currency(&conn, "STATEQ".into(), "State quote".into())?;
+            let market = add_market(&conn, base.currency_id, quote.currency_id)?;
+            let other_market = add_market(&conn, quote.currency_id, base.currency_id)?;
+
+            let timestamp = |hour| chrono::NaiveDate::from_ymd(2037, 1, 1).and_hms(hour, 0, 0);
+            let stamp1 = add_stamp(&conn, timestamp(0))?;
+            let stamp2 = add_stamp(&conn, timestamp(1))?;
+            let stamp3 = add_stamp(&conn, timestamp(2))?;
+            let submit = |stamp_id, state| {
+                add_or_update_myorder(
+                    &conn,
+                    "test-state".into(),
+                    market.market_id,
+                    stamp_id,
+                    1.0,
+                    1.0,
+                    1.0,
+                    OrderType::Limit,
+                    OrderSide::Buy,
+                    state,
+                )
+            };
+            let change_count = || {
+                myorder_state_change::table
+                    .inner_join(myorder::table)
+                    .filter(myorder::transaction_id.eq("test-state"))
+                    .count()
+                    .get_result::<i64>(&conn)
+            };
+
+            assert_eq!(
+                MyorderUpsert::Inserted,
+                submit(stamp1.stamp_id, OrderState::Opened)?
+            );
+            assert_eq!(0, change_count()?);
+
+            // Unchanged state writes no transition
+            assert_eq!(
+                MyorderUpsert::Unchanged,
+                submit(stamp2.stamp_id, OrderState::Opened)?
+            );
+            assert_eq!(0, change_count()?);
+
+            // Changed state writes exactly one transition
+            assert_eq!(
+                MyorderUpsert::Updated,
+                submit(stamp2.stamp_id, OrderState::Filled)?
+            );
+            assert_eq!(1, change_count()?);
+
+            assert_eq!(
+                MyorderUpsert::Unchanged,
+                submit(stamp3.stamp_id, OrderState::Filled)?
+            );
+            assert_eq!(
+                MyorderUpsert::IgnoredStale,
+                submit(stamp3.stamp_id, OrderState::Opened)?
+            );
+            assert_eq!(1, change_count()?);
+
+            let changes = load_order_state_changes(&conn, market.market_id, stamp1.stamp_id)?;
+            assert_eq!(1, changes.len());
+            let (change, changed_at) = &changes[0];
+            assert_eq!(OrderState::Opened, change.old_state);
+            assert_eq!(OrderState::Filled, change.new_state);
+            assert_eq!(stamp2.stamp_id, change.stamp_id);
+            assert_eq!(timestamp(1), *changed_at);
+
+            assert!(load_order_state_changes(&conn, market.market_id, stamp3.stamp_id)?.is_empty());
+            assert!(
+                load_order_state_changes(&conn, other_market.market_id, stamp1.stamp_id)?
+                    .is_empty()
+            );
+
+            Ok(())
+        });
+    }
+
+    /// Requires `TEST_SIM_DATABASE_URL` of a simulation DB. Added balances are deleted at the end
+    #[test]
+    #[ignore]
+    fn test_add_balances_bulk_concurrently() {
+        let url = std::env::var("TEST_SIM_DATABASE_URL").unwrap();
+        // Stamp which never exists
+        let stamp_id = StampId::new(-1);
+
+        // Two connections add balances at the same time, like overlapping speculator runs
+        let handles = (0..2)
+            .map(|_| {
+                let url = url.clone();
+                std::thread::spawn(move || {
+                    let conn = Conn::establish(&url).unwrap();
+                    (0..10)
+                        .flat_map(|_| {
+                            let items = [(CurrencyId::new(0), 1.0, 0.0); 3];
+                            add_balances_bulk(&conn, stamp_id, &items).unwrap()
+                        })
+                        .map(|balance| balance.balance_id)
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut ids = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>();
+
+        let conn = Conn::establish(&url).unwrap();
+        diesel::delete(balance::table.filter(balance::stamp_id.eq(stamp_id)))
+            .execute(&conn)
+            .unwrap();
+
+        ids.sort();
+        ids.dedup();
+        assert_eq!(60, ids.len());
+    }
+
+    /// Requires `TEST_SIM_DATABASE_URL` of a simulation DB. Added orders are deleted at the end
+    #[test]
+    #[ignore]
+    fn test_add_or_update_myorder_concurrently() {
+        let url = std::env::var("TEST_SIM_DATABASE_URL").unwrap();
+        // Stamp which never exists
+        let stamp_id = StampId::new(-1);
+
+        // Two connections record their own orders and the same orders at the same time,
+        // like the scraper and live execution recording a just-placed order
+        let handles = (0..2)
+            .map(|thread| {
+                let url = url.clone();
+                std::thread::spawn(move || {
+                    let conn = Conn::establish(&url).unwrap();
+                    (0..10)
+                        .flat_map(|i| {
+                            vec![
+                                format!("concurrent-{}-{}", thread, i),
+                                format!("concurrent-shared-{}", i),
+                            ]
+                        })
+                        .map(|transaction_id| {
+                            add_or_update_myorder(
+                                &conn,
+                                transaction_id,
+                                MarketId::new(0),
+                                stamp_id,
+                                1.0,
+                                1.0,
+                                1.0,
+                                OrderType::Limit,
+                                OrderSide::Buy,
+                                OrderState::Opened,
+                            )
+                            .unwrap()
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let upserts = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>();
+
+        let conn = Conn::establish(&url).unwrap();
+        let myorders = myorder::table
+            .filter(myorder::transaction_id.like("concurrent-%"))
+            .load::<MyOrder>(&conn)
+            .unwrap();
+        diesel::delete(myorder::table.filter(myorder::transaction_id.like("concurrent-%")))
+            .execute(&conn)
+            .unwrap();
+
+        // Each shared order is inserted once, and found by the other connection
+        assert_eq!(
+            30,
+            upserts
+                .iter()
+                .filter(|&&upsert| upsert == MyorderUpsert::Inserted)
+                .count()
+        );
+        assert_eq!(
+            10,
+            upserts
+                .iter()
+                .filter(|&&upsert| upsert == MyorderUpsert::Unchanged)
+                .count()
+        );
+        let mut ids = myorders
+            .iter()
+            .map(|myorder| myorder.myorder_id)
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(30, ids.len());
+    }
+
+    /// Requires `TEST_SIM_DATABASE_URL` of a simulation DB whose speculator lock is released
+    #[test]
+    #[ignore]
+    fn test_speculator_lock() {
+        let url = std::env::var("TEST_SIM_DATABASE_URL").unwrap();
+        let conn1 = Conn::establish(&url).unwrap();
+        let conn2 = Conn::establish(&url).unwrap();
+        let now = chrono::NaiveDate::from_ymd(2037, 1, 1).and_hms(0, 0, 0);
+        let stale_before = now - chrono::Duration::hours(1);
+
+        assert_eq!(
+            None,
+            try_lock_speculator(&conn1, "run 1", now, stale_before).unwrap()
+        );
+
+        // Another run can't take the lock
+        let lock = try_lock_speculator(&conn2, "run 2", now, stale_before)
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some("run 1".into()), lock.holder);
+        assert_eq!(Some(now), lock.acquired);
+
+        // Only the holder releases the lock
+        unlock_speculator(&conn2, "run 2").unwrap();
+        assert!(try_lock_speculator(&conn2, "run 2", now, stale_before)
+            .unwrap()
+            .is_some());
+        unlock_speculator(&conn1, "run 1").unwrap();
+        assert_eq!(
+            None,
+            try_lock_speculator(&conn2, "run 2", now, stale_before).unwrap()
+        );
+
+        // Stale lock is taken over
+        let later = now + chrono::Duration::hours(2);
+        assert_eq!(
+            None,
+            try_lock_speculator(&conn1, "run 1", later, later - chrono::Duration::hours(1))
+                .unwrap()
+        );
+
+        unlock_speculator(&conn1, "run 1").unwrap();
+    }
+
+    /// Requires `TEST_DATABASE_URL`. Changes are rolled back
+    #[test]
+    #[ignore]
+    fn test_save_alert_state() {
+        let url = std::env::var("TEST_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            assert_eq!(None, get_alert_state(&conn, "test alert")?);
+
+            let notified = chrono::NaiveDate::from_ymd(2037, 1, 1).and_hms(0, 0, 0);
+            let mut state = AlertState {
+                alert_name: "test alert".into(),
+                firing: true,
+                last_notified: Some(notified),
+            };
+            save_alert_state(&conn, &state)?;
+            assert_eq!(Some(state.clone()), get_alert_state(&conn, "test alert")?);
+
+            // Cleared condition replaces the state, keeping the last notification
+            state.firing = false;
+            save_alert_state(&conn, &state)?;
+            assert_eq!(Some(state), get_alert_state(&conn, "test alert")?);
+
+            Ok(())
+        });
+    }
+
+    /// Statement of the backend selected by features, checked without DB
+    #[test]
+    fn test_replace_row_sql() {
+        let state = AlertState {
+            alert_name: "test alert".into(),
+            firing: false,
+            last_notified: None,
+        };
+        let query = replace_row!(alert_state::table, alert_state::alert_name, &state);
+        let sql = diesel::debug_query::<Backend, _>(&query).to_string();
+
+        #[cfg(not(feature = "postgres"))]
+        assert!(sql.starts_with("REPLACE INTO `alert_state`"), "{}", sql);
+        #[cfg(feature = "postgres")]
+        {
+            assert!(sql.starts_with(r#"INSERT INTO "alert_state""#), "{}", sql);
+            assert!(
+                sql.contains(r#"ON CONFLICT ("alert_name") DO UPDATE SET "firing" = "#),
+                "{}",
+                sql
+            );
+            // NULL is written as REPLACE does
+            assert!(sql.contains(r#""last_notified" = "#), "{}", sql);
+        }
+    }
+
+    /// Requires `TEST_DATABASE_URL`. Changes are rolled back
+    #[test]
+    #[ignore]
+    fn test_record_scrape_run() {
+        let url = std::env::var("TEST_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let started_at = chrono::NaiveDate::from_ymd(2037, 1, 1).and_hms(0, 0, 0);
+            let stamp = add_stamp(&conn, started_at)?;
+            assert_eq!(None, load_scrape_run(&conn, stamp.stamp_id)?);
+
+            let mut run = ScrapeRun {
+                stamp_id: stamp.stamp_id,
+                started_at,
+                finished_at: started_at + chrono::Duration::seconds(30),
+                currency_fetched: false,
+                currency_rows: 0,
+                currency_errors: 0,
+                balance_fetched: true,
+                balance_rows: 10,
+                balance_errors: 0,
+                price_fetched: false,
+                price_rows: 0,
+                price_errors: 1,
+                orderbook_fetched: true,
+                orderbook_rows: 200,
+                orderbook_errors: 1,
+                myorder_fetched: true,
+                myorder_rows: 3,
+                myorder_errors: 0,
+            };
+            record_scrape_run(&conn, &run)?;
+            assert_eq!(Some(run.clone()), load_scrape_run(&conn, stamp.stamp_id)?);
+
+            // Recording again replaces the run
+            run.price_fetched = true;
+            record_scrape_run(&conn, &run)?;
+            assert_eq!(vec![run], list_scrape_runs(&conn, &[stamp.stamp_id])?);
+
+            Ok(())
+        });
+    }
+
+    /// Requires `TEST_DATABASE_URL`. Changes are rolled back
+    #[test]
+    #[ignore]
+    fn test_currency_tag() {
+        let url = std::env::var("TEST_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let currency = add_currency(&conn, "TAGTEST".into(), "Tag test".into())?;
+            let id = currency.currency_id;
+            let tags_of = || -> Result<Vec<String>> {
+                Ok(list_currency_tags(&conn)?
+                    .into_iter()
+                    .filter(|t| t.currency_id == id)
+                    .map(|t| t.tag)
+                    .collect())
+            };
+
+            add_currency_tag(&conn, id, "major")?;
+            add_currency_tag(&conn, id, "layer1")?;
+            // Adding twice is fine
+            add_currency_tag(&conn, id, "major")?;
+            assert_eq!(vec!["layer1", "major"], tags_of()?);
+            assert!(add_currency_tag(&conn, id, "Not valid").is_err());
+
+            assert!(remove_currency_tag(&conn, id, "layer1")?);
+            assert!(!remove_currency_tag(&conn, id, "layer1")?);
+            assert_eq!(vec!["major"], tags_of()?);
+
+            Ok(())
+        });
+    }
+
+    /// Requires `TEST_DATABASE_URL`. Changes are rolled back
+    #[test]
+    #[ignore]
+    fn test_save_sync_cursor() {
+        let url = std::env::var("TEST_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let base = add_currency(&conn, "SYNCB".into(), "Sync base".into())?;
+            let quote = add_currency(&conn, "SYNCQ".into(), "Sync quote".into())?;
+            let market = add_market(&conn, base.currency_id, quote.currency_id)?;
+            assert_eq!(
+                None,
+                get_sync_cursor(&conn, market.market_id, SyncKind::Myorder)?
+            );
+
+            let mut cursor = SyncCursor {
+                market_id: market.market_id,
+                kind: SyncKind::Myorder,
+                synced_until: chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 0, 0),
+            };
+            save_sync_cursor(&conn, &cursor)?;
+            cursor.synced_until = chrono::NaiveDate::from_ymd(2021, 1, 8).and_hms(0, 0, 0);
+            save_sync_cursor(&conn, &cursor)?;
+            assert_eq!(
+                Some(cursor),
+                get_sync_cursor(&conn, market.market_id, SyncKind::Myorder)?
+            );
+
+            Ok(())
+        });
+    }
+
+    /// Requires `TEST_DATABASE_URL`. Changes are rolled back
+    #[test]
+    #[ignore]
+    fn test_add_stamp_source() {
+        let url = std::env::var("TEST_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let timestamp = |hour| chrono::NaiveDate::from_ymd(2037, 1, 1).and_hms(hour, 0, 0);
+
+            for (hour, &source) in StampSource::ALL.iter().enumerate() {
+                let added = add_stamp_from(&conn, timestamp(hour as u32), source)?;
+                let loaded = stamp::table
+                    .filter(stamp::stamp_id.eq(added.stamp_id))
+                    .first::<Stamp>(&conn)?;
+                assert_eq!(added, loaded);
+                assert_eq!(source, loaded.source);
+            }
+
+            let scraper = add_stamp(&conn, timestamp(10))?;
+            assert_eq!(StampSource::Scraper, scraper.source);
+            add_stamp_from(&conn, timestamp(11), StampSource::Import)?;
+
+            let latest = |ignore| get_latest_stamp(&conn, ignore).map(|s| s.map(|s| s.timestamp));
+            assert_eq!(Some(timestamp(11)), latest(false)?);
+            assert_eq!(Some(timestamp(10)), latest(true)?);
+
+            Ok(())
+        });
+    }
+
+    /// Requires `TEST_DATABASE_URL`. Changes are rolled back
+    #[test]
+    #[ignore]
+    fn test_add_orderbooks_bulk() {
+        let url = std::env::var("TEST_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let base = add_currency(&conn, "BULKB".into(), "Bulk base".into())?;
+            let quote = add_currency(&conn, "BULKQ".into(), "Bulk quote".into())?;
+            let market = add_market(&conn, base.currency_id, quote.currency_id)?;
+            let stamp = add_stamp(
+                &conn,
+                chrono::NaiveDate::from_ymd(2037, 1, 1).and_hms(0, 0, 0),
+            )?;
+            let next_orderbook_id = || {
+                next_id::table
+                    .select(next_id::orderbook)
+                    .first::<OrderbookId>(&conn)
+            };
+
+            let items = [
+                (OrderSide::Buy, 99.0, 1.0),
+                (OrderSide::Buy, 98.0, 2.0),
+                (OrderSide::Sell, 101.0, 3.0),
+            ];
+            let first_id = next_orderbook_id()?;
+            let added = add_orderbooks_bulk(&conn, market.market_id, stamp.stamp_id, &items)?;
+
+            // Ids are contiguous, and the range is reserved at once
+            let ids = added
+                .iter()
+                .map(|o| o.orderbook_id.inner())
+                .collect::<Vec<_>>();
+            let expected_ids = (0..3).map(|i| first_id.inner() + i).collect::<Vec<_>>();
+            assert_eq!(expected_ids, ids);
+            assert_eq!(first_id.inner() + 3, next_orderbook_id()?.inner());
+
+            let loaded = orderbook::table
+                .filter(orderbook::stamp_id.eq(stamp.stamp_id))
+                .order(orderbook::orderbook_id.asc())
+                .load::<Orderbook>(&conn)?;
+            assert_eq!(added, loaded);
+
+            // A failed insert doesn't consume ids
+            let next = next_orderbook_id()?;
+            let unknown_stamp = StampId::new(stamp.stamp_id.inner() + 1_000_000);
+            assert!(add_orderbooks_bulk(&conn, market.market_id, unknown_stamp, &items).is_err());
+            assert_eq!(next, next_orderbook_id()?);
+
+            assert!(add_orderbooks_bulk(&conn, market.market_id, stamp.stamp_id, &[])?.is_empty());
+            assert_eq!(next, next_orderbook_id()?);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_satoshi_amount() {
+        let satoshi: Amount = 0.000_000_01;
+        let holding: Amount = 21.0;
+
+        // A satoshi added to a holding is kept, while f32 rounds it off
+        assert!((holding + satoshi - holding - satoshi).abs() < satoshi * 1e-3);
+        assert_eq!(holding as f32, holding as f32 + satoshi as f32);
+    }
+
+    /// Requires `TEST_DATABASE_URL`. Changes are rolled back
+    #[test]
+    #[ignore]
+    fn test_satoshi_amount_round_trip() {
+        let url = std::env::var("TEST_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let base = add_currency(&conn, "SATB".into(), "Satoshi base".into())?;
+            let quote = add_currency(&conn, "SATQ".into(), "Satoshi quote".into())?;
+            let market = add_market(&conn, base.currency_id, quote.currency_id)?;
+            let stamp = add_stamp(
+                &conn,
+                chrono::NaiveDate::from_ymd(2037, 1, 1).and_hms(0, 0, 0),
+            )?;
+
+            let available = 12.345_678_91;
+            let pending = 0.000_000_01;
+            let balance = add_balance(&conn, base.currency_id, stamp.stamp_id, available, pending)?;
+            let price = add_price(&conn, market.market_id, stamp.stamp_id, 0.000_012_34)?;
+
+            let loaded = balance::table
+                .find(balance.balance_id)
+                .first::<Balance>(&conn)?;
+            assert_eq!(available, loaded.available);
+            assert_eq!(pending, loaded.pending);
+            let loaded = price::table.find(price.price_id).first::<Price>(&conn)?;
+            assert_eq!(0.000_012_34, loaded.amount);
+
+            Ok(())
+        });
+    }
+
+    /// Requires `TEST_DATABASE_URL`. Changes are rolled back
+    #[test]
+    #[ignore]
+    fn test_add_price_duplicated() {
+        let url = std::env::var("TEST_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let base = add_currency(&conn, "DUPB".into(), "Duplicated base".into())?;
+            let quote = add_currency(&conn, "DUPQ".into(), "Duplicated quote".into())?;
+            let market = add_market(&conn, base.currency_id, quote.currency_id)?;
+            let other_market = add_market(&conn, quote.currency_id, base.currency_id)?;
+            let stamp = add_stamp(
+                &conn,
+                chrono::NaiveDate::from_ymd(2037, 1, 1).and_hms(0, 0, 0),
+            )?;
+
+            add_price(&conn, market.market_id, stamp.stamp_id, 1.0)?;
+            assert!(matches!(
+                add_price(&conn, market.market_id, stamp.stamp_id, 2.0),
+                Err(Error::Logic(LogicError::DuplicatedPrice))
+            ));
+
+            // Nothing of the batch is added if any market is duplicated
+            let items = [(other_market.market_id, 0.5), (market.market_id, 2.0)];
+            assert!(matches!(
+                add_prices_bulk(&conn, stamp.stamp_id, &items),
+                Err(Error::Logic(LogicError::DuplicatedPrice))
+            ));
+            let items = [(other_market.market_id, 0.5), (other_market.market_id, 0.6)];
+            assert!(matches!(
+                add_prices_bulk(&conn, stamp.stamp_id, &items),
+                Err(Error::Logic(LogicError::DuplicatedPrice))
+            ));
+            let loaded = price::table
+                .filter(price::stamp_id.eq(stamp.stamp_id))
+                .load::<Price>(&conn)?;
+            assert_eq!(1, loaded.len());
+            assert_eq!(1.0, loaded[0].amount);
+
+            // Other markets of the stamp are still added
+            add_price(&conn, other_market.market_id, stamp.stamp_id, 0.5)?;
+
+            Ok(())
+        });
+    }
+
+    /// Requires `TEST_DATABASE_URL`. Changes are rolled back
+    #[test]
+    #[ignore]
+    fn test_add_recommendations_bulk() {
+        let url = std::env::var("TEST_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let base = add_currency(&conn, "RECB".into(), "Recommended base".into())?;
+            let quote = add_currency(&conn, "RECQ".into(), "Recommended quote".into())?;
+            let market = add_market(&conn, base.currency_id, quote.currency_id)?;
+            let other_market = add_market(&conn, quote.currency_id, base.currency_id)?;
+            let timestamp = |hour| chrono::NaiveDate::from_ymd(2037, 1, 1).and_hms(hour, 0, 0);
+            let stamp = add_stamp(&conn, timestamp(0))?;
+            let next_stamp = add_stamp(&conn, timestamp(1))?;
+            add_price(&conn, market.market_id, stamp.stamp_id, 100.0)?;
+
+            let items = [
+                (
+                    market.market_id,
+                    Some("rsiCross".to_string()),
+                    RecommendationType::Buy,
+                    Some(1.0),
+                    "RSI crossed above 30".to_string(),
+                ),
+                (
+                    market.market_id,
+                    None,
+                    RecommendationType::Buy,
+                    Some(0.75),
+                    "Weighted mean 0.750".to_string(),
+                ),
+            ];
+            let first_id = next_id::table
+                .select(next_id::recommendation)
+                .first::<RecommendationId>(&conn)?;
+            let added = add_recommendations_bulk(&conn, stamp.stamp_id, &items)?;
+            assert_eq!(2, added.len());
+            assert_eq!(first_id, added[0].recommendation_id);
+            assert_eq!(first_id.inner() + 1, added[1].recommendation_id.inner());
+
+            // Recommendations of a market are recorded once per stamp
+            assert!(matches!(
+                add_recommendations_bulk(&conn, stamp.stamp_id, &items),
+                Err(Error::Logic(LogicError::DuplicatedRecommendation))
+            ));
+            let neutral = [(
+                other_market.market_id,
+                Some("fixed".to_string()),
+                RecommendationType::Neutral,
+                None,
+                "never decides".to_string(),
+            )];
+            add_recommendations_bulk(&conn, stamp.stamp_id, &neutral)?;
+            add_recommendations_bulk(&conn, next_stamp.stamp_id, &items[1..])?;
+
+            // Prices are joined if the market has a price at the stamp
+            let loaded =
+                list_recommendations(&conn, Some(market.market_id), timestamp(0), timestamp(1))?;
+            assert_eq!(3, loaded.len());
+            assert_eq!((added[0].clone(), timestamp(0), Some(100.0)), loaded[0]);
+            assert_eq!((added[1].clone(), timestamp(0), Some(100.0)), loaded[1]);
+            assert_eq!(None, loaded[2].0.rule_name);
+            assert_eq!((timestamp(1), None), (loaded[2].1, loaded[2].2));
+
+            let loaded = list_recommendations(&conn, None, timestamp(0), timestamp(0))?;
+            assert_eq!(3, loaded.len());
+            assert_eq!(None, loaded[2].0.score);
+            assert_eq!(RecommendationType::Neutral, loaded[2].0.recommendation_type);
+
+            Ok(())
+        });
+    }
+
+    /// Requires `TEST_DATABASE_URL`. Changes are rolled back
+    #[test]
+    #[ignore]
+    fn test_add_prices_and_balances_bulk() {
+        let url = std::env::var("TEST_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let base = add_currency(&conn, "BULKB".into(), "Bulk base".into())?;
+            let quote = add_currency(&conn, "BULKQ".into(), "Bulk quote".into())?;
+            let market = add_market(&conn, base.currency_id, quote.currency_id)?;
+            let other_market = add_market(&conn, quote.currency_id, base.currency_id)?;
+            let stamp = add_stamp(
+                &conn,
+                chrono::NaiveDate::from_ymd(2037, 1, 1).and_hms(0, 0, 0),
+            )?;
+
+            let prices = add_prices_bulk(
+                &conn,
+                stamp.stamp_id,
+                &[(market.market_id, 2.0), (other_market.market_id, 0.5)],
+            )?;
+            assert_eq!(prices[0].price_id.inner() + 1, prices[1].price_id.inner());
+            let loaded = price::table
+                .filter(price::stamp_id.eq(stamp.stamp_id))
+                .order(price::price_id.asc())
+                .load::<Price>(&conn)?;
+            assert_eq!(prices, loaded);
+
+            let balances = add_balances_bulk(
+                &conn,
+                stamp.stamp_id,
+                &[(base.currency_id, 1.0, 0.0), (quote.currency_id, 2.0, 0.5)],
+            )?;
+            assert_eq!(
+                balances[0].balance_id.inner() + 1,
+                balances[1].balance_id.inner()
+            );
+            let loaded = balance::table
+                .filter(balance::stamp_id.eq(stamp.stamp_id))
+                .order(balance::balance_id.asc())
+                .load::<Balance>(&conn)?;
+            assert_eq!(balances, loaded);
+
+            // A failed insert doesn't consume ids
+            let next_price_id = || {
+                next_id::table
+                    .select(next_id::price)
+                    .first::<PriceId>(&conn)
+            };
+            let next = next_price_id()?;
+            let unknown_stamp = StampId::new(stamp.stamp_id.inner() + 1_000_000);
+            assert!(add_prices_bulk(&conn, unknown_stamp, &[(market.market_id, 1.0)]).is_err());
+            assert_eq!(next, next_price_id()?);
+
+            Ok(())
+        });
+    }
+
+    /// Requires `TEST_DATABASE_URL`. Changes are rolled back
+    #[test]
+    #[ignore]
+    fn test_prune_older_than() {
+        let url = std::env::var("TEST_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let base = add_currency(&conn, "PRUNEB".into(), "Prune base".into())?;
+            let quote = add_currency(&conn, "PRUNEQ".into(), "Prune quote".into())?;
+            let market = add_market(&conn, base.currency_id, quote.currency_id)?;
+            // Older than any real stamp, but within the range of TIMESTAMP
+            let old = add_past_stamp(
+                &conn,
+                chrono::NaiveDate::from_ymd(1999, 1, 1).and_hms(0, 0, 0),
+            )?;
+            let old_with_balance = add_past_stamp(
+                &conn,
+                chrono::NaiveDate::from_ymd(1999, 1, 2).and_hms(0, 0, 0),
+            )?;
+            let cutoff = chrono::NaiveDate::from_ymd(2000, 1, 1).and_hms(0, 0, 0);
+            let new = add_stamp(
+                &conn,
+                chrono::NaiveDate::from_ymd(2037, 1, 1).and_hms(0, 0, 0),
+            )?;
+
+            let items = (0..5)
+                .map(|i| (OrderSide::Buy, 100.0 - i as Amount, 1.0))
+                .collect::<Vec<_>>();
+            add_orderbooks_bulk(&conn, market.market_id, old.stamp_id, &items)?;
+            add_orderbooks_bulk(&conn, market.market_id, new.stamp_id, &items)?;
+            add_prices_bulk(&conn, old.stamp_id, &[(market.market_id, 1.0)])?;
+            add_prices_bulk(&conn, new.stamp_id, &[(market.market_id, 1.0)])?;
+            add_balances_bulk(
+                &conn,
+                old_with_balance.stamp_id,
+                &[(base.currency_id, 1.0, 0.0)],
+            )?;
+
+            let count_orderbooks = |stamp_id: StampId| {
+                orderbook::table
+                    .filter(orderbook::stamp_id.eq(stamp_id))
+                    .count()
+                    .get_result::<i64>(&conn)
+            };
+
+            // Stamps referred by orderbooks or prices are kept
+            assert_eq!(0, prune_unreferenced_stamps(&conn, cutoff, 2)?);
+
+            // Several batches
+            assert_eq!(5, prune_orderbooks(&conn, cutoff, 2)?);
+            assert_eq!(0, count_orderbooks(old.stamp_id)?);
+            assert_eq!(5, count_orderbooks(new.stamp_id)?);
+
+            assert_eq!(1, prune_prices(&conn, cutoff, 2)?);
+            assert_eq!(0, prune_prices(&conn, cutoff, 2)?);
+
+            // A stamp referred by balances is kept
+            assert_eq!(1, prune_unreferenced_stamps(&conn, cutoff, 1)?);
+            let remaining = stamp::table
+                .filter(stamp::timestamp.lt(cutoff))
+                .select(stamp::stamp_id)
+                .load::<StampId>(&conn)?;
+            assert_eq!(vec![old_with_balance.stamp_id], remaining);
+            assert!(stamp::table
+                .find(new.stamp_id)
+                .first::<Stamp>(&conn)
+                .is_ok());
+
+            Ok(())
+        });
+    }
+
+    /// Requires `TEST_DATABASE_URL`. Changes are rolled back
+    #[test]
+    #[ignore]
+    fn test_merge_adjacent_stamps_conflicts() {
+        let url = std::env::var("TEST_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let base = add_currency(&conn, "MERGEB".into(), "Merge base".into())?;
+            let quote = add_currency(&conn, "MERGEQ".into(), "Merge quote".into())?;
+            let market = add_market(&conn, base.currency_id, quote.currency_id)?;
+            let other_market = add_market(&conn, quote.currency_id, base.currency_id)?;
+
+            let timestamp = |second| chrono::NaiveDate::from_ymd(2037, 1, 1).and_hms(0, 0, second);
+            let into = add_stamp(&conn, timestamp(0))?;
+            let from = add_stamp(&conn, timestamp(10))?;
+            let window = chrono::Duration::minutes(1);
+
+            add_balance(&conn, base.currency_id, into.stamp_id, 1.0, 0.0)?;
+            add_balance(&conn, quote.currency_id, into.stamp_id, 5.0, 0.0)?;
+            add_balance(&conn, base.currency_id, from.stamp_id, 2.0, 0.5)?;
+            add_price(&conn, market.market_id, into.stamp_id, 100.0)?;
+            add_price(&conn, market.market_id, from.stamp_id, 101.0)?;
+            add_price(&conn, other_market.market_id, from.stamp_id, 0.01)?;
+
+            let merge = StampMerge {
+                from: from.clone(),
+                into: into.clone(),
+            };
+
+            // Dry run writes nothing
+            let report = merge_adjacent_stamps(&conn, window, true)?;
+            assert!(report.merges.contains(&merge));
+            assert!(stamp::table
+                .find(from.stamp_id)
+                .first::<Stamp>(&conn)
+                .is_ok());
+
+            let report = merge_adjacent_stamps(&conn, window, false)?;
+            assert!(report.merges.contains(&merge));
+            assert!(stamp::table
+                .find(from.stamp_id)
+                .first::<Stamp>(&conn)
+                .optional()?
+                .is_none());
+
+            // Rows of the newer stamp win conflicts, and the rest of the older stamp are kept
+            let balances = balance::table
+                .filter(balance::stamp_id.eq(into.stamp_id))
+                .order(balance::currency_id.asc())
+                .load::<Balance>(&conn)?
+                .into_iter()
+                .map(|b| (b.currency_id, b.available, b.pending))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                vec![(base.currency_id, 2.0, 0.5), (quote.currency_id, 5.0, 0.0)],
+                balances
+            );
+            let prices = price::table
+                .filter(price::stamp_id.eq(into.stamp_id))
+                .order(price::market_id.asc())
+                .load::<Price>(&conn)?
+                .into_iter()
+                .map(|p| (p.market_id, p.amount))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                vec![(market.market_id, 101.0), (other_market.market_id, 0.01)],
+                prices
+            );
+
+            Ok(())
+        });
+    }
+
+    /// Requires `TEST_DATABASE_URL`. Changes are rolled back
+    #[test]
+    #[ignore]
+    fn test_merge_adjacent_stamps_foreign_keys() {
+        let url = std::env::var("TEST_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let base = add_currency(&conn, "MERGEB".into(), "Merge base".into())?;
+            let quote = add_currency(&conn, "MERGEQ".into(), "Merge quote".into())?;
+            let market = add_market(&conn, base.currency_id, quote.currency_id)?;
+            let other_market = add_market(&conn, quote.currency_id, base.currency_id)?;
+
+            let timestamp = |second| chrono::NaiveDate::from_ymd(2037, 1, 1).and_hms(0, 0, second);
+            let into = add_stamp(&conn, timestamp(0))?;
+            let from = add_stamp(&conn, timestamp(10))?;
+
+            // Orderbooks of a market are replaced as a whole
+            add_orderbooks_bulk(
+                &conn,
+                market.market_id,
+                into.stamp_id,
+                &[(OrderSide::Buy, 99.0, 1.0), (OrderSide::Sell, 102.0, 1.0)],
+            )?;
+            let moved_orderbooks = add_orderbooks_bulk(
+                &conn,
+                market.market_id,
+                from.stamp_id,
+                &[(OrderSide::Buy, 100.0, 2.0)],
+            )?;
+            let other_orderbooks = add_orderbooks_bulk(
+                &conn,
+                other_market.market_id,
+                from.stamp_id,
+                &[(OrderSide::Sell, 0.02, 3.0)],
+            )?;
+
+            let add_order = |id: &str, stamp_id, state| {
+                add_or_update_myorder(
+                    &conn,
+                    id.into(),
+                    market.market_id,
+                    stamp_id,
+                    1.0,
+                    1.0,
+                    1.0,
+                    OrderType::Limit,
+                    OrderSide::Buy,
+                    state,
+                )
+            };
+            add_order("merge-a", from.stamp_id, OrderState::Opened)?;
+            add_order("merge-b", into.stamp_id, OrderState::Opened)?;
+            add_order("merge-b", from.stamp_id, OrderState::Filled)?;
+
+            let expiry = timestamp(0) + chrono::Duration::minutes(5);
+            let approval = add_pending_approval(
+                &conn,
+                market.market_id,
+                from.stamp_id,
+                expiry,
+                100.0,
+                1.0,
+                100.0,
+                -100.0,
+                OrderType::Limit,
+                OrderSide::Buy,
+                "merge test".into(),
+            )?;
+
+            merge_adjacent_stamps(&conn, chrono::Duration::minutes(1), false)?;
+
+            let orderbooks = orderbook::table
+                .filter(orderbook::stamp_id.eq(into.stamp_id))
+                .order(orderbook::orderbook_id.asc())
+                .load::<Orderbook>(&conn)?;
+            let expected = moved_orderbooks
+                .into_iter()
+                .chain(other_orderbooks)
+                .map(|o| Orderbook {
+                    stamp_id: into.stamp_id,
+                    ..o
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(expected, orderbooks);
+
+            let orders = myorder::table
+                .filter(myorder::transaction_id.eq_any(vec!["merge-a", "merge-b"]))
+                .order(myorder::transaction_id.asc())
+                .load::<MyOrder>(&conn)?;
+            assert_eq!(2, orders.len());
+            for order in orders.iter() {
+                assert_eq!(into.stamp_id, order.created_stamp_id);
+                assert_eq!(into.stamp_id, order.modified_stamp_id);
+            }
+            let change = myorder_state_change::table
+                .filter(myorder_state_change::myorder_id.eq(orders[1].myorder_id))
+                .first::<MyorderStateChange>(&conn)?;
+            assert_eq!(into.stamp_id, change.stamp_id);
+
+            let approval = pending_approval::table
+                .find(approval.pending_approval_id)
+                .first::<PendingApproval>(&conn)?;
+            assert_eq!(into.stamp_id, approval.stamp_id);
+
+            Ok(())
+        });
+    }
+}
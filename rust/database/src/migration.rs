@@ -0,0 +1,79 @@
+//! Schema bootstrap of trade DB and simulation DB.
+//! Migrations require a user who can create tables, unlike `autotrader` of docker-autotrader-db.
+//! Tables already created by docker-autotrader-db are kept, so the initial migrations only record themselves.
+//! PostgreSQL has its own migrations under `migrations/postgres`, which start from the latest schema
+use crate::error::Result;
+use crate::logic::Conn;
+
+#[cfg(not(feature = "postgres"))]
+mod trade {
+    embed_migrations!("migrations/main");
+}
+
+#[cfg(not(feature = "postgres"))]
+mod sim {
+    embed_migrations!("migrations/sim");
+}
+
+#[cfg(feature = "postgres")]
+mod trade {
+    embed_migrations!("migrations/postgres/main");
+}
+
+#[cfg(feature = "postgres")]
+mod sim {
+    embed_migrations!("migrations/postgres/sim");
+}
+
+/// Create tables of trade DB and seed `next_id` if they don't exist yet
+pub fn run_migrations(conn: &Conn) -> Result<()> {
+    trade::embedded_migrations::run(conn)?;
+    Ok(())
+}
+
+/// Create tables of simulation DB and seed `next_id` and `speculator_lock` if they don't exist yet
+pub fn run_sim_migrations(conn: &Conn) -> Result<()> {
+    sim::embedded_migrations::run(conn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{next_id, speculator_lock};
+    use diesel::prelude::*;
+
+    /// Requires `TEST_MIGRATION_DATABASE_URL` of a disposable DB, whose user can create tables
+    #[test]
+    #[ignore]
+    fn test_run_migrations() {
+        let url = std::env::var("TEST_MIGRATION_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        // Second run has nothing to do
+        run_migrations(&conn).unwrap();
+        run_migrations(&conn).unwrap();
+
+        let seeds = next_id::table.count().get_result::<i64>(&conn).unwrap();
+        assert_eq!(1, seeds);
+    }
+
+    /// Requires `TEST_MIGRATION_SIM_DATABASE_URL` of a disposable DB, whose user can create tables
+    #[test]
+    #[ignore]
+    fn test_run_sim_migrations() {
+        let url = std::env::var("TEST_MIGRATION_SIM_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        run_sim_migrations(&conn).unwrap();
+        run_sim_migrations(&conn).unwrap();
+
+        let seeds = next_id::table.count().get_result::<i64>(&conn).unwrap();
+        assert_eq!(1, seeds);
+        let locks = speculator_lock::table
+            .count()
+            .get_result::<i64>(&conn)
+            .unwrap();
+        assert_eq!(1, locks);
+    }
+}
@@ -2,7 +2,8 @@ pub use crate::custom_sql_type::*;
 use crate::schema::*;
 pub use chrono::NaiveDateTime;
 
-pub type Amount = f32;
+/// Amounts and prices. Every amount column is DOUBLE, so that satoshi-scale quantities round-trip
+pub type Amount = f64;
 
 #[derive(Debug, Clone, PartialEq, Queryable, Insertable)]
 #[table_name = "currency"]
@@ -10,14 +11,18 @@ pub struct Currency {
     pub currency_id: CurrencyId,
     pub symbol: String,
     pub name: String,
+    /// `false` if the currency is delisted from the exchange
+    pub active: bool,
 }
 
 impl Currency {
+    /// Create an active currency
     pub fn new(currency_id: CurrencyId, symbol: String, name: String) -> Self {
         Self {
             currency_id,
             symbol,
             name,
+            active: true,
         }
     }
 }
@@ -27,13 +32,20 @@ impl Currency {
 pub struct Stamp {
     pub stamp_id: StampId,
     pub timestamp: NaiveDateTime,
+    pub source: StampSource,
 }
 
 impl Stamp {
+    /// Create a stamp of the scraper
     pub fn new(stamp_id: StampId, timestamp: NaiveDateTime) -> Self {
+        Self::with_source(stamp_id, timestamp, StampSource::Scraper)
+    }
+
+    pub fn with_source(stamp_id: StampId, timestamp: NaiveDateTime, source: StampSource) -> Self {
         Self {
             stamp_id,
             timestamp,
+            source,
         }
     }
 }
@@ -130,3 +142,135 @@ pub struct MyOrder {
     pub side: OrderSide,
     pub state: OrderState,
 }
+
+/// Transition of an order's state, found at `stamp_id`
+#[derive(Debug, Clone, PartialEq, Queryable, Insertable)]
+#[table_name = "myorder_state_change"]
+pub struct MyorderStateChange {
+    pub myorder_id: MyorderId,
+    pub old_state: OrderState,
+    pub new_state: OrderState,
+    pub stamp_id: StampId,
+}
+
+/// Category of a currency for reporting, like `stablecoin`. A currency may have several tags
+#[derive(Debug, Clone, PartialEq, Eq, Queryable, Insertable)]
+#[table_name = "currency_tag"]
+pub struct CurrencyTag {
+    pub currency_id: CurrencyId,
+    pub tag: String,
+}
+
+/// Progress of syncing `kind` of a market. Data before `synced_until` are already persisted
+#[derive(Debug, Clone, PartialEq, Queryable, Insertable, AsChangeset)]
+#[table_name = "sync_cursor"]
+#[primary_key(market_id, kind)]
+pub struct SyncCursor {
+    pub market_id: MarketId,
+    pub kind: SyncKind,
+    pub synced_until: NaiveDateTime,
+}
+
+/// Outcome of the scraper run which created a stamp, so that partially scraped stamps can be told.
+/// For each section, `*_fetched` is `false` if it was disabled, failed or fell short of the success threshold,
+/// `*_rows` counts fetched rows and `*_errors` counts failed fetches
+#[derive(Debug, Clone, PartialEq, Queryable, Insertable, AsChangeset)]
+#[table_name = "scrape_run"]
+#[primary_key(stamp_id)]
+pub struct ScrapeRun {
+    pub stamp_id: StampId,
+    pub started_at: NaiveDateTime,
+    pub finished_at: NaiveDateTime,
+    pub currency_fetched: bool,
+    pub currency_rows: i32,
+    pub currency_errors: i32,
+    pub balance_fetched: bool,
+    pub balance_rows: i32,
+    pub balance_errors: i32,
+    pub price_fetched: bool,
+    pub price_rows: i32,
+    pub price_errors: i32,
+    pub orderbook_fetched: bool,
+    pub orderbook_rows: i32,
+    pub orderbook_errors: i32,
+    pub myorder_fetched: bool,
+    pub myorder_rows: i32,
+    pub myorder_errors: i32,
+}
+
+/// Stop order held by the simulation until its trigger price is reached or it expires
+#[derive(Debug, Clone, PartialEq, Queryable, Insertable)]
+#[table_name = "stop_order"]
+pub struct StopOrder {
+    pub stop_order_id: StopOrderId,
+    pub market_id: MarketId,
+    pub side: OrderSide,
+    pub order_type: OrderType,
+    pub base_quantity: Amount,
+    pub quote_quantity: Amount,
+    /// Limit price, or expected fill price of stop market orders
+    pub price: Amount,
+    pub trigger_price: Amount,
+    /// Change of quote balance when this order is filled, including fee
+    pub expected_net_quote: Amount,
+    /// The order is not triggered after this time
+    pub expiry: NaiveDateTime,
+}
+
+/// Lock preventing speculator runs from overlapping. Released if `holder` is `None`
+#[derive(Debug, Clone, PartialEq, Queryable)]
+pub struct SpeculatorLock {
+    pub lock_id: i32,
+    pub holder: Option<String>,
+    pub acquired: Option<NaiveDateTime>,
+}
+
+/// Cooldown state of an alert rule
+#[derive(Debug, Clone, PartialEq, Queryable, Insertable, AsChangeset)]
+#[table_name = "alert_state"]
+#[primary_key(alert_name)]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct AlertState {
+    pub alert_name: String,
+    /// `true` while the condition holds. Cleared when the condition clears, re-arming the alert
+    pub firing: bool,
+    pub last_notified: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Queryable, Insertable)]
+#[table_name = "pending_approval"]
+pub struct PendingApproval {
+    pub pending_approval_id: PendingApprovalId,
+    pub market_id: MarketId,
+    /// Stamp at which the order was recommended
+    pub stamp_id: StampId,
+    /// The order is not executed after this time
+    pub expiry: NaiveDateTime,
+    pub price: Amount,
+    pub base_quantity: Amount,
+    pub quote_quantity: Amount,
+    /// Change of quote balance when this order is filled, including fee
+    pub expected_net_quote: Amount,
+    pub order_type: OrderType,
+    pub side: OrderSide,
+    /// Reasons of the recommendation
+    pub recommendation: String,
+    pub state: ApprovalState,
+    /// Market price when approved
+    pub approved_price: Option<Amount>,
+}
+
+/// Recommendation of a speculator rule at a stamp, kept to measure hit-rate of rules
+#[derive(Debug, Clone, PartialEq, Queryable, Insertable)]
+#[table_name = "recommendation"]
+pub struct RecommendationRecord {
+    pub recommendation_id: RecommendationId,
+    pub stamp_id: StampId,
+    pub market_id: MarketId,
+    /// `None` for the aggregated outcome of rules
+    pub rule_name: Option<String>,
+    pub recommendation_type: RecommendationType,
+    /// Score of the rule, or weighted mean of scores for the aggregated outcome. `None` if undefined
+    pub score: Option<f64>,
+    pub reason: String,
+}
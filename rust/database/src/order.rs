@@ -0,0 +1,130 @@
+use crate::custom_sql_type::*;
+use crate::model::{Amount, MyOrder};
+
+/// Order to be placed on a market
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewOrder {
+    pub market_id: MarketId,
+    pub side: OrderSide,
+    pub order_type: OrderType,
+    /// Limit price, or expected fill price of market orders
+    pub price: Amount,
+    pub base_quantity: Amount,
+    pub quote_quantity: Amount,
+    /// Market price at which a stop order is placed
+    pub trigger_price: Option<Amount>,
+}
+
+/// Order reported by the exchange. Its market is known by the caller, which queried the exchange for the market
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExchangeOrder {
+    pub transaction_id: String,
+    pub side: OrderSide,
+    pub order_type: OrderType,
+    pub price: Amount,
+    pub base_quantity: Amount,
+    pub quote_quantity: Amount,
+    pub state: OrderState,
+}
+
+impl ExchangeOrder {
+    /// Record of this order in `market_id`, created and modified at `stamp_id`
+    pub fn into_myorder(
+        self,
+        myorder_id: MyorderId,
+        market_id: MarketId,
+        stamp_id: StampId,
+    ) -> MyOrder {
+        MyOrder {
+            myorder_id,
+            transaction_id: self.transaction_id,
+            market_id,
+            created_stamp_id: stamp_id,
+            modified_stamp_id: stamp_id,
+            price: self.price,
+            base_quantity: self.base_quantity,
+            quote_quantity: self.quote_quantity,
+            order_type: self.order_type,
+            side: self.side,
+            state: self.state,
+        }
+    }
+}
+
+impl From<MyOrder> for ExchangeOrder {
+    fn from(myorder: MyOrder) -> Self {
+        Self {
+            transaction_id: myorder.transaction_id,
+            side: myorder.side,
+            order_type: myorder.order_type,
+            price: myorder.price,
+            base_quantity: myorder.base_quantity,
+            quote_quantity: myorder.quote_quantity,
+            state: myorder.state,
+        }
+    }
+}
+
+impl From<&MyOrder> for NewOrder {
+    fn from(myorder: &MyOrder) -> Self {
+        Self {
+            market_id: myorder.market_id,
+            side: myorder.side,
+            order_type: myorder.order_type,
+            price: myorder.price,
+            base_quantity: myorder.base_quantity,
+            quote_quantity: myorder.quote_quantity,
+            trigger_price: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange_order() -> ExchangeOrder {
+        ExchangeOrder {
+            transaction_id: "order-1".into(),
+            side: OrderSide::Sell,
+            order_type: OrderType::Limit,
+            price: 0.5,
+            base_quantity: 10.0,
+            quote_quantity: 5.0,
+            state: OrderState::Opened,
+        }
+    }
+
+    #[test]
+    fn test_exchange_order_round_trip() {
+        let myorder =
+            exchange_order().into_myorder(MyorderId::new(3), MarketId::new(2), StampId::new(1));
+
+        assert_eq!(MyorderId::new(3), myorder.myorder_id);
+        assert_eq!(MarketId::new(2), myorder.market_id);
+        assert_eq!(StampId::new(1), myorder.created_stamp_id);
+        assert_eq!(StampId::new(1), myorder.modified_stamp_id);
+        assert_eq!(exchange_order(), ExchangeOrder::from(myorder));
+    }
+
+    #[test]
+    fn test_new_order_from_myorder() {
+        let myorder =
+            exchange_order().into_myorder(MyorderId::new(3), MarketId::new(2), StampId::new(1));
+
+        let order = NewOrder::from(&myorder);
+
+        assert_eq!(
+            NewOrder {
+                market_id: MarketId::new(2),
+                side: OrderSide::Sell,
+                order_type: OrderType::Limit,
+                price: 0.5,
+                base_quantity: 10.0,
+                quote_quantity: 5.0,
+                trigger_price: None,
+            },
+            order
+        );
+    }
+}
@@ -5,14 +5,19 @@ table! {
         currency_id -> Integer,
         symbol -> VarChar,
         name -> VarChar,
+        active -> Bool,
     }
 }
 
 table! {
+    use diesel::sql_types::*;
+    use crate::custom_sql_type::*;
+
     stamp (stamp_id) {
         stamp_id -> Integer,
         #[sql_name = "stamp"]
         timestamp -> Timestamp,
+        source -> StampSourceMapping,
     }
 }
 
@@ -21,8 +26,8 @@ table! {
         balance_id -> Integer,
         currency_id -> Integer,
         stamp_id -> Integer,
-        available -> Float,
-        pending -> Float,
+        available -> Double,
+        pending -> Double,
     }
 }
 
@@ -43,7 +48,7 @@ table! {
         market_id -> Integer,
         stamp_id -> Integer,
         #[sql_name = "price"]
-        amount -> Float,
+        amount -> Double,
     }
 }
 
@@ -62,8 +67,8 @@ table! {
         market_id -> Integer,
         stamp_id -> Integer,
         side -> OrderSideMapping,
-        price -> Float,
-        volume -> Float,
+        price -> Double,
+        volume -> Double,
     }
 }
 
@@ -82,9 +87,9 @@ table! {
         market_id -> Integer,
         created_stamp_id -> Integer,
         modified_stamp_id -> Integer,
-        price -> Float,
-        base_quantity -> Float,
-        quote_quantity -> Float,
+        price -> Double,
+        base_quantity -> Double,
+        quote_quantity -> Double,
         order_type -> OrderTypeMapping,
         side -> OrderSideMapping,
         state -> OrderStateMapping,
@@ -94,6 +99,155 @@ table! {
 joinable!(myorder -> market(market_id));
 allow_tables_to_appear_in_same_query!(market, myorder);
 
+// Also exists in simulation DB
+table! {
+    use diesel::sql_types::*;
+    use crate::custom_sql_type::*;
+
+    myorder_state_change (myorder_id, new_state) {
+        myorder_id -> Integer,
+        old_state -> OrderStateMapping,
+        new_state -> OrderStateMapping,
+        stamp_id -> Integer,
+    }
+}
+
+joinable!(myorder_state_change -> myorder(myorder_id));
+joinable!(myorder_state_change -> stamp(stamp_id));
+allow_tables_to_appear_in_same_query!(myorder, myorder_state_change);
+allow_tables_to_appear_in_same_query!(stamp, myorder_state_change);
+
+table! {
+    use diesel::sql_types::*;
+    use crate::custom_sql_type::*;
+
+    pending_approval (pending_approval_id) {
+        pending_approval_id -> Integer,
+        market_id -> Integer,
+        stamp_id -> Integer,
+        expiry -> Timestamp,
+        price -> Double,
+        base_quantity -> Double,
+        quote_quantity -> Double,
+        expected_net_quote -> Double,
+        order_type -> OrderTypeMapping,
+        side -> OrderSideMapping,
+        recommendation -> VarChar,
+        state -> ApprovalStateMapping,
+        approved_price -> Nullable<Double>,
+    }
+}
+
+joinable!(pending_approval -> market(market_id));
+allow_tables_to_appear_in_same_query!(market, pending_approval);
+
+table! {
+    use diesel::sql_types::*;
+    use crate::custom_sql_type::*;
+
+    recommendation (recommendation_id) {
+        recommendation_id -> Integer,
+        stamp_id -> Integer,
+        market_id -> Integer,
+        rule_name -> Nullable<VarChar>,
+        recommendation_type -> RecommendationTypeMapping,
+        score -> Nullable<Double>,
+        reason -> Text,
+    }
+}
+
+joinable!(recommendation -> market(market_id));
+allow_tables_to_appear_in_same_query!(market, recommendation);
+joinable!(recommendation -> stamp(stamp_id));
+allow_tables_to_appear_in_same_query!(stamp, recommendation);
+allow_tables_to_appear_in_same_query!(price, recommendation);
+
+table! {
+    currency_tag (currency_id, tag) {
+        currency_id -> Integer,
+        tag -> VarChar,
+    }
+}
+
+joinable!(currency_tag -> currency(currency_id));
+allow_tables_to_appear_in_same_query!(currency, currency_tag);
+
+table! {
+    use diesel::sql_types::*;
+    use crate::custom_sql_type::*;
+
+    sync_cursor (market_id, kind) {
+        market_id -> Integer,
+        kind -> SyncKindMapping,
+        synced_until -> Timestamp,
+    }
+}
+
+joinable!(sync_cursor -> market(market_id));
+allow_tables_to_appear_in_same_query!(market, sync_cursor);
+
+table! {
+    scrape_run (stamp_id) {
+        stamp_id -> Integer,
+        started_at -> Timestamp,
+        finished_at -> Timestamp,
+        currency_fetched -> Bool,
+        currency_rows -> Integer,
+        currency_errors -> Integer,
+        balance_fetched -> Bool,
+        balance_rows -> Integer,
+        balance_errors -> Integer,
+        price_fetched -> Bool,
+        price_rows -> Integer,
+        price_errors -> Integer,
+        orderbook_fetched -> Bool,
+        orderbook_rows -> Integer,
+        orderbook_errors -> Integer,
+        myorder_fetched -> Bool,
+        myorder_rows -> Integer,
+        myorder_errors -> Integer,
+    }
+}
+
+joinable!(scrape_run -> stamp(stamp_id));
+allow_tables_to_appear_in_same_query!(stamp, scrape_run);
+
+// Exists only in simulation DB
+table! {
+    use diesel::sql_types::*;
+    use crate::custom_sql_type::*;
+
+    stop_order (stop_order_id) {
+        stop_order_id -> Integer,
+        market_id -> Integer,
+        side -> OrderSideMapping,
+        order_type -> OrderTypeMapping,
+        base_quantity -> Double,
+        quote_quantity -> Double,
+        price -> Double,
+        trigger_price -> Double,
+        expected_net_quote -> Double,
+        expiry -> Timestamp,
+    }
+}
+
+// Exists only in simulation DB. Has only one row
+table! {
+    speculator_lock (lock_id) {
+        lock_id -> Integer,
+        holder -> Nullable<VarChar>,
+        acquired -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    alert_state (alert_name) {
+        alert_name -> VarChar,
+        firing -> Bool,
+        last_notified -> Nullable<Timestamp>,
+    }
+}
+
 table! {
     next_id (currency) {
         currency -> Integer,
@@ -103,5 +257,7 @@ table! {
         price -> Integer,
         orderbook -> Integer,
         myorder -> Integer,
+        pending_approval -> Integer,
+        recommendation -> Integer,
     }
 }
@@ -2,10 +2,99 @@ use anyhow::{anyhow, Result};
 use apply::Apply;
 use database::model::NaiveDateTime;
 use json::JsonValue;
+use once_cell::sync::OnceCell;
 use qstring::QString;
+use reqwest::blocking::{Client, Request, RequestBuilder, Response};
+use reqwest::header::{HeaderName, CONTENT_TYPE, LOCATION};
+use reqwest::redirect::Policy;
 pub use reqwest::Method;
 use reqwest::Url;
+use std::collections::{HashMap, VecDeque};
 use std::env;
+use std::fmt::Debug;
+use std::io::Read;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error as ThisError;
+
+/// Maximum length of response body included in errors
+const SNIPPET_LEN: usize = 200;
+
+/// Default of `NICEHASH_MAX_RESPONSE_BYTES`
+const DEFAULT_MAX_RESPONSE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Default of `NICEHASH_HTTP_TIMEOUT_SECS`
+const DEFAULT_HTTP_TIMEOUT_SECS: u64 = 10;
+
+/// Server time is fetched again after this, so that drift of the local clock doesn't accumulate in long-running processes
+const CLOCK_OFFSET_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Failures of API calls which callers should handle distinctly from ordinary errors
+#[derive(Debug, ThisError)]
+pub enum ApiError {
+    /// Server returned non-JSON (typically HTML) body, e.g. during maintenance
+    #[error("NiceHash appears to be in maintenance (status {status}): {snippet}")]
+    ServiceUnavailable { status: u16, snippet: String },
+    /// Redirect which can't be followed. Signed private calls are never redirected because the signature covers the path
+    #[error("{path} is redirected to {location:?} (status {status})")]
+    Redirected {
+        status: u16,
+        path: String,
+        location: Option<String>,
+    },
+    /// No response within the timeout of the client, either while connecting or reading
+    #[error("{path} timed out")]
+    Timeout { path: String },
+    /// Response body exceeds `NICEHASH_MAX_RESPONSE_BYTES`, so it is not parsed
+    #[error("Response of {path} exceeds {limit} bytes")]
+    ResponseTooLarge { path: String, limit: u64 },
+    /// Server answered with an error JSON or an error status, e.g. for insufficient balance
+    #[error("{path} is rejected (status {status}): {error}")]
+    Rejected {
+        status: u16,
+        path: String,
+        #[source]
+        error: NicehashApiError,
+    },
+}
+
+/// Error payload like `{"error_id": "...", "errors": [{"code": 2001, "message": "..."}]}`
+#[derive(Debug, Clone, PartialEq, ThisError)]
+#[error("{} (code {:?}, error id {:?})", .messages.join(", "), .code, .error_id)]
+pub struct NicehashApiError {
+    pub error_id: Option<String>,
+    /// Code of the first error
+    pub code: Option<i64>,
+    pub messages: Vec<String>,
+}
+
+impl NicehashApiError {
+    /// Detect an error payload. `None` if `json` is not an error
+    pub fn from_json(json: &JsonValue) -> Option<Self> {
+        let errors = &json["errors"];
+        if !errors.is_array() || errors.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            error_id: json["error_id"].as_str().map(str::to_string),
+            code: errors[0]["code"].as_i64(),
+            messages: errors
+                .members()
+                .map(|e| e["message"].as_str().unwrap_or("Unknown error").to_string())
+                .collect(),
+        })
+    }
+}
+
+/// Return `true` if `e` is `ApiError::ServiceUnavailable`
+pub fn is_service_unavailable(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<ApiError>(),
+        Some(ApiError::ServiceUnavailable { .. })
+    )
+}
 
 #[derive(Debug, Clone)]
 pub struct ApiKey {
@@ -34,69 +123,129 @@ impl ApiKey {
     }
 }
 
+/// Retry of idempotent GET calls on connection errors, 429 and 5xx responses, with exponential backoff
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts including the first call
+    pub max_attempts: usize,
+    /// Delay before the first retry, doubled on each retry and extended by random jitter up to the same length
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Delay before `retry`-th retry, starting at 1. `jitter` is in [0, 1)
+    fn delay(&self, retry: usize, jitter: f64) -> Duration {
+        let exponent = retry.saturating_sub(1).min(16) as i32;
+        self.base_delay
+            .mul_f64(2f64.powi(exponent) * (1.0 + jitter.clamp(0.0, 1.0)))
+    }
+}
+
 pub struct PublicApi;
 
 pub struct PrivateApi;
 
 #[derive(Debug)]
-pub struct ApiCallBuilder<T, M, P, Q, K> {
+pub struct ApiCallBuilder<'a, T, M, P, Q, K, B = ()> {
     api_type: T,
     method: M,
     api_path: P,
     query: Q,
     api_key: K,
+    body: B,
+    retry: Option<RetryPolicy>,
+    transport: &'a dyn Transport,
 }
 
-impl ApiCallBuilder<(), (), (), (), ()> {
-    pub fn new() -> ApiCallBuilder<(), (), (), (), ()> {
+impl ApiCallBuilder<'static, (), (), (), (), (), ()> {
+    pub fn new() -> ApiCallBuilder<'static, (), (), (), (), (), ()> {
         ApiCallBuilder {
             api_type: (),
             method: (),
             api_path: (),
             query: (),
             api_key: (),
+            body: (),
+            retry: None,
+            transport: &DEFAULT_TRANSPORT,
         }
     }
 }
 
-impl<M, P, Q, K> ApiCallBuilder<(), M, P, Q, K> {
-    pub fn public_api(self) -> ApiCallBuilder<PublicApi, M, P, Q, K> {
+impl<'a, T, M, P, Q, K, B> ApiCallBuilder<'a, T, M, P, Q, K, B> {
+    /// Retry the call by `policy`. Calls are not retried by default
+    pub fn retry(self, policy: RetryPolicy) -> Self {
+        Self {
+            retry: Some(policy),
+            ..self
+        }
+    }
+
+    /// Send the call by `transport` instead of `HttpTransport` of the shared clients
+    pub fn transport<'t>(
+        self,
+        transport: &'t dyn Transport,
+    ) -> ApiCallBuilder<'t, T, M, P, Q, K, B> {
+        ApiCallBuilder {
+            api_type: self.api_type,
+            method: self.method,
+            api_path: self.api_path,
+            query: self.query,
+            api_key: self.api_key,
+            body: self.body,
+            retry: self.retry,
+            transport,
+        }
+    }
+}
+
+impl<'a, M, P, Q, K, B> ApiCallBuilder<'a, (), M, P, Q, K, B> {
+    pub fn public_api(self) -> ApiCallBuilder<'a, PublicApi, M, P, Q, K, B> {
         ApiCallBuilder {
             api_type: PublicApi,
             method: self.method,
             api_path: self.api_path,
             query: self.query,
             api_key: self.api_key,
+            body: self.body,
+            retry: self.retry,
+            transport: self.transport,
         }
     }
 
-    pub fn private_api(self) -> ApiCallBuilder<PrivateApi, M, P, Q, K> {
+    pub fn private_api(self) -> ApiCallBuilder<'a, PrivateApi, M, P, Q, K, B> {
         ApiCallBuilder {
             api_type: PrivateApi,
             method: self.method,
             api_path: self.api_path,
             query: self.query,
             api_key: self.api_key,
+            body: self.body,
+            retry: self.retry,
+            transport: self.transport,
         }
     }
 }
 
-impl<T, P, Q, K> ApiCallBuilder<T, (), P, Q, K> {
-    pub fn method(self, method: Method) -> ApiCallBuilder<T, Method, P, Q, K> {
+impl<'a, T, P, Q, K, B> ApiCallBuilder<'a, T, (), P, Q, K, B> {
+    pub fn method(self, method: Method) -> ApiCallBuilder<'a, T, Method, P, Q, K, B> {
         ApiCallBuilder {
             api_type: self.api_type,
             method,
             api_path: self.api_path,
             query: self.query,
             api_key: self.api_key,
+            body: self.body,
+            retry: self.retry,
+            transport: self.transport,
         }
     }
 }
 
-impl<T, M, Q, K> ApiCallBuilder<T, M, (), Q, K> {
+impl<'a, T, M, Q, K, B> ApiCallBuilder<'a, T, M, (), Q, K, B> {
     /// # Panics
     /// Panics if `path` does not start with '/'
-    pub fn path(self, path: impl Into<String>) -> ApiCallBuilder<T, M, String, Q, K> {
+    pub fn path(self, path: impl Into<String>) -> ApiCallBuilder<'a, T, M, String, Q, K, B> {
         let path = path.into();
         assert!(path.starts_with('/'));
 
@@ -106,15 +255,18 @@ impl<T, M, Q, K> ApiCallBuilder<T, M, (), Q, K> {
             api_path: path,
             query: self.query,
             api_key: self.api_key,
+            body: self.body,
+            retry: self.retry,
+            transport: self.transport,
         }
     }
 }
 
-impl<T, M, P, K> ApiCallBuilder<T, M, P, (), K> {
+impl<'a, T, M, P, K, B> ApiCallBuilder<'a, T, M, P, (), K, B> {
     pub fn query<QK, QV>(
         self,
         query: impl IntoIterator<Item = (QK, QV)>,
-    ) -> ApiCallBuilder<T, M, P, QString, K>
+    ) -> ApiCallBuilder<'a, T, M, P, QString, K, B>
     where
         QK: Into<String>,
         QV: Into<String>,
@@ -126,110 +278,729 @@ impl<T, M, P, K> ApiCallBuilder<T, M, P, (), K> {
             api_path: self.api_path,
             query,
             api_key: self.api_key,
+            body: self.body,
+            retry: self.retry,
+            transport: self.transport,
         }
     }
 
-    pub fn query_empty(self) -> ApiCallBuilder<T, M, P, QString, K> {
+    pub fn query_empty(self) -> ApiCallBuilder<'a, T, M, P, QString, K, B> {
         ApiCallBuilder {
             api_type: self.api_type,
             method: self.method,
             api_path: self.api_path,
             query: QString::default(),
             api_key: self.api_key,
+            body: self.body,
+            retry: self.retry,
+            transport: self.transport,
         }
     }
 }
 
-impl<PrivateApi, M, P, Q> ApiCallBuilder<PrivateApi, M, P, Q, ()> {
-    pub fn api_key(self, api_key: ApiKey) -> ApiCallBuilder<PrivateApi, M, P, Q, ApiKey> {
+impl<'a, PrivateApi, M, P, Q, B> ApiCallBuilder<'a, PrivateApi, M, P, Q, (), B> {
+    pub fn api_key(self, api_key: ApiKey) -> ApiCallBuilder<'a, PrivateApi, M, P, Q, ApiKey, B> {
         ApiCallBuilder {
             api_type: self.api_type,
             method: self.method,
             api_path: self.api_path,
             query: self.query,
             api_key,
+            body: self.body,
+            retry: self.retry,
+            transport: self.transport,
+        }
+    }
+}
+
+impl<'a, T, M, P, Q, K> ApiCallBuilder<'a, T, M, P, Q, K, ()> {
+    /// Send `json` as the request body, e.g. of POST and DELETE calls
+    pub fn body(self, json: JsonValue) -> ApiCallBuilder<'a, T, M, P, Q, K, JsonValue> {
+        ApiCallBuilder {
+            api_type: self.api_type,
+            method: self.method,
+            api_path: self.api_path,
+            query: self.query,
+            api_key: self.api_key,
+            body: json,
+            retry: self.retry,
+            transport: self.transport,
+        }
+    }
+}
+
+/// Request body of an API call, either `()` for none or `JsonValue`
+pub trait RequestBody {
+    /// Serialized body sent and signed as it is
+    fn serialize(&self) -> Option<String>;
+}
+
+impl RequestBody for () {
+    fn serialize(&self) -> Option<String> {
+        None
+    }
+}
+
+impl RequestBody for JsonValue {
+    fn serialize(&self) -> Option<String> {
+        Some(self.dump())
+    }
+}
+
+/// API call ready to be sent. Private calls are already signed
+#[derive(Debug, Clone)]
+pub struct PreparedRequest {
+    pub method: Method,
+    /// Path like `/api/v2/time`
+    pub path: String,
+    pub query: QString,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Option<String>,
+    /// Signed calls must not be redirected, since the signature covers the path
+    pub signed: bool,
+}
+
+/// Response of a transport before it is interpreted as JSON
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawResponse {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub location: Option<String>,
+    pub body: String,
+}
+
+impl RawResponse {
+    /// `body` with status 200
+    pub fn json(body: impl Into<String>) -> Self {
+        Self {
+            status: 200,
+            content_type: Some("application/json".into()),
+            location: None,
+            body: body.into(),
+        }
+    }
+
+    fn parse(&self, api_path: &str) -> Result<JsonValue> {
+        parse_response(
+            api_path,
+            self.status,
+            self.content_type.as_deref(),
+            self.location.as_deref(),
+            &self.body,
+        )
+    }
+}
+
+/// Way to send API calls. Shared by threads fetching concurrently
+pub trait Transport: Debug + Sync {
+    fn execute(&self, prepared: PreparedRequest) -> Result<RawResponse>;
+}
+
+/// Transport sending calls to api2.nicehash.com
+#[derive(Debug, Clone, Default)]
+pub struct HttpTransport {
+    /// Shared clients are used if `None`
+    client: Option<Client>,
+}
+
+/// Transport of calls without injected one
+static DEFAULT_TRANSPORT: HttpTransport = HttpTransport { client: None };
+
+impl HttpTransport {
+    /// Transport of the clients shared by every call
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Transport sending every call by `client`, e.g. of a shorter timeout.
+    /// Redirects are followed as `client` is configured
+    pub fn with_client(client: Client) -> Self {
+        Self {
+            client: Some(client),
+        }
+    }
+}
+
+impl Transport for HttpTransport {
+    fn execute(&self, prepared: PreparedRequest) -> Result<RawResponse> {
+        let url = build_url(&prepared.path)?;
+        let client = match self.client.as_ref() {
+            Some(client) => client,
+            None => shared_client(&prepared.method, prepared.signed)?,
+        };
+
+        let mut req = client
+            .request(prepared.method, url)
+            .query(&prepared.query.to_pairs());
+        for (name, value) in prepared.headers {
+            req = req.header(name, value);
         }
+        let req = with_body(req, prepared.body).build()?;
+
+        send(client, req, &prepared.path)
     }
 }
 
-impl ApiCallBuilder<PublicApi, Method, String, QString, ()> {
+/// Transport answering canned responses keyed by path, so that fetchers are tested offline.
+/// `/api/v2/time` is answered with the local clock unless canned, so that private calls can be signed
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    /// Responses of each path answered in order, the last one repeatedly
+    responses: Mutex<HashMap<String, VecDeque<RawResponse>>>,
+    requests: Mutex<Vec<PreparedRequest>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer `body` with status 200 to calls of `path`
+    pub fn with_json(self, path: impl Into<String>, body: impl Into<String>) -> Self {
+        self.with_response(path, RawResponse::json(body))
+    }
+
+    pub fn with_response(self, path: impl Into<String>, response: RawResponse) -> Self {
+        self.with_responses(path, vec![response])
+    }
+
+    /// Answer `responses` to consecutive calls of `path`, like pages of a paged fetch.
+    /// Calls after the last one are answered with the last response
+    pub fn with_responses(self, path: impl Into<String>, responses: Vec<RawResponse>) -> Self {
+        self.responses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(path.into(), responses.into());
+        self
+    }
+
+    /// Calls executed so far, in order
+    pub fn requests(&self) -> Vec<PreparedRequest> {
+        self.requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+impl Transport for MockTransport {
+    fn execute(&self, prepared: PreparedRequest) -> Result<RawResponse> {
+        let response = match self
+            .responses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_mut(&prepared.path)
+            .and_then(|responses| match responses.len() {
+                0 => None,
+                1 => responses.front().cloned(),
+                _ => responses.pop_front(),
+            }) {
+            Some(response) => response,
+            None if prepared.path == "/api/v2/time" => {
+                RawResponse::json(format!(r#"{{"serverTime":{}}}"#, local_timestamp_millis()?))
+            }
+            None => return Err(anyhow!("No response of {} is canned", prepared.path)),
+        };
+
+        self.requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(prepared);
+        Ok(response)
+    }
+}
+
+/// Attach `body`, if any, as JSON
+fn with_body(req: RequestBuilder, body: Option<String>) -> RequestBuilder {
+    match body {
+        Some(body) => req.header(CONTENT_TYPE, "application/json").body(body),
+        None => req,
+    }
+}
+
+impl<'a, B: RequestBody> ApiCallBuilder<'a, PublicApi, Method, String, QString, (), B> {
     pub fn call(self) -> Result<JsonValue> {
-        let url = build_url(&self.api_path)?;
-        let client = reqwest::blocking::ClientBuilder::default().build()?;
+        call_with_retry(self.retry, &self.method, &self.api_path, false, || {
+            self.call_once()
+        })
+    }
 
-        let req = client
-            .request(self.method, url)
-            .query(&self.query.to_pairs())
-            .build()?;
+    fn call_once(&self) -> Result<JsonValue> {
+        let prepared = PreparedRequest {
+            method: self.method.clone(),
+            path: self.api_path.clone(),
+            query: self.query.clone(),
+            headers: vec![],
+            body: self.body.serialize(),
+            signed: false,
+        };
 
-        // Get reponse
-        client
-            .execute(req)?
-            .text()?
-            .as_str()
-            .apply_ref(json::parse)
-            .map_err(Into::into)
+        self.transport.execute(prepared)?.parse(&self.api_path)
     }
 }
 
-impl ApiCallBuilder<PrivateApi, Method, String, QString, ApiKey> {
+impl<'a, B: RequestBody> ApiCallBuilder<'a, PrivateApi, Method, String, QString, ApiKey, B> {
+    /// `X-Auth` header of this call. The body is signed exactly as it is sent
+    fn auth_header(&self, timestamp_millis: i64, nonce: &str) -> String {
+        build_auth_header(
+            &self.api_key,
+            &self.method,
+            &self.api_path,
+            &self.query.to_string(),
+            self.body.serialize().as_deref(),
+            timestamp_millis,
+            nonce,
+        )
+    }
+
     pub fn call(self) -> Result<JsonValue> {
-        let url = build_url(&self.api_path)?;
-        // Fetch timestamp
-        let server_timestamp_millis = fetch_server_time()?.timestamp_millis();
+        call_with_retry(self.retry, &self.method, &self.api_path, true, || {
+            self.call_signed()
+        })
+    }
+
+    /// `call_once`, signed again by the current server time if the server rejects the timestamp.
+    /// The rejected call is not executed, so it is signed again regardless of the method
+    fn call_signed(&self) -> Result<JsonValue> {
+        match self.call_once() {
+            Err(e) if is_timestamp_rejection(&e) => {
+                warn!(
+                    "{} rejected the timestamp: {}. Fetch server time again",
+                    self.api_path, e
+                );
+                reset_clock_offset()?;
+                self.call_once()
+            }
+            ret => ret,
+        }
+    }
+
+    fn call_once(&self) -> Result<JsonValue> {
+        let server_timestamp_millis = server_timestamp_millis(self.transport)?;
 
         // Onetime phrase
-        let nonce = uuid::Uuid::new_v4();
+        let nonce = uuid::Uuid::new_v4().to_string();
         let request_id = uuid::Uuid::new_v4();
 
-        //
-        let query = self.query.to_string();
-        let organization_id = &self.api_key.organization_id;
-        let api_key = &self.api_key.key;
-        let api_secret_key = &self.api_key.secret_key;
-
-        // Digital signing
-        let auth = {
-            let input = format!(
-                "{}\0{}\0{}\0\0{}\0\0{}\0{}\0{}",
-                api_key,
-                server_timestamp_millis,
-                nonce,
-                organization_id,
-                self.method.as_str(),
-                self.api_path,
-                query
-            );
-            let signature = hmac_sha256::HMAC::mac(input.as_bytes(), api_secret_key.as_bytes())
-                .iter()
-                .map(|b| format!("{:02x}", b))
-                .fold(String::new(), |acc, cur| acc + &cur);
-            format!("{}:{}", api_key, signature)
+        let auth = self.auth_header(server_timestamp_millis, &nonce);
+        let prepared = PreparedRequest {
+            method: self.method.clone(),
+            path: self.api_path.clone(),
+            query: self.query.clone(),
+            headers: vec![
+                ("X-Time", server_timestamp_millis.to_string()),
+                ("X-Nonce", nonce),
+                ("X-Organization-Id", self.api_key.organization_id.clone()),
+                ("X-Request-Id", request_id.to_string()),
+                ("X-Auth", auth),
+            ],
+            body: self.body.serialize(),
+            signed: true,
+        };
+
+        self.transport.execute(prepared)?.parse(&self.api_path)
+    }
+}
+
+/// Call `call` until it succeeds, retrying by `policy` only if `method` is GET.
+/// Clock offset of server is fetched again before retrying `signed` calls, so that the retry is signed with the current server time.
+/// # Returns
+/// The last error with the number of attempts if all retries failed
+fn call_with_retry<R>(
+    policy: Option<RetryPolicy>,
+    method: &Method,
+    api_path: &str,
+    signed: bool,
+    mut call: impl FnMut() -> Result<R>,
+) -> Result<R> {
+    let policy = match policy {
+        Some(policy) if *method == Method::GET && policy.max_attempts > 1 => policy,
+        _ => return call(),
+    };
+
+    let mut attempt = 1;
+    loop {
+        let e = match call() {
+            Ok(ret) => return Ok(ret),
+            Err(e) => e,
         };
+        if !is_retryable(&e) {
+            return Err(e);
+        }
+        if attempt >= policy.max_attempts {
+            return Err(e.context(format!("{} failed after {} attempts", api_path, attempt)));
+        }
+
+        let delay = policy.delay(attempt, jitter());
+        warn!(
+            "{} failed at attempt {}/{}: {}. Retry in {}ms",
+            api_path,
+            attempt,
+            policy.max_attempts,
+            e,
+            delay.as_millis()
+        );
+        std::thread::sleep(delay);
+        if signed {
+            reset_clock_offset()?;
+        }
+        attempt += 1;
+    }
+}
+
+/// Connection errors, timeouts, 429 and 5xx responses are regarded as transient
+fn is_retryable(e: &anyhow::Error) -> bool {
+    if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+        return e.is_connect() || e.is_timeout();
+    }
+
+    let status = match e.downcast_ref::<ApiError>() {
+        Some(ApiError::Timeout { .. }) => return true,
+        Some(ApiError::ServiceUnavailable { status, .. }) => *status,
+        Some(ApiError::Rejected { status, .. }) => *status,
+        _ => return false,
+    };
+    status == 429 || status >= 500
+}
+
+/// NiceHash answers 401 to `X-Time` out of its tolerance, with a message naming the header
+fn is_timestamp_rejection(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<ApiError>() {
+        Some(ApiError::Rejected { status, error, .. }) => {
+            *status == 401
+                || error.messages.iter().any(|message| {
+                    let message = message.to_ascii_lowercase();
+                    message.contains("x-time") || message.contains("timestamp")
+                })
+        }
+        _ => false,
+    }
+}
+
+/// Random ratio in [0, 1) from the local clock, enough to spread retries of concurrent processes
+fn jitter() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.subsec_nanos() as f64 / 1e9)
+        .unwrap_or_default()
+}
+
+/// Build `X-Auth` header value of a private API call.
+/// `body` is signed only if it exists, as NiceHash requires.
+pub fn build_auth_header(
+    api_key: &ApiKey,
+    method: &Method,
+    path: &str,
+    query: &str,
+    body: Option<&str>,
+    timestamp_millis: i64,
+    nonce: &str,
+) -> String {
+    let input = auth_input(api_key, method, path, query, body, timestamp_millis, nonce);
+    let signature = hmac_sha256::HMAC::mac(input.as_bytes(), api_key.secret_key.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .fold(String::new(), |acc, cur| acc + &cur);
+    format!("{}:{}", api_key.key, signature)
+}
+
+/// Input of the signature, `key\0time\0nonce\0\0organization\0\0METHOD\0path\0query[\0body]`
+fn auth_input(
+    api_key: &ApiKey,
+    method: &Method,
+    path: &str,
+    query: &str,
+    body: Option<&str>,
+    timestamp_millis: i64,
+    nonce: &str,
+) -> String {
+    let mut input = format!(
+        "{}\0{}\0{}\0\0{}\0\0{}\0{}\0{}",
+        api_key.key,
+        timestamp_millis,
+        nonce,
+        api_key.organization_id,
+        method.as_str(),
+        path,
+        query
+    );
+    if let Some(body) = body {
+        input.push('\0');
+        input.push_str(body);
+    }
+
+    input
+}
+
+/// Offset of server clock from local clock
+#[derive(Debug, Clone, Copy)]
+struct ClockOffset {
+    millis: i64,
+    fetched_at: Instant,
+}
+
+impl ClockOffset {
+    fn is_expired(&self, now: Instant) -> bool {
+        now.duration_since(self.fetched_at) >= CLOCK_OFFSET_TTL
+    }
+}
+
+/// Clock offset shared by private calls of the process, refreshed every `CLOCK_OFFSET_TTL`
+static CLOCK_OFFSET: Mutex<Option<ClockOffset>> = Mutex::new(None);
+
+/// Estimate server time from local clock.
+/// Server time is fetched by `transport` only when the offset is unknown or expired,
+/// so private calls don't need an extra request each.
+fn server_timestamp_millis(transport: &dyn Transport) -> Result<i64> {
+    let mut offset = CLOCK_OFFSET
+        .lock()
+        .map_err(|_| anyhow!("Clock offset is poisoned"))?;
+
+    let offset = match *offset {
+        Some(offset) if !offset.is_expired(Instant::now()) => offset.millis,
+        _ => {
+            let server_millis = fetch_server_time_with_transport(transport)?.timestamp_millis();
+            let fetched = server_millis - local_timestamp_millis()?;
+            debug!("Server clock offset: {}ms", fetched);
+            *offset = Some(ClockOffset {
+                millis: fetched,
+                fetched_at: Instant::now(),
+            });
+            fetched
+        }
+    };
+
+    Ok(local_timestamp_millis()? + offset)
+}
+
+/// Forget the clock offset, so that server time is fetched again by the next private call
+fn reset_clock_offset() -> Result<()> {
+    *CLOCK_OFFSET
+        .lock()
+        .map_err(|_| anyhow!("Clock offset is poisoned"))? = None;
+    Ok(())
+}
+
+fn local_timestamp_millis() -> Result<i64> {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    Ok(elapsed.as_millis() as i64)
+}
+
+/// Redirects are followed only by unsigned GET calls
+fn follows_redirect(method: &Method, signed: bool) -> bool {
+    *method == Method::GET && !signed
+}
+
+/// Clients shared by every call, so that connections are pooled instead of handshaking on each call
+struct SharedClients {
+    /// Follows redirects
+    redirecting: Client,
+    direct: Client,
+}
+
+static SHARED_CLIENTS: OnceCell<SharedClients> = OnceCell::new();
+
+/// Shared client for a call, built at the first call with the timeout of `NICEHASH_HTTP_TIMEOUT_SECS`
+fn shared_client(method: &Method, signed: bool) -> Result<&'static Client> {
+    let clients = SHARED_CLIENTS.get_or_try_init(|| -> Result<_> {
+        let timeout = http_timeout();
+        Ok(SharedClients {
+            redirecting: build_client(Policy::default(), timeout)?,
+            direct: build_client(Policy::none(), timeout)?,
+        })
+    })?;
 
-        //
-        let client = reqwest::blocking::ClientBuilder::default().build()?;
+    if follows_redirect(method, signed) {
+        Ok(&clients.redirecting)
+    } else {
+        Ok(&clients.direct)
+    }
+}
+
+/// Load `NICEHASH_HTTP_TIMEOUT_SECS` (default 10)
+fn http_timeout() -> Duration {
+    env::var("NICEHASH_HTTP_TIMEOUT_SECS")
+        .ok()
+        .and_then(|s| u64::from_str(&s).ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(DEFAULT_HTTP_TIMEOUT_SECS)
+        .apply(Duration::from_secs)
+}
 
-        let req = client
-            .request(self.method, url)
-            .header("X-Time", server_timestamp_millis)
-            .header("X-Nonce", nonce.to_string())
-            .header("X-Organization-Id", organization_id)
-            .header("X-Request-Id", request_id.to_string())
-            .header("X-Auth", auth)
-            .query(&self.query.to_pairs())
-            .build()?;
+/// `timeout` limits both connecting and the whole call including reading the body
+fn build_client(policy: Policy, timeout: Duration) -> Result<Client> {
+    reqwest::blocking::ClientBuilder::default()
+        .redirect(policy)
+        .connect_timeout(timeout)
+        .timeout(timeout)
+        .build()
+        .map_err(Into::into)
+}
 
-        // Get reponse
-        client
-            .execute(req)?
-            .text()?
-            .as_str()
-            .apply_ref(json::parse)
-            .map_err(Into::into)
+/// Send `req` and read its response.
+/// # Returns
+/// `Err(ApiError::Timeout)` if the client timed out
+fn send(client: &Client, req: Request, api_path: &str) -> Result<RawResponse> {
+    client
+        .execute(req)
+        .map_err(Into::into)
+        .and_then(|response| read_response(response, api_path))
+        .map_err(|e| {
+            if is_timeout(&e) {
+                ApiError::Timeout {
+                    path: api_path.to_string(),
+                }
+                .into()
+            } else {
+                e
+            }
+        })
+}
+
+/// Timeouts while reading the body are wrapped in `std::io::Error`
+fn is_timeout(e: &anyhow::Error) -> bool {
+    if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+        return e.is_timeout();
+    }
+    match e.downcast_ref::<std::io::Error>() {
+        Some(e) if e.kind() == std::io::ErrorKind::TimedOut => true,
+        Some(e) => e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<reqwest::Error>())
+            .map_or(false, reqwest::Error::is_timeout),
+        None => false,
     }
 }
 
+fn read_response(response: Response, api_path: &str) -> Result<RawResponse> {
+    let status = response.status().as_u16();
+    let header = |name: HeaderName| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let content_type = header(CONTENT_TYPE);
+    let location = header(LOCATION);
+    let content_length = response.content_length();
+    let body = read_body(response, content_length, max_response_bytes(), api_path)?;
+
+    Ok(RawResponse {
+        status,
+        content_type,
+        location,
+        body,
+    })
+}
+
+/// Load `NICEHASH_MAX_RESPONSE_BYTES` (default 10 MiB)
+fn max_response_bytes() -> u64 {
+    env::var("NICEHASH_MAX_RESPONSE_BYTES")
+        .ok()
+        .and_then(|s| u64::from_str(&s).ok())
+        .unwrap_or(DEFAULT_MAX_RESPONSE_BYTES)
+}
+
+/// Read body up to `limit` bytes.
+/// Body is rejected without reading if `content_length` already exceeds `limit`.
+fn read_body(
+    reader: impl Read,
+    content_length: Option<u64>,
+    limit: u64,
+    api_path: &str,
+) -> Result<String> {
+    let too_large = || ApiError::ResponseTooLarge {
+        path: api_path.to_string(),
+        limit,
+    };
+
+    if matches!(content_length, Some(len) if len > limit) {
+        return Err(too_large().into());
+    }
+
+    // Read one more byte to detect bodies without content length exceeding the limit
+    let mut body = vec![];
+    reader.take(limit + 1).read_to_end(&mut body)?;
+    if body.len() as u64 > limit {
+        return Err(too_large().into());
+    }
+
+    String::from_utf8(body).map_err(Into::into)
+}
+
+/// Interpret HTTP response as JSON.
+/// # Returns
+/// `Err(ApiError::Redirected)` if the response is an unfollowed redirect
+///
+/// `Err(ApiError::ServiceUnavailable)` if the response is HTML instead of JSON
+///
+/// `Err(ApiError::Rejected)` if the response is an error JSON or has an error status
+fn parse_response(
+    api_path: &str,
+    status: u16,
+    content_type: Option<&str>,
+    location: Option<&str>,
+    body: &str,
+) -> Result<JsonValue> {
+    if (300..400).contains(&status) {
+        return Err(ApiError::Redirected {
+            status,
+            path: api_path.to_string(),
+            location: location.map(str::to_string),
+        }
+        .into());
+    }
+
+    let is_html_content = content_type
+        .map(|t| t.to_ascii_lowercase().contains("text/html"))
+        .unwrap_or(false);
+    let is_html_body = body.trim_start().starts_with('<');
+    if is_html_content || is_html_body {
+        return Err(ApiError::ServiceUnavailable {
+            status,
+            snippet: snippet(body),
+        }
+        .into());
+    }
+
+    let json = json::parse(body)?;
+    if let Some(e) = rejection(api_path, status, &json, body) {
+        return Err(e.into());
+    }
+
+    Ok(json)
+}
+
+/// Error of a response having an error payload or an error status
+fn rejection(api_path: &str, status: u16, json: &JsonValue, body: &str) -> Option<ApiError> {
+    let error = match NicehashApiError::from_json(json) {
+        Some(error) => error,
+        None if status >= 400 => NicehashApiError {
+            error_id: None,
+            code: None,
+            messages: vec![snippet(body)],
+        },
+        None => return None,
+    };
+
+    Some(ApiError::Rejected {
+        status,
+        path: api_path.to_string(),
+        error,
+    })
+}
+
+/// Leading part of `body` with whitespaces collapsed
+fn snippet(body: &str) -> String {
+    body.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(SNIPPET_LEN)
+        .collect()
+}
+
 fn build_url(api_path: &str) -> Result<Url> {
     Url::parse("https://api2.nicehash.com")?
         .join(api_path)
@@ -237,7 +1008,12 @@ fn build_url(api_path: &str) -> Result<Url> {
 }
 
 pub fn fetch_server_time() -> Result<NaiveDateTime> {
+    fetch_server_time_with_transport(&HttpTransport::new())
+}
+
+pub fn fetch_server_time_with_transport(transport: &dyn Transport) -> Result<NaiveDateTime> {
     let json = ApiCallBuilder::new()
+        .transport(transport)
         .public_api()
         .method(Method::GET)
         .path("/api/v2/time")
@@ -256,10 +1032,588 @@ pub fn fetch_server_time() -> Result<NaiveDateTime> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Instant;
 
+    /// Requires access to api2.nicehash.com
     #[test]
+    #[ignore]
     fn test_fetch_server_time() {
         let time = fetch_server_time().unwrap();
         assert!(time.timestamp() > 0);
     }
+
+    #[test]
+    fn test_fetch_server_time_with_transport() {
+        let transport =
+            MockTransport::new().with_json("/api/v2/time", r#"{"serverTime":1600000000123}"#);
+
+        let time = fetch_server_time_with_transport(&transport).unwrap();
+        assert_eq!(1600000000123, time.timestamp_millis());
+
+        let requests = transport.requests();
+        assert_eq!(1, requests.len());
+        assert_eq!(Method::GET, requests[0].method);
+        assert!(!requests[0].signed);
+    }
+
+    #[test]
+    fn test_call_with_transport_parses_response() {
+        let body =
+            r#"{"error_id":"8a1b","errors":[{"code":5054,"message":"Insufficient balance"}]}"#;
+        let transport = MockTransport::new().with_response(
+            "/exchange/api/v2/order",
+            RawResponse {
+                status: 400,
+                ..RawResponse::json(body)
+            },
+        );
+
+        let e = ApiCallBuilder::new()
+            .transport(&transport)
+            .private_api()
+            .method(Method::POST)
+            .path("/exchange/api/v2/order")
+            .query(vec![("market", "BTCUSDT")])
+            .api_key(ApiKey::new("org".into(), "key".into(), "secret".into()))
+            .call()
+            .unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<ApiError>(),
+            Some(ApiError::Rejected { status: 400, .. })
+        ));
+
+        // The call is signed by the server time, which is answered by the transport unless canned
+        let order = transport
+            .requests()
+            .into_iter()
+            .find(|request| request.path == "/exchange/api/v2/order")
+            .unwrap();
+        assert!(order.signed);
+        let header_names = order
+            .headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "X-Time",
+                "X-Nonce",
+                "X-Organization-Id",
+                "X-Request-Id",
+                "X-Auth"
+            ],
+            header_names
+        );
+    }
+
+    #[test]
+    fn test_call_signed_again_on_timestamp_rejection() {
+        let body = r#"{"error_id":"9c2d","errors":[{"code":2000,"message":"Invalid X-Time"}]}"#;
+        let transport = MockTransport::new().with_response(
+            "/main/api/v2/accounting/accounts2",
+            RawResponse {
+                status: 401,
+                ..RawResponse::json(body)
+            },
+        );
+
+        let e = ApiCallBuilder::new()
+            .transport(&transport)
+            .private_api()
+            .method(Method::POST)
+            .path("/main/api/v2/accounting/accounts2")
+            .query_empty()
+            .api_key(ApiKey::new("org".into(), "key".into(), "secret".into()))
+            .call()
+            .unwrap_err();
+        assert!(is_timestamp_rejection(&e));
+
+        // Signed once more, not retried endlessly
+        let calls = transport
+            .requests()
+            .into_iter()
+            .filter(|request| request.path == "/main/api/v2/accounting/accounts2")
+            .count();
+        assert_eq!(2, calls);
+    }
+
+    #[test]
+    fn test_is_timestamp_rejection() {
+        let rejected = |status, message: &str| -> anyhow::Error {
+            ApiError::Rejected {
+                status,
+                path: "/exchange/api/v2/order".into(),
+                error: NicehashApiError {
+                    error_id: None,
+                    code: None,
+                    messages: vec![message.into()],
+                },
+            }
+            .into()
+        };
+
+        assert!(is_timestamp_rejection(&rejected(401, "Unauthorized")));
+        assert!(is_timestamp_rejection(&rejected(
+            400,
+            "Invalid X-Time header"
+        )));
+        assert!(!is_timestamp_rejection(&rejected(
+            400,
+            "Insufficient balance"
+        )));
+        assert!(!is_timestamp_rejection(&anyhow!("Invalid X-Time header")));
+    }
+
+    #[test]
+    fn test_clock_offset_expiry() {
+        let fetched_at = Instant::now();
+        let offset = ClockOffset {
+            millis: 0,
+            fetched_at,
+        };
+
+        assert!(!offset.is_expired(fetched_at));
+        assert!(!offset.is_expired(fetched_at + CLOCK_OFFSET_TTL / 2));
+        assert!(offset.is_expired(fetched_at + CLOCK_OFFSET_TTL));
+    }
+
+    #[test]
+    fn test_mock_transport_without_response() {
+        let transport = MockTransport::new();
+
+        let e = ApiCallBuilder::new()
+            .transport(&transport)
+            .public_api()
+            .method(Method::GET)
+            .path("/main/api/v2/public/currencies")
+            .query_empty()
+            .call()
+            .unwrap_err();
+        assert_eq!(
+            "No response of /main/api/v2/public/currencies is canned",
+            e.to_string()
+        );
+    }
+
+    const MAINTENANCE_HTML: &str = "
+        <!DOCTYPE html>
+        <html>
+            <head><title>NiceHash - Maintenance</title></head>
+            <body>We are currently performing scheduled maintenance.</body>
+        </html>";
+
+    #[test]
+    fn test_parse_response_json() {
+        let json = parse_response(
+            "/api/v2/time",
+            200,
+            Some("application/json"),
+            None,
+            r#"{"serverTime":1600000000000}"#,
+        )
+        .unwrap();
+
+        assert_eq!(Some(1600000000000), json["serverTime"].as_u64());
+    }
+
+    #[test]
+    fn test_parse_response_maintenance_html() {
+        let e = parse_response(
+            "/api/v2/time",
+            200,
+            Some("text/html; charset=UTF-8"),
+            None,
+            MAINTENANCE_HTML,
+        )
+        .unwrap_err();
+
+        assert!(is_service_unavailable(&e));
+        match e.downcast_ref::<ApiError>() {
+            Some(ApiError::ServiceUnavailable { status, snippet }) => {
+                assert_eq!(200, *status);
+                assert!(snippet.starts_with("<!DOCTYPE html> <html>"));
+                assert!(snippet.len() <= SNIPPET_LEN);
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_parse_response_html_without_content_type() {
+        let e = parse_response("/api/v2/time", 200, None, None, MAINTENANCE_HTML).unwrap_err();
+
+        assert!(is_service_unavailable(&e));
+    }
+
+    #[test]
+    fn test_parse_response_invalid_json_is_not_maintenance() {
+        let e =
+            parse_response("/api/v2/time", 200, Some("application/json"), None, "{").unwrap_err();
+
+        assert!(!is_service_unavailable(&e));
+    }
+
+    #[test]
+    fn test_parse_response_redirect() {
+        let e = parse_response(
+            "/main/api/v2/accounting/accounts2",
+            302,
+            Some("text/html"),
+            Some("https://www.nicehash.com/maintenance"),
+            MAINTENANCE_HTML,
+        )
+        .unwrap_err();
+
+        assert!(!is_service_unavailable(&e));
+        match e.downcast_ref::<ApiError>() {
+            Some(ApiError::Redirected {
+                status,
+                path,
+                location,
+            }) => {
+                assert_eq!(302, *status);
+                assert_eq!("/main/api/v2/accounting/accounts2", path);
+                assert_eq!(
+                    Some("https://www.nicehash.com/maintenance"),
+                    location.as_deref()
+                );
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_parse_response_error_json() {
+        let body =
+            r#"{"error_id":"8a1b","errors":[{"code":5054,"message":"Insufficient balance"}]}"#;
+        let e = parse_response(
+            "/exchange/api/v2/order",
+            400,
+            Some("application/json"),
+            None,
+            body,
+        )
+        .unwrap_err();
+
+        assert!(!is_service_unavailable(&e));
+        match e.downcast_ref::<ApiError>() {
+            Some(ApiError::Rejected {
+                status,
+                path,
+                error,
+            }) => {
+                assert_eq!(400, *status);
+                assert_eq!("/exchange/api/v2/order", path);
+                assert_eq!(
+                    &NicehashApiError {
+                        error_id: Some("8a1b".into()),
+                        code: Some(5054),
+                        messages: vec!["Insufficient balance".into()],
+                    },
+                    error
+                );
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+
+        // Error JSON is rejected even with a success status
+        let e = parse_response("/exchange/api/v2/order", 200, None, None, body).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<ApiError>(),
+            Some(ApiError::Rejected { status: 200, .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_response_error_status() {
+        let e = parse_response(
+            "/exchange/api/v2/order",
+            500,
+            Some("application/json"),
+            None,
+            r#"{"message":"Internal error"}"#,
+        )
+        .unwrap_err();
+
+        match e.downcast_ref::<ApiError>() {
+            Some(ApiError::Rejected { error, .. }) => {
+                assert_eq!(None, error.code);
+                assert_eq!(vec![r#"{"message":"Internal error"}"#], error.messages);
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+
+        // An empty error list is not an error
+        assert!(parse_response("/api/v2/time", 200, None, None, r#"{"errors":[]}"#).is_ok());
+    }
+
+    #[test]
+    fn test_read_body() {
+        let body = r#"{"serverTime":1600000000000}"#;
+
+        let read = read_body(
+            body.as_bytes(),
+            Some(body.len() as u64),
+            100,
+            "/api/v2/time",
+        );
+        assert_eq!(body, read.unwrap());
+
+        // Exactly at the limit
+        let read = read_body(body.as_bytes(), None, body.len() as u64, "/api/v2/time");
+        assert_eq!(body, read.unwrap());
+    }
+
+    #[test]
+    fn test_read_body_too_large() {
+        let body = "x".repeat(101);
+
+        // Rejected by content length
+        let e = read_body(body.as_bytes(), Some(101), 100, "/api/v2/time").unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<ApiError>(),
+            Some(ApiError::ResponseTooLarge { limit: 100, .. })
+        ));
+
+        // Rejected while reading, since content length is unknown
+        let e = read_body(body.as_bytes(), None, 100, "/api/v2/time").unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<ApiError>(),
+            Some(ApiError::ResponseTooLarge { limit: 100, .. })
+        ));
+    }
+
+    /// (method, path, query, body, expected X-Auth).
+    /// The first is the example of NiceHash API documentation
+    const AUTH_VECTORS: &[(&str, &str, &str, Option<&str>, &str)] = &[
+        (
+            "GET",
+            "/main/api/v2/hashpower/orderBook",
+            "algorithm=X16R&page=0&size=100",
+            None,
+            "4ebd366d-76f4-4400-a3b6-e51515d054d6:21e6a16f6eb34ac476d59f969f548b47fffe3fea318d9c99e77fc710d2fed798",
+        ),
+        (
+            "GET",
+            "/main/api/v2/accounting/accounts2",
+            "",
+            None,
+            "4ebd366d-76f4-4400-a3b6-e51515d054d6:5ed73911dbeae6af06995fcbc010f296d78724f957a7cbd7fcaceb6321daa657",
+        ),
+        (
+            "POST",
+            "/exchange/api/v2/order",
+            "market=BTCUSDT&side=BUY&type=LIMIT&quantity=0.001&price=30000",
+            None,
+            "4ebd366d-76f4-4400-a3b6-e51515d054d6:c2c85d97a3be3e22d5dc7160523c3fe733199dfc29738bed52fcb7f8eea3c15b",
+        ),
+        (
+            "POST",
+            "/main/api/v2/hashpower/order",
+            "",
+            Some(r#"{"market":"EU"}"#),
+            "4ebd366d-76f4-4400-a3b6-e51515d054d6:e87e215d8eed5b317d46d80ee6c38c3705fcac36792ac231ac4177be91b848c6",
+        ),
+    ];
+
+    #[test]
+    fn test_build_auth_header() {
+        let api_key = ApiKey::new(
+            "da41b3bc-3d0b-4226-b7ea-aee73f94a518".into(),
+            "4ebd366d-76f4-4400-a3b6-e51515d054d6".into(),
+            "fd8a1652-728b-42fe-82b8-f623e56da8850750f5bf-ce66-4ca7-8b84-93651abc723b".into(),
+        );
+
+        for &(method, path, query, body, expected) in AUTH_VECTORS.iter() {
+            let method = Method::from_str(method).unwrap();
+            let auth = build_auth_header(
+                &api_key,
+                &method,
+                path,
+                query,
+                body,
+                1543597115712,
+                "9675d0f8-1325-484b-9594-c9d6d3268890",
+            );
+            assert_eq!(expected, auth, "{} {}", method, path);
+        }
+    }
+
+    #[test]
+    fn test_auth_input_with_body() {
+        let api_key = ApiKey::new("org".into(), "key".into(), "secret".into());
+
+        let input = auth_input(
+            &api_key,
+            &Method::POST,
+            "/exchange/api/v2/order",
+            "market=BTCUSDT",
+            Some(r#"{"side":"BUY"}"#),
+            1543597115712,
+            "nonce",
+        );
+        assert_eq!(
+            "key\01543597115712\0nonce\0\0org\0\0POST\0/exchange/api/v2/order\0market=BTCUSDT\0{\"side\":\"BUY\"}",
+            input
+        );
+
+        // Without body, the input ends with the query
+        let input = auth_input(
+            &api_key,
+            &Method::DELETE,
+            "/exchange/api/v2/order",
+            "",
+            None,
+            1543597115712,
+            "nonce",
+        );
+        assert_eq!(
+            "key\01543597115712\0nonce\0\0org\0\0DELETE\0/exchange/api/v2/order\0",
+            input
+        );
+    }
+
+    #[test]
+    fn test_auth_header_of_post_with_body() {
+        let api_key = ApiKey::new(
+            "da41b3bc-3d0b-4226-b7ea-aee73f94a518".into(),
+            "4ebd366d-76f4-4400-a3b6-e51515d054d6".into(),
+            "fd8a1652-728b-42fe-82b8-f623e56da8850750f5bf-ce66-4ca7-8b84-93651abc723b".into(),
+        );
+        let mut body = JsonValue::new_object();
+        body["market"] = "EU".into();
+
+        let call = ApiCallBuilder::new()
+            .private_api()
+            .method(Method::POST)
+            .path("/main/api/v2/hashpower/order")
+            .query_empty()
+            .body(body)
+            .api_key(api_key);
+
+        // Same as the vector of `test_build_auth_header`
+        assert_eq!(
+            AUTH_VECTORS[3].4,
+            call.auth_header(1543597115712, "9675d0f8-1325-484b-9594-c9d6d3268890")
+        );
+    }
+
+    fn rejected(status: u16) -> anyhow::Error {
+        ApiError::Rejected {
+            status,
+            path: "/api/v2/time".into(),
+            error: NicehashApiError {
+                error_id: None,
+                code: None,
+                messages: vec![],
+            },
+        }
+        .into()
+    }
+
+    const NO_DELAY: RetryPolicy = RetryPolicy {
+        max_attempts: 3,
+        base_delay: Duration::from_millis(0),
+    };
+
+    #[test]
+    fn test_retry_policy_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+        };
+
+        assert_eq!(Duration::from_millis(100), policy.delay(1, 0.0));
+        assert_eq!(Duration::from_millis(200), policy.delay(2, 0.0));
+        assert_eq!(Duration::from_millis(400), policy.delay(3, 0.0));
+        assert_eq!(Duration::from_millis(600), policy.delay(3, 0.5));
+    }
+
+    #[test]
+    fn test_call_with_retry_recovers() {
+        let mut attempts = 0;
+        let ret = call_with_retry(Some(NO_DELAY), &Method::GET, "/api/v2/time", false, || {
+            attempts += 1;
+            match attempts {
+                1 => Err(rejected(429)),
+                2 => Err(rejected(503)),
+                _ => Ok(attempts),
+            }
+        });
+
+        assert_eq!(3, ret.unwrap());
+    }
+
+    #[test]
+    fn test_call_with_retry_exhausted() {
+        let mut attempts = 0;
+        let e = call_with_retry::<()>(Some(NO_DELAY), &Method::GET, "/api/v2/time", false, || {
+            attempts += 1;
+            Err(rejected(502))
+        })
+        .unwrap_err();
+
+        assert_eq!(3, attempts);
+        assert_eq!("/api/v2/time failed after 3 attempts", e.to_string());
+        // The cause is still available
+        assert!(matches!(
+            e.downcast_ref::<ApiError>(),
+            Some(ApiError::Rejected { status: 502, .. })
+        ));
+    }
+
+    #[test]
+    fn test_call_with_retry_not_retried() {
+        let count_attempts = |policy, method, status| {
+            let mut attempts = 0;
+            let _ = call_with_retry::<()>(policy, &method, "/exchange/api/v2/order", false, || {
+                attempts += 1;
+                Err(rejected(status))
+            });
+            attempts
+        };
+
+        // Client errors
+        assert_eq!(1, count_attempts(Some(NO_DELAY), Method::GET, 400));
+        // Non idempotent calls
+        assert_eq!(1, count_attempts(Some(NO_DELAY), Method::POST, 503));
+        // No retry by default
+        assert_eq!(1, count_attempts(None, Method::GET, 503));
+    }
+
+    #[test]
+    fn test_send_timeout() {
+        // Connections are queued by the backlog, but never responded
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/v2/time", listener.local_addr().unwrap());
+        let client = build_client(Policy::none(), Duration::from_millis(200)).unwrap();
+        let req = client.get(&url).build().unwrap();
+
+        let start = Instant::now();
+        let e = send(&client, req, "/api/v2/time").unwrap_err();
+
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(matches!(
+            e.downcast_ref::<ApiError>(),
+            Some(ApiError::Timeout { .. })
+        ));
+        assert!(is_retryable(&e));
+    }
+
+    #[test]
+    fn test_is_timeout() {
+        let e = std::io::Error::from(std::io::ErrorKind::TimedOut);
+        assert!(is_timeout(&e.into()));
+
+        let e = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        assert!(!is_timeout(&e.into()));
+        assert!(!is_timeout(&rejected(503)));
+    }
+
+    #[test]
+    fn test_follows_redirect() {
+        assert!(follows_redirect(&Method::GET, false));
+        assert!(!follows_redirect(&Method::GET, true));
+        assert!(!follows_redirect(&Method::POST, false));
+    }
 }
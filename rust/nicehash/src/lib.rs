@@ -1,11 +1,23 @@
 pub mod api_common;
 
-use anyhow::Result;
+use anyhow::{anyhow, ensure, Result};
 use api_common::*;
 use apply::Apply;
 use database::model::*;
+use database::order::ExchangeOrder;
 use json::JsonValue;
+use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+#[macro_use]
+extern crate log;
+
+/// Retry of the idempotent GET fetchers below on transient failures, like 5xx during scraping
+pub const FETCH_RETRY_POLICY: RetryPolicy = RetryPolicy {
+    max_attempts: 3,
+    base_delay: std::time::Duration::from_millis(500),
+};
 
 #[derive(Debug, Clone)]
 pub struct IncompleteCurrency {
@@ -34,45 +46,104 @@ pub struct IncompleteOrderbook {
     pub volume: Amount,
 }
 
-#[derive(Debug, Clone)]
-pub struct IncompleteMyorder {
-    pub transaction_id: String,
-    pub price: Amount,
-    pub base_quantity: Amount,
-    pub quote_quantity: Amount,
-    pub order_type: OrderType,
-    pub side: OrderSide,
-    pub state: OrderState,
+/// Number of entries in a response. Malformed entries are skipped and counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FetchReport {
+    pub total: usize,
+    pub skipped: usize,
+}
+
+impl FetchReport {
+    /// Ratio of skipped entries. 0 if the response has no entries
+    pub fn skipped_ratio(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.skipped as f64 / self.total as f64
+        }
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Self {
+            total: self.total + other.total,
+            skipped: self.skipped + other.skipped,
+        }
+    }
 }
 
-pub fn fetch_all_currencies() -> Result<Vec<IncompleteCurrency>> {
+/// Parse each of `entries` by `parse`, skipping and counting entries which can't be parsed
+fn parse_entries<E, T>(
+    entries: impl IntoIterator<Item = E>,
+    mut parse: impl FnMut(E) -> Option<T>,
+) -> (Vec<T>, FetchReport) {
+    let mut report = FetchReport::default();
+    let items = entries
+        .into_iter()
+        .filter_map(|entry| {
+            report.total += 1;
+            let item = parse(entry);
+            if item.is_none() {
+                report.skipped += 1;
+            }
+            item
+        })
+        .collect::<Vec<_>>();
+
+    (items, report)
+}
+
+/// `json[key]`, or `Err` if it is not an array, e.g. because the response is not what is expected
+fn required_array<'a>(json: &'a JsonValue, key: &str) -> Result<&'a JsonValue> {
+    let value = &json[key];
+    ensure!(value.is_array(), "Response has no `{}` array", key);
+    Ok(value)
+}
+
+pub fn fetch_all_currencies() -> Result<(Vec<IncompleteCurrency>, FetchReport)> {
+    fetch_all_currencies_with_transport(&HttpTransport::new())
+}
+
+/// `fetch_all_currencies` sending the call by `transport`
+pub fn fetch_all_currencies_with_transport(
+    transport: &dyn Transport,
+) -> Result<(Vec<IncompleteCurrency>, FetchReport)> {
     let json = ApiCallBuilder::new()
+        .transport(transport)
+        .retry(FETCH_RETRY_POLICY)
         .public_api()
         .method(Method::GET)
         .path("/main/api/v2/public/currencies")
         .query_empty()
         .call()?;
 
-    json["currencies"]
-        .members()
-        .filter_map(|json| {
-            let symbol = json["symbol"].as_str();
-            let name = json["name"].as_str();
-            match (symbol, name) {
-                (Some(symbol), Some(name)) => IncompleteCurrency {
-                    symbol: symbol.to_string(),
-                    name: name.to_string(),
-                }
-                .apply(Some),
-                _ => None,
+    parse_entries(required_array(&json, "currencies")?.members(), |json| {
+        let symbol = json["symbol"].as_str();
+        let name = json["name"].as_str();
+        match (symbol, name) {
+            (Some(symbol), Some(name)) => IncompleteCurrency {
+                symbol: symbol.to_string(),
+                name: name.to_string(),
             }
-        })
-        .collect::<Vec<_>>()
-        .apply(Ok)
+            .apply(Some),
+            _ => None,
+        }
+    })
+    .apply(Ok)
 }
 
-pub fn fetch_all_balances(api_key: ApiKey) -> Result<Vec<IncompleteBalance>> {
+/// Balances of active currencies
+pub fn fetch_all_balances(api_key: ApiKey) -> Result<(Vec<IncompleteBalance>, FetchReport)> {
+    fetch_all_balances_with_transport(api_key, &HttpTransport::new())
+}
+
+/// `fetch_all_balances` sending the call by `transport`
+pub fn fetch_all_balances_with_transport(
+    api_key: ApiKey,
+    transport: &dyn Transport,
+) -> Result<(Vec<IncompleteBalance>, FetchReport)> {
     let json = ApiCallBuilder::new()
+        .transport(transport)
+        .retry(FETCH_RETRY_POLICY)
         .private_api()
         .method(Method::GET)
         .path("/main/api/v2/accounting/accounts2")
@@ -80,80 +151,149 @@ pub fn fetch_all_balances(api_key: ApiKey) -> Result<Vec<IncompleteBalance>> {
         .api_key(api_key)
         .call()?;
 
-    json["currencies"]
+    let active_balances = required_array(&json, "currencies")?
         .members()
-        .filter(|j| j["active"].as_bool() == Some(true))
-        .filter_map(|balance_json| {
-            let symbol = balance_json["currency"].as_str()?.to_string();
-            let available = balance_json["available"]
-                .as_str()
-                .and_then(|s| Amount::from_str(s).ok())?;
-            let pending = balance_json["pending"]
-                .as_str()
-                .and_then(|s| Amount::from_str(s).ok())?;
-            let balance = IncompleteBalance {
-                symbol,
-                available,
-                pending,
-            };
-
-            Some(balance)
-        })
-        .collect::<Vec<_>>()
-        .apply(Ok)
+        .filter(|j| j["active"].as_bool() == Some(true));
+    parse_entries(active_balances, |balance_json| {
+        let symbol = balance_json["currency"].as_str()?.to_string();
+        let available = balance_json["available"]
+            .as_str()
+            .and_then(|s| Amount::from_str(s).ok())?;
+        let pending = balance_json["pending"]
+            .as_str()
+            .and_then(|s| Amount::from_str(s).ok())?;
+        let balance = IncompleteBalance {
+            symbol,
+            available,
+            pending,
+        };
+
+        Some(balance)
+    })
+    .apply(Ok)
 }
 
+/// Prices of markets whose base and quote are in `known_symbols`.
+/// # Returns
+/// Prices, and markets which can't be split into known symbols. Those markets are not counted in the report
 pub fn fetch_all_market_prices<S: AsRef<str>>(
     known_symbols: &[S],
-) -> Result<Vec<IncompleteMarketPrice>> {
+) -> Result<(Vec<IncompleteMarketPrice>, Vec<String>, FetchReport)> {
+    fetch_all_market_prices_with_transport(known_symbols, &HttpTransport::new())
+}
+
+/// `fetch_all_market_prices` sending the call by `transport`
+pub fn fetch_all_market_prices_with_transport<S: AsRef<str>>(
+    known_symbols: &[S],
+    transport: &dyn Transport,
+) -> Result<(Vec<IncompleteMarketPrice>, Vec<String>, FetchReport)> {
     let json = ApiCallBuilder::new()
+        .transport(transport)
+        .retry(FETCH_RETRY_POLICY)
         .public_api()
         .method(Method::GET)
         .path("/exchange/api/v2/info/prices")
         .query_empty()
         .call()?;
+    ensure!(json.is_object(), "Response of prices is not an object");
 
-    json.entries()
-        .filter_map(|(market, json_price)| {
-            let base = known_symbols
-                .iter()
-                .find(|symbol| market.starts_with(symbol.as_ref()))?
-                .as_ref();
+    let mut unknown_markets = vec![];
+    let known_markets = json
+        .entries()
+        .filter_map(
+            |(market, json_price)| match split_market_symbol(market, known_symbols) {
+                Some((base, quote)) => Some((base, quote, json_price)),
+                None => {
+                    unknown_markets.push(market.to_string());
+                    None
+                }
+            },
+        )
+        .collect::<Vec<_>>();
+    let (market_prices, report) = parse_entries(known_markets, |(base, quote, json_price)| {
+        let price = json_price.as_f64()?;
 
-            let remaining_market = &market[base.len()..];
-            let quote = known_symbols
-                .iter()
-                .find(|symbol| remaining_market.starts_with(symbol.as_ref()))?
-                .as_ref();
+        let market_price = IncompleteMarketPrice {
+            base_symbol: base.to_string(),
+            quote_symbol: quote.to_string(),
+            price,
+        };
+        Some(market_price)
+    });
+
+    Ok((market_prices, unknown_markets, report))
+}
 
-            let price = json_price.as_f32()?;
+/// Split a market symbol like `BTCUSDT` into base and quote symbols, which must consume the whole market symbol.
+/// If several splits are possible, the longest base is preferred, then the longest quote.
+/// `known_symbols` should contain only listed currencies, because a delisted symbol can make another split possible.
+fn split_market_symbol<'a, S: AsRef<str>>(
+    market: &str,
+    known_symbols: &'a [S],
+) -> Option<(&'a str, &'a str)> {
+    let symbols = || {
+        known_symbols
+            .iter()
+            .map(|symbol| symbol.as_ref())
+            .filter(|symbol| !symbol.is_empty())
+    };
 
-            let market_price = IncompleteMarketPrice {
-                base_symbol: base.to_string(),
-                quote_symbol: quote.to_string(),
-                price,
-            };
-            Some(market_price)
+    symbols()
+        .filter(|base| market.starts_with(base))
+        .flat_map(|base| {
+            symbols()
+                .filter(move |quote| {
+                    base.len() + quote.len() == market.len() && market.ends_with(quote)
+                })
+                .map(move |quote| (base, quote))
         })
-        .collect::<Vec<_>>()
-        .apply(Ok)
+        .max_by_key(|(base, quote)| (base.len(), quote.len()))
 }
 
+/// Default of maximum orderbook levels per side
+pub const DEFAULT_MAX_ORDERBOOK_LEVELS: usize = 500;
+
+/// Fetch orderbooks of the market.
+/// Server may return more levels than `fetch_count`, so each side is truncated to the best-priced `max_levels_per_side` levels.
 pub fn fetch_orderbooks_of<SB, SQ>(
     base_symbol: SB,
     quote_symbol: SQ,
     fetch_count: usize,
-) -> Result<Vec<IncompleteOrderbook>>
+    max_levels_per_side: usize,
+) -> Result<(Vec<IncompleteOrderbook>, FetchReport)>
+where
+    SB: AsRef<str>,
+    SQ: AsRef<str>,
+{
+    fetch_orderbooks_of_with_transport(
+        base_symbol,
+        quote_symbol,
+        fetch_count,
+        max_levels_per_side,
+        &HttpTransport::new(),
+    )
+}
+
+/// `fetch_orderbooks_of` sending the call by `transport`
+pub fn fetch_orderbooks_of_with_transport<SB, SQ>(
+    base_symbol: SB,
+    quote_symbol: SQ,
+    fetch_count: usize,
+    max_levels_per_side: usize,
+    transport: &dyn Transport,
+) -> Result<(Vec<IncompleteOrderbook>, FetchReport)>
 where
     SB: AsRef<str>,
     SQ: AsRef<str>,
 {
     let market_symbol = get_market_symbol(base_symbol, quote_symbol);
     let query = vec![
-        ("market", market_symbol),
+        ("market", market_symbol.clone()),
         ("limit", fetch_count.to_string()),
     ];
     let json = ApiCallBuilder::new()
+        .transport(transport)
+        .retry(FETCH_RETRY_POLICY)
         .public_api()
         .method(Method::GET)
         .path("/exchange/api/v2/orderbook")
@@ -161,29 +301,131 @@ where
         .call()?;
 
     let parse_orders = |json: &JsonValue, side: OrderSide| {
-        json.members()
-            .filter_map(|buy_order_json| {
-                let price = buy_order_json[0].as_f32();
-                let volume = buy_order_json[1].as_f32();
-                match (price, volume) {
-                    (Some(price), Some(volume)) => Some((price, volume)),
-                    _ => None,
-                }
-            })
-            .map(|(price, volume)| IncompleteOrderbook {
+        parse_entries(json.members(), |order_json| {
+            let price = order_json[0].as_f64()?;
+            let volume = order_json[1].as_f64()?;
+            Some(IncompleteOrderbook {
                 side,
                 price,
                 volume,
             })
-            .collect::<Vec<_>>()
+        })
     };
 
-    let mut buy_orders = parse_orders(&json["buy"], OrderSide::Buy);
-    let mut sell_orders = parse_orders(&json["sell"], OrderSide::Sell);
+    let (mut buy_orders, buy_report) = parse_orders(required_array(&json, "buy")?, OrderSide::Buy);
+    let (mut sell_orders, sell_report) =
+        parse_orders(required_array(&json, "sell")?, OrderSide::Sell);
+
+    for orders in vec![&mut buy_orders, &mut sell_orders] {
+        if orders.len() > max_levels_per_side {
+            warn!(
+                "{} returned {} {:?} levels. Truncated to {}",
+                market_symbol,
+                orders.len(),
+                orders[0].side,
+                max_levels_per_side
+            );
+            truncate_orderbooks(orders, max_levels_per_side);
+        }
+    }
+
+    buy_orders.append(&mut sell_orders);
+
+    Ok((buy_orders, buy_report.merge(sell_report)))
+}
+
+/// Default of maximum concurrent requests of `fetch_orderbooks_many`
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 4;
+
+/// Fetch orderbooks of `markets` as `fetch_orderbooks_of` does, with at most `max_in_flight` requests at once.
+/// # Returns
+/// Result of each market keyed by its market symbol like `BTCUSDT`. A failed market doesn't affect the others
+pub fn fetch_orderbooks_many<SB, SQ>(
+    markets: &[(SB, SQ)],
+    fetch_count: usize,
+    max_levels_per_side: usize,
+    max_in_flight: usize,
+) -> HashMap<String, Result<(Vec<IncompleteOrderbook>, FetchReport)>>
+where
+    SB: AsRef<str> + Sync,
+    SQ: AsRef<str> + Sync,
+{
+    let results = map_concurrently(markets, max_in_flight, |(base, quote)| {
+        fetch_orderbooks_of(base, quote, fetch_count, max_levels_per_side)
+    });
+
+    markets
+        .iter()
+        .map(|(base, quote)| get_market_symbol(base, quote))
+        .zip(results)
+        .collect()
+}
+
+/// Apply `f` to each of `items` on at most `max_in_flight` threads.
+/// # Returns
+/// Results in the same order as `items`
+fn map_concurrently<T, R, F>(items: &[T], max_in_flight: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..items.len()).map(|_| None).collect::<Vec<Option<R>>>());
+
+    std::thread::scope(|scope| {
+        for _ in 0..max_in_flight.max(1).min(items.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let item = match items.get(i) {
+                    Some(item) => item,
+                    None => break,
+                };
+                let result = f(item);
+                results.lock().unwrap_or_else(|e| e.into_inner())[i] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner())
+        .into_iter()
+        .map(|result| result.expect("Every item is mapped"))
+        .collect()
+}
+
+/// Keep the best-priced `max_levels` orders of one side, i.e. the highest buys or the lowest sells.
+/// Kept orders are sorted from the best price.
+fn truncate_orderbooks(orders: &mut Vec<IncompleteOrderbook>, max_levels: usize) {
+    orders.sort_by(|o1, o2| {
+        let ordering = o1
+            .price
+            .partial_cmp(&o2.price)
+            .unwrap_or(std::cmp::Ordering::Equal);
+        match o1.side {
+            OrderSide::Buy => ordering.reverse(),
+            OrderSide::Sell => ordering,
+        }
+    });
+    orders.truncate(max_levels);
+}
 
+/// Keep the best-priced `top_n` orders of each side, i.e. the `top_n` highest buys and the `top_n` lowest sells.
+/// Orders of NaN price are dropped, and volumes are kept as they are.
+/// # Returns
+/// Buys followed by sells, each sorted from the best price
+pub fn trim_orderbook(orders: Vec<IncompleteOrderbook>, top_n: usize) -> Vec<IncompleteOrderbook> {
+    let (mut buy_orders, mut sell_orders): (Vec<_>, Vec<_>) = orders
+        .into_iter()
+        .filter(|order| !order.price.is_nan())
+        .partition(|order| order.side == OrderSide::Buy);
+
+    truncate_orderbooks(&mut buy_orders, top_n);
+    truncate_orderbooks(&mut sell_orders, top_n);
     buy_orders.append(&mut sell_orders);
 
-    Ok(buy_orders)
+    buy_orders
 }
 
 pub fn fetch_myorders<S: AsRef<str>>(
@@ -191,7 +433,24 @@ pub fn fetch_myorders<S: AsRef<str>>(
     quote_symbol: S,
     fetch_count: usize,
     api_key: ApiKey,
-) -> Result<Vec<IncompleteMyorder>> {
+) -> Result<(Vec<ExchangeOrder>, FetchReport)> {
+    fetch_myorders_with_transport(
+        base_symbol,
+        quote_symbol,
+        fetch_count,
+        api_key,
+        &HttpTransport::new(),
+    )
+}
+
+/// `fetch_myorders` sending the call by `transport`
+pub fn fetch_myorders_with_transport<S: AsRef<str>>(
+    base_symbol: S,
+    quote_symbol: S,
+    fetch_count: usize,
+    api_key: ApiKey,
+    transport: &dyn Transport,
+) -> Result<(Vec<ExchangeOrder>, FetchReport)> {
     let market_symbol = get_market_symbol(base_symbol, quote_symbol);
     let query = vec![
         ("market", market_symbol),
@@ -199,36 +458,247 @@ pub fn fetch_myorders<S: AsRef<str>>(
     ];
 
     let json = ApiCallBuilder::new()
+        .transport(transport)
+        .retry(FETCH_RETRY_POLICY)
         .private_api()
         .method(Method::GET)
         .path("/exchange/api/v2/info/myOrders")
         .query(query)
         .api_key(api_key)
         .call()?;
+    ensure!(json.is_array(), "Response of myOrders is not an array");
 
-    json.members()
-        .filter_map(|myorder_json| {
-            let transaction_id = myorder_json["orderId"].as_str()?;
-            let price = myorder_json["price"].as_f32()?;
-            let base_quantity = myorder_json["origQty"].as_f32()?;
-            let quote_quantity = myorder_json["origSndQty"].as_f32()?;
-            let order_type = myorder_json["type"].as_str().and_then(get_order_type)?;
-            let side = myorder_json["side"].as_str().and_then(get_order_side)?;
-            let state = myorder_json["state"].as_str().and_then(get_myorder_state)?;
-
-            let myorder = IncompleteMyorder {
-                transaction_id: transaction_id.to_string(),
-                price,
-                base_quantity,
-                quote_quantity,
-                order_type,
-                side,
-                state,
-            };
-            Some(myorder)
-        })
-        .collect::<Vec<_>>()
-        .apply(Ok)
+    parse_entries(json.members(), parse_myorder).apply(Ok)
+}
+
+/// Orders of a market created in [`from`, `to`), fetching `page_size` orders per request in ascending order of creation
+/// # Returns
+/// `Err(e)` if any order in the window fails to be parsed
+pub fn fetch_myorders_between<S: AsRef<str>>(
+    base_symbol: S,
+    quote_symbol: S,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    page_size: usize,
+    api_key: ApiKey,
+) -> Result<Vec<ExchangeOrder>> {
+    fetch_myorders_between_with_transport(
+        base_symbol,
+        quote_symbol,
+        from,
+        to,
+        page_size,
+        api_key,
+        &HttpTransport::new(),
+    )
+}
+
+/// `fetch_myorders_between` sending the calls by `transport`
+pub fn fetch_myorders_between_with_transport<S: AsRef<str>>(
+    base_symbol: S,
+    quote_symbol: S,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    page_size: usize,
+    api_key: ApiKey,
+    transport: &dyn Transport,
+) -> Result<Vec<ExchangeOrder>> {
+    let market_symbol = get_market_symbol(base_symbol, quote_symbol);
+    let to_millis = to.timestamp_millis();
+
+    let mut myorders = Vec::<ExchangeOrder>::new();
+    let mut page_from = from.timestamp_millis();
+    loop {
+        let query = vec![
+            ("market", market_symbol.clone()),
+            ("sortDirection", "ASC".to_string()),
+            // Orders created at or after `timestamp`
+            ("op", "GE".to_string()),
+            ("timestamp", page_from.to_string()),
+            ("limit", page_size.to_string()),
+        ];
+
+        let json = ApiCallBuilder::new()
+            .transport(transport)
+            .retry(FETCH_RETRY_POLICY)
+            .private_api()
+            .method(Method::GET)
+            .path("/exchange/api/v2/info/myOrders")
+            .query(query)
+            .api_key(api_key.clone())
+            .call()?;
+        ensure!(json.is_array(), "Response of myOrders is not an array");
+
+        let page_len = json.len();
+        let mut last_time = None;
+        for myorder_json in json.members() {
+            // A dropped order would be lost for good once the window is saved as synced, so the whole window fails instead
+            let time = myorder_json["time"]
+                .as_i64()
+                .ok_or_else(|| anyhow!("Order without creation time: {}", myorder_json.dump()))?;
+            last_time = Some(time);
+            if time < page_from || time >= to_millis {
+                continue;
+            }
+            let myorder = parse_myorder(myorder_json)
+                .ok_or_else(|| anyhow!("Failed to parse order: {}", myorder_json.dump()))?;
+            // Orders at the boundary of pages are fetched twice
+            if myorders
+                .iter()
+                .all(|m| m.transaction_id != myorder.transaction_id)
+            {
+                myorders.push(myorder)
+            }
+        }
+
+        match next_page_from(page_len, page_size, last_time, page_from, to_millis)? {
+            Some(next) => page_from = next,
+            None => break,
+        }
+    }
+
+    Ok(myorders)
+}
+
+/// Beginning of the next page of `fetch_myorders_between`, which is the creation time of the last order of the current page.
+/// # Returns
+/// `Ok(None)` if the window is exhausted.
+/// `Err(e)` if a full page has the same creation time, since paging can't advance
+fn next_page_from(
+    page_len: usize,
+    page_size: usize,
+    last_time: Option<i64>,
+    page_from: i64,
+    to: i64,
+) -> Result<Option<i64>> {
+    match last_time {
+        Some(last_time) if page_len >= page_size && last_time < to => {
+            if last_time <= page_from {
+                return Err(anyhow!(
+                    "More than {} orders are created at {}",
+                    page_size,
+                    page_from
+                ));
+            }
+            Ok(Some(last_time))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Place an order of `quantity` base currency at `price` on the exchange.
+/// Market buys are sent as quote quantity `quantity * price`, since the exchange takes them in quote currency.
+/// # Returns
+/// The created order.
+/// `Err(ApiError::Rejected)` if the exchange refuses the order, e.g. for insufficient balance
+pub fn place_order<SB: AsRef<str>, SQ: AsRef<str>>(
+    api_key: ApiKey,
+    base_symbol: SB,
+    quote_symbol: SQ,
+    order_type: OrderType,
+    side: OrderSide,
+    price: Amount,
+    quantity: Amount,
+    trigger_price: Option<Amount>,
+) -> Result<ExchangeOrder> {
+    let market_symbol = get_market_symbol(base_symbol, quote_symbol);
+    let query = order_query(
+        &market_symbol,
+        order_type,
+        side,
+        price,
+        quantity,
+        trigger_price,
+    )?;
+
+    let json = ApiCallBuilder::new()
+        .private_api()
+        .method(Method::POST)
+        .path("/exchange/api/v2/order")
+        .query(query)
+        .api_key(api_key)
+        .call()?;
+
+    parse_myorder(&json).ok_or(anyhow!("Invalid order response: {}", json.dump()))
+}
+
+/// Cancel an order of `market_symbol` like `BTCUSDT`
+/// # Returns
+/// The cancelled order
+pub fn cancel_order(api_key: ApiKey, market_symbol: &str, order_id: &str) -> Result<ExchangeOrder> {
+    let query = vec![("market", market_symbol), ("orderId", order_id)];
+
+    let json = ApiCallBuilder::new()
+        .private_api()
+        .method(Method::DELETE)
+        .path("/exchange/api/v2/order")
+        .query(query)
+        .api_key(api_key)
+        .call()?;
+
+    parse_myorder(&json).ok_or(anyhow!("Invalid order response: {}", json.dump()))
+}
+
+/// Query of `place_order`, exposed so that callers can log the exact payload.
+/// Stop orders require `trigger_price`, and are sized like limit or market orders
+pub fn order_query(
+    market_symbol: &str,
+    order_type: OrderType,
+    side: OrderSide,
+    price: Amount,
+    quantity: Amount,
+    trigger_price: Option<Amount>,
+) -> Result<Vec<(&'static str, String)>> {
+    let mut query = vec![
+        ("market", market_symbol.to_string()),
+        ("side", order_side_param(side).to_string()),
+        ("type", order_type_param(order_type).to_string()),
+    ];
+    match (order_type, side) {
+        (OrderType::Limit, _) | (OrderType::StopLimit, _) => {
+            query.push(("quantity", quantity.to_string()));
+            query.push(("price", price.to_string()));
+        }
+        (OrderType::Market, OrderSide::Buy) | (OrderType::StopMarket, OrderSide::Buy) => {
+            query.push(("secQuantity", (quantity * price).to_string()));
+        }
+        (OrderType::Market, OrderSide::Sell) | (OrderType::StopMarket, OrderSide::Sell) => {
+            query.push(("quantity", quantity.to_string()));
+        }
+    }
+    match (order_type, trigger_price) {
+        (OrderType::StopLimit, Some(trigger_price))
+        | (OrderType::StopMarket, Some(trigger_price)) => {
+            query.push(("stopPrice", trigger_price.to_string()));
+        }
+        (OrderType::StopLimit, None) | (OrderType::StopMarket, None) => {
+            return Err(anyhow!("{:?} orders require a trigger price", order_type))
+        }
+        (OrderType::Limit, _) | (OrderType::Market, _) => {}
+    }
+
+    Ok(query)
+}
+
+fn parse_myorder(myorder_json: &JsonValue) -> Option<ExchangeOrder> {
+    let transaction_id = myorder_json["orderId"].as_str()?;
+    let price = myorder_json["price"].as_f64()?;
+    let base_quantity = myorder_json["origQty"].as_f64()?;
+    let quote_quantity = myorder_json["origSndQty"].as_f64()?;
+    let order_type = myorder_json["type"].as_str().and_then(get_order_type)?;
+    let side = myorder_json["side"].as_str().and_then(get_order_side)?;
+    let state = myorder_json["state"].as_str().and_then(get_myorder_state)?;
+
+    let myorder = ExchangeOrder {
+        transaction_id: transaction_id.to_string(),
+        price,
+        base_quantity,
+        quote_quantity,
+        order_type,
+        side,
+        state,
+    };
+    Some(myorder)
 }
 
 pub fn get_market_symbol<SB: AsRef<str>, SQ: AsRef<str>>(
@@ -248,6 +718,15 @@ fn get_order_type<S: AsRef<str>>(s: S) -> Option<OrderType> {
     }
 }
 
+fn order_type_param(order_type: OrderType) -> &'static str {
+    match order_type {
+        OrderType::Limit => "LIMIT",
+        OrderType::Market => "MARKET",
+        OrderType::StopLimit => "STOP_LIMIT",
+        OrderType::StopMarket => "STOP_MARKET",
+    }
+}
+
 fn get_order_side<S: AsRef<str>>(s: S) -> Option<OrderSide> {
     match s.as_ref() {
         "BUY" => Some(OrderSide::Buy),
@@ -256,6 +735,13 @@ fn get_order_side<S: AsRef<str>>(s: S) -> Option<OrderSide> {
     }
 }
 
+fn order_side_param(side: OrderSide) -> &'static str {
+    match side {
+        OrderSide::Buy => "BUY",
+        OrderSide::Sell => "SELL",
+    }
+}
+
 fn get_myorder_state<S: AsRef<str>>(s: S) -> Option<OrderState> {
     match s.as_ref() {
         "CREATED" | "PARTIAL" | "RESERVED" | "INSERTED" | "ENTERED" | "RELEASED"
@@ -267,3 +753,679 @@ fn get_myorder_state<S: AsRef<str>>(s: S) -> Option<OrderState> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Captured from `/main/api/v2/public/currencies`, trimmed, with the last entry lacking its name
+    const CURRENCIES_JSON: &str = r#"{
+        "currencies": [
+            {"symbol": "BTC", "name": "Bitcoin", "transactionInfoUrl": "https://www.blockchain.com/btc/tx/", "walletVersion": "1", "order": 1},
+            {"symbol": "ETH", "name": "Ethereum", "transactionInfoUrl": "https://etherscan.io/tx/", "walletVersion": "1", "order": 2},
+            {"symbol": "USDT", "name": "Tether USD", "transactionInfoUrl": "https://etherscan.io/tx/", "walletVersion": "1", "order": 3},
+            {"symbol": "XYZ", "order": 4}
+        ]
+    }"#;
+
+    /// Captured from `/main/api/v2/accounting/accounts2`, with an inactive currency and a balance lacking `pending`
+    const BALANCES_JSON: &str = r#"{
+        "total": {"currency": "TOTAL", "totalBalance": "0.01234567", "available": "0.01000000", "pending": "0.00234567"},
+        "currencies": [
+            {"active": true, "currency": "BTC", "totalBalance": "0.01234567", "available": "0.01000000", "debt": "0.00000000", "pending": "0.00234567", "btcRate": 1.0, "fiatRate": 4100000.0, "status": "ACTIVE"},
+            {"active": true, "currency": "ETH", "totalBalance": "0.50000000", "available": "0.50000000", "debt": "0.00000000", "pending": "0.00000000", "btcRate": 0.065, "fiatRate": 266500.0, "status": "ACTIVE"},
+            {"active": false, "currency": "BCH", "totalBalance": "1.00000000", "available": "1.00000000", "debt": "0.00000000", "pending": "0.00000000", "btcRate": 0.005, "fiatRate": 20500.0, "status": "INACTIVE"},
+            {"active": true, "currency": "LTC", "totalBalance": "0.10000000", "available": "0.10000000", "debt": "0.00000000", "btcRate": 0.003, "fiatRate": 12300.0, "status": "ACTIVE"}
+        ]
+    }"#;
+
+    /// Captured from `/exchange/api/v2/info/prices`, with a price of invalid type
+    const PRICES_JSON: &str = r#"{
+        "BTCUSDT": 41000.12,
+        "ETHBTC": 0.06512,
+        "ETHUSDT": "2670.5",
+        "XYZBTC": 0.0000123
+    }"#;
+
+    /// Captured from `/exchange/api/v2/orderbook`, with a level of invalid price
+    const ORDERBOOK_JSON: &str = r#"{
+        "sell": [[41001.5, 0.12], [41003.0, 0.3]],
+        "buy": [[40999.0, 0.5], ["40998.0", 1.0], [40990.0, 2.0]],
+        "updatedTs": "2021-08-01T00:00:00.000000Z",
+        "tick": 1234567
+    }"#;
+
+    /// Captured from `/exchange/api/v2/info/myOrders`, with an order of unknown state
+    const MYORDERS_JSON: &str = r#"[
+        {"orderId": "a1b2c3", "price": 41000.0, "origQty": 0.001, "origSndQty": 41.0, "executedQty": 0.001, "executedSndQty": 41.0, "type": "LIMIT", "side": "BUY", "state": "FULL", "time": 1627776000000},
+        {"orderId": "d4e5f6", "price": 42000.0, "origQty": 0.002, "origSndQty": 84.0, "executedQty": 0.0, "executedSndQty": 0.0, "type": "LIMIT", "side": "SELL", "state": "ENTERED", "time": 1627776060000},
+        {"orderId": "g7h8i9", "price": 40000.0, "origQty": 0.003, "origSndQty": 120.0, "executedQty": 0.0, "executedSndQty": 0.0, "type": "LIMIT", "side": "BUY", "state": "UNKNOWN_STATE", "time": 1627776120000}
+    ]"#;
+
+    fn api_key() -> ApiKey {
+        ApiKey::new("org".into(), "key".into(), "secret".into())
+    }
+
+    #[test]
+    fn test_fetch_all_currencies() {
+        let transport =
+            MockTransport::new().with_json("/main/api/v2/public/currencies", CURRENCIES_JSON);
+
+        let (currencies, report) = fetch_all_currencies_with_transport(&transport).unwrap();
+
+        let currencies = currencies
+            .iter()
+            .map(|c| (c.symbol.as_str(), c.name.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("BTC", "Bitcoin"),
+                ("ETH", "Ethereum"),
+                ("USDT", "Tether USD")
+            ],
+            currencies
+        );
+        assert_eq!(
+            FetchReport {
+                total: 4,
+                skipped: 1
+            },
+            report
+        );
+    }
+
+    #[test]
+    fn test_fetch_all_currencies_retried() {
+        let transport = MockTransport::new().with_responses(
+            "/main/api/v2/public/currencies",
+            vec![
+                RawResponse {
+                    status: 503,
+                    ..RawResponse::json(r#"{"error_id":"5f1e","errors":[{"code":503,"message":"Service unavailable"}]}"#)
+                },
+                RawResponse::json(CURRENCIES_JSON),
+            ],
+        );
+
+        let (currencies, _) = fetch_all_currencies_with_transport(&transport).unwrap();
+
+        assert_eq!(3, currencies.len());
+        assert_eq!(2, transport.requests().len());
+    }
+
+    #[test]
+    fn test_fetch_all_currencies_without_array() {
+        let transport =
+            MockTransport::new().with_json("/main/api/v2/public/currencies", r#"{"message":"ok"}"#);
+
+        let e = fetch_all_currencies_with_transport(&transport).unwrap_err();
+        assert_eq!("Response has no `currencies` array", e.to_string());
+    }
+
+    #[test]
+    fn test_fetch_all_balances() {
+        let transport =
+            MockTransport::new().with_json("/main/api/v2/accounting/accounts2", BALANCES_JSON);
+
+        let (balances, report) = fetch_all_balances_with_transport(api_key(), &transport).unwrap();
+
+        // BCH is inactive, and LTC lacks pending
+        let balances = balances
+            .iter()
+            .map(|b| (b.symbol.as_str(), b.available, b.pending))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![("BTC", 0.01, 0.00234567), ("ETH", 0.5, 0.0)], balances);
+        // Inactive currencies are not counted
+        assert_eq!(
+            FetchReport {
+                total: 3,
+                skipped: 1
+            },
+            report
+        );
+
+        let requests = transport.requests();
+        let request = requests.last().unwrap();
+        assert_eq!("/main/api/v2/accounting/accounts2", request.path);
+        assert!(request.signed);
+    }
+
+    #[test]
+    fn test_fetch_all_market_prices() {
+        let transport = MockTransport::new().with_json("/exchange/api/v2/info/prices", PRICES_JSON);
+
+        let (prices, unknown_markets, report) =
+            fetch_all_market_prices_with_transport(&["BTC", "ETH", "USDT"], &transport).unwrap();
+
+        let prices = prices
+            .iter()
+            .map(|p| (p.base_symbol.as_str(), p.quote_symbol.as_str(), p.price))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![("BTC", "USDT", 41000.12), ("ETH", "BTC", 0.06512)],
+            prices
+        );
+        assert_eq!(vec!["XYZBTC".to_string()], unknown_markets);
+        assert_eq!(
+            FetchReport {
+                total: 3,
+                skipped: 1
+            },
+            report
+        );
+    }
+
+    #[test]
+    fn test_fetch_orderbooks_of() {
+        let transport =
+            MockTransport::new().with_json("/exchange/api/v2/orderbook", ORDERBOOK_JSON);
+
+        let (orders, report) =
+            fetch_orderbooks_of_with_transport("BTC", "USDT", 100, 2, &transport).unwrap();
+
+        // The invalid buy is skipped, then buys are truncated to the best 2
+        let orders = orders
+            .iter()
+            .map(|o| (o.side, o.price, o.volume))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (OrderSide::Buy, 40999.0, 0.5),
+                (OrderSide::Buy, 40990.0, 2.0),
+                (OrderSide::Sell, 41001.5, 0.12),
+                (OrderSide::Sell, 41003.0, 0.3),
+            ],
+            orders
+        );
+        assert_eq!(
+            FetchReport {
+                total: 5,
+                skipped: 1
+            },
+            report
+        );
+
+        let query = &transport.requests()[0].query;
+        assert_eq!(Some("BTCUSDT"), query.get("market"));
+        assert_eq!(Some("100"), query.get("limit"));
+    }
+
+    #[test]
+    fn test_fetch_orderbooks_of_without_side() {
+        let transport = MockTransport::new()
+            .with_json("/exchange/api/v2/orderbook", r#"{"buy":[[40999.0,0.5]]}"#);
+
+        let e = fetch_orderbooks_of_with_transport("BTC", "USDT", 100, 2, &transport).unwrap_err();
+        assert_eq!("Response has no `sell` array", e.to_string());
+    }
+
+    #[test]
+    fn test_fetch_myorders() {
+        let transport =
+            MockTransport::new().with_json("/exchange/api/v2/info/myOrders", MYORDERS_JSON);
+
+        let (myorders, report) =
+            fetch_myorders_with_transport("BTC", "USDT", 100, api_key(), &transport).unwrap();
+
+        let myorders = myorders
+            .iter()
+            .map(|m| (m.transaction_id.as_str(), m.side, m.state, m.base_quantity))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("a1b2c3", OrderSide::Buy, OrderState::Filled, 0.001),
+                ("d4e5f6", OrderSide::Sell, OrderState::Opened, 0.002),
+            ],
+            myorders
+        );
+        assert_eq!(
+            FetchReport {
+                total: 3,
+                skipped: 1
+            },
+            report
+        );
+    }
+
+    #[test]
+    fn test_fetch_myorders_not_array() {
+        let transport =
+            MockTransport::new().with_json("/exchange/api/v2/info/myOrders", r#"{"orders":[]}"#);
+
+        let e =
+            fetch_myorders_with_transport("BTC", "USDT", 100, api_key(), &transport).unwrap_err();
+        assert_eq!("Response of myOrders is not an array", e.to_string());
+    }
+
+    /// Order of `myOrders` created `minutes` after 2021-08-01 00:00:00
+    fn myorder_json(order_id: &str, minutes: i64) -> String {
+        format!(
+            r#"{{"orderId": "{}", "price": 41000.0, "origQty": 0.001, "origSndQty": 41.0, "type": "LIMIT", "side": "BUY", "state": "FULL", "time": {}}}"#,
+            order_id,
+            1627776000000 + minutes * 60_000
+        )
+    }
+
+    fn myorders_page(orders: &[(&str, i64)]) -> RawResponse {
+        let orders = orders
+            .iter()
+            .map(|(order_id, minutes)| myorder_json(order_id, *minutes))
+            .collect::<Vec<_>>();
+        RawResponse::json(format!("[{}]", orders.join(",")))
+    }
+
+    #[test]
+    fn test_fetch_myorders_between_pages() {
+        let from = NaiveDateTime::from_timestamp(1627776000, 0);
+        let to = NaiveDateTime::from_timestamp(1627776000 + 3600, 0);
+        let transport = MockTransport::new().with_responses(
+            "/exchange/api/v2/info/myOrders",
+            vec![
+                myorders_page(&[("a", 0), ("b", 1)]),
+                // The boundary order is fetched again
+                myorders_page(&[("b", 1), ("c", 2)]),
+                // The order after `to` is out of the window
+                myorders_page(&[("c", 2), ("d", 60)]),
+                myorders_page(&[("d", 60)]),
+            ],
+        );
+
+        let myorders = fetch_myorders_between_with_transport(
+            "BTC",
+            "USDT",
+            from,
+            to,
+            2,
+            api_key(),
+            &transport,
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec!["a", "b", "c"],
+            myorders
+                .iter()
+                .map(|m| m.transaction_id.as_str())
+                .collect::<Vec<_>>()
+        );
+        let requests = transport
+            .requests()
+            .into_iter()
+            .filter(|request| request.path == "/exchange/api/v2/info/myOrders")
+            .collect::<Vec<_>>();
+        // The page after `to` ends paging
+        assert_eq!(3, requests.len());
+        let page_froms = requests
+            .iter()
+            .map(|request| request.query.get("timestamp").map(str::to_string))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Some("1627776000000".to_string()),
+                Some("1627776060000".to_string()),
+                Some("1627776120000".to_string()),
+            ],
+            page_froms
+        );
+        for request in requests.iter() {
+            assert_eq!(Some("GE"), request.query.get("op"));
+            assert_eq!(Some("ASC"), request.query.get("sortDirection"));
+            assert_eq!(Some("2"), request.query.get("limit"));
+        }
+    }
+
+    #[test]
+    fn test_fetch_myorders_between_invalid_order() {
+        let from = NaiveDateTime::from_timestamp(1627776000, 0);
+        let to = NaiveDateTime::from_timestamp(1627776000 + 3600, 0);
+        let invalid = myorder_json("b", 1).replace("\"FULL\"", "\"UNKNOWN_STATE\"");
+        let transport = MockTransport::new().with_json(
+            "/exchange/api/v2/info/myOrders",
+            format!("[{},{}]", myorder_json("a", 0), invalid),
+        );
+
+        let e = fetch_myorders_between_with_transport(
+            "BTC",
+            "USDT",
+            from,
+            to,
+            10,
+            api_key(),
+            &transport,
+        )
+        .unwrap_err();
+
+        assert!(e.to_string().starts_with("Failed to parse order"));
+    }
+
+    #[test]
+    fn test_map_concurrently() {
+        let items = (0..20).collect::<Vec<u64>>();
+        let in_flight = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+
+        let results = map_concurrently(&items, 4, |&i| {
+            let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(current, Ordering::SeqCst);
+            // Later items finish earlier
+            std::thread::sleep(std::time::Duration::from_millis(20 - i));
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            i * 2
+        });
+
+        assert_eq!(items.iter().map(|i| i * 2).collect::<Vec<_>>(), results);
+        assert!(peak.load(Ordering::SeqCst) <= 4);
+        assert!(peak.load(Ordering::SeqCst) > 1);
+    }
+
+    #[test]
+    fn test_map_concurrently_edge_cases() {
+        assert!(map_concurrently(&Vec::<u8>::new(), 4, |&i| i).is_empty());
+        // No concurrency is regarded as sequential
+        assert_eq!(vec![1, 2], map_concurrently(&[0, 1], 0, |&i| i + 1));
+    }
+
+    #[test]
+    fn test_parse_entries() {
+        let json = json::parse(r#"[["100.0", 1.0], [101.0, 2.0], [102.0]]"#).unwrap();
+
+        let (prices, report) = parse_entries(json.members(), |j| j[1].as_f64());
+
+        assert_eq!(vec![1.0, 2.0], prices);
+        assert_eq!(
+            FetchReport {
+                total: 3,
+                skipped: 1
+            },
+            report
+        );
+        assert!((report.skipped_ratio() - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(0.0, FetchReport::default().skipped_ratio());
+    }
+
+    #[test]
+    fn test_required_array() {
+        let json = json::parse(r#"{"buy": [], "sell": {}}"#).unwrap();
+
+        assert!(required_array(&json, "buy").is_ok());
+        // Not an array
+        assert!(required_array(&json, "sell").is_err());
+        // Missing, e.g. in an error payload
+        assert!(required_array(&json, "currencies").is_err());
+    }
+
+    #[test]
+    fn test_order_query() {
+        let query = order_query(
+            "BTCUSDT",
+            OrderType::Limit,
+            OrderSide::Sell,
+            30000.0,
+            0.5,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                ("market", "BTCUSDT".to_string()),
+                ("side", "SELL".to_string()),
+                ("type", "LIMIT".to_string()),
+                ("quantity", "0.5".to_string()),
+                ("price", "30000".to_string()),
+            ],
+            query
+        );
+
+        // Market buys are in quote quantity
+        let query = order_query(
+            "BTCUSDT",
+            OrderType::Market,
+            OrderSide::Buy,
+            30000.0,
+            0.5,
+            None,
+        )
+        .unwrap();
+        assert_eq!(("secQuantity", "15000".to_string()), query[3]);
+        assert_eq!(4, query.len());
+
+        let query = order_query(
+            "BTCUSDT",
+            OrderType::Market,
+            OrderSide::Sell,
+            30000.0,
+            0.5,
+            None,
+        )
+        .unwrap();
+        assert_eq!(("quantity", "0.5".to_string()), query[3]);
+        assert_eq!(4, query.len());
+
+        // Stops are sized like limit or market orders, and placed at the trigger price
+        let query = order_query(
+            "BTCUSDT",
+            OrderType::StopLimit,
+            OrderSide::Sell,
+            30000.0,
+            0.5,
+            Some(31000.0),
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                ("market", "BTCUSDT".to_string()),
+                ("side", "SELL".to_string()),
+                ("type", "STOP_LIMIT".to_string()),
+                ("quantity", "0.5".to_string()),
+                ("price", "30000".to_string()),
+                ("stopPrice", "31000".to_string()),
+            ],
+            query
+        );
+
+        let query = order_query(
+            "BTCUSDT",
+            OrderType::StopMarket,
+            OrderSide::Buy,
+            30000.0,
+            0.5,
+            Some(31000.0),
+        )
+        .unwrap();
+        assert_eq!(("secQuantity", "15000".to_string()), query[3]);
+        assert_eq!(("stopPrice", "31000".to_string()), query[4]);
+        assert_eq!(5, query.len());
+
+        assert!(order_query(
+            "BTCUSDT",
+            OrderType::StopLimit,
+            OrderSide::Sell,
+            30000.0,
+            0.5,
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_order_params_round_trip() {
+        for &order_type in [
+            OrderType::Limit,
+            OrderType::Market,
+            OrderType::StopLimit,
+            OrderType::StopMarket,
+        ]
+        .iter()
+        {
+            assert_eq!(
+                Some(order_type),
+                get_order_type(order_type_param(order_type))
+            );
+        }
+        for &side in [OrderSide::Buy, OrderSide::Sell].iter() {
+            assert_eq!(Some(side), get_order_side(order_side_param(side)));
+        }
+    }
+
+    #[test]
+    fn test_next_page_from() {
+        // A full page continues from its last order
+        assert_eq!(
+            Some(150),
+            next_page_from(10, 10, Some(150), 100, 1000).unwrap()
+        );
+        // A short page, or a page reaching the window end, exhausts the window
+        assert_eq!(None, next_page_from(9, 10, Some(150), 100, 1000).unwrap());
+        assert_eq!(None, next_page_from(10, 10, Some(1000), 100, 1000).unwrap());
+        assert_eq!(None, next_page_from(0, 10, None, 100, 1000).unwrap());
+        // A full page at a single time can't advance
+        assert!(next_page_from(10, 10, Some(100), 100, 1000).is_err());
+    }
+
+    fn orderbooks(side: OrderSide, prices: &[Amount]) -> Vec<IncompleteOrderbook> {
+        prices
+            .iter()
+            .map(|&price| IncompleteOrderbook {
+                side,
+                price,
+                volume: 1.0,
+            })
+            .collect()
+    }
+
+    fn prices(orders: &[IncompleteOrderbook]) -> Vec<Amount> {
+        orders.iter().map(|o| o.price).collect()
+    }
+
+    #[test]
+    fn test_split_market_symbol() {
+        let symbols = ["BTC", "USDT", "ETH"];
+
+        assert_eq!(
+            Some(("BTC", "USDT")),
+            split_market_symbol("BTCUSDT", &symbols)
+        );
+        assert_eq!(
+            Some(("ETH", "BTC")),
+            split_market_symbol("ETHBTC", &symbols)
+        );
+        assert_eq!(None, split_market_symbol("XYZUSDT", &symbols));
+    }
+
+    #[test]
+    fn test_split_market_symbol_with_overlapping_symbols() {
+        let symbols = ["USD", "BTC", "USDT", "ETH", "ETC", "T", "TUSD", ""];
+        let cases = [
+            // Trailing `T` is not left unconsumed
+            ("BTCUSDT", Some(("BTC", "USDT"))),
+            ("BTCUSD", Some(("BTC", "USD"))),
+            ("USDTBTC", Some(("USDT", "BTC"))),
+            ("ETCETH", Some(("ETC", "ETH"))),
+            ("ETHETC", Some(("ETH", "ETC"))),
+            // Both USD-TUSD and USDT-USD are possible. The longest base wins
+            ("USDTUSD", Some(("USDT", "USD"))),
+            // Not decomposable
+            ("BTCUSDTX", None),
+            ("ETHE", None),
+            ("BTC", None),
+            ("", None),
+        ];
+
+        for (market, expected) in cases.iter() {
+            assert_eq!(
+                *expected,
+                split_market_symbol(market, &symbols),
+                "market {}",
+                market
+            );
+        }
+    }
+
+    #[test]
+    fn test_split_market_symbol_with_delisted_prefix() {
+        // USD is delisted, and is a prefix of USDT
+        let with_inactive = ["BTC", "USD", "USDT"];
+        let active = ["BTC", "USDT"];
+
+        // The whole market symbol must be consumed
+        assert_eq!(
+            Some(("BTC", "USDT")),
+            split_market_symbol("BTCUSDT", &with_inactive)
+        );
+        assert_eq!(
+            Some(("BTC", "USDT")),
+            split_market_symbol("BTCUSDT", &active)
+        );
+    }
+
+    #[test]
+    fn test_truncate_orderbooks_buy() {
+        let mut orders = orderbooks(OrderSide::Buy, &[98.0, 100.0, 97.0, 99.0]);
+
+        truncate_orderbooks(&mut orders, 2);
+
+        // Highest bids are kept
+        assert_eq!(vec![100.0, 99.0], prices(&orders));
+    }
+
+    #[test]
+    fn test_truncate_orderbooks_sell() {
+        let mut orders = orderbooks(OrderSide::Sell, &[102.0, 101.0, 104.0, 103.0]);
+
+        truncate_orderbooks(&mut orders, 2);
+
+        // Lowest asks are kept
+        assert_eq!(vec![101.0, 102.0], prices(&orders));
+    }
+
+    #[test]
+    fn test_trim_orderbook() {
+        let mut orders = orderbooks(OrderSide::Sell, &[102.0, 101.0, 104.0]);
+        orders.extend(orderbooks(OrderSide::Buy, &[98.0, 100.0, 99.0]));
+        orders[1].volume = 3.5;
+
+        let trimmed = trim_orderbook(orders, 2);
+
+        // Buys come first, each side from the best price
+        assert_eq!(vec![100.0, 99.0, 101.0, 102.0], prices(&trimmed));
+        assert_eq!(
+            vec![
+                OrderSide::Buy,
+                OrderSide::Buy,
+                OrderSide::Sell,
+                OrderSide::Sell
+            ],
+            trimmed.iter().map(|o| o.side).collect::<Vec<_>>()
+        );
+        assert_eq!(3.5, trimmed[2].volume);
+    }
+
+    #[test]
+    fn test_trim_orderbook_nan() {
+        let mut orders = orderbooks(OrderSide::Buy, &[Amount::NAN, 98.0, 99.0]);
+        orders.extend(orderbooks(OrderSide::Sell, &[Amount::NAN, 101.0]));
+
+        let trimmed = trim_orderbook(orders, 2);
+
+        assert_eq!(vec![99.0, 98.0, 101.0], prices(&trimmed));
+    }
+
+    #[test]
+    fn test_trim_orderbook_within_limit() {
+        let orders = orderbooks(OrderSide::Buy, &[98.0, 99.0]);
+
+        // One side is empty
+        assert_eq!(vec![99.0, 98.0], prices(&trim_orderbook(orders, 5)));
+        assert!(trim_orderbook(vec![], 5).is_empty());
+        assert!(trim_orderbook(orderbooks(OrderSide::Sell, &[101.0]), 0).is_empty());
+    }
+
+    #[test]
+    fn test_truncate_orderbooks_within_limit() {
+        let mut orders = orderbooks(OrderSide::Sell, &[102.0, 101.0]);
+
+        truncate_orderbooks(&mut orders, 5);
+
+        assert_eq!(vec![101.0, 102.0], prices(&orders));
+    }
+}
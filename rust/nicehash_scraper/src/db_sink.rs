@@ -0,0 +1,498 @@
+use crate::spool::{save_rows, RecordSink, RowsError, SinkError, SpoolRecord};
+use anyhow::anyhow;
+use database::error::{Error as DbError, LogicError};
+use database::logic::*;
+use database::model::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::Connection;
+
+/// A resumed stamp is looked up within this range, since DB may round fractional seconds of its timestamp
+const RESUME_STAMP_TOLERANCE_MILLIS: i64 = 1000;
+
+/// Substrings of MySQL client errors meaning the connection is lost
+const CONNECTION_ERROR_MESSAGES: &[&str] =
+    &["server has gone away", "Lost connection", "Can't connect"];
+
+/// Save records to DB through logic functions
+pub struct DbSink<'a> {
+    conn: &'a Conn,
+    /// Source of stamps added by this sink
+    stamp_source: StampSource,
+    stamp: Option<Stamp>,
+    currency_collection: CurrencyCollection,
+    market_collection: MarketCollection,
+}
+
+impl<'a> DbSink<'a> {
+    pub fn new(conn: &'a Conn, stamp_source: StampSource) -> Result<Self, SinkError> {
+        let currency_collection = list_currencies(conn).map_err(to_sink_error)?;
+        let market_collection = list_markets(conn).map_err(to_sink_error)?;
+
+        Ok(Self {
+            conn,
+            stamp_source,
+            stamp: None,
+            currency_collection,
+            market_collection,
+        })
+    }
+
+    /// Stamp saved last
+    pub fn saved_stamp_id(&self) -> Option<StampId> {
+        self.stamp.as_ref().map(|stamp| stamp.stamp_id)
+    }
+
+    fn stamp_id(&self) -> Result<StampId, SinkError> {
+        self.stamp
+            .as_ref()
+            .map(|stamp| stamp.stamp_id)
+            .ok_or_else(|| SinkError::Rejected(anyhow!("No stamp precedes the row")))
+    }
+
+    /// Spooled records may be older than delisting, so inactive currencies are resolved as well
+    fn currency(&self, symbol: &str) -> Result<&Currency, SinkError> {
+        self.currency_collection
+            .by_symbol_including_inactive(symbol)
+            .ok_or_else(|| SinkError::Rejected(anyhow!("Unknown currency {}", symbol)))
+    }
+
+    /// Get market of `base`-`quote`. Unknown market is added to DB if `add_if_missing` is `true`
+    fn market(
+        &mut self,
+        base: &str,
+        quote: &str,
+        add_if_missing: bool,
+    ) -> Result<Market, SinkError> {
+        let base_id = self.currency(base)?.currency_id;
+        let quote_id = self.currency(quote)?.currency_id;
+
+        if let Some(market) = self.market_collection.by_base_quote_id(base_id, quote_id) {
+            return Ok(market.clone());
+        }
+        if !add_if_missing {
+            return Err(SinkError::Rejected(anyhow!(
+                "Unknown market {}-{}",
+                base,
+                quote
+            )));
+        }
+
+        let market = add_market(self.conn, base_id, quote_id).map_err(to_sink_error)?;
+        info!("Add market: {}/{}", base, quote);
+        self.market_collection = list_markets(self.conn).map_err(to_sink_error)?;
+
+        Ok(market)
+    }
+
+    /// Save price rows of a stamp at once.
+    /// Rows of unknown currencies, invalid amounts or repeated markets are skipped and reported
+    fn save_prices(&mut self, records: &[SpoolRecord]) -> Result<(), SinkError> {
+        let stamp_id = self.stamp_id()?;
+
+        let mut items = vec![];
+        let mut skipped = 0;
+        for record in records {
+            if let SpoolRecord::Price {
+                base,
+                quote,
+                amount,
+            } = record
+            {
+                match self.market(base, quote, true) {
+                    Ok(_) if !amount.is_finite() => {
+                        warn!(
+                            "Skip price of {}-{}: invalid amount {}",
+                            base, quote, amount
+                        );
+                        skipped += 1;
+                    }
+                    Ok(market) => items.push((market.market_id, *amount)),
+                    Err(SinkError::Rejected(e)) => {
+                        warn!("Skip price of {}-{}: {}", base, quote, e);
+                        skipped += 1;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        let (items, duplicated) = skip_duplicates(items, |(market_id, _)| *market_id);
+        if duplicated > 0 {
+            warn!("Skip {} prices of repeated markets", duplicated);
+        }
+
+        let prices = match add_prices_bulk(self.conn, stamp_id, &items) {
+            Ok(prices) => prices,
+            // A retried run may save prices of the same stamp again. Only markets without price are inserted
+            Err(e) if is_duplicated_price(&e) => {
+                let saved_market_ids =
+                    list_priced_market_ids(self.conn, stamp_id).map_err(to_sink_error)?;
+                let (items, saved) = skip_saved_markets(items, &saved_market_ids);
+                warn!(
+                    "Skip {} prices: the stamp already has prices of the markets",
+                    saved
+                );
+                skipped += saved;
+                add_prices_bulk(self.conn, stamp_id, &items).map_err(to_sink_error)?
+            }
+            Err(e) => return Err(to_sink_error(e)),
+        };
+        info!(
+            "Inserted {} prices, skipped {}",
+            prices.len(),
+            skipped + duplicated
+        );
+
+        Ok(())
+    }
+
+    /// Save balance rows of a stamp at once.
+    /// Rows of unknown currencies, invalid amounts or repeated currencies are skipped and reported
+    fn save_balances(&mut self, records: &[SpoolRecord]) -> Result<(), SinkError> {
+        let stamp_id = self.stamp_id()?;
+
+        let mut items = vec![];
+        let mut skipped = 0;
+        for record in records {
+            if let SpoolRecord::Balance {
+                symbol,
+                available,
+                pending,
+            } = record
+            {
+                match self.currency(symbol) {
+                    Ok(_) if !available.is_finite() || !pending.is_finite() => {
+                        warn!(
+                            "Skip balance of {}: invalid amounts {}/{}",
+                            symbol, available, pending
+                        );
+                        skipped += 1;
+                    }
+                    Ok(currency) => items.push((currency.currency_id, *available, *pending)),
+                    Err(e) => {
+                        warn!("Skip balance of {}: {}", symbol, e);
+                        skipped += 1;
+                    }
+                }
+            }
+        }
+        let (items, duplicated) = skip_duplicates(items, |(currency_id, _, _)| *currency_id);
+        if duplicated > 0 {
+            warn!("Skip {} balances of repeated currencies", duplicated);
+        }
+
+        let balances = add_balances_bulk(self.conn, stamp_id, &items).map_err(to_sink_error)?;
+        info!(
+            "Inserted {} balances, skipped {}",
+            balances.len(),
+            skipped + duplicated
+        );
+
+        Ok(())
+    }
+}
+
+/// Keep the first item of each key.
+/// # Returns
+/// Kept items in the original order, and the number of skipped items
+fn skip_duplicates<T, K: PartialEq>(items: Vec<T>, key: impl Fn(&T) -> K) -> (Vec<T>, usize) {
+    let len = items.len();
+    let mut keys = vec![];
+    let kept = items
+        .into_iter()
+        .filter(|item| {
+            let key = key(item);
+            if keys.contains(&key) {
+                false
+            } else {
+                keys.push(key);
+                true
+            }
+        })
+        .collect::<Vec<_>>();
+    let skipped = len - kept.len();
+
+    (kept, skipped)
+}
+
+/// Drop price items of markets in `saved_market_ids`.
+/// # Returns
+/// Kept items in the original order, and the number of dropped items
+fn skip_saved_markets(
+    items: Vec<(MarketId, Amount)>,
+    saved_market_ids: &[MarketId],
+) -> (Vec<(MarketId, Amount)>, usize) {
+    let len = items.len();
+    let kept = items
+        .into_iter()
+        .filter(|(market_id, _)| !saved_market_ids.contains(market_id))
+        .collect::<Vec<_>>();
+    let skipped = len - kept.len();
+
+    (kept, skipped)
+}
+
+impl<'a> RecordSink for DbSink<'a> {
+    fn save(&mut self, record: &SpoolRecord) -> Result<(), SinkError> {
+        match record {
+            SpoolRecord::Stamp { timestamp } => {
+                let stamp = add_stamp_from(self.conn, *timestamp, self.stamp_source)
+                    .map_err(to_sink_error)?;
+                debug!("Add stamp: {} ({})", stamp.timestamp, stamp.source);
+                self.stamp = Some(stamp);
+            }
+            SpoolRecord::ResumeStamp { timestamp } => {
+                let tolerance = chrono::Duration::milliseconds(RESUME_STAMP_TOLERANCE_MILLIS);
+                let stamp = get_stamp_near(self.conn, *timestamp, tolerance)
+                    .map_err(to_sink_error)?
+                    .ok_or_else(|| {
+                        SinkError::Rejected(anyhow!("No stamp at {} to resume", timestamp))
+                    })?;
+                debug!("Resume stamp: {} ({})", stamp.timestamp, stamp.source);
+                self.stamp = Some(stamp);
+            }
+            SpoolRecord::Balance {
+                symbol,
+                available,
+                pending,
+            } => {
+                let stamp_id = self.stamp_id()?;
+                let currency_id = self.currency(symbol)?.currency_id;
+                let balance = add_balance(self.conn, currency_id, stamp_id, *available, *pending)
+                    .map_err(to_sink_error)?;
+                debug!(
+                    "Add balance: {}/{} {}",
+                    balance.available, balance.pending, symbol
+                );
+            }
+            SpoolRecord::Price {
+                base,
+                quote,
+                amount,
+            } => {
+                let stamp_id = self.stamp_id()?;
+                let market = self.market(base, quote, true)?;
+                match add_price(self.conn, market.market_id, stamp_id, *amount) {
+                    Ok(price) => debug!("Add price: {}/{}", price.market_id, price.amount),
+                    Err(e) if is_duplicated_price(&e) => {
+                        debug!("Skip price of {}-{}: already saved", base, quote)
+                    }
+                    Err(e) => return Err(to_sink_error(e)),
+                }
+            }
+            SpoolRecord::Orderbook {
+                base,
+                quote,
+                side,
+                price,
+                volume,
+            } => {
+                let stamp_id = self.stamp_id()?;
+                let market = self.market(base, quote, false)?;
+                let orderbook = add_orderbook(
+                    self.conn,
+                    market.market_id,
+                    stamp_id,
+                    *side,
+                    *price,
+                    *volume,
+                )
+                .map_err(to_sink_error)?;
+                debug!("Add orderbook. id: {}", orderbook.orderbook_id);
+            }
+            SpoolRecord::Myorder {
+                transaction_id,
+                base,
+                quote,
+                price,
+                base_quantity,
+                quote_quantity,
+                order_type,
+                side,
+                state,
+            } => {
+                let stamp_id = self.stamp_id()?;
+                let market = self.market(base, quote, false)?;
+                let upsert = add_or_update_myorder(
+                    self.conn,
+                    transaction_id.clone(),
+                    market.market_id,
+                    stamp_id,
+                    *price,
+                    *base_quantity,
+                    *quote_quantity,
+                    *order_type,
+                    *side,
+                    *state,
+                )
+                .map_err(to_sink_error)?;
+                match upsert {
+                    MyorderUpsert::IgnoredStale => warn!(
+                        "Ignore stale state {:?} of myorder transaction: {}",
+                        state, transaction_id
+                    ),
+                    upsert => debug!("{:?} myorder transaction: {}", upsert, transaction_id),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rows are inserted by `add_orderbooks_bulk` in a transaction, so none of them are saved on failure
+    fn save_orderbooks(&mut self, records: &[SpoolRecord]) -> Result<(), RowsError> {
+        let (base, quote) = match records.first() {
+            Some(SpoolRecord::Orderbook { base, quote, .. }) => (base, quote),
+            _ => return Ok(()),
+        };
+        let stamp_id = self.stamp_id()?;
+        let market = self.market(base, quote, false)?;
+
+        let levels = records
+            .iter()
+            .filter_map(|record| match record {
+                SpoolRecord::Orderbook {
+                    side,
+                    price,
+                    volume,
+                    ..
+                } => Some((*side, *price, *volume)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let orderbooks = add_orderbooks_bulk(self.conn, market.market_id, stamp_id, &levels)
+            .map_err(to_sink_error)?;
+        debug!(
+            "Add {} orderbooks of market {}",
+            orderbooks.len(),
+            market.market_id
+        );
+
+        Ok(())
+    }
+
+    /// Save rows of a stage in a transaction, so none of them are saved on failure
+    fn save_stage(&mut self, records: &[SpoolRecord]) -> Result<(), RowsError> {
+        let conn = self.conn;
+        let mut failure = None;
+        let result = conn.transaction::<(), DbError, _>(|| {
+            match records.first() {
+                Some(SpoolRecord::Price { .. }) => self.save_prices(records),
+                Some(SpoolRecord::Balance { .. }) => self.save_balances(records),
+                _ => save_rows(self, records).map_err(|e| e.error),
+            }
+            .map_err(|e| {
+                failure = Some(e);
+                DbError::Db(DieselError::RollbackTransaction)
+            })
+        });
+
+        match (result, failure) {
+            (_, Some(e)) => {
+                // Markets added in the rolled back transaction don't exist
+                if let Ok(markets) = list_markets(self.conn) {
+                    self.market_collection = markets;
+                }
+                Err(e.into())
+            }
+            (Err(e), None) => Err(to_sink_error(e).into()),
+            (Ok(()), None) => Ok(()),
+        }
+    }
+}
+
+fn to_sink_error(e: DbError) -> SinkError {
+    if is_connection_error(&e) {
+        SinkError::Unavailable(e.into())
+    } else {
+        SinkError::Rejected(e.into())
+    }
+}
+
+/// The market already has a price at the stamp. Saving it again is harmless to skip
+fn is_duplicated_price(e: &DbError) -> bool {
+    matches!(e, DbError::Logic(LogicError::DuplicatedPrice))
+}
+
+fn is_connection_error(e: &DbError) -> bool {
+    match e {
+        DbError::Db(DieselError::DatabaseError(DatabaseErrorKind::UnableToSendCommand, _)) => true,
+        DbError::Db(DieselError::DatabaseError(_, info)) => CONNECTION_ERROR_MESSAGES
+            .iter()
+            .any(|message| info.message().contains(message)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db_error(kind: DatabaseErrorKind, message: &str) -> DbError {
+        DbError::Db(DieselError::DatabaseError(
+            kind,
+            Box::new(message.to_string()),
+        ))
+    }
+
+    #[test]
+    fn test_skip_duplicates() {
+        let items = vec![(1, 10.0), (2, 20.0), (1, 11.0), (3, 30.0), (2, 21.0)];
+
+        let (kept, skipped) = skip_duplicates(items, |(id, _)| *id);
+
+        assert_eq!(vec![(1, 10.0), (2, 20.0), (3, 30.0)], kept);
+        assert_eq!(2, skipped);
+        assert_eq!(
+            (vec![], 0),
+            skip_duplicates::<(i32, Amount), _>(vec![], |(id, _)| *id)
+        );
+    }
+
+    #[test]
+    fn test_skip_saved_markets() {
+        let market = MarketId::new;
+        let items = vec![(market(1), 10.0), (market(2), 20.0), (market(3), 30.0)];
+
+        // Only the market priced by a previous run is dropped
+        let (kept, skipped) = skip_saved_markets(items.clone(), &[market(2), market(4)]);
+        assert_eq!(vec![(market(1), 10.0), (market(3), 30.0)], kept);
+        assert_eq!(1, skipped);
+
+        assert_eq!((items.clone(), 0), skip_saved_markets(items, &[]));
+    }
+
+    #[test]
+    fn test_is_connection_error() {
+        assert!(is_connection_error(&db_error(
+            DatabaseErrorKind::__Unknown,
+            "MySQL server has gone away"
+        )));
+        assert!(is_connection_error(&db_error(
+            DatabaseErrorKind::UnableToSendCommand,
+            ""
+        )));
+
+        assert!(!is_connection_error(&db_error(
+            DatabaseErrorKind::UniqueViolation,
+            "Duplicate entry"
+        )));
+        assert!(!is_connection_error(&DbError::Logic(
+            LogicError::NonLatestStamp
+        )));
+    }
+
+    #[test]
+    fn test_is_duplicated_price() {
+        assert!(is_duplicated_price(&DbError::Logic(
+            LogicError::DuplicatedPrice
+        )));
+
+        assert!(!is_duplicated_price(&DbError::Logic(
+            LogicError::DuplicatedCurrency
+        )));
+        assert!(!is_duplicated_price(&db_error(
+            DatabaseErrorKind::UniqueViolation,
+            "Duplicate entry"
+        )));
+    }
+}
@@ -0,0 +1,1025 @@
+use anyhow::{anyhow, Error, Result};
+use apply::Apply;
+use chrono::NaiveDateTime;
+use database::logic::*;
+use database::model::{MarketId, ScrapeRun, StampId, StampSource, SyncCursor, SyncKind};
+use db_sink::DbSink;
+use diesel::prelude::*;
+use nicehash::api_common::{is_service_unavailable, ApiKey};
+use nicehash::FetchReport;
+use spool::*;
+use std::env;
+use std::str::FromStr;
+#[macro_use]
+extern crate log;
+
+mod db_sink;
+pub mod myorder_sync;
+pub mod spool;
+
+fn connect_db() -> Result<Conn> {
+    let url = env::var("DATABASE_URL")?;
+    Conn::establish(&url).map_err(Into::into)
+}
+
+/// Active currencies and markets of local DB, to resolve target markets
+struct Catalog {
+    currencies: CurrencyCollection,
+    markets: MarketCollection,
+}
+
+fn load_catalog(conn: &Conn) -> database::error::Result<Catalog> {
+    Ok(Catalog {
+        currencies: list_currencies(conn)?.active(),
+        markets: list_markets(conn)?,
+    })
+}
+
+/// `BASE-QUOTE` pairs of environment variable `key`, separated by `:`. Unresolvable pairs are warned and ignored.
+/// Without `catalog`, i.e. while DB is unavailable, pairs are only checked to be well-formed,
+/// and rows of unknown markets are rejected when they are saved
+fn get_target_markets_from_env(
+    key: &str,
+    catalog: Option<&Catalog>,
+) -> Result<Vec<(String, String)>> {
+    let market_symbol_source = env::var(key)?;
+
+    let markets = match catalog {
+        Some(catalog) => {
+            let (resolved, errors) = resolve_market_symbols(
+                &market_symbol_source,
+                &catalog.currencies,
+                &catalog.markets,
+            );
+            for e in errors.iter() {
+                warn!("Ignore a market of {}: {}", key, e);
+            }
+            resolved
+                .into_iter()
+                .map(|(base, quote, _)| (base.symbol, quote.symbol))
+                .collect()
+        }
+        None => market_symbol_source
+            .split(':')
+            .filter(|pair| !pair.trim().is_empty())
+            .filter_map(|pair| match parse_market_pair(pair) {
+                Ok((base, quote)) => Some((base.to_string(), quote.to_string())),
+                Err(e) => {
+                    warn!("Ignore a market of {}: {}", key, e);
+                    None
+                }
+            })
+            .collect(),
+    };
+
+    Ok(markets)
+}
+
+fn get_fetch_count_from_env(key: &str) -> Result<usize> {
+    env::var(key)
+        .map_err(Error::from)
+        .and_then(|s| usize::from_str(&s).map_err(Error::from))
+}
+
+/// `ORDERBOOK_KEEP_TOP_LEVELS`. `None` if unset, so that every fetched level is persisted
+fn keep_top_levels_from_env() -> Result<Option<usize>> {
+    match env::var("ORDERBOOK_KEEP_TOP_LEVELS") {
+        Ok(s) => {
+            let levels = usize::from_str(&s)?;
+            if levels == 0 {
+                return Err(anyhow!("ORDERBOOK_KEEP_TOP_LEVELS must be positive"));
+            }
+            Ok(Some(levels))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+fn spool_from_env() -> Result<Spool> {
+    let dir = env::var("SPOOL_DIR").unwrap_or_else(|_| "spool".into());
+    let max_bytes = match env::var("SPOOL_MAX_BYTES") {
+        Ok(s) => u64::from_str(&s)?,
+        Err(_) => 100 * 1024 * 1024,
+    };
+    let max_attempts = match env::var("SPOOL_MAX_REPLAY_ATTEMPTS") {
+        Ok(s) => u32::from_str(&s)?,
+        Err(_) => 3,
+    };
+
+    Ok(Spool::new(dir, max_bytes, max_attempts))
+}
+
+/// Retention days of `key`. `None` if unset, so that nothing is pruned
+fn retention_days_from_env(key: &str) -> Result<Option<i64>> {
+    match env::var(key) {
+        Ok(s) => {
+            let days = i64::from_str(&s)?;
+            if days <= 0 {
+                return Err(anyhow!("{} must be positive: {}", key, days));
+            }
+            Ok(Some(days))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+/// Delete rows older than `PRUNE_ORDERBOOK_OLDER_THAN_DAYS`, `PRUNE_PRICE_OLDER_THAN_DAYS` and
+/// stamps referred by no row older than `PRUNE_STAMP_OLDER_THAN_DAYS`.
+/// Failures are only logged
+fn prune_old_rows(conn: &Conn, now: NaiveDateTime) {
+    type Prune = fn(&Conn, NaiveDateTime) -> database::error::Result<usize>;
+    // Stamps are pruned last, since orderbooks and prices refer them
+    let prunes: [(&str, &str, Prune); 3] = [
+        (
+            "PRUNE_ORDERBOOK_OLDER_THAN_DAYS",
+            "orderbook",
+            prune_orderbooks_older_than,
+        ),
+        (
+            "PRUNE_PRICE_OLDER_THAN_DAYS",
+            "price",
+            prune_prices_older_than,
+        ),
+        (
+            "PRUNE_STAMP_OLDER_THAN_DAYS",
+            "stamp",
+            prune_unreferenced_stamps_older_than,
+        ),
+    ];
+
+    for (key, table, prune) in prunes.iter() {
+        let cutoff = match retention_days_from_env(key) {
+            Ok(Some(days)) => now - chrono::Duration::days(days),
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("Can't load retention days: {}", e);
+                continue;
+            }
+        };
+
+        match prune(conn, cutoff) {
+            Ok(count) => info!("Pruned {} {} rows older than {}", count, table, cutoff),
+            Err(e) => warn!("Can't prune {} rows: {}", table, e),
+        }
+    }
+}
+
+/// Save records spooled while DB was unavailable
+fn replay_spool(spool: &Spool, conn: &Conn) -> Result<()> {
+    let mut sink = match DbSink::new(conn, StampSource::Replay) {
+        Ok(sink) => sink,
+        Err(e) => return Err(anyhow!("Can't replay spool: {}", e)),
+    };
+    let report = spool.replay(&mut sink)?;
+
+    if report != ReplayReport::default() {
+        info!(
+            "Spool replay: {} replayed, {} failed, {} quarantined{}",
+            report.replayed,
+            report.failed,
+            report.quarantined,
+            if report.interrupted {
+                ", interrupted by DB failure"
+            } else {
+                ""
+            }
+        );
+    }
+
+    Ok(())
+}
+
+fn push_spool(spool: &Spool, records: Vec<SpoolRecord>) -> Result<()> {
+    match spool.push(&records) {
+        Ok(path) => {
+            warn!("Spooled {} records to {:?}", records.len(), path);
+            Ok(())
+        }
+        Err(e) => Err(anyhow!("Can't spool records: {}", e)),
+    }
+}
+
+/// Save `records` to DB. Records are spooled if DB is unavailable
+/// # Returns
+/// Stamp of `records` if every record is saved or rejected, or `None` if they are spooled
+fn save_or_spool(conn: &Conn, spool: &Spool, records: Vec<SpoolRecord>) -> Result<Option<StampId>> {
+    let mut sink = match DbSink::new(conn, StampSource::Scraper) {
+        Ok(sink) => sink,
+        Err(SinkError::Unavailable(e)) => {
+            warn!("Can't save records to DB: {}", e);
+            return push_spool(spool, records).map(|_| None);
+        }
+        Err(SinkError::Rejected(e)) => return Err(e),
+    };
+
+    match save_records(&mut sink, records) {
+        Ok(()) => Ok(sink.saved_stamp_id()),
+        Err(SaveError::Unavailable(remaining)) => push_spool(spool, remaining).map(|_| None),
+        Err(SaveError::StampRejected(e)) => Err(anyhow!("Can't add timestamp to local DB: {}", e)),
+    }
+}
+
+/// Log fetch failure `e`, unless it is caused by maintenance.
+/// # Returns
+/// `Err(e)` if NiceHash is in maintenance, since the following fetches will fail as well
+fn warn_unless_maintenance(message: &str, e: Error) -> Result<()> {
+    if is_service_unavailable(&e) {
+        Err(e)
+    } else {
+        warn!("{}: {}", message, e);
+        Ok(())
+    }
+}
+
+/// Log that the run is aborted by maintenance, then return `e` as is
+fn log_maintenance(e: Error) -> Error {
+    error!("NiceHash appears to be in maintenance. Abort scraping");
+    e
+}
+
+/// Default of `STAGE_SUCCESS_THRESHOLD`
+const DEFAULT_STAGE_SUCCESS_THRESHOLD: f64 = 0.9;
+
+/// Rows fetched by a scraping stage, kept in memory until every stage is fetched
+struct StageFetch {
+    name: &'static str,
+    records: Vec<SpoolRecord>,
+    /// Number of fetches, i.e. one per target market for orderbooks and myorders
+    attempted: usize,
+    succeeded: usize,
+}
+
+impl StageFetch {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            records: vec![],
+            attempted: 0,
+            succeeded: 0,
+        }
+    }
+
+    /// Add rows of a fetch. Failures, and fetches of many malformed entries, are only logged
+    /// # Returns
+    /// `Err(e)` if NiceHash is in maintenance
+    fn add<I: IntoIterator<Item = SpoolRecord>>(
+        &mut self,
+        fetched: Result<(I, FetchReport)>,
+    ) -> Result<()> {
+        self.attempted += 1;
+        match fetched {
+            Ok((records, report)) => {
+                if is_lossy(&report) {
+                    warn!(
+                        "{}: {} of {} entries are malformed and skipped",
+                        self.name, report.skipped, report.total
+                    );
+                } else if report.skipped > 0 {
+                    debug!(
+                        "{}: {} of {} entries are malformed and skipped",
+                        self.name, report.skipped, report.total
+                    );
+                }
+                self.succeeded += 1;
+                self.records.extend(records);
+                Ok(())
+            }
+            Err(e) => warn_unless_maintenance(&format!("Can't fetch {}", self.name), e),
+        }
+    }
+
+    /// Summary of this stage, whose records are persisted if `persisted` is `true`
+    fn summary(&self, persisted: bool) -> StageSummary {
+        StageSummary {
+            fetched: persisted && self.succeeded > 0,
+            rows: self.records.len(),
+            errors: self.attempted - self.succeeded,
+        }
+    }
+}
+
+/// Outcome of a scraping stage, recorded in `scrape_run`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct StageSummary {
+    /// `false` if the stage was disabled, failed or fell short of the success threshold
+    fetched: bool,
+    rows: usize,
+    /// Failed fetches
+    errors: usize,
+}
+
+/// Outcome of each stage of a run
+#[derive(Debug, Clone, Default, PartialEq)]
+struct ScrapeSummary {
+    currency: StageSummary,
+    balance: StageSummary,
+    price: StageSummary,
+    orderbook: StageSummary,
+    myorder: StageSummary,
+}
+
+impl ScrapeSummary {
+    fn to_scrape_run(
+        &self,
+        stamp_id: StampId,
+        started_at: NaiveDateTime,
+        finished_at: NaiveDateTime,
+    ) -> ScrapeRun {
+        ScrapeRun {
+            stamp_id,
+            started_at,
+            finished_at,
+            currency_fetched: self.currency.fetched,
+            currency_rows: self.currency.rows as i32,
+            currency_errors: self.currency.errors as i32,
+            balance_fetched: self.balance.fetched,
+            balance_rows: self.balance.rows as i32,
+            balance_errors: self.balance.errors as i32,
+            price_fetched: self.price.fetched,
+            price_rows: self.price.rows as i32,
+            price_errors: self.price.errors as i32,
+            orderbook_fetched: self.orderbook.fetched,
+            orderbook_rows: self.orderbook.rows as i32,
+            orderbook_errors: self.orderbook.errors as i32,
+            myorder_fetched: self.myorder.fetched,
+            myorder_rows: self.myorder.rows as i32,
+            myorder_errors: self.myorder.errors as i32,
+        }
+    }
+}
+
+/// Fetches skipping more than this ratio of entries are warned, since the response format may have changed
+const MAX_SKIPPED_RATIO: f64 = 0.1;
+
+fn is_lossy(report: &FetchReport) -> bool {
+    report.skipped_ratio() > MAX_SKIPPED_RATIO
+}
+
+/// `true` if the ratio of succeeded fetches reaches `threshold`. Stages without fetches always meet it
+fn meets_threshold(attempted: usize, succeeded: usize, threshold: f64) -> bool {
+    attempted == 0 || succeeded as f64 / attempted as f64 >= threshold
+}
+
+fn fetch_balance_stage(api_key: &ApiKey, known_symbols: &[String]) -> Result<StageFetch> {
+    let mut stage = StageFetch::new("balance");
+
+    if let Ok("1") = env::var("FETCH_BALANCE_FROM_REMOTE_SERVER").as_deref() {
+        nicehash::fetch_all_balances(api_key.clone())
+            .map(|(balances, report)| {
+                let records = balances
+                    .into_iter()
+                    .filter(|balance| known_symbols.contains(&balance.symbol))
+                    .map(|balance| SpoolRecord::Balance {
+                        symbol: balance.symbol,
+                        available: balance.available,
+                        pending: balance.pending,
+                    })
+                    .collect::<Vec<_>>();
+                (records, report)
+            })
+            .apply(|fetched| stage.add(fetched))?;
+    }
+
+    Ok(stage)
+}
+
+fn fetch_price_stage(known_symbols: &[String]) -> Result<StageFetch> {
+    let mut stage = StageFetch::new("price");
+
+    if let Ok("1") = env::var("FETCH_MARKET_AND_PRICE_FROM_REMOTE_SERVER").as_deref() {
+        nicehash::fetch_all_market_prices(known_symbols)
+            .map(|(market_prices, unknown_markets, report)| {
+                if !unknown_markets.is_empty() {
+                    warn!(
+                        "price: {} markets of unknown currencies are skipped: {}",
+                        unknown_markets.len(),
+                        unknown_markets.join(", ")
+                    );
+                }
+
+                let records = market_prices
+                    .into_iter()
+                    .map(|market_price| SpoolRecord::Price {
+                        base: market_price.base_symbol,
+                        quote: market_price.quote_symbol,
+                        amount: market_price.price,
+                    })
+                    .collect::<Vec<_>>();
+                (records, report)
+            })
+            .apply(|fetched| stage.add(fetched))?;
+    }
+
+    Ok(stage)
+}
+
+fn fetch_orderbook_stage(catalog: Option<&Catalog>) -> Result<StageFetch> {
+    let mut stage = StageFetch::new("orderbook");
+    // Rules only read levels near the best prices
+    let keep_top_levels = keep_top_levels_from_env().unwrap_or_else(|e| {
+        warn!("Can't load orderbook levels to keep: {}", e);
+        None
+    });
+
+    match get_target_markets_from_env("FETCH_ORDERBOOK_TARGET_MARKETS", catalog) {
+        Ok(markets) => match get_fetch_count_from_env("ORDERBOOK_FETCH_COUNT_PER_MARKET") {
+            Ok(0) => {}
+            Ok(fetch_count) => {
+                let max_levels = get_fetch_count_from_env("ORDERBOOK_MAX_LEVELS_PER_SIDE")
+                    .unwrap_or(nicehash::DEFAULT_MAX_ORDERBOOK_LEVELS);
+                let max_in_flight = get_fetch_count_from_env("ORDERBOOK_MAX_IN_FLIGHT")
+                    .unwrap_or(nicehash::DEFAULT_MAX_IN_FLIGHT);
+                let mut fetched = nicehash::fetch_orderbooks_many(
+                    &markets,
+                    fetch_count,
+                    max_levels,
+                    max_in_flight,
+                );
+                for (base, quote) in markets.into_iter() {
+                    let market_symbol = nicehash::get_market_symbol(&base, &quote);
+                    fetched
+                        .remove(&market_symbol)
+                        .unwrap_or_else(|| {
+                            Err(anyhow!("{} is listed more than once", market_symbol))
+                        })
+                        .map(|(orderbooks, report)| {
+                            let orderbooks = match keep_top_levels {
+                                Some(top_n) => nicehash::trim_orderbook(orderbooks, top_n),
+                                None => orderbooks,
+                            };
+                            let records = orderbooks
+                                .into_iter()
+                                .map(|orderbook| SpoolRecord::Orderbook {
+                                    base: base.clone(),
+                                    quote: quote.clone(),
+                                    side: orderbook.side,
+                                    price: orderbook.price,
+                                    volume: orderbook.volume,
+                                })
+                                .collect::<Vec<_>>();
+                            (records, report)
+                        })
+                        .apply(|fetched| stage.add(fetched))?;
+                }
+            }
+            Err(e) => warn!("Can't load orderbook-fetch count: {}", e),
+        },
+        Err(e) => warn!("Can't list orderbook-fetch target markets: {}", e),
+    }
+
+    Ok(stage)
+}
+
+fn fetch_myorder_stage(api_key: &ApiKey, catalog: Option<&Catalog>) -> Result<StageFetch> {
+    let mut stage = StageFetch::new("myorder");
+
+    match get_target_markets_from_env("FETCH_MYORDER_TARGET_MARKETS", catalog) {
+        Ok(markets) => match get_fetch_count_from_env("MYORDER_FETCH_COUNT_PER_MARKET") {
+            Ok(0) => {}
+            Ok(fetch_count) => {
+                for (base, quote) in markets.into_iter() {
+                    nicehash::fetch_myorders(&base, &quote, fetch_count, api_key.clone())
+                        .map(|(myorders, report)| {
+                            let records = myorders
+                                .into_iter()
+                                .map(|myorder| SpoolRecord::Myorder {
+                                    transaction_id: myorder.transaction_id,
+                                    base: base.clone(),
+                                    quote: quote.clone(),
+                                    price: myorder.price,
+                                    base_quantity: myorder.base_quantity,
+                                    quote_quantity: myorder.quote_quantity,
+                                    order_type: myorder.order_type,
+                                    side: myorder.side,
+                                    state: myorder.state,
+                                })
+                                .collect::<Vec<_>>();
+                            (records, report)
+                        })
+                        .apply(|fetched| stage.add(fetched))?;
+                }
+            }
+            Err(e) => warn!("Can't load myorder-fetch count: {}", e),
+        },
+        Err(e) => warn!("Can't list myorder-fetch target markets: {}", e),
+    }
+
+    Ok(stage)
+}
+
+/// Default of `MYORDER_SYNC_SINCE`, before NiceHash exchange opened
+const DEFAULT_MYORDER_SYNC_SINCE: &str = "2019-01-01T00:00:00";
+
+/// Settings of `sync_myorder_history`
+#[derive(Debug, Clone, PartialEq)]
+struct MyorderSyncSetting {
+    /// Beginning of the history of markets without a cursor
+    since: NaiveDateTime,
+    window: chrono::Duration,
+    /// Limit per market and run, so that a long history is synced over several runs
+    max_windows: usize,
+    page_size: usize,
+}
+
+fn myorder_sync_setting_from_env() -> Result<MyorderSyncSetting> {
+    let since = env::var("MYORDER_SYNC_SINCE")
+        .unwrap_or_else(|_| DEFAULT_MYORDER_SYNC_SINCE.into())
+        .apply(|s| NaiveDateTime::parse_from_str(&s, "%Y-%m-%dT%H:%M:%S"))?;
+    let window_days = match env::var("MYORDER_SYNC_WINDOW_DAYS") {
+        Ok(s) => i64::from_str(&s)?,
+        Err(_) => 7,
+    };
+    let max_windows = get_fetch_count_from_env("MYORDER_SYNC_MAX_WINDOWS").unwrap_or(10);
+    let page_size = get_fetch_count_from_env("MYORDER_SYNC_PAGE_SIZE").unwrap_or(100);
+
+    Ok(MyorderSyncSetting {
+        since,
+        window: chrono::Duration::days(window_days.max(1)),
+        max_windows,
+        page_size: page_size.max(1),
+    })
+}
+
+/// Markets of `FETCH_MYORDER_TARGET_MARKETS` known by DB.
+/// Markets of delisted currencies are included, so that their history is synced as well
+fn myorder_sync_markets(conn: &Conn) -> Result<Vec<(String, String, MarketId)>> {
+    let currencies = list_currencies(conn)?;
+    let markets = list_markets(conn)?;
+    let key = "FETCH_MYORDER_TARGET_MARKETS";
+
+    let (resolved, errors) = resolve_market_symbols(&env::var(key)?, &currencies, &markets);
+    for e in errors.iter() {
+        warn!("Ignore a market of {}: {}", key, e);
+    }
+
+    resolved
+        .into_iter()
+        .map(|(base, quote, market)| (base.symbol, quote.symbol, market.market_id))
+        .collect::<Vec<_>>()
+        .apply(Ok)
+}
+
+/// Sync order history of each market from its cursor, a window at a time.
+/// Orders of a window and the advanced cursor are saved in one transaction, so an interrupted sync resumes from the cursor.
+/// Synced orders are recorded at `stamp_id`.
+/// # Returns
+/// `Err(e)` if NiceHash is in maintenance. Failures of each market are only logged
+fn sync_myorder_history(
+    conn: &Conn,
+    api_key: &ApiKey,
+    stamp_id: StampId,
+    until: NaiveDateTime,
+) -> Result<()> {
+    let setting = match myorder_sync_setting_from_env() {
+        Ok(setting) if setting.max_windows == 0 => return Ok(()),
+        Ok(setting) => setting,
+        Err(e) => {
+            warn!("Can't load myorder sync setting: {}", e);
+            return Ok(());
+        }
+    };
+    let markets = match myorder_sync_markets(conn) {
+        Ok(markets) => markets,
+        Err(e) => {
+            warn!("Can't list myorder sync target markets: {}", e);
+            return Ok(());
+        }
+    };
+
+    for (base, quote, market_id) in markets.into_iter() {
+        let cursor = match get_sync_cursor(conn, market_id, SyncKind::Myorder) {
+            Ok(cursor) => cursor.map_or(setting.since, |c| c.synced_until),
+            Err(e) => {
+                warn!(
+                    "Can't load myorder sync cursor of {}-{}: {}",
+                    base, quote, e
+                );
+                continue;
+            }
+        };
+
+        let synced = myorder_sync::sync_windows(
+            cursor,
+            until,
+            setting.window,
+            setting.max_windows,
+            |from, to| {
+                nicehash::fetch_myorders_between(
+                    &base,
+                    &quote,
+                    from,
+                    to,
+                    setting.page_size,
+                    api_key.clone(),
+                )
+            },
+            |myorders, to| {
+                conn.transaction::<_, database::error::Error, _>(|| {
+                    for myorder in myorders.into_iter() {
+                        add_or_update_exchange_order(conn, myorder, market_id, stamp_id)?;
+                    }
+                    save_sync_cursor(
+                        conn,
+                        &SyncCursor {
+                            market_id,
+                            kind: SyncKind::Myorder,
+                            synced_until: to,
+                        },
+                    )
+                })
+                .map_err(Into::into)
+            },
+        );
+
+        match synced {
+            Ok(report) if report.windows > 0 => info!(
+                "Synced {} myorders of {}-{} in {} windows until {}",
+                report.orders, base, quote, report.windows, report.synced_until
+            ),
+            Ok(_) => {}
+            Err(e) => warn_unless_maintenance(
+                &format!("Can't sync myorder history of {}-{}", base, quote),
+                e,
+            )?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Restart syncing order history of `FETCH_MYORDER_TARGET_MARKETS` from `from`.
+/// Orders already saved are kept, and updated by the next sync
+pub fn resync_myorders_from(from: NaiveDateTime) -> Result<()> {
+    let conn = connect_db()?;
+
+    for (base, quote, market_id) in myorder_sync_markets(&conn)?.into_iter() {
+        save_sync_cursor(
+            &conn,
+            &SyncCursor {
+                market_id,
+                kind: SyncKind::Myorder,
+                synced_until: from,
+            },
+        )?;
+        info!(
+            "Reset myorder sync cursor of {}-{} to {}",
+            base, quote, from
+        );
+    }
+
+    Ok(())
+}
+
+/// Fetch data from nicehash. Every stage is fetched before any row is persisted.
+/// Rows of a stage are dropped if less than `threshold` of its fetches succeeded,
+/// so that a stamp never has a partial stage which can't be told from missing data.
+/// Fetch failures are only logged
+/// # Returns
+/// `Err(e)` if NiceHash is in maintenance
+fn scrape(
+    api_key: &ApiKey,
+    timestamp: NaiveDateTime,
+    known_symbols: &[String],
+    catalog: Option<&Catalog>,
+    threshold: f64,
+) -> Result<(Vec<SpoolRecord>, ScrapeSummary)> {
+    let stages = vec![
+        fetch_balance_stage(api_key, known_symbols)?,
+        fetch_price_stage(known_symbols)?,
+        fetch_orderbook_stage(catalog)?,
+        fetch_myorder_stage(api_key, catalog)?,
+    ];
+
+    let mut records = vec![SpoolRecord::Stamp { timestamp }];
+    let mut summaries = vec![];
+    for stage in stages.into_iter() {
+        let persisted = meets_threshold(stage.attempted, stage.succeeded, threshold);
+        summaries.push(stage.summary(persisted));
+        if persisted {
+            records.extend(stage.records);
+        } else {
+            warn!(
+                "Skip persisting {} stage: only {}/{} fetches succeeded (threshold {})",
+                stage.name, stage.succeeded, stage.attempted, threshold
+            );
+        }
+    }
+
+    let summary = ScrapeSummary {
+        currency: StageSummary::default(),
+        balance: summaries[0],
+        price: summaries[1],
+        orderbook: summaries[2],
+        myorder: summaries[3],
+    };
+
+    Ok((records, summary))
+}
+
+/// Check DB consistency and repair it.
+/// Stamps created closer than `MERGE_STAMP_WINDOW_SEC` (default 60) are merged into the older one.
+///
+/// If `dry_run` is `true`, only planned repairs are logged.
+pub fn check_db(dry_run: bool) -> Result<()> {
+    let conn = connect_db()?;
+
+    let window_sec = match env::var("MERGE_STAMP_WINDOW_SEC") {
+        Ok(s) => i64::from_str(&s)?,
+        Err(_) => 60,
+    };
+    let report = merge_adjacent_stamps(&conn, chrono::Duration::seconds(window_sec), dry_run)?;
+
+    for merge in report.merges.iter() {
+        info!(
+            "{} stamp {}({}) into {}({})",
+            if dry_run { "Plan to merge" } else { "Merged" },
+            merge.from.stamp_id,
+            merge.from.timestamp,
+            merge.into.stamp_id,
+            merge.into.timestamp
+        );
+    }
+    info!(
+        "Stamp merge: {} merges, {} rows replaced, {} rows moved",
+        report.merges.len(),
+        report.replaced_rows,
+        report.moved_rows
+    );
+
+    Ok(())
+}
+
+/// Scrape nicehash once and save the result to DB.
+/// Tables are created beforehand if `RUN_MIGRATIONS=1`, see `database::run_migrations`.
+/// Each stage is persisted only if `STAGE_SUCCESS_THRESHOLD` (default 0.9) of its fetches succeeded.
+/// While DB is unavailable, the result is spooled to `SPOOL_DIR` and replayed by later runs.
+/// Then order history is synced from each market's cursor, see `MYORDER_SYNC_*` variables.
+/// Outcome of each stage is recorded in `scrape_run`, so that consumers can tell partially scraped stamps.
+/// Finally old rows are pruned, see `PRUNE_*_OLDER_THAN_DAYS` variables.
+/// # Returns
+/// `Err(e)` if scraping can't continue. Failures of each item are only logged.
+pub fn run() -> Result<()> {
+    let api_key = {
+        let organization_id = env::var("NICEHASH_ORGANIZATION_ID");
+        let key = env::var("NICEHASH_API_KEY");
+        let secret_key = env::var("NICEHASH_API_SECRET_KEY");
+
+        match (organization_id, key, secret_key) {
+            (Ok(id), Ok(key), Ok(skey)) => ApiKey::new(id, key, skey),
+            _ => return Err(anyhow!("Can't load api key from environment variable")),
+        }
+    };
+
+    let now = chrono::Local::now();
+    info!(
+        "Nicehash scraper started at {} with {}",
+        now,
+        common::build_info()
+    );
+
+    let spool = spool_from_env()?;
+    let threshold = match env::var("STAGE_SUCCESS_THRESHOLD") {
+        Ok(s) => f64::from_str(&s)?,
+        Err(_) => DEFAULT_STAGE_SUCCESS_THRESHOLD,
+    };
+
+    let conn = match connect_db() {
+        Ok(conn) => Some(conn),
+        Err(e) => {
+            warn!(
+                "Can't connect database. Scraped data will be spooled: {}",
+                e
+            );
+            None
+        }
+    };
+
+    let mut currency_summary = StageSummary::default();
+    if let Some(conn) = conn.as_ref() {
+        // Bootstrap a fresh DB before anything is written
+        if let Ok("1") = env::var("RUN_MIGRATIONS").as_deref() {
+            database::run_migrations(conn)?;
+        }
+
+        // Spooled stamps must be added before the newer one
+        if let Err(e) = replay_spool(&spool, conn) {
+            warn!("{}", e);
+        }
+
+        // Fetch currency info between remote server
+        if let Ok("1") = env::var("FETCH_CURRENCY_FROM_REMOTE_SERVER").as_deref() {
+            match nicehash::fetch_all_currencies() {
+                Ok((currencies, report)) => {
+                    if is_lossy(&report) {
+                        warn!(
+                            "currency: {} of {} entries are malformed and skipped",
+                            report.skipped, report.total
+                        );
+                    }
+                    currency_summary.fetched = true;
+                    currency_summary.rows = currencies.len();
+
+                    for c in currencies.iter() {
+                        match add_currency(conn, c.symbol.clone(), c.name.clone()) {
+                            Ok(_) => info!("Add currency {}/{}", c.symbol, c.name),
+                            Err(database::error::Error::Logic(
+                                database::error::LogicError::DuplicatedCurrency,
+                            )) => {}
+                            Err(e) => {
+                                warn!("Can't add currency: {}", e)
+                            }
+                        }
+                    }
+
+                    let listed_symbols =
+                        currencies.into_iter().map(|c| c.symbol).collect::<Vec<_>>();
+                    match update_currency_activity(conn, &listed_symbols) {
+                        Ok(change) => {
+                            for c in change.deactivated.iter() {
+                                info!("Deactivate delisted currency {}/{}", c.symbol, c.name);
+                            }
+                            for c in change.reactivated.iter() {
+                                info!("Reactivate currency {}/{}", c.symbol, c.name);
+                            }
+                        }
+                        Err(e) => warn!("Can't update currency activity: {}", e),
+                    }
+                }
+                Err(e) => {
+                    currency_summary.errors += 1;
+                    warn_unless_maintenance("Can't fetch currencies", e).map_err(log_maintenance)?
+                }
+            }
+        }
+    }
+
+    // Load active currencies from local DB, or from remote server while DB is unavailable.
+    // Delisted symbols are excluded since they can mis-split market symbols
+    let catalog = match conn.as_ref().map(load_catalog) {
+        Some(Ok(catalog)) => Some(catalog),
+        Some(Err(e)) => {
+            warn!("Can't load currencies and markets: {}", e);
+            None
+        }
+        None => None,
+    };
+    let known_symbols = match catalog.as_ref() {
+        Some(catalog) => catalog.currencies.symbols(false),
+        None => match nicehash::fetch_all_currencies() {
+            Ok((currencies, _)) => currencies.into_iter().map(|c| c.symbol).collect::<Vec<_>>(),
+            Err(e) if is_service_unavailable(&e) => return Err(log_maintenance(e)),
+            Err(e) => return Err(anyhow!("Can't list currencies: {}", e)),
+        },
+    };
+
+    let (records, mut summary) = scrape(
+        &api_key,
+        now.naive_utc(),
+        &known_symbols,
+        catalog.as_ref(),
+        threshold,
+    )
+    .map_err(log_maintenance)?;
+    summary.currency = currency_summary;
+
+    match conn.as_ref() {
+        Some(conn) => {
+            let stamp_id = save_or_spool(conn, &spool, records)?;
+
+            // Older orders than the myorder stage fetches are synced into the stamp just saved
+            match get_latest_stamp(conn, false) {
+                Ok(Some(stamp)) => {
+                    sync_myorder_history(conn, &api_key, stamp.stamp_id, now.naive_utc())
+                        .map_err(log_maintenance)?
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Can't sync myorder history: {}", e),
+            }
+
+            // Runs of spooled records are not recorded, so their stamps are treated as complete
+            if let Some(stamp_id) = stamp_id {
+                let run = summary.to_scrape_run(
+                    stamp_id,
+                    now.naive_utc(),
+                    chrono::Utc::now().naive_utc(),
+                );
+                if let Err(e) = record_scrape_run(conn, &run) {
+                    warn!("Can't record scrape run: {}", e);
+                }
+            }
+
+            prune_old_rows(conn, now.naive_utc());
+        }
+        None => push_spool(&spool, records)?,
+    }
+
+    info!("Nicehash scraper finished at {}", chrono::Local::now());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meets_threshold() {
+        assert!(meets_threshold(10, 9, 0.9));
+        assert!(!meets_threshold(10, 8, 0.9));
+        assert!(meets_threshold(31, 28, 0.9));
+        assert!(!meets_threshold(31, 27, 0.9));
+        assert!(meets_threshold(1, 1, 0.9));
+        assert!(!meets_threshold(1, 0, 0.9));
+        // Nothing to fetch
+        assert!(meets_threshold(0, 0, 0.9));
+    }
+
+    #[test]
+    fn test_stage_fetch_add() {
+        let mut stage = StageFetch::new("price");
+        let price = SpoolRecord::Price {
+            base: "BTC".into(),
+            quote: "USDT".into(),
+            amount: 30000.0,
+        };
+
+        stage
+            .add(Ok((vec![price.clone()], FetchReport::default())))
+            .unwrap();
+        stage
+            .add::<Vec<_>>(Err(anyhow!("Connection reset")))
+            .unwrap();
+
+        assert_eq!(2, stage.attempted);
+        assert_eq!(1, stage.succeeded);
+        assert_eq!(vec![price], stage.records);
+    }
+
+    #[test]
+    fn test_stage_summary() {
+        let mut stage = StageFetch::new("price");
+        let price = |base: &str| SpoolRecord::Price {
+            base: base.into(),
+            quote: "USDT".into(),
+            amount: 100.0,
+        };
+        stage
+            .add(Ok((
+                vec![price("BTC"), price("ETH")],
+                FetchReport::default(),
+            )))
+            .unwrap();
+        stage
+            .add::<Vec<_>>(Err(anyhow!("Connection reset")))
+            .unwrap();
+
+        let summary = stage.summary(true);
+        assert!(summary.fetched);
+        assert_eq!(2, summary.rows);
+        assert_eq!(1, summary.errors);
+        // Rows are counted even if the stage falls short of the threshold
+        let summary = stage.summary(false);
+        assert!(!summary.fetched);
+        assert_eq!(2, summary.rows);
+
+        // Disabled stage
+        assert!(!StageFetch::new("price").summary(true).fetched);
+    }
+
+    #[test]
+    fn test_retention_days_from_env() {
+        let key = "TEST_NICEHASH_SCRAPER_RETENTION_DAYS";
+
+        env::remove_var(key);
+        assert_eq!(None, retention_days_from_env(key).unwrap());
+        env::set_var(key, "30");
+        assert_eq!(Some(30), retention_days_from_env(key).unwrap());
+        env::set_var(key, "0");
+        assert!(retention_days_from_env(key).is_err());
+        env::set_var(key, "a month");
+        assert!(retention_days_from_env(key).is_err());
+        env::remove_var(key);
+    }
+
+    #[test]
+    fn test_keep_top_levels_from_env() {
+        let key = "ORDERBOOK_KEEP_TOP_LEVELS";
+
+        env::remove_var(key);
+        assert_eq!(None, keep_top_levels_from_env().unwrap());
+        env::set_var(key, "20");
+        assert_eq!(Some(20), keep_top_levels_from_env().unwrap());
+        env::set_var(key, "0");
+        assert!(keep_top_levels_from_env().is_err());
+        env::remove_var(key);
+    }
+
+    #[test]
+    fn test_is_lossy() {
+        let report = |total, skipped| FetchReport { total, skipped };
+
+        assert!(!is_lossy(&report(0, 0)));
+        assert!(!is_lossy(&report(100, 10)));
+        assert!(is_lossy(&report(100, 11)));
+    }
+}
@@ -0,0 +1,289 @@
+//! Resumable sync of order history, one time window at a time.
+//!
+//! The cursor of a market is persisted in the same transaction as the orders of a window,
+//! so that an interrupted sync resumes from the first window not persisted.
+use anyhow::Result;
+use chrono::{Duration, NaiveDateTime};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyncReport {
+    /// Number of persisted windows
+    pub windows: usize,
+    pub orders: usize,
+    /// End of the last persisted window, i.e. the cursor to resume from
+    pub synced_until: NaiveDateTime,
+}
+
+/// Sync windows of `window` length from `cursor` until `until`, at most `max_windows` windows.
+/// Orders of each window [from, to) are fetched by `fetch(from, to)`,
+/// then persisted by `persist(orders, to)` which must save `to` as the cursor in the same transaction.
+///
+/// The cursor never advances past a window whose `fetch` or `persist` failed,
+/// so the window is fetched again on the next sync and no order is skipped.
+/// # Returns
+/// `Err(e)` of the first failure. Windows persisted before it are kept
+pub fn sync_windows<T, F, P>(
+    cursor: NaiveDateTime,
+    until: NaiveDateTime,
+    window: Duration,
+    max_windows: usize,
+    mut fetch: F,
+    mut persist: P,
+) -> Result<SyncReport>
+where
+    F: FnMut(NaiveDateTime, NaiveDateTime) -> Result<Vec<T>>,
+    P: FnMut(Vec<T>, NaiveDateTime) -> Result<()>,
+{
+    let mut report = SyncReport {
+        windows: 0,
+        orders: 0,
+        synced_until: cursor,
+    };
+
+    while report.synced_until < until && report.windows < max_windows {
+        let from = report.synced_until;
+        let to = (from + window).min(until);
+
+        let orders = fetch(from, to)?;
+        let count = orders.len();
+        persist(orders, to)?;
+
+        report.windows += 1;
+        report.orders += count;
+        report.synced_until = to;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    fn day(d: u32) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd(2021, 1, d).and_hms(0, 0, 0)
+    }
+
+    fn hour(d: u32, h: u32) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd(2021, 1, d).and_hms(h, 0, 0)
+    }
+
+    /// Orders on the exchange as (creation time, id)
+    fn history() -> Vec<(NaiveDateTime, u32)> {
+        vec![
+            (hour(1, 3), 1),
+            (hour(1, 23), 2),
+            // Exactly at a window boundary
+            (day(2), 3),
+            (hour(3, 12), 4),
+            (hour(4, 1), 5),
+            (hour(4, 2), 6),
+            (hour(6, 0), 7),
+        ]
+    }
+
+    fn fetch_from(
+        history: &[(NaiveDateTime, u32)],
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Vec<u32> {
+        history
+            .iter()
+            .filter(|(time, _)| from <= *time && *time < to)
+            .map(|(_, id)| *id)
+            .collect()
+    }
+
+    /// DB keeping orders and the cursor, committed together
+    #[derive(Default)]
+    struct Db {
+        orders: Vec<u32>,
+        cursor: Option<NaiveDateTime>,
+    }
+
+    impl Db {
+        fn commit(&mut self, orders: Vec<u32>, to: NaiveDateTime) {
+            for id in orders {
+                if !self.orders.contains(&id) {
+                    self.orders.push(id);
+                }
+            }
+            self.cursor = Some(to);
+        }
+    }
+
+    #[test]
+    fn test_sync_windows() {
+        let history = history();
+        let mut db = Db::default();
+
+        let report = sync_windows(
+            day(1),
+            day(7),
+            Duration::days(1),
+            100,
+            |from, to| Ok(fetch_from(&history, from, to)),
+            |orders, to| {
+                db.commit(orders, to);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(6, report.windows);
+        assert_eq!(7, report.orders);
+        assert_eq!(day(7), report.synced_until);
+        assert_eq!(vec![1, 2, 3, 4, 5, 6, 7], db.orders);
+        assert_eq!(Some(day(7)), db.cursor);
+    }
+
+    #[test]
+    fn test_sync_windows_last_window_is_clamped() {
+        let history = history();
+        let mut windows = vec![];
+
+        let report = sync_windows(
+            day(1),
+            hour(2, 12),
+            Duration::days(1),
+            100,
+            |from, to| {
+                windows.push((from, to));
+                Ok(fetch_from(&history, from, to))
+            },
+            |_, _| Ok(()),
+        )
+        .unwrap();
+
+        assert_eq!(vec![(day(1), day(2)), (day(2), hour(2, 12))], windows);
+        assert_eq!(hour(2, 12), report.synced_until);
+    }
+
+    #[test]
+    fn test_sync_windows_max_windows() {
+        let history = history();
+        let mut db = Db::default();
+
+        let report = sync_windows(
+            day(1),
+            day(7),
+            Duration::days(1),
+            2,
+            |from, to| Ok(fetch_from(&history, from, to)),
+            |orders, to| {
+                db.commit(orders, to);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(2, report.windows);
+        assert_eq!(day(3), report.synced_until);
+        assert_eq!(vec![1, 2, 3], db.orders);
+    }
+
+    #[test]
+    fn test_sync_windows_up_to_date() {
+        let report = sync_windows::<u32, _, _>(
+            day(7),
+            day(7),
+            Duration::days(1),
+            100,
+            |_, _| panic!("Nothing to fetch"),
+            |_, _| panic!("Nothing to persist"),
+        )
+        .unwrap();
+
+        assert_eq!(0, report.windows);
+        assert_eq!(day(7), report.synced_until);
+    }
+
+    #[test]
+    fn test_sync_windows_resumes_after_persist_failure() {
+        let history = history();
+        let mut db = Db::default();
+
+        // Crash while persisting the 3rd window. Its transaction is rolled back, so nothing of it is saved
+        let mut persist_count = 0;
+        let ret = sync_windows(
+            day(1),
+            day(7),
+            Duration::days(1),
+            100,
+            |from, to| Ok(fetch_from(&history, from, to)),
+            |orders, to| {
+                persist_count += 1;
+                if persist_count == 3 {
+                    return Err(anyhow!("Connection lost"));
+                }
+                db.commit(orders, to);
+                Ok(())
+            },
+        );
+        assert!(ret.is_err());
+        assert_eq!(Some(day(3)), db.cursor);
+        assert_eq!(vec![1, 2, 3], db.orders);
+
+        // Resume from the persisted cursor
+        let report = sync_windows(
+            db.cursor.unwrap(),
+            day(7),
+            Duration::days(1),
+            100,
+            |from, to| Ok(fetch_from(&history, from, to)),
+            |orders, to| {
+                db.commit(orders, to);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(4, report.windows);
+        assert_eq!(vec![1, 2, 3, 4, 5, 6, 7], db.orders);
+        assert_eq!(Some(day(7)), db.cursor);
+    }
+
+    #[test]
+    fn test_sync_windows_resumes_after_fetch_failure() {
+        let history = history();
+        let mut db = Db::default();
+
+        // Fetching the window of day 4 fails, e.g. by a lost page
+        let ret = sync_windows(
+            day(1),
+            day(7),
+            Duration::days(1),
+            100,
+            |from, to| {
+                if from == day(4) {
+                    Err(anyhow!("Timed out"))
+                } else {
+                    Ok(fetch_from(&history, from, to))
+                }
+            },
+            |orders, to| {
+                db.commit(orders, to);
+                Ok(())
+            },
+        );
+        assert!(ret.is_err());
+        assert_eq!(Some(day(4)), db.cursor);
+
+        sync_windows(
+            db.cursor.unwrap(),
+            day(7),
+            Duration::days(1),
+            100,
+            |from, to| Ok(fetch_from(&history, from, to)),
+            |orders, to| {
+                db.commit(orders, to);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        // Orders 5 and 6 of the failed window are not skipped
+        assert_eq!(vec![1, 2, 3, 4, 5, 6, 7], db.orders);
+    }
+}
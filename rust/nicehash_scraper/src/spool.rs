@@ -0,0 +1,868 @@
+use anyhow::{anyhow, bail, Error, Result};
+use chrono::NaiveDateTime;
+use database::custom_sql_type::{OrderSide, OrderState, OrderType};
+use database::model::Amount;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error as ThisError;
+
+/// A row to be saved to DB.
+/// Currencies and markets are referred by symbols, because their IDs can't be resolved while DB is down.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum SpoolRecord {
+    /// Following records belong to this stamp
+    Stamp { timestamp: NaiveDateTime },
+    /// Following records belong to the stamp of `timestamp`, which is already saved.
+    /// Spooled instead of `Stamp` when the stamp was saved but its later rows were not
+    ResumeStamp { timestamp: NaiveDateTime },
+    Balance {
+        symbol: String,
+        available: Amount,
+        pending: Amount,
+    },
+    Price {
+        base: String,
+        quote: String,
+        amount: Amount,
+    },
+    Orderbook {
+        base: String,
+        quote: String,
+        side: OrderSide,
+        price: Amount,
+        volume: Amount,
+    },
+    Myorder {
+        transaction_id: String,
+        base: String,
+        quote: String,
+        price: Amount,
+        base_quantity: Amount,
+        quote_quantity: Amount,
+        order_type: OrderType,
+        side: OrderSide,
+        state: OrderState,
+    },
+}
+
+impl SpoolRecord {
+    /// `true` if this is a stamp or a resume marker, which following rows belong to
+    fn is_stamp(&self) -> bool {
+        self.stamp_timestamp().is_some()
+    }
+
+    fn stamp_timestamp(&self) -> Option<NaiveDateTime> {
+        match self {
+            SpoolRecord::Stamp { timestamp } | SpoolRecord::ResumeStamp { timestamp } => {
+                Some(*timestamp)
+            }
+            _ => None,
+        }
+    }
+
+    /// Marker resuming the stamp of this record after it is saved
+    fn resumed(self) -> Self {
+        match self {
+            SpoolRecord::Stamp { timestamp } => SpoolRecord::ResumeStamp { timestamp },
+            record => record,
+        }
+    }
+
+    /// Name of the scraping stage producing this record
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SpoolRecord::Stamp { .. } => "stamp",
+            SpoolRecord::ResumeStamp { .. } => "resumeStamp",
+            SpoolRecord::Balance { .. } => "balance",
+            SpoolRecord::Price { .. } => "price",
+            SpoolRecord::Orderbook { .. } => "orderbook",
+            SpoolRecord::Myorder { .. } => "myorder",
+        }
+    }
+
+    /// Return (base, quote) if this is an orderbook row
+    fn orderbook_market(&self) -> Option<(&str, &str)> {
+        match self {
+            SpoolRecord::Orderbook { base, quote, .. } => Some((base, quote)),
+            _ => None,
+        }
+    }
+}
+
+/// Maximum number of orderbook rows saved at once
+pub const ORDERBOOK_CHUNK_SIZE: usize = 100;
+
+#[derive(Debug, ThisError)]
+pub enum SinkError {
+    /// DB can't be reached. The record and following ones should be spooled
+    #[error("DB unavailable: {0}")]
+    Unavailable(Error),
+    /// The record is invalid or conflicts with DB
+    #[error("{0}")]
+    Rejected(Error),
+}
+
+/// Failure of saving several rows
+#[derive(Debug)]
+pub struct RowsError {
+    pub error: SinkError,
+    /// Number of leading rows saved before the failure, which must not be saved again.
+    /// It is 0 if the sink rolled the rows back
+    pub saved: usize,
+}
+
+impl From<SinkError> for RowsError {
+    fn from(error: SinkError) -> Self {
+        Self { error, saved: 0 }
+    }
+}
+
+/// Destination of records, usually DB
+pub trait RecordSink {
+    fn save(&mut self, record: &SpoolRecord) -> Result<(), SinkError>;
+
+    /// Save orderbook rows of the same market and stamp.
+    /// Sinks should save all or none of them; the default implementation saves them one by one,
+    /// reporting the rows saved before a failure.
+    fn save_orderbooks(&mut self, records: &[SpoolRecord]) -> Result<(), RowsError> {
+        for (saved, record) in records.iter().enumerate() {
+            self.save(record)
+                .map_err(|error| RowsError { error, saved })?;
+        }
+        Ok(())
+    }
+
+    /// Save rows of a stage, i.e. consecutive rows of the same kind following a stamp.
+    /// Sinks should save all or none of them; the default implementation saves them by `save_rows`.
+    fn save_stage(&mut self, records: &[SpoolRecord]) -> Result<(), RowsError> {
+        save_rows(self, records)
+    }
+}
+
+/// Save rows in order. Consecutive orderbook rows of a market are saved in chunks of `ORDERBOOK_CHUNK_SIZE`.
+/// # Returns
+/// The first error with the number of rows saved before it. Following rows are not saved
+pub fn save_rows<S: RecordSink + ?Sized>(
+    sink: &mut S,
+    records: &[SpoolRecord],
+) -> Result<(), RowsError> {
+    let mut i = 0;
+    while i < records.len() {
+        match records[i].orderbook_market() {
+            Some(market) => {
+                let len = records[i..]
+                    .iter()
+                    .take(ORDERBOOK_CHUNK_SIZE)
+                    .take_while(|record| record.orderbook_market() == Some(market))
+                    .count();
+                sink.save_orderbooks(&records[i..i + len])
+                    .map_err(|e| RowsError {
+                        saved: i + e.saved,
+                        ..e
+                    })?;
+                i += len;
+            }
+            None => {
+                sink.save(&records[i])
+                    .map_err(|error| RowsError { error, saved: i })?;
+                i += 1;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    /// Records not saved yet, beginning with their stamp
+    Unavailable(Vec<SpoolRecord>),
+    /// Following rows can't be saved without their stamp
+    StampRejected(Error),
+}
+
+/// Save `records` to `sink` in order.
+/// Consecutive rows of the same kind form a stage, which is saved at once by `RecordSink::save_stage`,
+/// so that a stamp has either all or none of the stage's rows.
+/// Rejected stages are only logged.
+///
+/// If `sink` becomes unavailable after a stamp is saved, the remaining records begin with `ResumeStamp` of it,
+/// so that the stamp is not added again when they are replayed.
+/// Rows which `sink` saved before the failure are not included either.
+pub fn save_records<S: RecordSink>(
+    sink: &mut S,
+    records: Vec<SpoolRecord>,
+) -> Result<(), SaveError> {
+    let mut current_stamp: Option<SpoolRecord> = None;
+    let mut records = records.into_iter().peekable();
+
+    while let Some(record) = records.next() {
+        if record.is_stamp() {
+            match sink.save(&record) {
+                Ok(()) => current_stamp = Some(record.resumed()),
+                Err(SinkError::Unavailable(e)) => {
+                    warn!("DB became unavailable: {}", e);
+                    let remaining = std::iter::once(record).chain(records).collect();
+                    return Err(SaveError::Unavailable(remaining));
+                }
+                Err(SinkError::Rejected(e)) => return Err(SaveError::StampRejected(e)),
+            }
+            continue;
+        }
+
+        let mut stage = vec![record];
+        while let Some(next) = records.peek() {
+            if next.kind() != stage[0].kind() {
+                break;
+            }
+            stage.extend(records.next());
+        }
+
+        match sink.save_stage(&stage) {
+            Ok(()) => {}
+            Err(RowsError {
+                error: SinkError::Unavailable(e),
+                saved,
+            }) => {
+                warn!("DB became unavailable: {}", e);
+                let remaining = current_stamp
+                    .into_iter()
+                    .chain(stage.into_iter().skip(saved))
+                    .chain(records)
+                    .collect();
+                return Err(SaveError::Unavailable(remaining));
+            }
+            Err(RowsError {
+                error: SinkError::Rejected(e),
+                saved,
+            }) => warn!(
+                "Can't save {} rows of {} stage: {}",
+                stage.len() - saved,
+                stage[0].kind(),
+                e
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReplayReport {
+    pub replayed: usize,
+    pub failed: usize,
+    pub quarantined: usize,
+    /// `true` if DB became unavailable during replay
+    pub interrupted: bool,
+}
+
+/// Directory keeping records which couldn't be saved to DB, as JSON-lines files.
+///
+/// Each file is named by the timestamp of its first stamp, so files are replayed in timestamp order.
+/// Files failing replay `max_attempts` times are moved to `quarantine` subdirectory with the last error.
+#[derive(Debug, Clone)]
+pub struct Spool {
+    dir: PathBuf,
+    max_bytes: u64,
+    max_attempts: u32,
+}
+
+impl Spool {
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: u64, max_attempts: u32) -> Self {
+        Self {
+            dir: dir.into(),
+            max_bytes,
+            max_attempts,
+        }
+    }
+
+    pub fn quarantine_dir(&self) -> PathBuf {
+        self.dir.join("quarantine")
+    }
+
+    /// Write `records` to a new spool file.
+    /// # Returns
+    /// `Err(e)` if `records` does not begin with a stamp, or the spool would exceed its size limit
+    pub fn push(&self, records: &[SpoolRecord]) -> Result<PathBuf> {
+        let timestamp = match records.first().and_then(SpoolRecord::stamp_timestamp) {
+            Some(timestamp) => timestamp,
+            None => bail!("Spooled records must begin with a stamp"),
+        };
+
+        fs::create_dir_all(&self.dir)?;
+
+        let content = serialize_records(records)?;
+        let size = self.size()?;
+        if size + content.len() as u64 > self.max_bytes {
+            bail!(
+                "Spool {:?} is full ({} bytes). Drop {} records of {}",
+                self.dir,
+                size,
+                records.len(),
+                timestamp
+            );
+        }
+
+        let path = self
+            .dir
+            .join(format!("{}.jsonl", timestamp.format("%Y%m%dT%H%M%S%.6f")));
+        write_atomically(&path, &content)?;
+
+        Ok(path)
+    }
+
+    /// Save spooled records to `sink` in timestamp order.
+    /// Replay stops when `sink` becomes unavailable, keeping records not saved yet.
+    pub fn replay<S: RecordSink>(&self, sink: &mut S) -> Result<ReplayReport> {
+        let mut report = ReplayReport::default();
+
+        for path in self.spooled_files()?.into_iter() {
+            let records = match read_records(&path) {
+                Ok(records) => records,
+                Err(e) => {
+                    self.fail(&path, e, &mut report)?;
+                    continue;
+                }
+            };
+
+            match save_records(sink, records) {
+                Ok(()) => {
+                    fs::remove_file(&path)?;
+                    remove_if_exists(&attempts_path(&path))?;
+                    info!("Replayed spool {:?}", path);
+                    report.replayed += 1;
+                }
+                Err(SaveError::Unavailable(remaining)) => {
+                    write_atomically(&path, &serialize_records(&remaining)?)?;
+                    report.interrupted = true;
+                    break;
+                }
+                Err(SaveError::StampRejected(e)) => self.fail(&path, e, &mut report)?,
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Spooled files sorted by their timestamps
+    fn spooled_files(&self) -> Result<Vec<PathBuf>> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut paths = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().map_or(false, |e| e == "jsonl"))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        Ok(paths)
+    }
+
+    /// Total bytes of spooled files
+    fn size(&self) -> Result<u64> {
+        let mut size = 0;
+        for path in self.spooled_files()?.iter() {
+            size += fs::metadata(path)?.len();
+        }
+        Ok(size)
+    }
+
+    fn fail(&self, path: &Path, error: Error, report: &mut ReplayReport) -> Result<()> {
+        let attempts_path = attempts_path(path);
+        let attempts = fs::read_to_string(&attempts_path)
+            .ok()
+            .and_then(|s| s.trim().parse::<u32>().ok())
+            .unwrap_or(0)
+            + 1;
+
+        if attempts < self.max_attempts {
+            warn!(
+                "Can't replay spool {:?} ({} attempts): {}",
+                path, attempts, error
+            );
+            fs::write(&attempts_path, attempts.to_string())?;
+            report.failed += 1;
+            return Ok(());
+        }
+
+        let quarantine_dir = self.quarantine_dir();
+        fs::create_dir_all(&quarantine_dir)?;
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("Invalid spool path {:?}", path))?;
+        let quarantined = quarantine_dir.join(file_name);
+        fs::rename(path, &quarantined)?;
+        fs::write(
+            quarantined.with_extension("error"),
+            format!("attempts: {}\n{}\n", attempts, error),
+        )?;
+        remove_if_exists(&attempts_path)?;
+
+        error!(
+            "Quarantined spool {:?} after {} attempts: {}",
+            quarantined, attempts, error
+        );
+        report.quarantined += 1;
+
+        Ok(())
+    }
+}
+
+fn attempts_path(path: &Path) -> PathBuf {
+    path.with_extension("attempts")
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn serialize_records(records: &[SpoolRecord]) -> Result<String> {
+    let mut content = String::new();
+    for record in records.iter() {
+        content.push_str(&serde_json::to_string(record)?);
+        content.push('\n');
+    }
+    Ok(content)
+}
+
+fn read_records(path: &Path) -> Result<Vec<SpoolRecord>> {
+    let records = BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| serde_json::from_str(&line?).map_err(Error::from))
+        .collect::<Result<Vec<SpoolRecord>>>()?;
+
+    match records.first() {
+        Some(record) if record.is_stamp() => Ok(records),
+        _ => bail!("Spool {:?} does not begin with a stamp", path),
+    }
+}
+
+/// Write to a temporary file then rename it, so that a crash never leaves a half-written spool
+fn write_atomically(path: &Path, content: &str) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    /// In-memory DB which denies non-latest stamps like `add_stamp`
+    #[derive(Default)]
+    struct MemorySink {
+        stamps: Vec<NaiveDateTime>,
+        /// Stamp which following rows belong to
+        current: Option<NaiveDateTime>,
+        /// (timestamp of stamp, row)
+        rows: Vec<(NaiveDateTime, SpoolRecord)>,
+    }
+
+    impl RecordSink for MemorySink {
+        fn save(&mut self, record: &SpoolRecord) -> Result<(), SinkError> {
+            match record {
+                SpoolRecord::Stamp { timestamp } => match self.stamps.last() {
+                    Some(latest) if latest >= timestamp => {
+                        Err(SinkError::Rejected(anyhow!("Non latest stamp")))
+                    }
+                    _ => {
+                        self.stamps.push(*timestamp);
+                        self.current = Some(*timestamp);
+                        Ok(())
+                    }
+                },
+                SpoolRecord::ResumeStamp { timestamp } if self.stamps.contains(timestamp) => {
+                    self.current = Some(*timestamp);
+                    Ok(())
+                }
+                SpoolRecord::ResumeStamp { timestamp } => Err(SinkError::Rejected(anyhow!(
+                    "No stamp at {} to resume",
+                    timestamp
+                ))),
+                row => {
+                    self.rows.push((self.current.unwrap(), row.clone()));
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Connection which is lost after saving `available` records
+    struct FailingSink {
+        inner: MemorySink,
+        available: usize,
+    }
+
+    impl RecordSink for FailingSink {
+        fn save(&mut self, record: &SpoolRecord) -> Result<(), SinkError> {
+            if self.available == 0 {
+                return Err(SinkError::Unavailable(anyhow!(
+                    "MySQL server has gone away"
+                )));
+            }
+            self.available -= 1;
+            self.inner.save(record)
+        }
+    }
+
+    fn spool(name: &str, max_bytes: u64) -> Spool {
+        let dir = std::env::temp_dir().join(format!("asset_management_spool_test_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        Spool::new(dir, max_bytes, 2)
+    }
+
+    fn timestamp(hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2021, 1, 1).and_hms(hour, 0, 0)
+    }
+
+    fn records(hour: u32) -> Vec<SpoolRecord> {
+        vec![
+            SpoolRecord::Stamp {
+                timestamp: timestamp(hour),
+            },
+            SpoolRecord::Balance {
+                symbol: "BTC".into(),
+                available: 1.0,
+                pending: 0.0,
+            },
+            SpoolRecord::Price {
+                base: "BTC".into(),
+                quote: "USDT".into(),
+                amount: 30000.0,
+            },
+            SpoolRecord::Myorder {
+                transaction_id: "tx".into(),
+                base: "BTC".into(),
+                quote: "USDT".into(),
+                price: 30000.0,
+                base_quantity: 0.1,
+                quote_quantity: 3000.0,
+                order_type: OrderType::Limit,
+                side: OrderSide::Buy,
+                state: OrderState::Opened,
+            },
+        ]
+    }
+
+    fn orderbooks(count: usize) -> Vec<SpoolRecord> {
+        (0..count)
+            .map(|i| SpoolRecord::Orderbook {
+                base: "BTC".into(),
+                quote: "USDT".into(),
+                side: OrderSide::Buy,
+                price: 30000.0 - i as Amount,
+                volume: 1.0,
+            })
+            .collect()
+    }
+
+    /// Record sizes of orderbook chunks
+    #[derive(Default)]
+    struct ChunkSink {
+        inner: MemorySink,
+        chunks: Vec<usize>,
+    }
+
+    impl RecordSink for ChunkSink {
+        fn save(&mut self, record: &SpoolRecord) -> Result<(), SinkError> {
+            self.inner.save(record)
+        }
+
+        fn save_orderbooks(&mut self, records: &[SpoolRecord]) -> Result<(), RowsError> {
+            self.chunks.push(records.len());
+            records
+                .iter()
+                .try_for_each(|record| self.inner.save(record))
+                .map_err(RowsError::from)
+        }
+    }
+
+    #[test]
+    fn test_save_records_orderbook_chunks() {
+        let mut records = records(1);
+        records.extend(orderbooks(ORDERBOOK_CHUNK_SIZE * 2 + 1));
+        records.push(SpoolRecord::Price {
+            base: "ETH".into(),
+            quote: "USDT".into(),
+            amount: 2000.0,
+        });
+        records.extend(orderbooks(3));
+
+        let mut sink = ChunkSink::default();
+        save_records(&mut sink, records.clone()).unwrap();
+
+        assert_eq!(
+            vec![ORDERBOOK_CHUNK_SIZE, ORDERBOOK_CHUNK_SIZE, 1, 3],
+            sink.chunks
+        );
+        // Order of rows is kept
+        let rows = sink
+            .inner
+            .rows
+            .into_iter()
+            .map(|(_, row)| row)
+            .collect::<Vec<_>>();
+        assert_eq!(records[1..].to_vec(), rows);
+    }
+
+    #[test]
+    fn test_save_records_orderbook_chunk_unavailable() {
+        let mut records = records(1);
+        records.extend(orderbooks(3));
+
+        // Connection is lost in the middle of the chunk
+        let mut failing = FailingSink {
+            inner: MemorySink::default(),
+            available: 5,
+        };
+        let remaining = match save_records(&mut failing, records.clone()) {
+            Err(SaveError::Unavailable(remaining)) => remaining,
+            other => panic!("{:?}", other),
+        };
+
+        // Rows of the chunk not saved yet are spooled with the saved stamp to resume
+        assert_eq!(3, remaining.len());
+        assert_eq!(
+            SpoolRecord::ResumeStamp {
+                timestamp: timestamp(1)
+            },
+            remaining[0]
+        );
+        assert_eq!(records[5..].to_vec(), remaining[1..].to_vec());
+
+        // Replay completes the chunk without saving the first row again
+        failing.available = usize::MAX;
+        save_records(&mut failing, remaining).unwrap();
+        let rows = failing
+            .inner
+            .rows
+            .into_iter()
+            .map(|(_, row)| row)
+            .collect::<Vec<_>>();
+        assert_eq!(records[1..].to_vec(), rows);
+    }
+
+    /// Sink which saves stages all or nothing, rejecting balances of unknown currencies
+    #[derive(Default)]
+    struct TransactionalSink {
+        inner: MemorySink,
+        /// (kind, number of rows) of saved stages
+        stages: Vec<(&'static str, usize)>,
+    }
+
+    impl RecordSink for TransactionalSink {
+        fn save(&mut self, record: &SpoolRecord) -> Result<(), SinkError> {
+            match record {
+                SpoolRecord::Balance { symbol, .. } if symbol == "XYZ" => {
+                    Err(SinkError::Rejected(anyhow!("Unknown currency {}", symbol)))
+                }
+                record => self.inner.save(record),
+            }
+        }
+
+        fn save_stage(&mut self, records: &[SpoolRecord]) -> Result<(), RowsError> {
+            let saved = self.inner.rows.len();
+            match save_rows(self, records) {
+                Ok(()) => {
+                    self.stages.push((records[0].kind(), records.len()));
+                    Ok(())
+                }
+                // Rollback
+                Err(e) => {
+                    self.inner.rows.truncate(saved);
+                    Err(e.error.into())
+                }
+            }
+        }
+    }
+
+    fn balance(symbol: &str) -> SpoolRecord {
+        SpoolRecord::Balance {
+            symbol: symbol.into(),
+            available: 1.0,
+            pending: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_save_records_stage_all_or_nothing() {
+        let mut records = records(1);
+        // Balance stage including a rejected row
+        records.insert(2, balance("XYZ"));
+        records.insert(3, balance("ETH"));
+
+        let mut sink = TransactionalSink::default();
+        save_records(&mut sink, records.clone()).unwrap();
+
+        // Balance stage is rolled back, while the following stages are saved
+        let rows = sink
+            .inner
+            .rows
+            .into_iter()
+            .map(|(_, row)| row)
+            .collect::<Vec<_>>();
+        assert_eq!(records[4..].to_vec(), rows);
+    }
+
+    #[test]
+    fn test_save_records_stages() {
+        let mut records = records(1);
+        records.insert(2, balance("ETH"));
+
+        let mut sink = TransactionalSink::default();
+        save_records(&mut sink, records).unwrap();
+
+        assert_eq!(
+            vec![("balance", 2), ("price", 1), ("myorder", 1)],
+            sink.stages
+        );
+        assert_eq!(vec![timestamp(1)], sink.inner.stamps);
+    }
+
+    #[test]
+    fn test_spool_and_replay() {
+        let spool = spool("replay", 1 << 20);
+
+        // Connection is lost after the stamp and the balance are saved
+        let mut failing = FailingSink {
+            inner: MemorySink::default(),
+            available: 2,
+        };
+        let remaining = match save_records(&mut failing, records(1)) {
+            Err(SaveError::Unavailable(remaining)) => remaining,
+            other => panic!("{:?}", other),
+        };
+        // Remaining rows are spooled with the saved stamp to resume
+        assert_eq!(3, remaining.len());
+        assert_eq!(records(1)[0].clone().resumed(), remaining[0]);
+        spool.push(&remaining).unwrap();
+
+        // Whole records are spooled while DB is down
+        spool.push(&records(2)).unwrap();
+        assert_eq!(2, spool.spooled_files().unwrap().len());
+
+        // DB is back, keeping the saved stamp
+        failing.available = usize::MAX;
+        let report = spool.replay(&mut failing).unwrap();
+        let sink = failing.inner;
+
+        assert_eq!(2, report.replayed);
+        assert!(!report.interrupted);
+        assert!(spool.spooled_files().unwrap().is_empty());
+        // Rows land with their original timestamps, in timestamp order
+        assert_eq!(vec![timestamp(1), timestamp(2)], sink.stamps);
+        assert_eq!(6, sink.rows.len());
+        assert_eq!((timestamp(1), records(1)[1].clone()), sink.rows[0]);
+        assert_eq!((timestamp(1), records(1)[2].clone()), sink.rows[1]);
+        assert_eq!((timestamp(2), records(2)[1].clone()), sink.rows[3]);
+    }
+
+    #[test]
+    fn test_replay_interrupted() {
+        let spool = spool("interrupted", 1 << 20);
+        spool.push(&records(1)).unwrap();
+        spool.push(&records(2)).unwrap();
+
+        let mut failing = FailingSink {
+            inner: MemorySink::default(),
+            available: 2,
+        };
+        let report = spool.replay(&mut failing).unwrap();
+
+        assert!(report.interrupted);
+        assert_eq!(0, report.replayed);
+
+        // Saved stamp and rows are not replayed again
+        failing.available = usize::MAX;
+        let report = spool.replay(&mut failing).unwrap();
+        assert_eq!(2, report.replayed);
+        assert_eq!(0, report.failed);
+        assert_eq!(vec![timestamp(1), timestamp(2)], failing.inner.stamps);
+        assert_eq!(3 + 3, failing.inner.rows.len());
+    }
+
+    #[test]
+    fn test_replay_quarantine() {
+        let spool = spool("quarantine", 1 << 20);
+        let path = spool.push(&records(1)).unwrap();
+
+        // Stamp is rejected because DB already has a newer one
+        let mut sink = MemorySink {
+            stamps: vec![timestamp(5)],
+            ..Default::default()
+        };
+
+        let report = spool.replay(&mut sink).unwrap();
+        assert_eq!(1, report.failed);
+        assert!(path.exists());
+
+        let report = spool.replay(&mut sink).unwrap();
+        assert_eq!(1, report.quarantined);
+        assert!(!path.exists());
+        assert!(spool.spooled_files().unwrap().is_empty());
+
+        let quarantined = spool.quarantine_dir().join(path.file_name().unwrap());
+        assert!(quarantined.exists());
+        let error = fs::read_to_string(quarantined.with_extension("error")).unwrap();
+        assert!(error.contains("Non latest stamp"));
+        assert!(sink.rows.is_empty());
+    }
+
+    #[test]
+    fn test_replay_broken_file() {
+        let spool = spool("broken", 1 << 20);
+        fs::create_dir_all(&spool.dir).unwrap();
+        fs::write(spool.dir.join("20210101T000000.000000.jsonl"), "{broken").unwrap();
+
+        let mut sink = MemorySink::default();
+        assert_eq!(1, spool.replay(&mut sink).unwrap().failed);
+        assert_eq!(1, spool.replay(&mut sink).unwrap().quarantined);
+    }
+
+    #[test]
+    fn test_push_size_limit() {
+        let size = serialize_records(&records(1)).unwrap().len() as u64;
+        let spool = spool("size_limit", size * 2);
+
+        assert!(spool.push(&records(1)).is_ok());
+        assert!(spool.push(&records(2)).is_ok());
+        // Full spool rejects newer records instead of dropping older ones
+        assert!(spool.push(&records(3)).is_err());
+        assert_eq!(2, spool.spooled_files().unwrap().len());
+    }
+
+    #[test]
+    fn test_push_without_stamp() {
+        let spool = spool("without_stamp", 1 << 20);
+        assert!(spool.push(&records(1)[1..]).is_err());
+    }
+
+    #[test]
+    fn test_replay_resume_unknown_stamp() {
+        let spool = spool("resume_unknown", 1 << 20);
+        let mut records = records(1);
+        records[0] = records[0].clone().resumed();
+        spool.push(&records).unwrap();
+
+        // Rows are not saved to another stamp
+        let mut sink = MemorySink {
+            stamps: vec![timestamp(0)],
+            ..Default::default()
+        };
+        assert_eq!(1, spool.replay(&mut sink).unwrap().failed);
+        assert!(sink.rows.is_empty());
+    }
+}
@@ -0,0 +1,604 @@
+use crate::{construct_exchange_graph, construct_speculators, group_by, MarketFees, MarketSetting};
+use anyhow::{anyhow, Result};
+use apply::Apply;
+use database::exchange::ExchangeGraph;
+use database::logic::*;
+use database::model::*;
+use database::schema;
+use diesel::dsl::max;
+use diesel::prelude::*;
+use serde::Serialize;
+use speculator::execution;
+use speculator::ledger::{self, OrderFunds};
+use speculator::rule::MarketState;
+use speculator::trade::{Holdings, TradeAggregation};
+use std::collections::{BTreeMap, HashMap};
+
+/// Limit order waiting for its settlement at the next price of the market
+#[derive(Debug, Clone, PartialEq)]
+struct OpenOrder {
+    side: OrderSide,
+    price: Amount,
+    funds: OrderFunds,
+}
+
+/// Simulation replaying market states in memory.
+/// Markets are processed in the order of their ids, so the same inputs always give the same result
+pub struct Backtest {
+    aggregations: BTreeMap<MarketId, TradeAggregation>,
+    fees: MarketFees,
+    balances: HashMap<CurrencyId, Balance>,
+    open_orders: BTreeMap<MarketId, Vec<OpenOrder>>,
+    /// Balance changes by filled orders of each market
+    market_diffs: BTreeMap<MarketId, HashMap<CurrencyId, f64>>,
+    /// Currencies of the markets, resolving the reference currency of fixed quote sizing
+    currencies: CurrencyCollection,
+    /// Latest replayed price of each market
+    prices: BTreeMap<MarketId, Amount>,
+}
+
+impl Backtest {
+    pub fn new(
+        aggregations: HashMap<MarketId, TradeAggregation>,
+        fees: MarketFees,
+        balances: HashMap<CurrencyId, Balance>,
+    ) -> Self {
+        let currencies = aggregations
+            .values()
+            .flat_map(|a| vec![a.market_info().base.clone(), a.market_info().quote.clone()])
+            .map(|c| (c.currency_id, c))
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .map(|(_, c)| c)
+            .collect::<Vec<_>>()
+            .apply(CurrencyCollection::new);
+
+        Self {
+            aggregations: aggregations.into_iter().collect(),
+            fees,
+            balances,
+            open_orders: BTreeMap::new(),
+            market_diffs: BTreeMap::new(),
+            currencies,
+            prices: BTreeMap::new(),
+        }
+    }
+
+    pub fn balances(&self) -> &HashMap<CurrencyId, Balance> {
+        &self.balances
+    }
+
+    /// Feed market states to rules without trading, so that their indicators are ready at the beginning of the backtest
+    pub fn warm_up(&mut self, market_states: HashMap<MarketId, MarketState>) {
+        for (market_id, market_state) in market_states.into_iter() {
+            if let Some(aggregation) = self.aggregations.get_mut(&market_id) {
+                if let Err(errors) = aggregation.update_market_state(market_state) {
+                    for e in errors.into_iter() {
+                        debug!("{}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Replay market states of a stamp.
+    /// Limit orders placed on the previous step are settled at the new price, then new orders are placed
+    pub fn step(&mut self, mut market_states: HashMap<MarketId, MarketState>) {
+        for (market_id, market_state) in market_states.iter() {
+            self.prices.insert(*market_id, market_state.price.amount);
+        }
+
+        let market_ids = self.aggregations.keys().copied().collect::<Vec<_>>();
+        for market_id in market_ids.into_iter() {
+            if let Some(market_state) = market_states.remove(&market_id) {
+                self.step_market(market_id, market_state);
+            }
+        }
+    }
+
+    fn step_market(&mut self, market_id: MarketId, market_state: MarketState) {
+        let price = market_state.price.amount;
+        self.settle(market_id, price);
+
+        let aggregation = match self.aggregations.get_mut(&market_id) {
+            Some(aggregation) => aggregation,
+            None => return,
+        };
+        if let Err(errors) = aggregation.update_market_state(market_state) {
+            for e in errors.into_iter() {
+                debug!("{}", e);
+            }
+        }
+
+        let market_info = aggregation.market_info().clone();
+        let market = &market_info.market;
+        let holdings = match (
+            self.balances.get(&market.base_id),
+            self.balances.get(&market.quote_id),
+        ) {
+            (Some(base), Some(quote)) => match Holdings::from_balances(base, quote, market) {
+                Ok(holdings) => holdings,
+                Err(e) => {
+                    warn!("{}", e);
+                    return;
+                }
+            },
+            _ => return,
+        };
+
+        let fees = self.fees.fee_schedule(market);
+        let exchange_graph = self.exchange_graph();
+        let orders = self.aggregations[&market_id]
+            .recommend()
+            .with_exchange_graph(&exchange_graph, &self.currencies)
+            .recommend_orders(&holdings, &fees);
+        for order in orders.iter() {
+            if order.is_stop() || order.base_quantity <= 0.0 {
+                continue;
+            }
+
+            let funds = OrderFunds::from_order(market, order);
+            if execution::placed_state(order.order_type) == OrderState::Opened {
+                if let Err(e) = ledger::open(&mut self.balances, &funds) {
+                    debug!("Skip {:?}: {}", order, e);
+                    continue;
+                }
+                let open_order = OpenOrder {
+                    side: order.side,
+                    price: order.price,
+                    funds,
+                };
+                self.open_orders
+                    .entry(market_id)
+                    .or_insert_with(Vec::new)
+                    .push(open_order);
+            } else {
+                if let Err(e) = ledger::fill(&mut self.balances, &funds) {
+                    debug!("Skip {:?}: {}", order, e);
+                    continue;
+                }
+                add_market_diff(&mut self.market_diffs, market_id, &funds);
+            }
+        }
+    }
+
+    /// Exchange graph of the latest replayed prices
+    fn exchange_graph(&self) -> ExchangeGraph<CurrencyId> {
+        self.prices
+            .iter()
+            .filter(|(_, price)| price.is_finite() && **price > 0.0)
+            .filter_map(|(market_id, price)| {
+                let market = &self.aggregations.get(market_id)?.market_info().market;
+                Some((market.base_id, market.quote_id, *price))
+            })
+            .apply(ExchangeGraph::from_rates)
+    }
+
+    /// Fill or cancel open orders of a market at `price`
+    fn settle(&mut self, market_id: MarketId, price: Amount) {
+        let open_orders = self.open_orders.remove(&market_id).unwrap_or_default();
+        for open_order in open_orders.into_iter() {
+            match execution::settle_limit_order(open_order.side, open_order.price, price) {
+                OrderState::Filled => {
+                    ledger::fill_opened(&mut self.balances, &open_order.funds);
+                    add_market_diff(&mut self.market_diffs, market_id, &open_order.funds);
+                }
+                _ => ledger::cancel_opened(&mut self.balances, &open_order.funds),
+            }
+        }
+    }
+
+    /// Value trades in `fiat_id` at rates of `graph`.
+    /// Profit is the final value compared with holding `initial_balances` without trading
+    pub fn report(
+        &self,
+        initial_balances: &HashMap<CurrencyId, Balance>,
+        graph: &ExchangeGraph<CurrencyId>,
+        fiat_id: CurrencyId,
+    ) -> BacktestReport {
+        let markets = self
+            .aggregations
+            .iter()
+            .map(|(market_id, aggregation)| {
+                let pnl = self.market_diffs.get(market_id).map_or(0.0, |diffs| {
+                    value_of(diffs.iter().map(|(&c, &a)| (c, a)), graph, fiat_id)
+                });
+                MarketProfit {
+                    market: aggregation.market_label(),
+                    pnl,
+                }
+            })
+            .collect();
+
+        let initial_value = value_of(total_amounts(initial_balances), graph, fiat_id);
+        let final_value = value_of(total_amounts(&self.balances), graph, fiat_id);
+
+        BacktestReport {
+            markets,
+            initial_value,
+            final_value,
+            total_pnl: final_value - initial_value,
+        }
+    }
+}
+
+fn add_market_diff(
+    market_diffs: &mut BTreeMap<MarketId, HashMap<CurrencyId, f64>>,
+    market_id: MarketId,
+    funds: &OrderFunds,
+) {
+    let diffs = market_diffs.entry(market_id).or_insert_with(HashMap::new);
+    let (spent_id, spent) = funds.spent;
+    let (received_id, received) = funds.received;
+    *diffs.entry(spent_id).or_default() -= spent;
+    *diffs.entry(received_id).or_default() += received;
+}
+
+/// Available and pending amounts of each currency
+fn total_amounts(
+    balances: &HashMap<CurrencyId, Balance>,
+) -> impl Iterator<Item = (CurrencyId, f64)> + '_ {
+    balances
+        .iter()
+        .map(|(&currency_id, b)| (currency_id, b.available + b.pending))
+}
+
+/// Sum of `amounts` valued in `fiat_id`. Currencies which can't be valued are ignored with warning
+fn value_of(
+    amounts: impl Iterator<Item = (CurrencyId, f64)>,
+    graph: &ExchangeGraph<CurrencyId>,
+    fiat_id: CurrencyId,
+) -> f64 {
+    // Summed in the order of currency ids, so that rounding is the same on every run
+    amounts
+        .filter(|&(_, amount)| amount != 0.0)
+        .collect::<BTreeMap<_, _>>()
+        .into_iter()
+        .filter_map(|(currency_id, amount)| {
+            let rate = graph.rate_between(currency_id, fiat_id);
+            if rate.is_none() {
+                warn!("Currency {} can't be valued", currency_id);
+            }
+            rate.map(|rate| amount * rate)
+        })
+        .sum()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketProfit {
+    pub market: String,
+    pub pnl: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BacktestReport {
+    /// Profit of filled orders of each market, valued at the final prices
+    pub markets: Vec<MarketProfit>,
+    /// Value of the initial balances at the final prices
+    pub initial_value: f64,
+    pub final_value: f64,
+    pub total_pnl: f64,
+}
+
+impl BacktestReport {
+    fn format(&self, fiat: &str) -> String {
+        let mut lines = self
+            .markets
+            .iter()
+            .map(|m| format!("{:<16} {:>16.4} {}", m.market, m.pnl, fiat))
+            .collect::<Vec<_>>();
+        lines.push(format!(
+            "{:<16} {:>16.4} {}",
+            "initial value", self.initial_value, fiat
+        ));
+        lines.push(format!(
+            "{:<16} {:>16.4} {}",
+            "final value", self.final_value, fiat
+        ));
+        lines.push(format!("{:<16} {:>16.4} {}", "total", self.total_pnl, fiat));
+        lines.join("\n")
+    }
+}
+
+/// Market states of each stamp, in the order of `stamps`.
+/// Myorders are not loaded, since orders of the backtest are not the ones recorded in DB
+fn load_stamp_market_states(
+    conn: &Conn,
+    stamps: &[Stamp],
+) -> Result<Vec<HashMap<MarketId, MarketState>>> {
+    let (oldest_stamp_id, newest_stamp_id) = match (
+        stamps.iter().map(|s| s.stamp_id).min(),
+        stamps.iter().map(|s| s.stamp_id).max(),
+    ) {
+        (Some(oldest), Some(newest)) => (oldest, newest),
+        _ => return Ok(vec![]),
+    };
+
+    let price_group = schema::price::table
+        .filter(schema::price::stamp_id.ge(oldest_stamp_id))
+        .filter(schema::price::stamp_id.le(newest_stamp_id))
+        .load::<Price>(conn)?
+        .apply(|prices| group_by(prices, |p| p.stamp_id));
+    let orderbook_group = schema::orderbook::table
+        .filter(schema::orderbook::stamp_id.ge(oldest_stamp_id))
+        .filter(schema::orderbook::stamp_id.le(newest_stamp_id))
+        .load::<Orderbook>(conn)?
+        .apply(|orderbooks| group_by(orderbooks, |o| (o.market_id, o.stamp_id)));
+
+    stamps
+        .iter()
+        .map(|stamp| {
+            price_group
+                .get(&stamp.stamp_id)
+                .into_iter()
+                .flatten()
+                .map(|price| {
+                    let orderbooks = orderbook_group
+                        .get(&(price.market_id, stamp.stamp_id))
+                        .cloned()
+                        .unwrap_or_default();
+                    let market_state =
+                        MarketState::new(stamp.clone(), price.clone(), orderbooks, vec![]);
+                    (price.market_id, market_state)
+                })
+                .collect()
+        })
+        .collect::<Vec<_>>()
+        .apply(Ok)
+}
+
+/// Balances of the main DB at the latest stamp not after `timestamp`.
+/// Currencies without balance are regarded as 0
+fn load_initial_balances(
+    conn: &Conn,
+    currency_collection: &CurrencyCollection,
+    timestamp: NaiveDateTime,
+) -> Result<HashMap<CurrencyId, Balance>> {
+    let stamp_id = schema::balance::table
+        .inner_join(schema::stamp::table.on(schema::balance::stamp_id.eq(schema::stamp::stamp_id)))
+        .filter(schema::stamp::timestamp.le(timestamp))
+        .select(max(schema::balance::stamp_id))
+        .first::<Option<StampId>>(conn)?
+        .ok_or(anyhow!("No balance exists until {}", timestamp))?;
+    let balances = schema::balance::table
+        .filter(schema::balance::stamp_id.eq(stamp_id))
+        .load::<Balance>(conn)?
+        .into_iter()
+        .map(|b| (b.currency_id, b))
+        .collect::<HashMap<_, _>>();
+
+    currency_collection
+        .currencies()
+        .iter()
+        .map(|c| {
+            let balance = balances.get(&c.currency_id).cloned().unwrap_or_else(|| {
+                Balance::new(BalanceId::new(0), c.currency_id, stamp_id, 0.0, 0.0)
+            });
+            (c.currency_id, balance)
+        })
+        .collect::<HashMap<_, _>>()
+        .apply(Ok)
+}
+
+/// Replay stored stamps between `since` and `until` through the configured rules, trading on balances of the main DB at `since`,
+/// then report profit valued in `fiat`.
+///
+/// Stamps before `since` warm up rules by their duration requirement. Nothing is written to databases.
+/// Stop orders and allocation caps are not simulated.
+pub fn backtest(since: NaiveDateTime, until: NaiveDateTime, fiat: &str, json: bool) -> Result<()> {
+    let url = std::env::var("DATABASE_URL")?;
+    let conn = Conn::establish(&url)?;
+
+    let currency_collection = list_currencies(&conn)?;
+    let market_collection = list_markets(&conn)?;
+    let aggregations = construct_speculators(&currency_collection, &market_collection)?;
+    let fiat_id = currency_collection
+        .by_symbol_including_inactive(fiat)
+        .ok_or(anyhow!("Unknown fiat {}", fiat))?
+        .currency_id;
+
+    let market_setting = std::env::var("MARKET_JSON")?
+        .apply(std::fs::File::open)?
+        .apply(MarketSetting::from_reader)?;
+    let (fees, fee_errors) = market_setting.market_fees(&currency_collection, &market_collection);
+    for e in fee_errors.into_iter() {
+        warn!("Fee override is ignored: {}", e);
+    }
+
+    let warmup_since = aggregations
+        .values()
+        .flat_map(|a| a.duration_requirement())
+        .max()
+        .map_or(since, |d| since - d);
+    let stamps = schema::stamp::table
+        .filter(schema::stamp::timestamp.ge(warmup_since))
+        .filter(schema::stamp::timestamp.le(until))
+        .order_by((
+            schema::stamp::timestamp.asc(),
+            schema::stamp::stamp_id.asc(),
+        ))
+        .load::<Stamp>(&conn)?;
+    let last_stamp =
+        stamps
+            .last()
+            .cloned()
+            .ok_or(anyhow!("No stamp between {} and {}", since, until))?;
+    let market_states = load_stamp_market_states(&conn, &stamps)?;
+
+    let initial_balances = load_initial_balances(&conn, &currency_collection, since)?;
+    let mut backtest = Backtest::new(aggregations, fees, initial_balances.clone());
+
+    info!(
+        "Backtest {} stamps from {} to {}",
+        stamps.len(),
+        since,
+        until
+    );
+
+    for (stamp, states) in stamps.iter().zip(market_states.into_iter()) {
+        if stamp.timestamp < since {
+            backtest.warm_up(states);
+        } else {
+            backtest.step(states);
+        }
+    }
+
+    let graph = construct_exchange_graph(&conn, last_stamp.stamp_id)?;
+    let report = backtest.report(&initial_balances, &graph, fiat_id);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("{}", report.format(fiat));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use speculator::rule::RuleParameter;
+    use speculator::trade::{MarketInfo, TradeParameter, WeightedRule};
+
+    const DOGE: CurrencyId = CurrencyId::new(1);
+    const USDT: CurrencyId = CurrencyId::new(2);
+    const DOGE_USDT: MarketId = MarketId::new(1);
+
+    fn market_info() -> MarketInfo {
+        let doge = Currency::new(DOGE, "DOGE".into(), "Dogecoin".into());
+        let usdt = Currency::new(USDT, "USDT".into(), "Tether".into());
+        MarketInfo::new(Market::new(DOGE_USDT, DOGE, USDT), doge, usdt)
+    }
+
+    /// Backtest always buying DOGE with all of USDT 1000, without fee
+    fn backtest(market_ratio: f64, buy_limit_diff_ratio: f64) -> Backtest {
+        let market_info = market_info();
+        let rule = serde_json::from_str::<Box<dyn RuleParameter>>(
+            r#"{ "algorithm": "fixed", "side": "Buy" }"#,
+        )
+        .unwrap()
+        .create_rule(market_info.market.clone());
+        let trade_parameter = TradeParameter::new(
+            0.5,
+            0.5,
+            1.0,
+            1.0,
+            market_ratio,
+            1.0 - market_ratio,
+            1.0,
+            1.0,
+            buy_limit_diff_ratio,
+            1.0,
+        )
+        .unwrap();
+        let aggregation = TradeAggregation::new(
+            market_info,
+            trade_parameter,
+            vec![WeightedRule::new(rule, 1.0).unwrap()],
+        );
+        let aggregations = vec![(DOGE_USDT, aggregation)].into_iter().collect();
+
+        let currencies = CurrencyCollection::new(vec![]);
+        let markets = MarketCollection::new(vec![]);
+        let (fees, _) = MarketSetting::from_reader(r#"{ "feeRatio": 0.0 }"#.as_bytes())
+            .unwrap()
+            .market_fees(&currencies, &markets);
+
+        Backtest::new(aggregations, fees, initial_balances())
+    }
+
+    fn initial_balances() -> HashMap<CurrencyId, Balance> {
+        vec![(DOGE, 0.0), (USDT, 1000.0)]
+            .into_iter()
+            .map(|(currency_id, available)| {
+                let balance = Balance::new(
+                    BalanceId::new(0),
+                    currency_id,
+                    StampId::new(0),
+                    available,
+                    0.0,
+                );
+                (currency_id, balance)
+            })
+            .collect()
+    }
+
+    /// Replay hourly `prices` of DOGE-USDT
+    fn replay(backtest: &mut Backtest, prices: &[Amount]) {
+        for (hour, &amount) in prices.iter().enumerate() {
+            let stamp_id = StampId::new(hour as i32);
+            let timestamp = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(hour as u32, 0, 0);
+            let stamp = Stamp::new(stamp_id, timestamp);
+            let price = Price::new(PriceId::new(hour as i32), DOGE_USDT, stamp_id, amount);
+            let states = vec![(DOGE_USDT, MarketState::new(stamp, price, vec![], vec![]))]
+                .into_iter()
+                .collect();
+            backtest.step(states);
+        }
+    }
+
+    fn graph(price: f64) -> ExchangeGraph<CurrencyId> {
+        ExchangeGraph::from_rates(vec![(DOGE, USDT, price)])
+    }
+
+    #[test]
+    fn test_market_orders() {
+        let mut backtest = backtest(1.0, 1.0);
+        replay(&mut backtest, &[100.0, 110.0, 120.0]);
+
+        // All of USDT is spent at the first price
+        let balances = backtest.balances();
+        assert_eq!(10.0, balances[&DOGE].available);
+        assert_eq!(0.0, balances[&USDT].available);
+        assert_eq!(0.0, balances[&USDT].pending);
+
+        let report = backtest.report(&initial_balances(), &graph(120.0), USDT);
+        assert_eq!(1000.0, report.initial_value);
+        assert_eq!(1200.0, report.final_value);
+        assert_eq!(200.0, report.total_pnl);
+        assert_eq!(
+            vec![MarketProfit {
+                market: "DOGE-USDT".into(),
+                pnl: 200.0
+            }],
+            report.markets
+        );
+    }
+
+    #[test]
+    fn test_limit_orders() {
+        let mut backtest = backtest(0.0, 0.8);
+
+        // Limit buy at 80 is placed, reserving all of USDT
+        replay(&mut backtest, &[100.0]);
+        assert_eq!(1000.0, backtest.balances()[&USDT].pending);
+        assert_eq!(0.0, backtest.balances()[&USDT].available);
+
+        // Not filled at 90, so cancelled. Then limit buy at 72 is placed
+        replay(&mut backtest, &[90.0]);
+        assert_eq!(0.0, backtest.balances()[&DOGE].available);
+        assert_eq!(1000.0, backtest.balances()[&USDT].pending);
+
+        // Filled at 70
+        replay(&mut backtest, &[70.0]);
+        let balances = backtest.balances();
+        assert!((1000.0 / 72.0 - balances[&DOGE].available).abs() < 1e-3);
+        assert_eq!(0.0, balances[&USDT].available);
+        assert_eq!(0.0, balances[&USDT].pending);
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let prices = [100.0, 90.0, 70.0, 75.0, 60.0];
+        let reports = (0..2)
+            .map(|_| {
+                let mut backtest = backtest(0.5, 0.9);
+                replay(&mut backtest, &prices);
+                backtest.report(&initial_balances(), &graph(60.0), USDT)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(reports[0], reports[1]);
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{construct_speculators, get_latest_stamp, CADENCE_SAMPLE_COUNT};
+use anyhow::{anyhow, Result};
+use database::logic::*;
+use database::model::*;
+use database::schema;
+use diesel::prelude::*;
+use speculator::evaluation::*;
+use speculator::rule::MarketState;
+use std::collections::HashMap;
+
+/// Recommendation waiting for its forward price
+struct Entry {
+    rule: String,
+    parameter_hash: String,
+    recommendation_type: speculator::rule::RecommendationType,
+    price: Amount,
+    horizon: chrono::Duration,
+}
+
+/// Replay stored prices of the last `days` through the configured rules,
+/// then compare each rule-level and aggregated recommendation with the market price after `horizon_candles` candlesticks.
+///
+/// Rules without candlesticks use the scraper's cadence as their interval.
+/// Forward prices are taken at the stamp nearest to the horizon within the cadence.
+/// Orderbooks are not replayed because no rule uses them yet.
+pub fn evaluate(horizon_candles: i32, days: i64, json: bool) -> Result<()> {
+    let url = std::env::var("DATABASE_URL")?;
+    let conn = Conn::establish(&url)?;
+
+    let currency_collection = list_currencies(&conn)?;
+    let market_collection = list_markets(&conn)?;
+    let mut aggregations = construct_speculators(&currency_collection, &market_collection)?;
+
+    let cadence = estimate_stamp_cadence(&conn, CADENCE_SAMPLE_COUNT)?.ok_or(anyhow!(
+        "Can't estimate scraper cadence because of too few stamps"
+    ))?;
+
+    let latest_stamp = get_latest_stamp(&conn)?;
+    let stamps = schema::stamp::table
+        .filter(schema::stamp::timestamp.ge(latest_stamp.timestamp - chrono::Duration::days(days)))
+        .order_by(schema::stamp::timestamp.asc())
+        .load::<Stamp>(&conn)?;
+    let oldest_stamp = stamps
+        .first()
+        .cloned()
+        .ok_or(anyhow!("No stamp to evaluate"))?;
+    let prices = schema::price::table
+        .filter(schema::price::stamp_id.ge(oldest_stamp.stamp_id))
+        .load::<Price>(&conn)?
+        .into_iter()
+        .map(|p| ((p.market_id, p.stamp_id), p))
+        .collect::<HashMap<_, _>>();
+
+    info!(
+        "Evaluate {} stamps since {} with horizon of {} candlesticks",
+        stamps.len(),
+        oldest_stamp.timestamp,
+        horizon_candles
+    );
+
+    let mut samples = vec![];
+    for (&market_id, aggregation) in aggregations.iter_mut() {
+        let descriptions = aggregation.rule_descriptions();
+        let aggregated_horizon = aggregation
+            .shortest_candlestick_interval()
+            .unwrap_or(cadence)
+            * horizon_candles;
+        let mut entries = vec![];
+
+        for stamp in stamps.iter().cloned() {
+            let price = match prices.get(&(market_id, stamp.stamp_id)) {
+                Some(price) => price.clone(),
+                None => continue,
+            };
+            let amount = price.amount;
+            let market_state = MarketState::new(stamp.clone(), price, vec![], vec![]);
+            if let Err(errors) = aggregation.update_market_state(market_state) {
+                for e in errors.into_iter() {
+                    debug!("{}", e);
+                }
+            }
+
+            let recommendation = aggregation.recommend();
+            for (description, source) in descriptions
+                .iter()
+                .zip(recommendation.source_recommendations().iter())
+            {
+                let horizon = description.candlestick_interval.unwrap_or(cadence) * horizon_candles;
+                entries.push((
+                    stamp.timestamp,
+                    Entry {
+                        rule: description.name.to_string(),
+                        parameter_hash: description.parameter_hash.clone(),
+                        recommendation_type: source.recommendation_type(),
+                        price: amount,
+                        horizon,
+                    },
+                ));
+            }
+            entries.push((
+                stamp.timestamp,
+                Entry {
+                    rule: AGGREGATED_RULE.to_string(),
+                    parameter_hash: String::new(),
+                    recommendation_type: recommendation.recommendation_type(),
+                    price: amount,
+                    horizon: aggregated_horizon,
+                },
+            ));
+        }
+
+        for (timestamp, entry) in entries.into_iter() {
+            let forward_price = nearest_stamp(&stamps, timestamp + entry.horizon, cadence)
+                .and_then(|s| prices.get(&(market_id, s.stamp_id)));
+            let ret = forward_price.and_then(|p| forward_return(entry.price, p.amount));
+
+            if let Some(ret) = ret {
+                samples.push(ForwardSample {
+                    rule: entry.rule,
+                    parameter_hash: entry.parameter_hash,
+                    recommendation_type: entry.recommendation_type,
+                    forward_return: ret,
+                });
+            }
+        }
+    }
+
+    let accuracies = aggregate_accuracy(&samples);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&accuracies)?);
+    } else {
+        println!("{}", format_accuracy_table(&accuracies));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,1001 @@
+use crate::{
+    apply_allocation_cap, approval_setting_from_env, approved_order, construct_exchange_graph,
+    construct_speculators, expire_pending_approvals, get_latest_stamp, load_current_price,
+    load_market_states, recommend_markets, record_recommendations, request_approval, MarketSetting,
+};
+use anyhow::{anyhow, bail, ensure, Result};
+use apply::Apply;
+use chrono::NaiveDateTime;
+use database::error::SymbolResolutionError;
+use database::exchange::ExchangeGraph;
+use database::logic::*;
+use database::model::*;
+use database::order::{ExchangeOrder, NewOrder};
+use database::schema;
+use diesel::dsl::max;
+use diesel::prelude::*;
+use nicehash::api_common::ApiKey;
+use speculator::timing::Timings;
+use speculator::trade::{
+    CancelRecommendation, CappedOrders, Holdings, MarketInfo, OrderRecommendation,
+};
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+
+/// Value of `EXECUTE_ORDERS_CONFIRM` required to place orders on the exchange
+const EXECUTION_CONFIRMATION: &str = "yes-i-know";
+
+/// Settings of placing recommended orders on the exchange
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutionSetting {
+    /// Max total value of orders placed in a run, valued in `notional_fiat`
+    pub max_notional_per_run: f64,
+    pub notional_fiat: String,
+    /// Place orders even if opened orders of previous runs remain
+    pub allow_stacked_orders: bool,
+}
+
+impl ExecutionSetting {
+    /// Load settings if `EXECUTE_ORDERS` is `1`.
+    ///
+    /// `EXECUTE_ORDERS_CONFIRM` must be `yes-i-know`, and `MAX_NOTIONAL_PER_RUN` is required.
+    /// Notional is valued in `MAX_NOTIONAL_FIAT` (`USDT` by default).
+    /// Opened orders of previous runs stop execution unless `ALLOW_STACKED_ORDERS` is `1`.
+    pub fn from_env() -> Result<Option<Self>> {
+        if !matches!(env::var("EXECUTE_ORDERS").as_deref(), Ok("1")) {
+            return Ok(None);
+        }
+        if env::var("EXECUTE_ORDERS_CONFIRM").as_deref() != Ok(EXECUTION_CONFIRMATION) {
+            bail!(
+                "EXECUTE_ORDERS_CONFIRM must be {} to place orders on the exchange",
+                EXECUTION_CONFIRMATION
+            );
+        }
+
+        let max_notional_per_run = env::var("MAX_NOTIONAL_PER_RUN")
+            .map_err(|_| anyhow!("MAX_NOTIONAL_PER_RUN is required to place orders"))?
+            .apply(|s| f64::from_str(&s))?;
+        ensure!(
+            max_notional_per_run >= 0.0,
+            "MAX_NOTIONAL_PER_RUN must be non-negative: {}",
+            max_notional_per_run
+        );
+        let notional_fiat = env::var("MAX_NOTIONAL_FIAT").unwrap_or_else(|_| "USDT".into());
+        let allow_stacked_orders = matches!(env::var("ALLOW_STACKED_ORDERS").as_deref(), Ok("1"));
+
+        Self {
+            max_notional_per_run,
+            notional_fiat,
+            allow_stacked_orders,
+        }
+        .apply(Some)
+        .apply(Ok)
+    }
+}
+
+/// Client placing and cancelling orders on the exchange
+pub trait OrderClient {
+    fn place_order(&self, market_info: &MarketInfo, order: &NewOrder) -> Result<ExchangeOrder>;
+
+    fn cancel_order(&self, market_info: &MarketInfo, transaction_id: &str)
+        -> Result<ExchangeOrder>;
+}
+
+/// Client placing orders by NiceHash API
+pub struct NicehashOrderClient {
+    api_key: ApiKey,
+}
+
+impl NicehashOrderClient {
+    pub fn new(api_key: ApiKey) -> Self {
+        Self { api_key }
+    }
+}
+
+impl OrderClient for NicehashOrderClient {
+    fn place_order(&self, market_info: &MarketInfo, order: &NewOrder) -> Result<ExchangeOrder> {
+        nicehash::place_order(
+            self.api_key.clone(),
+            &market_info.base.symbol,
+            &market_info.quote.symbol,
+            order.order_type,
+            order.side,
+            order.price,
+            order.base_quantity,
+            order.trigger_price,
+        )
+    }
+
+    fn cancel_order(
+        &self,
+        market_info: &MarketInfo,
+        transaction_id: &str,
+    ) -> Result<ExchangeOrder> {
+        let (base_symbol, quote_symbol) = market_info.market_symbols();
+        let market_symbol = nicehash::get_market_symbol(base_symbol, quote_symbol);
+        nicehash::cancel_order(self.api_key.clone(), &market_symbol, transaction_id)
+    }
+}
+
+/// Remaining value of orders placeable in a run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NotionalBudget {
+    fiat_id: CurrencyId,
+    remaining: f64,
+}
+
+impl NotionalBudget {
+    pub fn new(fiat_id: CurrencyId, max_notional: f64) -> Self {
+        Self {
+            fiat_id,
+            remaining: max_notional,
+        }
+    }
+
+    pub fn remaining(&self) -> f64 {
+        self.remaining
+    }
+
+    /// Reserve the quote quantity of `order` valued in the fiat.
+    /// # Returns
+    /// The reserved value.
+    /// `Err(e)` if the order exceeds the remaining budget, or its value is unknown. Nothing is reserved then
+    pub fn reserve(
+        &mut self,
+        graph: &ExchangeGraph<CurrencyId>,
+        market_info: &MarketInfo,
+        order: &OrderRecommendation,
+    ) -> Result<f64> {
+        let rate = graph
+            .rate_between(market_info.quote.currency_id, self.fiat_id)
+            .ok_or_else(|| {
+                anyhow!(
+                    "{} can't be converted into the notional fiat",
+                    market_info.quote.symbol
+                )
+            })?;
+        let notional = order.quote_quantity * rate;
+        if notional > self.remaining {
+            bail!(
+                "Notional {} exceeds the remaining budget {}",
+                notional,
+                self.remaining
+            );
+        }
+
+        self.remaining -= notional;
+        Ok(notional)
+    }
+}
+
+/// Refuse to place orders while `open_myorders` of previous runs remain, unless `allow_stacked_orders`
+pub fn check_stacked_orders(open_myorders: &[MyOrder], allow_stacked_orders: bool) -> Result<()> {
+    if open_myorders.is_empty() {
+        return Ok(());
+    }
+    if allow_stacked_orders {
+        warn!(
+            "{} opened orders remain. New orders are stacked on them",
+            open_myorders.len()
+        );
+        return Ok(());
+    }
+
+    let transaction_ids = open_myorders
+        .iter()
+        .map(|myorder| myorder.transaction_id.as_str())
+        .collect::<Vec<_>>();
+    bail!(
+        "Opened orders of previous runs remain: {}. Set ALLOW_STACKED_ORDERS=1 to place orders anyway",
+        transaction_ids.join(", ")
+    )
+}
+
+/// Subtract funds spent by `order` placed on `market` from `balances`, so that following markets are sized by the rest.
+/// Received funds are not added, since they are not available until the order is filled
+pub fn subtract_placed_order(
+    balances: &mut HashMap<CurrencyId, Balance>,
+    market: &Market,
+    order: &OrderRecommendation,
+) {
+    let (base_diff, quote_diff) = order.balance_diff();
+    for (currency_id, diff) in vec![(market.base_id, base_diff), (market.quote_id, quote_diff)] {
+        if let Some(balance) = balances.get_mut(&currency_id) {
+            balance.available += diff.min(0.0);
+        }
+    }
+}
+
+/// Cancel open orders of a market on the exchange.
+/// # Returns
+/// Orders reported by the exchange after cancelling. Failed cancels are only logged
+pub fn cancel_orders(
+    client: &dyn OrderClient,
+    market_info: &MarketInfo,
+    cancels: &[CancelRecommendation],
+) -> Vec<ExchangeOrder> {
+    let (base_symbol, quote_symbol) = market_info.market_symbols();
+    let market_symbol = nicehash::get_market_symbol(base_symbol, quote_symbol);
+    let mut cancelled = vec![];
+
+    for cancel in cancels.iter() {
+        info!(
+            "Cancel order of {}: {:?}",
+            market_symbol,
+            vec![
+                ("market", market_symbol.as_str()),
+                ("orderId", cancel.transaction_id.as_str())
+            ]
+        );
+        match client.cancel_order(market_info, &cancel.transaction_id) {
+            Ok(myorder) => {
+                info!("Order of {} is cancelled: {:?}", market_symbol, myorder);
+                cancelled.push(myorder);
+            }
+            Err(e) => warn!(
+                "Cancel of order {} of {} failed: {}",
+                cancel.transaction_id, market_symbol, e
+            ),
+        }
+    }
+
+    cancelled
+}
+
+/// Place `orders` of a market as long as `budget` allows.
+/// Stop orders are placed with their trigger prices, and reserve the budget like other orders.
+/// # Returns
+/// Orders accepted by the exchange with their recommendations. Rejected or failed orders are only logged
+pub fn place_orders(
+    client: &dyn OrderClient,
+    graph: &ExchangeGraph<CurrencyId>,
+    budget: &mut NotionalBudget,
+    market_info: &MarketInfo,
+    orders: &[OrderRecommendation],
+) -> Vec<(OrderRecommendation, ExchangeOrder)> {
+    let (base_symbol, quote_symbol) = market_info.market_symbols();
+    let market_symbol = nicehash::get_market_symbol(base_symbol, quote_symbol);
+    let mut placed = vec![];
+
+    for order in orders.iter() {
+        if order.base_quantity <= 0.0 {
+            continue;
+        }
+        let query = match nicehash::order_query(
+            &market_symbol,
+            order.order_type,
+            order.side,
+            order.price,
+            order.base_quantity,
+            order.trigger_price,
+        ) {
+            Ok(query) => query,
+            Err(e) => {
+                warn!("Order of {} is not placed: {}", market_symbol, e);
+                continue;
+            }
+        };
+        let notional = match budget.reserve(graph, market_info, order) {
+            Ok(notional) => notional,
+            Err(e) => {
+                warn!(
+                    "Order of {} is not placed: {}. {:?}",
+                    market_symbol, e, query
+                );
+                continue;
+            }
+        };
+
+        info!(
+            "Place order of {} (notional {}): {:?}",
+            market_symbol, notional, query
+        );
+        let new_order = order.new_order(market_info.market.market_id);
+        match client.place_order(market_info, &new_order) {
+            Ok(myorder) => {
+                info!("Order of {} is placed: {:?}", market_symbol, myorder);
+                placed.push((order.clone(), myorder));
+            }
+            Err(e) => warn!("Order of {} failed: {}. {:?}", market_symbol, e, query),
+        }
+    }
+
+    placed
+}
+
+/// Outcome of placing an approved order
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApprovedOrder {
+    Placed(OrderRecommendation, ExchangeOrder),
+    Rejected(ApprovalRejection),
+    /// Rejected by the budget or the exchange. The approval is retried on the next run until it expires
+    NotPlaced,
+}
+
+/// Place the order of `approval` as long as `budget` allows.
+/// The order is rejected if it is past its expiry at `now`,
+/// or `current_price` moved from the approved price by more than `max_price_drift`.
+#[allow(clippy::too_many_arguments)]
+pub fn place_approved_order(
+    client: &dyn OrderClient,
+    graph: &ExchangeGraph<CurrencyId>,
+    budget: &mut NotionalBudget,
+    market_info: &MarketInfo,
+    approval: &PendingApproval,
+    now: NaiveDateTime,
+    current_price: Amount,
+    max_price_drift: Amount,
+) -> ApprovedOrder {
+    if let Err(rejection) = check_approved_order(approval, now, current_price, max_price_drift) {
+        return ApprovedOrder::Rejected(rejection);
+    }
+
+    let order = approved_order(approval);
+    match place_orders(client, graph, budget, market_info, &[order])
+        .into_iter()
+        .next()
+    {
+        Some((order, myorder)) => ApprovedOrder::Placed(order, myorder),
+        None => ApprovedOrder::NotPlaced,
+    }
+}
+
+/// Place orders approved since the previous runs on the exchange, then record them into main DB.
+/// Pending orders past their expiry are expired.
+/// # Returns
+/// Placed orders with their markets
+fn execute_approved_orders(
+    conn: &Conn,
+    latest_stamp: &Stamp,
+    client: &dyn OrderClient,
+    graph: &ExchangeGraph<CurrencyId>,
+    budget: &mut NotionalBudget,
+    market_infos: &HashMap<MarketId, &MarketInfo>,
+    max_price_drift: Amount,
+) -> Result<Vec<(Market, OrderRecommendation)>> {
+    let now = latest_stamp.timestamp;
+    expire_pending_approvals(conn, now)?;
+
+    let mut placed = vec![];
+    for approval in list_pending_approvals(conn, ApprovalState::Approved)?.into_iter() {
+        let id = approval.pending_approval_id;
+        let market_info = match market_infos.get(&approval.market_id) {
+            Some(market_info) => market_info,
+            None => {
+                warn!("Market of approval {} is not found", id);
+                continue;
+            }
+        };
+        let current_price =
+            match load_current_price(conn, latest_stamp.stamp_id, approval.market_id)? {
+                Some(price) => price,
+                None => {
+                    warn!("No current price of approval {}. Retry next time", id);
+                    continue;
+                }
+            };
+
+        match place_approved_order(
+            client,
+            graph,
+            budget,
+            market_info,
+            &approval,
+            now,
+            current_price,
+            max_price_drift,
+        ) {
+            ApprovedOrder::Placed(order, myorder) => {
+                update_pending_approval_state(conn, id, ApprovalState::Executed)?;
+                let transaction_id = myorder.transaction_id.clone();
+                if let Err(e) = add_or_update_exchange_order(
+                    conn,
+                    myorder,
+                    approval.market_id,
+                    latest_stamp.stamp_id,
+                ) {
+                    error!(
+                        "Placed order {} of approval {} is not recorded: {}",
+                        transaction_id, id, e
+                    );
+                }
+                placed.push((market_info.market.clone(), order));
+            }
+            ApprovedOrder::Rejected(ApprovalRejection::Expired) => {
+                info!("Approval {} expired. Skipped", id);
+                update_pending_approval_state(conn, id, ApprovalState::Expired)?;
+            }
+            ApprovedOrder::Rejected(ApprovalRejection::Drifted { drift }) => {
+                warn!(
+                    "Price moved by {:.2}% since approval {}. Skipped",
+                    drift * 100.0,
+                    id
+                );
+                update_pending_approval_state(conn, id, ApprovalState::Drifted)?;
+            }
+            ApprovedOrder::NotPlaced => {
+                warn!("Order of approval {} is not placed. Retry next time", id)
+            }
+        }
+    }
+
+    Ok(placed)
+}
+
+/// Place orders recommended at the latest stamp on the exchange, then record them into main DB.
+/// Recommendations follow the same fees, minimum sizes and allocation caps as simulation,
+/// and each market is sized by balances left by orders placed on the previous markets.
+///
+/// Opened orders working against the recommendations are cancelled first, so that they don't count as stacked orders.
+/// Funds released by the cancels are used from the next run, when balances are scraped again.
+///
+/// If `REQUIRE_APPROVAL` is `1`, orders approved since the previous runs are placed within the same budget,
+/// and new orders await approval instead of being placed.
+pub fn execute(conn: &Conn, setting: &ExecutionSetting, client: &dyn OrderClient) -> Result<()> {
+    let approval_setting = approval_setting_from_env(conn)?;
+    let latest_stamp = get_latest_stamp(conn)?;
+    let currency_collection = list_currencies(conn)?;
+    let market_collection = list_markets(conn)?;
+    let fiat_id = currency_collection
+        .by_symbol(&setting.notional_fiat)
+        .map(|c| c.currency_id)
+        .ok_or_else(|| anyhow!("Unknown notional fiat {}", setting.notional_fiat))?;
+
+    let mut speculators = construct_speculators(&currency_collection, &market_collection)?;
+    let mut timings = Timings::disabled();
+    load_market_states(conn, latest_stamp.clone(), &mut speculators, &mut timings)?;
+
+    let market_setting = env::var("MARKET_JSON")?
+        .apply(std::fs::File::open)?
+        .apply(MarketSetting::from_reader)?;
+    let (market_fees, fee_errors) =
+        market_setting.market_fees(&currency_collection, &market_collection);
+    let (min_order_sizes, min_order_size_errors) =
+        market_setting.min_order_sizes(&currency_collection, &market_collection);
+    for e in fee_errors.iter() {
+        warn!("Fee override is ignored: {}", e);
+    }
+    for e in min_order_size_errors.iter() {
+        warn!("Minimum order size is ignored: {}", e);
+    }
+    let ignored_settings = fee_errors
+        .into_iter()
+        .chain(min_order_size_errors.into_iter())
+        .collect::<Vec<_>>();
+    let exchange_graph = construct_exchange_graph(conn, latest_stamp.stamp_id)?;
+    let allocation_fiat_id = currency_collection
+        .by_symbol(&market_setting.allocation_fiat)
+        .map(|c| c.currency_id);
+    let mut balances = load_latest_balances(conn)?;
+
+    let recommendations = recommend_markets(speculators, &mut timings);
+    for (speculator, recommendation) in recommendations.iter() {
+        record_recommendations(conn, latest_stamp.stamp_id, recommendation);
+
+        let market_info = speculator.market_info();
+        let market_id = market_info.market.market_id;
+        let open_myorders = load_open_myorders(conn, Some(market_id))?;
+        let cancels = recommendation.recommend_cancels(&open_myorders);
+        for myorder in cancel_orders(client, market_info, &cancels).into_iter() {
+            let transaction_id = myorder.transaction_id.clone();
+            if let Err(e) =
+                add_or_update_exchange_order(conn, myorder, market_id, latest_stamp.stamp_id)
+            {
+                error!("Cancelled order {} is not recorded: {}", transaction_id, e);
+            }
+        }
+    }
+    check_stacked_orders(
+        &load_open_myorders(conn, None)?,
+        setting.allow_stacked_orders,
+    )?;
+
+    let mut budget = NotionalBudget::new(fiat_id, setting.max_notional_per_run);
+    info!(
+        "Execute orders at stamp {} within {} {}",
+        latest_stamp.stamp_id.inner(),
+        setting.max_notional_per_run,
+        setting.notional_fiat
+    );
+
+    // Approved orders are placed before new recommendations
+    if let Some(approval_setting) = approval_setting {
+        let market_infos = recommendations
+            .iter()
+            .map(|(speculator, _)| {
+                let market_info = speculator.market_info();
+                (market_info.market.market_id, market_info)
+            })
+            .collect::<HashMap<_, _>>();
+        let placed = execute_approved_orders(
+            conn,
+            &latest_stamp,
+            client,
+            &exchange_graph,
+            &mut budget,
+            &market_infos,
+            approval_setting.max_price_drift,
+        )?;
+        for (market, order) in placed.iter() {
+            subtract_placed_order(&mut balances, market, order);
+        }
+    }
+
+    for (speculator, recommendation) in recommendations.into_iter() {
+        let market_info = speculator.market_info();
+        let market = &market_info.market;
+        // Orders sized by ignored settings might violate the exchange's rules
+        if let Some(e) = ignored_settings
+            .iter()
+            .find(|e| is_affected_market(e, market_info))
+        {
+            warn!(
+                "No order of {} is placed because its setting is ignored: {}",
+                speculator.market_label(),
+                e
+            );
+            continue;
+        }
+        let holdings = match (
+            balances.get(&market.base_id),
+            balances.get(&market.quote_id),
+        ) {
+            (Some(base), Some(quote)) => match Holdings::from_balances(base, quote, market) {
+                Ok(holdings) => holdings,
+                Err(e) => {
+                    warn!("{}", e);
+                    continue;
+                }
+            },
+            _ => {
+                warn!(
+                    "Balances of {} are not found. No order is placed",
+                    speculator.market_label()
+                );
+                continue;
+            }
+        };
+
+        let recommendation =
+            recommendation.with_exchange_graph(&exchange_graph, &currency_collection);
+        let orders = recommendation.recommend_orders(&holdings, &market_fees.fee_schedule(market));
+        let CappedOrders { orders, reason } =
+            match market_setting.max_market_allocation(&speculator.market_label()) {
+                Some(cap) => apply_allocation_cap(
+                    &exchange_graph,
+                    allocation_fiat_id,
+                    &balances,
+                    market_info,
+                    cap,
+                    orders,
+                ),
+                None => CappedOrders {
+                    orders,
+                    reason: None,
+                },
+            };
+        let orders = min_order_sizes.filter_orders(market, orders);
+
+        if let Some(approval_setting) = approval_setting {
+            let mut reasons = recommendation.reasons();
+            reasons.extend(reason);
+            for order in orders.iter() {
+                if order.is_stop() {
+                    warn!("Stop orders can't await approval. Skipped: {:?}", order);
+                    continue;
+                }
+                if let Err(e) = request_approval(
+                    conn,
+                    &latest_stamp,
+                    approval_setting,
+                    market_info,
+                    order,
+                    &reasons,
+                ) {
+                    warn!("Can't save order for approval: {}", e);
+                }
+            }
+            continue;
+        }
+
+        let placed = place_orders(client, &exchange_graph, &mut budget, market_info, &orders);
+        for (order, myorder) in placed.into_iter() {
+            subtract_placed_order(&mut balances, market, &order);
+            let transaction_id = myorder.transaction_id.clone();
+            if let Err(e) =
+                add_or_update_exchange_order(conn, myorder, market.market_id, latest_stamp.stamp_id)
+            {
+                error!("Placed order {} is not recorded: {}", transaction_id, e);
+            }
+        }
+    }
+
+    info!(
+        "Remaining notional budget: {} {}",
+        budget.remaining(),
+        setting.notional_fiat
+    );
+    Ok(())
+}
+
+/// Whether the setting of `market_info` which failed to be resolved by `e` may be meant for the market
+fn is_affected_market(e: &SymbolResolutionError, market_info: &MarketInfo) -> bool {
+    let (base_symbol, quote_symbol) = market_info.market_symbols();
+    match e {
+        SymbolResolutionError::MalformedPair(pair) | SymbolResolutionError::UnknownMarket(pair) => {
+            let market_label = format!("{}-{}", base_symbol, quote_symbol);
+            pair.trim().eq_ignore_ascii_case(&market_label)
+        }
+        SymbolResolutionError::UnknownBaseCurrency(symbol)
+        | SymbolResolutionError::UnknownQuoteCurrency(symbol) => {
+            symbol.eq_ignore_ascii_case(base_symbol) || symbol.eq_ignore_ascii_case(quote_symbol)
+        }
+    }
+}
+
+/// Opened orders in main DB, of `market_id` if given
+fn load_open_myorders(conn: &Conn, market_id: Option<MarketId>) -> Result<Vec<MyOrder>> {
+    let filter = MyorderFilter {
+        market_id,
+        state: Some(OrderState::Opened),
+        ..MyorderFilter::default()
+    };
+    list_myorders(conn, filter)?.apply(Ok)
+}
+
+/// Balances of the latest stamp scraped from the exchange
+fn load_latest_balances(conn: &Conn) -> Result<HashMap<CurrencyId, Balance>> {
+    let latest_balance_stamp_id = schema::balance::table
+        .select(max(schema::balance::stamp_id))
+        .first::<Option<StampId>>(conn)?
+        .ok_or(anyhow!("No balance exists in main DB"))?;
+    schema::balance::table
+        .filter(schema::balance::stamp_id.eq(latest_balance_stamp_id))
+        .load::<Balance>(conn)?
+        .into_iter()
+        .map(|balance| (balance.currency_id, balance))
+        .collect::<HashMap<_, _>>()
+        .apply(Ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    const DOGE: CurrencyId = CurrencyId::new(1);
+    const USDT: CurrencyId = CurrencyId::new(2);
+    const BTC: CurrencyId = CurrencyId::new(3);
+
+    fn market_info() -> MarketInfo {
+        let doge = Currency::new(DOGE, "DOGE".into(), "Dogecoin".into());
+        let usdt = Currency::new(USDT, "USDT".into(), "Tether".into());
+        MarketInfo::new(Market::new(MarketId::new(1), DOGE, USDT), doge, usdt)
+    }
+
+    /// Graph where 1 DOGE is 0.1 USDT
+    fn graph() -> ExchangeGraph<CurrencyId> {
+        ExchangeGraph::from_rates(vec![(DOGE, USDT, 0.1)])
+    }
+
+    fn buy_order(order_type: OrderType, quote_quantity: f64) -> OrderRecommendation {
+        OrderRecommendation {
+            side: OrderSide::Buy,
+            order_type,
+            base_quantity: quote_quantity / 0.1,
+            quote_quantity,
+            price: 0.1,
+            trigger_price: None,
+            expected_net_quote: -quote_quantity,
+        }
+    }
+
+    /// Transaction id of an order which the exchange doesn't know
+    const UNKNOWN_ORDER: &str = "unknown";
+
+    /// Accept all orders and cancels of known orders, recording them
+    #[derive(Default)]
+    struct MockOrderClient {
+        orders: RefCell<Vec<NewOrder>>,
+        cancels: RefCell<Vec<String>>,
+    }
+
+    impl OrderClient for MockOrderClient {
+        fn place_order(&self, _: &MarketInfo, order: &NewOrder) -> Result<ExchangeOrder> {
+            let mut orders = self.orders.borrow_mut();
+            orders.push(order.clone());
+            Ok(ExchangeOrder {
+                transaction_id: format!("order-{}", orders.len()),
+                price: order.price,
+                base_quantity: order.base_quantity,
+                quote_quantity: order.quote_quantity,
+                order_type: order.order_type,
+                side: order.side,
+                state: OrderState::Opened,
+            })
+        }
+
+        fn cancel_order(&self, _: &MarketInfo, transaction_id: &str) -> Result<ExchangeOrder> {
+            if transaction_id == UNKNOWN_ORDER {
+                bail!("Order {} is not found", transaction_id);
+            }
+            self.cancels.borrow_mut().push(transaction_id.to_string());
+            Ok(ExchangeOrder {
+                state: OrderState::Cancelled,
+                ..ExchangeOrder::from(myorder(transaction_id))
+            })
+        }
+    }
+
+    fn myorder(transaction_id: &str) -> MyOrder {
+        MyOrder {
+            myorder_id: MyorderId::new(1),
+            transaction_id: transaction_id.into(),
+            market_id: MarketId::new(1),
+            created_stamp_id: StampId::new(1),
+            modified_stamp_id: StampId::new(1),
+            price: 0.1,
+            base_quantity: 100.0,
+            quote_quantity: 10.0,
+            order_type: OrderType::Limit,
+            side: OrderSide::Buy,
+            state: OrderState::Opened,
+        }
+    }
+
+    #[test]
+    fn test_place_orders_within_cap() {
+        let client = MockOrderClient::default();
+        let mut budget = NotionalBudget::new(USDT, 100.0);
+        let orders = vec![
+            buy_order(OrderType::Market, 60.0),
+            // Exceeds the remaining 40 USDT
+            buy_order(OrderType::Limit, 50.0),
+            buy_order(OrderType::Limit, 40.0),
+        ];
+
+        let placed = place_orders(&client, &graph(), &mut budget, &market_info(), &orders);
+
+        assert_eq!(
+            vec!["order-1", "order-2"],
+            placed
+                .iter()
+                .map(|(_, myorder)| myorder.transaction_id.as_str())
+                .collect::<Vec<_>>()
+        );
+        let sent = client.orders.borrow();
+        assert_eq!(
+            vec![60.0, 40.0],
+            sent.iter().map(|o| o.quote_quantity).collect::<Vec<_>>()
+        );
+        assert!(sent.iter().all(|o| o.market_id == MarketId::new(1)));
+        assert_eq!(0.0, budget.remaining());
+    }
+
+    #[test]
+    fn test_place_orders_in_other_fiat() {
+        // 1 BTC is 50000 USDT, so budget of 0.001 BTC is 50 USDT
+        let graph = ExchangeGraph::from_rates(vec![(DOGE, USDT, 0.1), (BTC, USDT, 50000.0)]);
+        let client = MockOrderClient::default();
+        let mut budget = NotionalBudget::new(BTC, 0.001);
+
+        let placed = place_orders(
+            &client,
+            &graph,
+            &mut budget,
+            &market_info(),
+            &[buy_order(OrderType::Market, 60.0)],
+        );
+        assert!(placed.is_empty());
+
+        let placed = place_orders(
+            &client,
+            &graph,
+            &mut budget,
+            &market_info(),
+            &[buy_order(OrderType::Market, 40.0)],
+        );
+        assert_eq!(1, placed.len());
+    }
+
+    #[test]
+    fn test_place_orders_unknown_value() {
+        // Orders are never placed if the cap can't be enforced
+        let graph = ExchangeGraph::from_rates(vec![(DOGE, USDT, 0.1)]);
+        let client = MockOrderClient::default();
+        let mut budget = NotionalBudget::new(BTC, 1.0);
+
+        let placed = place_orders(
+            &client,
+            &graph,
+            &mut budget,
+            &market_info(),
+            &[buy_order(OrderType::Market, 1.0)],
+        );
+
+        assert!(placed.is_empty());
+        assert!(client.orders.borrow().is_empty());
+        assert_eq!(1.0, budget.remaining());
+    }
+
+    #[test]
+    fn test_place_stop_orders() {
+        let client = MockOrderClient::default();
+        let mut budget = NotionalBudget::new(USDT, 100.0);
+        let mut stop = buy_order(OrderType::StopMarket, 60.0);
+        stop.trigger_price = Some(0.12);
+        // Exceeds the remaining 40 USDT
+        let mut stop_limit = buy_order(OrderType::StopLimit, 50.0);
+        stop_limit.trigger_price = Some(0.12);
+        // Never placed without its trigger price
+        let untriggered = buy_order(OrderType::StopLimit, 10.0);
+
+        let placed = place_orders(
+            &client,
+            &graph(),
+            &mut budget,
+            &market_info(),
+            &[stop, stop_limit, untriggered],
+        );
+
+        assert_eq!(1, placed.len());
+        let sent = client.orders.borrow();
+        assert_eq!(1, sent.len());
+        assert_eq!(OrderType::StopMarket, sent[0].order_type);
+        assert_eq!(Some(0.12), sent[0].trigger_price);
+        assert_eq!(40.0, budget.remaining());
+    }
+
+    #[test]
+    fn test_cancel_orders() {
+        let client = MockOrderClient::default();
+        let cancel = |transaction_id: &str| CancelRecommendation {
+            transaction_id: transaction_id.into(),
+            market_id: MarketId::new(1),
+            side: OrderSide::Sell,
+        };
+
+        let cancelled = cancel_orders(
+            &client,
+            &market_info(),
+            &[cancel("a"), cancel(UNKNOWN_ORDER), cancel("b")],
+        );
+
+        // Failed cancels are skipped
+        assert_eq!(
+            vec![("a", OrderState::Cancelled), ("b", OrderState::Cancelled)],
+            cancelled
+                .iter()
+                .map(|myorder| (myorder.transaction_id.as_str(), myorder.state))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(vec!["a", "b"], *client.cancels.borrow());
+    }
+
+    #[test]
+    fn test_subtract_placed_order() {
+        let balance = |currency_id, available| {
+            Balance::new(
+                BalanceId::new(1),
+                currency_id,
+                StampId::new(1),
+                available,
+                0.0,
+            )
+        };
+        let mut balances = vec![(DOGE, balance(DOGE, 1000.0)), (USDT, balance(USDT, 100.0))]
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let market = market_info().market;
+
+        subtract_placed_order(&mut balances, &market, &buy_order(OrderType::Limit, 60.0));
+
+        // Bought DOGE is not available until the order is filled
+        assert_eq!(1000.0, balances[&DOGE].available);
+        assert_eq!(40.0, balances[&USDT].available);
+
+        // The next market is sized by the rest
+        let mut sell = buy_order(OrderType::Limit, 10.0);
+        sell.side = OrderSide::Sell;
+        sell.expected_net_quote = 10.0;
+        subtract_placed_order(&mut balances, &market, &sell);
+        assert_eq!(900.0, balances[&DOGE].available);
+        assert_eq!(40.0, balances[&USDT].available);
+    }
+
+    #[test]
+    fn test_is_affected_market() {
+        let market_info = market_info();
+
+        assert!(is_affected_market(
+            &SymbolResolutionError::UnknownMarket(" doge-usdt ".into()),
+            &market_info
+        ));
+        assert!(is_affected_market(
+            &SymbolResolutionError::UnknownQuoteCurrency("USDT".into()),
+            &market_info
+        ));
+        assert!(!is_affected_market(
+            &SymbolResolutionError::UnknownMarket("BTC-USDT".into()),
+            &market_info
+        ));
+        assert!(!is_affected_market(
+            &SymbolResolutionError::UnknownBaseCurrency("BTC".into()),
+            &market_info
+        ));
+    }
+
+    /// Approval of buying DOGE for `quote_quantity` USDT, approved at 0.1 USDT
+    fn approval(expiry: NaiveDateTime, quote_quantity: f64) -> PendingApproval {
+        PendingApproval {
+            pending_approval_id: PendingApprovalId::new(1),
+            market_id: MarketId::new(1),
+            stamp_id: StampId::new(1),
+            expiry,
+            price: 0.1,
+            base_quantity: quote_quantity / 0.1,
+            quote_quantity,
+            expected_net_quote: -quote_quantity,
+            order_type: OrderType::Limit,
+            side: OrderSide::Buy,
+            recommendation: String::new(),
+            state: ApprovalState::Approved,
+            approved_price: Some(0.1),
+        }
+    }
+
+    #[test]
+    fn test_place_approved_order() {
+        let client = MockOrderClient::default();
+        let mut budget = NotionalBudget::new(USDT, 100.0);
+        let now = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 5, 0);
+        let before = now - chrono::Duration::minutes(1);
+        let after = now + chrono::Duration::minutes(1);
+        let mut place = |approval: &PendingApproval, current_price: Amount| {
+            place_approved_order(
+                &client,
+                &graph(),
+                &mut budget,
+                &market_info(),
+                approval,
+                now,
+                current_price,
+                0.01,
+            )
+        };
+
+        // Approved and unexpired
+        match place(&approval(after, 60.0), 0.1005) {
+            ApprovedOrder::Placed(order, myorder) => {
+                assert_eq!(60.0, order.quote_quantity);
+                assert_eq!("order-1", myorder.transaction_id);
+            }
+            outcome => panic!("Approved order is not placed: {:?}", outcome),
+        }
+        assert_eq!(
+            ApprovedOrder::Rejected(ApprovalRejection::Expired),
+            place(&approval(before, 10.0), 0.1)
+        );
+        assert!(matches!(
+            place(&approval(after, 10.0), 0.12),
+            ApprovedOrder::Rejected(ApprovalRejection::Drifted { drift }) if drift > 0.19
+        ));
+        // Exceeds the remaining 40 USDT
+        assert_eq!(ApprovedOrder::NotPlaced, place(&approval(after, 50.0), 0.1));
+
+        assert_eq!(1, client.orders.borrow().len());
+        assert_eq!(40.0, budget.remaining());
+    }
+
+    #[test]
+    fn test_check_stacked_orders() {
+        assert!(check_stacked_orders(&[], false).is_ok());
+
+        let open_myorders = vec![myorder("a"), myorder("b")];
+        let e = check_stacked_orders(&open_myorders, false).unwrap_err();
+        assert!(e.to_string().contains("a, b"));
+
+        assert!(check_stacked_orders(&open_myorders, true).is_ok());
+    }
+}
@@ -0,0 +1,2070 @@
+mod backtest;
+mod evaluation;
+mod executor;
+mod market_parse;
+
+pub use backtest::{backtest, BacktestReport, MarketProfit};
+pub use evaluation::evaluate;
+pub use market_parse::{MarketFees, MarketSetting, MinOrderSizes};
+
+use anyhow::{anyhow, Result};
+use apply::Apply;
+use database::display::format_amount;
+use database::exchange::{ExchangeGraph, FromPrices};
+use database::logic::*;
+use database::model::*;
+use database::schema;
+use diesel::dsl::max;
+use diesel::insert_into;
+use diesel::prelude::*;
+use itertools::Itertools;
+use rayon::prelude::*;
+use speculator::execution;
+use speculator::fee::FeeSchedule;
+use speculator::ledger::{self, OrderFunds};
+use speculator::rule::MarketState;
+use speculator::rule::RecommendationType;
+use speculator::timing::Timings;
+use speculator::trade::{
+    cap_buy_orders, trigger_stop_orders, AggregatedRecommendation, CancelRecommendation,
+    CappedOrders, HeldStopOrder, Holdings, MarketInfo, OrderRecommendation, StopOrderUpdate,
+    TradeAggregation, TradeAggregationParameter, TradeParameter,
+};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::hash::Hash;
+use std::str::FromStr;
+#[macro_use]
+extern crate log;
+
+fn group_by<V, K, F>(iter: impl IntoIterator<Item = V>, mut f: F) -> HashMap<K, Vec<V>>
+where
+    K: Eq + Hash,
+    V: Clone,
+    F: FnMut(&V) -> K,
+{
+    let mut map = HashMap::new();
+    for v in iter.into_iter() {
+        let key = f(&v);
+        map.entry(key)
+            .and_modify(|vec: &mut Vec<V>| vec.push(v.clone()))
+            .or_insert_with(|| vec![v]);
+    }
+
+    map
+}
+
+fn sync_balance(conn: &Conn, balance_sim_conn: &Conn, latest_main_stamp: Stamp) -> Result<()> {
+    let balances = schema::balance::table
+        .filter(schema::balance::stamp_id.eq(latest_main_stamp.stamp_id))
+        .load::<Balance>(conn)?;
+
+    info!("Sync: found {} balances in main DB", balances.len());
+
+    let items = balances
+        .iter()
+        .map(|b| (b.currency_id, b.available, b.pending))
+        .collect_vec();
+    add_balances_bulk(balance_sim_conn, latest_main_stamp.stamp_id, &items)?;
+
+    info!("Synced balances with main DB");
+
+    Ok(())
+}
+
+/// The latest stamp of main DB.
+///
+/// If `IGNORE_NEWER_IMPORT_STAMPS` is `1`, imported stamps newer than the latest scraper stamp are ignored,
+/// so that a backfill in progress is not simulated as fresh data.
+fn get_latest_stamp(conn: &Conn) -> Result<Stamp> {
+    let ignore_newer_imports = matches!(env::var("IGNORE_NEWER_IMPORT_STAMPS").as_deref(), Ok("1"));
+
+    database::logic::get_latest_stamp(conn, ignore_newer_imports)?
+        .ok_or_else(|| anyhow!("No stamp exists in main DB"))
+}
+
+fn construct_speculators(
+    currency_collection: &CurrencyCollection,
+    market_collection: &MarketCollection,
+) -> Result<HashMap<MarketId, TradeAggregation>> {
+    let rule_parameter = env::var("RULE_JSON")?.apply(TradeAggregationParameter::from_json_file)?;
+    let trade_parameter = env::var("TRADE_JSON")?
+        .apply(std::fs::File::open)?
+        .apply(TradeParameter::from_reader)?;
+
+    let active_currencies = currency_collection.active();
+    let resolve_market =
+        |str: &str| match resolve_market_symbol(str, &active_currencies, market_collection) {
+            Ok((base, quote, market)) => MarketInfo::new(market, base, quote).apply(Some),
+            Err(e) => {
+                warn!("Can't resolve market of rule: {}", e);
+                None
+            }
+        };
+
+    rule_parameter.finalize(trade_parameter, resolve_market)
+}
+
+/// Number of the latest stamps used to estimate the scraper's cadence
+const CADENCE_SAMPLE_COUNT: i64 = 100;
+
+/// Warn rules whose candlesticks are built from too few stamps.
+/// Candlestick intervals must be at least `CANDLESTICK_CADENCE_RATIO` (default 3) times the scraper's cadence.
+///
+/// If `CANDLESTICK_CADENCE_STRICT` is `1`, violation is an error instead of warning.
+fn check_candlestick_cadence(
+    conn: &Conn,
+    aggregations: &HashMap<MarketId, TradeAggregation>,
+) -> Result<()> {
+    let cadence = match estimate_stamp_cadence(conn, CADENCE_SAMPLE_COUNT)? {
+        Some(cadence) => cadence,
+        None => {
+            warn!("Can't estimate scraper cadence because of too few stamps");
+            return Ok(());
+        }
+    };
+    let min_ratio = match env::var("CANDLESTICK_CADENCE_RATIO") {
+        Ok(s) => i32::from_str(&s)?,
+        Err(_) => 3,
+    };
+    let strict = matches!(env::var("CANDLESTICK_CADENCE_STRICT").as_deref(), Ok("1"));
+
+    info!("Estimated scraper cadence: {} sec", cadence.num_seconds());
+
+    let violations = aggregations
+        .values()
+        .filter_map(|aggregation| aggregation.check_cadence(cadence, min_ratio).err())
+        .sorted()
+        .collect_vec();
+    for violation in violations.iter() {
+        warn!("{}", violation);
+    }
+
+    if strict && !violations.is_empty() {
+        Err(anyhow!(
+            "{} markets have too short candlestick interval",
+            violations.len()
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Exclude stamps whose scraper run failed to fetch prices, since other sections of them may be partial.
+/// Stamps without a run, such as imported ones, are kept
+fn skip_stamps_without_prices(stamps: Vec<Stamp>, runs: &[ScrapeRun]) -> Vec<Stamp> {
+    let skipped = runs
+        .iter()
+        .filter(|run| !run.price_fetched)
+        .map(|run| run.stamp_id)
+        .collect::<HashSet<_>>();
+
+    stamps
+        .into_iter()
+        .filter(|stamp| {
+            let skip = skipped.contains(&stamp.stamp_id);
+            if skip {
+                debug!(
+                    "Skip stamp {}({}): prices were not fetched",
+                    stamp.stamp_id, stamp.timestamp
+                );
+            }
+            !skip
+        })
+        .collect()
+}
+
+/// Group myorders by market and the stamp they are modified at
+fn group_myorders(myorders: Vec<MyOrder>) -> HashMap<(MarketId, StampId), Vec<MyOrder>> {
+    group_by(myorders, |m| (m.market_id, m.modified_stamp_id))
+}
+
+pub fn load_market_states(
+    conn: &Conn,
+    latest_main_stamp: Stamp,
+    aggregations: &mut HashMap<MarketId, TradeAggregation>,
+    timings: &mut Timings,
+) -> Result<()> {
+    let required_duration = aggregations
+        .values()
+        .flat_map(|a| a.duration_requirement())
+        .max();
+    let required_stamp_count = aggregations
+        .values()
+        .map(|a| a.stamp_count_requirement())
+        .max()
+        .unwrap_or_default();
+    if required_duration.is_none() && required_stamp_count == 0 {
+        return Ok(());
+    }
+
+    // Load necessary timestamps. Newer stamps are excluded, since an older stamp may be processed on catch-up
+    let stamps = {
+        let oldest_by_duration = required_duration.map(|d| latest_main_stamp.timestamp - d);
+        let oldest_by_count = if required_stamp_count > 0 {
+            nth_latest_timestamp(conn, latest_main_stamp.timestamp, required_stamp_count)?
+        } else {
+            None
+        };
+        let oldest_timestamp = oldest_by_duration
+            .into_iter()
+            .chain(oldest_by_count)
+            .min()
+            .unwrap_or(latest_main_stamp.timestamp);
+        schema::stamp::table
+            .filter(schema::stamp::timestamp.ge(oldest_timestamp))
+            .filter(schema::stamp::timestamp.le(latest_main_stamp.timestamp))
+            .order_by(schema::stamp::timestamp.asc())
+            .load::<Stamp>(conn)?
+    };
+    let stamps = {
+        let stamp_ids = stamps.iter().map(|s| s.stamp_id).collect::<Vec<_>>();
+        let runs = list_scrape_runs(conn, &stamp_ids)?;
+        skip_stamps_without_prices(stamps, &runs)
+    };
+    let oldest_stamp = match stamps.first().cloned() {
+        Some(stamp) => stamp,
+        None => return Ok(()),
+    };
+
+    // Load prices/orderbooks of all markets within timespan
+    let price_group = schema::price::table
+        .filter(schema::price::stamp_id.ge(oldest_stamp.stamp_id))
+        .load::<Price>(conn)?
+        .into_iter()
+        .map(|p| ((p.market_id, p.stamp_id), p))
+        .collect::<HashMap<_, _>>();
+    let orderbook_group = schema::orderbook::table
+        .filter(schema::orderbook::stamp_id.ge(oldest_stamp.stamp_id))
+        .load::<Orderbook>(conn)?
+        .apply(|orderbooks| group_by(orderbooks, |o| (o.market_id, o.stamp_id)));
+    // Myorders are loaded only for markets whose rules read them, in order of modification.
+    // Orders modified before the stamps are kept apart, so that rules see fills since their positions opened
+    let mut myorder_group = HashMap::new();
+    let mut earlier_myorders = HashMap::new();
+    for (&market_id, _) in aggregations
+        .iter()
+        .filter(|(_, aggregation)| aggregation.requires_myorders())
+    {
+        let filter = MyorderFilter {
+            market_id: Some(market_id),
+            ..MyorderFilter::default()
+        };
+        let (earlier, within): (Vec<_>, Vec<_>) = list_myorders(conn, filter)?
+            .into_iter()
+            .partition(|m| m.modified_stamp_id < oldest_stamp.stamp_id);
+        earlier_myorders.insert(market_id, earlier);
+        myorder_group.extend(group_myorders(within));
+    }
+
+    // Market states are built as plain data first, then pushed to rules in parallel
+    let market_states = build_market_states(
+        aggregations.keys().copied(),
+        &stamps,
+        &price_group,
+        &orderbook_group,
+        &myorder_group,
+        earlier_myorders,
+    );
+    update_market_states(aggregations, market_states, timings);
+
+    Ok(())
+}
+
+/// Timestamp of the `count`-th latest stamp not newer than `latest_timestamp`.
+/// # Returns
+/// The oldest timestamp if fewer stamps exist. `None` if no stamp exists
+fn nth_latest_timestamp(
+    conn: &Conn,
+    latest_timestamp: chrono::NaiveDateTime,
+    count: usize,
+) -> Result<Option<chrono::NaiveDateTime>> {
+    let timestamps = schema::stamp::table
+        .filter(schema::stamp::timestamp.le(latest_timestamp))
+        .order_by(schema::stamp::timestamp.desc())
+        .limit(count as i64)
+        .select(schema::stamp::timestamp)
+        .load::<chrono::NaiveDateTime>(conn)?;
+
+    Ok(timestamps.last().copied())
+}
+
+/// Market states of `market_ids` at `stamps` having their prices.
+/// `earlier_myorders` of a market, modified before `stamps`, precede myorders of its first market state
+fn build_market_states(
+    market_ids: impl IntoIterator<Item = MarketId>,
+    stamps: &[Stamp],
+    price_group: &HashMap<(MarketId, StampId), Price>,
+    orderbook_group: &HashMap<(MarketId, StampId), Vec<Orderbook>>,
+    myorder_group: &HashMap<(MarketId, StampId), Vec<MyOrder>>,
+    mut earlier_myorders: HashMap<MarketId, Vec<MyOrder>>,
+) -> HashMap<MarketId, Vec<MarketState>> {
+    let mut market_states = HashMap::new();
+    for market_id in market_ids.into_iter() {
+        let mut states = stamps
+            .iter()
+            .filter_map(|stamp| {
+                let price = price_group.get(&(market_id, stamp.stamp_id))?.clone();
+                let orderbooks = orderbook_group
+                    .get(&(market_id, stamp.stamp_id))
+                    .cloned()
+                    .unwrap_or_default();
+                let myorders = myorder_group
+                    .get(&(market_id, stamp.stamp_id))
+                    .cloned()
+                    .unwrap_or_default();
+                Some(MarketState {
+                    stamp: stamp.clone(),
+                    price,
+                    orderbooks,
+                    myorders,
+                })
+            })
+            .collect::<Vec<_>>();
+        if let (Some(first), Some(earlier)) =
+            (states.first_mut(), earlier_myorders.remove(&market_id))
+        {
+            first.myorders.splice(0..0, earlier);
+        }
+        market_states.insert(market_id, states);
+    }
+
+    market_states
+}
+
+/// Push `market_states` of each market in chronological order.
+/// Markets are independent of each other, so they are updated in parallel
+pub fn update_market_states(
+    aggregations: &mut HashMap<MarketId, TradeAggregation>,
+    mut market_states: HashMap<MarketId, Vec<MarketState>>,
+    timings: &mut Timings,
+) {
+    let timings_enabled = timings.is_enabled();
+    let market_timings = aggregations
+        .iter_mut()
+        .map(|(market_id, aggregation)| {
+            let states = market_states.remove(market_id).unwrap_or_default();
+            (aggregation, states)
+        })
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|(aggregation, states)| {
+            let mut market_timings = Timings::new(timings_enabled);
+            let timer = market_timings.start();
+            for market_state in states.into_iter() {
+                if let Err(errors) =
+                    aggregation.update_market_state_timed(market_state, &mut market_timings)
+                {
+                    for e in errors.into_iter() {
+                        warn!("{}", e);
+                    }
+                }
+            }
+            market_timings.stop(timer, &aggregation.market_label(), "load");
+            market_timings
+        })
+        .collect::<Vec<_>>();
+
+    for market_timing in market_timings.into_iter() {
+        timings.merge(market_timing);
+    }
+}
+
+/// Recommend trades of all markets in parallel.
+/// # Returns
+/// Each aggregation paired with its recommendation, so that orders are executed sequentially
+pub fn recommend_markets(
+    aggregations: HashMap<MarketId, TradeAggregation>,
+    timings: &mut Timings,
+) -> Vec<(TradeAggregation, AggregatedRecommendation)> {
+    let timings_enabled = timings.is_enabled();
+    let recommendations = aggregations
+        .into_par_iter()
+        .map(|(_, aggregation)| {
+            let mut market_timings = Timings::new(timings_enabled);
+            let timer = market_timings.start();
+            let recommendation = aggregation.recommend_timed(&mut market_timings);
+            market_timings.stop(timer, &aggregation.market_label(), "recommend");
+            (aggregation, recommendation, market_timings)
+        })
+        .collect::<Vec<_>>();
+
+    recommendations
+        .into_iter()
+        .map(|(aggregation, recommendation, market_timings)| {
+            timings.merge(market_timings);
+            (aggregation, recommendation)
+        })
+        .collect()
+}
+
+/// Rows of `recommendation` table for evaluations of rules of a market, followed by their aggregated outcome
+pub fn recommendation_rows(
+    recommendation: &AggregatedRecommendation,
+) -> Vec<(
+    MarketId,
+    Option<String>,
+    RecommendationType,
+    Option<f64>,
+    String,
+)> {
+    let market_id = recommendation.market_info().market.market_id;
+    let rules = recommendation
+        .rule_evaluations()
+        .iter()
+        .zip_eq(recommendation.source_recommendations().iter())
+        .map(|(evaluation, source)| {
+            (
+                market_id,
+                Some(evaluation.name.to_string()),
+                evaluation.recommendation_type,
+                evaluation.score,
+                source.reason(),
+            )
+        });
+
+    // Mean is NaN if all rules are neutral
+    let explanation = recommendation.explain();
+    let mean = Some(explanation.mean).filter(|mean| mean.is_finite());
+    let aggregated = (
+        market_id,
+        None,
+        explanation.recommendation_type,
+        mean,
+        format!(
+            "Weighted mean {:.3} of {} rules, buy trigger {}, sell trigger {}",
+            explanation.mean,
+            explanation.rules.len(),
+            explanation.buy_trigger,
+            explanation.sell_trigger
+        ),
+    );
+
+    rules.chain(std::iter::once(aggregated)).collect()
+}
+
+/// Persist recommendations of a market at `stamp_id`, so that hit-rate of rules can be measured later.
+/// Recommendations already recorded at the stamp, like by the simulation of the same stamp, are kept.
+/// Failures are only logged, since they don't affect trading
+pub fn record_recommendations(
+    conn: &Conn,
+    stamp_id: StampId,
+    recommendation: &AggregatedRecommendation,
+) {
+    let (base_symbol, quote_symbol) = recommendation.market_symbols();
+    match add_recommendations_bulk(conn, stamp_id, &recommendation_rows(recommendation)) {
+        Ok(_) => {}
+        Err(database::error::Error::Logic(
+            database::error::LogicError::DuplicatedRecommendation,
+        )) => {
+            debug!(
+                "Recommendations of {}-{} are already recorded at stamp {}",
+                base_symbol, quote_symbol, stamp_id
+            )
+        }
+        Err(e) => warn!(
+            "Can't record recommendations of {}-{}: {}",
+            base_symbol, quote_symbol, e
+        ),
+    }
+}
+
+/// Settings of manual approval of recommended orders
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ApprovalSetting {
+    /// Recommended orders are not executed after this duration
+    expiry: chrono::Duration,
+    /// Allowed relative change of market price between approval and execution
+    max_price_drift: Amount,
+}
+
+/// Max length of recommendation reasons saved with approvals
+const APPROVAL_RECOMMENDATION_LEN: usize = 1024;
+
+/// Load approval settings if `REQUIRE_APPROVAL` is `1`.
+///
+/// Expiry is `APPROVAL_EXPIRY_SEC`, or one and a half of the scraper's cadence by default,
+/// so that an approval is executable on the next run even if the next stamp comes a bit late.
+fn approval_setting_from_env(conn: &Conn) -> Result<Option<ApprovalSetting>> {
+    if !matches!(env::var("REQUIRE_APPROVAL").as_deref(), Ok("1")) {
+        return Ok(None);
+    }
+
+    let expiry = match env::var("APPROVAL_EXPIRY_SEC") {
+        Ok(s) => i64::from_str(&s)?.apply(chrono::Duration::seconds),
+        Err(_) => estimate_stamp_cadence(conn, CADENCE_SAMPLE_COUNT)?
+            .map(|cadence| cadence * 3 / 2)
+            .ok_or(anyhow!(
+                "Can't decide approval expiry because of too few stamps. Set APPROVAL_EXPIRY_SEC"
+            ))?,
+    };
+    let max_price_drift = match env::var("APPROVAL_MAX_PRICE_DRIFT") {
+        Ok(s) => Amount::from_str(&s)?,
+        Err(_) => 0.01,
+    };
+
+    info!(
+        "Orders require approval. expiry: {} sec, max price drift: {}",
+        expiry.num_seconds(),
+        max_price_drift
+    );
+
+    Some(ApprovalSetting {
+        expiry,
+        max_price_drift,
+    })
+    .apply(Ok)
+}
+
+/// Expire pending orders which were not approved until `now`
+fn expire_pending_approvals(conn: &Conn, now: chrono::NaiveDateTime) -> Result<()> {
+    for approval in list_pending_approvals(conn, ApprovalState::Pending)?.into_iter() {
+        if is_approval_expired(&approval, now) {
+            info!(
+                "Approval {} expired without approval",
+                approval.pending_approval_id
+            );
+            update_pending_approval_state(
+                conn,
+                approval.pending_approval_id,
+                ApprovalState::Expired,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Market price of `market_id` at `stamp_id`, compared with the approved price before execution
+fn load_current_price(
+    conn: &Conn,
+    stamp_id: StampId,
+    market_id: MarketId,
+) -> Result<Option<Amount>> {
+    schema::price::table
+        .filter(schema::price::market_id.eq(market_id))
+        .filter(schema::price::stamp_id.eq(stamp_id))
+        .select(schema::price::amount)
+        .first::<Amount>(conn)
+        .optional()?
+        .apply(Ok)
+}
+
+/// Order of `approval`. Stop orders never await approval
+fn approved_order(approval: &PendingApproval) -> OrderRecommendation {
+    OrderRecommendation {
+        side: approval.side,
+        order_type: approval.order_type,
+        base_quantity: approval.base_quantity,
+        quote_quantity: approval.quote_quantity,
+        price: approval.price,
+        trigger_price: None,
+        expected_net_quote: approval.expected_net_quote,
+    }
+}
+
+/// Execute approved orders on the simulation balances, skipping expired or price-drifted ones.
+/// Pending orders past their expiry are expired.
+fn execute_approved_orders(
+    conn: &Conn,
+    latest_main_stamp: &Stamp,
+    setting: ApprovalSetting,
+    market_collection: &MarketCollection,
+    current_balances: &mut HashMap<CurrencyId, Balance>,
+) -> Result<()> {
+    let now = latest_main_stamp.timestamp;
+    expire_pending_approvals(conn, now)?;
+
+    for approval in list_pending_approvals(conn, ApprovalState::Approved)?.into_iter() {
+        let id = approval.pending_approval_id;
+        let market = match market_collection.by_id(approval.market_id) {
+            Some(market) => market,
+            None => {
+                warn!("Market of approval {} is not found", id);
+                continue;
+            }
+        };
+        let current_price =
+            load_current_price(conn, latest_main_stamp.stamp_id, approval.market_id)?;
+        let current_price = match current_price {
+            Some(price) => price,
+            None => {
+                warn!("No current price of approval {}. Retry next time", id);
+                continue;
+            }
+        };
+
+        match check_approved_order(&approval, now, current_price, setting.max_price_drift) {
+            Ok(()) => {}
+            Err(ApprovalRejection::Expired) => {
+                info!("Approval {} expired. Skipped", id);
+                update_pending_approval_state(conn, id, ApprovalState::Expired)?;
+                continue;
+            }
+            Err(ApprovalRejection::Drifted { drift }) => {
+                warn!(
+                    "Price moved by {:.2}% since approval {}. Skipped",
+                    drift * 100.0,
+                    id
+                );
+                update_pending_approval_state(conn, id, ApprovalState::Drifted)?;
+                continue;
+            }
+        }
+
+        let order = approved_order(&approval);
+        let (base_diff, quote_diff) = order.balance_diff();
+        let base_available = current_balances
+            .get(&market.base_id)
+            .map(|b| b.available)
+            .unwrap_or_default();
+        let quote_available = current_balances
+            .get(&market.quote_id)
+            .map(|b| b.available)
+            .unwrap_or_default();
+        if base_available + base_diff < 0.0 || quote_available + quote_diff < 0.0 {
+            warn!(
+                "Too much order of approval {}. base available: {}, quote available: {}, order: {:?}",
+                id, base_available, quote_available, order
+            );
+            continue;
+        }
+
+        for (currency_id, diff) in vec![(market.base_id, base_diff), (market.quote_id, quote_diff)]
+        {
+            if let Some(balance) = current_balances.get_mut(&currency_id) {
+                balance.available += diff;
+            }
+        }
+        update_pending_approval_state(conn, id, ApprovalState::Executed)?;
+
+        info!(
+            "Executed approval {}: Order:{:?}-{:?} price: {}, base_diff:{}, quote_diff:{}",
+            id, order.order_type, order.side, order.price, base_diff, quote_diff
+        );
+    }
+
+    Ok(())
+}
+
+/// Save `order` to wait for approval instead of executing it
+fn request_approval(
+    conn: &Conn,
+    latest_main_stamp: &Stamp,
+    setting: ApprovalSetting,
+    market_info: &MarketInfo,
+    order: &OrderRecommendation,
+    reasons: &[String],
+) -> Result<()> {
+    let recommendation = reasons
+        .join("\n")
+        .chars()
+        .take(APPROVAL_RECOMMENDATION_LEN)
+        .collect::<String>();
+    let approval = add_pending_approval(
+        conn,
+        market_info.market.market_id,
+        latest_main_stamp.stamp_id,
+        latest_main_stamp.timestamp + setting.expiry,
+        order.price,
+        order.base_quantity,
+        order.quote_quantity,
+        order.expected_net_quote,
+        order.order_type,
+        order.side,
+        recommendation,
+    )?;
+
+    info!(
+        "Order awaits approval {}: Market:{}-{} Order:{:?}-{:?} price: {}, base_quantity: {}, quote_quantity: {}, expiry: {}",
+        approval.pending_approval_id,
+        market_info.base.symbol,
+        market_info.quote.symbol,
+        order.order_type,
+        order.side,
+        order.price,
+        format_amount(&market_info.base, order.base_quantity),
+        format_amount(&market_info.quote, order.quote_quantity),
+        approval.expiry,
+    );
+
+    Ok(())
+}
+
+fn load_latest_sim_balances(
+    balance_sim_conn: &Conn,
+    currency_collection: &CurrencyCollection,
+) -> Result<HashMap<CurrencyId, Balance>> {
+    let latest_balance_stamp_id = schema::balance::table
+        .select(max(schema::balance::stamp_id))
+        .first::<Option<StampId>>(balance_sim_conn)?
+        .ok_or(anyhow!("No balance exists in simulation DB"))?;
+    currency_collection
+        .currencies()
+        .into_iter()
+        .filter_map(|c| {
+            let latest_balance = schema::balance::table
+                .filter(schema::balance::currency_id.eq(c.currency_id))
+                .filter(schema::balance::stamp_id.eq(latest_balance_stamp_id))
+                .first(balance_sim_conn)
+                .optional();
+            match latest_balance {
+                Ok(Some(balance)) => Some(balance),
+                Ok(None) => {
+                    debug!(
+                        "Currency {} is not found in simulation balances. Its balance is assumed 0",
+                        c.name
+                    );
+                    // Id is allocated when the balance is saved
+                    let balance = Balance::new(
+                        BalanceId::new(0),
+                        c.currency_id,
+                        latest_balance_stamp_id,
+                        0.0,
+                        0.0,
+                    );
+                    Some(balance)
+                }
+                Err(e) => {
+                    warn!("Can't fetch balance of currency {}: {}", c.name, e);
+                    None
+                }
+            }
+        })
+        .map(|b| (b.currency_id, b))
+        .collect::<HashMap<_, _>>()
+        .apply(Ok)
+}
+
+fn construct_exchange_graph(conn: &Conn, stamp_id: StampId) -> Result<ExchangeGraph<CurrencyId>> {
+    let prices = schema::price::table
+        .inner_join(
+            schema::market::table.on(schema::price::market_id.eq(schema::market::market_id)),
+        )
+        .filter(schema::price::stamp_id.eq(stamp_id))
+        .load::<(Price, Market)>(conn)?;
+
+    Ok(ExchangeGraph::from_prices(&prices))
+}
+
+/// Reduce or skip buy `orders` of a market so that its base currency stays within `cap` of the portfolio valued in `fiat_id`.
+/// The cap is not enforced, with a warning, if the base currency can't be valued or the portfolio value is near zero
+fn apply_allocation_cap(
+    graph: &ExchangeGraph<CurrencyId>,
+    fiat_id: Option<CurrencyId>,
+    balances: &HashMap<CurrencyId, Balance>,
+    market_info: &MarketInfo,
+    cap: f64,
+    orders: Vec<OrderRecommendation>,
+) -> CappedOrders {
+    let (base_symbol, quote_symbol) = market_info.market_symbols();
+    let unchanged = |orders| CappedOrders {
+        orders,
+        reason: None,
+    };
+
+    let fiat_id = match fiat_id {
+        Some(id) => id,
+        None => {
+            warn!(
+                "Allocation cap of {}-{} is not enforced: unknown allocation fiat",
+                base_symbol, quote_symbol
+            );
+            return unchanged(orders);
+        }
+    };
+    let base_id = market_info.base.currency_id;
+    let base_rate = match graph.rate_between(base_id, fiat_id) {
+        Some(rate) => rate,
+        None => {
+            warn!(
+                "Allocation cap of {}-{} is not enforced: {} can't be converted into the allocation fiat",
+                base_symbol, quote_symbol, base_symbol
+            );
+            return unchanged(orders);
+        }
+    };
+
+    let values = balances
+        .iter()
+        .filter_map(|(&currency_id, balance)| {
+            let rate = graph.rate_between(currency_id, fiat_id)?;
+            Some((currency_id, (balance.available + balance.pending) * rate))
+        })
+        .collect_vec();
+    let total_value = values.iter().map(|(_, value)| value).sum::<f64>();
+    let share = match common::allocation::share_of(&values, &base_id) {
+        Some(share) => share,
+        None => {
+            warn!(
+                "Allocation cap of {}-{} is not enforced: portfolio value is near zero",
+                base_symbol, quote_symbol
+            );
+            return unchanged(orders);
+        }
+    };
+
+    cap_buy_orders(orders, share * total_value, total_value, base_rate, cap)
+}
+
+/// Place `order` on the simulation balances with maker/taker fee applied.
+/// Market orders are filled immediately, while limit orders reserve their spent balance as pending until they are settled.
+/// The order is skipped with a warning if available balance is not enough.
+/// # Returns
+/// `true` if the order is placed
+fn fill_sim_order(
+    current_balances: &mut HashMap<CurrencyId, Balance>,
+    market_info: &MarketInfo,
+    order: &OrderRecommendation,
+    fees: &FeeSchedule,
+) -> bool {
+    let MarketInfo { base, quote, .. } = market_info;
+    let (base_diff, quote_diff) = order.balance_diff();
+    let funds = OrderFunds::from_order(&market_info.market, order);
+
+    let result = match execution::placed_state(order.order_type) {
+        OrderState::Opened => ledger::open(current_balances, &funds),
+        _ => ledger::fill(current_balances, &funds),
+    };
+    if let Err(e) = result {
+        warn!("Too much {:?}. {}, order: {:?}", order.side, e, order);
+        return false;
+    }
+
+    info!(
+        "Market:{}-{} Order:{:?}-{:?} price: {}, fee: {}, expected_net_quote: {}, base_diff:{}, quote_diff:{}",
+        base.symbol,
+        quote.symbol,
+        order.order_type,
+        order.side,
+        order.price,
+        fees.fee_ratio(order.order_type),
+        format_amount(quote, order.expected_net_quote),
+        format_amount(base, base_diff),
+        format_amount(quote, quote_diff),
+    );
+
+    true
+}
+
+/// Generator of transaction ids of simulated orders, like `sim-<stamp_id>-<n>`
+struct SimOrderIds {
+    stamp_id: StampId,
+    count: usize,
+}
+
+impl SimOrderIds {
+    fn new(stamp_id: StampId) -> Self {
+        Self { stamp_id, count: 0 }
+    }
+
+    fn next(&mut self) -> String {
+        let id = format!("sim-{}-{}", self.stamp_id, self.count);
+        self.count += 1;
+        id
+    }
+}
+
+/// Save a placed order in simulation DB.
+/// Market orders are saved as filled, and limit orders as opened until they are settled on the next run
+fn record_sim_order(
+    balance_sim_conn: &Conn,
+    latest_main_stamp: &Stamp,
+    market_id: MarketId,
+    ids: &mut SimOrderIds,
+    order: &OrderRecommendation,
+) -> Result<()> {
+    add_or_update_myorder(
+        balance_sim_conn,
+        ids.next(),
+        market_id,
+        latest_main_stamp.stamp_id,
+        order.price,
+        order.base_quantity,
+        order.quote_quantity,
+        order.order_type,
+        order.side,
+        execution::placed_state(order.order_type),
+    )?;
+    Ok(())
+}
+
+/// Fill `order` on the simulation balances, then save it in simulation DB if filled
+fn place_sim_order(
+    balance_sim_conn: &Conn,
+    latest_main_stamp: &Stamp,
+    ids: &mut SimOrderIds,
+    current_balances: &mut HashMap<CurrencyId, Balance>,
+    market_info: &MarketInfo,
+    order: &OrderRecommendation,
+    fees: &FeeSchedule,
+) {
+    if !fill_sim_order(current_balances, market_info, order, fees) {
+        return;
+    }
+    if let Err(e) = record_sim_order(
+        balance_sim_conn,
+        latest_main_stamp,
+        market_info.market.market_id,
+        ids,
+        order,
+    ) {
+        warn!("Can't save simulated order {:?}: {}", order, e);
+    }
+}
+
+/// Fill or cancel open simulated limit orders of a market,
+/// depending on whether the latest price crossed their limit price.
+/// Pending balance reserved by the orders is consumed or released
+fn settle_open_sim_orders(
+    conn: &Conn,
+    balance_sim_conn: &Conn,
+    latest_main_stamp: &Stamp,
+    market_info: &MarketInfo,
+    current_balances: &mut HashMap<CurrencyId, Balance>,
+    fees: &FeeSchedule,
+) -> Result<()> {
+    let market_id = market_info.market.market_id;
+    let open_myorders = load_open_sim_myorders(balance_sim_conn, market_id)?;
+    if open_myorders.is_empty() {
+        return Ok(());
+    }
+
+    let current_price = schema::price::table
+        .filter(schema::price::market_id.eq(market_id))
+        .filter(schema::price::stamp_id.eq(latest_main_stamp.stamp_id))
+        .select(schema::price::amount)
+        .first::<Amount>(conn)
+        .optional()?;
+    let current_price = match current_price {
+        Some(price) => price,
+        None => {
+            warn!(
+                "No current price of market {}. Open orders are kept",
+                market_id
+            );
+            return Ok(());
+        }
+    };
+
+    for myorder in open_myorders.into_iter() {
+        let state = execution::settle_limit_order(myorder.side, myorder.price, current_price);
+        add_or_update_myorder(
+            balance_sim_conn,
+            myorder.transaction_id.clone(),
+            market_id,
+            latest_main_stamp.stamp_id,
+            myorder.price,
+            myorder.base_quantity,
+            myorder.quote_quantity,
+            myorder.order_type,
+            myorder.side,
+            state,
+        )?;
+
+        let funds = OrderFunds::from_myorder(
+            &market_info.market,
+            &myorder,
+            fees.fee_ratio(myorder.order_type),
+        );
+        match state {
+            OrderState::Filled => ledger::fill_opened(current_balances, &funds),
+            _ => ledger::cancel_opened(current_balances, &funds),
+        }
+        info!(
+            "Simulated order {} is {:?} at {}",
+            myorder.transaction_id, state, current_price
+        );
+    }
+
+    Ok(())
+}
+
+/// Open orders of `market_id` in simulation DB, in order of modification
+fn load_open_sim_myorders(balance_sim_conn: &Conn, market_id: MarketId) -> Result<Vec<MyOrder>> {
+    let filter = MyorderFilter {
+        market_id: Some(market_id),
+        state: Some(OrderState::Opened),
+        ..MyorderFilter::default()
+    };
+    list_myorders(balance_sim_conn, filter)?.apply(Ok)
+}
+
+/// Cancel an open order in simulation DB, then release its reserved balance
+fn cancel_sim_order(
+    balance_sim_conn: &Conn,
+    latest_main_stamp: &Stamp,
+    market_info: &MarketInfo,
+    current_balances: &mut HashMap<CurrencyId, Balance>,
+    myorder: &MyOrder,
+    fees: &FeeSchedule,
+) -> Result<()> {
+    add_or_update_myorder(
+        balance_sim_conn,
+        myorder.transaction_id.clone(),
+        myorder.market_id,
+        latest_main_stamp.stamp_id,
+        myorder.price,
+        myorder.base_quantity,
+        myorder.quote_quantity,
+        myorder.order_type,
+        myorder.side,
+        OrderState::Cancelled,
+    )?;
+
+    let funds = OrderFunds::from_myorder(
+        &market_info.market,
+        myorder,
+        fees.fee_ratio(myorder.order_type),
+    );
+    ledger::cancel_opened(current_balances, &funds);
+
+    let (base_symbol, quote_symbol) = market_info.market_symbols();
+    info!(
+        "Market:{}-{} Cancel:{:?}-{:?} transaction: {}, price: {}",
+        base_symbol,
+        quote_symbol,
+        myorder.order_type,
+        myorder.side,
+        myorder.transaction_id,
+        myorder.price
+    );
+
+    Ok(())
+}
+
+/// Stop orders are held for `STOP_ORDER_EXPIRY_SEC`, a day by default
+fn stop_order_expiry_from_env() -> Result<chrono::Duration> {
+    match env::var("STOP_ORDER_EXPIRY_SEC") {
+        Ok(s) => i64::from_str(&s)?
+            .apply(chrono::Duration::seconds)
+            .apply(Ok),
+        Err(_) => Ok(chrono::Duration::days(1)),
+    }
+}
+
+fn load_held_stop_orders(
+    balance_sim_conn: &Conn,
+    market_id: MarketId,
+) -> Result<Vec<HeldStopOrder>> {
+    schema::stop_order::table
+        .filter(schema::stop_order::market_id.eq(market_id))
+        .order(schema::stop_order::stop_order_id)
+        .load::<StopOrder>(balance_sim_conn)?
+        .into_iter()
+        .map(|stop| HeldStopOrder {
+            order: OrderRecommendation {
+                side: stop.side,
+                order_type: stop.order_type,
+                base_quantity: stop.base_quantity,
+                quote_quantity: stop.quote_quantity,
+                price: stop.price,
+                trigger_price: Some(stop.trigger_price),
+                expected_net_quote: stop.expected_net_quote,
+            },
+            expiry: stop.expiry,
+        })
+        .collect_vec()
+        .apply(Ok)
+}
+
+/// Hold stop orders of `market_id` in simulation DB, in addition to already held ones
+fn add_held_stop_orders(
+    balance_sim_conn: &Conn,
+    market_id: MarketId,
+    held: &[HeldStopOrder],
+) -> Result<()> {
+    let last_id = schema::stop_order::table
+        .select(max(schema::stop_order::stop_order_id))
+        .first::<Option<StopOrderId>>(balance_sim_conn)?
+        .map_or(0, StopOrderId::inner);
+    let stops = held
+        .iter()
+        .zip(last_id + 1..)
+        .filter_map(|(stop, id)| {
+            let trigger_price = stop.order.trigger_price?;
+            Some(StopOrder {
+                stop_order_id: StopOrderId::new(id),
+                market_id,
+                side: stop.order.side,
+                order_type: stop.order.order_type,
+                base_quantity: stop.order.base_quantity,
+                quote_quantity: stop.order.quote_quantity,
+                price: stop.order.price,
+                trigger_price,
+                expected_net_quote: stop.order.expected_net_quote,
+                expiry: stop.expiry,
+            })
+        })
+        .collect_vec();
+    if !stops.is_empty() {
+        insert_into(schema::stop_order::table)
+            .values(&stops)
+            .execute(balance_sim_conn)?;
+    }
+
+    Ok(())
+}
+
+/// Replace stop orders of `market_id` in simulation DB with `held`
+fn replace_held_stop_orders(
+    balance_sim_conn: &Conn,
+    market_id: MarketId,
+    held: &[HeldStopOrder],
+) -> Result<()> {
+    balance_sim_conn.transaction::<_, anyhow::Error, _>(|| {
+        diesel::delete(
+            schema::stop_order::table.filter(schema::stop_order::market_id.eq(market_id)),
+        )
+        .execute(balance_sim_conn)?;
+        add_held_stop_orders(balance_sim_conn, market_id, held)
+    })
+}
+
+/// Hold `new_stops` of `market_id` in simulation DB, replacing held stops of the same side.
+/// Protective stops are recommended on every run, so they would pile up otherwise
+fn supersede_held_stop_orders(
+    balance_sim_conn: &Conn,
+    market_id: MarketId,
+    new_stops: Vec<HeldStopOrder>,
+) -> Result<()> {
+    if new_stops.is_empty() {
+        return Ok(());
+    }
+
+    let held = load_held_stop_orders(balance_sim_conn, market_id)?
+        .into_iter()
+        .filter(|stop| {
+            new_stops
+                .iter()
+                .all(|new| new.order.side != stop.order.side)
+        })
+        .chain(new_stops)
+        .collect_vec();
+    replace_held_stop_orders(balance_sim_conn, market_id, &held)
+}
+
+/// Fill stop orders of a market held in simulation DB whose trigger is crossed by the latest price,
+/// the same way as other orders. Triggered and expired orders are removed from simulation DB.
+fn trigger_held_stop_orders(
+    conn: &Conn,
+    balance_sim_conn: &Conn,
+    latest_main_stamp: &Stamp,
+    ids: &mut SimOrderIds,
+    market_info: &MarketInfo,
+    current_balances: &mut HashMap<CurrencyId, Balance>,
+    fees: &FeeSchedule,
+) -> Result<()> {
+    let market_id = market_info.market.market_id;
+    let held = load_held_stop_orders(balance_sim_conn, market_id)?;
+    if held.is_empty() {
+        return Ok(());
+    }
+
+    let current_price = schema::price::table
+        .filter(schema::price::market_id.eq(market_id))
+        .filter(schema::price::stamp_id.eq(latest_main_stamp.stamp_id))
+        .select(schema::price::amount)
+        .first::<Amount>(conn)
+        .optional()?;
+    let current_price = match current_price {
+        Some(price) => price,
+        None => {
+            warn!(
+                "No current price of market {}. Stop orders are kept",
+                market_id
+            );
+            return Ok(());
+        }
+    };
+
+    let StopOrderUpdate {
+        triggered,
+        held,
+        expired,
+    } = trigger_stop_orders(held, current_price, latest_main_stamp.timestamp);
+    if triggered.is_empty() && expired.is_empty() {
+        return Ok(());
+    }
+    replace_held_stop_orders(balance_sim_conn, market_id, &held)?;
+
+    for stop in expired.iter() {
+        info!("Stop order expired: {:?}", stop.order);
+    }
+    for order in triggered.iter() {
+        info!("Stop order triggered at {}: {:?}", current_price, order);
+        place_sim_order(
+            balance_sim_conn,
+            latest_main_stamp,
+            ids,
+            current_balances,
+            market_info,
+            order,
+            fees,
+        );
+    }
+
+    Ok(())
+}
+
+/// Approvals are left to live execution if `live_execution`, so that they are not consumed twice.
+/// Orders are simulated without approval then
+fn simulate_trade(
+    conn: &Conn,
+    balance_sim_conn: &Conn,
+    latest_main_stamp: Stamp,
+    live_execution: bool,
+) -> Result<()> {
+    let currency_collection = list_currencies(&conn)?;
+    let market_collection = list_markets(&conn)?;
+
+    let mut timings = Timings::new(matches!(env::var("SPECULATOR_TIMINGS").as_deref(), Ok("1")));
+    // Print evaluations of rules of each market as JSON lines
+    let explain = matches!(env::var("SPECULATOR_EXPLAIN").as_deref(), Ok("1"));
+
+    let mut speculators = construct_speculators(&currency_collection, &market_collection)?;
+    check_candlestick_cadence(conn, &speculators)?;
+    load_market_states(
+        conn,
+        latest_main_stamp.clone(),
+        &mut speculators,
+        &mut timings,
+    )?;
+
+    let market_setting = env::var("MARKET_JSON")?
+        .apply(std::fs::File::open)?
+        .apply(MarketSetting::from_reader)?;
+    let (market_fees, fee_errors) =
+        market_setting.market_fees(&currency_collection, &market_collection);
+    for e in fee_errors.into_iter() {
+        warn!("Fee override is ignored: {}", e);
+    }
+    let (min_order_sizes, min_order_size_errors) =
+        market_setting.min_order_sizes(&currency_collection, &market_collection);
+    for e in min_order_size_errors.into_iter() {
+        warn!("Minimum order size is ignored: {}", e);
+    }
+    let exchange_graph = construct_exchange_graph(conn, latest_main_stamp.stamp_id)?;
+    let allocation_fiat_id = currency_collection
+        .by_symbol(&market_setting.allocation_fiat)
+        .map(|c| c.currency_id);
+
+    let mut current_balances = load_latest_sim_balances(&balance_sim_conn, &currency_collection)?;
+    let stop_order_expiry = stop_order_expiry_from_env()?;
+    let mut sim_order_ids = SimOrderIds::new(latest_main_stamp.stamp_id);
+
+    // Approved orders are executed before new recommendations
+    let approval_setting = if live_execution {
+        None
+    } else {
+        approval_setting_from_env(conn)?
+    };
+    if let Some(setting) = approval_setting {
+        execute_approved_orders(
+            conn,
+            &latest_main_stamp,
+            setting,
+            &market_collection,
+            &mut current_balances,
+        )?;
+    }
+
+    // Rules are evaluated in parallel, then orders are executed market by market
+    for (speculator, recommendation) in recommend_markets(speculators, &mut timings).into_iter() {
+        record_recommendations(conn, latest_main_stamp.stamp_id, &recommendation);
+
+        let market_info = speculator.market_info();
+        let MarketInfo { base, quote, .. } = market_info;
+        let fees = market_fees.fee_schedule(&market_info.market);
+
+        // Stale orders on the opposite side are cancelled first, so that only the rest can be settled below
+        let open_myorders =
+            match load_open_sim_myorders(balance_sim_conn, market_info.market.market_id) {
+                Ok(myorders) => myorders,
+                Err(e) => {
+                    warn!(
+                        "Can't load open orders of {}: {}",
+                        speculator.market_label(),
+                        e
+                    );
+                    vec![]
+                }
+            };
+        for CancelRecommendation { transaction_id, .. } in
+            recommendation.recommend_cancels(&open_myorders).iter()
+        {
+            let myorder = open_myorders
+                .iter()
+                .find(|m| &m.transaction_id == transaction_id);
+            if let Some(myorder) = myorder {
+                if let Err(e) = cancel_sim_order(
+                    balance_sim_conn,
+                    &latest_main_stamp,
+                    market_info,
+                    &mut current_balances,
+                    myorder,
+                    &fees,
+                ) {
+                    warn!("Can't cancel order {}: {}", transaction_id, e);
+                }
+            }
+        }
+
+        // Limit orders surviving the cancels are settled before new recommendations
+        if let Err(e) = settle_open_sim_orders(
+            conn,
+            balance_sim_conn,
+            &latest_main_stamp,
+            market_info,
+            &mut current_balances,
+            &fees,
+        ) {
+            warn!(
+                "Can't settle open orders of {}: {}",
+                speculator.market_label(),
+                e
+            );
+        }
+
+        // Held stop orders are triggered before new recommendations
+        if let Err(e) = trigger_held_stop_orders(
+            conn,
+            balance_sim_conn,
+            &latest_main_stamp,
+            &mut sim_order_ids,
+            market_info,
+            &mut current_balances,
+            &fees,
+        ) {
+            warn!(
+                "Can't trigger stop orders of {}: {}",
+                speculator.market_label(),
+                e
+            );
+        }
+
+        let base_balance = match current_balances.get(&base.currency_id).cloned() {
+            Some(b) => b,
+            None => {
+                warn!("Currency {} is not found in balances", base.name);
+                continue;
+            }
+        };
+        let quote_balance = match current_balances.get(&quote.currency_id).cloned() {
+            Some(b) => b,
+            None => {
+                warn!("Currency {} is not found in balances", quote.name);
+                continue;
+            }
+        };
+
+        let holdings =
+            match Holdings::from_balances(&base_balance, &quote_balance, &market_info.market) {
+                Ok(holdings) => holdings,
+                Err(e) => {
+                    warn!("{}", e);
+                    continue;
+                }
+            };
+
+        if explain {
+            match serde_json::to_string(&recommendation.explain()) {
+                Ok(line) => println!("{}", line),
+                Err(e) => warn!("Can't explain {}: {}", speculator.market_label(), e),
+            }
+        }
+
+        let recommendation =
+            recommendation.with_exchange_graph(&exchange_graph, &currency_collection);
+        let orders = recommendation.recommend_orders(&holdings, &fees);
+        let CappedOrders { orders, reason } =
+            match market_setting.max_market_allocation(&speculator.market_label()) {
+                Some(cap) => apply_allocation_cap(
+                    &exchange_graph,
+                    allocation_fiat_id,
+                    &current_balances,
+                    market_info,
+                    cap,
+                    orders,
+                ),
+                None => CappedOrders {
+                    orders,
+                    reason: None,
+                },
+            };
+        // Dust orders are dropped before they reach the balances
+        let orders = min_order_sizes.filter_orders(&market_info.market, orders);
+        let mut reasons = recommendation.reasons();
+        if let Some(reason) = reason {
+            info!("{}: {}", speculator.market_label(), reason);
+            reasons.push(reason);
+        }
+
+        let mut new_stops = vec![];
+        for order in orders.iter() {
+            if order.is_stop() && approval_setting.is_some() {
+                warn!("Stop orders can't await approval. Skipped: {:?}", order);
+                continue;
+            }
+            if let Some(setting) = approval_setting {
+                if let Err(e) = request_approval(
+                    conn,
+                    &latest_main_stamp,
+                    setting,
+                    market_info,
+                    order,
+                    &reasons,
+                ) {
+                    warn!("Can't save order for approval: {}", e);
+                }
+                continue;
+            }
+
+            if order.is_stop() {
+                info!(
+                    "Market:{}-{} Hold stop order:{:?}-{:?} trigger: {:?}, price: {}, base_quantity: {}",
+                    base.symbol,
+                    quote.symbol,
+                    order.order_type,
+                    order.side,
+                    order.trigger_price,
+                    order.price,
+                    format_amount(base, order.base_quantity),
+                );
+                new_stops.push(HeldStopOrder {
+                    order: order.clone(),
+                    expiry: latest_main_stamp.timestamp + stop_order_expiry,
+                });
+                continue;
+            }
+
+            place_sim_order(
+                balance_sim_conn,
+                &latest_main_stamp,
+                &mut sim_order_ids,
+                &mut current_balances,
+                market_info,
+                order,
+                &fees,
+            );
+        }
+
+        if let Err(e) =
+            supersede_held_stop_orders(balance_sim_conn, market_info.market.market_id, new_stops)
+        {
+            warn!(
+                "Can't hold stop orders of {}: {}",
+                speculator.market_label(),
+                e
+            );
+        }
+
+        let recommendation_type = recommendation.recommendation_type();
+        match recommendation_type {
+            RecommendationType::Buy | RecommendationType::Sell => {
+                info!("{:?} reasons:", recommendation_type);
+                for reason in recommendation.reasons() {
+                    info!("{}", reason);
+                }
+            }
+            RecommendationType::Pending | RecommendationType::Neutral => {
+                debug!("{:?} reasons:", recommendation_type);
+                for reason in recommendation.reasons() {
+                    debug!("{}", reason);
+                }
+            }
+        }
+    }
+
+    // Zero balances are omitted
+    let items = current_balances
+        .into_iter()
+        .filter(|(_, b)| b.available != 0.0 || b.pending != 0.0)
+        .map(|(currency_id, b)| (currency_id, b.available, b.pending))
+        .collect_vec();
+    add_balances_bulk(balance_sim_conn, latest_main_stamp.stamp_id, &items)?;
+
+    if timings.is_enabled() {
+        info!("Timings:\n{}", timings.report());
+    }
+
+    Ok(())
+}
+
+/// Simulate trades on each stamp of main DB added since the last run.
+/// Balances are synchronized with main DB on the first run.
+/// Tables of both DBs are created beforehand if `RUN_MIGRATIONS=1`.
+pub fn run() -> Result<()> {
+    info!(
+        "Nicehash speculator started at {} with {}",
+        chrono::Local::now(),
+        common::build_info()
+    );
+
+    let ret = batch();
+
+    info!("Nicehash speculator finished at {}", chrono::Local::now());
+
+    ret
+}
+
+fn batch() -> Result<()> {
+    let url = env::var("DATABASE_URL")?;
+    let conn = Conn::establish(&url)?;
+    let sim_url = env::var("SIM_DATABASE_URL")?;
+    let balance_sim_conn = Conn::establish(&sim_url)?;
+
+    // Bootstrap fresh DBs. Speculator lock is also created by simulation DB migrations
+    if let Ok("1") = env::var("RUN_MIGRATIONS").as_deref() {
+        database::run_migrations(&conn)?;
+        database::run_sim_migrations(&balance_sim_conn)?;
+    }
+
+    // Checked before simulation, so that misconfiguration is reported even without new stamps
+    let execution_setting = executor::ExecutionSetting::from_env()?;
+
+    // Overlapping runs would simulate the same stamps twice
+    let holder = format!("nicehash_speculator pid {}", std::process::id());
+    let now = chrono::Utc::now().naive_utc();
+    let stale_before = now - speculator_lock_stale_from_env()?;
+    if let Some(lock) = try_lock_speculator(&balance_sim_conn, &holder, now, stale_before)? {
+        warn!(
+            "Another speculator run ({}) holds the lock since {}. This run is skipped",
+            lock.holder.unwrap_or_default(),
+            lock.acquired
+                .map(|acquired| acquired.to_string())
+                .unwrap_or_default()
+        );
+        return Ok(());
+    }
+
+    // Orders are placed only once per new stamp, after it is simulated
+    let live_execution = execution_setting.is_some();
+    let ret = simulate_new_stamps(&conn, &balance_sim_conn, live_execution).and_then(|_| {
+        match execution_setting {
+            Some(setting) => execute_orders(&conn, &setting),
+            None => Ok(()),
+        }
+    });
+
+    if let Err(e) = unlock_speculator(&balance_sim_conn, &holder) {
+        warn!("Can't release speculator lock: {}", e);
+    }
+
+    ret
+}
+
+/// Place recommended orders on the exchange. See `executor::ExecutionSetting::from_env` for settings
+fn execute_orders(conn: &Conn, setting: &executor::ExecutionSetting) -> Result<()> {
+    let api_key = nicehash::api_common::ApiKey::from_env()
+        .map_err(|_| anyhow!("Can't load api key from environment variable"))?;
+    let client = executor::NicehashOrderClient::new(api_key);
+    warn!(
+        "Orders are placed on the exchange within {} {} (ALLOW_STACKED_ORDERS={})",
+        setting.max_notional_per_run, setting.notional_fiat, setting.allow_stacked_orders
+    );
+    executor::execute(conn, setting, &client)
+}
+
+/// A lock older than `SPECULATOR_LOCK_STALE_SEC` (an hour by default) is regarded as left by a crashed run
+fn speculator_lock_stale_from_env() -> Result<chrono::Duration> {
+    match env::var("SPECULATOR_LOCK_STALE_SEC") {
+        Ok(s) => i64::from_str(&s)?
+            .apply(chrono::Duration::seconds)
+            .apply(Ok),
+        Err(_) => Ok(chrono::Duration::hours(1)),
+    }
+}
+
+fn simulate_new_stamps(conn: &Conn, balance_sim_conn: &Conn, live_execution: bool) -> Result<()> {
+    let last_sim_stamp_id = schema::balance::table
+        .select(max(schema::balance::stamp_id))
+        .first::<Option<StampId>>(balance_sim_conn)?;
+    let latest_main_stamp = get_latest_stamp(conn)?;
+
+    let last_sim_stamp_id = match last_sim_stamp_id {
+        Some(id) => id,
+        None => return sync_balance(conn, balance_sim_conn, latest_main_stamp),
+    };
+
+    let last_sim_stamp = schema::stamp::table
+        .find(last_sim_stamp_id)
+        .first::<Stamp>(conn)?;
+
+    // Stamps imported later may have larger ids than the last simulated one despite being older
+    let candidates = schema::stamp::table
+        .filter(schema::stamp::stamp_id.gt(last_sim_stamp.stamp_id))
+        .filter(schema::stamp::timestamp.gt(last_sim_stamp.timestamp))
+        .filter(schema::stamp::timestamp.le(latest_main_stamp.timestamp))
+        .order((schema::stamp::timestamp, schema::stamp::stamp_id))
+        .load::<Stamp>(conn)?;
+    let stamps = select_catchup_stamps(
+        candidates,
+        &last_sim_stamp,
+        &latest_main_stamp,
+        max_catchup_stamps_from_env()?,
+    );
+    if stamps.is_empty() {
+        return Err(anyhow!("No new timestamp exists in main DB"));
+    }
+
+    // Each stamp starts from balances written on the previous one
+    for stamp in stamps.into_iter() {
+        info!("Simulate trades at stamp {}", stamp.stamp_id.inner());
+        simulate_trade(conn, balance_sim_conn, stamp, live_execution)?;
+    }
+
+    Ok(())
+}
+
+/// Stamps simulated in a run are at most `MAX_CATCHUP_STAMPS`, 12 by default
+fn max_catchup_stamps_from_env() -> Result<usize> {
+    match env::var("MAX_CATCHUP_STAMPS") {
+        Ok(s) => match usize::from_str(&s)? {
+            0 => Err(anyhow!("MAX_CATCHUP_STAMPS must be positive")),
+            max => Ok(max),
+        },
+        Err(_) => Ok(12),
+    }
+}
+
+/// Stamps of `candidates` to simulate in chronological order.
+/// They are newer than `last_sim_stamp` both in id and timestamp, and not newer than `latest_main_stamp`.
+///
+/// If more than `max_stamps` stamps are found, only the latest `max_stamps` are kept,
+/// so that a long outage doesn't delay simulation of the latest stamp
+fn select_catchup_stamps(
+    candidates: Vec<Stamp>,
+    last_sim_stamp: &Stamp,
+    latest_main_stamp: &Stamp,
+    max_stamps: usize,
+) -> Vec<Stamp> {
+    let stamps = candidates
+        .into_iter()
+        .filter(|stamp| stamp.stamp_id > last_sim_stamp.stamp_id)
+        .filter(|stamp| stamp.timestamp > last_sim_stamp.timestamp)
+        .filter(|stamp| stamp.timestamp <= latest_main_stamp.timestamp)
+        .sorted_by_key(|stamp| (stamp.timestamp, stamp.stamp_id))
+        .collect_vec();
+
+    let skipped = stamps.len().saturating_sub(max_stamps);
+    if skipped > 0 {
+        warn!(
+            "{} stamps are skipped because more than {} stamps are added since the last run",
+            skipped, max_stamps
+        );
+    }
+
+    stamps.into_iter().skip(skipped).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn myorder(id: i32, transaction_id: &str, market_id: i32, modified: i32) -> MyOrder {
+        MyOrder {
+            myorder_id: MyorderId::new(id),
+            transaction_id: transaction_id.into(),
+            market_id: MarketId::new(market_id),
+            created_stamp_id: StampId::new(0),
+            modified_stamp_id: StampId::new(modified),
+            price: 100.0,
+            base_quantity: 1.0,
+            quote_quantity: 100.0,
+            order_type: OrderType::Limit,
+            side: OrderSide::Buy,
+            state: OrderState::Opened,
+        }
+    }
+
+    #[test]
+    fn test_group_myorders() {
+        let myorders = vec![
+            myorder(0, "a", 1, 10),
+            myorder(1, "b", 1, 10),
+            myorder(2, "c", 2, 10),
+            myorder(3, "d", 1, 11),
+        ];
+
+        let group = group_myorders(myorders);
+
+        let ids = |market_id, stamp_id| {
+            group
+                .get(&(MarketId::new(market_id), StampId::new(stamp_id)))
+                .map(|myorders| {
+                    myorders
+                        .iter()
+                        .map(|m| m.transaction_id.as_str())
+                        .collect::<Vec<_>>()
+                })
+        };
+        assert_eq!(3, group.len());
+        // Orders of a market and stamp keep their order
+        assert_eq!(Some(vec!["a", "b"]), ids(1, 10));
+        assert_eq!(Some(vec!["c"]), ids(2, 10));
+        // Grouped by modified stamp, not created stamp
+        assert_eq!(Some(vec!["d"]), ids(1, 11));
+        assert_eq!(None, ids(1, 0));
+    }
+
+    fn stamp(id: i32, minute: u32) -> Stamp {
+        let timestamp = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(0, minute, 0);
+        Stamp::new(StampId::new(id), timestamp)
+    }
+
+    #[test]
+    fn test_build_market_states_earlier_myorders() {
+        let market_id = MarketId::new(1);
+        let stamps = vec![stamp(10, 10), stamp(11, 11), stamp(12, 12)];
+        // No price at the first stamp
+        let price_group = [11, 12]
+            .iter()
+            .map(|&id| {
+                let stamp_id = StampId::new(id);
+                let price = Price::new(PriceId::new(id), market_id, stamp_id, 100.0);
+                ((market_id, stamp_id), price)
+            })
+            .collect::<HashMap<_, _>>();
+        let myorder_group = group_myorders(vec![myorder(2, "c", 1, 12)]);
+        let earlier_myorders = vec![(
+            market_id,
+            vec![myorder(0, "a", 1, 3), myorder(1, "b", 1, 5)],
+        )]
+        .into_iter()
+        .collect();
+
+        let market_states = build_market_states(
+            vec![market_id],
+            &stamps,
+            &price_group,
+            &HashMap::new(),
+            &myorder_group,
+            earlier_myorders,
+        );
+
+        let states = &market_states[&market_id];
+        let transaction_ids = states
+            .iter()
+            .map(|state| {
+                state
+                    .myorders
+                    .iter()
+                    .map(|m| m.transaction_id.as_str())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        // Earlier orders are attached to the first state having a price
+        assert_eq!(vec![vec!["a", "b"], vec!["c"]], transaction_ids);
+        assert_eq!(StampId::new(11), states[0].stamp.stamp_id);
+    }
+
+    fn stamp_ids(stamps: &[Stamp]) -> Vec<i32> {
+        stamps.iter().map(|s| s.stamp_id.inner()).collect()
+    }
+
+    fn scrape_run(stamp_id: i32, price_fetched: bool) -> ScrapeRun {
+        let started_at = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(0, stamp_id as u32, 0);
+        ScrapeRun {
+            stamp_id: StampId::new(stamp_id),
+            started_at,
+            finished_at: started_at,
+            currency_fetched: false,
+            currency_rows: 0,
+            currency_errors: 0,
+            balance_fetched: true,
+            balance_rows: 5,
+            balance_errors: 0,
+            price_fetched,
+            price_rows: if price_fetched { 30 } else { 0 },
+            price_errors: if price_fetched { 0 } else { 1 },
+            orderbook_fetched: true,
+            orderbook_rows: 100,
+            orderbook_errors: 0,
+            myorder_fetched: true,
+            myorder_rows: 0,
+            myorder_errors: 0,
+        }
+    }
+
+    #[test]
+    fn test_skip_stamps_without_prices() {
+        let stamps = (0..5).map(|i| stamp(i, i as u32)).collect::<Vec<_>>();
+        // Stamp 2 has no run
+        let runs = vec![
+            scrape_run(0, true),
+            scrape_run(1, false),
+            scrape_run(3, false),
+            scrape_run(4, true),
+        ];
+
+        let kept = skip_stamps_without_prices(stamps, &runs);
+
+        assert_eq!(vec![0, 2, 4], stamp_ids(&kept));
+    }
+
+    /// Requires `TEST_DATABASE_URL`. Changes are rolled back
+    #[test]
+    #[ignore]
+    fn test_load_market_states_by_stamp_count() {
+        use speculator::rule::{Recommendation, RuleParameter};
+        use speculator::trade::WeightedRule;
+
+        let url = env::var("TEST_DATABASE_URL").unwrap();
+        let conn = Conn::establish(&url).unwrap();
+
+        conn.test_transaction::<_, anyhow::Error, _>(|| {
+            let base = add_currency(&conn, "TESTBASE".into(), "Test base".into())?;
+            let quote = add_currency(&conn, "TESTQUOTE".into(), "Test quote".into())?;
+            let market = add_market(&conn, base.currency_id, quote.currency_id)?;
+            // Bids dominate orderbooks of the latest stamps
+            let mut latest_stamp = None;
+            for hour in 0..3 {
+                let timestamp = chrono::NaiveDate::from_ymd(2037, 1, 1).and_hms(hour, 0, 0);
+                let stamp = add_stamp(&conn, timestamp)?;
+                add_price(&conn, market.market_id, stamp.stamp_id, 1.0)?;
+                let levels = [(OrderSide::Buy, 0.9, 9.0), (OrderSide::Sell, 1.1, 1.0)];
+                add_orderbooks_bulk(&conn, market.market_id, stamp.stamp_id, &levels)?;
+                latest_stamp = Some(stamp);
+            }
+
+            // The rule is alone, so no other rule requires any duration
+            let rule = serde_json::from_str::<Box<dyn RuleParameter>>(
+                r#"{
+                    "algorithm": "orderbookImbalance",
+                    "depthLevels": 1,
+                    "buyImbalanceTrigger": 0.65,
+                    "sellImbalanceTrigger": 0.35,
+                    "smoothingWindow": 2
+                }"#,
+            )?
+            .create_rule(market.clone());
+            let trade_parameter =
+                TradeParameter::new(0.3, 0.3, 0.5, 0.5, 0.5, 0.5, 1.0, 1.0, 1.0, 1.0).unwrap();
+            let aggregation = TradeAggregation::new(
+                MarketInfo::new(market.clone(), base, quote),
+                trade_parameter,
+                vec![WeightedRule::new(rule, 1.0).unwrap()],
+            );
+            let mut aggregations = vec![(market.market_id, aggregation)]
+                .into_iter()
+                .collect::<HashMap<_, _>>();
+
+            load_market_states(
+                &conn,
+                latest_stamp.unwrap(),
+                &mut aggregations,
+                &mut Timings::disabled(),
+            )?;
+
+            let recommendation = aggregations[&market.market_id].recommend();
+            assert_eq!(
+                RecommendationType::Buy,
+                recommendation.source_recommendations()[0].recommendation_type()
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_select_catchup_stamps() {
+        let candidates = vec![stamp(13, 30), stamp(11, 10), stamp(12, 20), stamp(10, 0)];
+
+        let stamps = select_catchup_stamps(candidates, &stamp(10, 0), &stamp(13, 30), 12);
+
+        // Chronological order, excluding the last simulated stamp
+        assert_eq!(vec![11, 12, 13], stamp_ids(&stamps));
+    }
+
+    #[test]
+    fn test_select_catchup_stamps_bounded() {
+        let candidates = (11..=15).map(|id| stamp(id, id as u32)).collect_vec();
+
+        let stamps = select_catchup_stamps(candidates, &stamp(10, 10), &stamp(15, 15), 2);
+
+        assert_eq!(vec![14, 15], stamp_ids(&stamps));
+    }
+
+    #[test]
+    fn test_select_catchup_stamps_newer_than_latest() {
+        // Stamp 13 is an import newer than the latest scraper stamp
+        let candidates = vec![stamp(11, 10), stamp(12, 20), stamp(13, 50)];
+
+        let stamps = select_catchup_stamps(candidates, &stamp(10, 0), &stamp(12, 20), 12);
+
+        assert_eq!(vec![11, 12], stamp_ids(&stamps));
+    }
+
+    #[test]
+    fn test_select_catchup_stamps_up_to_date() {
+        let candidates = vec![stamp(10, 0)];
+
+        let stamps = select_catchup_stamps(candidates, &stamp(10, 0), &stamp(10, 0), 12);
+
+        assert!(stamps.is_empty());
+    }
+
+    #[test]
+    fn test_select_catchup_stamps_older_import() {
+        // Stamp 12 is an import older than the last simulated stamp despite its larger id
+        let candidates = vec![stamp(11, 30), stamp(12, 5), stamp(13, 40)];
+
+        let stamps = select_catchup_stamps(candidates, &stamp(10, 20), &stamp(13, 40), 12);
+
+        assert_eq!(vec![11, 13], stamp_ids(&stamps));
+    }
+
+    #[test]
+    fn test_sim_order_ids() {
+        let mut ids = SimOrderIds::new(StampId::new(42));
+        assert_eq!("sim-42-0", ids.next());
+        assert_eq!("sim-42-1", ids.next());
+    }
+
+    const MARKET_COUNT: i32 = 8;
+    const STAMP_COUNT: i32 = 5000;
+
+    /// Aggregations of RSI rules for `MARKET_COUNT` markets like `C0-USDT`
+    fn rsi_aggregations() -> HashMap<MarketId, TradeAggregation> {
+        let json = r#"{
+            "rules": [
+                {
+                    "algorithm": "rsiCross",
+                    "weight": 0.5,
+                    "candlestickIntervalMin": 15,
+                    "candlestickCount": 14,
+                    "buyTrigger": 30.0,
+                    "sellTrigger": 70.0,
+                    "upperPendingTrigger": 70.0,
+                    "lowerPendingTrigger": 30.0
+                },
+                {
+                    "algorithm": "rsiDivergence",
+                    "weight": 0.5,
+                    "candlestickIntervalMin": 60,
+                    "candlestickCount": 14,
+                    "candlestickMaximaIntervalMin": 3,
+                    "candlestickMaximaIntervalMax": 20,
+                    "upperDivergenceTrigger": 70.0,
+                    "lowerDivergenceTrigger": 30.0
+                }
+            ],
+            "defaultMarkets": ["C0-USDT", "C1-USDT", "C2-USDT", "C3-USDT", "C4-USDT", "C5-USDT", "C6-USDT", "C7-USDT"]
+        }"#;
+        let trade_parameter =
+            TradeParameter::new(0.3, 0.3, 0.5, 0.5, 0.5, 0.5, 1.0, 1.0, 1.0, 1.0).unwrap();
+        let usdt = Currency::new(CurrencyId::new(100), "USDT".into(), "Tether".into());
+        let resolve_market = |market_str: &str| {
+            let base_symbol = market_str.strip_suffix("-USDT")?;
+            let id = i32::from_str(base_symbol.strip_prefix('C')?).ok()?;
+            let base = Currency::new(CurrencyId::new(id), base_symbol.into(), base_symbol.into());
+            let market = Market::new(MarketId::new(id), base.currency_id, usdt.currency_id);
+            Some(MarketInfo::new(market, base, usdt.clone()))
+        };
+
+        TradeAggregationParameter::from_reader(json.as_bytes())
+            .unwrap()
+            .finalize(trade_parameter, resolve_market)
+            .unwrap()
+    }
+
+    /// Minutely market states of `STAMP_COUNT` stamps, oscillating differently for each market
+    fn oscillating_market_states() -> HashMap<MarketId, Vec<MarketState>> {
+        (0..MARKET_COUNT)
+            .map(|id| {
+                let market_id = MarketId::new(id);
+                let states = (0..STAMP_COUNT)
+                    .map(|i| {
+                        let stamp_id = StampId::new(i);
+                        let timestamp = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 0, 0)
+                            + chrono::Duration::minutes(i as i64);
+                        let phase = i as f64 / (200.0 + 30.0 * id as f64);
+                        let amount = 100.0 + 10.0 * phase.sin() + 0.001 * i as f64;
+                        let price = Price::new(PriceId::new(i), market_id, stamp_id, amount);
+                        MarketState::new(Stamp::new(stamp_id, timestamp), price, vec![], vec![])
+                    })
+                    .collect();
+                (market_id, states)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_recommendation_rows() {
+        let json = r#"{
+            "rules": [
+                { "algorithm": "fixed", "weight": 1.0, "side": "buy" },
+                { "algorithm": "fixed", "weight": 0.5, "side": "sell" }
+            ],
+            "defaultMarkets": ["DOGE-USDT"]
+        }"#;
+        let trade_parameter =
+            TradeParameter::new(0.3, 0.3, 0.5, 0.5, 0.5, 0.5, 1.0, 1.0, 1.0, 1.0).unwrap();
+        let base = Currency::new(CurrencyId::new(1), "DOGE".into(), "Dogecoin".into());
+        let quote = Currency::new(CurrencyId::new(2), "USDT".into(), "Tether".into());
+        let market = Market::new(MarketId::new(3), base.currency_id, quote.currency_id);
+        let market_info = MarketInfo::new(market, base, quote);
+        let aggregations = TradeAggregationParameter::from_reader(json.as_bytes())
+            .unwrap()
+            .finalize(trade_parameter, |_| Some(market_info.clone()))
+            .unwrap();
+
+        let rows = recommendation_rows(&aggregations[&MarketId::new(3)].recommend());
+
+        // Rules, followed by the aggregated outcome
+        assert_eq!(3, rows.len());
+        assert!(rows.iter().all(|row| row.0 == MarketId::new(3)));
+        assert_eq!(Some("fixed".to_string()), rows[0].1);
+        assert_eq!(RecommendationType::Buy, rows[0].2);
+        assert_eq!(Some(1.0), rows[0].3);
+        assert_eq!("Based on fixed trade rule", rows[0].4);
+        assert_eq!(RecommendationType::Sell, rows[1].2);
+        assert_eq!(Some(-1.0), rows[1].3);
+        // (1 * 1 - 1 * 0.5) / 1.5, above buy trigger 0.3
+        assert_eq!(None, rows[2].1);
+        assert_eq!(RecommendationType::Buy, rows[2].2);
+        assert!((rows[2].3.unwrap() - 1.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_update_market_states_parallel() {
+        let started = std::time::Instant::now();
+        let mut aggregations = rsi_aggregations();
+        let mut timings = Timings::new(true);
+        update_market_states(&mut aggregations, oscillating_market_states(), &mut timings);
+        let recommendations = recommend_markets(aggregations, &mut timings);
+        let elapsed = started.elapsed();
+
+        // Generous budget for unoptimized builds
+        assert!(
+            elapsed < std::time::Duration::from_secs(10),
+            "Evaluation took {:?}",
+            elapsed
+        );
+        assert_eq!(MARKET_COUNT as usize, recommendations.len());
+        // Timings measured on worker threads are merged
+        let loads = timings
+            .entries()
+            .into_iter()
+            .filter(|(_, item, _)| *item == "load")
+            .count();
+        assert_eq!(MARKET_COUNT as usize, loads);
+
+        // Same result as sequential updates
+        let mut sequential = rsi_aggregations();
+        for (market_id, states) in oscillating_market_states().into_iter() {
+            let aggregation = sequential.get_mut(&market_id).unwrap();
+            for state in states.into_iter() {
+                let _ = aggregation.update_market_state(state);
+            }
+        }
+        for (aggregation, recommendation) in recommendations.iter() {
+            let expected = sequential[&aggregation.market().market_id].recommend();
+            assert_eq!(expected.mean(), recommendation.mean());
+            assert_eq!(
+                expected.recommendation_type(),
+                recommendation.recommendation_type()
+            );
+        }
+    }
+}
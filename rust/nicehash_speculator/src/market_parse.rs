@@ -1,8 +1,399 @@
+use anyhow::{ensure, Result};
+use database::custom_sql_type::{MarketId, OrderType};
+use database::error::SymbolResolutionError;
+use database::logic::{resolve_market_symbol, CurrencyCollection, MarketCollection};
+use database::model::Market;
 use serde::Deserialize;
+use speculator::fee::FeeSchedule;
+use speculator::trade::OrderRecommendation;
+use std::collections::HashMap;
+use std::io::Read;
 use validator::Validate;
 
 #[derive(Debug, Deserialize, Validate)]
 #[serde(rename_all = "camelCase")]
 pub struct MarketSetting {
-    pub fee_ratio: f64,
+    /// Fee of both limit and market orders. Can be omitted if both of maker and taker fees are given
+    #[serde(default)]
+    #[validate(range(min = 0, max = 1.0))]
+    pub fee_ratio: Option<f64>,
+    /// Fee of limit orders. `fee_ratio` is used if omitted
+    #[serde(default)]
+    #[validate(range(min = 0, max = 1.0))]
+    pub maker_fee_ratio: Option<f64>,
+    /// Fee of market orders. `fee_ratio` is used if omitted
+    #[serde(default)]
+    #[validate(range(min = 0, max = 1.0))]
+    pub taker_fee_ratio: Option<f64>,
+    /// Fees of markets overriding the defaults above
+    #[serde(default)]
+    pub market_fees: Vec<MarketFeeSetting>,
+    /// Currency valuing the portfolio for `max_market_allocation`
+    #[serde(default = "default_allocation_fiat")]
+    pub allocation_fiat: String,
+    /// Cap of the base currency's share of the total portfolio value for each market like `DOGE-USDT`.
+    /// Buy orders are reduced or skipped to keep the share within the cap
+    #[serde(default)]
+    pub max_market_allocation: HashMap<String, f64>,
+    /// Minimum order sizes of markets. Orders of markets without them are never dropped
+    #[serde(default)]
+    pub min_order_sizes: Vec<MinOrderSizeSetting>,
+}
+
+/// Fees of a market like promotional rates
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketFeeSetting {
+    /// Market like `BTC-USDT`
+    pub pair: String,
+    #[validate(range(min = 0, max = 1.0))]
+    pub maker_fee: f64,
+    #[validate(range(min = 0, max = 1.0))]
+    pub taker_fee: f64,
+}
+
+/// Minimum order sizes of a market like exchange minimums.
+/// Orders below either of them are dropped, so that dust balances don't produce orders
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct MinOrderSizeSetting {
+    /// Market like `BTC-USDT`
+    pub pair: String,
+    #[serde(default)]
+    #[validate(range(min = 0))]
+    pub min_base_quantity: Option<f64>,
+    #[serde(default)]
+    #[validate(range(min = 0))]
+    pub min_quote_quantity: Option<f64>,
+}
+
+fn default_allocation_fiat() -> String {
+    "USDT".into()
+}
+
+impl MarketSetting {
+    /// Deserialize JSON from `reader`, then validate it
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let setting: Self = serde_json::from_reader(reader)?;
+        setting.validate()?;
+        ensure!(
+            setting.fee_ratio.is_some()
+                || (setting.maker_fee_ratio.is_some() && setting.taker_fee_ratio.is_some()),
+            "feeRatio is required unless both of makerFeeRatio and takerFeeRatio are given"
+        );
+        for market_fee in setting.market_fees.iter() {
+            market_fee.validate()?;
+        }
+        for min_order_size in setting.min_order_sizes.iter() {
+            min_order_size.validate()?;
+        }
+        for (market, cap) in setting.max_market_allocation.iter() {
+            ensure!(
+                *cap > 0.0 && *cap <= 1.0,
+                "Allocation cap of {} must be in (0, 1]: {}",
+                market,
+                cap
+            );
+        }
+        Ok(setting)
+    }
+
+    /// Allocation cap of `market_label` like `DOGE-USDT`. `None` if not capped
+    pub fn max_market_allocation(&self, market_label: &str) -> Option<f64> {
+        self.max_market_allocation.get(market_label).copied()
+    }
+
+    /// Default fees of markets without overrides
+    pub fn fee_schedule(&self) -> FeeSchedule {
+        // Either of fee ratios is given by validation
+        let fee = |ratio: Option<f64>| ratio.or(self.fee_ratio).unwrap_or_default();
+        FeeSchedule::new(fee(self.maker_fee_ratio), fee(self.taker_fee_ratio))
+    }
+
+    /// Resolve pairs of `market_fees`.
+    /// # Returns
+    /// Fees of markets, and errors of pairs which can't be resolved
+    pub fn market_fees(
+        &self,
+        currencies: &CurrencyCollection,
+        markets: &MarketCollection,
+    ) -> (MarketFees, Vec<SymbolResolutionError>) {
+        let mut overrides = HashMap::new();
+        let mut errors = vec![];
+        for market_fee in self.market_fees.iter() {
+            match resolve_market_symbol(&market_fee.pair, currencies, markets) {
+                Ok((_, _, market)) => {
+                    let fees = FeeSchedule::new(market_fee.maker_fee, market_fee.taker_fee);
+                    overrides.insert(market.market_id, fees);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+
+        let fees = MarketFees {
+            default: self.fee_schedule(),
+            overrides,
+        };
+        (fees, errors)
+    }
+
+    /// Resolve pairs of `min_order_sizes`.
+    /// # Returns
+    /// Minimum order sizes of markets, and errors of pairs which can't be resolved
+    pub fn min_order_sizes(
+        &self,
+        currencies: &CurrencyCollection,
+        markets: &MarketCollection,
+    ) -> (MinOrderSizes, Vec<SymbolResolutionError>) {
+        let mut sizes = HashMap::new();
+        let mut errors = vec![];
+        for setting in self.min_order_sizes.iter() {
+            match resolve_market_symbol(&setting.pair, currencies, markets) {
+                Ok((_, _, market)) => {
+                    let size = MinOrderSize {
+                        base_quantity: setting.min_base_quantity.unwrap_or_default(),
+                        quote_quantity: setting.min_quote_quantity.unwrap_or_default(),
+                    };
+                    sizes.insert(market.market_id, size);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+
+        (MinOrderSizes { sizes }, errors)
+    }
+}
+
+/// Fees resolved for each market
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarketFees {
+    default: FeeSchedule,
+    overrides: HashMap<MarketId, FeeSchedule>,
+}
+
+impl MarketFees {
+    pub fn fee_schedule(&self, market: &Market) -> FeeSchedule {
+        self.overrides
+            .get(&market.market_id)
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    /// Maker fee for limit orders, and taker fee for market orders of `market`
+    pub fn fee_for(&self, market: &Market, order_type: OrderType) -> f64 {
+        self.fee_schedule(market).fee_ratio(order_type)
+    }
+}
+
+/// Minimum quantities of an order. Zero means unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MinOrderSize {
+    pub base_quantity: f64,
+    pub quote_quantity: f64,
+}
+
+impl MinOrderSize {
+    pub fn allows(&self, order: &OrderRecommendation) -> bool {
+        order.base_quantity >= self.base_quantity && order.quote_quantity >= self.quote_quantity
+    }
+}
+
+/// Minimum order sizes resolved for each market
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MinOrderSizes {
+    sizes: HashMap<MarketId, MinOrderSize>,
+}
+
+impl MinOrderSizes {
+    pub fn min_order_size(&self, market: &Market) -> MinOrderSize {
+        self.sizes
+            .get(&market.market_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Drop `orders` of `market` below its minimum order size
+    pub fn filter_orders(
+        &self,
+        market: &Market,
+        orders: Vec<OrderRecommendation>,
+    ) -> Vec<OrderRecommendation> {
+        let min_order_size = self.min_order_size(market);
+        orders
+            .into_iter()
+            .filter(|order| {
+                let allowed = min_order_size.allows(order);
+                if !allowed {
+                    debug!(
+                        "Order below minimum size {:?} is dropped: {:?}",
+                        min_order_size, order
+                    );
+                }
+                allowed
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_market_allocation() {
+        let json = r#"{ "feeRatio": 0.003, "maxMarketAllocation": { "DOGE-USDT": 0.05 } }"#;
+
+        let setting = MarketSetting::from_reader(json.as_bytes()).unwrap();
+
+        assert_eq!("USDT", setting.allocation_fiat);
+        assert_eq!(Some(0.05), setting.max_market_allocation("DOGE-USDT"));
+        assert_eq!(None, setting.max_market_allocation("BTC-USDT"));
+
+        let json = r#"{ "feeRatio": 0.003, "maxMarketAllocation": { "DOGE-USDT": 1.5 } }"#;
+        assert!(MarketSetting::from_reader(json.as_bytes()).is_err());
+    }
+
+    fn collections() -> (CurrencyCollection, MarketCollection) {
+        use database::custom_sql_type::CurrencyId;
+        use database::model::Currency;
+
+        let currency =
+            |id, symbol: &str| Currency::new(CurrencyId::new(id), symbol.into(), symbol.into());
+        let currencies = CurrencyCollection::new(vec![
+            currency(1, "BTC"),
+            currency(2, "USDT"),
+            currency(3, "DOGE"),
+        ]);
+        let markets = MarketCollection::new(vec![
+            Market::new(MarketId::new(1), CurrencyId::new(1), CurrencyId::new(2)),
+            Market::new(MarketId::new(2), CurrencyId::new(3), CurrencyId::new(2)),
+        ]);
+        (currencies, markets)
+    }
+
+    #[test]
+    fn test_market_fees() {
+        let json = r#"{
+            "makerFeeRatio": 0.002,
+            "takerFeeRatio": 0.005,
+            "marketFees": [
+                { "pair": "BTC-USDT", "makerFee": 0.001, "takerFee": 0.002 },
+                { "pair": "ETH-USDT", "makerFee": 0.0, "takerFee": 0.0 }
+            ]
+        }"#;
+        let setting = MarketSetting::from_reader(json.as_bytes()).unwrap();
+        assert_eq!(2, setting.market_fees.len());
+
+        let (currencies, markets) = collections();
+        let (fees, errors) = setting.market_fees(&currencies, &markets);
+
+        // Unknown pair is reported, not failed
+        assert_eq!(1, errors.len());
+        assert!(matches!(
+            errors[0],
+            SymbolResolutionError::UnknownBaseCurrency(_)
+        ));
+
+        let btc_usdt = markets.by_id(MarketId::new(1)).unwrap();
+        assert_eq!(0.001, fees.fee_for(btc_usdt, OrderType::Limit));
+        assert_eq!(0.002, fees.fee_for(btc_usdt, OrderType::Market));
+        let doge_usdt = markets.by_id(MarketId::new(2)).unwrap();
+        assert_eq!(0.002, fees.fee_for(doge_usdt, OrderType::Limit));
+        assert_eq!(0.005, fees.fee_for(doge_usdt, OrderType::Market));
+    }
+
+    #[test]
+    fn test_default_fees() {
+        // Single fee ratio is used for both of maker and taker
+        let setting = MarketSetting::from_reader(r#"{ "feeRatio": 0.003 }"#.as_bytes()).unwrap();
+        assert_eq!(FeeSchedule::flat(0.003), setting.fee_schedule());
+
+        let json = r#"{ "feeRatio": 0.003, "makerFeeRatio": 0.001 }"#;
+        let setting = MarketSetting::from_reader(json.as_bytes()).unwrap();
+        assert_eq!(FeeSchedule::new(0.001, 0.003), setting.fee_schedule());
+
+        // Taker fee is unknown
+        let json = r#"{ "makerFeeRatio": 0.001 }"#;
+        assert!(MarketSetting::from_reader(json.as_bytes()).is_err());
+
+        let json = r#"{ "feeRatio": 0.003, "marketFees": [{ "pair": "BTC-USDT", "makerFee": 1.5, "takerFee": 0.0 }] }"#;
+        assert!(MarketSetting::from_reader(json.as_bytes()).is_err());
+    }
+
+    fn order(order_type: OrderType, base_quantity: f64, price: f64) -> OrderRecommendation {
+        use database::custom_sql_type::OrderSide;
+
+        OrderRecommendation {
+            side: OrderSide::Sell,
+            order_type,
+            base_quantity,
+            quote_quantity: base_quantity * price,
+            price,
+            trigger_price: None,
+            expected_net_quote: base_quantity * price,
+        }
+    }
+
+    fn min_order_sizes(json: &str) -> (MinOrderSizes, MarketCollection) {
+        let setting = MarketSetting::from_reader(json.as_bytes()).unwrap();
+        let (currencies, markets) = collections();
+        let (sizes, errors) = setting.min_order_sizes(&currencies, &markets);
+        assert!(errors.is_empty());
+        (sizes, markets)
+    }
+
+    #[test]
+    fn test_min_order_sizes_drop_market_order() {
+        let json = r#"{
+            "feeRatio": 0.003,
+            "minOrderSizes": [{ "pair": "BTC-USDT", "minBaseQuantity": 0.0001 }]
+        }"#;
+        let (sizes, markets) = min_order_sizes(json);
+        let btc_usdt = markets.by_id(MarketId::new(1)).unwrap();
+
+        let orders = vec![
+            order(OrderType::Market, 0.00002, 50000.0),
+            order(OrderType::Limit, 0.0002, 50000.0),
+        ];
+        let orders = sizes.filter_orders(btc_usdt, orders);
+
+        assert_eq!(1, orders.len());
+        assert_eq!(OrderType::Limit, orders[0].order_type);
+    }
+
+    #[test]
+    fn test_min_order_sizes_drop_limit_order() {
+        let json = r#"{
+            "feeRatio": 0.003,
+            "minOrderSizes": [{ "pair": "DOGE-USDT", "minQuoteQuantity": 1.0 }]
+        }"#;
+        let (sizes, markets) = min_order_sizes(json);
+        let doge_usdt = markets.by_id(MarketId::new(2)).unwrap();
+
+        let orders = vec![
+            order(OrderType::Market, 30.0, 0.05),
+            order(OrderType::Limit, 10.0, 0.05),
+        ];
+        let orders = sizes.filter_orders(doge_usdt, orders);
+
+        assert_eq!(1, orders.len());
+        assert_eq!(OrderType::Market, orders[0].order_type);
+    }
+
+    #[test]
+    fn test_min_order_sizes_default() {
+        // Orders of markets without minimums are kept, even if they are dust
+        let (sizes, markets) = min_order_sizes(r#"{ "feeRatio": 0.003 }"#);
+        let btc_usdt = markets.by_id(MarketId::new(1)).unwrap();
+        assert_eq!(MinOrderSize::default(), sizes.min_order_size(btc_usdt));
+
+        let orders = vec![
+            order(OrderType::Market, 0.00000001, 50000.0),
+            order(OrderType::Limit, 0.0, 50000.0),
+        ];
+        assert_eq!(2, sizes.filter_orders(btc_usdt, orders).len());
+
+        let json = r#"{ "feeRatio": 0.003, "minOrderSizes": [{ "pair": "BTC-USDT", "minBaseQuantity": -1.0 }] }"#;
+        assert!(MarketSetting::from_reader(json.as_bytes()).is_err());
+    }
 }
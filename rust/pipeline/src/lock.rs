@@ -0,0 +1,75 @@
+use anyhow::{anyhow, Result};
+use fs2::FileExt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// File-based lock preventing overlapping pipeline instances.
+/// The lock is an advisory `flock` on the file, so the OS releases it when the holder exits or crashes.
+/// The lock file itself is left in place and reused by later instances.
+#[derive(Debug)]
+pub struct PipelineLock {
+    path: PathBuf,
+    file: File,
+}
+
+impl PipelineLock {
+    /// # Returns
+    /// `Err(e)` if another running instance holds the lock
+    pub fn acquire(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&path)
+            .map_err(|e| anyhow!("Can't open lock {:?}: {}", path, e))?;
+        file.try_lock_exclusive()
+            .map_err(|e| anyhow!("Can't acquire lock {:?}: {}", path, e))?;
+
+        // PID of the holder, for diagnostics only
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+
+        Ok(Self { path, file })
+    }
+}
+
+impl Drop for PipelineLock {
+    fn drop(&mut self) {
+        if let Err(e) = self.file.unlock() {
+            warn!("Can't release lock {:?}: {}", self.path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acquire() {
+        let path = std::env::temp_dir().join("asset_management_pipeline_test.lock");
+        let _ = std::fs::remove_file(&path);
+
+        let lock = PipelineLock::acquire(&path).unwrap();
+        // Overlapping instance is denied
+        assert!(PipelineLock::acquire(&path).is_err());
+
+        // Lock is available again after release
+        drop(lock);
+        assert!(PipelineLock::acquire(&path).is_ok());
+    }
+
+    #[test]
+    fn test_acquire_stale_lock_file() {
+        let path = std::env::temp_dir().join("asset_management_pipeline_stale_test.lock");
+        // Left by a crashed instance which no longer holds the lock
+        std::fs::write(&path, "4294967295\n").unwrap();
+
+        let _lock = PipelineLock::acquire(&path).unwrap();
+
+        let pid = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(format!("{}\n", std::process::id()), pid);
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::{Error, Result};
+use lock::PipelineLock;
+use nicehash::api_common::is_service_unavailable;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+#[macro_use]
+extern crate log;
+
+mod lock;
+
+struct PipelineSetting {
+    interval: Duration,
+    stage_timeout: Duration,
+    lock_path: PathBuf,
+}
+
+impl PipelineSetting {
+    fn from_env() -> Result<Self> {
+        let secs = |key: &str, default: u64| -> Result<Duration> {
+            match env::var(key) {
+                Ok(s) => u64::from_str(&s)
+                    .map(Duration::from_secs)
+                    .map_err(Error::from),
+                Err(_) => Ok(Duration::from_secs(default)),
+            }
+        };
+
+        let interval = secs("PIPELINE_INTERVAL_SEC", 300)?;
+        let stage_timeout = secs("PIPELINE_STAGE_TIMEOUT_SEC", 240)?;
+        let lock_path = env::var("PIPELINE_LOCK_FILE")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| env::temp_dir().join("asset_management_pipeline.lock"));
+
+        Ok(Self {
+            interval,
+            stage_timeout,
+            lock_path,
+        })
+    }
+}
+
+#[derive(Debug)]
+enum StageOutcome {
+    Succeeded,
+    Failed(Error),
+    /// Failed because the exchange is in maintenance
+    Unavailable(Error),
+    TimedOut,
+    /// Not executed because of the previous stage's failure
+    Skipped,
+}
+
+impl StageOutcome {
+    fn is_succeeded(&self) -> bool {
+        matches!(self, StageOutcome::Succeeded)
+    }
+}
+
+/// Run `stage` on another thread and wait for it up to `timeout`.
+///
+/// NOTE: A timed-out stage can't be killed, so its thread is returned
+/// to make the next cycle wait for it.
+fn run_stage(
+    name: &'static str,
+    timeout: Duration,
+    stage: fn() -> Result<()>,
+) -> (StageOutcome, Option<JoinHandle<()>>) {
+    let (sender, receiver) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        sender.send(stage()).ok();
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(Ok(())) => (StageOutcome::Succeeded, None),
+        Ok(Err(e)) if is_service_unavailable(&e) => {
+            warn!("Stage {} is unavailable: {}", name, e);
+            (StageOutcome::Unavailable(e), None)
+        }
+        Ok(Err(e)) => {
+            error!("Stage {} failed: {}", name, e);
+            (StageOutcome::Failed(e), None)
+        }
+        Err(_) => {
+            error!("Stage {} timed out after {:?}", name, timeout);
+            (StageOutcome::TimedOut, Some(handle))
+        }
+    }
+}
+
+/// Sleep until `deadline` unless shutdown is requested
+fn sleep_until(deadline: Instant, shutdown: &AtomicBool) {
+    while !shutdown.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if now >= deadline {
+            return;
+        }
+        thread::sleep((deadline - now).min(Duration::from_secs(1)));
+    }
+}
+
+fn run_pipeline(setting: &PipelineSetting, shutdown: &AtomicBool) {
+    let mut hung_stages: Vec<JoinHandle<()>> = vec![];
+
+    while !shutdown.load(Ordering::Relaxed) {
+        let cycle_started = Instant::now();
+        info!("Pipeline cycle started at {}", chrono::Local::now());
+
+        hung_stages.retain(|handle| !handle.is_finished());
+        if !hung_stages.is_empty() {
+            warn!("Skip cycle because a timed-out stage is still running");
+            sleep_until(cycle_started + setting.interval, shutdown);
+            continue;
+        }
+
+        let (scrape, hung) = run_stage("scrape", setting.stage_timeout, nicehash_scraper::run);
+        hung_stages.extend(hung);
+
+        // Speculate only on a fully scraped stamp
+        let speculate = if !scrape.is_succeeded() {
+            StageOutcome::Skipped
+        } else if shutdown.load(Ordering::Relaxed) {
+            info!("Shutdown requested. Skip speculate stage");
+            StageOutcome::Skipped
+        } else {
+            let (speculate, hung) =
+                run_stage("speculate", setting.stage_timeout, nicehash_speculator::run);
+            hung_stages.extend(hung);
+            speculate
+        };
+
+        info!(
+            "Pipeline cycle report: scrape: {:?}, speculate: {:?}, elapsed: {:?}",
+            scrape,
+            speculate,
+            cycle_started.elapsed()
+        );
+
+        sleep_until(cycle_started + setting.interval, shutdown);
+    }
+}
+
+fn main() {
+    dotenv::dotenv().ok();
+
+    env_logger::init();
+
+    let setting = match PipelineSetting::from_env() {
+        Ok(setting) => setting,
+        Err(e) => {
+            error!("Can't load pipeline setting: {}", e);
+            return;
+        }
+    };
+
+    let _lock = match PipelineLock::acquire(&setting.lock_path) {
+        Ok(lock) => lock,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+
+    // Stop between stages on SIGTERM/SIGINT
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for &signal in [SIGTERM, SIGINT].iter() {
+        if let Err(e) = signal_hook::flag::register(signal, shutdown.clone()) {
+            error!("Can't register signal handler: {}", e);
+            return;
+        }
+    }
+
+    info!(
+        "Pipeline started at {} with {}",
+        chrono::Local::now(),
+        common::build_info()
+    );
+
+    run_pipeline(&setting, &shutdown);
+
+    info!("Pipeline finished at {}", chrono::Local::now());
+}
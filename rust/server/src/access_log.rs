@@ -0,0 +1,77 @@
+use anyhow::Result;
+use hyper::{Body, Request, Response};
+use log::Level;
+use std::future::Future;
+use std::time::Instant;
+
+/// Respond to `req` by `respond`, logging method, path, status and elapsed time.
+/// Streamed responses like `/events` are logged when their headers are ready
+pub async fn logged<F, R>(req: Request<Body>, respond: F) -> Result<Response<Body>>
+where
+    F: FnOnce(Request<Body>) -> R,
+    R: Future<Output = Result<Response<Body>>>,
+{
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let start = Instant::now();
+
+    let response = respond(req).await;
+
+    let elapsed_ms = start.elapsed().as_millis();
+    match response.as_ref() {
+        Ok(response) => log!(
+            level(&path),
+            "{} {} {} {}ms",
+            method,
+            path,
+            response.status().as_u16(),
+            elapsed_ms
+        ),
+        Err(e) => warn!("{} {} failed in {}ms: {}", method, path, elapsed_ms, e),
+    }
+
+    response
+}
+
+/// Health checks are polled by probes, so they are logged at debug level
+fn level(path: &str) -> Level {
+    if path.ends_with("/api/health") {
+        Level::Debug
+    } else {
+        Level::Info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::StatusCode;
+
+    #[test]
+    fn test_level() {
+        assert_eq!(Level::Debug, level("/api/health"));
+        assert_eq!(Level::Debug, level("/asset/api/health"));
+        assert_eq!(Level::Info, level("/api/balance_history"));
+        assert_eq!(Level::Info, level("/health.html"));
+    }
+
+    #[tokio::test]
+    async fn test_logged_passes_response() {
+        let req = Request::builder()
+            .uri("/api/status?sim=1")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = logged(req, |req| async move {
+            assert_eq!("/api/status", req.uri().path());
+            Ok(Response::builder()
+                .status(StatusCode::ACCEPTED)
+                .body(Body::empty())
+                .unwrap())
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(StatusCode::ACCEPTED, response.status());
+    }
+}
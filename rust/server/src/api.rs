@@ -1,57 +1,1109 @@
 use std::collections::HashMap;
 use std::str::FromStr;
 
-use crate::exchange_graph::ExchangeGraph;
-use anyhow::Result;
+use crate::auth;
+use crate::balance_current;
+use crate::balance_history;
+use crate::candle;
+use crate::config::ServerConfig;
+use crate::currency_filter;
+use crate::depth::Depth;
+use crate::graph_cache::GraphCache;
+use crate::http::{rejection_message, HttpError};
+use crate::journal;
+use crate::risk;
+use crate::tag_group;
+use crate::window::{
+    downsample, keep_latest, parse_query_limit, parse_query_step, parse_query_timestamp,
+};
+use anyhow::{anyhow, Result};
 use apply::Apply;
 use chrono::{Duration, NaiveDateTime};
+use database::diesel::result::OptionalExtension;
 use database::diesel::QueryDsl;
 use database::diesel::*;
+use database::display::format_amount_with_rate;
+use database::exchange::{ExchangeGraph, FromPrices};
 use database::logic::Conn;
 use database::logic::*;
 use database::model::*;
 use database::schema;
+use hyper::Method;
 use itertools::Itertools;
-use json::JsonValue;
 use qstring::QString;
 use rayon::prelude::*;
-use std::env;
-use std::ops::Deref;
+use server_client::response::*;
 use std::rc::Rc;
 
-pub fn api_balance_history(query: &QString) -> Result<JsonValue> {
-    let (price_conn, balance_conn, _) = connect_db(&query)?;
+/// Number of the latest stamps used to estimate the scraper's cadence
+const CADENCE_SAMPLE_COUNT: i64 = 100;
+
+pub fn api_status(config: &ServerConfig, query: &QString) -> Result<StatusResponse> {
+    let (price_conn, _, _) = connect_db(config, &query)?;
+
+    let latest_stamp = schema::stamp::table
+        .order(schema::stamp::timestamp.desc())
+        .first::<Stamp>(&*price_conn)
+        .optional()?;
+    let latest_scraper_stamp = schema::stamp::table
+        .filter(schema::stamp::source.eq(StampSource::Scraper))
+        .order(schema::stamp::timestamp.desc())
+        .first::<Stamp>(&*price_conn)
+        .optional()?;
+    let cadence = estimate_stamp_cadence(&price_conn, CADENCE_SAMPLE_COUNT)?;
+
+    Ok(StatusResponse {
+        success: true,
+        latest_stamp: latest_stamp.as_ref().map(format_stamp),
+        latest_stamp_source: latest_stamp.map(|stamp| stamp.source.to_string()),
+        latest_scraper_stamp: latest_scraper_stamp.as_ref().map(format_stamp),
+        stamp_cadence_sec: cadence.map(|cadence| cadence.num_seconds()),
+        build: build_info(),
+    })
+}
+
+pub fn api_balance_history(
+    config: &ServerConfig,
+    graphs: &GraphCache,
+    query: &QString,
+) -> Result<BalanceHistoryResponse> {
+    let (price_conn, balance_conn, _) = connect_db(config, &query)?;
 
     let timestamps = {
-        let since = query
-            .get("since")
-            .and_then(|s| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.fZ").ok());
-        let until = query
-            .get("until")
-            .and_then(|s| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.fZ").ok());
+        let since = parse_query_timestamp(query, "since");
+        let until = parse_query_timestamp(query, "until");
         let step = query
             .get("step")
             .and_then(|s| parse_query_step(s))
             .unwrap_or(Duration::days(1));
 
-        get_target_timestamps(&price_conn, since, until, step)
-    }?;
+        // The latest stamps are kept, so that a long range is cut from its old end
+        get_target_timestamps(&price_conn, since, until, step)?
+            .apply(|timestamps| keep_latest(timestamps, parse_query_limit(query)))
+    };
 
     let currency_collection = list_currencies(&price_conn)?;
+    let fiat_currency = balance_current::resolve_fiat(&currency_collection, query.get("fiat"))?;
 
-    let fiat_symbol = query.get("fiat");
-    let fiat_currency = fiat_symbol
+    // Resolve symbols once, so that balances are filtered by SQL
+    let strict = matches!(query.get("strict"), Some("1"));
+    let currency_filter = query
+        .get("symbols")
+        .map(|symbols| {
+            currency_filter::resolve_symbols(currency_collection.currencies(), symbols, strict)
+        })
+        .transpose()?;
+    if let Some(currency_filter) = currency_filter.as_ref() {
+        for symbol in currency_filter.ignored_symbols.iter() {
+            warn!("Unknown currency {} is ignored", symbol);
+        }
+    }
+    let exclude_zero = matches!(query.get("exclude_zero"), Some("1"));
+
+    // Tags are of main DB even if balances are simulated
+    let primary_tags = if tag_group::is_grouped_by_tag(query.get("group_by"))? {
+        if fiat_currency.is_none() {
+            return Err(HttpError::bad_request("group_by=tag requires fiat").into());
+        }
+        Some(primary_currency_tags(&list_currency_tags(&price_conn)?))
+    } else {
+        None
+    };
+
+    let filter_currency_ids = currency_filter
         .as_ref()
-        .and_then(|symbol| currency_collection.by_symbol(symbol));
+        .map(|filter| filter.currency_ids.as_slice());
+    // Fiat total covers all currencies, so the breakdown is filtered after loading
+    let history = load_balance_history(
+        &price_conn,
+        &balance_conn,
+        graphs,
+        timestamps,
+        fiat_currency,
+        filter_currency_ids.filter(|_| fiat_currency.is_none()),
+    )?;
+
+    let history = history
+        .into_iter()
+        .map(|(stamp, balances, rates)| {
+            let summary = balance_history::summarize(
+                balances,
+                rates,
+                filter_currency_ids,
+                &currency_collection,
+            );
+            let groups = match primary_tags.as_ref() {
+                Some(primary_tags) => {
+                    let values = summary
+                        .balances
+                        .iter()
+                        .filter_map(|(balance, rate)| {
+                            let symbol = currency_collection
+                                .by_id(balance.currency_id)?
+                                .symbol
+                                .clone();
+                            Some((
+                                balance.currency_id,
+                                symbol,
+                                balance_value(balance, (*rate)?),
+                            ))
+                        })
+                        .collect_vec();
+                    tag_group::group_by_tag(&values, primary_tags)
+                }
+                None => vec![],
+            };
+            let currencies = summary
+                .balances
+                .into_iter()
+                .filter(|(balance, _)| !exclude_zero || balance.available + balance.pending != 0.0)
+                .filter_map(|(balance, rate)| {
+                    let currency = currency_collection.by_id(balance.currency_id)?;
+                    CurrencyBalance {
+                        name: currency.name.clone(),
+                        symbol: currency.symbol.clone(),
+                        available: balance.available,
+                        pending: balance.pending,
+                        available_display: format_amount_with_rate(
+                            currency,
+                            balance.available,
+                            rate,
+                        ),
+                        pending_display: format_amount_with_rate(currency, balance.pending, rate),
+                        rate,
+                    }
+                    .apply(Some)
+                })
+                .sorted_by(|c1, c2| c1.symbol.cmp(&c2.symbol))
+                .collect();
+            BalanceHistoryEntry {
+                stamp: format_stamp(&stamp),
+                currencies,
+                groups,
+                total_fiat: fiat_currency.map(|_| summary.total_fiat),
+                missing_rate_symbols: fiat_currency
+                    .map(|_| summary.missing_rate_symbols)
+                    .unwrap_or_default(),
+            }
+        })
+        .collect();
+
+    let filter = BalanceHistoryFilter {
+        symbols: currency_filter
+            .as_ref()
+            .map(|filter| filter.symbols.clone()),
+        ignored_symbols: currency_filter
+            .map(|filter| filter.ignored_symbols)
+            .unwrap_or_default(),
+        exclude_zero,
+    };
+
+    Ok(BalanceHistoryResponse {
+        success: true,
+        history,
+        filter,
+    })
+}
+
+/// Balances at the latest stamp having balances, with their values in `fiat` if specified
+pub fn api_balance_current(
+    config: &ServerConfig,
+    query: &QString,
+) -> Result<BalanceCurrentResponse> {
+    let (price_conn, balance_conn, _) = connect_db(config, &query)?;
+
+    let currency_collection = list_currencies(&price_conn)?;
+    let fiat_currency = balance_current::resolve_fiat(&currency_collection, query.get("fiat"))?;
+    let include_zero = matches!(query.get("include_zero"), Some("1"));
+
+    // Simulated balances refer stamps of main DB
+    let latest_stamp_id = schema::balance::table
+        .select(dsl::max(schema::balance::stamp_id))
+        .first::<Option<StampId>>(&*balance_conn)?;
+    let latest_stamp = match latest_stamp_id {
+        Some(stamp_id) => schema::stamp::table
+            .find(stamp_id)
+            .first::<Stamp>(&*price_conn)?,
+        None => {
+            return Ok(BalanceCurrentResponse {
+                success: true,
+                stamp: None,
+                fiat: fiat_currency.map(|c| c.symbol.clone()),
+                currencies: vec![],
+                total_value: fiat_currency.map(|_| 0.0),
+            })
+        }
+    };
+
+    let balances = schema::balance::table
+        .filter(schema::balance::stamp_id.eq(latest_stamp.stamp_id))
+        .load::<Balance>(&*balance_conn)?;
+    let graph = match fiat_currency {
+        Some(_) => construct_exchange_graph(&price_conn, latest_stamp.stamp_id)?,
+        None => ExchangeGraph::from_rates(std::iter::empty()),
+    };
+    let currencies = balance_current::current_balances(
+        &balances,
+        &currency_collection,
+        &graph,
+        fiat_currency.map(|c| c.currency_id),
+        include_zero,
+    );
+
+    Ok(BalanceCurrentResponse {
+        success: true,
+        stamp: Some(format_stamp(&latest_stamp)),
+        fiat: fiat_currency.map(|c| c.symbol.clone()),
+        total_value: fiat_currency.map(|_| balance_current::total_value(&currencies)),
+        currencies,
+    })
+}
+
+pub fn api_inconsistent_cycles(
+    config: &ServerConfig,
+    query: &QString,
+) -> Result<InconsistentCyclesResponse> {
+    let (price_conn, _, _) = connect_db(config, &query)?;
+
+    let max_len = query
+        .get("max_len")
+        .and_then(|s| usize::from_str(s).ok())
+        .unwrap_or(4);
+    let threshold = query
+        .get("threshold")
+        .and_then(|s| f64::from_str(s).ok())
+        .unwrap_or(0.01);
+
+    let latest_stamp = schema::stamp::table
+        .order(schema::stamp::timestamp.desc())
+        .first::<Stamp>(&*price_conn)?;
+    let currency_collection = list_currencies(&price_conn)?;
+    let reports = construct_exchange_graph(&price_conn, latest_stamp.stamp_id)?
+        .find_inconsistent_cycles(max_len, threshold);
+
+    for report in reports.iter() {
+        warn!(
+            "Inconsistent cycle at stamp {}: {:?} product: {}",
+            latest_stamp.stamp_id, report.currencies, report.rate_product
+        );
+    }
+
+    let cycles = reports
+        .into_iter()
+        .map(|report| {
+            let currencies = report
+                .currencies
+                .into_iter()
+                .map(|currency_id| match currency_collection.by_id(currency_id) {
+                    Some(currency) => currency.symbol.clone(),
+                    None => currency_id.to_string(),
+                })
+                .collect();
+            InconsistentCycle {
+                currencies,
+                rate_product: report.rate_product,
+            }
+        })
+        .collect();
+
+    Ok(InconsistentCyclesResponse {
+        success: true,
+        stamp: format_stamp(&latest_stamp),
+        cycles,
+    })
+}
+
+/// Bids and asks of a market at `stamp`, which is an id or `latest`.
+/// The latest stamp is the latest one having orderbooks of the market, or simply the latest stamp if none has
+pub fn api_orderbook_depth(
+    config: &ServerConfig,
+    query: &QString,
+) -> Result<OrderbookDepthResponse> {
+    let (price_conn, _, _) = connect_db(config, &query)?;
+
+    let market = resolve_market_query(&price_conn, query)?;
+
+    let stamp: Stamp = match query.get("stamp") {
+        None | Some("latest") => {
+            let latest_orderbook_stamp_id = schema::orderbook::table
+                .filter(schema::orderbook::market_id.eq(market.market_id))
+                .select(dsl::max(schema::orderbook::stamp_id))
+                .first::<Option<StampId>>(&*price_conn)?;
+            match latest_orderbook_stamp_id {
+                Some(stamp_id) => schema::stamp::table.find(stamp_id).first(&*price_conn)?,
+                None => schema::stamp::table
+                    .order(schema::stamp::timestamp.desc())
+                    .first(&*price_conn)?,
+            }
+        }
+        Some(id) => schema::stamp::table
+            .find(i32::from_str(id)?.apply(StampId::new))
+            .first(&*price_conn)?,
+    };
+
+    let orderbooks = schema::orderbook::table
+        .filter(schema::orderbook::market_id.eq(market.market_id))
+        .filter(schema::orderbook::stamp_id.eq(stamp.stamp_id))
+        .load::<Orderbook>(&*price_conn)?;
+    let depth = Depth::from_orderbooks(&orderbooks);
+
+    Ok(OrderbookDepthResponse {
+        success: true,
+        stamp: format_stamp(&stamp),
+        levels_available: !orderbooks.is_empty(),
+        mid_price: depth.mid_price(),
+        spread: depth.spread(),
+        bids: depth.bids.into_iter().map(Into::into).collect(),
+        asks: depth.asks.into_iter().map(Into::into).collect(),
+    })
+}
+
+/// Default number of events per page of `api/market_journal`
+const JOURNAL_PAGE_LIMIT: usize = 500;
+
+pub fn api_market_journal(config: &ServerConfig, query: &QString) -> Result<MarketJournalResponse> {
+    let (price_conn, _, _) = connect_db(config, &query)?;
+
+    let market = resolve_market_query(&price_conn, query)?;
+    let market_str = market_query(query)?;
+    let since = parse_query_timestamp(query, "since");
+    let until = parse_query_timestamp(query, "until");
+    let cursor = query
+        .get("cursor")
+        .map(|s| i32::from_str(s).map(StampId::new))
+        .transpose()?;
+    let limit = parse_query_limit(query).unwrap_or(JOURNAL_PAGE_LIMIT);
+
+    // Each stamp has at least one event if the market is scraped, so `limit` stamps are enough for a page.
+    // One more stamp is loaded to know whether the next page exists
+    let mut stamps = {
+        let mut stamps = schema::stamp::table.into_boxed();
+        if let Some(since) = since {
+            stamps = stamps.filter(schema::stamp::timestamp.ge(since));
+        }
+        if let Some(until) = until {
+            stamps = stamps.filter(schema::stamp::timestamp.le(until));
+        }
+        if let Some(cursor) = cursor {
+            stamps = stamps.filter(schema::stamp::stamp_id.ge(cursor));
+        }
+        stamps
+            .order(schema::stamp::stamp_id.asc())
+            .limit(limit as i64 + 1)
+            .load::<Stamp>(&*price_conn)?
+    };
+    let following_stamp_id = if stamps.len() > limit {
+        stamps.pop().map(|stamp| stamp.stamp_id)
+    } else {
+        None
+    };
+    let (first_stamp_id, last_stamp_id) = match (stamps.first(), stamps.last()) {
+        (Some(first), Some(last)) => (first.stamp_id, last.stamp_id),
+        _ => {
+            return Ok(MarketJournalResponse {
+                success: true,
+                market: market_str,
+                events: vec![],
+                next_cursor: None,
+            })
+        }
+    };
+    let stamp_map = stamps
+        .into_iter()
+        .map(|stamp| (stamp.stamp_id, stamp))
+        .collect::<HashMap<_, _>>();
+
+    let prices = schema::price::table
+        .filter(schema::price::market_id.eq(market.market_id))
+        .filter(schema::price::stamp_id.between(first_stamp_id, last_stamp_id))
+        .order(schema::price::stamp_id.asc())
+        .load::<Price>(&*price_conn)?
+        .into_iter()
+        .filter(|price| stamp_map.contains_key(&price.stamp_id))
+        .map(|price| {
+            journal::Event::new(
+                price.stamp_id,
+                journal::EventKind::Price,
+                JournalPayload::Price(JournalPrice {
+                    price: price.amount,
+                }),
+            )
+        })
+        .collect_vec();
+
+    let order_filter = MyorderFilter {
+        market_id: Some(market.market_id),
+        stamp_range: Some((first_stamp_id, last_stamp_id)),
+        ..Default::default()
+    };
+    let orders = list_myorders(&*price_conn, order_filter)?
+        .into_iter()
+        .flat_map(|myorder| {
+            let updated = if myorder.modified_stamp_id != myorder.created_stamp_id {
+                Some((myorder.modified_stamp_id, "updated"))
+            } else {
+                None
+            };
+            std::iter::once((myorder.created_stamp_id, "opened"))
+                .chain(updated)
+                .map(move |(stamp_id, event)| (stamp_id, event, myorder.clone()))
+        })
+        .filter(|(stamp_id, _, _)| stamp_map.contains_key(stamp_id))
+        .sorted_by_key(|(stamp_id, _, myorder)| (*stamp_id, myorder.myorder_id))
+        .map(|(stamp_id, event, myorder)| {
+            journal::Event::new(
+                stamp_id,
+                journal::EventKind::Order,
+                JournalPayload::Order(JournalOrder {
+                    transaction_id: myorder.transaction_id,
+                    event: event.to_string(),
+                    order_type: format!("{:?}", myorder.order_type),
+                    side: format!("{:?}", myorder.side),
+                    state: format!("{:?}", myorder.state),
+                    price: myorder.price,
+                    base_quantity: myorder.base_quantity,
+                    quote_quantity: myorder.quote_quantity,
+                }),
+            )
+        })
+        .collect_vec();
+
+    // Recommendations are not persisted yet
+    let recommendations = vec![];
+
+    let events = journal::merge_events(prices, recommendations, orders);
+    let (page, cursor) = journal::paginate(events, limit);
+
+    let events = page
+        .into_iter()
+        .map(|event| JournalEntry {
+            stamp: format_stamp(&stamp_map[&event.stamp]),
+            stamp_id: event.stamp.inner(),
+            stamp_source: stamp_map[&event.stamp].source.to_string(),
+            kind: match event.kind {
+                journal::EventKind::Price => JournalEventKind::Price,
+                journal::EventKind::Recommendation => JournalEventKind::Recommendation,
+                journal::EventKind::Order => JournalEventKind::Order,
+            },
+            payload: event.payload,
+        })
+        .collect();
+
+    Ok(MarketJournalResponse {
+        success: true,
+        market: market_str,
+        events,
+        next_cursor: cursor.or(following_stamp_id).map(StampId::inner),
+    })
+}
+
+/// Default range of `api/price_history`
+const PRICE_HISTORY_DEFAULT_DAYS: i64 = 1;
+
+/// Prices of a market, or candles derived from them if `candle_interval` like `1_hour` is specified.
+/// Prices are downsampled like `api/balance_history` if `step` is specified.
+///
+/// Unknown market is reported by `success: false` with message. Nothing is listed if no stamp exists
+pub fn api_price_history(config: &ServerConfig, query: &QString) -> Result<PriceHistoryResponse> {
+    let (price_conn, _, _) = connect_db(config, &query)?;
+
+    let market_str = market_query(query).unwrap_or_default();
+    let market = match resolve_market_str(&price_conn, &market_str) {
+        Ok(market) => market,
+        Err(e) => {
+            return Ok(PriceHistoryResponse {
+                success: false,
+                message: Some(rejection_message(e)?),
+                market: market_str,
+                candle_interval_sec: None,
+                prices: vec![],
+                candles: vec![],
+            })
+        }
+    };
+    let until = parse_query_timestamp(query, "until");
+    let since = parse_query_timestamp(query, "since");
+    let candle_interval = query
+        .get("candle_interval")
+        .map(|s| {
+            // Candles can't be derived by non-positive interval
+            parse_query_step(s)
+                .filter(|interval| *interval > Duration::zero())
+                .ok_or_else(|| HttpError::bad_request(format!("Invalid candle_interval: {}", s)))
+        })
+        .transpose()?;
+    let step = query
+        .get("step")
+        .map(|s| {
+            parse_query_step(s)
+                .ok_or_else(|| HttpError::bad_request(format!("Invalid step: {}", s)))
+        })
+        .transpose()?;
+
+    let until = match until {
+        Some(until) => until,
+        None => match latest_timestamp(&price_conn)? {
+            Some(until) => until,
+            None => {
+                return Ok(PriceHistoryResponse {
+                    success: true,
+                    message: None,
+                    market: market_str,
+                    candle_interval_sec: candle_interval.map(|interval| interval.num_seconds()),
+                    prices: vec![],
+                    candles: vec![],
+                })
+            }
+        },
+    };
+    let since = since.unwrap_or(until - Duration::days(PRICE_HISTORY_DEFAULT_DAYS));
+
+    let prices = schema::price::table
+        .inner_join(schema::stamp::table.on(schema::price::stamp_id.eq(schema::stamp::stamp_id)))
+        .filter(schema::price::market_id.eq(market.market_id))
+        .filter(schema::stamp::timestamp.between(since, until))
+        .order(schema::stamp::timestamp.asc())
+        .select((schema::stamp::timestamp, schema::price::amount))
+        .load::<(NaiveDateTime, Amount)>(&*price_conn)?;
+
+    let (prices, candles) = match candle_interval {
+        Some(interval) => {
+            let candles = candle::derive_candles(&prices, interval)?
+                .into_iter()
+                .map(|candle| CandleEntry {
+                    open_time: format_timestamp(&candle.open_time),
+                    open: candle.open,
+                    high: candle.high,
+                    low: candle.low,
+                    close: candle.close,
+                    count: candle.count,
+                })
+                .collect();
+            (vec![], candles)
+        }
+        None => {
+            let prices = match step {
+                Some(step) => downsample(prices, step, |(timestamp, _)| *timestamp),
+                None => prices,
+            };
+            let prices = prices
+                .into_iter()
+                .map(|(timestamp, price)| PricePoint {
+                    stamp: format_timestamp(&timestamp),
+                    price,
+                })
+                .collect();
+            (prices, vec![])
+        }
+    };
+
+    Ok(PriceHistoryResponse {
+        success: true,
+        message: None,
+        market: market_str,
+        candle_interval_sec: candle_interval.map(|interval| interval.num_seconds()),
+        prices,
+        candles,
+    })
+}
+
+/// Default range of `api/recommendation_history`
+const RECOMMENDATION_HISTORY_DEFAULT_DAYS: i64 = 1;
+
+/// Recommendations recorded by the speculator, each with the price of its market at the stamp,
+/// so that they can be overlaid on the price chart. Recommendations of all markets are listed if market is not specified.
+///
+/// Unknown market is reported by `success: false` with message. Nothing is listed if no stamp exists
+pub fn api_recommendation_history(
+    config: &ServerConfig,
+    query: &QString,
+) -> Result<RecommendationHistoryResponse> {
+    let (price_conn, _, _) = connect_db(config, &query)?;
+
+    let market_str = market_query(query).ok();
+    let market = match market_str.as_ref() {
+        Some(market_str) => match resolve_market_str(&price_conn, market_str) {
+            Ok(market) => Some(market),
+            Err(e) => {
+                return Ok(RecommendationHistoryResponse {
+                    success: false,
+                    message: Some(rejection_message(e)?),
+                    market: Some(market_str.clone()),
+                    recommendations: vec![],
+                })
+            }
+        },
+        None => None,
+    };
+    let until = match parse_query_timestamp(query, "until") {
+        Some(until) => until,
+        None => match latest_timestamp(&price_conn)? {
+            Some(until) => until,
+            None => {
+                return Ok(RecommendationHistoryResponse {
+                    success: true,
+                    message: None,
+                    market: market_str,
+                    recommendations: vec![],
+                })
+            }
+        },
+    };
+    let since = parse_query_timestamp(query, "since")
+        .unwrap_or(until - Duration::days(RECOMMENDATION_HISTORY_DEFAULT_DAYS));
+
+    let currency_collection = list_currencies(&price_conn)?;
+    let market_labels = list_markets(&price_conn)?
+        .markets()
+        .iter()
+        .map(|m| {
+            let symbol = |currency_id| match currency_collection.by_id(currency_id) {
+                Some(currency) => currency.symbol.clone(),
+                None => currency_id.to_string(),
+            };
+            (
+                m.market_id,
+                format!("{}-{}", symbol(m.base_id), symbol(m.quote_id)),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+
+    let recommendations = list_recommendations(
+        &price_conn,
+        market.map(|market| market.market_id),
+        since,
+        until,
+    )?
+    .into_iter()
+    .map(|(recommendation, timestamp, price)| RecommendationEntry {
+        stamp: format_timestamp(&timestamp),
+        market: market_labels
+            .get(&recommendation.market_id)
+            .cloned()
+            .unwrap_or_else(|| recommendation.market_id.to_string()),
+        rule: recommendation.rule_name,
+        recommendation_type: format!("{:?}", recommendation.recommendation_type),
+        score: recommendation.score,
+        reason: recommendation.reason,
+        price,
+    })
+    .collect();
 
+    Ok(RecommendationHistoryResponse {
+        success: true,
+        message: None,
+        market: market_str,
+        recommendations,
+    })
+}
+
+/// Default range of `api/order_history`
+const ORDER_HISTORY_DEFAULT_DAYS: i64 = 7;
+
+/// Orders of a market created or modified in [`since`, `until`], with their state transitions.
+/// Nothing is listed if no stamp exists
+pub fn api_order_history(config: &ServerConfig, query: &QString) -> Result<OrderHistoryResponse> {
+    let (price_conn, _, _) = connect_db(config, &query)?;
+
+    let market = resolve_market_query(&price_conn, query)?;
+    let market_str = market_query(query)?;
+    let until = match parse_query_timestamp(query, "until") {
+        Some(until) => until,
+        None => match latest_timestamp(&price_conn)? {
+            Some(until) => until,
+            None => {
+                return Ok(OrderHistoryResponse {
+                    success: true,
+                    market: market_str,
+                    orders: vec![],
+                })
+            }
+        },
+    };
+    let since = parse_query_timestamp(query, "since")
+        .unwrap_or(until - Duration::days(ORDER_HISTORY_DEFAULT_DAYS));
+
+    let stamp_range = schema::stamp::table
+        .filter(schema::stamp::timestamp.between(since, until))
+        .select((
+            dsl::min(schema::stamp::stamp_id),
+            dsl::max(schema::stamp::stamp_id),
+        ))
+        .first::<(Option<StampId>, Option<StampId>)>(&*price_conn)?;
+    let myorders = match stamp_range {
+        (Some(first), Some(last)) => list_myorders(
+            &price_conn,
+            MyorderFilter {
+                market_id: Some(market.market_id),
+                stamp_range: Some((first, last)),
+                ..Default::default()
+            },
+        )?,
+        _ => vec![],
+    };
+
+    let created_timestamps = schema::stamp::table
+        .filter(
+            schema::stamp::stamp_id.eq_any(
+                myorders
+                    .iter()
+                    .map(|myorder| myorder.created_stamp_id)
+                    .collect_vec(),
+            ),
+        )
+        .load::<Stamp>(&*price_conn)?
+        .into_iter()
+        .map(|stamp| (stamp.stamp_id, stamp.timestamp))
+        .collect::<HashMap<_, _>>();
+    // Transitions of an order follow its creation
+    let mut transitions = match myorders
+        .iter()
+        .map(|myorder| myorder.created_stamp_id)
+        .min()
+    {
+        Some(first_created) => {
+            load_order_state_changes(&price_conn, market.market_id, first_created)?
+        }
+        None => vec![],
+    }
+    .into_iter()
+    .map(|(change, timestamp)| {
+        let transition = OrderTransition {
+            stamp: format_timestamp(&timestamp),
+            from: format!("{:?}", change.old_state),
+            to: format!("{:?}", change.new_state),
+        };
+        (change.myorder_id, transition)
+    })
+    .into_group_map();
+
+    let orders = myorders
+        .into_iter()
+        .map(|myorder| OrderHistoryEntry {
+            created: created_timestamps
+                .get(&myorder.created_stamp_id)
+                .map(format_timestamp),
+            transitions: transitions.remove(&myorder.myorder_id).unwrap_or_default(),
+            transaction_id: myorder.transaction_id,
+            order_type: format!("{:?}", myorder.order_type),
+            side: format!("{:?}", myorder.side),
+            state: format!("{:?}", myorder.state),
+            price: myorder.price,
+            base_quantity: myorder.base_quantity,
+            quote_quantity: myorder.quote_quantity,
+        })
+        .collect();
+
+    Ok(OrderHistoryResponse {
+        success: true,
+        market: market_str,
+        orders,
+    })
+}
+
+/// Approve an order waiting for approval, recording the latest market price to check price drift before execution
+pub fn api_approve_order(
+    config: &ServerConfig,
+    method: &Method,
+    authorization: Option<&str>,
+    query: &QString,
+) -> Result<ApproveOrderResponse> {
+    if method != Method::POST {
+        return Err(HttpError::method_not_allowed("approve_order accepts only POST").into());
+    }
+    auth::authorize(config.api_token.as_deref(), authorization)?;
+
+    let id = query
+        .get("id")
+        .ok_or_else(|| HttpError::bad_request("id is not specified"))?
+        .apply(i32::from_str)?
+        .apply(PendingApprovalId::new);
+
+    let conn = Conn::establish(&config.database_url)?;
+    let approval = schema::pending_approval::table
+        .find(id)
+        .first::<PendingApproval>(&conn)
+        .optional()?
+        .ok_or_else(|| HttpError::not_found(format!("Approval {} is not found", id)))?;
+    let latest_price = schema::price::table
+        .filter(schema::price::market_id.eq(approval.market_id))
+        .order(schema::price::stamp_id.desc())
+        .select(schema::price::amount)
+        .first::<Amount>(&conn)
+        .optional()?
+        .ok_or(anyhow!("No price of approval {}", id))?;
+
+    let approval =
+        approve_pending_approval(&conn, id, latest_price, chrono::Utc::now().naive_utc())?;
+
+    info!("Approved {}", id);
+
+    ApproveOrderResponse {
+        success: true,
+        pending_approval_id: id.inner(),
+        state: format!("{:?}", approval.state),
+        approved_price: latest_price,
+        expiry: format_timestamp(&approval.expiry),
+    }
+    .apply(Ok)
+}
+
+/// List tags of currencies by GET.
+/// POST adds `tag` to the currency of `symbol`, or removes it if `remove=1`, then lists tags
+pub fn api_currency_tags(
+    config: &ServerConfig,
+    method: &Method,
+    authorization: Option<&str>,
+    query: &QString,
+) -> Result<CurrencyTagsResponse> {
+    if method != Method::GET && method != Method::POST {
+        return Err(
+            HttpError::method_not_allowed("currency_tags accepts only GET and POST").into(),
+        );
+    }
+
+    let conn = Conn::establish(&config.database_url)?;
+    let currency_collection = list_currencies(&conn)?;
+
+    if method == Method::POST {
+        auth::authorize(config.api_token.as_deref(), authorization)?;
+
+        let symbol = query
+            .get("symbol")
+            .ok_or_else(|| HttpError::bad_request("symbol is not specified"))?;
+        let tag = query
+            .get("tag")
+            .ok_or_else(|| HttpError::bad_request("tag is not specified"))?;
+        let currency = currency_collection
+            .by_symbol_including_inactive(symbol)
+            .ok_or_else(|| HttpError::bad_request(format!("Unknown currency: {}", symbol)))?;
+
+        if matches!(query.get("remove"), Some("1")) {
+            let removed = remove_currency_tag(&conn, currency.currency_id, tag)?;
+            if !removed {
+                let message = format!("{} doesn't have tag {}", symbol, tag);
+                return Err(HttpError::not_found(message).into());
+            }
+            info!("Removed tag {} from {}", tag, symbol);
+        } else {
+            add_currency_tag(&conn, currency.currency_id, tag)?;
+            info!("Tagged {} as {}", symbol, tag);
+        }
+    }
+
+    let currencies = list_currency_tags(&conn)?
+        .into_iter()
+        .group_by(|t| t.currency_id)
+        .into_iter()
+        .filter_map(|(currency_id, tags)| {
+            CurrencyTags {
+                symbol: currency_collection.by_id(currency_id)?.symbol.clone(),
+                tags: tags.map(|t| t.tag).collect(),
+            }
+            .apply(Some)
+        })
+        .sorted_by(|c1, c2| c1.symbol.cmp(&c2.symbol))
+        .collect();
+
+    Ok(CurrencyTagsResponse {
+        success: true,
+        currencies,
+    })
+}
+
+pub fn api_risk_metrics(
+    config: &ServerConfig,
+    graphs: &GraphCache,
+    query: &QString,
+) -> Result<RiskMetricsResponse> {
+    let (price_conn, balance_conn, _) = connect_db(config, &query)?;
+
+    let window_days = query
+        .get("window_days")
+        .and_then(|s| i64::from_str(s).ok())
+        .unwrap_or(90);
+
+    let currency_collection = list_currencies(&price_conn)?;
+    let fiat_symbol = query
+        .get("fiat")
+        .ok_or_else(|| HttpError::bad_request("fiat is not specified"))?;
+    let fiat_currency = currency_collection
+        .by_symbol_including_inactive(fiat_symbol)
+        .ok_or_else(|| HttpError::bad_request(format!("Unknown currency: {}", fiat_symbol)))?;
+
+    let latest_stamp = schema::stamp::table
+        .order(schema::stamp::timestamp.desc())
+        .first::<Stamp>(&*price_conn)?;
+
+    // Downsampled daily total equity. Stamps without valued balance are skipped
+    let equity_series = {
+        let since = latest_stamp.timestamp - Duration::days(window_days);
+        let timestamps = get_target_timestamps(
+            &price_conn,
+            Some(since),
+            Some(latest_stamp.timestamp),
+            Duration::days(1),
+        )?;
+        load_balance_history(
+            &price_conn,
+            &balance_conn,
+            graphs,
+            timestamps,
+            Some(fiat_currency),
+            None,
+        )?
+        .into_iter()
+        .map(|(stamp, balances, rates)| {
+            let equity = balances
+                .iter()
+                .zip_eq(rates)
+                .filter_map(|(balance, rate)| Some(balance_value(balance, rate?)))
+                .sum::<f64>();
+            (stamp.timestamp, equity)
+        })
+        .filter(|(_, equity)| *equity > 0.0)
+        .collect_vec()
+    };
+
+    let latest_valued = load_balance_history(
+        &price_conn,
+        &balance_conn,
+        graphs,
+        vec![latest_stamp],
+        Some(fiat_currency),
+        None,
+    )?
+    .into_iter()
+    .flat_map(|(_, balances, rates)| balances.into_iter().zip_eq(rates))
+    .filter_map(|(balance, rate)| {
+        let symbol = currency_collection
+            .by_id(balance.currency_id)?
+            .symbol
+            .clone();
+        Some((balance.currency_id, symbol, balance_value(&balance, rate?)))
+    })
+    .collect_vec();
+    let latest_values = latest_valued
+        .iter()
+        .map(|(_, symbol, value)| (symbol.clone(), *value))
+        .collect_vec();
+
+    let groups = if tag_group::is_grouped_by_tag(query.get("group_by"))? {
+        let primary_tags = primary_currency_tags(&list_currency_tags(&price_conn)?);
+        tag_group::group_by_tag(&latest_valued, &primary_tags)
+    } else {
+        vec![]
+    };
+
+    let mut unavailable = vec![];
+    let points = equity_series.len();
+    let insufficient_points = |metric: &str, required: usize| UnavailableMetric {
+        metric: metric.into(),
+        reason: format!(
+            "At least {} daily points are required, but {} found",
+            required, points
+        ),
+    };
+
+    let annualized_volatility = risk::annualized_volatility(&equity_series);
+    if annualized_volatility.is_none() {
+        unavailable.push(insufficient_points(
+            "annualizedVolatility",
+            risk::VOLATILITY_MIN_POINTS,
+        ));
+    }
+
+    let max_drawdown = risk::max_drawdown(&equity_series).map(|drawdown| MaxDrawdown {
+        ratio: drawdown.ratio,
+        start: format_timestamp(&drawdown.peak),
+        end: format_timestamp(&drawdown.trough),
+    });
+    if max_drawdown.is_none() {
+        unavailable.push(insufficient_points(
+            "maxDrawdown",
+            risk::DRAWDOWN_MIN_POINTS,
+        ));
+    }
+
+    let current_drawdown = risk::current_drawdown(&equity_series);
+    if current_drawdown.is_none() {
+        unavailable.push(insufficient_points(
+            "currentDrawdown",
+            risk::DRAWDOWN_MIN_POINTS,
+        ));
+    }
+
+    let concentration = risk::concentration(&latest_values).map(|(symbol, share)| Concentration {
+        symbol: symbol.clone(),
+        share,
+    });
+    if concentration.is_none() {
+        unavailable.push(UnavailableMetric {
+            metric: "concentration".into(),
+            reason: format!(
+                "No balance is valued in {} at the latest stamp",
+                fiat_symbol
+            ),
+        });
+    }
+
+    Ok(RiskMetricsResponse {
+        success: true,
+        fiat: fiat_symbol.to_string(),
+        window_days,
+        points,
+        annualized_volatility,
+        max_drawdown,
+        current_drawdown,
+        concentration,
+        unavailable,
+        groups,
+    })
+}
+
+fn build_info() -> BuildInfo {
+    let info = common::build_info();
+    BuildInfo {
+        version: info.version.to_string(),
+        git_hash: info.git_hash.to_string(),
+        build_timestamp: info.build_timestamp.to_string(),
+    }
+}
+
+/// Value of the whole `balance` in fiat currency
+fn balance_value(balance: &Balance, rate: f64) -> f64 {
+    (balance.available + balance.pending) * rate
+}
+
+/// Load balances at each of `timestamps`, with their exchange rates to `fiat_currency`.
+/// Rates are `None` if `fiat_currency` is not specified or the rate is unknown.
+/// Exchange graphs are reused through `graphs`.
+///
+/// Only balances of `currency_ids` are loaded if specified.
+fn load_balance_history(
+    price_conn: &Conn,
+    balance_conn: &Conn,
+    graphs: &GraphCache,
+    timestamps: Vec<Stamp>,
+    fiat_currency: Option<&Currency>,
+    currency_ids: Option<&[CurrencyId]>,
+) -> Result<Vec<(Stamp, Vec<Balance>, Vec<Option<f64>>)>> {
     let timestamp_ids = timestamps
         .iter()
         .map(|stamp| stamp.stamp_id)
         .collect::<Vec<_>>();
 
-    let balance_history = schema::balance::table
+    let mut balance_query = schema::balance::table
         .filter(schema::balance::stamp_id.eq_any(timestamp_ids))
-        .load::<Balance>(&*balance_conn)?
+        .into_boxed();
+    if let Some(currency_ids) = currency_ids {
+        balance_query =
+            balance_query.filter(schema::balance::currency_id.eq_any(currency_ids.to_vec()));
+    }
+
+    let balance_history = balance_query
+        .order(schema::balance::stamp_id.asc())
+        .load::<Balance>(balance_conn)?
         .into_iter()
         .group_by(|b| b.stamp_id)
         .into_iter()
@@ -62,7 +1114,11 @@ pub fn api_balance_history(query: &QString) -> Result<JsonValue> {
         Some(fiat_currency) => {
             let exchange_rate_history = timestamps
                 .iter()
-                .map(|stamp| construct_exchange_graph(&price_conn, stamp.stamp_id))
+                .map(|stamp| {
+                    graphs.get_or_construct(stamp.stamp_id, || {
+                        construct_exchange_graph(price_conn, stamp.stamp_id)
+                    })
+                })
                 .collect::<Vec<_>>();
             timestamps
                 .into_par_iter()
@@ -98,68 +1154,91 @@ pub fn api_balance_history(query: &QString) -> Result<JsonValue> {
             .collect(),
     };
 
-    let mut json = JsonValue::new_object();
-    json["success"] = true.into();
-    let mut history_array = JsonValue::new_array();
-    for (stamp, balances, rates) in history {
-        let mut history = JsonValue::new_object();
-        history["stamp"] = stamp.timestamp.format("%Y-%m-%dT%H:%M").to_string().into();
-        let mut currencies = JsonValue::new_array();
-        for (balance, rate) in balances.into_iter().zip_eq(rates) {
-            if let Some(currency) = currency_collection.by_id(balance.currency_id) {
-                let mut currency_json = JsonValue::new_object();
-                currency_json["name"] = currency.name.as_str().into();
-                currency_json["symbol"] = currency.symbol.as_str().into();
-                currency_json["available"] = balance.available.into();
-                currency_json["pending"] = balance.pending.into();
-                if let Some(rate) = rate {
-                    currency_json["rate"] = rate.into();
-                }
-                currencies.push(currency_json).ok();
-            }
-        }
-        history["currencies"] = currencies;
-        history_array.push(history).ok();
+    Ok(history)
+}
+
+/// Market of `market` query like `BTC-USDT`, or `base` and `quote` queries like `base=BTC&quote=USDT`
+fn market_query(query: &QString) -> Result<String> {
+    match (query.get("base"), query.get("quote")) {
+        (Some(base), Some(quote)) => Ok(format!("{}-{}", base, quote)),
+        _ => query
+            .get("market")
+            .map(str::to_string)
+            .ok_or_else(|| HttpError::bad_request("market is not specified").into()),
     }
-    json["history"] = history_array;
+}
+
+/// Resolve market of the query. Markets of delisted currencies are resolved for their history
+fn resolve_market_query(conn: &Conn, query: &QString) -> Result<Market> {
+    resolve_market_str(conn, &market_query(query)?)
+}
+
+/// Resolve `market_str` like `BTC-USDT`. Markets of delisted currencies are resolved for their history.
+/// # Returns
+/// `Err(e)` of `HttpError` if `market_str` is invalid or unknown
+fn resolve_market_str(conn: &Conn, market_str: &str) -> Result<Market> {
+    let currency_collection = list_currencies(conn)?;
+    let market_collection = list_markets(conn)?;
+
+    let (base_symbol, quote_symbol) = market_str
+        .split('-')
+        .collect_tuple::<(_, _)>()
+        .ok_or_else(|| HttpError::bad_request(format!("Invalid market: {}", market_str)))?;
+    let base = currency_collection
+        .by_symbol_including_inactive(base_symbol)
+        .ok_or_else(|| HttpError::bad_request(format!("Unknown currency: {}", base_symbol)))?;
+    let quote = currency_collection
+        .by_symbol_including_inactive(quote_symbol)
+        .ok_or_else(|| HttpError::bad_request(format!("Unknown currency: {}", quote_symbol)))?;
+    market_collection
+        .by_base_quote_id(base.currency_id, quote.currency_id)
+        .ok_or_else(|| HttpError::bad_request(format!("Unknown market: {}", market_str)))?
+        .clone()
+        .apply(Ok)
+}
+
+/// Timestamp of the latest stamp. `None` if no stamp exists
+fn latest_timestamp(conn: &Conn) -> Result<Option<NaiveDateTime>> {
+    schema::stamp::table
+        .select(schema::stamp::timestamp)
+        .order(schema::stamp::timestamp.desc())
+        .first::<NaiveDateTime>(conn)
+        .optional()?
+        .apply(Ok)
+}
 
-    Ok(json)
+pub fn format_timestamp(timestamp: &NaiveDateTime) -> String {
+    timestamp.format("%Y-%m-%dT%H:%M").to_string()
+}
+
+fn format_stamp(stamp: &Stamp) -> String {
+    format_timestamp(&stamp.timestamp)
 }
 
 /// # Returns
 /// `Ok(db_conn, balance_conn)` if successfully connected.
 ///
 /// NOTE: If query specifies using simulation, `balance_conn` refers simulation DB.
-fn connect_db(query: &QString) -> Result<(Rc<Conn>, Rc<Conn>, bool)> {
+fn connect_db(config: &ServerConfig, query: &QString) -> Result<(Rc<Conn>, Rc<Conn>, bool)> {
     let use_simulation_balance = matches!(query.get("sim"), Some("1"));
-
-    let price_conn = env::var("DATABASE_URL")?
-        .deref()
-        .apply(Conn::establish)?
-        .apply(Rc::new);
-    let balance_conn = if use_simulation_balance {
-        env::var("SIM_DATABASE_URL")?
-            .deref()
-            .apply(Conn::establish)?
-            .apply(Rc::new)
+    // Checked before connecting, so that misconfiguration is reported even if main DB is down
+    let sim_url = if use_simulation_balance {
+        config
+            .sim_database_url
+            .as_ref()
+            .ok_or(anyhow!("SIM_DATABASE_URL is not configured"))?
+            .apply(Some)
     } else {
-        price_conn.clone()
+        None
     };
 
-    Ok((price_conn, balance_conn, use_simulation_balance))
-}
-
-fn parse_query_step(step_str: &str) -> Option<Duration> {
-    let mut split = step_str.split('_');
-    let num = split.next().and_then(|s| i64::from_str(s).ok())?;
-    let unit = split.next()?;
+    let price_conn = Conn::establish(&config.database_url)?.apply(Rc::new);
+    let balance_conn = match sim_url {
+        Some(sim_url) => Conn::establish(sim_url)?.apply(Rc::new),
+        None => price_conn.clone(),
+    };
 
-    match unit {
-        "day" => Some(Duration::days(num)),
-        "hour" => Some(Duration::hours(num)),
-        "minute" => Some(Duration::minutes(num)),
-        _ => None,
-    }
+    Ok((price_conn, balance_conn, use_simulation_balance))
 }
 
 fn get_target_timestamps(
@@ -193,26 +1272,7 @@ fn get_target_timestamps(
         },
     };
 
-    let mut last_valid_stamp: Option<Stamp> = None;
-    let filtered_timestamps = timestamps
-        .into_iter()
-        .filter_map(|current| match last_valid_stamp.as_mut() {
-            Some(stamp) => {
-                if current.timestamp - stamp.timestamp >= step {
-                    *stamp = current.clone();
-                    Some(current)
-                } else {
-                    None
-                }
-            }
-            None => {
-                last_valid_stamp = Some(current.clone());
-                Some(current)
-            }
-        })
-        .collect();
-
-    Ok(filtered_timestamps)
+    Ok(downsample(timestamps, step, |stamp| stamp.timestamp))
 }
 
 fn construct_exchange_graph(
@@ -226,9 +1286,45 @@ fn construct_exchange_graph(
         .filter(price::stamp_id.eq(timestamp_id))
         .load::<(Price, Market)>(conn)?;
 
-    prices
-        .into_iter()
-        .map(|(p, m)| (m.base_id, m.quote_id, p.amount as f64))
-        .apply(ExchangeGraph::from_rates)
-        .apply(Ok)
+    Ok(ExchangeGraph::from_prices(&prices))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn config(sim_database_url: Option<&str>) -> ServerConfig {
+        ServerConfig {
+            address: ([127, 0, 0, 1], 0).into(),
+            webcontent_root: std::env::temp_dir(),
+            // Nothing listens on port 1, so connections are refused
+            database_url: "mysql://127.0.0.1:1/trade".into(),
+            sim_database_url: sim_database_url.map(Into::into),
+            path_prefix: None,
+            disable_static: false,
+            api_token: None,
+            events_poll_interval: Duration::from_secs(1),
+            graph_cache_ttl: Duration::from_secs(60),
+            graph_cache_capacity: 16,
+            shutdown_drain_timeout: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn test_connect_db_sim_not_configured() {
+        let query = QString::from("sim=1&fiat=USDT&step=1_hour&limit=24");
+
+        let e = connect_db(&config(None), &query).unwrap_err();
+
+        assert_eq!("SIM_DATABASE_URL is not configured", e.to_string());
+    }
+
+    #[test]
+    fn test_connect_db_without_sim() {
+        // Main DB is connected unless simulated balances are requested
+        let e = connect_db(&config(None), &QString::from("sim=0")).unwrap_err();
+
+        assert_ne!("SIM_DATABASE_URL is not configured", e.to_string());
+    }
 }
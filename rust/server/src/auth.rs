@@ -0,0 +1,58 @@
+use crate::http::HttpError;
+use anyhow::Result;
+
+/// Check `authorization` header value like `Bearer <token>` against the configured `token`.
+/// Every request is rejected if no token is configured
+/// # Returns
+/// `Err(e)` of 403 if no token is configured, or 401 if the given token is missing or wrong
+pub fn authorize(token: Option<&str>, authorization: Option<&str>) -> Result<()> {
+    let token = token.ok_or_else(|| HttpError::forbidden("API_TOKEN is not configured"))?;
+    let given = authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| HttpError::unauthorized("Bearer token is required"))?;
+
+    if !constant_time_eq(token, given.trim()) {
+        return Err(HttpError::unauthorized("Invalid token").into());
+    }
+
+    Ok(())
+}
+
+/// Compare without returning early, so that response time doesn't leak how many leading bytes matched
+fn constant_time_eq(s1: &str, s2: &str) -> bool {
+    s1.len() == s2.len()
+        && s1
+            .bytes()
+            .zip(s2.bytes())
+            .fold(0, |acc, (b1, b2)| acc | (b1 ^ b2))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::error_status;
+    use hyper::StatusCode;
+
+    #[test]
+    fn test_authorize() {
+        assert!(authorize(Some("secret"), Some("Bearer secret")).is_ok());
+
+        assert!(authorize(Some("secret"), Some("Bearer wrong")).is_err());
+        assert!(authorize(Some("secret"), Some("Bearer secre")).is_err());
+        assert!(authorize(Some("secret"), Some("secret")).is_err());
+        assert!(authorize(Some("secret"), None).is_err());
+
+        let e = authorize(Some("secret"), Some("Bearer wrong")).unwrap_err();
+        assert_eq!(StatusCode::UNAUTHORIZED, error_status(&e));
+    }
+
+    #[test]
+    fn test_authorize_without_token() {
+        assert!(authorize(None, Some("Bearer secret")).is_err());
+        assert!(authorize(None, None).is_err());
+
+        let e = authorize(None, Some("Bearer secret")).unwrap_err();
+        assert_eq!(StatusCode::FORBIDDEN, error_status(&e));
+    }
+}
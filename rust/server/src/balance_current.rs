@@ -0,0 +1,229 @@
+use crate::http::HttpError;
+use anyhow::Result;
+use database::exchange::ExchangeGraph;
+use database::logic::CurrencyCollection;
+use database::model::{Balance, Currency, CurrencyId};
+use server_client::response::CurrentBalance;
+
+/// Currency of `fiat` query, if specified.
+/// # Returns
+/// `Err(e)` of status 400 if `fiat` is unknown
+pub fn resolve_fiat<'a>(
+    currency_collection: &'a CurrencyCollection,
+    fiat: Option<&str>,
+) -> Result<Option<&'a Currency>> {
+    fiat.map(|symbol| {
+        currency_collection
+            .by_symbol_including_inactive(symbol)
+            .ok_or_else(|| HttpError::bad_request(format!("Unknown fiat: {}", symbol)).into())
+    })
+    .transpose()
+}
+
+/// Convert `balances` at a stamp into the response of `api/balance_current`, sorted by symbol.
+/// Rates are taken from `graph` if `fiat_id` is specified.
+///
+/// Currencies whose available and pending are both zero are omitted unless `include_zero`.
+/// Balances of unknown currencies are omitted
+pub fn current_balances(
+    balances: &[Balance],
+    currency_collection: &CurrencyCollection,
+    graph: &ExchangeGraph<CurrencyId>,
+    fiat_id: Option<CurrencyId>,
+    include_zero: bool,
+) -> Vec<CurrentBalance> {
+    let mut currencies = balances
+        .iter()
+        .filter(|b| include_zero || b.available + b.pending != 0.0)
+        .filter_map(|b| {
+            let currency = currency_collection.by_id(b.currency_id)?;
+            let total = b.available + b.pending;
+            let rate = fiat_id.and_then(|fiat_id| graph.rate_between(b.currency_id, fiat_id));
+            Some(CurrentBalance {
+                name: currency.name.clone(),
+                symbol: currency.symbol.clone(),
+                available: b.available,
+                pending: b.pending,
+                total,
+                rate,
+                value: rate.map(|rate| total * rate),
+            })
+        })
+        .collect::<Vec<_>>();
+    currencies.sort_by(|c1, c2| c1.symbol.cmp(&c2.symbol));
+
+    currencies
+}
+
+/// Sum of values of `currencies` whose rate is known
+pub fn total_value(currencies: &[CurrentBalance]) -> f64 {
+    currencies.iter().filter_map(|c| c.value).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::exchange::FromPrices;
+    use database::model::*;
+
+    const BTC: CurrencyId = CurrencyId::new(1);
+    const DOGE: CurrencyId = CurrencyId::new(2);
+    const USDT: CurrencyId = CurrencyId::new(3);
+    const XYZ: CurrencyId = CurrencyId::new(4);
+
+    fn currency_collection() -> CurrencyCollection {
+        CurrencyCollection::new(vec![
+            Currency::new(BTC, "BTC".into(), "Bitcoin".into()),
+            Currency::new(DOGE, "DOGE".into(), "Dogecoin".into()),
+            Currency::new(USDT, "USDT".into(), "Tether".into()),
+            Currency::new(XYZ, "XYZ".into(), "Unlisted".into()),
+        ])
+    }
+
+    fn balances() -> Vec<Balance> {
+        vec![
+            (USDT, 100.0, 50.0),
+            (BTC, 1.5, 0.5),
+            (DOGE, 0.0, 0.0),
+            (XYZ, 10.0, 0.0),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, (currency_id, available, pending))| {
+            Balance::new(
+                BalanceId::new(i as i32),
+                currency_id,
+                StampId::new(1),
+                available,
+                pending,
+            )
+        })
+        .collect()
+    }
+
+    /// BTC-USDT and DOGE-BTC are listed, while XYZ has no market
+    fn graph() -> ExchangeGraph<CurrencyId> {
+        let prices = vec![(1, BTC, USDT, 30000.0), (2, DOGE, BTC, 0.0000125)]
+            .into_iter()
+            .map(|(market_id, base_id, quote_id, amount)| {
+                let market_id = MarketId::new(market_id);
+                let price = Price::new(
+                    PriceId::new(market_id.inner()),
+                    market_id,
+                    StampId::new(1),
+                    amount,
+                );
+                (price, Market::new(market_id, base_id, quote_id))
+            })
+            .collect::<Vec<_>>();
+        ExchangeGraph::from_prices(&prices)
+    }
+
+    #[test]
+    fn test_current_balances() {
+        let currencies = current_balances(
+            &balances(),
+            &currency_collection(),
+            &graph(),
+            Some(USDT),
+            false,
+        );
+
+        let symbols = currencies
+            .iter()
+            .map(|c| c.symbol.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["BTC", "USDT", "XYZ"], symbols);
+
+        assert_eq!(
+            CurrentBalance {
+                name: "Bitcoin".into(),
+                symbol: "BTC".into(),
+                available: 1.5,
+                pending: 0.5,
+                total: 2.0,
+                rate: Some(30000.0),
+                value: Some(60000.0),
+            },
+            currencies[0]
+        );
+        assert_eq!(150.0, currencies[1].total);
+        assert_eq!(Some(1.0), currencies[1].rate);
+        assert_eq!(Some(150.0), currencies[1].value);
+        // No market to value XYZ
+        assert_eq!(None, currencies[2].rate);
+        assert_eq!(None, currencies[2].value);
+
+        assert_eq!(60150.0, total_value(&currencies));
+    }
+
+    #[test]
+    fn test_current_balances_include_zero() {
+        let currencies = current_balances(
+            &balances(),
+            &currency_collection(),
+            &graph(),
+            Some(USDT),
+            true,
+        );
+
+        let doge = currencies.iter().find(|c| c.symbol == "DOGE").unwrap();
+        assert_eq!(0.0, doge.total);
+        assert_eq!(Some(0.0), doge.value);
+        assert_eq!(4, currencies.len());
+    }
+
+    #[test]
+    fn test_resolve_fiat() {
+        let currency_collection = currency_collection();
+
+        let fiat = resolve_fiat(&currency_collection, Some("USDT")).unwrap();
+        assert_eq!(Some(USDT), fiat.map(|c| c.currency_id));
+        assert!(resolve_fiat(&currency_collection, None).unwrap().is_none());
+
+        let e = resolve_fiat(&currency_collection, Some("JPY")).unwrap_err();
+        assert_eq!("Unknown fiat: JPY", e.to_string());
+        assert_eq!(
+            hyper::StatusCode::BAD_REQUEST,
+            crate::http::error_status(&e)
+        );
+    }
+
+    #[test]
+    fn test_current_balances_without_fiat() {
+        let currencies =
+            current_balances(&balances(), &currency_collection(), &graph(), None, false);
+
+        assert!(currencies
+            .iter()
+            .all(|c| c.rate.is_none() && c.value.is_none()));
+    }
+
+    #[test]
+    fn test_current_balances_json() {
+        let currencies = current_balances(
+            &balances(),
+            &currency_collection(),
+            &graph(),
+            Some(USDT),
+            false,
+        );
+
+        let json = serde_json::to_value(&currencies).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "name": "Bitcoin",
+                "symbol": "BTC",
+                "available": 1.5,
+                "pending": 0.5,
+                "total": 2.0,
+                "rate": 30000.0,
+                "value": 60000.0,
+            }),
+            json[0]
+        );
+        // Unknown rate is omitted
+        assert!(json[2].get("rate").is_none());
+        assert!(json[2].get("value").is_none());
+    }
+}
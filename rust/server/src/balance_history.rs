@@ -0,0 +1,145 @@
+use database::logic::CurrencyCollection;
+use database::model::{Balance, CurrencyId};
+use itertools::Itertools;
+
+/// Balances at a stamp summarized for `api/balance_history`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StampSummary {
+    /// Balances listed in the per-currency breakdown, with their rates to fiat
+    pub balances: Vec<(Balance, Option<f64>)>,
+    /// Sum of values of all balances whose rate is known, including the ones out of the breakdown
+    pub total_fiat: f64,
+    /// Sorted symbols of currencies having non-zero balance without rate
+    pub missing_rate_symbols: Vec<String>,
+}
+
+/// Summarize `balances` at a stamp with their `rates` to fiat.
+/// The breakdown is restricted to `currency_ids` if specified, while the total covers all of `balances`.
+///
+/// Balances of unknown currencies are ignored
+pub fn summarize(
+    balances: Vec<Balance>,
+    rates: Vec<Option<f64>>,
+    currency_ids: Option<&[CurrencyId]>,
+    currency_collection: &CurrencyCollection,
+) -> StampSummary {
+    let balances = balances
+        .into_iter()
+        .zip_eq(rates)
+        .filter(|(balance, _)| currency_collection.by_id(balance.currency_id).is_some())
+        .collect_vec();
+
+    let total_fiat = balances
+        .iter()
+        .filter_map(|(balance, rate)| Some((balance.available + balance.pending) * (*rate)?))
+        .sum();
+    let missing_rate_symbols = balances
+        .iter()
+        .filter(|(balance, rate)| rate.is_none() && balance.available + balance.pending != 0.0)
+        .filter_map(|(balance, _)| currency_collection.by_id(balance.currency_id))
+        .map(|currency| currency.symbol.clone())
+        .sorted()
+        .dedup()
+        .collect();
+
+    let balances = match currency_ids {
+        Some(currency_ids) => balances
+            .into_iter()
+            .filter(|(balance, _)| currency_ids.contains(&balance.currency_id))
+            .collect(),
+        None => balances,
+    };
+
+    StampSummary {
+        balances,
+        total_fiat,
+        missing_rate_symbols,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::model::*;
+
+    const BTC: CurrencyId = CurrencyId::new(1);
+    const XYZ: CurrencyId = CurrencyId::new(2);
+    const UNKNOWN: CurrencyId = CurrencyId::new(3);
+
+    fn currency_collection() -> CurrencyCollection {
+        CurrencyCollection::new(vec![
+            Currency::new(BTC, "BTC".into(), "Bitcoin".into()),
+            Currency::new(XYZ, "XYZ".into(), "Unlisted".into()),
+        ])
+    }
+
+    /// BTC is convertible to fiat, while XYZ has no market
+    fn fixture() -> (Vec<Balance>, Vec<Option<f64>>) {
+        vec![
+            (BTC, 1.5, 0.5, Some(30000.0)),
+            (XYZ, 10.0, 0.0, None),
+            (UNKNOWN, 1.0, 0.0, None),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, (currency_id, available, pending, rate))| {
+            let balance = Balance::new(
+                BalanceId::new(i as i32),
+                currency_id,
+                StampId::new(1),
+                available,
+                pending,
+            );
+            (balance, rate)
+        })
+        .unzip()
+    }
+
+    #[test]
+    fn test_summarize() {
+        let (balances, rates) = fixture();
+
+        let summary = summarize(balances, rates, None, &currency_collection());
+
+        let currency_ids = summary
+            .balances
+            .iter()
+            .map(|(balance, _)| balance.currency_id)
+            .collect_vec();
+        assert_eq!(vec![BTC, XYZ], currency_ids);
+        assert_eq!(60000.0, summary.total_fiat);
+        assert_eq!(vec!["XYZ".to_string()], summary.missing_rate_symbols);
+    }
+
+    #[test]
+    fn test_summarize_filtered() {
+        let (balances, rates) = fixture();
+
+        let summary = summarize(balances, rates, Some(&[XYZ][..]), &currency_collection());
+
+        // Breakdown is restricted, while the total covers BTC too
+        assert_eq!(1, summary.balances.len());
+        assert_eq!(XYZ, summary.balances[0].0.currency_id);
+        assert_eq!(None, summary.balances[0].1);
+        assert_eq!(60000.0, summary.total_fiat);
+        assert_eq!(vec!["XYZ".to_string()], summary.missing_rate_symbols);
+
+        let (balances, rates) = fixture();
+        let summary = summarize(balances, rates, Some(&[BTC][..]), &currency_collection());
+        assert_eq!(BTC, summary.balances[0].0.currency_id);
+        assert_eq!(Some(30000.0), summary.balances[0].1);
+        assert_eq!(vec!["XYZ".to_string()], summary.missing_rate_symbols);
+    }
+
+    #[test]
+    fn test_summarize_zero_balance_without_rate() {
+        let (mut balances, rates) = fixture();
+        balances[1].available = 0.0;
+
+        let summary = summarize(balances, rates, None, &currency_collection());
+
+        // Zero balance is worth nothing even without rate
+        assert!(summary.missing_rate_symbols.is_empty());
+        assert_eq!(60000.0, summary.total_fiat);
+    }
+}
@@ -0,0 +1,166 @@
+use anyhow::{ensure, Result};
+use chrono::{Duration, NaiveDateTime};
+use database::model::Amount;
+use speculator::indicator::{DataItemBuffer, PriceStamp};
+use ta::{Close, DataItem, High, Low, Open};
+
+/// OHLC of prices within an interval
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candle {
+    /// Beginning of the interval, aligned to multiples of the interval since UNIX epoch like speculator's candlesticks
+    pub open_time: NaiveDateTime,
+    pub open: Amount,
+    pub high: Amount,
+    pub low: Amount,
+    pub close: Amount,
+    /// Number of prices in the interval
+    pub count: usize,
+}
+
+impl Candle {
+    fn new(open_time: NaiveDateTime, item: &DataItem, count: usize) -> Self {
+        Self {
+            open_time,
+            open: item.open(),
+            high: item.high(),
+            low: item.low(),
+            close: item.close(),
+            count,
+        }
+    }
+}
+
+/// Aggregate `prices` into candles of `interval` by speculator's `DataItemBuffer`.
+///
+/// `prices` are sorted by timestamp first, then prices of duplicated timestamps but the first one are dropped,
+/// so the input may be unsorted and contain duplicates.
+/// Intervals without prices are omitted rather than filled.
+/// # Returns
+/// `Err(e)` if `interval` is not positive
+pub fn derive_candles(
+    prices: &[(NaiveDateTime, Amount)],
+    interval: Duration,
+) -> Result<Vec<Candle>> {
+    // DataItemBuffer panics under non-positive interval
+    ensure!(
+        interval > Duration::zero(),
+        "Candle interval must be positive: {}",
+        interval
+    );
+
+    let mut prices = prices.to_vec();
+    // Stable sort keeps the first of duplicated timestamps at the front
+    prices.sort_by_key(|(timestamp, _)| *timestamp);
+    prices.dedup_by_key(|(timestamp, _)| *timestamp);
+
+    let mut buffer = DataItemBuffer::new(interval);
+    let mut candles = vec![];
+    for (timestamp, price) in prices.into_iter() {
+        // The buffer emits the candle of the open interval when a price of the next interval arrives
+        let open_time = buffer.current_open_time();
+        let count = buffer.current_len();
+        if let (Some(open_time), Some(item)) =
+            (open_time, buffer.next(PriceStamp::new(timestamp, price))?)
+        {
+            candles.push(Candle::new(open_time, &item, count));
+        }
+    }
+    // The last interval is still open
+    if let (Some(open_time), Some(item)) = (buffer.current_open_time(), buffer.current_partial()) {
+        candles.push(Candle::new(open_time, &item, buffer.current_len()));
+    }
+
+    Ok(candles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn timestamp(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2021, 1, 1).and_hms(hour, minute, 0)
+    }
+
+    /// Synthetic price of each minute of a day
+    fn minute_prices() -> Vec<(NaiveDateTime, Amount)> {
+        (0..24 * 60)
+            .map(|i| {
+                let price = 100.0 + (i as Amount * 0.37).sin() * 10.0 + (i % 17) as Amount;
+                (timestamp(0, 0) + Duration::minutes(i), price)
+            })
+            .collect()
+    }
+
+    /// Straightforward reference implementation filtering prices of each hour
+    fn reference_hourly_candles(prices: &[(NaiveDateTime, Amount)]) -> Vec<Candle> {
+        (0..24)
+            .filter_map(|hour| {
+                let open_time = timestamp(hour, 0);
+                let in_hour = prices
+                    .iter()
+                    .filter(|(t, _)| open_time <= *t && *t < open_time + Duration::hours(1))
+                    .map(|(_, price)| *price)
+                    .collect::<Vec<_>>();
+                let first = *in_hour.first()?;
+                Some(Candle {
+                    open_time,
+                    open: first,
+                    high: in_hour.iter().copied().fold(Amount::MIN, Amount::max),
+                    low: in_hour.iter().copied().fold(Amount::MAX, Amount::min),
+                    close: *in_hour.last()?,
+                    count: in_hour.len(),
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_derive_candles_hourly() {
+        let prices = minute_prices();
+
+        let candles = derive_candles(&prices, Duration::hours(1)).unwrap();
+
+        assert_eq!(24, candles.len());
+        assert_eq!(reference_hourly_candles(&prices), candles);
+    }
+
+    #[test]
+    fn test_derive_candles_unsorted_and_duplicated() {
+        let prices = minute_prices();
+        let mut unsorted = prices.iter().rev().copied().collect::<Vec<_>>();
+        // Duplicates following the original prices are dropped
+        unsorted.push((timestamp(3, 0), 1.0));
+        unsorted.push((timestamp(3, 30), 1000.0));
+
+        let candles = derive_candles(&unsorted, Duration::hours(1)).unwrap();
+
+        assert_eq!(reference_hourly_candles(&prices), candles);
+    }
+
+    #[test]
+    fn test_derive_candles_omits_empty_intervals() {
+        let prices = vec![
+            (timestamp(0, 10), 3.0),
+            (timestamp(0, 50), 5.0),
+            // No price in 01:00-03:00
+            (timestamp(3, 20), 4.0),
+        ];
+
+        let candles = derive_candles(&prices, Duration::hours(1)).unwrap();
+
+        assert_eq!(
+            vec![timestamp(0, 0), timestamp(3, 0)],
+            candles.iter().map(|c| c.open_time).collect::<Vec<_>>()
+        );
+        let c = candles[0];
+        assert_eq!((3.0, 5.0, 3.0, 5.0), (c.open, c.high, c.low, c.close));
+        assert_eq!(1, candles[1].count);
+    }
+
+    #[test]
+    fn test_derive_candles_invalid_interval() {
+        assert!(derive_candles(&minute_prices(), Duration::zero()).is_err());
+        assert!(derive_candles(&[], Duration::hours(1)).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,381 @@
+use anyhow::{anyhow, Result};
+use database::diesel::Connection;
+use database::logic::Conn;
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+const DEFAULT_SERVER_ADDRESS: &str = "127.0.0.1:7878";
+const DEFAULT_EVENTS_POLL_INTERVAL_SEC: u64 = 3;
+const DEFAULT_GRAPH_CACHE_TTL_MIN: u64 = 60;
+const DEFAULT_GRAPH_CACHE_CAPACITY: usize = 4096;
+const DEFAULT_SHUTDOWN_DRAIN_TIMEOUT_SEC: u64 = 10;
+
+/// Server settings loaded once at startup
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerConfig {
+    pub address: SocketAddr,
+    pub webcontent_root: PathBuf,
+    pub database_url: String,
+    /// `None` if simulation DB is not configured
+    pub sim_database_url: Option<String>,
+    /// Path prefix under which the server is exposed, like `/asset`. Never ends with `/`
+    pub path_prefix: Option<String>,
+    /// Serve only APIs, leaving static files to a reverse proxy
+    pub disable_static: bool,
+    /// Bearer token required by APIs changing data. Such APIs are disabled if `None`
+    pub api_token: Option<String>,
+    /// How often the latest stamp is polled for `api/events`
+    pub events_poll_interval: Duration,
+    /// How long exchange graphs of stamps are cached
+    pub graph_cache_ttl: Duration,
+    /// Maximum number of cached exchange graphs. Caching is disabled if 0
+    pub graph_cache_capacity: usize,
+    /// How long in-flight requests are waited for after a shutdown signal
+    pub shutdown_drain_timeout: Duration,
+}
+
+impl ServerConfig {
+    /// Load settings from environment variables, then check that DBs are connectable.
+    /// # Returns
+    /// `Err(e)` listing every problem found
+    pub fn from_env() -> Result<Self> {
+        let config = Self::from_vars(|key| env::var(key).ok()).map_err(to_error)?;
+        config.validate_connections().map_err(to_error)?;
+        Ok(config)
+    }
+
+    /// Parse and validate settings except DB connectivity.
+    /// # Returns
+    /// `Err(problems)` if any setting is invalid
+    pub fn from_vars<F>(var: F) -> std::result::Result<Self, Vec<String>>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut problems = vec![];
+
+        let address = var("SERVER_ADDRESS").unwrap_or_else(|| DEFAULT_SERVER_ADDRESS.into());
+        let address = match SocketAddr::from_str(&address) {
+            Ok(address) => Some(address),
+            Err(e) => {
+                problems.push(format!("Invalid SERVER_ADDRESS {}: {}", address, e));
+                None
+            }
+        };
+
+        let webcontent_root = match var("WEBCONTENT_ROOT") {
+            Some(root) => {
+                let root = PathBuf::from(root);
+                match std::fs::read_dir(&root) {
+                    Ok(_) => Some(root),
+                    Err(e) => {
+                        problems.push(format!(
+                            "WEBCONTENT_ROOT {:?} is not a readable directory: {}",
+                            root, e
+                        ));
+                        None
+                    }
+                }
+            }
+            None => {
+                problems.push("WEBCONTENT_ROOT is not set".into());
+                None
+            }
+        };
+
+        let database_url = var("DATABASE_URL");
+        if database_url.is_none() {
+            problems.push("DATABASE_URL is not set".into());
+        }
+
+        let sim_database_url = var("SIM_DATABASE_URL");
+
+        let path_prefix = var("SERVER_PATH_PREFIX").and_then(|s| normalize_path_prefix(&s));
+        let disable_static = matches!(var("DISABLE_STATIC").as_deref(), Some("1"));
+        let api_token = var("API_TOKEN").filter(|token| !token.is_empty());
+
+        let events_poll_interval = match var("EVENTS_POLL_INTERVAL_SEC") {
+            Some(sec) => match u64::from_str(&sec) {
+                Ok(sec) if sec > 0 => Duration::from_secs(sec),
+                _ => {
+                    problems.push(format!(
+                        "EVENTS_POLL_INTERVAL_SEC must be a positive integer: {}",
+                        sec
+                    ));
+                    Duration::from_secs(DEFAULT_EVENTS_POLL_INTERVAL_SEC)
+                }
+            },
+            None => Duration::from_secs(DEFAULT_EVENTS_POLL_INTERVAL_SEC),
+        };
+
+        let graph_cache_ttl = match var("GRAPH_CACHE_TTL_MIN") {
+            Some(min) => match u64::from_str(&min) {
+                Ok(min) => Duration::from_secs(min * 60),
+                Err(_) => {
+                    problems.push(format!(
+                        "GRAPH_CACHE_TTL_MIN must be a non-negative integer: {}",
+                        min
+                    ));
+                    Duration::from_secs(DEFAULT_GRAPH_CACHE_TTL_MIN * 60)
+                }
+            },
+            None => Duration::from_secs(DEFAULT_GRAPH_CACHE_TTL_MIN * 60),
+        };
+
+        let graph_cache_capacity = match var("GRAPH_CACHE_CAPACITY") {
+            Some(capacity) => match usize::from_str(&capacity) {
+                Ok(capacity) => capacity,
+                Err(_) => {
+                    problems.push(format!(
+                        "GRAPH_CACHE_CAPACITY must be a non-negative integer: {}",
+                        capacity
+                    ));
+                    DEFAULT_GRAPH_CACHE_CAPACITY
+                }
+            },
+            None => DEFAULT_GRAPH_CACHE_CAPACITY,
+        };
+
+        let shutdown_drain_timeout = match var("SHUTDOWN_DRAIN_TIMEOUT_SEC") {
+            Some(sec) => match u64::from_str(&sec) {
+                Ok(sec) => Duration::from_secs(sec),
+                Err(_) => {
+                    problems.push(format!(
+                        "SHUTDOWN_DRAIN_TIMEOUT_SEC must be a non-negative integer: {}",
+                        sec
+                    ));
+                    Duration::from_secs(DEFAULT_SHUTDOWN_DRAIN_TIMEOUT_SEC)
+                }
+            },
+            None => Duration::from_secs(DEFAULT_SHUTDOWN_DRAIN_TIMEOUT_SEC),
+        };
+
+        match (address, webcontent_root, database_url) {
+            (Some(address), Some(webcontent_root), Some(database_url)) if problems.is_empty() => {
+                Ok(Self {
+                    address,
+                    webcontent_root,
+                    database_url,
+                    sim_database_url,
+                    path_prefix,
+                    disable_static,
+                    api_token,
+                    events_poll_interval,
+                    graph_cache_ttl,
+                    graph_cache_capacity,
+                    shutdown_drain_timeout,
+                })
+            }
+            _ => Err(problems),
+        }
+    }
+
+    fn validate_connections(&self) -> std::result::Result<(), Vec<String>> {
+        let mut problems = vec![];
+
+        if let Err(e) = Conn::establish(&self.database_url) {
+            problems.push(format!("Can't connect DATABASE_URL: {}", e));
+        }
+        if let Some(url) = self.sim_database_url.as_ref() {
+            if let Err(e) = Conn::establish(url) {
+                problems.push(format!("Can't connect SIM_DATABASE_URL: {}", e));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+/// Make `prefix` like `/asset`. Empty prefix means no prefix
+fn normalize_path_prefix(prefix: &str) -> Option<String> {
+    match prefix.trim().trim_matches('/') {
+        "" => None,
+        trimmed => Some(format!("/{}", trimmed)),
+    }
+}
+
+fn to_error(problems: Vec<String>) -> anyhow::Error {
+    anyhow!("Invalid server config:\n{}", problems.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn vars(pairs: &[(&str, String)]) -> impl Fn(&str) -> Option<String> {
+        let map = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect::<HashMap<_, _>>();
+        move |key| map.get(key).cloned()
+    }
+
+    fn temp_dir_str() -> String {
+        env::temp_dir().to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_from_vars() {
+        let config = ServerConfig::from_vars(vars(&[
+            ("SERVER_ADDRESS", "127.0.0.1:8080".into()),
+            ("WEBCONTENT_ROOT", temp_dir_str()),
+            ("DATABASE_URL", "mysql://localhost/trade".into()),
+        ]))
+        .unwrap();
+
+        assert_eq!(SocketAddr::from(([127, 0, 0, 1], 8080)), config.address);
+        assert_eq!(env::temp_dir(), config.webcontent_root);
+        assert_eq!("mysql://localhost/trade", config.database_url);
+        assert_eq!(None, config.sim_database_url);
+        assert_eq!(None, config.path_prefix);
+        assert!(!config.disable_static);
+        assert_eq!(None, config.api_token);
+        assert_eq!(Duration::from_secs(3), config.events_poll_interval);
+        assert_eq!(Duration::from_secs(3600), config.graph_cache_ttl);
+        assert_eq!(4096, config.graph_cache_capacity);
+        assert_eq!(Duration::from_secs(10), config.shutdown_drain_timeout);
+    }
+
+    #[test]
+    fn test_from_vars_shutdown_drain_timeout() {
+        let config = ServerConfig::from_vars(vars(&[
+            ("WEBCONTENT_ROOT", temp_dir_str()),
+            ("DATABASE_URL", "mysql://localhost/trade".into()),
+            ("SHUTDOWN_DRAIN_TIMEOUT_SEC", "0".into()),
+        ]))
+        .unwrap();
+        assert_eq!(Duration::from_secs(0), config.shutdown_drain_timeout);
+
+        let problems = ServerConfig::from_vars(vars(&[
+            ("WEBCONTENT_ROOT", temp_dir_str()),
+            ("DATABASE_URL", "mysql://localhost/trade".into()),
+            ("SHUTDOWN_DRAIN_TIMEOUT_SEC", "forever".into()),
+        ]))
+        .unwrap_err();
+        assert_eq!(1, problems.len());
+        assert!(problems[0].contains("SHUTDOWN_DRAIN_TIMEOUT_SEC"));
+    }
+
+    #[test]
+    fn test_from_vars_graph_cache() {
+        let config = ServerConfig::from_vars(vars(&[
+            ("WEBCONTENT_ROOT", temp_dir_str()),
+            ("DATABASE_URL", "mysql://localhost/trade".into()),
+            ("GRAPH_CACHE_TTL_MIN", "10".into()),
+            ("GRAPH_CACHE_CAPACITY", "0".into()),
+        ]))
+        .unwrap();
+        assert_eq!(Duration::from_secs(600), config.graph_cache_ttl);
+        assert_eq!(0, config.graph_cache_capacity);
+
+        let problems = ServerConfig::from_vars(vars(&[
+            ("WEBCONTENT_ROOT", temp_dir_str()),
+            ("DATABASE_URL", "mysql://localhost/trade".into()),
+            ("GRAPH_CACHE_TTL_MIN", "-1".into()),
+            ("GRAPH_CACHE_CAPACITY", "many".into()),
+        ]))
+        .unwrap_err();
+        assert_eq!(2, problems.len());
+        assert!(problems[0].contains("GRAPH_CACHE_TTL_MIN"));
+        assert!(problems[1].contains("GRAPH_CACHE_CAPACITY"));
+    }
+
+    #[test]
+    fn test_from_vars_events_poll_interval() {
+        let config = ServerConfig::from_vars(vars(&[
+            ("WEBCONTENT_ROOT", temp_dir_str()),
+            ("DATABASE_URL", "mysql://localhost/trade".into()),
+            ("EVENTS_POLL_INTERVAL_SEC", "10".into()),
+        ]))
+        .unwrap();
+        assert_eq!(Duration::from_secs(10), config.events_poll_interval);
+
+        let problems = ServerConfig::from_vars(vars(&[
+            ("WEBCONTENT_ROOT", temp_dir_str()),
+            ("DATABASE_URL", "mysql://localhost/trade".into()),
+            ("EVENTS_POLL_INTERVAL_SEC", "0".into()),
+        ]))
+        .unwrap_err();
+        assert_eq!(1, problems.len());
+        assert!(problems[0].contains("EVENTS_POLL_INTERVAL_SEC"));
+    }
+
+    #[test]
+    fn test_from_vars_path_prefix() {
+        let config = ServerConfig::from_vars(vars(&[
+            ("WEBCONTENT_ROOT", temp_dir_str()),
+            ("DATABASE_URL", "mysql://localhost/trade".into()),
+            ("SERVER_PATH_PREFIX", "asset/".into()),
+            ("DISABLE_STATIC", "1".into()),
+        ]))
+        .unwrap();
+
+        assert_eq!(Some("/asset".into()), config.path_prefix);
+        assert!(config.disable_static);
+    }
+
+    #[test]
+    fn test_from_vars_api_token() {
+        let config = ServerConfig::from_vars(vars(&[
+            ("WEBCONTENT_ROOT", temp_dir_str()),
+            ("DATABASE_URL", "mysql://localhost/trade".into()),
+            ("API_TOKEN", "secret".into()),
+        ]))
+        .unwrap();
+        assert_eq!(Some("secret".into()), config.api_token);
+
+        // Empty token disables APIs changing data
+        let config = ServerConfig::from_vars(vars(&[
+            ("WEBCONTENT_ROOT", temp_dir_str()),
+            ("DATABASE_URL", "mysql://localhost/trade".into()),
+            ("API_TOKEN", "".into()),
+        ]))
+        .unwrap();
+        assert_eq!(None, config.api_token);
+    }
+
+    #[test]
+    fn test_normalize_path_prefix() {
+        assert_eq!(Some("/asset".into()), normalize_path_prefix("/asset"));
+        assert_eq!(Some("/asset".into()), normalize_path_prefix("/asset/"));
+        assert_eq!(Some("/a/b".into()), normalize_path_prefix("a/b"));
+        assert_eq!(None, normalize_path_prefix("/"));
+        assert_eq!(None, normalize_path_prefix(""));
+    }
+
+    #[test]
+    fn test_from_vars_default_address() {
+        let config = ServerConfig::from_vars(vars(&[
+            ("WEBCONTENT_ROOT", temp_dir_str()),
+            ("DATABASE_URL", "mysql://localhost/trade".into()),
+        ]))
+        .unwrap();
+
+        assert_eq!(SocketAddr::from(([127, 0, 0, 1], 7878)), config.address);
+    }
+
+    #[test]
+    fn test_from_vars_reports_every_problem() {
+        let missing_dir = env::temp_dir().join("asset_management_no_such_dir");
+        let problems = ServerConfig::from_vars(vars(&[
+            ("SERVER_ADDRESS", "localhost:port".into()),
+            (
+                "WEBCONTENT_ROOT",
+                missing_dir.to_string_lossy().into_owned(),
+            ),
+        ]))
+        .unwrap_err();
+
+        assert_eq!(3, problems.len());
+        assert!(problems[0].contains("SERVER_ADDRESS"));
+        assert!(problems[1].contains("WEBCONTENT_ROOT"));
+        assert!(problems[2].contains("DATABASE_URL"));
+    }
+}
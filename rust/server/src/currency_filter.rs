@@ -0,0 +1,92 @@
+use crate::http::HttpError;
+use anyhow::Result;
+use database::model::{Currency, CurrencyId};
+
+/// Currencies selected by `symbols` query
+#[derive(Debug, Clone, PartialEq)]
+pub struct CurrencyFilter {
+    /// Symbols in the order of the query, without unknown ones
+    pub symbols: Vec<String>,
+    pub currency_ids: Vec<CurrencyId>,
+    /// Symbols not found in `currencies`. Always empty if resolved strictly
+    pub ignored_symbols: Vec<String>,
+}
+
+/// Resolve comma separated `symbols` like `BTC,ETH,USDT` into ids of `currencies`.
+/// Blank items and duplicates are skipped.
+/// # Returns
+/// `Err(e)` if `strict` and any symbol is unknown. Otherwise unknown symbols are reported in `ignored_symbols`
+pub fn resolve_symbols(
+    currencies: &[Currency],
+    symbols: &str,
+    strict: bool,
+) -> Result<CurrencyFilter> {
+    let mut filter = CurrencyFilter {
+        symbols: vec![],
+        currency_ids: vec![],
+        ignored_symbols: vec![],
+    };
+
+    for symbol in symbols.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        if filter.symbols.iter().any(|s| s == symbol)
+            || filter.ignored_symbols.iter().any(|s| s == symbol)
+        {
+            continue;
+        }
+
+        match currencies.iter().find(|c| c.symbol == symbol) {
+            Some(currency) => {
+                filter.symbols.push(symbol.to_string());
+                filter.currency_ids.push(currency.currency_id);
+            }
+            None if strict => {
+                return Err(HttpError::bad_request(format!("Unknown currency: {}", symbol)).into())
+            }
+            None => filter.ignored_symbols.push(symbol.to_string()),
+        }
+    }
+
+    Ok(filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn currencies() -> Vec<Currency> {
+        vec![
+            Currency::new(CurrencyId::new(1), "BTC".into(), "Bitcoin".into()),
+            Currency::new(CurrencyId::new(2), "ETH".into(), "Ethereum".into()),
+            Currency::new(CurrencyId::new(3), "USDT".into(), "Tether".into()),
+        ]
+    }
+
+    #[test]
+    fn test_resolve_symbols() {
+        let filter = resolve_symbols(&currencies(), "USDT, BTC,,BTC", false).unwrap();
+
+        assert_eq!(vec!["USDT", "BTC"], filter.symbols);
+        assert_eq!(
+            vec![CurrencyId::new(3), CurrencyId::new(1)],
+            filter.currency_ids
+        );
+        assert!(filter.ignored_symbols.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_symbols_unknown() {
+        let filter = resolve_symbols(&currencies(), "BTC,XYZ,ETH", false).unwrap();
+        assert_eq!(vec!["BTC", "ETH"], filter.symbols);
+        assert_eq!(vec!["XYZ"], filter.ignored_symbols);
+
+        assert!(resolve_symbols(&currencies(), "BTC,XYZ,ETH", true).is_err());
+        assert!(resolve_symbols(&currencies(), "BTC,ETH", true).is_ok());
+    }
+
+    #[test]
+    fn test_resolve_symbols_empty() {
+        let filter = resolve_symbols(&currencies(), "", true).unwrap();
+
+        assert!(filter.currency_ids.is_empty());
+    }
+}
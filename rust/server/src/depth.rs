@@ -0,0 +1,200 @@
+use database::model::{Amount, OrderSide, Orderbook};
+use itertools::Itertools;
+
+/// Price level of depth chart
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthLevel {
+    pub price: Amount,
+    pub volume: Amount,
+    /// Sum of volumes from the best price to this level
+    pub cumulative_volume: Amount,
+}
+
+/// Bid and ask levels of a market at a time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Depth {
+    /// Sorted by descending price
+    pub bids: Vec<DepthLevel>,
+    /// Sorted by ascending price
+    pub asks: Vec<DepthLevel>,
+}
+
+impl From<DepthLevel> for server_client::DepthLevel {
+    fn from(level: DepthLevel) -> Self {
+        Self {
+            price: level.price,
+            volume: level.volume,
+            cumulative_volume: level.cumulative_volume,
+        }
+    }
+}
+
+impl Depth {
+    pub fn from_orderbooks<'a>(orderbooks: impl IntoIterator<Item = &'a Orderbook>) -> Self {
+        let (bids, asks): (Vec<_>, Vec<_>) = orderbooks
+            .into_iter()
+            .partition(|o| o.side == OrderSide::Buy);
+
+        Self {
+            bids: cumulate(bids, OrderSide::Buy),
+            asks: cumulate(asks, OrderSide::Sell),
+        }
+    }
+
+    /// Return the middle of the best bid and the best ask
+    pub fn mid_price(&self) -> Option<Amount> {
+        match (self.bids.first(), self.asks.first()) {
+            (Some(bid), Some(ask)) => Some((bid.price + ask.price) / 2.0),
+            _ => None,
+        }
+    }
+
+    /// Return the best ask minus the best bid
+    pub fn spread(&self) -> Option<Amount> {
+        match (self.bids.first(), self.asks.first()) {
+            (Some(bid), Some(ask)) => Some(ask.price - bid.price),
+            _ => None,
+        }
+    }
+}
+
+/// Merge levels of the same price, then accumulate volumes from the best price.
+/// Levels with NaN price or volume are dropped.
+fn cumulate(orderbooks: Vec<&Orderbook>, side: OrderSide) -> Vec<DepthLevel> {
+    let sorted = orderbooks
+        .into_iter()
+        .filter(|o| !o.price.is_nan() && !o.volume.is_nan())
+        .map(|o| (o.price, o.volume))
+        // NaN is already removed, so no panic occurs
+        .sorted_by(|(p1, _), (p2, _)| match side {
+            OrderSide::Buy => p2.partial_cmp(p1).unwrap(),
+            OrderSide::Sell => p1.partial_cmp(p2).unwrap(),
+        });
+
+    let mut levels: Vec<DepthLevel> = vec![];
+    for (price, volume) in sorted {
+        let last_cumulative_volume = levels.last().map(|l| l.cumulative_volume).unwrap_or(0.0);
+        match levels.last_mut() {
+            Some(last) if last.price == price => {
+                last.volume += volume;
+                last.cumulative_volume += volume;
+            }
+            _ => levels.push(DepthLevel {
+                price,
+                volume,
+                cumulative_volume: last_cumulative_volume + volume,
+            }),
+        }
+    }
+
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::model::{MarketId, OrderbookId, StampId};
+
+    fn orderbook(side: OrderSide, price: Amount, volume: Amount) -> Orderbook {
+        Orderbook {
+            orderbook_id: OrderbookId::new(0),
+            market_id: MarketId::new(0),
+            stamp_id: StampId::new(0),
+            side,
+            price,
+            volume,
+        }
+    }
+
+    fn level(price: Amount, volume: Amount, cumulative_volume: Amount) -> DepthLevel {
+        DepthLevel {
+            price,
+            volume,
+            cumulative_volume,
+        }
+    }
+
+    #[test]
+    fn test_from_orderbooks() {
+        let orderbooks = vec![
+            orderbook(OrderSide::Buy, 9.0, 1.0),
+            orderbook(OrderSide::Sell, 12.0, 3.0),
+            orderbook(OrderSide::Buy, 10.0, 2.0),
+            orderbook(OrderSide::Sell, 11.0, 4.0),
+        ];
+
+        let depth = Depth::from_orderbooks(&orderbooks);
+
+        assert_eq!(
+            vec![level(10.0, 2.0, 2.0), level(9.0, 1.0, 3.0)],
+            depth.bids
+        );
+        assert_eq!(
+            vec![level(11.0, 4.0, 4.0), level(12.0, 3.0, 7.0)],
+            depth.asks
+        );
+        assert_eq!(Some(10.5), depth.mid_price());
+        assert_eq!(Some(1.0), depth.spread());
+    }
+
+    #[test]
+    fn test_from_orderbooks_merge_duplicated_levels() {
+        let orderbooks = vec![
+            orderbook(OrderSide::Buy, 10.0, 2.0),
+            orderbook(OrderSide::Buy, 9.0, 1.0),
+            orderbook(OrderSide::Buy, 10.0, 0.5),
+            orderbook(OrderSide::Sell, 11.0, 4.0),
+            orderbook(OrderSide::Sell, 11.0, 1.0),
+        ];
+
+        let depth = Depth::from_orderbooks(&orderbooks);
+
+        assert_eq!(
+            vec![level(10.0, 2.5, 2.5), level(9.0, 1.0, 3.5)],
+            depth.bids
+        );
+        assert_eq!(vec![level(11.0, 5.0, 5.0)], depth.asks);
+    }
+
+    #[test]
+    fn test_from_orderbooks_nan() {
+        let orderbooks = vec![
+            orderbook(OrderSide::Buy, Amount::NAN, 2.0),
+            orderbook(OrderSide::Buy, 9.0, Amount::NAN),
+            orderbook(OrderSide::Buy, 8.0, 1.0),
+        ];
+
+        let depth = Depth::from_orderbooks(&orderbooks);
+
+        assert_eq!(vec![level(8.0, 1.0, 1.0)], depth.bids);
+        assert!(depth.asks.is_empty());
+        assert_eq!(None, depth.mid_price());
+        assert_eq!(None, depth.spread());
+    }
+
+    #[test]
+    fn test_from_orderbooks_nan_on_both_sides() {
+        let orderbooks = vec![
+            orderbook(OrderSide::Sell, 13.0, 1.0),
+            orderbook(OrderSide::Sell, Amount::NAN, 5.0),
+            orderbook(OrderSide::Buy, 9.0, 2.0),
+            orderbook(OrderSide::Sell, 12.0, 2.0),
+            orderbook(OrderSide::Buy, Amount::NAN, 5.0),
+            orderbook(OrderSide::Buy, 10.0, 1.0),
+        ];
+
+        let depth = Depth::from_orderbooks(&orderbooks);
+
+        // NaN rows neither break sorting nor add volume
+        assert_eq!(
+            vec![level(10.0, 1.0, 1.0), level(9.0, 2.0, 3.0)],
+            depth.bids
+        );
+        assert_eq!(
+            vec![level(12.0, 2.0, 2.0), level(13.0, 1.0, 3.0)],
+            depth.asks
+        );
+        assert_eq!(Some(11.0), depth.mid_price());
+        assert_eq!(Some(2.0), depth.spread());
+    }
+}
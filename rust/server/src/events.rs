@@ -0,0 +1,513 @@
+use crate::api::format_timestamp;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use database::diesel::dsl::exists;
+use database::diesel::prelude::*;
+use database::diesel::select;
+use database::logic::Conn;
+use database::model::StampId;
+use database::schema;
+use futures_util::stream::{self, Stream};
+use qstring::QString;
+use server_client::response::{EventsResponse, StampEvent};
+use std::convert::Infallible;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Default and maximum wait of `api/events`, shorter than common proxy timeouts
+const MAX_EVENTS_TIMEOUT: Duration = Duration::from_secs(55);
+
+/// Interval of comments sent by `/events` without new stamps, so that proxies keep the stream open
+const EVENT_STREAM_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Latest stamp and tables written at a stamp. Methods block, so they are called on the blocking thread pool
+pub trait LatestStampSource: Send + Sync + 'static {
+    fn latest_stamp_id(&self) -> Result<Option<StampId>>;
+
+    /// Names of tables having rows at `stamp_id`
+    fn tables_at(&self, stamp_id: StampId) -> Result<Vec<String>>;
+
+    /// Timestamp of `stamp_id` and the number of rows written at it
+    fn stamp_event(&self, stamp_id: StampId) -> Result<StampEvent>;
+}
+
+/// Latest stamp source reading main DB through a connection reused across polls
+pub struct DbLatestStampSource {
+    database_url: String,
+    conn: Mutex<Option<Conn>>,
+}
+
+impl DbLatestStampSource {
+    pub fn new(database_url: impl Into<String>) -> Self {
+        Self {
+            database_url: database_url.into(),
+            conn: Mutex::new(None),
+        }
+    }
+
+    /// Run `f` on the cached connection. The connection is discarded on error, then reconnected next time
+    fn with_conn<T>(&self, f: impl FnOnce(&Conn) -> Result<T>) -> Result<T> {
+        let mut conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        if conn.is_none() {
+            *conn = Some(Conn::establish(&self.database_url)?);
+        }
+
+        let ret = conn
+            .as_ref()
+            .map(f)
+            .expect("Connection is established above");
+        if ret.is_err() {
+            *conn = None;
+        }
+        ret
+    }
+}
+
+impl LatestStampSource for DbLatestStampSource {
+    fn latest_stamp_id(&self) -> Result<Option<StampId>> {
+        self.with_conn(|conn| {
+            schema::stamp::table
+                .select(schema::stamp::stamp_id)
+                .order(schema::stamp::stamp_id.desc())
+                .first::<StampId>(conn)
+                .optional()
+                .map_err(Into::into)
+        })
+    }
+
+    fn tables_at(&self, stamp_id: StampId) -> Result<Vec<String>> {
+        use schema::*;
+
+        self.with_conn(|conn| {
+            let checks = [
+                (
+                    "balance",
+                    select(exists(
+                        balance::table.filter(balance::stamp_id.eq(stamp_id)),
+                    ))
+                    .get_result::<bool>(conn)?,
+                ),
+                (
+                    "price",
+                    select(exists(price::table.filter(price::stamp_id.eq(stamp_id))))
+                        .get_result::<bool>(conn)?,
+                ),
+                (
+                    "orderbook",
+                    select(exists(
+                        orderbook::table.filter(orderbook::stamp_id.eq(stamp_id)),
+                    ))
+                    .get_result::<bool>(conn)?,
+                ),
+                (
+                    "myorder",
+                    select(exists(
+                        myorder::table.filter(myorder::modified_stamp_id.eq(stamp_id)),
+                    ))
+                    .get_result::<bool>(conn)?,
+                ),
+                (
+                    "pending_approval",
+                    select(exists(
+                        pending_approval::table.filter(pending_approval::stamp_id.eq(stamp_id)),
+                    ))
+                    .get_result::<bool>(conn)?,
+                ),
+            ];
+
+            Ok(checks
+                .iter()
+                .filter(|(_, exists)| *exists)
+                .map(|(table, _)| table.to_string())
+                .collect())
+        })
+    }
+
+    fn stamp_event(&self, stamp_id: StampId) -> Result<StampEvent> {
+        use schema::*;
+
+        self.with_conn(|conn| {
+            let timestamp = stamp::table
+                .find(stamp_id)
+                .select(stamp::timestamp)
+                .first::<NaiveDateTime>(conn)?;
+            let price_rows = price::table
+                .filter(price::stamp_id.eq(stamp_id))
+                .count()
+                .get_result::<i64>(conn)?;
+            let balance_rows = balance::table
+                .filter(balance::stamp_id.eq(stamp_id))
+                .count()
+                .get_result::<i64>(conn)?;
+
+            Ok(StampEvent {
+                stamp_id: stamp_id.inner(),
+                timestamp: format_timestamp(&timestamp),
+                price_rows,
+                balance_rows,
+            })
+        })
+    }
+}
+
+/// Latest stamp id shared by all waiting requests, so that they don't poll DB by themselves
+pub struct EventHub<S: LatestStampSource> {
+    source: Arc<S>,
+    latest: watch::Receiver<Option<StampId>>,
+    /// Event of the last stamp newer than its predecessor, pushed by `/events`
+    stamp_events: watch::Receiver<Option<StampEvent>>,
+}
+
+impl<S: LatestStampSource> EventHub<S> {
+    /// Read the latest stamp once, then spawn a task polling it every `interval`.
+    /// When the latest stamp increases, its event is also read once for every stream of `/events`.
+    /// The task ends when the hub is dropped
+    pub async fn start(source: Arc<S>, interval: Duration) -> Result<Self> {
+        let initial = {
+            let source = source.clone();
+            tokio::task::spawn_blocking(move || source.latest_stamp_id()).await??
+        };
+        let (sender, latest) = watch::channel(initial);
+        let (event_sender, stamp_events) = watch::channel(None);
+
+        let polled = source.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if sender.is_closed() {
+                    break;
+                }
+
+                let source = polled.clone();
+                match tokio::task::spawn_blocking(move || source.latest_stamp_id()).await {
+                    Ok(Ok(latest)) => {
+                        let previous = *sender.borrow();
+                        if previous != latest {
+                            sender.send(latest).ok();
+                        }
+                        let increased = latest.filter(|latest| Some(*latest) > previous);
+                        if let Some(stamp_id) = increased {
+                            let source = polled.clone();
+                            match tokio::task::spawn_blocking(move || source.stamp_event(stamp_id))
+                                .await
+                            {
+                                Ok(Ok(event)) => {
+                                    event_sender.send(Some(event)).ok();
+                                }
+                                Ok(Err(e)) => {
+                                    warn!("Can't read the event of {:?}: {}", stamp_id, e)
+                                }
+                                Err(e) => {
+                                    warn!("Reading the event of {:?} panicked: {}", stamp_id, e)
+                                }
+                            }
+                        }
+                    }
+                    Ok(Err(e)) => warn!("Can't poll the latest stamp: {}", e),
+                    Err(e) => warn!("Polling the latest stamp panicked: {}", e),
+                }
+            }
+        });
+
+        Ok(Self {
+            source,
+            latest,
+            stamp_events,
+        })
+    }
+
+    /// Wait until the latest stamp id differs from `since`, or `timeout` elapses.
+    /// Dropping the returned future, e.g. when the client disconnects, stops waiting without leaving tasks.
+    /// # Returns
+    /// `Some(latest)` if the latest stamp id changed, `None` if timed out
+    pub async fn wait_for_stamp(
+        &self,
+        since: Option<StampId>,
+        timeout: Duration,
+    ) -> Option<Option<StampId>> {
+        let mut latest = self.latest.clone();
+        let wait = async move {
+            loop {
+                let current = *latest.borrow();
+                if current != since {
+                    return Some(current);
+                }
+                if latest.changed().await.is_err() {
+                    // The polling task stopped, so the stamp never changes
+                    std::future::pending::<()>().await;
+                }
+            }
+        };
+
+        tokio::time::timeout(timeout, wait).await.unwrap_or(None)
+    }
+
+    /// `/events`. Server-Sent Events of stamps newer than the connection, with keep-alive comments between them.
+    /// The stream runs no task by itself, so it is dropped with the body when the client disconnects
+    pub fn stamp_event_stream(&self) -> impl Stream<Item = Result<String, Infallible>> + Send {
+        self.stamp_event_stream_with(EVENT_STREAM_KEEP_ALIVE)
+    }
+
+    fn stamp_event_stream_with(
+        &self,
+        keep_alive: Duration,
+    ) -> impl Stream<Item = Result<String, Infallible>> + Send {
+        let mut events = self.stamp_events.clone();
+        // Stamps before the connection are not pushed
+        events.borrow_and_update();
+
+        stream::unfold(events, move |mut events| async move {
+            let changed = tokio::time::timeout(keep_alive, events.changed()).await;
+            let chunk = match changed {
+                Ok(Ok(())) => {
+                    let event = events.borrow_and_update().clone();
+                    match event {
+                        Some(event) => format_stamp_event(&event),
+                        None => keep_alive_comment(),
+                    }
+                }
+                // The polling task stopped, so no event comes anymore
+                Ok(Err(_)) => return None,
+                Err(_) => keep_alive_comment(),
+            };
+            Some((Ok(chunk), events))
+        })
+    }
+
+    /// `api/events?since_stamp=<id>&timeout_sec=<sec>`.
+    /// Respond as soon as the latest stamp id differs from `since_stamp`, or `changed: false` after the timeout
+    pub async fn api_events(&self, query: &QString) -> Result<EventsResponse> {
+        let since = query
+            .get("since_stamp")
+            .map(|s| i32::from_str(s).map(StampId::new))
+            .transpose()?;
+        let timeout = query
+            .get("timeout_sec")
+            .and_then(|s| u64::from_str(s).ok())
+            .map(Duration::from_secs)
+            .filter(|timeout| *timeout <= MAX_EVENTS_TIMEOUT)
+            .unwrap_or(MAX_EVENTS_TIMEOUT);
+
+        let latest = match self.wait_for_stamp(since, timeout).await {
+            Some(latest) => latest,
+            None => {
+                return Ok(EventsResponse {
+                    success: true,
+                    changed: false,
+                    stamp_id: since.map(StampId::inner),
+                    tables: vec![],
+                })
+            }
+        };
+
+        let tables = match latest {
+            Some(stamp_id) => {
+                let source = self.source.clone();
+                tokio::task::spawn_blocking(move || source.tables_at(stamp_id)).await??
+            }
+            None => vec![],
+        };
+
+        Ok(EventsResponse {
+            success: true,
+            changed: true,
+            stamp_id: latest.map(StampId::inner),
+            tables,
+        })
+    }
+}
+
+/// Event named `stamp` with the JSON of `event`
+fn format_stamp_event(event: &StampEvent) -> String {
+    format!(
+        "event: stamp\nid: {}\ndata: {}\n\n",
+        event.stamp_id,
+        serde_json::to_string(event).unwrap_or_default()
+    )
+}
+
+/// Comment ignored by clients
+fn keep_alive_comment() -> String {
+    ": keep-alive\n\n".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::time::Instant;
+
+    /// Latest stamp id changed by tests. Negative means no stamp
+    struct MockSource(AtomicI32);
+
+    impl MockSource {
+        fn new(latest: i32) -> Arc<Self> {
+            Arc::new(Self(AtomicI32::new(latest)))
+        }
+
+        fn set(&self, latest: i32) {
+            self.0.store(latest, Ordering::SeqCst);
+        }
+    }
+
+    impl LatestStampSource for MockSource {
+        fn latest_stamp_id(&self) -> Result<Option<StampId>> {
+            let latest = self.0.load(Ordering::SeqCst);
+            Ok(Some(latest).filter(|id| *id >= 0).map(StampId::new))
+        }
+
+        fn tables_at(&self, _: StampId) -> Result<Vec<String>> {
+            Ok(vec!["price".into()])
+        }
+
+        fn stamp_event(&self, stamp_id: StampId) -> Result<StampEvent> {
+            Ok(StampEvent {
+                stamp_id: stamp_id.inner(),
+                timestamp: "2021-01-01T00:00".into(),
+                price_rows: 4,
+                balance_rows: 2,
+            })
+        }
+    }
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    #[tokio::test]
+    async fn test_wait_for_stamp_timeout() {
+        let hub = EventHub::start(MockSource::new(1), POLL_INTERVAL)
+            .await
+            .unwrap();
+
+        let started = Instant::now();
+        let latest = hub
+            .wait_for_stamp(Some(StampId::new(1)), Duration::from_millis(100))
+            .await;
+
+        assert_eq!(None, latest);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_stamp_wakes_on_new_stamp() {
+        let source = MockSource::new(1);
+        let hub = EventHub::start(source.clone(), POLL_INTERVAL)
+            .await
+            .unwrap();
+
+        let writer = {
+            let source = source.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                source.set(2);
+            })
+        };
+
+        let started = Instant::now();
+        let latest = hub
+            .wait_for_stamp(Some(StampId::new(1)), Duration::from_secs(10))
+            .await;
+        writer.await.unwrap();
+
+        assert_eq!(Some(Some(StampId::new(2))), latest);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_stamp_already_changed() {
+        let hub = EventHub::start(MockSource::new(3), POLL_INTERVAL)
+            .await
+            .unwrap();
+
+        // A client without stamps, or behind the latest stamp, is answered immediately
+        assert_eq!(
+            Some(Some(StampId::new(3))),
+            hub.wait_for_stamp(None, Duration::from_secs(10)).await
+        );
+        assert_eq!(
+            Some(Some(StampId::new(3))),
+            hub.wait_for_stamp(Some(StampId::new(2)), Duration::from_secs(10))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_api_events() {
+        let hub = EventHub::start(MockSource::new(5), POLL_INTERVAL)
+            .await
+            .unwrap();
+
+        let response = hub
+            .api_events(&QString::from("since_stamp=4"))
+            .await
+            .unwrap();
+        assert!(response.changed);
+        assert_eq!(Some(5), response.stamp_id);
+        assert_eq!(vec!["price".to_string()], response.tables);
+
+        let response = hub
+            .api_events(&QString::from("since_stamp=5&timeout_sec=0"))
+            .await
+            .unwrap();
+        assert!(!response.changed);
+        assert_eq!(Some(5), response.stamp_id);
+        assert!(response.tables.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stamp_event_stream() {
+        let source = MockSource::new(1);
+        let hub = EventHub::start(source.clone(), POLL_INTERVAL)
+            .await
+            .unwrap();
+        let mut stream = Box::pin(hub.stamp_event_stream_with(Duration::from_secs(10)));
+
+        source.set(2);
+        let chunk = tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        let mut lines = chunk.lines();
+        assert_eq!(Some("event: stamp"), lines.next());
+        assert_eq!(Some("id: 2"), lines.next());
+        let data = lines.next().unwrap().strip_prefix("data: ").unwrap();
+        let event = serde_json::from_str::<StampEvent>(data).unwrap();
+        assert_eq!(2, event.stamp_id);
+        assert_eq!(4, event.price_rows);
+        assert_eq!(2, event.balance_rows);
+        assert!(chunk.ends_with("\n\n"));
+    }
+
+    #[tokio::test]
+    async fn test_stamp_event_stream_ignores_older_stamp() {
+        let source = MockSource::new(5);
+        let hub = EventHub::start(source.clone(), POLL_INTERVAL)
+            .await
+            .unwrap();
+        let mut stream = Box::pin(hub.stamp_event_stream_with(Duration::from_millis(100)));
+
+        // Deleted stamps don't push events, so only a keep-alive comes
+        source.set(4);
+        let chunk = stream.next().await.unwrap().unwrap();
+        assert_eq!(": keep-alive\n\n", chunk);
+    }
+
+    #[tokio::test]
+    async fn test_stamp_event_stream_ends_with_hub() {
+        let hub = EventHub::start(MockSource::new(1), POLL_INTERVAL)
+            .await
+            .unwrap();
+        let mut stream = Box::pin(hub.stamp_event_stream_with(Duration::from_secs(10)));
+
+        drop(hub);
+        let next = tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap();
+        assert!(next.is_none());
+    }
+}
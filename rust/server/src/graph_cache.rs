@@ -0,0 +1,163 @@
+use anyhow::Result;
+use apply::Apply;
+use database::exchange::ExchangeGraph;
+use database::model::{CurrencyId, StampId};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Exchange graphs constructed at stamps, shared across requests.
+///
+/// Prices of a stamp never change once written, so entries are dropped only when they are older than `ttl`
+/// or the cache exceeds `capacity`
+pub struct GraphCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<HashMap<StampId, (Instant, Arc<ExchangeGraph<CurrencyId>>)>>,
+}
+
+impl GraphCache {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Graph at `stamp_id`, constructed by `construct` unless cached.
+    /// Failures of `construct` are not cached
+    pub fn get_or_construct<F>(
+        &self,
+        stamp_id: StampId,
+        construct: F,
+    ) -> Result<Arc<ExchangeGraph<CurrencyId>>>
+    where
+        F: FnOnce() -> Result<ExchangeGraph<CurrencyId>>,
+    {
+        let now = Instant::now();
+
+        if let Some(graph) = self.get(stamp_id, now) {
+            return Ok(graph);
+        }
+
+        // Construct without the lock, since construction queries DB
+        let graph = construct()?.apply(Arc::new);
+        self.insert(stamp_id, graph.clone(), now);
+
+        Ok(graph)
+    }
+
+    fn get(&self, stamp_id: StampId, now: Instant) -> Option<Arc<ExchangeGraph<CurrencyId>>> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .get(&stamp_id)
+            .filter(|(inserted, _)| now.duration_since(*inserted) < self.ttl)
+            .map(|(_, graph)| graph.clone())
+    }
+
+    fn insert(&self, stamp_id: StampId, graph: Arc<ExchangeGraph<CurrencyId>>, now: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.retain(|_, (inserted, _)| now.duration_since(*inserted) < self.ttl);
+        while entries.len() >= self.capacity && !entries.contains_key(&stamp_id) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (inserted, _))| *inserted)
+                .map(|(stamp_id, _)| *stamp_id);
+            match oldest {
+                Some(oldest) => entries.remove(&oldest),
+                None => break,
+            };
+        }
+        entries.insert(stamp_id, (now, graph));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::cell::Cell;
+
+    const BTC: CurrencyId = CurrencyId::new(1);
+    const USDT: CurrencyId = CurrencyId::new(2);
+
+    fn graph(rate: f64) -> ExchangeGraph<CurrencyId> {
+        ExchangeGraph::from_rates(vec![(BTC, USDT, rate)])
+    }
+
+    #[test]
+    fn test_get_or_construct_hit() {
+        let cache = GraphCache::new(Duration::from_secs(60), 10);
+        let count = Cell::new(0);
+        let construct = |rate| {
+            count.set(count.get() + 1);
+            Ok(graph(rate))
+        };
+
+        let first = cache
+            .get_or_construct(StampId::new(1), || construct(30000.0))
+            .unwrap();
+        let second = cache
+            .get_or_construct(StampId::new(1), || construct(40000.0))
+            .unwrap();
+
+        assert_eq!(1, count.get());
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(Some(30000.0), second.rate_between(BTC, USDT));
+
+        // Another stamp is constructed separately
+        let other = cache
+            .get_or_construct(StampId::new(2), || construct(40000.0))
+            .unwrap();
+        assert_eq!(2, count.get());
+        assert_eq!(Some(40000.0), other.rate_between(BTC, USDT));
+    }
+
+    #[test]
+    fn test_get_or_construct_error_is_not_cached() {
+        let cache = GraphCache::new(Duration::from_secs(60), 10);
+
+        let result = cache.get_or_construct(StampId::new(1), || Err(anyhow!("DB is down")));
+        assert!(result.is_err());
+
+        let graph = cache
+            .get_or_construct(StampId::new(1), || Ok(graph(30000.0)))
+            .unwrap();
+        assert_eq!(Some(30000.0), graph.rate_between(BTC, USDT));
+    }
+
+    #[test]
+    fn test_get_or_construct_expired() {
+        let cache = GraphCache::new(Duration::from_secs(0), 10);
+        let count = Cell::new(0);
+        let construct = || {
+            count.set(count.get() + 1);
+            Ok(graph(30000.0))
+        };
+
+        cache.get_or_construct(StampId::new(1), construct).unwrap();
+        cache.get_or_construct(StampId::new(1), construct).unwrap();
+
+        assert_eq!(2, count.get());
+    }
+
+    #[test]
+    fn test_insert_evicts_oldest() {
+        let cache = GraphCache::new(Duration::from_secs(60), 2);
+        let origin = Instant::now();
+        for (i, stamp_id) in [1, 2, 3].iter().enumerate() {
+            let now = origin + Duration::from_millis(i as u64);
+            cache.insert(StampId::new(*stamp_id), Arc::new(graph(1.0)), now);
+        }
+
+        let now = origin + Duration::from_millis(3);
+        assert!(cache.get(StampId::new(1), now).is_none());
+        assert!(cache.get(StampId::new(2), now).is_some());
+        assert!(cache.get(StampId::new(3), now).is_some());
+    }
+}
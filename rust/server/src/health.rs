@@ -0,0 +1,188 @@
+use crate::api::format_timestamp;
+use crate::config::ServerConfig;
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use database::diesel::dsl::sql;
+use database::diesel::prelude::*;
+use database::diesel::select;
+use database::diesel::sql_types::Integer;
+use database::logic::Conn;
+use database::schema;
+use server_client::response::{DatabaseHealth, HealthResponse};
+use std::time::Duration;
+
+/// Deadline of each DB check, so that `api/health` responds in bounded time even if DB hangs
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// `api/health`. DBs are checked concurrently, and simulation DB only if configured
+pub async fn api_health(config: &ServerConfig) -> HealthResponse {
+    check_health(config, CHECK_TIMEOUT, chrono::Utc::now().naive_utc()).await
+}
+
+async fn check_health(
+    config: &ServerConfig,
+    timeout: Duration,
+    now: NaiveDateTime,
+) -> HealthResponse {
+    let main = {
+        let url = config.database_url.clone();
+        with_deadline(timeout, move || {
+            let conn = Conn::establish(&url)?;
+            ping(&conn)?;
+            latest_stamp_timestamp(&conn)
+        })
+    };
+    let sim = config.sim_database_url.clone().map(|url| {
+        with_deadline(timeout, move || {
+            let conn = Conn::establish(&url)?;
+            ping(&conn)
+        })
+    });
+    let sim = async move {
+        match sim {
+            Some(sim) => Some(sim.await),
+            None => None,
+        }
+    };
+    let (main, sim) = tokio::join!(main, sim);
+
+    let latest_stamp = main.as_ref().ok().copied().flatten();
+    let mut databases = vec![database_health("main", main.map(|_| ()))];
+    databases.extend(sim.map(|sim| database_health("sim", sim)));
+    let ok = databases.iter().all(|database| database.ok);
+
+    HealthResponse {
+        success: true,
+        status: if ok { "ok" } else { "degraded" }.to_string(),
+        databases,
+        latest_stamp: latest_stamp.as_ref().map(format_timestamp),
+        latest_stamp_age_sec: latest_stamp.map(|timestamp| (now - timestamp).num_seconds()),
+    }
+}
+
+/// Run blocking `f` on the blocking thread pool, giving up after `timeout`.
+/// `f` keeps its thread until it ends by itself, like a connection attempt timing out
+async fn with_deadline<T, F>(timeout: Duration, f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    match tokio::time::timeout(timeout, tokio::task::spawn_blocking(f)).await {
+        Ok(joined) => joined?,
+        Err(_) => Err(anyhow!("No response in {} sec", timeout.as_secs_f64())),
+    }
+}
+
+fn ping(conn: &Conn) -> Result<()> {
+    select(sql::<Integer>("1")).get_result::<i32>(conn)?;
+    Ok(())
+}
+
+fn latest_stamp_timestamp(conn: &Conn) -> Result<Option<NaiveDateTime>> {
+    schema::stamp::table
+        .select(schema::stamp::timestamp)
+        .order(schema::stamp::timestamp.desc())
+        .first::<NaiveDateTime>(conn)
+        .optional()
+        .map_err(Into::into)
+}
+
+fn database_health(name: &str, result: Result<()>) -> DatabaseHealth {
+    if let Err(e) = result.as_ref() {
+        warn!("Health check of {} DB failed: {}", name, e);
+    }
+
+    DatabaseHealth {
+        name: name.to_string(),
+        ok: result.is_ok(),
+        error: result.err().map(|e| e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use std::time::Instant;
+
+    /// Nothing listens on port 1, so connections are refused
+    const BOGUS_URL: &str = "mysql://127.0.0.1:1/trade";
+
+    fn config(sim_database_url: Option<&str>) -> ServerConfig {
+        ServerConfig {
+            address: ([127, 0, 0, 1], 0).into(),
+            webcontent_root: std::env::temp_dir(),
+            database_url: BOGUS_URL.into(),
+            sim_database_url: sim_database_url.map(Into::into),
+            path_prefix: None,
+            disable_static: false,
+            api_token: None,
+            events_poll_interval: Duration::from_secs(1),
+            graph_cache_ttl: Duration::from_secs(60),
+            graph_cache_capacity: 16,
+            shutdown_drain_timeout: Duration::from_secs(1),
+        }
+    }
+
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 0, 0)
+    }
+
+    #[tokio::test]
+    async fn test_degraded() {
+        let health = check_health(&config(None), CHECK_TIMEOUT, now()).await;
+
+        assert!(health.success);
+        assert_eq!("degraded", health.status);
+        assert_eq!(1, health.databases.len());
+        assert_eq!("main", health.databases[0].name);
+        assert!(!health.databases[0].ok);
+        assert!(health.databases[0].error.is_some());
+        assert_eq!(None, health.latest_stamp);
+        assert_eq!(None, health.latest_stamp_age_sec);
+    }
+
+    #[tokio::test]
+    async fn test_degraded_sim() {
+        let health = check_health(&config(Some(BOGUS_URL)), CHECK_TIMEOUT, now()).await;
+
+        assert_eq!("degraded", health.status);
+        let names = health
+            .databases
+            .iter()
+            .map(|database| database.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["main", "sim"], names);
+        assert!(health.databases.iter().all(|database| !database.ok));
+    }
+
+    #[tokio::test]
+    async fn test_with_deadline() {
+        let start = Instant::now();
+        let hung = with_deadline(Duration::from_millis(100), || {
+            std::thread::sleep(Duration::from_secs(2));
+            Ok(())
+        })
+        .await;
+
+        assert!(hung.is_err());
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let answered = with_deadline(Duration::from_secs(1), || Ok(1)).await;
+        assert_eq!(1, answered.unwrap());
+    }
+
+    /// Requires `TEST_DATABASE_URL`
+    #[tokio::test]
+    #[ignore]
+    async fn test_ok() {
+        let mut config = config(None);
+        config.database_url = std::env::var("TEST_DATABASE_URL").unwrap();
+
+        let health = check_health(&config, CHECK_TIMEOUT, now()).await;
+
+        assert_eq!("ok", health.status);
+        assert!(health.databases[0].ok);
+        assert_eq!(None, health.databases[0].error);
+    }
+}
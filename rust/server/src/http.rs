@@ -0,0 +1,270 @@
+use database::diesel::result::{ConnectionError, Error as DieselError};
+use database::error::{Error as DatabaseError, LogicError};
+use hyper::header::{CONTENT_TYPE, LOCATION};
+use hyper::{Body, Response, StatusCode};
+use thiserror::Error as ThisError;
+
+/// Error carrying the status code of its response
+#[derive(Debug, ThisError)]
+#[error("{message}")]
+pub struct HttpError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl HttpError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+
+    pub fn method_not_allowed(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::METHOD_NOT_ALLOWED, message)
+    }
+}
+
+/// Status code of the response to `e`.
+///
+/// `HttpError` has its own status. Missing rows or files are 404, and malformed numbers in queries are 400.
+/// Rejections by logic of DB are classified by their kinds.
+/// Other errors are unexpected failures like those of DB, so they are 500
+pub fn error_status(e: &anyhow::Error) -> StatusCode {
+    if let Some(e) = e.downcast_ref::<HttpError>() {
+        return e.status;
+    }
+    if let Some(e) = e.downcast_ref::<DieselError>() {
+        return match e {
+            DieselError::NotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+    }
+    if let Some(e) = e.downcast_ref::<DatabaseError>() {
+        return match e {
+            DatabaseError::Db(DieselError::NotFound) => StatusCode::NOT_FOUND,
+            DatabaseError::Logic(LogicError::ApprovalNotFound) => StatusCode::NOT_FOUND,
+            DatabaseError::Logic(LogicError::ApprovalNotPending)
+            | DatabaseError::Logic(LogicError::ApprovalExpired) => StatusCode::CONFLICT,
+            DatabaseError::Logic(LogicError::InvalidTag) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+    }
+    if e.is::<ConnectionError>() {
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    if e.is::<std::num::ParseIntError>() || e.is::<std::num::ParseFloatError>() {
+        return StatusCode::BAD_REQUEST;
+    }
+    if let Some(e) = e.downcast_ref::<std::io::Error>() {
+        return match e.kind() {
+            std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+    }
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+/// Message of `e` if it rejects the query, like an unknown market, to be reported by `success: false`.
+/// # Returns
+/// `Err(e)` for other errors like failures of DB, so that they are responded with their status
+pub fn rejection_message(e: anyhow::Error) -> anyhow::Result<String> {
+    match e.downcast_ref::<HttpError>() {
+        Some(http_error) if http_error.status.is_client_error() => Ok(http_error.message.clone()),
+        _ => Err(e),
+    }
+}
+
+/// Content type of a static file, inferred from its extension
+pub fn content_type(path: &str) -> &'static str {
+    let extension = path
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        Some("txt") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Rendered content of a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rendered {
+    pub status: StatusCode,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+    /// `Location` header of redirects
+    pub location: Option<String>,
+}
+
+impl Rendered {
+    pub fn json(body: Vec<u8>) -> Self {
+        Self {
+            status: StatusCode::OK,
+            content_type: "application/json",
+            body,
+            location: None,
+        }
+    }
+
+    pub fn file(path: &str, body: Vec<u8>) -> Self {
+        Self {
+            status: StatusCode::OK,
+            content_type: content_type(path),
+            body,
+            location: None,
+        }
+    }
+
+    /// `{"success":false,"error":...}` with the status of `e`
+    pub fn api_error(e: &anyhow::Error) -> Self {
+        let response = serde_json::json!({
+            "success": false,
+            "error": e.to_string(),
+        });
+        Self {
+            status: error_status(e),
+            content_type: "application/json",
+            body: serde_json::to_vec(&response).unwrap_or_default(),
+            location: None,
+        }
+    }
+
+    /// HTML page linking to `index` with the status of `e`
+    pub fn page_error(e: &anyhow::Error, index: &str) -> Self {
+        let body = format!("<html><body>An error occurred during parsing http request <a href=\"{}\">index</a></body></html>", index);
+        Self {
+            status: error_status(e),
+            content_type: "text/html; charset=utf-8",
+            body: body.into_bytes(),
+            location: None,
+        }
+    }
+
+    /// Permanent redirect to `location`
+    pub fn redirect(location: String) -> Self {
+        Self {
+            status: StatusCode::MOVED_PERMANENTLY,
+            content_type: "text/html; charset=utf-8",
+            body: vec![],
+            location: Some(location),
+        }
+    }
+
+    pub fn into_response(self) -> Response<Body> {
+        let mut builder = Response::builder()
+            .status(self.status)
+            .header(CONTENT_TYPE, self.content_type);
+        if let Some(location) = self.location {
+            builder = builder.header(LOCATION, location);
+        }
+        builder
+            .body(Body::from(self.body))
+            .expect("Status, content type and location are always valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_error_status() {
+        assert_eq!(
+            StatusCode::NOT_FOUND,
+            error_status(&HttpError::not_found("No route").into())
+        );
+        assert_eq!(
+            StatusCode::NOT_FOUND,
+            error_status(&DieselError::NotFound.into())
+        );
+        assert_eq!(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            error_status(&DieselError::RollbackTransaction.into())
+        );
+        assert_eq!(
+            StatusCode::NOT_FOUND,
+            error_status(&std::io::Error::from(std::io::ErrorKind::NotFound).into())
+        );
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            error_status(&HttpError::bad_request("market is not specified").into())
+        );
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            error_status(&i32::from_str("x").unwrap_err().into())
+        );
+        assert_eq!(
+            StatusCode::CONFLICT,
+            error_status(&DatabaseError::Logic(LogicError::ApprovalExpired).into())
+        );
+        assert_eq!(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            error_status(&DatabaseError::Logic(LogicError::NonLatestStamp).into())
+        );
+        assert_eq!(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            error_status(&anyhow!("No price of approval 1"))
+        );
+    }
+
+    #[test]
+    fn test_rejection_message() {
+        assert_eq!(
+            "Unknown market: BTC-XYZ",
+            rejection_message(HttpError::bad_request("Unknown market: BTC-XYZ").into()).unwrap()
+        );
+
+        let e = rejection_message(DieselError::RollbackTransaction.into()).unwrap_err();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, error_status(&e));
+    }
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!("text/html; charset=utf-8", content_type("index.html"));
+        assert_eq!("text/css; charset=utf-8", content_type("style.css"));
+        assert_eq!("text/javascript; charset=utf-8", content_type("chart.js"));
+        assert_eq!("image/png", content_type("logo.PNG"));
+        assert_eq!("image/svg+xml", content_type("icon.svg"));
+        assert_eq!("application/octet-stream", content_type("README"));
+    }
+
+    #[test]
+    fn test_api_error() {
+        let rendered = Rendered::api_error(&HttpError::bad_request("Invalid market: BTC").into());
+
+        assert_eq!(StatusCode::BAD_REQUEST, rendered.status);
+        assert_eq!("application/json", rendered.content_type);
+        let json: serde_json::Value = serde_json::from_slice(&rendered.body).unwrap();
+        assert_eq!(
+            serde_json::json!({ "success": false, "error": "Invalid market: BTC" }),
+            json
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use itertools::Itertools;
+
+/// Kinds of journal events. Events at the same stamp are ordered as declared
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EventKind {
+    Price,
+    Recommendation,
+    Order,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event<S, P> {
+    pub stamp: S,
+    pub kind: EventKind,
+    pub payload: P,
+}
+
+impl<S, P> Event<S, P> {
+    pub fn new(stamp: S, kind: EventKind, payload: P) -> Self {
+        Self {
+            stamp,
+            kind,
+            payload,
+        }
+    }
+}
+
+/// Merge three streams sorted by stamp into one time-ordered stream.
+///
+/// Events at the same stamp are ordered as price < recommendation < order,
+/// and events of the same kind keep their original order.
+pub fn merge_events<S, P>(
+    prices: impl IntoIterator<Item = Event<S, P>>,
+    recommendations: impl IntoIterator<Item = Event<S, P>>,
+    orders: impl IntoIterator<Item = Event<S, P>>,
+) -> impl Iterator<Item = Event<S, P>>
+where
+    S: Ord,
+{
+    // Taking from the left on ties keeps the kind order and the stability
+    let le = |e1: &Event<S, P>, e2: &Event<S, P>| (&e1.stamp, e1.kind) <= (&e2.stamp, e2.kind);
+
+    prices
+        .into_iter()
+        .merge_by(recommendations, le)
+        .merge_by(orders, le)
+}
+
+/// Take events of whole stamps as long as their count does not exceed `limit`.
+/// Events of the first stamp are always taken even if they exceed `limit`, so that pages always advance.
+/// # Returns
+/// Taken events and the stamp of the first remaining event, which is the cursor of the next page
+pub fn paginate<S, P>(
+    events: impl IntoIterator<Item = Event<S, P>>,
+    limit: usize,
+) -> (Vec<Event<S, P>>, Option<S>)
+where
+    S: PartialEq + Clone,
+{
+    let mut page = vec![];
+
+    for (stamp, group) in events.into_iter().group_by(|e| e.stamp.clone()).into_iter() {
+        let group = group.collect_vec();
+        if !page.is_empty() && page.len() + group.len() > limit {
+            return (page, Some(stamp));
+        }
+        page.extend(group);
+    }
+
+    (page, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(stamp: i32, kind: EventKind, payload: &'static str) -> Event<i32, &'static str> {
+        Event::new(stamp, kind, payload)
+    }
+
+    #[test]
+    fn test_merge_events() {
+        let prices = vec![
+            event(1, EventKind::Price, "p1"),
+            event(2, EventKind::Price, "p2"),
+            event(4, EventKind::Price, "p4"),
+        ];
+        let recommendations = vec![
+            event(2, EventKind::Recommendation, "r2"),
+            event(3, EventKind::Recommendation, "r3"),
+        ];
+        let orders = vec![
+            event(2, EventKind::Order, "o2a"),
+            event(2, EventKind::Order, "o2b"),
+            event(4, EventKind::Order, "o4"),
+        ];
+
+        // Pass streams in reverse order of kinds to check tie-breaks don't depend on argument order
+        let merged = merge_events(orders.clone(), recommendations.clone(), prices.clone())
+            .map(|e| e.payload)
+            .collect_vec();
+        let expected = vec!["p1", "p2", "r2", "o2a", "o2b", "r3", "p4", "o4"];
+        assert_eq!(expected, merged);
+
+        let merged = merge_events(prices, recommendations, orders)
+            .map(|e| e.payload)
+            .collect_vec();
+        assert_eq!(expected, merged);
+    }
+
+    #[test]
+    fn test_merge_events_empty_streams() {
+        let prices = vec![event(1, EventKind::Price, "p1")];
+
+        let merged = merge_events(prices, vec![], vec![]).collect_vec();
+
+        assert_eq!(vec![event(1, EventKind::Price, "p1")], merged);
+        let empty: Vec<Event<i32, ()>> = vec![];
+        assert_eq!(0, merge_events(empty.clone(), empty.clone(), empty).count());
+    }
+
+    #[test]
+    fn test_paginate() {
+        let events = vec![
+            event(1, EventKind::Price, "p1"),
+            event(1, EventKind::Order, "o1"),
+            event(2, EventKind::Price, "p2"),
+            event(2, EventKind::Order, "o2"),
+            event(3, EventKind::Price, "p3"),
+        ];
+
+        // Stamp 2 doesn't fit in the page
+        let (page, cursor) = paginate(events.clone(), 3);
+        assert_eq!(
+            vec!["p1", "o1"],
+            page.iter().map(|e| e.payload).collect_vec()
+        );
+        assert_eq!(Some(2), cursor);
+
+        let (page, cursor) = paginate(events.clone(), 5);
+        assert_eq!(5, page.len());
+        assert_eq!(None, cursor);
+    }
+
+    #[test]
+    fn test_paginate_first_stamp_exceeds_limit() {
+        let events = vec![
+            event(1, EventKind::Price, "p1"),
+            event(1, EventKind::Order, "o1"),
+            event(2, EventKind::Price, "p2"),
+        ];
+
+        let (page, cursor) = paginate(events, 1);
+
+        assert_eq!(
+            vec!["p1", "o1"],
+            page.iter().map(|e| e.payload).collect_vec()
+        );
+        assert_eq!(Some(2), cursor);
+    }
+}
@@ -1,45 +1,92 @@
-use anyhow::{anyhow, ensure, Error, Result};
-use apply::Apply;
+use anyhow::{Error, Result};
+use config::ServerConfig;
+use events::{DbLatestStampSource, EventHub, LatestStampSource};
+use graph_cache::GraphCache;
+use http::{HttpError, Rendered};
+use hyper::header::{AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE};
 use hyper::server::Server;
 use hyper::service::*;
-use hyper::{Body, Request, Response, Uri};
-use json::JsonValue;
+use hyper::{Body, Request, Response, StatusCode};
 use qstring::QString;
-use std::env;
+use route::Route;
+use serde::Serialize;
+use std::future::Future;
 use std::io::Read;
 use std::net::SocketAddr;
-use std::ops::Deref;
-use std::path::PathBuf;
-use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 #[macro_use]
 extern crate log;
 
+mod access_log;
 mod api;
-mod exchange_graph;
+mod auth;
+mod balance_current;
+mod balance_history;
+mod candle;
+mod config;
+mod currency_filter;
+mod depth;
+mod events;
+mod graph_cache;
+mod health;
+mod http;
+mod journal;
+mod risk;
+mod route;
+mod tag_group;
+mod window;
 
-fn render(uri: &Uri) -> Result<Vec<u8>> {
-    // Skip front slash
-    let path = &uri.path()[1..];
+/// Render a request except `/events`, `api/events` and `api/health`.
+/// Failures of APIs are rendered as JSON, and the others as HTML page, with their status codes
+fn render(config: &ServerConfig, graphs: &GraphCache, req: &Request<Body>) -> Rendered {
+    let uri = req.uri();
     let query = QString::from(uri.query().unwrap_or_default());
 
-    if path.starts_with("api/") {
-        let api_path = &path["api/".len()..];
-        render_api(api_path, &query).map(|json| json.to_string().into_bytes())
-    } else {
-        render_file(path)
-    }
+    let route = route::resolve(
+        uri.path(),
+        config.path_prefix.as_deref(),
+        !config.disable_static,
+    );
+    let rendered = match route {
+        Some(Route::Api(api_path)) => {
+            render_api(config, graphs, api_path, &query, req).map(Rendered::json)
+        }
+        Some(Route::File(path)) => render_file(config, path).map(|body| Rendered::file(path, body)),
+        Some(Route::PrefixRoot) => {
+            let mut location = route::link(config.path_prefix.as_deref(), "");
+            if let Some(query) = uri.query() {
+                location = format!("{}?{}", location, query);
+            }
+            Ok(Rendered::redirect(location))
+        }
+        // `/events` is streamed by `stream_events`
+        Some(Route::Events) | None => {
+            Err(HttpError::not_found(format!("No route for {}", uri.path())).into())
+        }
+    };
+
+    rendered.unwrap_or_else(|e| {
+        warn!("{}", e);
+        match route {
+            Some(Route::Api(_)) => Rendered::api_error(&e),
+            _ => Rendered::page_error(
+                &e,
+                &route::link(config.path_prefix.as_deref(), "index.html"),
+            ),
+        }
+    })
 }
 
-fn render_file(path: &str) -> Result<Vec<u8>> {
+fn render_file(config: &ServerConfig, path: &str) -> Result<Vec<u8>> {
     let is_safe_path = path
         .chars()
         .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-');
-    ensure!(is_safe_path, "Invalid file path: {}", path);
+    if !is_safe_path {
+        return Err(HttpError::bad_request(format!("Invalid file path: {}", path)).into());
+    }
 
-    let path = env::var("WEBCONTENT_ROOT")?
-        .deref()
-        .apply(PathBuf::from)
-        .apply_ref(|p| p.join(path));
+    let path = config.webcontent_root.join(path);
 
     debug!("Read file: {:?}", path);
 
@@ -50,24 +97,231 @@ fn render_file(path: &str) -> Result<Vec<u8>> {
     Ok(bytes)
 }
 
-fn render_api(api_path: &str, query: &QString) -> Result<JsonValue> {
+fn render_api(
+    config: &ServerConfig,
+    graphs: &GraphCache,
+    api_path: &str,
+    query: &QString,
+    req: &Request<Body>,
+) -> Result<Vec<u8>> {
     match api_path {
-        "balance_history" => api::api_balance_history(query),
-        other => Err(anyhow!("Invalid api: {}", other)),
+        "status" => api::api_status(config, query).and_then(to_json),
+        "balance_history" => api::api_balance_history(config, graphs, query).and_then(to_json),
+        "balance_current" => api::api_balance_current(config, query).and_then(to_json),
+        "inconsistent_cycles" => api::api_inconsistent_cycles(config, query).and_then(to_json),
+        "orderbook" | "orderbook_depth" => {
+            api::api_orderbook_depth(config, query).and_then(to_json)
+        }
+        "risk_metrics" => api::api_risk_metrics(config, graphs, query).and_then(to_json),
+        "market_journal" => api::api_market_journal(config, query).and_then(to_json),
+        "price_history" => api::api_price_history(config, query).and_then(to_json),
+        "recommendation_history" => {
+            api::api_recommendation_history(config, query).and_then(to_json)
+        }
+        "order_history" => api::api_order_history(config, query).and_then(to_json),
+        "approve_order" => api::api_approve_order(config, req.method(), authorization(req), query)
+            .and_then(to_json),
+        "currency_tags" => api::api_currency_tags(config, req.method(), authorization(req), query)
+            .and_then(to_json),
+        other => Err(HttpError::not_found(format!("Invalid api: {}", other)).into()),
     }
 }
 
-async fn handle(req: Request<Body>) -> Result<Response<Body>> {
-    let content = match render(req.uri()) {
-        Ok(content) => content,
-        Err(e) => {
-            warn!("{}", e);
-            "<html><body>An error occurred during parsing http request <a href=\"index.html\">index</a></body></html>"
-            .as_bytes().to_vec()
+fn authorization(req: &Request<Body>) -> Option<&str> {
+    req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+}
+
+fn to_json<T: Serialize>(response: T) -> Result<Vec<u8>> {
+    serde_json::to_vec(&response).map_err(Into::into)
+}
+
+/// `api/events` waits for a new stamp, and `api/health` waits for DB checks with deadlines,
+/// so they are rendered asynchronously unlike the other APIs
+async fn render_async<S: LatestStampSource>(
+    config: &ServerConfig,
+    events: &EventHub<S>,
+    req: &Request<Body>,
+) -> Option<Rendered> {
+    let route = route::resolve(
+        req.uri().path(),
+        config.path_prefix.as_deref(),
+        !config.disable_static,
+    );
+    let rendered = match route {
+        Some(Route::Api("events")) => {
+            let query = QString::from(req.uri().query().unwrap_or_default());
+            events
+                .api_events(&query)
+                .await
+                .and_then(to_json)
+                .map(Rendered::json)
         }
+        Some(Route::Api("health")) => {
+            let health = health::api_health(config).await;
+            // Reverse proxies and probes see unreachable DBs by the status
+            let status = match health.status.as_str() {
+                "ok" => StatusCode::OK,
+                _ => StatusCode::SERVICE_UNAVAILABLE,
+            };
+            to_json(health).map(|body| Rendered {
+                status,
+                ..Rendered::json(body)
+            })
+        }
+        _ => return None,
     };
 
-    Ok(Response::new(Body::from(content)))
+    let rendered = rendered.unwrap_or_else(|e| {
+        warn!("{}", e);
+        Rendered::api_error(&e)
+    });
+    Some(rendered)
+}
+
+/// `/events` keeps the response open, pushing Server-Sent Events of new stamps
+fn stream_events<S: LatestStampSource>(
+    config: &ServerConfig,
+    events: &EventHub<S>,
+    req: &Request<Body>,
+) -> Option<Response<Body>> {
+    let route = route::resolve(
+        req.uri().path(),
+        config.path_prefix.as_deref(),
+        !config.disable_static,
+    );
+    if route != Some(Route::Events) {
+        return None;
+    }
+
+    let response = Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(Body::wrap_stream(events.stamp_event_stream()))
+        .expect("Headers are always valid");
+    Some(response)
+}
+
+async fn handle<S: LatestStampSource>(
+    config: Arc<ServerConfig>,
+    events: Arc<EventHub<S>>,
+    graphs: Arc<GraphCache>,
+    req: Request<Body>,
+) -> Result<Response<Body>> {
+    if let Some(response) = stream_events(&config, &events, &req) {
+        return Ok(response);
+    }
+
+    let rendered = match render_async(&config, &events, &req).await {
+        Some(rendered) => rendered,
+        None => render(&config, &graphs, &req),
+    };
+
+    Ok(rendered.into_response())
+}
+
+/// Bind `config.address`, serving until `shutdown` completes.
+/// # Returns
+/// The bound address, which differs from `config.address` of port 0, and the server to be awaited
+fn bind<S, F>(
+    config: Arc<ServerConfig>,
+    events: Arc<EventHub<S>>,
+    graphs: Arc<GraphCache>,
+    shutdown: F,
+) -> hyper::Result<(SocketAddr, impl Future<Output = hyper::Result<()>>)>
+where
+    S: LatestStampSource,
+    F: Future<Output = ()> + Send + 'static,
+{
+    let addr = config.address;
+    let drain_timeout = config.shutdown_drain_timeout;
+    let handler = move |req| handle(config.clone(), events.clone(), graphs.clone(), req);
+
+    serve(&addr, handler, shutdown, drain_timeout)
+}
+
+/// Bind `addr` and respond by `handler` with access logs.
+/// After `shutdown` completes, new connections are refused,
+/// and in-flight requests are waited for up to `drain_timeout`.
+/// # Returns
+/// The bound address and the server to be awaited, which ends after draining
+fn serve<H, R, F>(
+    addr: &SocketAddr,
+    handler: H,
+    shutdown: F,
+    drain_timeout: Duration,
+) -> hyper::Result<(SocketAddr, impl Future<Output = hyper::Result<()>>)>
+where
+    H: Fn(Request<Body>) -> R + Clone + Send + 'static,
+    R: Future<Output = Result<Response<Body>>> + Send + 'static,
+    F: Future<Output = ()> + Send + 'static,
+{
+    let make_service = make_service_fn(move |_conn| {
+        let handler = handler.clone();
+        async move {
+            Result::<_, Error>::Ok(service_fn(move |req| {
+                access_log::logged(req, handler.clone())
+            }))
+        }
+    });
+
+    let server = Server::try_bind(addr)?.serve(make_service);
+    let local_addr = server.local_addr();
+
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+    let server = server.with_graceful_shutdown(async move {
+        shutdown.await;
+        info!("Shutting down, waiting in-flight requests");
+        shutdown_tx.send(()).ok();
+    });
+    let server = async move {
+        // The sender is dropped without sending only if the server ended before shutdown
+        let drain_deadline = async move {
+            match shutdown_rx.await {
+                Ok(()) => tokio::time::sleep(drain_timeout).await,
+                Err(_) => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            result = server => result,
+            _ = drain_deadline => {
+                warn!(
+                    "In-flight requests are dropped after {} sec",
+                    drain_timeout.as_secs_f64()
+                );
+                Ok(())
+            }
+        }
+    };
+
+    Ok((local_addr, server))
+}
+
+/// Complete on SIGINT, or SIGTERM like `docker stop`
+async fn shutdown_signal() {
+    let interrupt = tokio::signal::ctrl_c();
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = interrupt => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(e) => {
+                warn!("Can't listen SIGTERM: {}", e);
+                interrupt.await.ok();
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    interrupt.await.ok();
 }
 
 #[tokio::main]
@@ -75,24 +329,348 @@ async fn main() {
     dotenv::dotenv().ok();
     env_logger::try_init().ok();
 
-    let addr = match env::var("SERVER_ADDRESS")
-        .map_err(Error::from)
-        .and_then(|addr| SocketAddr::from_str(&addr).map_err(Error::from))
-    {
-        Ok(addr) => addr,
+    info!("Server started with {}", common::build_info());
+
+    let config = match ServerConfig::from_env() {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+
+    let source = Arc::new(DbLatestStampSource::new(config.database_url.clone()));
+    let events = match EventHub::start(source, config.events_poll_interval).await {
+        Ok(events) => Arc::new(events),
         Err(e) => {
-            error!("Can't determine server address: {}", e);
+            error!("{}", e);
             return;
         }
     };
 
-    let make_service =
-        make_service_fn(|_conn| async { Result::<_, Error>::Ok(service_fn(handle)) });
+    // Graphs are shared across requests, since constructing them for long history is expensive
+    let graphs = Arc::new(GraphCache::new(
+        config.graph_cache_ttl,
+        config.graph_cache_capacity,
+    ));
 
-    let server = Server::bind(&addr).serve(make_service);
+    let server = match bind(config, events, graphs, shutdown_signal()) {
+        Ok((_, server)) => server,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
 
-    // Run forever...
+    // Run until shutdown signal
     if let Err(e) = server.await {
         eprintln!("server error: {}", e);
     }
+
+    info!("Server stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::model::StampId;
+    use hyper::body::HttpBody;
+    use hyper::header::LOCATION;
+    use hyper::Method;
+    use server_client::response::StampEvent;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::time::Duration;
+
+    struct FixedSource;
+
+    impl LatestStampSource for FixedSource {
+        fn latest_stamp_id(&self) -> Result<Option<StampId>> {
+            Ok(Some(StampId::new(1)))
+        }
+
+        fn tables_at(&self, _: StampId) -> Result<Vec<String>> {
+            Ok(vec!["price".into()])
+        }
+
+        fn stamp_event(&self, stamp_id: StampId) -> Result<StampEvent> {
+            Ok(StampEvent {
+                stamp_id: stamp_id.inner(),
+                timestamp: "2021-01-01T00:00".into(),
+                price_rows: 1,
+                balance_rows: 0,
+            })
+        }
+    }
+
+    /// Latest stamp id set by tests, as if stamps are inserted
+    struct InsertedSource(AtomicI32);
+
+    impl LatestStampSource for InsertedSource {
+        fn latest_stamp_id(&self) -> Result<Option<StampId>> {
+            Ok(Some(StampId::new(self.0.load(Ordering::SeqCst))))
+        }
+
+        fn tables_at(&self, _: StampId) -> Result<Vec<String>> {
+            Ok(vec!["balance".into(), "price".into()])
+        }
+
+        fn stamp_event(&self, stamp_id: StampId) -> Result<StampEvent> {
+            Ok(StampEvent {
+                stamp_id: stamp_id.inner(),
+                timestamp: "2021-01-01T00:10".into(),
+                price_rows: 40,
+                balance_rows: 3,
+            })
+        }
+    }
+
+    /// Web content root containing `index.html` only
+    fn webcontent_root() -> PathBuf {
+        let root = std::env::temp_dir().join(format!("server_handle_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("index.html"), "<html></html>").unwrap();
+        root
+    }
+
+    /// DB is unreachable, so APIs reading DB fail
+    fn config() -> Arc<ServerConfig> {
+        Arc::new(ServerConfig {
+            address: ([127, 0, 0, 1], 0).into(),
+            webcontent_root: webcontent_root(),
+            database_url: "mysql://127.0.0.1:1/trade".into(),
+            sim_database_url: None,
+            path_prefix: None,
+            disable_static: false,
+            api_token: None,
+            events_poll_interval: Duration::from_secs(1),
+            graph_cache_ttl: Duration::from_secs(60),
+            graph_cache_capacity: 16,
+            shutdown_drain_timeout: Duration::from_secs(1),
+        })
+    }
+
+    async fn request(method: Method, path: &str) -> Response<Body> {
+        request_to(config(), method, path).await
+    }
+
+    async fn request_to(config: Arc<ServerConfig>, method: Method, path: &str) -> Response<Body> {
+        let events = EventHub::start(Arc::new(FixedSource), Duration::from_secs(1))
+            .await
+            .unwrap();
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::empty())
+            .unwrap();
+        let graphs = GraphCache::new(Duration::from_secs(60), 16);
+        handle(config, Arc::new(events), Arc::new(graphs), req)
+            .await
+            .unwrap()
+    }
+
+    fn content_type(response: &Response<Body>) -> &str {
+        response.headers()[CONTENT_TYPE].to_str().unwrap()
+    }
+
+    async fn json(response: Response<Body>) -> serde_json::Value {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_handle_static_file() {
+        let response = request(Method::GET, "/").await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("text/html; charset=utf-8", content_type(&response));
+
+        let response = request(Method::GET, "/missing.js").await;
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert_eq!("text/html; charset=utf-8", content_type(&response));
+    }
+
+    #[tokio::test]
+    async fn test_handle_bare_prefix() {
+        let config = Arc::new(ServerConfig {
+            path_prefix: Some("/asset".into()),
+            ..(*config()).clone()
+        });
+
+        // Relative links of the page resolve under the prefix after redirect
+        let response = request_to(config.clone(), Method::GET, "/asset?sim=1").await;
+        assert_eq!(StatusCode::MOVED_PERMANENTLY, response.status());
+        assert_eq!("/asset/?sim=1", response.headers()[LOCATION]);
+
+        let response = request_to(config, Method::GET, "/asset/").await;
+        assert_eq!(StatusCode::OK, response.status());
+    }
+
+    #[tokio::test]
+    async fn test_handle_unsafe_path() {
+        let response = request(Method::GET, "/index~.html").await;
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    }
+
+    #[tokio::test]
+    async fn test_handle_unknown_api() {
+        let response = request(Method::GET, "/api/unknown").await;
+
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert_eq!("application/json", content_type(&response));
+        let json = json(response).await;
+        assert_eq!(serde_json::json!(false), json["success"]);
+        assert_eq!(serde_json::json!("Invalid api: unknown"), json["error"]);
+    }
+
+    #[tokio::test]
+    async fn test_handle_api_method_and_token() {
+        let response = request(Method::GET, "/api/approve_order?id=1").await;
+        assert_eq!(StatusCode::METHOD_NOT_ALLOWED, response.status());
+
+        // No token is configured
+        let response = request(Method::POST, "/api/approve_order?id=1").await;
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+        assert_eq!("application/json", content_type(&response));
+    }
+
+    #[tokio::test]
+    async fn test_handle_db_failure() {
+        let response = request(Method::GET, "/api/status").await;
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, response.status());
+        assert_eq!(serde_json::json!(false), json(response).await["success"]);
+    }
+
+    #[tokio::test]
+    async fn test_handle_events() {
+        let response = request(Method::GET, "/api/events?since_stamp=0").await;
+
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("application/json", content_type(&response));
+        let json = json(response).await;
+        assert_eq!(serde_json::json!(true), json["changed"]);
+        assert_eq!(serde_json::json!(1), json["stampId"]);
+    }
+
+    #[tokio::test]
+    async fn test_handle_health() {
+        let response = request(Method::GET, "/api/health").await;
+
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+        assert_eq!("application/json", content_type(&response));
+        let json = json(response).await;
+        assert_eq!(serde_json::json!(true), json["success"]);
+        assert_eq!(serde_json::json!("degraded"), json["status"]);
+        assert_eq!(serde_json::json!("main"), json["databases"][0]["name"]);
+        assert_eq!(serde_json::json!(false), json["databases"][0]["ok"]);
+        assert!(json["databases"][0]["error"].is_string());
+        assert!(json["latestStamp"].is_null());
+        assert!(json["latestStampAgeSec"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_event_stream() {
+        let source = Arc::new(InsertedSource(AtomicI32::new(1)));
+        let events = EventHub::start(source.clone(), Duration::from_millis(10))
+            .await
+            .unwrap();
+        let graphs = GraphCache::new(Duration::from_secs(60), 16);
+        let (addr, server) = bind(
+            config(),
+            Arc::new(events),
+            Arc::new(graphs),
+            std::future::pending(),
+        )
+        .unwrap();
+        let server = tokio::spawn(server);
+
+        let uri = format!("http://{}/events", addr).parse().unwrap();
+        let mut response = hyper::Client::new().get(uri).await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("text/event-stream", content_type(&response));
+
+        // A stamp is inserted after connecting
+        source.0.store(2, Ordering::SeqCst);
+        let chunk = tokio::time::timeout(Duration::from_secs(5), response.body_mut().data())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let chunk = String::from_utf8(chunk.to_vec()).unwrap();
+
+        assert!(chunk.starts_with("event: stamp\nid: 2\n"));
+        assert!(chunk.contains(r#""priceRows":40"#));
+        assert!(chunk.contains(r#""balanceRows":3"#));
+
+        drop(response);
+        server.abort();
+    }
+
+    /// Serve by a handler taking `delay`, then signal shutdown while a request is in flight.
+    /// # Returns
+    /// The pending response, and the result of the server with the time it took to end after the signal
+    async fn shutdown_during_request(
+        delay: Duration,
+        drain_timeout: Duration,
+    ) -> (
+        tokio::task::JoinHandle<hyper::Result<Response<Body>>>,
+        hyper::Result<()>,
+        Duration,
+    ) {
+        let handler = move |_| async move {
+            tokio::time::sleep(delay).await;
+            Ok(Response::new(Body::from("slow")))
+        };
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let shutdown = async move {
+            shutdown_rx.await.ok();
+        };
+        let (addr, server) = serve(
+            &([127, 0, 0, 1], 0).into(),
+            handler,
+            shutdown,
+            drain_timeout,
+        )
+        .unwrap();
+        let server = tokio::spawn(server);
+
+        let uri = format!("http://{}/slow", addr).parse().unwrap();
+        let response = tokio::spawn(hyper::Client::new().get(uri));
+
+        // Signal while the handler is in flight
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        shutdown_tx.send(()).unwrap();
+        let signaled = std::time::Instant::now();
+
+        let result = tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap();
+        (response, result, signaled.elapsed())
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown() {
+        let (response, result, _) =
+            shutdown_during_request(Duration::from_millis(500), Duration::from_secs(3)).await;
+
+        // In-flight request completes, then the server ends
+        assert!(result.is_ok());
+        let response = response.await.unwrap().unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&b"slow"[..], &body[..]);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drain_timeout() {
+        let (response, result, elapsed) =
+            shutdown_during_request(Duration::from_secs(3), Duration::from_millis(200)).await;
+
+        // The server ends without waiting the slow handler
+        assert!(result.is_ok());
+        assert!(elapsed < Duration::from_secs(2));
+        response.abort();
+    }
 }
@@ -0,0 +1,236 @@
+use chrono::NaiveDateTime;
+use common::allocation;
+
+/// Crypto markets are open every day
+const DAYS_PER_YEAR: f64 = 365.0;
+
+/// Minimum points of the series to compute volatility. 2 returns are needed for sample variance
+pub const VOLATILITY_MIN_POINTS: usize = 3;
+/// Minimum points of the series to compute drawdowns
+pub const DRAWDOWN_MIN_POINTS: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Drawdown {
+    /// Ratio of the decline from the peak, in [0, 1]
+    pub ratio: f64,
+    pub peak: NaiveDateTime,
+    pub trough: NaiveDateTime,
+}
+
+/// Return ratio between consecutive values.
+/// Returns from non-positive values are skipped since they are undefined.
+pub fn returns(series: &[(NaiveDateTime, f64)]) -> Vec<f64> {
+    series
+        .windows(2)
+        .filter(|w| w[0].1 > 0.0)
+        .map(|w| w[1].1 / w[0].1 - 1.0)
+        .collect()
+}
+
+/// Annualized volatility of daily `series`, i.e. sample standard deviation of daily returns multiplied by sqrt(365).
+/// # Returns
+/// `None` if fewer than 2 returns are available
+pub fn annualized_volatility(series: &[(NaiveDateTime, f64)]) -> Option<f64> {
+    let returns = returns(series);
+    if returns.len() < VOLATILITY_MIN_POINTS - 1 {
+        return None;
+    }
+
+    let n = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / n;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0);
+
+    Some(variance.sqrt() * DAYS_PER_YEAR.sqrt())
+}
+
+/// Largest decline from a preceding peak to a following trough.
+/// If the series never declines, the ratio is 0 and both ends are the first point.
+/// # Returns
+/// `None` if `series` has fewer than 2 points
+pub fn max_drawdown(series: &[(NaiveDateTime, f64)]) -> Option<Drawdown> {
+    if series.len() < DRAWDOWN_MIN_POINTS {
+        return None;
+    }
+
+    let (first_timestamp, first_value) = series[0];
+    let mut peak = (first_timestamp, first_value);
+    let mut max = Drawdown {
+        ratio: 0.0,
+        peak: first_timestamp,
+        trough: first_timestamp,
+    };
+
+    for &(timestamp, value) in series.iter().skip(1) {
+        if value > peak.1 {
+            peak = (timestamp, value);
+            continue;
+        }
+
+        let ratio = drawdown_ratio(peak.1, value);
+        if ratio > max.ratio {
+            max = Drawdown {
+                ratio,
+                peak: peak.0,
+                trough: timestamp,
+            };
+        }
+    }
+
+    Some(max)
+}
+
+/// Decline of the last value from the highest value so far
+/// # Returns
+/// `None` if `series` has fewer than 2 points
+pub fn current_drawdown(series: &[(NaiveDateTime, f64)]) -> Option<f64> {
+    if series.len() < DRAWDOWN_MIN_POINTS {
+        return None;
+    }
+
+    let peak = series
+        .iter()
+        .map(|&(_, value)| value)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let (_, last) = series[series.len() - 1];
+
+    Some(drawdown_ratio(peak, last))
+}
+
+/// Largest share of a single item in the portfolio.
+/// # Returns
+/// `None` if the total is not above `allocation::MIN_TOTAL_VALUE`
+pub fn concentration<'a, T>(values: &'a [(T, f64)]) -> Option<(&'a T, f64)> {
+    let shares = allocation::shares(values)?;
+
+    values
+        .iter()
+        .zip(shares)
+        .max_by(|(_, s1), (_, s2)| s1.partial_cmp(s2).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|((item, _), share)| (item, share))
+}
+
+fn drawdown_ratio(peak: f64, value: f64) -> f64 {
+    if peak > 0.0 {
+        (peak - value) / peak
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use chrono::NaiveDate;
+
+    fn day(d: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2021, 1, d).and_hms(0, 0, 0)
+    }
+
+    fn series(values: &[f64]) -> Vec<(NaiveDateTime, f64)> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| (day(i as u32 + 1), v))
+            .collect()
+    }
+
+    #[test]
+    fn test_returns() {
+        let returns = returns(&series(&[100.0, 110.0, 99.0]));
+
+        assert_eq!(2, returns.len());
+        assert_approx_eq!(0.1, returns[0]);
+        assert_approx_eq!(-0.1, returns[1]);
+    }
+
+    #[test]
+    fn test_returns_skip_non_positive() {
+        let returns = returns(&series(&[0.0, 100.0, 110.0]));
+
+        assert_eq!(1, returns.len());
+        assert_approx_eq!(0.1, returns[0]);
+    }
+
+    #[test]
+    fn test_annualized_volatility() {
+        // Returns are +10%, -10%: mean 0, sample variance 0.02
+        let volatility = annualized_volatility(&series(&[100.0, 110.0, 99.0])).unwrap();
+        assert_approx_eq!(0.02f64.sqrt() * 365f64.sqrt(), volatility);
+
+        let constant = annualized_volatility(&series(&[100.0, 100.0, 100.0])).unwrap();
+        assert_approx_eq!(0.0, constant);
+    }
+
+    #[test]
+    fn test_annualized_volatility_insufficient() {
+        assert_eq!(None, annualized_volatility(&series(&[])));
+        assert_eq!(None, annualized_volatility(&series(&[100.0, 110.0])));
+    }
+
+    #[test]
+    fn test_max_drawdown() {
+        // 120 -> 60 is larger than 100 -> 80
+        let drawdown = max_drawdown(&series(&[100.0, 80.0, 120.0, 60.0, 90.0])).unwrap();
+
+        assert_approx_eq!(0.5, drawdown.ratio);
+        assert_eq!(day(3), drawdown.peak);
+        assert_eq!(day(4), drawdown.trough);
+    }
+
+    #[test]
+    fn test_max_drawdown_peak_at_first_trough_at_last() {
+        let drawdown = max_drawdown(&series(&[100.0, 90.0, 70.0])).unwrap();
+
+        assert_approx_eq!(0.3, drawdown.ratio);
+        assert_eq!(day(1), drawdown.peak);
+        assert_eq!(day(3), drawdown.trough);
+    }
+
+    #[test]
+    fn test_max_drawdown_keeps_earlier_larger_drawdown() {
+        // Recovery to a new peak must not reset the recorded drawdown
+        let drawdown = max_drawdown(&series(&[100.0, 50.0, 200.0, 180.0])).unwrap();
+
+        assert_approx_eq!(0.5, drawdown.ratio);
+        assert_eq!(day(1), drawdown.peak);
+        assert_eq!(day(2), drawdown.trough);
+    }
+
+    #[test]
+    fn test_max_drawdown_monotonic_increase() {
+        let drawdown = max_drawdown(&series(&[100.0, 110.0, 120.0])).unwrap();
+
+        assert_approx_eq!(0.0, drawdown.ratio);
+        assert_eq!(day(1), drawdown.peak);
+        assert_eq!(day(1), drawdown.trough);
+    }
+
+    #[test]
+    fn test_max_drawdown_insufficient() {
+        assert_eq!(None, max_drawdown(&series(&[100.0])));
+    }
+
+    #[test]
+    fn test_current_drawdown() {
+        assert_approx_eq!(
+            0.25,
+            current_drawdown(&series(&[100.0, 200.0, 150.0])).unwrap()
+        );
+        // At the peak
+        assert_approx_eq!(0.0, current_drawdown(&series(&[100.0, 200.0])).unwrap());
+        assert_eq!(None, current_drawdown(&series(&[100.0])));
+    }
+
+    #[test]
+    fn test_concentration() {
+        let values = vec![("BTC", 60.0), ("ETH", 30.0), ("USDT", 10.0)];
+        let (symbol, share) = concentration(&values).unwrap();
+
+        assert_eq!("BTC", *symbol);
+        assert_approx_eq!(0.6, share);
+
+        assert_eq!(None, concentration::<&str>(&[]));
+        assert_eq!(None, concentration(&[("BTC", 0.0)]));
+    }
+}
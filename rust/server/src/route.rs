@@ -0,0 +1,138 @@
+/// Destination of a request path
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Route<'a> {
+    /// API name following `api/`
+    Api(&'a str),
+    /// `events`, the stream of Server-Sent Events
+    Events,
+    /// File path relative to the web content root
+    File(&'a str),
+    /// Bare prefix like `/asset`, redirected to `/asset/` so that relative links of pages stay under the prefix
+    PrefixRoot,
+}
+
+/// Resolve request `path` after stripping `prefix`, like `/asset`.
+/// # Returns
+/// `None` if `path` is out of `prefix`, or it refers a file while static files are not served
+pub fn resolve<'a>(path: &'a str, prefix: Option<&str>, serve_static: bool) -> Option<Route<'a>> {
+    let path = match prefix {
+        Some(prefix) => {
+            let rest = path.strip_prefix(prefix)?;
+            // `/asset` and `/asset/` are the root, but `/assets` is out of the prefix
+            if !rest.is_empty() && !rest.starts_with('/') {
+                return None;
+            }
+            if rest.is_empty() {
+                return if serve_static {
+                    Some(Route::PrefixRoot)
+                } else {
+                    None
+                };
+            }
+            rest
+        }
+        None => path,
+    };
+    let path = path.trim_start_matches('/');
+
+    match path.strip_prefix("api/") {
+        Some(api_path) => Some(Route::Api(api_path)),
+        None if path == "events" => Some(Route::Events),
+        None if !serve_static => None,
+        None if path.is_empty() => Some(Route::File("index.html")),
+        None => Some(Route::File(path)),
+    }
+}
+
+/// Return root-relative link to `path`, including `prefix`
+pub fn link(prefix: Option<&str>, path: &str) -> String {
+    format!(
+        "{}/{}",
+        prefix.unwrap_or_default(),
+        path.trim_start_matches('/')
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_without_prefix() {
+        assert_eq!(
+            Some(Route::Api("balance_history")),
+            resolve("/api/balance_history", None, true)
+        );
+        assert_eq!(
+            Some(Route::File("chart.js")),
+            resolve("/chart.js", None, true)
+        );
+        assert_eq!(Some(Route::File("index.html")), resolve("/", None, true));
+    }
+
+    #[test]
+    fn test_resolve_with_prefix() {
+        let prefix = Some("/asset");
+
+        assert_eq!(
+            Some(Route::Api("balance_history")),
+            resolve("/asset/api/balance_history", prefix, true)
+        );
+        assert_eq!(
+            Some(Route::File("chart.js")),
+            resolve("/asset/chart.js", prefix, true)
+        );
+
+        // Requests without the prefix are rejected
+        assert_eq!(None, resolve("/api/balance_history", prefix, true));
+        assert_eq!(None, resolve("/chart.js", prefix, true));
+        assert_eq!(None, resolve("/assets/chart.js", prefix, true));
+    }
+
+    #[test]
+    fn test_resolve_bare_prefix() {
+        let prefix = Some("/asset");
+
+        assert_eq!(Some(Route::PrefixRoot), resolve("/asset", prefix, true));
+        assert_eq!(
+            Some(Route::File("index.html")),
+            resolve("/asset/", prefix, true)
+        );
+        assert_eq!(None, resolve("/asset", prefix, false));
+        assert_eq!(None, resolve("/asset/", prefix, false));
+    }
+
+    #[test]
+    fn test_resolve_static_disabled() {
+        assert_eq!(
+            Some(Route::Api("orderbook_depth")),
+            resolve("/api/orderbook_depth", None, false)
+        );
+        assert_eq!(None, resolve("/index.html", None, false));
+        assert_eq!(None, resolve("/", None, false));
+    }
+
+    #[test]
+    fn test_resolve_events() {
+        assert_eq!(Some(Route::Events), resolve("/events", None, true));
+        assert_eq!(Some(Route::Events), resolve("/events", None, false));
+        assert_eq!(
+            Some(Route::Events),
+            resolve("/asset/events", Some("/asset"), true)
+        );
+        assert_eq!(
+            Some(Route::File("events.js")),
+            resolve("/events.js", None, true)
+        );
+    }
+
+    #[test]
+    fn test_link() {
+        assert_eq!("/index.html", link(None, "index.html"));
+        assert_eq!("/asset/index.html", link(Some("/asset"), "index.html"));
+        assert_eq!(
+            "/asset/api/risk_metrics",
+            link(Some("/asset"), "/api/risk_metrics")
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use crate::http::HttpError;
+use anyhow::Result;
+use common::allocation;
+use database::logic::UNTAGGED;
+use database::model::CurrencyId;
+use server_client::response::TagGroup;
+use std::collections::{BTreeMap, HashMap};
+
+/// Parse `group_by` query. Only `tag` is supported
+/// # Returns
+/// `Ok(true)` if values are grouped by tag
+pub fn is_grouped_by_tag(group_by: Option<&str>) -> Result<bool> {
+    match group_by {
+        None => Ok(false),
+        Some("tag") => Ok(true),
+        Some(other) => {
+            Err(HttpError::bad_request(format!("Unsupported group_by: {}", other)).into())
+        }
+    }
+}
+
+/// Sum fiat values of currencies per tag of `primary_tags`, sorted by tag.
+/// `values` are (currency, symbol, value) of currencies whose rate is known.
+/// Currencies without tags are grouped into `untagged`
+pub fn group_by_tag(
+    values: &[(CurrencyId, String, f64)],
+    primary_tags: &HashMap<CurrencyId, String>,
+) -> Vec<TagGroup> {
+    let mut groups = BTreeMap::<&str, (Vec<String>, f64)>::new();
+    for (currency_id, symbol, value) in values.iter() {
+        let tag = primary_tags
+            .get(currency_id)
+            .map(String::as_str)
+            .unwrap_or(UNTAGGED);
+        let group = groups.entry(tag).or_default();
+        group.0.push(symbol.clone());
+        group.1 += value;
+    }
+
+    let totals = groups
+        .iter()
+        .map(|(tag, (_, value))| (*tag, *value))
+        .collect::<Vec<_>>();
+    let shares = allocation::shares(&totals);
+
+    groups
+        .into_iter()
+        .enumerate()
+        .map(|(i, (tag, (mut symbols, value)))| {
+            symbols.sort();
+            TagGroup {
+                tag: tag.to_string(),
+                symbols,
+                value,
+                share: shares.as_ref().map(|shares| shares[i]),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn value(id: i32, symbol: &str, value: f64) -> (CurrencyId, String, f64) {
+        (CurrencyId::new(id), symbol.into(), value)
+    }
+
+    #[test]
+    fn test_is_grouped_by_tag() {
+        assert!(!is_grouped_by_tag(None).unwrap());
+        assert!(is_grouped_by_tag(Some("tag")).unwrap());
+        assert!(is_grouped_by_tag(Some("market")).is_err());
+    }
+
+    #[test]
+    fn test_group_by_tag() {
+        let values = vec![
+            value(0, "USDT", 500.0),
+            value(1, "BTC", 300.0),
+            value(2, "ETH", 100.0),
+            value(3, "LTC", 50.0),
+            value(4, "DOGE", 50.0),
+        ];
+        let primary_tags = vec![
+            (CurrencyId::new(0), "stablecoin".to_string()),
+            (CurrencyId::new(1), "major".to_string()),
+            (CurrencyId::new(2), "major".to_string()),
+        ]
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+        let groups = group_by_tag(&values, &primary_tags);
+
+        let tags = groups.iter().map(|g| g.tag.as_str()).collect::<Vec<_>>();
+        assert_eq!(vec!["major", "stablecoin", "untagged"], tags);
+        assert_eq!(vec!["BTC", "ETH"], groups[0].symbols);
+        assert_approx_eq!(400.0, groups[0].value);
+        assert_approx_eq!(0.4, groups[0].share.unwrap());
+        assert_approx_eq!(500.0, groups[1].value);
+        assert_eq!(vec!["DOGE", "LTC"], groups[2].symbols);
+        assert_approx_eq!(100.0, groups[2].value);
+    }
+
+    #[test]
+    fn test_group_by_tag_empty_portfolio() {
+        let groups = group_by_tag(&[value(0, "BTC", 0.0)], &HashMap::new());
+
+        assert_eq!(1, groups.len());
+        assert_eq!("untagged", groups[0].tag);
+        assert_eq!(None, groups[0].share);
+    }
+}
@@ -0,0 +1,153 @@
+use chrono::{Duration, NaiveDateTime};
+use qstring::QString;
+use std::str::FromStr;
+
+/// Parse timestamp of `key` in `query`, formatted as `%Y-%m-%dT%H:%M:%S%.fZ`.
+/// Invalid timestamps are regarded as unspecified
+pub fn parse_query_timestamp(query: &QString, key: &str) -> Option<NaiveDateTime> {
+    query
+        .get(key)
+        .and_then(|s| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.fZ").ok())
+}
+
+/// Parse step like `1_day`, `4_hour` or `30_minute`
+pub fn parse_query_step(step_str: &str) -> Option<Duration> {
+    let mut split = step_str.split('_');
+    let num = split.next().and_then(|s| i64::from_str(s).ok())?;
+    let unit = split.next()?;
+
+    match unit {
+        "day" => Some(Duration::days(num)),
+        "hour" => Some(Duration::hours(num)),
+        "minute" => Some(Duration::minutes(num)),
+        _ => None,
+    }
+}
+
+/// Parse `limit` in `query`. Invalid or zero limits are regarded as unspecified
+pub fn parse_query_limit(query: &QString) -> Option<usize> {
+    query
+        .get("limit")
+        .and_then(|s| usize::from_str(s).ok())
+        .filter(|&limit| limit > 0)
+}
+
+/// Keep the last `limit` of `items`, or all of them if `limit` is `None`
+pub fn keep_latest<T>(mut items: Vec<T>, limit: Option<usize>) -> Vec<T> {
+    if let Some(limit) = limit {
+        let skipped = items.len().saturating_sub(limit);
+        items.drain(..skipped);
+    }
+    items
+}
+
+/// Keep the first of `items`, then each item at least `step` after the last kept one.
+/// `items` must be sorted by `timestamp`
+pub fn downsample<T>(
+    items: impl IntoIterator<Item = T>,
+    step: Duration,
+    timestamp: impl Fn(&T) -> NaiveDateTime,
+) -> Vec<T> {
+    let mut last_kept: Option<NaiveDateTime> = None;
+    items
+        .into_iter()
+        .filter(|item| {
+            let current = timestamp(item);
+            match last_kept {
+                Some(last) if current - last < step => false,
+                _ => {
+                    last_kept = Some(current);
+                    true
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn minutes(minutes: &[i64]) -> Vec<NaiveDateTime> {
+        let origin = NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 0, 0);
+        minutes
+            .iter()
+            .map(|&m| origin + Duration::minutes(m))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_query_timestamp() {
+        let query = QString::from("since=2021-01-01T00:00:00.000Z&until=yesterday");
+
+        assert_eq!(
+            Some(NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 0, 0)),
+            parse_query_timestamp(&query, "since")
+        );
+        assert_eq!(None, parse_query_timestamp(&query, "until"));
+        assert_eq!(None, parse_query_timestamp(&query, "step"));
+    }
+
+    #[test]
+    fn test_parse_query_step() {
+        assert_eq!(Some(Duration::days(1)), parse_query_step("1_day"));
+        assert_eq!(Some(Duration::hours(4)), parse_query_step("4_hour"));
+        assert_eq!(Some(Duration::minutes(30)), parse_query_step("30_minute"));
+        assert_eq!(None, parse_query_step("1_week"));
+        assert_eq!(None, parse_query_step("day"));
+    }
+
+    #[test]
+    fn test_parse_query_limit() {
+        assert_eq!(Some(3), parse_query_limit(&QString::from("limit=3")));
+        assert_eq!(None, parse_query_limit(&QString::from("limit=0")));
+        assert_eq!(None, parse_query_limit(&QString::from("limit=-1")));
+        assert_eq!(None, parse_query_limit(&QString::from("step=1_day")));
+    }
+
+    #[test]
+    fn test_keep_latest() {
+        let timestamps = minutes(&[0, 5, 10]);
+
+        assert_eq!(minutes(&[5, 10]), keep_latest(timestamps.clone(), Some(2)));
+        assert_eq!(timestamps, keep_latest(timestamps.clone(), Some(5)));
+        assert_eq!(timestamps, keep_latest(timestamps.clone(), None));
+    }
+
+    #[test]
+    fn test_downsample_step_larger_than_spacing() {
+        // Stamps every 5 minutes, sampled every 12 minutes
+        let timestamps = minutes(&[0, 5, 10, 15, 20, 25, 30, 35]);
+
+        let sampled = downsample(timestamps, Duration::minutes(12), |t| *t);
+
+        assert_eq!(minutes(&[0, 15, 30]), sampled);
+    }
+
+    #[test]
+    fn test_downsample_irregular_spacing() {
+        // Elapsed time is measured from the last kept stamp, not from the first one
+        let timestamps = minutes(&[0, 7, 11, 19, 23, 40]);
+
+        let sampled = downsample(timestamps, Duration::minutes(10), |t| *t);
+
+        assert_eq!(minutes(&[0, 11, 23, 40]), sampled);
+    }
+
+    #[test]
+    fn test_downsample_step_smaller_than_spacing() {
+        let timestamps = minutes(&[0, 5, 10]);
+
+        let sampled = downsample(timestamps.clone(), Duration::minutes(1), |t| *t);
+
+        assert_eq!(timestamps, sampled);
+    }
+
+    #[test]
+    fn test_downsample_empty() {
+        let sampled = downsample(Vec::<NaiveDateTime>::new(), Duration::days(1), |t| *t);
+
+        assert!(sampled.is_empty());
+    }
+}
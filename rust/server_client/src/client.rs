@@ -0,0 +1,294 @@
+use crate::response::*;
+use anyhow::{ensure, Result};
+use reqwest::blocking::{Client, RequestBuilder};
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+
+/// Margin over the wait of `api/events`, so that the request doesn't time out before the server responds
+const EVENTS_TIMEOUT_MARGIN: Duration = Duration::from_secs(10);
+
+/// Blocking client of the server's JSON API
+#[derive(Debug, Clone)]
+pub struct ServerClient {
+    client: Client,
+    base_url: String,
+    token: Option<String>,
+}
+
+impl ServerClient {
+    /// `base_url` is like `http://127.0.0.1:7878`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            client: Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            token: None,
+        }
+    }
+
+    /// Send `token` as bearer token on every request
+    pub fn with_token(self, token: impl Into<String>) -> Self {
+        Self {
+            token: Some(token.into()),
+            ..self
+        }
+    }
+
+    pub fn status(&self) -> Result<StatusResponse> {
+        self.get("status", &[])
+    }
+
+    /// Fails if any DB is unreachable, since the server responds 503 then
+    pub fn health(&self) -> Result<HealthResponse> {
+        self.get("health", &[])
+    }
+
+    /// `since`/`until` are formatted as `%Y-%m-%dT%H:%M:%S%.fZ`, and `step` as `1_day`, `4_hour`, ...
+    ///
+    /// `symbols` is like `BTC,ETH,USDT`. Unknown symbols are ignored unless `strict`
+    ///
+    /// Values are also summed per tag if `group_by_tag`, which requires `fiat`
+    #[allow(clippy::too_many_arguments)]
+    pub fn balance_history(
+        &self,
+        fiat: Option<&str>,
+        since: Option<&str>,
+        until: Option<&str>,
+        step: Option<&str>,
+        symbols: Option<&str>,
+        strict: bool,
+        exclude_zero: bool,
+        sim: bool,
+        group_by_tag: bool,
+    ) -> Result<BalanceHistoryResponse> {
+        let mut query = vec![];
+        query.extend(fiat.map(|s| ("fiat", s)));
+        query.extend(since.map(|s| ("since", s)));
+        query.extend(until.map(|s| ("until", s)));
+        query.extend(step.map(|s| ("step", s)));
+        query.extend(symbols.map(|s| ("symbols", s)));
+        if strict {
+            query.push(("strict", "1"));
+        }
+        if exclude_zero {
+            query.push(("exclude_zero", "1"));
+        }
+        if sim {
+            query.push(("sim", "1"));
+        }
+        if group_by_tag {
+            query.push(("group_by", "tag"));
+        }
+
+        self.get("balance_history", &query)
+    }
+
+    /// Values are converted to `fiat` if specified, which fails with 400 if unknown. Zero balances are omitted unless `include_zero`
+    pub fn balance_current(
+        &self,
+        fiat: Option<&str>,
+        include_zero: bool,
+        sim: bool,
+    ) -> Result<BalanceCurrentResponse> {
+        let mut query = vec![];
+        query.extend(fiat.map(|s| ("fiat", s)));
+        if include_zero {
+            query.push(("include_zero", "1"));
+        }
+        if sim {
+            query.push(("sim", "1"));
+        }
+
+        self.get("balance_current", &query)
+    }
+
+    pub fn inconsistent_cycles(
+        &self,
+        max_len: usize,
+        threshold: f64,
+    ) -> Result<InconsistentCyclesResponse> {
+        let max_len = max_len.to_string();
+        let threshold = threshold.to_string();
+        let query = [("max_len", max_len.as_str()), ("threshold", &threshold)];
+
+        self.get("inconsistent_cycles", &query)
+    }
+
+    /// `market` is like `BTC-USDT`.
+    /// The latest stamp having orderbooks of the market is used if `stamp_id` is `None`
+    pub fn orderbook_depth(
+        &self,
+        market: &str,
+        stamp_id: Option<i32>,
+    ) -> Result<OrderbookDepthResponse> {
+        let stamp = stamp_id
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| "latest".into());
+        let query = [("market", market), ("stamp", &stamp)];
+
+        self.get("orderbook_depth", &query)
+    }
+
+    /// `fiat` is the currency symbol valuing the portfolio, like `USDT`.
+    /// The latest values are also summed per tag if `group_by_tag`
+    pub fn risk_metrics(
+        &self,
+        fiat: &str,
+        window_days: Option<i64>,
+        sim: bool,
+        group_by_tag: bool,
+    ) -> Result<RiskMetricsResponse> {
+        let window_days = window_days.map(|days| days.to_string());
+        let mut query = vec![("fiat", fiat)];
+        query.extend(window_days.as_deref().map(|s| ("window_days", s)));
+        if sim {
+            query.push(("sim", "1"));
+        }
+        if group_by_tag {
+            query.push(("group_by", "tag"));
+        }
+
+        self.get("risk_metrics", &query)
+    }
+
+    /// `market` is like `BTC-USDT`. `since`/`until` are formatted as `%Y-%m-%dT%H:%M:%S%.fZ`.
+    /// Pass `next_cursor` of the previous response as `cursor` to fetch the next page
+    pub fn market_journal(
+        &self,
+        market: &str,
+        since: Option<&str>,
+        until: Option<&str>,
+        cursor: Option<i32>,
+        limit: Option<usize>,
+    ) -> Result<MarketJournalResponse> {
+        let cursor = cursor.map(|id| id.to_string());
+        let limit = limit.map(|limit| limit.to_string());
+        let mut query = vec![("market", market)];
+        query.extend(since.map(|s| ("since", s)));
+        query.extend(until.map(|s| ("until", s)));
+        query.extend(cursor.as_deref().map(|s| ("cursor", s)));
+        query.extend(limit.as_deref().map(|s| ("limit", s)));
+
+        self.get("market_journal", &query)
+    }
+
+    /// `market` is like `BTC-USDT`. `since`/`until` are formatted as `%Y-%m-%dT%H:%M:%S%.fZ`.
+    ///
+    /// Candles are derived from prices if `candle_interval` like `1_hour` is specified.
+    /// Otherwise prices are downsampled if `step` like `1_hour` is specified
+    pub fn price_history(
+        &self,
+        market: &str,
+        since: Option<&str>,
+        until: Option<&str>,
+        candle_interval: Option<&str>,
+        step: Option<&str>,
+    ) -> Result<PriceHistoryResponse> {
+        let mut query = vec![("market", market)];
+        query.extend(since.map(|s| ("since", s)));
+        query.extend(until.map(|s| ("until", s)));
+        query.extend(candle_interval.map(|s| ("candle_interval", s)));
+        query.extend(step.map(|s| ("step", s)));
+
+        self.get("price_history", &query)
+    }
+
+    /// `market` is like `BTC-USDT`. Recommendations of all markets are listed if `market` is `None`.
+    /// `since`/`until` are formatted as `%Y-%m-%dT%H:%M:%S%.fZ`
+    pub fn recommendation_history(
+        &self,
+        market: Option<&str>,
+        since: Option<&str>,
+        until: Option<&str>,
+    ) -> Result<RecommendationHistoryResponse> {
+        let mut query = vec![];
+        query.extend(market.map(|s| ("market", s)));
+        query.extend(since.map(|s| ("since", s)));
+        query.extend(until.map(|s| ("until", s)));
+
+        self.get("recommendation_history", &query)
+    }
+
+    /// Orders of `market` like `BTC-USDT` with their state transitions.
+    /// `since`/`until` are formatted as `%Y-%m-%dT%H:%M:%S%.fZ`
+    pub fn order_history(
+        &self,
+        market: &str,
+        since: Option<&str>,
+        until: Option<&str>,
+    ) -> Result<OrderHistoryResponse> {
+        let mut query = vec![("market", market)];
+        query.extend(since.map(|s| ("since", s)));
+        query.extend(until.map(|s| ("until", s)));
+
+        self.get("order_history", &query)
+    }
+
+    /// Approve an order waiting for approval. Requires the token
+    pub fn approve_order(&self, pending_approval_id: i32) -> Result<ApproveOrderResponse> {
+        let id = pending_approval_id.to_string();
+
+        self.post("approve_order", &[("id", &id)])
+    }
+
+    pub fn currency_tags(&self) -> Result<CurrencyTagsResponse> {
+        self.get("currency_tags", &[])
+    }
+
+    /// Tag the currency of `symbol`. Requires the token
+    pub fn add_currency_tag(&self, symbol: &str, tag: &str) -> Result<CurrencyTagsResponse> {
+        self.post("currency_tags", &[("symbol", symbol), ("tag", tag)])
+    }
+
+    /// Remove `tag` from the currency of `symbol`. Requires the token
+    pub fn remove_currency_tag(&self, symbol: &str, tag: &str) -> Result<CurrencyTagsResponse> {
+        self.post(
+            "currency_tags",
+            &[("symbol", symbol), ("tag", tag), ("remove", "1")],
+        )
+    }
+
+    /// Wait until the latest stamp differs from `since_stamp`, at most `timeout_sec` (55 by default).
+    /// Pass `stamp_id` of the previous response as `since_stamp` to keep watching
+    pub fn events(
+        &self,
+        since_stamp: Option<i32>,
+        timeout_sec: Option<u64>,
+    ) -> Result<EventsResponse> {
+        let since_stamp = since_stamp.map(|id| id.to_string());
+        let timeout = Duration::from_secs(timeout_sec.unwrap_or(55)) + EVENTS_TIMEOUT_MARGIN;
+        let timeout_sec = timeout_sec.map(|sec| sec.to_string());
+        let mut query = vec![];
+        query.extend(since_stamp.as_deref().map(|s| ("since_stamp", s)));
+        query.extend(timeout_sec.as_deref().map(|s| ("timeout_sec", s)));
+
+        let url = format!("{}/api/events", self.base_url);
+        let request = self.client.get(&url).query(&query).timeout(timeout);
+        self.send(request, &url)
+    }
+
+    fn get<T: DeserializeOwned>(&self, api: &str, query: &[(&str, &str)]) -> Result<T> {
+        let url = format!("{}/api/{}", self.base_url, api);
+        self.send(self.client.get(&url).query(query), &url)
+    }
+
+    fn post<T: DeserializeOwned>(&self, api: &str, query: &[(&str, &str)]) -> Result<T> {
+        let url = format!("{}/api/{}", self.base_url, api);
+        self.send(self.client.post(&url).query(query), &url)
+    }
+
+    fn send<T: DeserializeOwned>(&self, mut request: RequestBuilder, url: &str) -> Result<T> {
+        if let Some(token) = self.token.as_ref() {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request.send()?;
+        ensure!(
+            response.status().is_success(),
+            "{} responded {}",
+            url,
+            response.status()
+        );
+
+        response.json().map_err(Into::into)
+    }
+}
@@ -0,0 +1,962 @@
+use serde::{Deserialize, Serialize};
+
+/// Response of `api/balance_history`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceHistoryResponse {
+    pub success: bool,
+    pub history: Vec<BalanceHistoryEntry>,
+    #[serde(default)]
+    pub filter: BalanceHistoryFilter,
+}
+
+/// Filter applied to `api/balance_history`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceHistoryFilter {
+    /// Symbols of returned currencies. `None` if all currencies are returned
+    pub symbols: Option<Vec<String>>,
+    /// Unknown symbols in the query, which are ignored
+    pub ignored_symbols: Vec<String>,
+    /// Currencies whose available and pending are both zero are dropped at each stamp
+    pub exclude_zero: bool,
+}
+
+/// Balances at a stamp
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceHistoryEntry {
+    /// Formatted as `%Y-%m-%dT%H:%M`
+    pub stamp: String,
+    pub currencies: Vec<CurrencyBalance>,
+    /// Fiat values per tag. Empty unless `group_by=tag`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<TagGroup>,
+    /// Sum of fiat values of all currencies whose rate is known, including the ones out of `symbols` filter.
+    /// `None` if fiat is not specified
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_fiat: Option<f64>,
+    /// Sorted symbols of currencies having balance which could not be converted to fiat
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub missing_rate_symbols: Vec<String>,
+}
+
+/// Fiat value of currencies grouped by tag.
+/// A currency having several tags belongs to its alphabetically first tag only
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagGroup {
+    /// `untagged` for currencies without tags
+    pub tag: String,
+    /// Currencies whose rate to fiat is known
+    pub symbols: Vec<String>,
+    pub value: f64,
+    /// Share of the total value of all groups. `None` if the total is zero
+    pub share: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CurrencyBalance {
+    pub name: String,
+    pub symbol: String,
+    pub available: f64,
+    pub pending: f64,
+    /// `available` formatted with the currency's display precision
+    #[serde(default)]
+    pub available_display: String,
+    /// `pending` formatted with the currency's display precision
+    #[serde(default)]
+    pub pending_display: String,
+    /// Exchange rate to fiat currency. Omitted if fiat is not specified or rate is unknown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<f64>,
+}
+
+/// Response of `api/balance_current`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceCurrentResponse {
+    pub success: bool,
+    /// Formatted as `%Y-%m-%dT%H:%M`. `None` if no balance exists
+    pub stamp: Option<String>,
+    /// Symbol of fiat currency which values are converted to. `None` if fiat is not specified
+    pub fiat: Option<String>,
+    /// Sorted by symbol
+    pub currencies: Vec<CurrentBalance>,
+    /// Sum of `value` of currencies whose rate is known. `None` if fiat is not specified
+    pub total_value: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CurrentBalance {
+    pub name: String,
+    pub symbol: String,
+    pub available: f64,
+    pub pending: f64,
+    /// Sum of `available` and `pending`
+    pub total: f64,
+    /// Exchange rate to fiat currency. Omitted if fiat is not specified or rate is unknown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<f64>,
+    /// `total` in fiat currency. Omitted if fiat is not specified or rate is unknown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<f64>,
+}
+
+/// Response of `api/inconsistent_cycles`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InconsistentCyclesResponse {
+    pub success: bool,
+    pub stamp: String,
+    pub cycles: Vec<InconsistentCycle>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InconsistentCycle {
+    /// Currency symbols along the cycle
+    pub currencies: Vec<String>,
+    pub rate_product: f64,
+}
+
+/// Response of `api/orderbook_depth`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderbookDepthResponse {
+    pub success: bool,
+    pub stamp: String,
+    #[serde(rename = "levels_available")]
+    pub levels_available: bool,
+    /// Sorted by descending price
+    pub bids: Vec<DepthLevel>,
+    /// Sorted by ascending price
+    pub asks: Vec<DepthLevel>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mid_price: Option<f64>,
+    /// The best ask minus the best bid. Omitted unless both sides have levels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spread: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DepthLevel {
+    pub price: f64,
+    pub volume: f64,
+    pub cumulative_volume: f64,
+}
+
+/// Response of `api/status`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusResponse {
+    pub success: bool,
+    /// Formatted as `%Y-%m-%dT%H:%M`. `None` if no stamp exists
+    pub latest_stamp: Option<String>,
+    /// Source of `latest_stamp` like `scraper` or `import`
+    #[serde(default)]
+    pub latest_stamp_source: Option<String>,
+    /// Formatted as `%Y-%m-%dT%H:%M`. Differs from `latest_stamp` while newer stamps are imported or replayed
+    #[serde(default)]
+    pub latest_scraper_stamp: Option<String>,
+    /// Median interval of recent stamps. `None` if too few stamps exist
+    pub stamp_cadence_sec: Option<i64>,
+    /// Build of the server
+    #[serde(default)]
+    pub build: BuildInfo,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildInfo {
+    pub version: String,
+    /// Short commit hash. `unknown` if built without git
+    pub git_hash: String,
+    /// UTC, formatted as `%Y-%m-%dT%H:%M:%SZ`
+    pub build_timestamp: String,
+}
+
+/// Response of `api/risk_metrics`.
+/// Metrics which can't be computed are null, and their reasons are listed in `unavailable`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RiskMetricsResponse {
+    pub success: bool,
+    pub fiat: String,
+    pub window_days: i64,
+    /// Number of daily total-equity points used
+    pub points: usize,
+    pub annualized_volatility: Option<f64>,
+    pub max_drawdown: Option<MaxDrawdown>,
+    /// Decline of the latest equity from its peak, in [0, 1]
+    pub current_drawdown: Option<f64>,
+    pub concentration: Option<Concentration>,
+    pub unavailable: Vec<UnavailableMetric>,
+    /// Values at the latest stamp per tag. Empty unless `group_by=tag`
+    #[serde(default)]
+    pub groups: Vec<TagGroup>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MaxDrawdown {
+    /// Ratio of the decline from the peak, in [0, 1]
+    pub ratio: f64,
+    /// Formatted as `%Y-%m-%dT%H:%M`
+    pub start: String,
+    /// Formatted as `%Y-%m-%dT%H:%M`
+    pub end: String,
+}
+
+/// Largest single-currency share of the portfolio at the latest stamp
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Concentration {
+    pub symbol: String,
+    pub share: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnavailableMetric {
+    pub metric: String,
+    pub reason: String,
+}
+
+/// Response of `api/market_journal`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketJournalResponse {
+    pub success: bool,
+    /// Like `BTC-USDT`
+    pub market: String,
+    /// Events in chronological order. Events at the same stamp are ordered as price, recommendation, order
+    pub events: Vec<JournalEntry>,
+    /// Pass as `cursor` to fetch the next page. `None` if this is the last page
+    pub next_cursor: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    /// Formatted as `%Y-%m-%dT%H:%M`
+    pub stamp: String,
+    pub stamp_id: i32,
+    /// Source of the stamp like `scraper` or `import`
+    #[serde(default)]
+    pub stamp_source: String,
+    pub kind: JournalEventKind,
+    pub payload: JournalPayload,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JournalEventKind {
+    Price,
+    Recommendation,
+    Order,
+}
+
+/// Payload of a journal event. Its variant matches `JournalEntry::kind`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum JournalPayload {
+    Price(JournalPrice),
+    Recommendation(JournalRecommendation),
+    Order(JournalOrder),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct JournalPrice {
+    pub price: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct JournalRecommendation {
+    /// `Buy`, `Sell`, `Pending` or `Neutral`
+    pub recommendation_type: String,
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct JournalOrder {
+    pub transaction_id: String,
+    /// `opened` at the created stamp, `updated` at the last modified stamp
+    pub event: String,
+    pub order_type: String,
+    pub side: String,
+    /// The latest state, since states at intermediate stamps are not stored
+    pub state: String,
+    pub price: f64,
+    pub base_quantity: f64,
+    pub quote_quantity: f64,
+}
+
+/// Response of `api/approve_order`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApproveOrderResponse {
+    pub success: bool,
+    pub pending_approval_id: i32,
+    /// `Approved`
+    pub state: String,
+    /// Market price when approved. The order is skipped if the price moves too much before execution
+    pub approved_price: f64,
+    /// Formatted as `%Y-%m-%dT%H:%M`
+    pub expiry: String,
+}
+
+/// Response of `api/price_history`.
+/// Either `prices` or `candles` is filled, depending on whether candles are requested
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceHistoryResponse {
+    pub success: bool,
+    /// Reason of failure like unknown market. `None` if succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Like `BTC-USDT`
+    pub market: String,
+    /// `None` if raw prices are requested
+    pub candle_interval_sec: Option<i64>,
+    /// In chronological order
+    pub prices: Vec<PricePoint>,
+    /// In chronological order. Intervals without prices are omitted
+    pub candles: Vec<CandleEntry>,
+}
+
+/// Response of `api/recommendation_history`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecommendationHistoryResponse {
+    pub success: bool,
+    /// Reason of failure like unknown market. `None` if succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Like `BTC-USDT`. `None` if recommendations of all markets are listed
+    pub market: Option<String>,
+    /// In chronological order
+    pub recommendations: Vec<RecommendationEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecommendationEntry {
+    /// Formatted as `%Y-%m-%dT%H:%M`
+    pub stamp: String,
+    /// Like `BTC-USDT`
+    pub market: String,
+    /// Name of the rule. `None` for the aggregated outcome of rules
+    pub rule: Option<String>,
+    /// `Buy`, `Sell`, `Pending` or `Neutral`
+    pub recommendation_type: String,
+    /// Score of the rule, or weighted mean of scores for the aggregated outcome. `None` if undefined
+    pub score: Option<f64>,
+    pub reason: String,
+    /// Price of the market at the stamp. `None` if not scraped
+    pub price: Option<f64>,
+}
+
+/// Response of `api/order_history`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderHistoryResponse {
+    pub success: bool,
+    /// Like `BTC-USDT`
+    pub market: String,
+    /// Ordered by the last modified stamp
+    pub orders: Vec<OrderHistoryEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderHistoryEntry {
+    pub transaction_id: String,
+    pub order_type: String,
+    pub side: String,
+    /// The latest state
+    pub state: String,
+    pub price: f64,
+    pub base_quantity: f64,
+    pub quote_quantity: f64,
+    /// Formatted as `%Y-%m-%dT%H:%M`. `None` if the stamp is not found, like orders of simulation
+    pub created: Option<String>,
+    /// In chronological order. Empty if the state has never changed
+    pub transitions: Vec<OrderTransition>,
+}
+
+/// Change of an order's state
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderTransition {
+    /// Formatted as `%Y-%m-%dT%H:%M`
+    pub stamp: String,
+    /// Like `Opened`
+    pub from: String,
+    /// Like `Filled`
+    pub to: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PricePoint {
+    /// Formatted as `%Y-%m-%dT%H:%M`
+    pub stamp: String,
+    pub price: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CandleEntry {
+    /// Beginning of the interval, formatted as `%Y-%m-%dT%H:%M`
+    pub open_time: String,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    /// Number of prices in the interval
+    pub count: usize,
+}
+
+/// Response of `api/currency_tags`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CurrencyTagsResponse {
+    pub success: bool,
+    /// Currencies having tags, sorted by symbol
+    pub currencies: Vec<CurrencyTags>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CurrencyTags {
+    pub symbol: String,
+    /// Sorted alphabetically. The first one is used for grouping
+    pub tags: Vec<String>,
+}
+
+/// Response of `api/events`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventsResponse {
+    pub success: bool,
+    /// `false` if the latest stamp didn't change until the timeout
+    pub changed: bool,
+    /// The latest stamp. `since_stamp` of the request if not changed
+    pub stamp_id: Option<i32>,
+    /// Tables having data at `stamp_id`, like `price`. Empty if not changed
+    pub tables: Vec<String>,
+}
+
+/// Data of `stamp` events pushed by `/events` when a newer stamp is written
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StampEvent {
+    pub stamp_id: i32,
+    /// Formatted as `%Y-%m-%dT%H:%M`
+    pub timestamp: String,
+    /// Number of prices written at the stamp
+    pub price_rows: i64,
+    /// Number of balances written at the stamp
+    pub balance_rows: i64,
+}
+
+/// Response of `api/health`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthResponse {
+    pub success: bool,
+    /// `ok` if every DB is reachable, otherwise `degraded`
+    pub status: String,
+    /// Main DB followed by simulation DB if configured
+    pub databases: Vec<DatabaseHealth>,
+    /// Formatted as `%Y-%m-%dT%H:%M`. `None` if no stamp exists or main DB is unreachable
+    pub latest_stamp: Option<String>,
+    /// Seconds since `latest_stamp`
+    pub latest_stamp_age_sec: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatabaseHealth {
+    /// `main` or `sim`
+    pub name: String,
+    pub ok: bool,
+    /// Why the check failed
+    pub error: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::DeserializeOwned;
+    use std::fmt::Debug;
+
+    fn assert_round_trip<T>(value: T)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + Debug,
+    {
+        let json = serde_json::to_string(&value).unwrap();
+        let restored: T = serde_json::from_str(&json).unwrap();
+        assert_eq!(value, restored);
+    }
+
+    #[test]
+    fn test_balance_history_round_trip() {
+        let response = BalanceHistoryResponse {
+            success: true,
+            history: vec![BalanceHistoryEntry {
+                stamp: "2021-01-01T00:00".into(),
+                currencies: vec![
+                    CurrencyBalance {
+                        name: "Bitcoin".into(),
+                        symbol: "BTC".into(),
+                        available: 1.5,
+                        pending: 0.5,
+                        available_display: "1.50000000".into(),
+                        pending_display: "0.50000000".into(),
+                        rate: Some(30000.0),
+                    },
+                    CurrencyBalance {
+                        name: "Dogecoin".into(),
+                        symbol: "DOGE".into(),
+                        available: 100.0,
+                        pending: 0.0,
+                        available_display: "100.00".into(),
+                        pending_display: "0.00".into(),
+                        rate: None,
+                    },
+                ],
+                groups: vec![TagGroup {
+                    tag: "major".into(),
+                    symbols: vec!["BTC".into()],
+                    value: 60000.0,
+                    share: Some(1.0),
+                }],
+                total_fiat: Some(60000.0),
+                missing_rate_symbols: vec!["DOGE".into()],
+            }],
+            filter: BalanceHistoryFilter {
+                symbols: Some(vec!["BTC".into(), "DOGE".into()]),
+                ignored_symbols: vec!["XYZ".into()],
+                exclude_zero: true,
+            },
+        };
+
+        assert_round_trip(response);
+    }
+
+    #[test]
+    fn test_balance_history_without_filter() {
+        // Responses of older servers have no filter
+        let json = r#"{"success":true,"history":[]}"#;
+
+        let response: BalanceHistoryResponse = serde_json::from_str(json).unwrap();
+
+        assert_eq!(BalanceHistoryFilter::default(), response.filter);
+    }
+
+    #[test]
+    fn test_balance_history_omits_unknown_rate() {
+        let balance = CurrencyBalance {
+            name: "Dogecoin".into(),
+            symbol: "DOGE".into(),
+            available: 100.0,
+            pending: 0.0,
+            available_display: "100.00".into(),
+            pending_display: "0.00".into(),
+            rate: None,
+        };
+
+        let json = serde_json::to_value(&balance).unwrap();
+        assert!(json.get("rate").is_none());
+    }
+
+    #[test]
+    fn test_balance_current_round_trip() {
+        let response = BalanceCurrentResponse {
+            success: true,
+            stamp: Some("2021-01-01T00:00".into()),
+            fiat: Some("USDT".into()),
+            currencies: vec![
+                CurrentBalance {
+                    name: "Bitcoin".into(),
+                    symbol: "BTC".into(),
+                    available: 1.5,
+                    pending: 0.5,
+                    total: 2.0,
+                    rate: Some(30000.0),
+                    value: Some(60000.0),
+                },
+                CurrentBalance {
+                    name: "Dogecoin".into(),
+                    symbol: "DOGE".into(),
+                    available: 100.0,
+                    pending: 0.0,
+                    total: 100.0,
+                    rate: None,
+                    value: None,
+                },
+            ],
+            total_value: Some(60000.0),
+        };
+
+        assert_round_trip(response);
+    }
+
+    #[test]
+    fn test_inconsistent_cycles_round_trip() {
+        let response = InconsistentCyclesResponse {
+            success: true,
+            stamp: "2021-01-01T00:00".into(),
+            cycles: vec![InconsistentCycle {
+                currencies: vec!["BTC".into(), "ETH".into(), "USDT".into()],
+                rate_product: 1000.0,
+            }],
+        };
+
+        assert_round_trip(response);
+    }
+
+    #[test]
+    fn test_orderbook_depth_round_trip() {
+        let response = OrderbookDepthResponse {
+            success: true,
+            stamp: "2021-01-01T00:00".into(),
+            levels_available: true,
+            bids: vec![DepthLevel {
+                price: 10.0,
+                volume: 2.0,
+                cumulative_volume: 2.0,
+            }],
+            asks: vec![DepthLevel {
+                price: 11.0,
+                volume: 1.0,
+                cumulative_volume: 1.0,
+            }],
+            mid_price: Some(10.5),
+            spread: Some(1.0),
+        };
+
+        let json = serde_json::to_value(&response).unwrap();
+        assert!(json.get("levels_available").is_some());
+        assert!(json["bids"][0].get("cumulativeVolume").is_some());
+
+        assert_round_trip(response);
+    }
+
+    #[test]
+    fn test_risk_metrics_round_trip() {
+        let response = RiskMetricsResponse {
+            success: true,
+            fiat: "USDT".into(),
+            window_days: 90,
+            points: 2,
+            annualized_volatility: None,
+            max_drawdown: Some(MaxDrawdown {
+                ratio: 0.25,
+                start: "2021-01-01T00:00".into(),
+                end: "2021-01-02T00:00".into(),
+            }),
+            current_drawdown: Some(0.25),
+            concentration: Some(Concentration {
+                symbol: "BTC".into(),
+                share: 0.6,
+            }),
+            unavailable: vec![UnavailableMetric {
+                metric: "annualizedVolatility".into(),
+                reason: "At least 3 points are required, but 2 found".into(),
+            }],
+            groups: vec![],
+        };
+
+        // Unavailable metrics are explicitly null
+        let json = serde_json::to_value(&response).unwrap();
+        assert!(json["annualizedVolatility"].is_null());
+
+        assert_round_trip(response);
+    }
+
+    #[test]
+    fn test_status_round_trip() {
+        let response = StatusResponse {
+            success: true,
+            latest_stamp: Some("2021-01-01T00:10".into()),
+            latest_stamp_source: Some("import".into()),
+            latest_scraper_stamp: Some("2021-01-01T00:00".into()),
+            stamp_cadence_sec: Some(300),
+            build: BuildInfo {
+                version: "0.1.0".into(),
+                git_hash: "0123456789ab".into(),
+                build_timestamp: "2021-01-01T00:00:00Z".into(),
+            },
+        };
+
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(300, json["stampCadenceSec"]);
+        assert_eq!("import", json["latestStampSource"]);
+        assert_eq!("0123456789ab", json["build"]["gitHash"]);
+
+        assert_round_trip(response);
+    }
+
+    #[test]
+    fn test_market_journal_round_trip() {
+        let response = MarketJournalResponse {
+            success: true,
+            market: "BTC-USDT".into(),
+            events: vec![
+                JournalEntry {
+                    stamp: "2021-01-01T00:00".into(),
+                    stamp_id: 1,
+                    stamp_source: "scraper".into(),
+                    kind: JournalEventKind::Price,
+                    payload: JournalPayload::Price(JournalPrice { price: 30000.0 }),
+                },
+                JournalEntry {
+                    stamp: "2021-01-01T00:00".into(),
+                    stamp_id: 1,
+                    stamp_source: "scraper".into(),
+                    kind: JournalEventKind::Recommendation,
+                    payload: JournalPayload::Recommendation(JournalRecommendation {
+                        recommendation_type: "Buy".into(),
+                        reasons: vec!["RSI crossed 30 upward".into()],
+                    }),
+                },
+                JournalEntry {
+                    stamp: "2021-01-01T00:00".into(),
+                    stamp_id: 1,
+                    stamp_source: "scraper".into(),
+                    kind: JournalEventKind::Order,
+                    payload: JournalPayload::Order(JournalOrder {
+                        transaction_id: "abc".into(),
+                        event: "opened".into(),
+                        order_type: "Limit".into(),
+                        side: "Buy".into(),
+                        state: "Filled".into(),
+                        price: 29900.0,
+                        base_quantity: 0.01,
+                        quote_quantity: 299.0,
+                    }),
+                },
+            ],
+            next_cursor: Some(2),
+        };
+
+        assert_round_trip(response);
+    }
+
+    #[test]
+    fn test_approve_order_round_trip() {
+        let response = ApproveOrderResponse {
+            success: true,
+            pending_approval_id: 3,
+            state: "Approved".into(),
+            approved_price: 30000.0,
+            expiry: "2021-01-01T00:07".into(),
+        };
+
+        assert_round_trip(response);
+    }
+
+    #[test]
+    fn test_price_history_round_trip() {
+        let response = PriceHistoryResponse {
+            success: true,
+            message: None,
+            market: "BTC-USDT".into(),
+            candle_interval_sec: Some(3600),
+            prices: vec![],
+            candles: vec![CandleEntry {
+                open_time: "2021-01-01T00:00".into(),
+                open: 30000.0,
+                high: 30500.0,
+                low: 29800.0,
+                close: 30100.0,
+                count: 12,
+            }],
+        };
+        assert_round_trip(response);
+
+        let response = PriceHistoryResponse {
+            success: true,
+            message: None,
+            market: "BTC-USDT".into(),
+            candle_interval_sec: None,
+            prices: vec![PricePoint {
+                stamp: "2021-01-01T00:05".into(),
+                price: 30000.0,
+            }],
+            candles: vec![],
+        };
+        assert_round_trip(response);
+    }
+
+    #[test]
+    fn test_recommendation_history_round_trip() {
+        let response = RecommendationHistoryResponse {
+            success: true,
+            message: None,
+            market: Some("BTC-USDT".into()),
+            recommendations: vec![
+                RecommendationEntry {
+                    stamp: "2021-01-01T00:05".into(),
+                    market: "BTC-USDT".into(),
+                    rule: Some("rsiCross".into()),
+                    recommendation_type: "Buy".into(),
+                    score: Some(1.0),
+                    reason: "RSI crossed above 30".into(),
+                    price: Some(30000.0),
+                },
+                RecommendationEntry {
+                    stamp: "2021-01-01T00:05".into(),
+                    market: "BTC-USDT".into(),
+                    rule: None,
+                    recommendation_type: "Pending".into(),
+                    score: None,
+                    reason: "Weighted mean NaN of 1 rules, buy trigger 0.5, sell trigger 0.5"
+                        .into(),
+                    price: None,
+                },
+            ],
+        };
+        assert_round_trip(response);
+
+        let response = RecommendationHistoryResponse {
+            success: false,
+            message: Some("Unknown market: XYZ-USDT".into()),
+            market: Some("XYZ-USDT".into()),
+            recommendations: vec![],
+        };
+        assert_round_trip(response);
+    }
+
+    #[test]
+    fn test_order_history_round_trip() {
+        let response = OrderHistoryResponse {
+            success: true,
+            market: "BTC-USDT".into(),
+            orders: vec![OrderHistoryEntry {
+                transaction_id: "order-1".into(),
+                order_type: "Limit".into(),
+                side: "Buy".into(),
+                state: "Filled".into(),
+                price: 30000.0,
+                base_quantity: 0.01,
+                quote_quantity: 300.0,
+                created: Some("2021-01-01T00:00".into()),
+                transitions: vec![OrderTransition {
+                    stamp: "2021-01-01T00:05".into(),
+                    from: "Opened".into(),
+                    to: "Filled".into(),
+                }],
+            }],
+        };
+        assert_round_trip(response);
+    }
+
+    #[test]
+    fn test_price_history_failure() {
+        let response = PriceHistoryResponse {
+            success: false,
+            message: Some("Unknown currency: XYZ".into()),
+            market: "XYZ-USDT".into(),
+            candle_interval_sec: None,
+            prices: vec![],
+            candles: vec![],
+        };
+
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(Some(&serde_json::json!(false)), json.get("success"));
+        assert_eq!(
+            Some(&serde_json::json!("Unknown currency: XYZ")),
+            json.get("message")
+        );
+        assert_round_trip(response);
+    }
+
+    #[test]
+    fn test_events_round_trip() {
+        let response = EventsResponse {
+            success: true,
+            changed: true,
+            stamp_id: Some(12),
+            tables: vec!["balance".into(), "price".into()],
+        };
+        assert_round_trip(response);
+
+        let response = EventsResponse {
+            success: true,
+            changed: false,
+            stamp_id: None,
+            tables: vec![],
+        };
+        assert_round_trip(response);
+    }
+
+    #[test]
+    fn test_stamp_event_round_trip() {
+        let event = StampEvent {
+            stamp_id: 12,
+            timestamp: "2021-01-01T00:00".into(),
+            price_rows: 40,
+            balance_rows: 3,
+        };
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(12, json["stampId"]);
+        assert_eq!(40, json["priceRows"]);
+        assert_eq!(3, json["balanceRows"]);
+
+        assert_round_trip(event);
+    }
+
+    #[test]
+    fn test_health_round_trip() {
+        let response = HealthResponse {
+            success: true,
+            status: "degraded".into(),
+            databases: vec![
+                DatabaseHealth {
+                    name: "main".into(),
+                    ok: true,
+                    error: None,
+                },
+                DatabaseHealth {
+                    name: "sim".into(),
+                    ok: false,
+                    error: Some("No response in 3 sec".into()),
+                },
+            ],
+            latest_stamp: Some("2021-01-01T00:00".into()),
+            latest_stamp_age_sec: Some(120),
+        };
+
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!("degraded", json["status"]);
+        assert_eq!(120, json["latestStampAgeSec"]);
+        assert_eq!("sim", json["databases"][1]["name"]);
+        assert_eq!(false, json["databases"][1]["ok"]);
+
+        assert_round_trip(response);
+    }
+
+    #[test]
+    fn test_currency_tags_round_trip() {
+        let response = CurrencyTagsResponse {
+            success: true,
+            currencies: vec![CurrencyTags {
+                symbol: "USDT".into(),
+                tags: vec!["fiat-backed".into(), "stablecoin".into()],
+            }],
+        };
+
+        assert_round_trip(response);
+    }
+}
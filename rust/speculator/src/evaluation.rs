@@ -0,0 +1,238 @@
+use crate::rule::RecommendationType;
+use database::model::Amount;
+use itertools::Itertools;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Rule name of aggregated recommendations in evaluation
+pub const AGGREGATED_RULE: &str = "aggregated";
+
+/// A recommendation paired with the market's forward return
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForwardSample {
+    pub rule: String,
+    pub parameter_hash: String,
+    pub recommendation_type: RecommendationType,
+    pub forward_return: f64,
+}
+
+/// Relative price change from `entry` to `exit`.
+/// # Returns
+/// `None` if `entry` is not positive
+pub fn forward_return(entry: Amount, exit: Amount) -> Option<f64> {
+    if entry > 0.0 {
+        Some(exit / entry - 1.0)
+    } else {
+        None
+    }
+}
+
+/// Accuracy of recommendations of a rule with the same parameters
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleAccuracy {
+    pub rule: String,
+    pub parameter_hash: String,
+    pub buy_count: usize,
+    pub sell_count: usize,
+    /// Ratio of buys followed by rise and sells followed by fall. `None` if no buy or sell
+    pub hit_rate: Option<f64>,
+    pub mean_return_after_buy: Option<f64>,
+    pub mean_return_after_sell: Option<f64>,
+}
+
+/// Aggregate samples per (rule, parameter hash). Pending and neutral recommendations are ignored.
+/// # Returns
+/// Accuracies in descending order of hit rate. Rules without buy or sell come last
+pub fn aggregate_accuracy(samples: &[ForwardSample]) -> Vec<RuleAccuracy> {
+    let mut groups = BTreeMap::new();
+    for sample in samples.iter() {
+        groups
+            .entry((sample.rule.as_str(), sample.parameter_hash.as_str()))
+            .or_insert_with(Vec::new)
+            .push(sample);
+    }
+
+    groups
+        .into_iter()
+        .map(|((rule, parameter_hash), samples)| {
+            let returns_of = |recommendation_type| {
+                samples
+                    .iter()
+                    .filter(|s| s.recommendation_type == recommendation_type)
+                    .map(|s| s.forward_return)
+                    .collect_vec()
+            };
+            let buy_returns = returns_of(RecommendationType::Buy);
+            let sell_returns = returns_of(RecommendationType::Sell);
+
+            let hits = buy_returns.iter().filter(|&&r| r > 0.0).count()
+                + sell_returns.iter().filter(|&&r| r < 0.0).count();
+            let count = buy_returns.len() + sell_returns.len();
+            let hit_rate = if count > 0 {
+                Some(hits as f64 / count as f64)
+            } else {
+                None
+            };
+
+            RuleAccuracy {
+                rule: rule.to_string(),
+                parameter_hash: parameter_hash.to_string(),
+                buy_count: buy_returns.len(),
+                sell_count: sell_returns.len(),
+                hit_rate,
+                mean_return_after_buy: mean(&buy_returns),
+                mean_return_after_sell: mean(&sell_returns),
+            }
+        })
+        .sorted_by(|a1, a2| {
+            let key = |a: &RuleAccuracy| a.hit_rate.unwrap_or(-1.0);
+            key(a2)
+                .partial_cmp(&key(a1))
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| (&a1.rule, &a1.parameter_hash).cmp(&(&a2.rule, &a2.parameter_hash)))
+        })
+        .collect()
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<f64>() / values.len() as f64)
+    }
+}
+
+/// Format accuracies as a table. Rates and returns are in percent
+pub fn format_accuracy_table(accuracies: &[RuleAccuracy]) -> String {
+    let percent = |value: Option<f64>| match value {
+        Some(value) => format!("{:.2}", value * 100.0),
+        None => "-".to_string(),
+    };
+    let rule_width = accuracies
+        .iter()
+        .map(|a| a.rule.len())
+        .chain(std::iter::once("rule".len()))
+        .max()
+        .unwrap_or_default();
+
+    let mut lines = vec![format!(
+        "{:<rw$}  {:<16}  {:>5}  {:>5}  {:>8}  {:>10}  {:>10}",
+        "rule",
+        "parameter",
+        "buy",
+        "sell",
+        "hit[%]",
+        "buy_ret[%]",
+        "sell_ret[%]",
+        rw = rule_width
+    )];
+    for a in accuracies.iter() {
+        lines.push(format!(
+            "{:<rw$}  {:<16}  {:>5}  {:>5}  {:>8}  {:>10}  {:>10}",
+            a.rule,
+            a.parameter_hash,
+            a.buy_count,
+            a.sell_count,
+            percent(a.hit_rate),
+            percent(a.mean_return_after_buy),
+            percent(a.mean_return_after_sell),
+            rw = rule_width
+        ));
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn sample(
+        rule: &str,
+        recommendation_type: RecommendationType,
+        forward_return: f64,
+    ) -> ForwardSample {
+        ForwardSample {
+            rule: rule.into(),
+            parameter_hash: "0000000000000000".into(),
+            recommendation_type,
+            forward_return,
+        }
+    }
+
+    #[test]
+    fn test_forward_return() {
+        assert_approx_eq!(0.1, forward_return(100.0, 110.0).unwrap());
+        assert_approx_eq!(-0.05, forward_return(100.0, 95.0).unwrap());
+        assert_eq!(None, forward_return(0.0, 95.0));
+    }
+
+    #[test]
+    fn test_aggregate_accuracy() {
+        let samples = vec![
+            sample("rsiCross", RecommendationType::Buy, 0.02),
+            sample("rsiCross", RecommendationType::Buy, -0.01),
+            sample("rsiCross", RecommendationType::Sell, -0.03),
+            sample("rsiCross", RecommendationType::Pending, 0.5),
+            sample("rsiCross", RecommendationType::Neutral, 0.5),
+            sample(AGGREGATED_RULE, RecommendationType::Sell, -0.01),
+        ];
+
+        let accuracies = aggregate_accuracy(&samples);
+
+        assert_eq!(2, accuracies.len());
+        // Sorted by hit rate
+        assert_eq!(AGGREGATED_RULE, accuracies[0].rule);
+        assert_eq!(Some(1.0), accuracies[0].hit_rate);
+        assert_eq!(None, accuracies[0].mean_return_after_buy);
+
+        let rsi = &accuracies[1];
+        assert_eq!("rsiCross", rsi.rule);
+        assert_eq!(2, rsi.buy_count);
+        assert_eq!(1, rsi.sell_count);
+        assert_approx_eq!(2.0 / 3.0, rsi.hit_rate.unwrap());
+        assert_approx_eq!(0.005, rsi.mean_return_after_buy.unwrap());
+        assert_approx_eq!(-0.03, rsi.mean_return_after_sell.unwrap());
+    }
+
+    #[test]
+    fn test_aggregate_accuracy_separates_parameters() {
+        let mut other = sample("rsiCross", RecommendationType::Buy, 0.01);
+        other.parameter_hash = "ffffffffffffffff".into();
+        let samples = vec![
+            sample("rsiCross", RecommendationType::Buy, -0.01),
+            other,
+            sample("fixed", RecommendationType::Pending, 0.01),
+        ];
+
+        let accuracies = aggregate_accuracy(&samples);
+
+        assert_eq!(
+            vec![
+                ("rsiCross", "ffffffffffffffff"),
+                ("rsiCross", "0000000000000000"),
+                ("fixed", "0000000000000000"),
+            ],
+            accuracies
+                .iter()
+                .map(|a| (a.rule.as_str(), a.parameter_hash.as_str()))
+                .collect_vec()
+        );
+        // Only pending recommendations
+        assert_eq!(None, accuracies[2].hit_rate);
+    }
+
+    #[test]
+    fn test_format_accuracy_table() {
+        let accuracies = aggregate_accuracy(&[sample("rsiCross", RecommendationType::Buy, 0.02)]);
+
+        let expected = [
+            "rule      parameter           buy   sell    hit[%]  buy_ret[%]  sell_ret[%]",
+            "rsiCross  0000000000000000      1      0    100.00        2.00           -",
+        ]
+        .join("\n");
+        assert_eq!(expected, format_accuracy_table(&accuracies));
+    }
+}
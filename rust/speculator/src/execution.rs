@@ -0,0 +1,59 @@
+use database::custom_sql_type::{OrderSide, OrderState, OrderType};
+use database::model::Amount;
+
+/// State of a simulated order just after it is placed.
+/// Market orders fill immediately, while limit orders wait on the orderbook
+pub fn placed_state(order_type: OrderType) -> OrderState {
+    match order_type {
+        OrderType::Market | OrderType::StopMarket => OrderState::Filled,
+        OrderType::Limit | OrderType::StopLimit => OrderState::Opened,
+    }
+}
+
+/// Whether a limit order at `limit_price` is filled when the market moves to `price`.
+/// Buy orders fill when the price falls to the limit or below, and sell orders when it rises to the limit or above
+pub fn is_limit_filled(side: OrderSide, limit_price: Amount, price: Amount) -> bool {
+    match side {
+        OrderSide::Buy => price <= limit_price,
+        OrderSide::Sell => price >= limit_price,
+    }
+}
+
+/// State of an open simulated limit order on the next run at market `price`.
+/// Orders not filled by then are cancelled, since the recommendation placing them is outdated
+pub fn settle_limit_order(side: OrderSide, limit_price: Amount, price: Amount) -> OrderState {
+    if is_limit_filled(side, limit_price, price) {
+        OrderState::Filled
+    } else {
+        OrderState::Cancelled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placed_state() {
+        assert_eq!(OrderState::Filled, placed_state(OrderType::Market));
+        assert_eq!(OrderState::Opened, placed_state(OrderType::Limit));
+    }
+
+    #[test]
+    fn test_settle_limit_buy() {
+        use OrderState::*;
+
+        assert_eq!(Filled, settle_limit_order(OrderSide::Buy, 100.0, 99.0));
+        assert_eq!(Filled, settle_limit_order(OrderSide::Buy, 100.0, 100.0));
+        assert_eq!(Cancelled, settle_limit_order(OrderSide::Buy, 100.0, 101.0));
+    }
+
+    #[test]
+    fn test_settle_limit_sell() {
+        use OrderState::*;
+
+        assert_eq!(Filled, settle_limit_order(OrderSide::Sell, 100.0, 101.0));
+        assert_eq!(Filled, settle_limit_order(OrderSide::Sell, 100.0, 100.0));
+        assert_eq!(Cancelled, settle_limit_order(OrderSide::Sell, 100.0, 99.0));
+    }
+}
@@ -0,0 +1,37 @@
+use database::custom_sql_type::OrderType;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+/// Fee ratios charged on filled orders.
+///
+/// Limit orders rest on the orderbook and pay maker fee,
+/// while market orders take liquidity and pay taker fee.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeSchedule {
+    #[validate(range(min = 0, max = 1.0))]
+    maker_fee_ratio: f64,
+    #[validate(range(min = 0, max = 1.0))]
+    taker_fee_ratio: f64,
+}
+
+impl FeeSchedule {
+    pub fn new(maker_fee_ratio: f64, taker_fee_ratio: f64) -> Self {
+        Self {
+            maker_fee_ratio,
+            taker_fee_ratio,
+        }
+    }
+
+    /// Charge the same fee regardless of order type
+    pub fn flat(fee_ratio: f64) -> Self {
+        Self::new(fee_ratio, fee_ratio)
+    }
+
+    pub fn fee_ratio(&self, order_type: OrderType) -> f64 {
+        match order_type {
+            OrderType::Limit | OrderType::StopLimit => self.maker_fee_ratio,
+            OrderType::Market | OrderType::StopMarket => self.taker_fee_ratio,
+        }
+    }
+}
@@ -1,17 +1,29 @@
 use anyhow::{ensure, Result};
-use chrono::{Duration, DurationRound, NaiveDateTime};
+use chrono::{Duration, NaiveDateTime};
 use itertools::Itertools;
-use ta::{DataItem, Next};
+use serde::{Deserialize, Serialize};
+use ta::{Close, DataItem, Next, Reset};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct PriceStamp {
     stamp: NaiveDateTime,
     price: f64,
+    volume: f64,
 }
 
 impl PriceStamp {
+    /// Price without volume, regarded as 0
     pub fn new(stamp: NaiveDateTime, price: f64) -> Self {
-        Self { stamp, price }
+        Self::with_volume(stamp, price, 0.0)
+    }
+
+    /// Candlesticks containing negative or NaN `volume` fail to be built
+    pub fn with_volume(stamp: NaiveDateTime, price: f64, volume: f64) -> Self {
+        Self {
+            stamp,
+            price,
+            volume,
+        }
     }
 
     pub fn stamp(&self) -> NaiveDateTime {
@@ -21,30 +33,115 @@ impl PriceStamp {
     pub fn price(&self) -> f64 {
         self.price
     }
+
+    pub fn volume(&self) -> f64 {
+        self.volume
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct DataItemBuffer {
     interval: Duration,
+    /// Intervals begin at multiples of `interval` from this
+    origin: NaiveDateTime,
+    /// Whether the first candlestick is dropped if its interval began before the first price stamp
+    discard_first_incomplete: bool,
+    /// Whether the current interval is the first one to be dropped
+    first_incomplete: bool,
     stamps: Vec<PriceStamp>,
 }
 
 impl DataItemBuffer {
+    /// Buffer whose intervals are aligned to multiples of `interval` since UNIX epoch.
     /// # Panics
     /// Panics under non-positive `interval`.
     pub fn new(interval: Duration) -> Self {
+        Self::with_origin(interval, NaiveDateTime::from_timestamp(0, 0))
+    }
+
+    /// Buffer whose intervals are aligned to multiples of `interval` from `origin`,
+    /// like 90 minutes intervals beginning at 00:00 of a day.
+    /// # Panics
+    /// Panics under non-positive `interval`.
+    pub fn with_origin(interval: Duration, origin: NaiveDateTime) -> Self {
         assert!(interval > Duration::zero());
         Self {
             interval,
+            origin,
+            discard_first_incomplete: false,
+            first_incomplete: false,
             stamps: vec![],
         }
     }
 
+    /// If `discard` is true, the first candlestick is dropped
+    /// unless the first price stamp is exactly at the beginning of its interval,
+    /// since such candlestick lacks prices before the buffer started
+    pub fn with_discard_first_incomplete(self, discard: bool) -> Self {
+        Self {
+            discard_first_incomplete: discard,
+            ..self
+        }
+    }
+
     pub fn interval(&self) -> Duration {
         self.interval
     }
 
-    fn next(&mut self, price_stamp: PriceStamp) -> Result<Option<DataItem>> {
+    pub fn origin(&self) -> NaiveDateTime {
+        self.origin
+    }
+
+    pub fn discard_first_incomplete(&self) -> bool {
+        self.discard_first_incomplete
+    }
+
+    /// Candlestick of the stamps accumulated so far in the open interval, without consuming them.
+    /// # Returns
+    /// `None` if no stamp is accumulated, the interval is the incomplete first one to be discarded,
+    /// or the candlestick can't be built like under negative volume
+    pub fn current_partial(&self) -> Option<DataItem> {
+        if self.stamps.is_empty() || self.first_incomplete {
+            return None;
+        }
+        build_dataitem(&self.stamps).ok()
+    }
+
+    /// Beginning of the open interval, or `None` if no stamp is accumulated
+    pub fn current_open_time(&self) -> Option<NaiveDateTime> {
+        self.stamps.first().map(|s| self.truncate(s.stamp()))
+    }
+
+    /// Number of price stamps accumulated in the open interval
+    pub fn current_len(&self) -> usize {
+        self.stamps.len()
+    }
+
+    /// Number of intervals without any price stamp, between the current interval and the one of `price_stamp`
+    fn missing_intervals(&self, price_stamp: &PriceStamp) -> usize {
+        match self.stamps.last() {
+            Some(last) => {
+                let trunc1 = self.truncate(last.stamp());
+                let trunc2 = self.truncate(price_stamp.stamp());
+                let intervals =
+                    (trunc2 - trunc1).num_milliseconds() / self.interval.num_milliseconds();
+                (intervals - 1).max(0) as usize
+            }
+            None => 0,
+        }
+    }
+
+    /// Beginning of the interval containing `stamp`
+    fn truncate(&self, stamp: NaiveDateTime) -> NaiveDateTime {
+        let millis = (stamp - self.origin).num_milliseconds();
+        let interval_millis = self.interval.num_milliseconds();
+        self.origin + Duration::milliseconds(millis - millis.rem_euclid(interval_millis))
+    }
+
+    /// Feed `price_stamp`, which must be newer than the previously fed one.
+    /// # Returns
+    /// Candlestick of the previous interval if `price_stamp` begins a new interval
+    pub fn next(&mut self, price_stamp: PriceStamp) -> Result<Option<DataItem>> {
         match self.stamps.last() {
             Some(last) => {
                 ensure!(
@@ -52,31 +149,27 @@ impl DataItemBuffer {
                     "Timestamp constraint failure"
                 );
 
-                let trunc1 = to_utc(last.stamp()).duration_trunc(self.interval)?;
-                let trunc2 = to_utc(price_stamp.stamp()).duration_trunc(self.interval)?;
+                let trunc1 = self.truncate(last.stamp());
+                let trunc2 = self.truncate(price_stamp.stamp());
                 if trunc1 == trunc2 {
                     self.stamps.push(price_stamp);
                     Ok(None)
                 } else {
                     // Use all stamps of previous interval
-                    let prices = self.stamps.drain(..).map(|s| s.price).collect_vec();
-                    // `prices` is not empty, so no panic occurs below unwrap().
-                    let open = prices[0];
-                    let close = prices.last().copied().unwrap();
-                    let (low, high) = prices.into_iter().minmax().into_option().unwrap();
-                    let item = DataItem::builder()
-                        .open(open)
-                        .close(close)
-                        .high(high)
-                        .low(low)
-                        .volume(0.0)
-                        .build()?;
+                    let item = build_dataitem(&self.stamps)?;
+                    self.stamps.clear();
                     // Next interval
                     self.stamps.push(price_stamp);
-                    Ok(Some(item))
+                    if std::mem::replace(&mut self.first_incomplete, false) {
+                        Ok(None)
+                    } else {
+                        Ok(Some(item))
+                    }
                 }
             }
             None => {
+                self.first_incomplete = self.discard_first_incomplete
+                    && self.truncate(price_stamp.stamp()) != price_stamp.stamp();
                 self.stamps.push(price_stamp);
                 Ok(None)
             }
@@ -84,11 +177,60 @@ impl DataItemBuffer {
     }
 }
 
+/// Candlestick of non-empty `stamps` in time order
+fn build_dataitem(stamps: &[PriceStamp]) -> Result<DataItem> {
+    let volume = stamps.iter().map(|s| s.volume).sum::<f64>();
+    let prices = stamps.iter().map(|s| s.price).collect_vec();
+    // `prices` is not empty, so no panic occurs below unwrap().
+    let open = prices[0];
+    let close = prices.last().copied().unwrap();
+    let (low, high) = prices.into_iter().minmax().into_option().unwrap();
+    DataItem::builder()
+        .open(open)
+        .close(close)
+        .high(high)
+        .low(low)
+        .volume(volume)
+        .build()
+}
+
+/// How candlesticks treat intervals without any price stamp, such as scraper downtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum GapPolicy {
+    /// The candlestick after a gap directly follows the one before it
+    SkipGaps,
+    /// Flat candlesticks carrying the last close are emitted for missing intervals
+    FillForward,
+    /// Indicator is reset when more intervals than this are missing.
+    /// The candlestick before the gap is discarded then
+    ResetOnGap(usize),
+}
+
+impl Default for GapPolicy {
+    fn default() -> Self {
+        GapPolicy::SkipGaps
+    }
+}
+
+/// Candlesticks determined by a price stamp
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candlesticks<U> {
+    /// Candlesticks with their indicator outputs in time order.
+    /// Empty while an interval continues
+    pub items: Vec<(DataItem, U)>,
+    /// Whether the indicator was reset by a gap
+    pub reset: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct IndicatorBuffer<T> {
     indicator: T,
     buffer: DataItemBuffer,
-    dataitem: DataItem,
+    gap_policy: GapPolicy,
+    /// The last determined data item, whose close is carried over to filled gaps.
+    /// `None` until the first interval finishes
+    dataitem: Option<DataItem>,
 }
 
 impl<T> IndicatorBuffer<T> {
@@ -98,9 +240,32 @@ impl<T> IndicatorBuffer<T> {
         Self {
             indicator,
             buffer: DataItemBuffer::new(interval),
-            // dataitem field is only used after after value is set on next() method.
-            // uninit value is not used, thus this unsafe code is safe.
-            dataitem: unsafe { std::mem::MaybeUninit::zeroed().assume_init() },
+            gap_policy: GapPolicy::default(),
+            dataitem: None,
+        }
+    }
+
+    pub fn with_gap_policy(self, gap_policy: GapPolicy) -> Self {
+        Self { gap_policy, ..self }
+    }
+
+    /// Align intervals to multiples of the interval from `origin` instead of UNIX epoch.
+    /// See `DataItemBuffer::with_origin`
+    pub fn with_origin(self, origin: NaiveDateTime) -> Self {
+        Self {
+            buffer: DataItemBuffer {
+                origin,
+                ..self.buffer
+            },
+            ..self
+        }
+    }
+
+    /// See `DataItemBuffer::with_discard_first_incomplete`
+    pub fn with_discard_first_incomplete(self, discard: bool) -> Self {
+        Self {
+            buffer: self.buffer.with_discard_first_incomplete(discard),
+            ..self
         }
     }
 
@@ -112,36 +277,142 @@ impl<T> IndicatorBuffer<T> {
         self.buffer.interval()
     }
 
-    pub fn next<'a>(&'a mut self, price_stamp: PriceStamp) -> Result<Option<(DataItem, T::Output)>>
+    pub fn gap_policy(&self) -> GapPolicy {
+        self.gap_policy
+    }
+
+    pub fn origin(&self) -> NaiveDateTime {
+        self.buffer.origin()
+    }
+
+    pub fn discard_first_incomplete(&self) -> bool {
+        self.buffer.discard_first_incomplete()
+    }
+
+    /// The last determined data item. `None` after the indicator is reset by a gap
+    pub fn last_dataitem(&self) -> Option<&DataItem> {
+        self.dataitem.as_ref()
+    }
+
+    /// Candlestick partially built from price stamps of the open interval. See `DataItemBuffer::current_partial`
+    pub fn partial_dataitem(&self) -> Option<DataItem> {
+        self.buffer.current_partial()
+    }
+
+    /// Partial candlestick of the open interval with the indicator output as if the interval finished now.
+    /// The output is computed by a clone of the indicator, so the state of this buffer is untouched
+    pub fn provisional_output<U>(&self) -> Option<(DataItem, U)>
+    where
+        T: Clone + for<'a> Next<&'a DataItem, Output = U>,
+    {
+        let dataitem = self.partial_dataitem()?;
+        let output = self.indicator.clone().next(&dataitem);
+        Some((dataitem, output))
+    }
+
+    /// The last candlestick determined by `price_stamp`
+    pub fn next<U>(&mut self, price_stamp: PriceStamp) -> Result<Option<(DataItem, U)>>
+    where
+        T: for<'a> Next<&'a DataItem, Output = U> + Reset,
+    {
+        let candlesticks = self.next_candlesticks(price_stamp)?;
+        Ok(candlesticks.items.into_iter().last())
+    }
+
+    /// All candlesticks determined by `price_stamp`, including ones filling a gap
+    pub fn next_candlesticks<U>(&mut self, price_stamp: PriceStamp) -> Result<Candlesticks<U>>
     where
-        T: Next<&'a DataItem>,
+        T: for<'a> Next<&'a DataItem, Output = U> + Reset,
     {
-        match self.buffer.next(price_stamp) {
-            Ok(Some(dataitem)) => {
-                self.dataitem = dataitem;
-                let output = self.indicator.next(&self.dataitem);
-                Ok(Some((self.dataitem.clone(), output)))
+        let missing = self.buffer.missing_intervals(&price_stamp);
+        let dataitem = match self.buffer.next(price_stamp)? {
+            Some(dataitem) => dataitem,
+            None => {
+                return Ok(Candlesticks {
+                    items: vec![],
+                    reset: false,
+                })
+            }
+        };
+
+        match self.gap_policy {
+            GapPolicy::ResetOnGap(max_missing) if missing > max_missing => {
+                self.indicator.reset();
+                self.dataitem = None;
+                Ok(Candlesticks {
+                    items: vec![],
+                    reset: true,
+                })
             }
-            Ok(None) => Ok(None),
-            Err(e) => Err(e),
+            GapPolicy::FillForward => {
+                let close = dataitem.close();
+                let mut items = vec![self.next_dataitem(dataitem)];
+                for _ in 0..missing {
+                    let flat = DataItem::builder()
+                        .open(close)
+                        .close(close)
+                        .high(close)
+                        .low(close)
+                        .volume(0.0)
+                        .build()?;
+                    items.push(self.next_dataitem(flat));
+                }
+                Ok(Candlesticks {
+                    items,
+                    reset: false,
+                })
+            }
+            _ => Ok(Candlesticks {
+                items: vec![self.next_dataitem(dataitem)],
+                reset: false,
+            }),
         }
     }
+
+    fn next_dataitem<U>(&mut self, dataitem: DataItem) -> (DataItem, U)
+    where
+        T: for<'a> Next<&'a DataItem, Output = U>,
+    {
+        let output = self.indicator.next(&dataitem);
+        self.dataitem = Some(dataitem.clone());
+        (dataitem, output)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct IndicatorHistory<T, U> {
     indicator_buffer: IndicatorBuffer<T>,
+    /// A determined candlestick per entry, or `None` for a price stamp determining none.
+    /// Cleared when the indicator is reset by a gap
     history: Vec<Option<(DataItem, U)>>,
+    /// Maximum number of retained candlesticks
+    capacity_limit: Option<usize>,
 }
 
 impl<T, U> IndicatorHistory<T, U> {
-    pub fn new<'a>(indicator_buffer: IndicatorBuffer<T>) -> Self
+    pub fn new(indicator_buffer: IndicatorBuffer<T>) -> Self
     where
-        T: Next<&'a DataItem, Output = U>,
+        T: for<'a> Next<&'a DataItem, Output = U>,
     {
         Self {
             indicator_buffer,
             history: vec![],
+            capacity_limit: None,
+        }
+    }
+
+    /// History retaining only the last `max_len` candlesticks and entries after them,
+    /// so that its memory is bounded however many price stamps are fed.
+    /// # Panics
+    /// Panics if `max_len` is 0.
+    pub fn with_capacity_limit(indicator_buffer: IndicatorBuffer<T>, max_len: usize) -> Self
+    where
+        T: for<'a> Next<&'a DataItem, Output = U>,
+    {
+        assert!(max_len > 0);
+        Self {
+            capacity_limit: Some(max_len),
+            ..Self::new(indicator_buffer)
         }
     }
 
@@ -165,22 +436,59 @@ impl<T, U> IndicatorHistory<T, U> {
             .map(|h| h.as_ref().map(|(_, output)| output))
     }
 
-    pub fn next<'a>(&'a mut self, price_stamp: PriceStamp) -> Result<Option<&(DataItem, U)>>
+    /// See `IndicatorBuffer::partial_dataitem`
+    pub fn partial_dataitem(&self) -> Option<DataItem> {
+        self.indicator_buffer.partial_dataitem()
+    }
+
+    /// See `IndicatorBuffer::provisional_output`. History is untouched
+    pub fn provisional_output(&self) -> Option<(DataItem, U)>
     where
-        T: Next<&'a DataItem, Output = U>,
+        T: Clone + for<'a> Next<&'a DataItem, Output = U>,
     {
-        match self.indicator_buffer.next(price_stamp) {
-            Ok(opt) => {
-                self.history.push(opt);
-                Ok(self.history.last().unwrap().as_ref())
-            }
-            Err(e) => Err(e),
+        self.indicator_buffer.provisional_output()
+    }
+
+    pub fn next(&mut self, price_stamp: PriceStamp) -> Result<Option<&(DataItem, U)>>
+    where
+        T: for<'a> Next<&'a DataItem, Output = U> + Reset,
+    {
+        let candlesticks = self.indicator_buffer.next_candlesticks(price_stamp)?;
+
+        if candlesticks.reset {
+            self.history.clear();
+        }
+        if candlesticks.items.is_empty() {
+            self.history.push(None);
+        } else {
+            self.history
+                .extend(candlesticks.items.into_iter().map(Some));
         }
+        self.trim();
+
+        Ok(self.history.last().unwrap().as_ref())
     }
-}
 
-fn to_utc(stamp: NaiveDateTime) -> chrono::DateTime<chrono::Utc> {
-    chrono::DateTime::from_utc(stamp, chrono::Utc)
+    /// Drop the oldest entries exceeding the capacity limit
+    fn trim(&mut self) {
+        let max_len = match self.capacity_limit {
+            Some(max_len) => max_len,
+            None => return,
+        };
+
+        let determined = self.history.iter().filter(|h| h.is_some()).count();
+        if determined > max_len {
+            // Entries before the oldest retained candlestick are dropped.
+            // At least one candlestick remains, so no panic occurs below unwrap().
+            let end = self
+                .history
+                .iter()
+                .positions(|h| h.is_some())
+                .nth(determined - max_len - 1)
+                .unwrap();
+            self.history.drain(..=end);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -225,6 +533,106 @@ mod tests_dataitem_buffer {
         assert_eq!(0.0, dataitem_span2.volume());
     }
 
+    #[test]
+    fn test_next_volume() {
+        let mut b = DataItemBuffer::new(Duration::hours(1));
+
+        // Span 1
+        b.next(pvstamp(1, 0, 2.0, 1.5)).unwrap();
+        b.next(pvstamp(1, 1, 1.0, 0.0)).unwrap();
+        b.next(pvstamp(1, 59, 3.0, 2.5)).unwrap();
+
+        // Volumes within span1 are summed, excluding span2's one
+        let dataitem_span1 = b.next(pvstamp(2, 0, 4.0, 10.0)).unwrap().unwrap();
+        assert_eq!(4.0, dataitem_span1.volume());
+        assert_eq!(3.0, dataitem_span1.close());
+
+        // Price without volume counts as 0
+        b.next(pstamp(2, 30, 3.0)).unwrap();
+        let dataitem_span2 = b.next(pstamp(3, 0, 5.0)).unwrap().unwrap();
+        assert_eq!(10.0, dataitem_span2.volume());
+    }
+
+    #[test]
+    fn test_next_negative_volume() {
+        let mut b = DataItemBuffer::new(Duration::hours(1));
+
+        b.next(pvstamp(1, 0, 2.0, -1.0)).unwrap();
+        let ret = b.next(pvstamp(2, 0, 3.0, 1.0));
+        assert!(ret.is_err());
+    }
+
+    #[test]
+    fn test_next_with_origin() {
+        // 90 minutes intervals from 00:30, beginning at 00:30, 02:00, 03:30, ...
+        let mut b = DataItemBuffer::with_origin(Duration::minutes(90), hm(0, 30));
+        assert_eq!(hm(0, 30), b.origin());
+
+        assert!(matches!(b.next(pstamp(0, 40, 1.0)), Ok(None)));
+        // 01:30 is a boundary from UNIX epoch, but not from the origin
+        assert!(matches!(b.next(pstamp(1, 30, 2.0)), Ok(None)));
+        assert!(matches!(b.next(pstamp(1, 59, 3.0)), Ok(None)));
+
+        let dataitem = b.next(pstamp(2, 0, 4.0)).unwrap().unwrap();
+        assert_eq!(1.0, dataitem.open());
+        assert_eq!(3.0, dataitem.close());
+
+        assert!(matches!(b.next(pstamp(3, 29, 5.0)), Ok(None)));
+        let dataitem = b.next(pstamp(3, 30, 6.0)).unwrap().unwrap();
+        assert_eq!(4.0, dataitem.open());
+        assert_eq!(5.0, dataitem.close());
+    }
+
+    #[test]
+    fn test_next_before_origin() {
+        // Intervals extend backward from the origin, beginning at 23:00 of the previous day
+        let mut b = DataItemBuffer::with_origin(Duration::minutes(90), hm(0, 30));
+
+        b.next(PriceStamp::new(hm(0, 0) - Duration::minutes(61), 1.0))
+            .unwrap();
+        let dataitem = b
+            .next(PriceStamp::new(hm(0, 0) - Duration::minutes(60), 2.0))
+            .unwrap();
+        assert_eq!(Some(1.0), dataitem.map(|item| item.close()));
+
+        assert!(matches!(b.next(pstamp(0, 29, 3.0)), Ok(None)));
+        assert!(b.next(pstamp(0, 30, 4.0)).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_next_discard_first_incomplete() {
+        // Started mid-interval, so the first candlestick lacks prices before 01:10
+        let mut b = DataItemBuffer::new(Duration::hours(1)).with_discard_first_incomplete(true);
+        assert!(matches!(b.next(pstamp(1, 10, 1.0)), Ok(None)));
+        assert!(b.current_partial().is_none());
+        assert!(matches!(b.next(pstamp(2, 0, 2.0)), Ok(None)));
+        assert_eq!(Some(2.0), b.current_partial().map(|item| item.open()));
+        let dataitem = b.next(pstamp(3, 0, 3.0)).unwrap().unwrap();
+        assert_eq!(2.0, dataitem.open());
+
+        // Started at the beginning of an interval, so nothing is dropped
+        let mut b = DataItemBuffer::new(Duration::hours(1)).with_discard_first_incomplete(true);
+        assert!(matches!(b.next(pstamp(1, 0, 1.0)), Ok(None)));
+        let dataitem = b.next(pstamp(2, 0, 2.0)).unwrap().unwrap();
+        assert_eq!(1.0, dataitem.open());
+
+        // Without the flag, the first candlestick is emitted even if incomplete
+        let mut b = DataItemBuffer::new(Duration::hours(1));
+        assert!(matches!(b.next(pstamp(1, 10, 1.0)), Ok(None)));
+        assert!(b.next(pstamp(2, 0, 2.0)).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_missing_intervals() {
+        let mut b = DataItemBuffer::new(Duration::hours(1));
+        assert_eq!(0, b.missing_intervals(&pstamp(1, 0, 1.0)));
+
+        b.next(pstamp(1, 30, 1.0)).unwrap();
+        assert_eq!(0, b.missing_intervals(&pstamp(1, 59, 1.0)));
+        assert_eq!(0, b.missing_intervals(&pstamp(2, 0, 1.0)));
+        assert_eq!(2, b.missing_intervals(&pstamp(4, 10, 1.0)));
+    }
+
     #[test]
     fn test_next_invalid_timestamp() {
         let mut b = DataItemBuffer::new(Duration::hours(1));
@@ -242,6 +650,38 @@ mod tests_dataitem_buffer {
     fn test_non_positive_interval() {
         let _ = DataItemBuffer::new(Duration::zero());
     }
+
+    #[test]
+    fn test_current_partial() {
+        let mut b = DataItemBuffer::new(Duration::hours(1));
+        assert!(b.current_partial().is_none());
+
+        b.next(pstamp(1, 0, 2.0)).unwrap();
+        b.next(pstamp(1, 10, 4.0)).unwrap();
+        b.next(pstamp(1, 20, 1.0)).unwrap();
+
+        let partial = b.current_partial().unwrap();
+        assert_eq!(2.0, partial.open());
+        assert_eq!(4.0, partial.high());
+        assert_eq!(1.0, partial.low());
+        assert_eq!(1.0, partial.close());
+
+        // Stamps are not consumed, so the determined candlestick covers all of them
+        b.next(pstamp(1, 30, 3.0)).unwrap();
+        let partial = b.current_partial().unwrap();
+        assert_eq!(3.0, partial.close());
+
+        let item = b.next(pstamp(2, 0, 5.0)).unwrap().unwrap();
+        assert_eq!(2.0, item.open());
+        assert_eq!(4.0, item.high());
+        assert_eq!(1.0, item.low());
+        assert_eq!(3.0, item.close());
+
+        // Next interval consists of the latest stamp only
+        let partial = b.current_partial().unwrap();
+        assert_eq!(5.0, partial.open());
+        assert_eq!(5.0, partial.close());
+    }
 }
 
 #[cfg(test)]
@@ -314,6 +754,163 @@ mod tests_indicator_buffer {
         let indicator = SimpleMovingAverage::new(3).unwrap();
         let _ = IndicatorBuffer::new(indicator, Duration::zero());
     }
+
+    #[test]
+    fn test_next_volume() {
+        let indicator = SimpleMovingAverage::new(3).unwrap();
+        let mut b = IndicatorBuffer::new(indicator, Duration::hours(1));
+
+        b.next(pvstamp(1, 0, 2.0, 1.0)).unwrap();
+        b.next(pvstamp(1, 30, 3.0, 2.0)).unwrap();
+
+        let (dataitem_span1, output_span1) = b.next(pvstamp(2, 0, 4.0, 4.0)).unwrap().unwrap();
+        assert_eq!(3.0, dataitem_span1.volume());
+        // SMA reads close only
+        assert_eq!(3.0, output_span1);
+    }
+
+    /// Interval 1 has prices, intervals 2 and 3 miss any, and interval 4 is finished at 5:00
+    fn feed_two_interval_gap(
+        gap_policy: GapPolicy,
+    ) -> (Candlesticks<f64>, Candlesticks<f64>, Candlesticks<f64>) {
+        let indicator = SimpleMovingAverage::new(3).unwrap();
+        let mut b = IndicatorBuffer::new(indicator, Duration::hours(1)).with_gap_policy(gap_policy);
+
+        b.next_candlesticks(pstamp(1, 0, 2.0)).unwrap();
+        let span1 = b.next_candlesticks(pstamp(1, 30, 3.0)).unwrap();
+        let after_gap = b.next_candlesticks(pvstamp(4, 0, 5.0, 1.0)).unwrap();
+        let span4 = b.next_candlesticks(pstamp(5, 0, 7.0)).unwrap();
+        (span1, after_gap, span4)
+    }
+
+    #[test]
+    fn test_gap_skip() {
+        let (span1, after_gap, span4) = feed_two_interval_gap(GapPolicy::SkipGaps);
+
+        assert!(span1.items.is_empty());
+        assert!(!after_gap.reset);
+        assert_eq!(1, after_gap.items.len());
+        let (dataitem_span1, output_span1) = &after_gap.items[0];
+        assert_eq!(3.0, dataitem_span1.close());
+        assert_eq!(3.0, *output_span1);
+
+        // Span4 directly follows span1
+        assert_eq!(1, span4.items.len());
+        let (dataitem_span4, output_span4) = &span4.items[0];
+        assert_eq!(5.0, dataitem_span4.close());
+        assert_eq!(1.0, dataitem_span4.volume());
+        assert_eq!((3.0 + 5.0) / 2.0, *output_span4);
+    }
+
+    #[test]
+    fn test_gap_fill_forward() {
+        let (_, after_gap, span4) = feed_two_interval_gap(GapPolicy::FillForward);
+
+        // Span1 and flat candlesticks of span2 and span3
+        assert!(!after_gap.reset);
+        assert_eq!(3, after_gap.items.len());
+        assert_eq!(3.0, after_gap.items[0].0.close());
+        assert_eq!(2.0, after_gap.items[0].0.open());
+        for (dataitem, output) in &after_gap.items[1..] {
+            assert_eq!(3.0, dataitem.open());
+            assert_eq!(3.0, dataitem.high());
+            assert_eq!(3.0, dataitem.low());
+            assert_eq!(3.0, dataitem.close());
+            assert_eq!(0.0, dataitem.volume());
+            assert_eq!(3.0, *output);
+        }
+
+        assert_eq!(1, span4.items.len());
+        let (dataitem_span4, output_span4) = &span4.items[0];
+        assert_eq!(5.0, dataitem_span4.close());
+        assert_eq!((3.0 + 3.0 + 5.0) / 3.0, *output_span4);
+    }
+
+    #[test]
+    fn test_gap_reset() {
+        let (_, after_gap, span4) = feed_two_interval_gap(GapPolicy::ResetOnGap(1));
+
+        // Span1 is discarded with the indicator state
+        assert!(after_gap.reset);
+        assert!(after_gap.items.is_empty());
+
+        assert!(!span4.reset);
+        assert_eq!(1, span4.items.len());
+        let (dataitem_span4, output_span4) = &span4.items[0];
+        assert_eq!(5.0, dataitem_span4.close());
+        assert_eq!(5.0, *output_span4);
+    }
+
+    #[test]
+    fn test_gap_reset_within_limit() {
+        let (_, after_gap, span4) = feed_two_interval_gap(GapPolicy::ResetOnGap(2));
+
+        // Two missing intervals don't exceed the limit, then skipped
+        assert!(!after_gap.reset);
+        assert_eq!(1, after_gap.items.len());
+        assert_eq!(3.0, after_gap.items[0].1);
+        assert_eq!(1, span4.items.len());
+        assert_eq!((3.0 + 5.0) / 2.0, span4.items[0].1);
+    }
+
+    #[test]
+    fn test_next_returns_last_filled() {
+        let indicator = SimpleMovingAverage::new(3).unwrap();
+        let mut b = IndicatorBuffer::new(indicator, Duration::hours(1))
+            .with_gap_policy(GapPolicy::FillForward);
+
+        b.next(pstamp(1, 0, 2.0)).unwrap();
+        let (dataitem, output) = b.next(pstamp(4, 0, 5.0)).unwrap().unwrap();
+        assert_eq!(2.0, dataitem.open());
+        assert_eq!(2.0, dataitem.close());
+        assert_eq!(2.0, output);
+        assert_eq!(Some(2.0), b.last_dataitem().map(|d| d.close()));
+    }
+
+    #[test]
+    fn test_provisional_output() {
+        let indicator = SimpleMovingAverage::new(2).unwrap();
+        let mut b = IndicatorBuffer::new(indicator.clone(), Duration::hours(1));
+        let mut untouched = IndicatorBuffer::new(indicator, Duration::hours(1));
+        assert!(b.provisional_output().is_none());
+
+        let stamps = vec![
+            pstamp(1, 0, 2.0),
+            pstamp(1, 30, 4.0),
+            pstamp(2, 0, 6.0),
+            pstamp(2, 30, 8.0),
+            pstamp(3, 0, 10.0),
+            pstamp(4, 0, 12.0),
+        ];
+        for stamp in stamps {
+            let output = b.next(stamp).unwrap().map(|(_, output)| output);
+            let expected = untouched.next(stamp).unwrap().map(|(_, output)| output);
+            // Provisional evaluation doesn't affect the determined outputs
+            assert_eq!(expected, output);
+
+            let (dataitem, _) = b.provisional_output().unwrap();
+            assert_eq!(stamp.price, dataitem.close());
+            assert_eq!(dataitem.open(), b.partial_dataitem().unwrap().open());
+        }
+
+        // Span 4 is open with price 12.0, and span 3 closed at 10.0
+        let (_, provisional) = b.provisional_output().unwrap();
+        assert_eq!((10.0 + 12.0) / 2.0, provisional);
+        // Repeated evaluation gives the same output
+        let (_, provisional) = b.provisional_output().unwrap();
+        assert_eq!((10.0 + 12.0) / 2.0, provisional);
+    }
+
+    #[test]
+    fn test_drop_without_next() {
+        let indicator = SimpleMovingAverage::new(3).unwrap();
+        let b = IndicatorBuffer::new(indicator, Duration::hours(1));
+
+        assert_eq!(Duration::hours(1), b.interval());
+        let cloned = b.clone();
+        drop(b);
+        drop(cloned);
+    }
 }
 
 #[cfg(test)]
@@ -409,6 +1006,74 @@ mod tests_indicator_history {
         assert_eq!(expected, smas);
     }
 
+    #[test]
+    fn test_next_gap() {
+        let feed = |gap_policy| {
+            let indicator = SimpleMovingAverage::new(3).unwrap();
+            let b = IndicatorBuffer::new(indicator, Duration::hours(1)).with_gap_policy(gap_policy);
+            let mut h = IndicatorHistory::new(b);
+            h.next(pstamp(1, 0, 2.0)).unwrap();
+            h.next(pstamp(1, 30, 3.0)).unwrap();
+            h.next(pstamp(4, 0, 5.0)).unwrap();
+            h.next(pstamp(5, 0, 7.0)).unwrap();
+            h.outputs().map(|opt| opt.copied()).collect_vec()
+        };
+
+        let expected = vec![None, None, Some(3.0), Some((3.0 + 5.0) / 2.0)];
+        assert_eq!(expected, feed(GapPolicy::SkipGaps));
+
+        // Filled candlesticks are recorded as well
+        let expected = vec![
+            None,
+            None,
+            Some(3.0),
+            Some(3.0),
+            Some(3.0),
+            Some((3.0 + 3.0 + 5.0) / 3.0),
+        ];
+        assert_eq!(expected, feed(GapPolicy::FillForward));
+
+        // History before the gap is cleared with the indicator
+        let expected = vec![None, Some(5.0)];
+        assert_eq!(expected, feed(GapPolicy::ResetOnGap(1)));
+    }
+
+    #[test]
+    fn test_capacity_limit() {
+        let new_buffer =
+            || IndicatorBuffer::new(SimpleMovingAverage::new(3).unwrap(), Duration::hours(1));
+        let mut limited = IndicatorHistory::with_capacity_limit(new_buffer(), 5);
+        let mut unlimited = IndicatorHistory::new(new_buffer());
+
+        // 3 stamps per candlestick
+        let origin = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 0, 0);
+        for i in 0..10000 {
+            let stamp = origin + Duration::minutes(20 * i);
+            let price_stamp = PriceStamp::new(stamp, (i % 7) as f64);
+            let expected = unlimited.next(price_stamp).unwrap().cloned();
+            let actual = limited.next(price_stamp).unwrap().cloned();
+            assert_eq!(expected.map(|(_, o)| o), actual.map(|(_, o)| o));
+
+            // 5 candlesticks with stamps of their intervals, and the ones before the first candlestick
+            assert!(limited.history().len() <= (5 + 1) * 3);
+        }
+
+        // Retained window is the tail of the whole history
+        assert_eq!(5, limited.outputs().flatten().count());
+        assert_eq!(5, limited.dataitems().flatten().count());
+        let expected = unlimited.outputs().flatten().copied().collect_vec();
+        let actual = limited.outputs().flatten().copied().collect_vec();
+        assert_eq!(expected[expected.len() - 5..], actual[..]);
+        assert_eq!(expected.len(), 10000 / 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_zero_capacity_limit() {
+        let b = IndicatorBuffer::new(SimpleMovingAverage::new(3).unwrap(), Duration::hours(1));
+        let _ = IndicatorHistory::with_capacity_limit(b, 0);
+    }
+
     #[test]
     fn test_next_invalid_timestamp() {
         let indicator = SimpleMovingAverage::new(3).unwrap();
@@ -422,6 +1087,28 @@ mod tests_indicator_history {
         let ret = h.next(pstamp(1, 0, 2.0));
         assert!(ret.is_err());
     }
+    #[test]
+    fn test_provisional_output() {
+        let indicator = SimpleMovingAverage::new(2).unwrap();
+        let b = IndicatorBuffer::new(indicator, Duration::hours(1));
+        let mut h = IndicatorHistory::new(b);
+
+        h.next(pstamp(1, 0, 2.0)).unwrap();
+        h.next(pstamp(2, 0, 4.0)).unwrap();
+        h.next(pstamp(2, 30, 6.0)).unwrap();
+        let history_len = h.history().len();
+
+        let (dataitem, output) = h.provisional_output().unwrap();
+        assert_eq!(4.0, dataitem.open());
+        assert_eq!(6.0, dataitem.close());
+        assert_eq!((2.0 + 6.0) / 2.0, output);
+        assert_eq!(6.0, h.partial_dataitem().unwrap().close());
+
+        // History is untouched
+        assert_eq!(history_len, h.history().len());
+        let (_, output) = h.next(pstamp(3, 0, 8.0)).unwrap().unwrap();
+        assert_eq!((2.0 + 6.0) / 2.0, *output);
+    }
 }
 
 #[cfg(test)]
@@ -434,7 +1121,12 @@ mod tests {
         PriceStamp::new(stamp, price)
     }
 
-    fn hm(hour: u32, minute: u32) -> NaiveDateTime {
+    pub fn pvstamp(hour: u32, minute: u32, price: f64, volume: f64) -> PriceStamp {
+        let stamp = hm(hour, minute);
+        PriceStamp::with_volume(stamp, price, volume)
+    }
+
+    pub fn hm(hour: u32, minute: u32) -> NaiveDateTime {
         chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(hour, minute, 0)
     }
 }
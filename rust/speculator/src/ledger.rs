@@ -0,0 +1,269 @@
+use crate::trade::OrderRecommendation;
+use database::custom_sql_type::{CurrencyId, OrderSide};
+use database::model::{Amount, Balance, Market, MyOrder};
+use std::collections::HashMap;
+use thiserror::Error as ThisError;
+
+/// Balances of currencies moved by an order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrderFunds {
+    /// Currency and quantity paid, including fee of buy orders
+    pub spent: (CurrencyId, Amount),
+    /// Currency and quantity got when filled, excluding fee of sell orders
+    pub received: (CurrencyId, Amount),
+}
+
+impl OrderFunds {
+    /// Buy orders spend quote and receive base, and sell orders vice versa
+    pub fn new(
+        market: &Market,
+        side: OrderSide,
+        base_quantity: Amount,
+        quote_quantity: Amount,
+        fee_ratio: f64,
+    ) -> Self {
+        match side {
+            OrderSide::Buy => Self {
+                spent: (market.quote_id, quote_quantity * (1.0 + fee_ratio)),
+                received: (market.base_id, base_quantity),
+            },
+            OrderSide::Sell => Self {
+                spent: (market.base_id, base_quantity),
+                received: (market.quote_id, quote_quantity * (1.0 - fee_ratio)),
+            },
+        }
+    }
+
+    /// Funds of a recommended order, whose expected net quote already includes fee
+    pub fn from_order(market: &Market, order: &OrderRecommendation) -> Self {
+        let (base_diff, quote_diff) = order.balance_diff();
+        match order.side {
+            OrderSide::Buy => Self {
+                spent: (market.quote_id, -quote_diff),
+                received: (market.base_id, base_diff),
+            },
+            OrderSide::Sell => Self {
+                spent: (market.base_id, -base_diff),
+                received: (market.quote_id, quote_diff),
+            },
+        }
+    }
+
+    pub fn from_myorder(market: &Market, myorder: &MyOrder, fee_ratio: f64) -> Self {
+        Self::new(
+            market,
+            myorder.side,
+            myorder.base_quantity,
+            myorder.quote_quantity,
+            fee_ratio,
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, ThisError)]
+pub enum LedgerError {
+    #[error("Currency {currency_id} needs {required}, but only {available} is available")]
+    Insufficient {
+        currency_id: CurrencyId,
+        available: Amount,
+        required: Amount,
+    },
+}
+
+fn available(balances: &HashMap<CurrencyId, Balance>, currency_id: CurrencyId) -> Amount {
+    balances
+        .get(&currency_id)
+        .map(|b| b.available)
+        .unwrap_or_default()
+}
+
+/// Pending balance is not spendable, so only available balance is checked
+fn check_available(
+    balances: &HashMap<CurrencyId, Balance>,
+    (currency_id, required): (CurrencyId, Amount),
+) -> Result<(), LedgerError> {
+    let available = available(balances, currency_id);
+    if available < required {
+        return Err(LedgerError::Insufficient {
+            currency_id,
+            available,
+            required,
+        });
+    }
+    Ok(())
+}
+
+fn add_available(
+    balances: &mut HashMap<CurrencyId, Balance>,
+    (currency_id, quantity): (CurrencyId, Amount),
+) {
+    if let Some(balance) = balances.get_mut(&currency_id) {
+        balance.available += quantity;
+    }
+}
+
+/// Fill an order immediately, e.g. market orders
+pub fn fill(
+    balances: &mut HashMap<CurrencyId, Balance>,
+    funds: &OrderFunds,
+) -> Result<(), LedgerError> {
+    check_available(balances, funds.spent)?;
+
+    let (currency_id, quantity) = funds.spent;
+    add_available(balances, (currency_id, -quantity));
+    add_available(balances, funds.received);
+    Ok(())
+}
+
+/// Reserve spent balance of an order waiting on the orderbook by moving it from available to pending
+pub fn open(
+    balances: &mut HashMap<CurrencyId, Balance>,
+    funds: &OrderFunds,
+) -> Result<(), LedgerError> {
+    check_available(balances, funds.spent)?;
+
+    let (currency_id, quantity) = funds.spent;
+    if let Some(balance) = balances.get_mut(&currency_id) {
+        balance.available -= quantity;
+        balance.pending += quantity;
+    }
+    Ok(())
+}
+
+/// Take reserved balance from pending, no more than pending
+fn take_pending(
+    balances: &mut HashMap<CurrencyId, Balance>,
+    (currency_id, quantity): (CurrencyId, Amount),
+) -> Amount {
+    match balances.get_mut(&currency_id) {
+        Some(balance) => {
+            let taken = quantity.min(balance.pending).max(0.0);
+            balance.pending -= taken;
+            taken
+        }
+        None => 0.0,
+    }
+}
+
+/// Fill an opened order. Its reserved balance is consumed, and the received currency becomes available
+pub fn fill_opened(balances: &mut HashMap<CurrencyId, Balance>, funds: &OrderFunds) {
+    take_pending(balances, funds.spent);
+    add_available(balances, funds.received);
+}
+
+/// Cancel an opened order. Its reserved balance becomes available again
+pub fn cancel_opened(balances: &mut HashMap<CurrencyId, Balance>, funds: &OrderFunds) {
+    let (currency_id, _) = funds.spent;
+    let released = take_pending(balances, funds.spent);
+    add_available(balances, (currency_id, released));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::custom_sql_type::{BalanceId, MarketId, StampId};
+
+    const DOGE: CurrencyId = CurrencyId::new(1);
+    const USDT: CurrencyId = CurrencyId::new(2);
+
+    fn market() -> Market {
+        Market::new(MarketId::new(1), DOGE, USDT)
+    }
+
+    /// DOGE 10 and USDT 1000 are available
+    fn balances() -> HashMap<CurrencyId, Balance> {
+        vec![(DOGE, 10.0), (USDT, 1000.0)]
+            .into_iter()
+            .map(|(currency_id, available)| {
+                let balance = Balance::new(
+                    BalanceId::new(currency_id.inner()),
+                    currency_id,
+                    StampId::new(1),
+                    available,
+                    0.0,
+                );
+                (currency_id, balance)
+            })
+            .collect()
+    }
+
+    /// (available, pending) of `currency_id`
+    fn split(balances: &HashMap<CurrencyId, Balance>, currency_id: CurrencyId) -> (Amount, Amount) {
+        let balance = &balances[&currency_id];
+        (balance.available, balance.pending)
+    }
+
+    #[test]
+    fn test_order_funds() {
+        let buy = OrderFunds::new(&market(), OrderSide::Buy, 2.0, 200.0, 0.25);
+        assert_eq!((USDT, 250.0), buy.spent);
+        assert_eq!((DOGE, 2.0), buy.received);
+
+        let sell = OrderFunds::new(&market(), OrderSide::Sell, 2.0, 200.0, 0.25);
+        assert_eq!((DOGE, 2.0), sell.spent);
+        assert_eq!((USDT, 150.0), sell.received);
+    }
+
+    #[test]
+    fn test_fill() {
+        let mut balances = balances();
+        let funds = OrderFunds::new(&market(), OrderSide::Buy, 2.0, 200.0, 0.0);
+
+        fill(&mut balances, &funds).unwrap();
+        assert_eq!((12.0, 0.0), split(&balances, DOGE));
+        assert_eq!((800.0, 0.0), split(&balances, USDT));
+    }
+
+    #[test]
+    fn test_open_then_fill() {
+        let mut balances = balances();
+        let funds = OrderFunds::new(&market(), OrderSide::Buy, 2.0, 200.0, 0.0);
+
+        open(&mut balances, &funds).unwrap();
+        assert_eq!((10.0, 0.0), split(&balances, DOGE));
+        assert_eq!((800.0, 200.0), split(&balances, USDT));
+
+        fill_opened(&mut balances, &funds);
+        assert_eq!((12.0, 0.0), split(&balances, DOGE));
+        assert_eq!((800.0, 0.0), split(&balances, USDT));
+    }
+
+    #[test]
+    fn test_open_then_cancel() {
+        let mut balances = balances();
+        let funds = OrderFunds::new(&market(), OrderSide::Sell, 4.0, 400.0, 0.0);
+
+        open(&mut balances, &funds).unwrap();
+        assert_eq!((6.0, 4.0), split(&balances, DOGE));
+        assert_eq!((1000.0, 0.0), split(&balances, USDT));
+
+        cancel_opened(&mut balances, &funds);
+        assert_eq!((10.0, 0.0), split(&balances, DOGE));
+        assert_eq!((1000.0, 0.0), split(&balances, USDT));
+    }
+
+    #[test]
+    fn test_insufficient_available() {
+        let mut balances = balances();
+        let funds = OrderFunds::new(&market(), OrderSide::Sell, 8.0, 800.0, 0.0);
+        open(&mut balances, &funds).unwrap();
+
+        // Pending balance can't be spent
+        assert!(matches!(
+            open(&mut balances, &funds),
+            Err(LedgerError::Insufficient { .. })
+        ));
+        assert!(fill(&mut balances, &funds).is_err());
+        assert_eq!((2.0, 8.0), split(&balances, DOGE));
+    }
+
+    #[test]
+    fn test_release_no_more_than_pending() {
+        let mut balances = balances();
+        let funds = OrderFunds::new(&market(), OrderSide::Buy, 2.0, 200.0, 0.0);
+
+        // Not opened
+        cancel_opened(&mut balances, &funds);
+        assert_eq!((1000.0, 0.0), split(&balances, USDT));
+    }
+}
@@ -1,5 +1,14 @@
+#[macro_use]
+extern crate log;
+
+pub mod evaluation;
+pub mod execution;
+pub mod fee;
 pub mod indicator;
+pub mod ledger;
+pub mod rsi;
 pub mod rule;
+pub mod timing;
 pub mod trade;
 
 pub type Timestamp = chrono::NaiveDateTime;
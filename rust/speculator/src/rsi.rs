@@ -0,0 +1,324 @@
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use ta::indicators::RelativeStrengthIndex;
+use ta::{Close, Next, Period, Reset};
+
+/// How gains and losses of closes are averaged in RSI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RsiMethod {
+    /// EMA with `k = 2 / (period + 1)`, as `ta::indicators::RelativeStrengthIndex`
+    Exponential,
+    /// Sum over the last `period` changes
+    Simple,
+    /// Wilder's smoothing with `k = 1 / period`, seeded by the mean of the first `period` changes
+    Wilder,
+}
+
+impl RsiMethod {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RsiMethod::Exponential => "exponential",
+            RsiMethod::Simple => "simple",
+            RsiMethod::Wilder => "wilder",
+        }
+    }
+}
+
+impl Default for RsiMethod {
+    fn default() -> Self {
+        RsiMethod::Exponential
+    }
+}
+
+/// RSI of candlestick closes, calculated by `RsiMethod`
+#[derive(Debug, Clone)]
+pub struct Rsi {
+    method: RsiMethod,
+    period: usize,
+    calculator: Calculator,
+}
+
+impl Rsi {
+    pub fn new(method: RsiMethod, period: usize) -> Result<Self> {
+        ensure!(period > 0, "RSI period must be positive");
+
+        let calculator = match method {
+            RsiMethod::Exponential => Calculator::Exponential(RelativeStrengthIndex::new(period)?),
+            RsiMethod::Simple => Calculator::Simple(SimpleRsi::new(period)),
+            RsiMethod::Wilder => Calculator::Wilder(WilderRsi::new(period)),
+        };
+
+        Ok(Self {
+            method,
+            period,
+            calculator,
+        })
+    }
+
+    pub fn method(&self) -> RsiMethod {
+        self.method
+    }
+
+    fn calculator_mut(&mut self) -> &mut dyn RsiCalculator {
+        match &mut self.calculator {
+            Calculator::Exponential(c) => c,
+            Calculator::Simple(c) => c,
+            Calculator::Wilder(c) => c,
+        }
+    }
+}
+
+impl Period for Rsi {
+    fn period(&self) -> usize {
+        self.period
+    }
+}
+
+impl<T: Close> Next<&T> for Rsi {
+    type Output = f64;
+
+    fn next(&mut self, input: &T) -> f64 {
+        self.calculator_mut().next_close(input.close())
+    }
+}
+
+impl Reset for Rsi {
+    fn reset(&mut self) {
+        self.calculator_mut().reset_close();
+    }
+}
+
+/// RSI calculation of a method, fed by closes
+trait RsiCalculator {
+    fn next_close(&mut self, close: f64) -> f64;
+
+    fn reset_close(&mut self);
+}
+
+#[derive(Debug, Clone)]
+enum Calculator {
+    Exponential(RelativeStrengthIndex),
+    Simple(SimpleRsi),
+    Wilder(WilderRsi),
+}
+
+impl RsiCalculator for RelativeStrengthIndex {
+    fn next_close(&mut self, close: f64) -> f64 {
+        self.next(close)
+    }
+
+    fn reset_close(&mut self) {
+        self.reset();
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SimpleRsi {
+    period: usize,
+    prev_close: Option<f64>,
+    /// Gains and losses of the last `period` changes
+    changes: VecDeque<(f64, f64)>,
+}
+
+impl SimpleRsi {
+    fn new(period: usize) -> Self {
+        Self {
+            period,
+            prev_close: None,
+            changes: VecDeque::with_capacity(period + 1),
+        }
+    }
+}
+
+impl RsiCalculator for SimpleRsi {
+    fn next_close(&mut self, close: f64) -> f64 {
+        if let Some(change) = change(&mut self.prev_close, close) {
+            self.changes.push_back(change);
+            if self.changes.len() > self.period {
+                self.changes.pop_front();
+            }
+        }
+
+        let gain = self.changes.iter().map(|(gain, _)| gain).sum();
+        let loss = self.changes.iter().map(|(_, loss)| loss).sum();
+        rsi(gain, loss)
+    }
+
+    fn reset_close(&mut self) {
+        self.prev_close = None;
+        self.changes.clear();
+    }
+}
+
+#[derive(Debug, Clone)]
+struct WilderRsi {
+    period: usize,
+    prev_close: Option<f64>,
+    /// Number of changes, up to `period`
+    count: usize,
+    /// Averaged gain and loss
+    average: (f64, f64),
+}
+
+impl WilderRsi {
+    fn new(period: usize) -> Self {
+        Self {
+            period,
+            prev_close: None,
+            count: 0,
+            average: (0.0, 0.0),
+        }
+    }
+}
+
+impl RsiCalculator for WilderRsi {
+    fn next_close(&mut self, close: f64) -> f64 {
+        if let Some((gain, loss)) = change(&mut self.prev_close, close) {
+            // Running mean until `period` changes, which becomes Wilder's smoothing after that
+            self.count = (self.count + 1).min(self.period);
+            let n = self.count as f64;
+            let (average_gain, average_loss) = self.average;
+            self.average = (
+                average_gain + (gain - average_gain) / n,
+                average_loss + (loss - average_loss) / n,
+            );
+        }
+
+        rsi(self.average.0, self.average.1)
+    }
+
+    fn reset_close(&mut self) {
+        *self = Self::new(self.period);
+    }
+}
+
+/// Gain and loss from the previous close, which is replaced by `close`
+fn change(prev_close: &mut Option<f64>, close: f64) -> Option<(f64, f64)> {
+    match prev_close.replace(close) {
+        Some(prev) if close > prev => Some((close - prev, 0.0)),
+        Some(prev) => Some((0.0, prev - close)),
+        None => None,
+    }
+}
+
+/// 50 without any gain or loss
+fn rsi(gain: f64, loss: f64) -> f64 {
+    if gain + loss > 0.0 {
+        100.0 * gain / (gain + loss)
+    } else {
+        50.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ta::DataItem;
+
+    const CLOSES: [f64; 5] = [100.0, 110.0, 105.0, 112.0, 108.0];
+
+    fn item(close: f64) -> DataItem {
+        DataItem::builder()
+            .open(close)
+            .high(close)
+            .low(close)
+            .close(close)
+            .volume(0.0)
+            .build()
+            .unwrap()
+    }
+
+    fn rsis(method: RsiMethod) -> Vec<f64> {
+        let mut rsi = Rsi::new(method, 2).unwrap();
+        CLOSES.iter().map(|close| rsi.next(&item(*close))).collect()
+    }
+
+    fn assert_approx(expected: &[f64], actual: &[f64]) {
+        assert_eq!(expected.len(), actual.len());
+        for (e, a) in expected.iter().zip(actual) {
+            assert!(
+                (e - a).abs() < 1e-9,
+                "expected {:?}, got {:?}",
+                expected,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn test_simple() {
+        // Changes: +10, -5, +7, -4
+        let expected = [
+            50.0,
+            100.0,
+            100.0 * 10.0 / 15.0,
+            100.0 * 7.0 / 12.0,
+            100.0 * 7.0 / 11.0,
+        ];
+        assert_approx(&expected, &rsis(RsiMethod::Simple));
+    }
+
+    #[test]
+    fn test_wilder() {
+        // Averages: (10, 0) -> (5, 2.5) seeded -> (6, 1.25) -> (3, 2.625)
+        let expected = [
+            50.0,
+            100.0,
+            100.0 * 5.0 / 7.5,
+            100.0 * 6.0 / 7.25,
+            100.0 * 3.0 / 5.625,
+        ];
+        assert_approx(&expected, &rsis(RsiMethod::Wilder));
+    }
+
+    #[test]
+    fn test_exponential() {
+        let mut expected = RelativeStrengthIndex::new(2).unwrap();
+        let expected = CLOSES.iter().map(|c| expected.next(*c)).collect::<Vec<_>>();
+        assert_approx(&expected, &rsis(RsiMethod::Exponential));
+
+        // Ups and downs are seeded by 0.1, then k = 2/3
+        let up = 2.0 / 3.0 * 10.0 + 0.1 / 3.0;
+        let down = 0.1 / 3.0;
+        assert_approx(&[50.0, 100.0 * up / (up + down)], &expected[..2]);
+    }
+
+    #[test]
+    fn test_methods_disagree() {
+        let simple = rsis(RsiMethod::Simple);
+        let wilder = rsis(RsiMethod::Wilder);
+
+        // Identical until the window is filled
+        assert_approx(&simple[..3], &wilder[..3]);
+        assert!((simple[3] - wilder[3]).abs() > 1.0);
+    }
+
+    #[test]
+    fn test_reset() {
+        for method in [RsiMethod::Exponential, RsiMethod::Simple, RsiMethod::Wilder].iter() {
+            let mut rsi = Rsi::new(*method, 2).unwrap();
+            rsi.next(&item(105.0));
+            rsi.next(&item(110.0));
+
+            // No change is known after reset
+            rsi.reset();
+            assert_eq!(50.0, rsi.next(&item(100.0)));
+            assert_eq!(*method, rsi.method());
+        }
+    }
+
+    #[test]
+    fn test_invalid_period() {
+        assert!(Rsi::new(RsiMethod::Simple, 0).is_err());
+        assert!(Rsi::new(RsiMethod::Exponential, 0).is_err());
+    }
+
+    #[test]
+    fn test_method_json() {
+        let method = serde_json::from_str::<RsiMethod>(r#""wilder""#).unwrap();
+        assert_eq!(RsiMethod::Wilder, method);
+        assert_eq!(RsiMethod::Exponential, RsiMethod::default());
+    }
+}
@@ -1,11 +1,17 @@
+pub mod bollinger;
 pub mod fixed;
+pub mod macd_cross;
+pub mod orderbook_imbalance;
 pub mod rsi_cross;
 pub mod rsi_divergence;
+pub mod stop;
 
+use crate::indicator::PriceStamp;
 use crate::Duration;
 use anyhow::Error;
 pub use database::model::*;
 use thiserror::Error as ThisError;
+use validator::ValidationErrors;
 
 /// Market state at a time
 #[derive(Debug, Clone)]
@@ -35,38 +41,71 @@ impl MarketState {
             myorders,
         }
     }
-}
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum RecommendationType {
-    Buy,
-    Sell,
-    /// Should not do any trade
-    Pending,
-    /// Leave determination to other rules
-    Neutral,
+    /// Price and volume of this state for candlesticks.
+    /// Volume is the sum of orderbook volumes, which is 0 without orderbooks. Invalid volumes are ignored
+    pub fn price_stamp(&self) -> PriceStamp {
+        let volume = self
+            .orderbooks
+            .iter()
+            .map(|o| o.volume)
+            .filter(|volume| volume.is_finite() && *volume > 0.0)
+            .sum();
+        PriceStamp::with_volume(self.stamp.timestamp, self.price.amount, volume)
+    }
 }
 
-/// Trade recommendation by speculator rule
-pub trait Recommendation {
+/// Trade recommendation by speculator rule.
+/// `Send` so that markets are evaluated in parallel
+pub trait Recommendation: Send {
     fn recommendation_type(&self) -> RecommendationType;
 
     fn reason(&self) -> String;
+
+    /// Trigger price of a sell stop protecting the held position, if this recommendation asks for one
+    fn stop_loss_trigger(&self) -> Option<f64> {
+        None
+    }
 }
 
 #[typetag::serde(tag = "algorithm")]
 pub trait RuleParameter {
     fn create_rule(&self, market: Market) -> Box<dyn Rule>;
+
+    /// Check constraints of this parameter
+    fn validate_parameter(&self) -> Result<(), ValidationErrors>;
 }
 
-/// Speculator rule
-pub trait Rule {
+/// Speculator rule.
+/// `Send` so that rules of different markets are updated in parallel
+pub trait Rule: Send {
+    /// Return algorithm name, same as `algorithm` of rule JSON
+    fn name(&self) -> &'static str;
+
     /// Return target-market of this rule
     fn market(&self) -> Market;
 
     /// Return the shortest duration required to generate recommendation
     fn duration_requirement(&self) -> Option<Duration>;
 
+    /// Return the number of the latest market states required to generate recommendation, regardless of their duration.
+    /// Market states are loaded to cover both this and `duration_requirement`
+    fn stamp_count_requirement(&self) -> usize {
+        0
+    }
+
+    /// Return interval of candlesticks if this rule uses them
+    fn candlestick_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Return `true` if this rule reads `MarketState::myorders`.
+    /// Market states of other rules may have no myorders.
+    /// The first market state loaded also carries myorders modified before it
+    fn requires_myorders(&self) -> bool {
+        false
+    }
+
     /// Push newer market state
     /// # Returns
     /// `Ok(())` if succeeds
@@ -87,6 +126,52 @@ pub trait Rule {
     }
 }
 
+/// Suppression of buy/sell recommendations for some candlesticks after a rule recommends them.
+///
+/// Rules feed it on every determined candlestick, so that its state is rebuilt from market states
+/// loaded on each launch
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cooldown {
+    candlesticks: usize,
+    remaining: usize,
+    suppressing: bool,
+}
+
+impl Cooldown {
+    /// Suppress the next `candlesticks` determined candlesticks. No suppression if 0
+    pub fn new(candlesticks: usize) -> Self {
+        Self {
+            candlesticks,
+            remaining: 0,
+            suppressing: false,
+        }
+    }
+
+    /// Feed a newly determined candlestick, where the rule recommends buy/sell if `fires`.
+    /// Recommendations during cooldown don't extend it
+    pub fn next(&mut self, fires: bool) {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.suppressing = true;
+        } else {
+            self.suppressing = false;
+            if fires {
+                self.remaining = self.candlesticks;
+            }
+        }
+    }
+
+    /// Return `true` if recommendation on the latest determined candlestick is suppressed
+    pub fn is_suppressing(&self) -> bool {
+        self.suppressing
+    }
+
+    /// Return the number of candlesticks suppressed after the latest determined one
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+}
+
 #[derive(Debug, ThisError)]
 pub enum RuleError {
     #[error("Market constraint failure")]
@@ -0,0 +1,331 @@
+use super::*;
+use crate::indicator::*;
+use anyhow::Result;
+use database::model::*;
+use serde::{Deserialize, Serialize};
+use ta::indicators::{BollingerBands, BollingerBandsOutput};
+use ta::Close;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+#[validate(schema(function = "validate_scores"))]
+pub struct BollingerParameter {
+    #[validate(range(min = 1))]
+    candlestick_interval_min: i64,
+    #[validate(range(min = 2))]
+    window_size: usize,
+    /// Buy if the latest close is lower than the average by more than this times standard deviation
+    buy_score: f64,
+    /// Sell if the latest close is higher than the average by more than this times standard deviation
+    sell_score: f64,
+}
+
+impl BollingerParameter {
+    fn candlestick_interval(&self) -> Duration {
+        Duration::minutes(self.candlestick_interval_min)
+    }
+}
+
+#[typetag::serde(name = "bollinger")]
+impl RuleParameter for BollingerParameter {
+    fn create_rule(&self, market: Market) -> Box<dyn Rule> {
+        Box::from(BollingerRule::new(market, *self))
+    }
+
+    fn validate_parameter(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
+}
+
+fn validate_scores(parameter: &BollingerParameter) -> Result<(), ValidationError> {
+    if parameter.buy_score > 0.0 && parameter.sell_score > 0.0 {
+        Ok(())
+    } else {
+        Err(ValidationError::new("Scores must be positive"))
+    }
+}
+
+/// Distance of `close` from the band's average, in unit of standard deviation.
+/// 0 if the band has no width
+fn deviation_score(band: &BollingerBandsOutput, close: f64) -> f64 {
+    // The band is built with multiplier 1, so its half width is standard deviation
+    let sd = band.upper - band.average;
+    if sd > 0.0 {
+        (close - band.average) / sd
+    } else {
+        0.0
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BollingerRule {
+    market: Market,
+    parameter: BollingerParameter,
+    market_states: Vec<MarketState>,
+    band_history: IndicatorHistory<BollingerBands, BollingerBandsOutput>,
+}
+
+impl BollingerRule {
+    fn new(market: Market, parameter: BollingerParameter) -> Self {
+        // Parameter holds BollingerBands' constraint by its validation,
+        // so no panic occurs
+        let indicator = BollingerBands::new(parameter.window_size, 1.0).unwrap();
+        let indicator_buffer = IndicatorBuffer::new(indicator, parameter.candlestick_interval());
+        let band_history = IndicatorHistory::new(indicator_buffer);
+
+        Self {
+            market,
+            parameter,
+            market_states: vec![],
+            band_history,
+        }
+    }
+}
+
+impl Rule for BollingerRule {
+    fn name(&self) -> &'static str {
+        "bollinger"
+    }
+
+    fn market(&self) -> Market {
+        self.market.clone()
+    }
+
+    fn duration_requirement(&self) -> Option<Duration> {
+        let b = self.band_history.indicator_buffer();
+        let d = b.interval() * self.parameter.window_size as i32;
+        Some(d)
+    }
+
+    fn candlestick_interval(&self) -> Option<Duration> {
+        Some(self.band_history.indicator_buffer().interval())
+    }
+
+    fn update_market_state(&mut self, mut market_state: MarketState) -> Result<(), RuleError> {
+        if !self.is_correct_market_state(&market_state) {
+            return Err(RuleError::MarketConstraint);
+        }
+
+        // Deny older timestamp data
+        if let Some(last_state) = self.market_states.last() {
+            if last_state.stamp.timestamp >= market_state.stamp.timestamp {
+                return Err(RuleError::StampConstraint);
+            }
+        }
+
+        let price_stamp = market_state.price_stamp();
+
+        self.band_history
+            .next(price_stamp)
+            .map_err(RuleError::Other)?;
+
+        // Drop needless myorder data for band-based speculation
+        market_state
+            .myorders
+            .retain(|m| m.state == OrderState::Opened);
+
+        self.market_states.push(market_state);
+
+        Ok(())
+    }
+
+    fn recommend(&self) -> Box<dyn Recommendation> {
+        let p = self.parameter;
+        let history = self.band_history.history();
+
+        // Recommend only when candlestick is determined just now.
+        // This condition prevents continuous recommendation by launch-by-launch this rule.
+        let (dataitem, band) = match history.last() {
+            Some(Some((dataitem, band))) => (dataitem, band),
+            _ => return Box::from(BollingerRecommendation::Neutral(p)),
+        };
+
+        // Standard deviation of fewer candlesticks than the window is meaningless
+        let determined_count = history.iter().flatten().count();
+        if determined_count < p.window_size {
+            return Box::from(BollingerRecommendation::BandUndetermined(p));
+        }
+
+        let close = dataitem.close();
+        let score = deviation_score(band, close);
+        let recommendation = if score < -p.buy_score {
+            BollingerRecommendation::Buy(score, close, p)
+        } else if score > p.sell_score {
+            BollingerRecommendation::Sell(score, close, p)
+        } else {
+            BollingerRecommendation::Neutral(p)
+        };
+
+        Box::from(recommendation)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BollingerRecommendation {
+    /// score, close
+    Buy(f64, f64, BollingerParameter),
+    /// score, close
+    Sell(f64, f64, BollingerParameter),
+    Neutral(BollingerParameter),
+    BandUndetermined(BollingerParameter),
+}
+
+impl Recommendation for BollingerRecommendation {
+    fn recommendation_type(&self) -> RecommendationType {
+        use BollingerRecommendation::*;
+
+        match self {
+            Buy(..) => RecommendationType::Buy,
+            Sell(..) => RecommendationType::Sell,
+            Neutral(..) => RecommendationType::Neutral,
+            BandUndetermined(..) => RecommendationType::Pending,
+        }
+    }
+
+    fn reason(&self) -> String {
+        use BollingerRecommendation::*;
+
+        let parameter = match self {
+            Buy(_, _, p) | Sell(_, _, p) | Neutral(p) | BandUndetermined(p) => p,
+        };
+        let mut header = format!(
+            "Bollinger({}m {}x): ",
+            parameter.candlestick_interval().num_minutes(),
+            parameter.window_size
+        );
+
+        let description = match self {
+            Buy(score, close, _) | Sell(score, close, _) => {
+                format!("score {} at close {}", score, close)
+            }
+            Neutral(_) => String::from("trigger condition is not satisfied"),
+            BandUndetermined(_) => String::from("undetermined band"),
+        };
+
+        header.push_str(&description);
+        header
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn market() -> Market {
+        Market::new(MarketId::new(1), CurrencyId::new(1), CurrencyId::new(2))
+    }
+
+    fn market_state(hour: u32, minute: u32, price: Amount) -> MarketState {
+        let timestamp = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(hour, minute, 0);
+        let stamp_id = StampId::new((hour * 60 + minute) as i32);
+        let stamp = Stamp::new(stamp_id, timestamp);
+        let price = Price::new(
+            PriceId::new(stamp_id.inner()),
+            market().market_id,
+            stamp_id,
+            price,
+        );
+        MarketState::new(stamp, price, vec![], vec![])
+    }
+
+    fn parameter() -> BollingerParameter {
+        BollingerParameter {
+            candlestick_interval_min: 60,
+            window_size: 3,
+            buy_score: 1.0,
+            sell_score: 1.0,
+        }
+    }
+
+    /// Recommendation after candlesticks of hourly `closes` are determined
+    fn recommend(closes: &[Amount]) -> RecommendationType {
+        let mut rule = BollingerRule::new(market(), parameter());
+        for (hour, &price) in closes.iter().enumerate() {
+            rule.update_market_state(market_state(hour as u32, 0, price))
+                .unwrap();
+        }
+        // Close the last candlestick
+        rule.update_market_state(market_state(closes.len() as u32, 0, 100.0))
+            .unwrap();
+
+        rule.recommend().recommendation_type()
+    }
+
+    #[test]
+    fn test_recommend_undetermined_band() {
+        assert_eq!(RecommendationType::Pending, recommend(&[100.0, 90.0]));
+    }
+
+    #[test]
+    fn test_recommend_buy() {
+        assert_eq!(
+            RecommendationType::Buy,
+            recommend(&[100.0, 102.0, 100.0, 90.0])
+        );
+    }
+
+    #[test]
+    fn test_recommend_sell() {
+        assert_eq!(
+            RecommendationType::Sell,
+            recommend(&[100.0, 98.0, 100.0, 110.0])
+        );
+    }
+
+    #[test]
+    fn test_recommend_neutral() {
+        assert_eq!(
+            RecommendationType::Neutral,
+            recommend(&[100.0, 102.0, 100.0, 101.0])
+        );
+        // No band width
+        assert_eq!(
+            RecommendationType::Neutral,
+            recommend(&[100.0, 100.0, 100.0])
+        );
+    }
+
+    #[test]
+    fn test_recommend_only_when_candlestick_is_determined() {
+        let mut rule = BollingerRule::new(market(), parameter());
+        for (hour, &price) in [100.0, 102.0, 100.0, 90.0, 90.0].iter().enumerate() {
+            rule.update_market_state(market_state(hour as u32, 0, price))
+                .unwrap();
+        }
+        assert_eq!(
+            RecommendationType::Buy,
+            rule.recommend().recommendation_type()
+        );
+
+        rule.update_market_state(market_state(4, 30, 90.0)).unwrap();
+        assert_eq!(
+            RecommendationType::Neutral,
+            rule.recommend().recommendation_type()
+        );
+    }
+
+    #[test]
+    fn test_parameter() {
+        let json = r#"{
+            "algorithm": "bollinger",
+            "candlestickIntervalMin": 30,
+            "windowSize": 20,
+            "buyScore": 2.0,
+            "sellScore": 2.0
+        }"#;
+        let parameter = serde_json::from_str::<Box<dyn RuleParameter>>(json).unwrap();
+        assert!(parameter.validate_parameter().is_ok());
+        assert_eq!(
+            Some(Duration::hours(10)),
+            parameter.create_rule(market()).duration_requirement()
+        );
+
+        let mut invalid = self::parameter();
+        invalid.window_size = 1;
+        assert!(invalid.validate().is_err());
+        let mut invalid = self::parameter();
+        invalid.buy_score = 0.0;
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use super::*;
 use database::custom_sql_type::OrderSide;
 use serde::{Deserialize, Serialize};
-use validator::Validate;
+use validator::{Validate, ValidationErrors};
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Validate)]
 #[serde(rename_all = "camelCase")]
@@ -14,6 +14,10 @@ impl RuleParameter for FixedParameterSerde {
     fn create_rule(&self, market: Market) -> Box<dyn Rule> {
         Box::from(FixedRule::new(market, self.side))
     }
+
+    fn validate_parameter(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -29,6 +33,10 @@ impl FixedRule {
 }
 
 impl Rule for FixedRule {
+    fn name(&self) -> &'static str {
+        "fixed"
+    }
+
     fn market(&self) -> Market {
         self.market.clone()
     }
@@ -0,0 +1,358 @@
+use super::*;
+use crate::indicator::*;
+use anyhow::Result;
+use database::model::*;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use ta::indicators::{
+    MovingAverageConvergenceDivergence, MovingAverageConvergenceDivergenceOutput,
+};
+use validator::{Validate, ValidationError, ValidationErrors};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+#[validate(schema(function = "validate_periods"))]
+pub struct MacdCrossParameter {
+    #[validate(range(min = 1))]
+    candlestick_interval_min: i64,
+    #[validate(range(min = 1))]
+    fast_period: usize,
+    #[validate(range(min = 1))]
+    slow_period: usize,
+    #[validate(range(min = 1))]
+    signal_period: usize,
+    /// Crossings whose histogram is smaller than this in magnitude are ignored
+    #[serde(default)]
+    #[validate(range(min = 0))]
+    min_histogram: Option<f64>,
+}
+
+impl MacdCrossParameter {
+    fn candlestick_interval(&self) -> Duration {
+        Duration::minutes(self.candlestick_interval_min)
+    }
+
+    /// Number of candlesticks until MACD and its signal line settle
+    fn warmup_count(&self) -> usize {
+        self.slow_period + self.signal_period
+    }
+}
+
+#[typetag::serde(name = "macdCross")]
+impl RuleParameter for MacdCrossParameter {
+    fn create_rule(&self, market: Market) -> Box<dyn Rule> {
+        Box::from(MacdCrossRule::new(market, *self))
+    }
+
+    fn validate_parameter(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
+}
+
+fn validate_periods(parameter: &MacdCrossParameter) -> Result<(), ValidationError> {
+    if parameter.fast_period < parameter.slow_period {
+        Ok(())
+    } else {
+        Err(ValidationError::new(
+            "Fast period must be shorter than slow one",
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MacdCrossRule {
+    market: Market,
+    parameter: MacdCrossParameter,
+    market_states: Vec<MarketState>,
+    macd_history: IndicatorHistory<
+        MovingAverageConvergenceDivergence,
+        MovingAverageConvergenceDivergenceOutput,
+    >,
+}
+
+impl MacdCrossRule {
+    fn new(market: Market, parameter: MacdCrossParameter) -> Self {
+        // Parameter holds MACD's constraint by its validation,
+        // so no panic occurs
+        let indicator = MovingAverageConvergenceDivergence::new(
+            parameter.fast_period,
+            parameter.slow_period,
+            parameter.signal_period,
+        )
+        .unwrap();
+        let indicator_buffer = IndicatorBuffer::new(indicator, parameter.candlestick_interval());
+        let macd_history = IndicatorHistory::new(indicator_buffer);
+
+        Self {
+            market,
+            parameter,
+            market_states: vec![],
+            macd_history,
+        }
+    }
+}
+
+impl Rule for MacdCrossRule {
+    fn name(&self) -> &'static str {
+        "macdCross"
+    }
+
+    fn market(&self) -> Market {
+        self.market.clone()
+    }
+
+    fn duration_requirement(&self) -> Option<Duration> {
+        let b = self.macd_history.indicator_buffer();
+        let d = b.interval() * (self.parameter.warmup_count() as i32 + 1);
+        Some(d)
+    }
+
+    fn candlestick_interval(&self) -> Option<Duration> {
+        Some(self.macd_history.indicator_buffer().interval())
+    }
+
+    fn update_market_state(&mut self, mut market_state: MarketState) -> Result<(), RuleError> {
+        if !self.is_correct_market_state(&market_state) {
+            return Err(RuleError::MarketConstraint);
+        }
+
+        // Deny older timestamp data
+        if let Some(last_state) = self.market_states.last() {
+            if last_state.stamp.timestamp >= market_state.stamp.timestamp {
+                return Err(RuleError::StampConstraint);
+            }
+        }
+
+        let price_stamp = market_state.price_stamp();
+
+        self.macd_history
+            .next(price_stamp)
+            .map_err(RuleError::Other)?;
+
+        // Drop needless myorder data for MACD-based speculation
+        market_state
+            .myorders
+            .retain(|m| m.state == OrderState::Opened);
+
+        self.market_states.push(market_state);
+
+        Ok(())
+    }
+
+    fn recommend(&self) -> Box<dyn Recommendation> {
+        let p = self.parameter;
+
+        let (prev, current) = {
+            let outputs = self.macd_history.outputs().collect_vec();
+
+            // Recommend only when candlestick is determined just now.
+            // This condition prevents continuous recommendation by launch-by-launch this rule.
+            if matches!(outputs.last(), Some(None)) {
+                return Box::from(MacdCrossRecommendation::MacdUndetermined(p));
+            }
+
+            let outputs = outputs
+                .into_iter()
+                .flat_map(std::convert::identity)
+                .cloned()
+                .collect_vec();
+            if outputs.len() < p.warmup_count() {
+                return Box::from(MacdCrossRecommendation::MacdUndetermined(p));
+            }
+
+            match outputs.into_iter().tuple_windows().last() {
+                Some((prev, current)) => (prev, current),
+                None => return Box::from(MacdCrossRecommendation::MacdUndetermined(p)),
+            }
+        };
+
+        let is_large_enough = p
+            .min_histogram
+            .map_or(true, |min| current.histogram.abs() >= min);
+
+        let recommendation = if !is_large_enough {
+            MacdCrossRecommendation::Neutral(p)
+        } else if prev.macd <= prev.signal && current.macd > current.signal {
+            MacdCrossRecommendation::Buy(current.macd, current.signal, p)
+        } else if prev.macd >= prev.signal && current.macd < current.signal {
+            MacdCrossRecommendation::Sell(current.macd, current.signal, p)
+        } else {
+            MacdCrossRecommendation::Neutral(p)
+        };
+
+        Box::from(recommendation)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MacdCrossRecommendation {
+    /// MACD crossed above signal. macd, signal
+    Buy(f64, f64, MacdCrossParameter),
+    /// MACD crossed below signal. macd, signal
+    Sell(f64, f64, MacdCrossParameter),
+    Neutral(MacdCrossParameter),
+    MacdUndetermined(MacdCrossParameter),
+}
+
+impl Recommendation for MacdCrossRecommendation {
+    fn recommendation_type(&self) -> RecommendationType {
+        use MacdCrossRecommendation::*;
+
+        match self {
+            Buy(..) => RecommendationType::Buy,
+            Sell(..) => RecommendationType::Sell,
+            Neutral(..) | MacdUndetermined(..) => RecommendationType::Neutral,
+        }
+    }
+
+    fn reason(&self) -> String {
+        use MacdCrossRecommendation::*;
+
+        let parameter = match self {
+            Buy(_, _, p) | Sell(_, _, p) | Neutral(p) | MacdUndetermined(p) => p,
+        };
+        let mut header = format!(
+            "Macd({}m {}/{}/{}): ",
+            parameter.candlestick_interval().num_minutes(),
+            parameter.fast_period,
+            parameter.slow_period,
+            parameter.signal_period
+        );
+
+        let description = match self {
+            Buy(macd, signal, _) => format!("macd {} crossed above signal {}", macd, signal),
+            Sell(macd, signal, _) => format!("macd {} crossed below signal {}", macd, signal),
+            Neutral(_) => String::from("trigger condition is not satisfied"),
+            MacdUndetermined(_) => String::from("undetermined MACD"),
+        };
+
+        header.push_str(&description);
+        header
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn market() -> Market {
+        Market::new(MarketId::new(1), CurrencyId::new(1), CurrencyId::new(2))
+    }
+
+    fn market_state(hour: u32, minute: u32, price: Amount) -> MarketState {
+        let timestamp = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(hour, minute, 0);
+        let stamp_id = StampId::new((hour * 60 + minute) as i32);
+        let stamp = Stamp::new(stamp_id, timestamp);
+        let price = Price::new(
+            PriceId::new(stamp_id.inner()),
+            market().market_id,
+            stamp_id,
+            price,
+        );
+        MarketState::new(stamp, price, vec![], vec![])
+    }
+
+    fn parameter(min_histogram: Option<f64>) -> MacdCrossParameter {
+        MacdCrossParameter {
+            candlestick_interval_min: 60,
+            fast_period: 2,
+            slow_period: 4,
+            signal_period: 2,
+            min_histogram,
+        }
+    }
+
+    /// Hourly closes rising, falling, then rising again
+    const PRICES: [Amount; 14] = [
+        100.0, 101.0, 102.0, 103.0, 104.0, 105.0, 104.0, 102.0, 100.0, 99.0, 100.0, 102.0, 105.0,
+        106.0,
+    ];
+
+    /// Push a state at every hour, and recommend after each push
+    fn recommendations(parameter: MacdCrossParameter) -> Vec<RecommendationType> {
+        let mut rule = parameter.create_rule(market());
+
+        // The last state closes the last candlestick
+        PRICES
+            .iter()
+            .chain(std::iter::once(&106.0))
+            .enumerate()
+            .map(|(hour, &price)| {
+                rule.update_market_state(market_state(hour as u32, 0, price))
+                    .unwrap();
+                rule.recommend().recommendation_type()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_recommend() {
+        use RecommendationType::*;
+
+        let recommendations = recommendations(parameter(None));
+
+        // No recommendation until the slow and signal averages are filled
+        let expected = vec![
+            Neutral, Neutral, Neutral, Neutral, Neutral, Neutral, Neutral, Sell, Neutral, Neutral,
+            Neutral, Buy, Neutral, Neutral, Neutral,
+        ];
+        assert_eq!(expected, recommendations);
+    }
+
+    #[test]
+    fn test_recommend_min_histogram() {
+        use RecommendationType::*;
+
+        // The sell crossing has histogram of about -0.147, and the buy one about 0.127
+        let recommendations = recommendations(parameter(Some(0.13)));
+
+        assert_eq!(1, recommendations.iter().filter(|r| **r == Sell).count());
+        assert!(!recommendations.contains(&Buy));
+    }
+
+    #[test]
+    fn test_recommend_only_when_candlestick_is_determined() {
+        let mut rule = MacdCrossRule::new(market(), parameter(None));
+        for (hour, &price) in PRICES.iter().take(8).enumerate() {
+            rule.update_market_state(market_state(hour as u32, 0, price))
+                .unwrap();
+        }
+        let recommendation = rule.recommend();
+        assert_eq!(
+            RecommendationType::Sell,
+            recommendation.recommendation_type()
+        );
+        assert!(recommendation
+            .reason()
+            .starts_with("Macd(60m 2/4/2): macd "));
+
+        // The candlestick of hour 7 is in progress
+        rule.update_market_state(market_state(7, 30, 101.0))
+            .unwrap();
+        assert_eq!(
+            RecommendationType::Neutral,
+            rule.recommend().recommendation_type()
+        );
+    }
+
+    #[test]
+    fn test_parameter() {
+        let json = r#"{
+            "algorithm": "macdCross",
+            "candlestickIntervalMin": 60,
+            "fastPeriod": 12,
+            "slowPeriod": 26,
+            "signalPeriod": 9
+        }"#;
+        let parameter = serde_json::from_str::<Box<dyn RuleParameter>>(json).unwrap();
+        assert!(parameter.validate_parameter().is_ok());
+
+        let rule = parameter.create_rule(market());
+        assert_eq!("macdCross", rule.name());
+        assert_eq!(Some(Duration::hours(36)), rule.duration_requirement());
+
+        let mut invalid = self::parameter(None);
+        invalid.fast_period = 4;
+        assert!(invalid.validate().is_err());
+    }
+}
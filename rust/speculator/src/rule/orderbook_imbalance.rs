@@ -0,0 +1,405 @@
+use super::*;
+use crate::Timestamp;
+use database::model::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+#[validate(schema(function = "validate_triggers"))]
+pub struct OrderbookImbalanceParameter {
+    /// Number of the best price levels of each side summed up
+    #[validate(range(min = 1))]
+    depth_levels: usize,
+    /// Buy if the smoothed imbalance is higher than this
+    #[validate(range(min = 0, max = 1))]
+    buy_imbalance_trigger: f64,
+    /// Sell if the smoothed imbalance is lower than this
+    #[validate(range(min = 0, max = 1))]
+    sell_imbalance_trigger: f64,
+    /// Number of the latest market states whose imbalances are averaged
+    #[validate(range(min = 1))]
+    smoothing_window: usize,
+}
+
+#[typetag::serde(name = "orderbookImbalance")]
+impl RuleParameter for OrderbookImbalanceParameter {
+    fn create_rule(&self, market: Market) -> Box<dyn Rule> {
+        Box::from(OrderbookImbalanceRule::new(market, *self))
+    }
+
+    fn validate_parameter(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
+}
+
+fn validate_triggers(parameter: &OrderbookImbalanceParameter) -> Result<(), ValidationError> {
+    if parameter.sell_imbalance_trigger <= parameter.buy_imbalance_trigger {
+        Ok(())
+    } else {
+        Err(ValidationError::new(
+            "Sell trigger must not exceed buy trigger",
+        ))
+    }
+}
+
+/// Bid volume / (bid + ask volume) over the best `depth_levels` levels of each side.
+/// Orderbooks of NaN or infinite price or volume, or negative volume, are ignored.
+/// # Returns
+/// `None` if no valid volume is left, like without orderbooks
+fn imbalance(orderbooks: &[Orderbook], depth_levels: usize) -> Option<f64> {
+    let side_volume = |side: OrderSide| {
+        let mut levels = orderbooks
+            .iter()
+            .filter(|o| o.side == side)
+            .filter(|o| o.price.is_finite() && o.volume.is_finite() && o.volume >= 0.0)
+            .collect::<Vec<_>>();
+        // Best bid is the highest, and best ask is the lowest.
+        // Prices are finite, so no panic occurs below unwrap()
+        levels.sort_by(|a, b| match side {
+            OrderSide::Buy => b.price.partial_cmp(&a.price).unwrap(),
+            OrderSide::Sell => a.price.partial_cmp(&b.price).unwrap(),
+        });
+        levels
+            .into_iter()
+            .take(depth_levels)
+            .map(|o| o.volume)
+            .sum::<f64>()
+    };
+
+    let bid = side_volume(OrderSide::Buy);
+    let ask = side_volume(OrderSide::Sell);
+    if bid + ask > 0.0 {
+        Some(bid / (bid + ask))
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+struct OrderbookImbalanceRule {
+    market: Market,
+    parameter: OrderbookImbalanceParameter,
+    last_timestamp: Option<Timestamp>,
+    /// Imbalances of the latest `smoothing_window` market states, `None` for states without orderbooks
+    imbalances: VecDeque<Option<f64>>,
+}
+
+impl OrderbookImbalanceRule {
+    fn new(market: Market, parameter: OrderbookImbalanceParameter) -> Self {
+        Self {
+            market,
+            parameter,
+            last_timestamp: None,
+            imbalances: VecDeque::with_capacity(parameter.smoothing_window),
+        }
+    }
+}
+
+impl Rule for OrderbookImbalanceRule {
+    fn name(&self) -> &'static str {
+        "orderbookImbalance"
+    }
+
+    fn market(&self) -> Market {
+        self.market.clone()
+    }
+
+    /// Imbalances are averaged over market states rather than duration. See `stamp_count_requirement`
+    fn duration_requirement(&self) -> Option<Duration> {
+        None
+    }
+
+    /// The latest `smoothing_window` market states are averaged
+    fn stamp_count_requirement(&self) -> usize {
+        self.parameter.smoothing_window
+    }
+
+    fn update_market_state(&mut self, market_state: MarketState) -> Result<(), RuleError> {
+        if !self.is_correct_market_state(&market_state) {
+            return Err(RuleError::MarketConstraint);
+        }
+
+        // Deny older timestamp data
+        if let Some(last_timestamp) = self.last_timestamp {
+            if last_timestamp >= market_state.stamp.timestamp {
+                return Err(RuleError::StampConstraint);
+            }
+        }
+        self.last_timestamp = Some(market_state.stamp.timestamp);
+
+        if self.imbalances.len() == self.parameter.smoothing_window {
+            self.imbalances.pop_front();
+        }
+        self.imbalances.push_back(imbalance(
+            &market_state.orderbooks,
+            self.parameter.depth_levels,
+        ));
+
+        Ok(())
+    }
+
+    fn recommend(&self) -> Box<dyn Recommendation> {
+        let p = self.parameter;
+
+        let imbalances = self.imbalances.iter().flatten().collect::<Vec<_>>();
+        if imbalances.len() < p.smoothing_window {
+            return Box::from(OrderbookImbalanceRecommendation::Undetermined(
+                imbalances.len(),
+                p,
+            ));
+        }
+
+        let mean = imbalances.into_iter().sum::<f64>() / p.smoothing_window as f64;
+        let recommendation = if mean > p.buy_imbalance_trigger {
+            OrderbookImbalanceRecommendation::Buy(mean, p)
+        } else if mean < p.sell_imbalance_trigger {
+            OrderbookImbalanceRecommendation::Sell(mean, p)
+        } else {
+            OrderbookImbalanceRecommendation::Neutral(mean, p)
+        };
+
+        Box::from(recommendation)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderbookImbalanceRecommendation {
+    /// Smoothed imbalance
+    Buy(f64, OrderbookImbalanceParameter),
+    /// Smoothed imbalance
+    Sell(f64, OrderbookImbalanceParameter),
+    /// Smoothed imbalance
+    Neutral(f64, OrderbookImbalanceParameter),
+    /// Number of states having orderbooks within the window
+    Undetermined(usize, OrderbookImbalanceParameter),
+}
+
+impl Recommendation for OrderbookImbalanceRecommendation {
+    fn recommendation_type(&self) -> RecommendationType {
+        use OrderbookImbalanceRecommendation::*;
+
+        match self {
+            Buy(..) => RecommendationType::Buy,
+            Sell(..) => RecommendationType::Sell,
+            Neutral(..) => RecommendationType::Neutral,
+            Undetermined(..) => RecommendationType::Pending,
+        }
+    }
+
+    fn reason(&self) -> String {
+        use OrderbookImbalanceRecommendation::*;
+
+        let parameter = match self {
+            Buy(_, p) | Sell(_, p) | Neutral(_, p) | Undetermined(_, p) => p,
+        };
+        let mut header = format!(
+            "OrderbookImbalance({} levels {}x): ",
+            parameter.depth_levels, parameter.smoothing_window
+        );
+
+        let description = match self {
+            Buy(imbalance, _) | Sell(imbalance, _) | Neutral(imbalance, _) => {
+                format!(
+                    "imbalance {} against buy trigger {}, sell trigger {}",
+                    imbalance, parameter.buy_imbalance_trigger, parameter.sell_imbalance_trigger
+                )
+            }
+            Undetermined(count, _) => format!(
+                "only {} of {} states have orderbooks",
+                count, parameter.smoothing_window
+            ),
+        };
+
+        header.push_str(&description);
+        header
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn market() -> Market {
+        Market::new(MarketId::new(1), CurrencyId::new(1), CurrencyId::new(2))
+    }
+
+    /// Orderbooks of `(side, price, volume)` at `minute`
+    fn market_state(minute: u32, orderbooks: &[(OrderSide, Amount, Amount)]) -> MarketState {
+        let timestamp = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(0, minute, 0);
+        let stamp_id = StampId::new(minute as i32);
+        let stamp = Stamp::new(stamp_id, timestamp);
+        let price = Price::new(
+            PriceId::new(minute as i32),
+            market().market_id,
+            stamp_id,
+            100.0,
+        );
+        let orderbooks = orderbooks
+            .iter()
+            .enumerate()
+            .map(|(i, &(side, price, volume))| Orderbook {
+                orderbook_id: OrderbookId::new(i as i32),
+                market_id: market().market_id,
+                stamp_id,
+                side,
+                price,
+                volume,
+            })
+            .collect();
+        MarketState::new(stamp, price, orderbooks, vec![])
+    }
+
+    fn parameter() -> OrderbookImbalanceParameter {
+        OrderbookImbalanceParameter {
+            depth_levels: 2,
+            buy_imbalance_trigger: 0.6,
+            sell_imbalance_trigger: 0.4,
+            smoothing_window: 2,
+        }
+    }
+
+    /// Bids of `bid` and asks of `ask` in total on the best 2 levels, with large volumes beyond them
+    fn book(bid: Amount, ask: Amount) -> Vec<(OrderSide, Amount, Amount)> {
+        vec![
+            (OrderSide::Buy, 99.0, bid / 2.0),
+            (OrderSide::Buy, 98.0, bid / 2.0),
+            (OrderSide::Buy, 90.0, 1000.0),
+            (OrderSide::Sell, 101.0, ask / 2.0),
+            (OrderSide::Sell, 102.0, ask / 2.0),
+            (OrderSide::Sell, 110.0, 1000.0),
+        ]
+    }
+
+    fn recommend(books: &[Vec<(OrderSide, Amount, Amount)>]) -> RecommendationType {
+        let mut rule = OrderbookImbalanceRule::new(market(), parameter());
+        for (minute, book) in books.iter().enumerate() {
+            rule.update_market_state(market_state(minute as u32, book))
+                .unwrap();
+        }
+        rule.recommend().recommendation_type()
+    }
+
+    #[test]
+    fn test_imbalance() {
+        let state = market_state(0, &book(3.0, 1.0));
+        assert_eq!(Some(0.75), imbalance(&state.orderbooks, 2));
+
+        // Levels are sorted by price, so the best ones are used regardless of the order
+        let mut orderbooks = state.orderbooks.clone();
+        orderbooks.reverse();
+        assert_eq!(Some(0.75), imbalance(&orderbooks, 2));
+
+        // Deeper levels are included
+        let expected = 1003.0 / 2004.0;
+        assert_eq!(Some(expected), imbalance(&state.orderbooks, 3));
+    }
+
+    #[test]
+    fn test_imbalance_invalid_orderbooks() {
+        let mut orderbooks = book(3.0, 1.0);
+        orderbooks.push((OrderSide::Buy, f64::NAN, 100.0));
+        orderbooks.push((OrderSide::Buy, 100.0, f64::NAN));
+        orderbooks.push((OrderSide::Sell, 100.5, -1.0));
+        let state = market_state(0, &orderbooks);
+
+        assert_eq!(Some(0.75), imbalance(&state.orderbooks, 2));
+    }
+
+    #[test]
+    fn test_imbalance_empty_book() {
+        assert_eq!(None, imbalance(&[], 2));
+        let state = market_state(0, &book(0.0, 0.0));
+        assert_eq!(None, imbalance(&state.orderbooks, 2));
+    }
+
+    #[test]
+    fn test_recommend_buy() {
+        assert_eq!(
+            RecommendationType::Buy,
+            recommend(&[book(3.0, 1.0), book(2.0, 1.0)])
+        );
+    }
+
+    #[test]
+    fn test_recommend_sell() {
+        assert_eq!(
+            RecommendationType::Sell,
+            recommend(&[book(1.0, 3.0), book(1.0, 2.0)])
+        );
+    }
+
+    #[test]
+    fn test_recommend_neutral() {
+        // Mean of 0.75 and 0.25
+        assert_eq!(
+            RecommendationType::Neutral,
+            recommend(&[book(3.0, 1.0), book(1.0, 3.0)])
+        );
+    }
+
+    #[test]
+    fn test_recommend_smoothed_over_window() {
+        // The oldest state drops out of the window
+        assert_eq!(
+            RecommendationType::Sell,
+            recommend(&[book(3.0, 1.0), book(1.0, 3.0), book(1.0, 3.0)])
+        );
+    }
+
+    #[test]
+    fn test_recommend_empty_book() {
+        assert_eq!(RecommendationType::Pending, recommend(&[]));
+        assert_eq!(RecommendationType::Pending, recommend(&[book(3.0, 1.0)]));
+        // A state without orderbooks in the window
+        assert_eq!(
+            RecommendationType::Pending,
+            recommend(&[book(3.0, 1.0), vec![], book(3.0, 1.0)])
+        );
+        assert_eq!(
+            RecommendationType::Buy,
+            recommend(&[vec![], book(3.0, 1.0), book(3.0, 1.0)])
+        );
+    }
+
+    #[test]
+    fn test_update_older_state() {
+        let mut rule = OrderbookImbalanceRule::new(market(), parameter());
+        rule.update_market_state(market_state(1, &book(1.0, 1.0)))
+            .unwrap();
+
+        let ret = rule.update_market_state(market_state(1, &book(1.0, 1.0)));
+        assert!(matches!(ret, Err(RuleError::StampConstraint)));
+    }
+
+    #[test]
+    fn test_parameter() {
+        let json = r#"{
+            "algorithm": "orderbookImbalance",
+            "depthLevels": 5,
+            "buyImbalanceTrigger": 0.65,
+            "sellImbalanceTrigger": 0.35,
+            "smoothingWindow": 3
+        }"#;
+        let parameter = serde_json::from_str::<Box<dyn RuleParameter>>(json).unwrap();
+        assert!(parameter.validate_parameter().is_ok());
+        let rule = parameter.create_rule(market());
+        assert_eq!("orderbookImbalance", rule.name());
+        assert_eq!(None, rule.duration_requirement());
+        assert_eq!(3, rule.stamp_count_requirement());
+
+        let mut invalid = self::parameter();
+        invalid.depth_levels = 0;
+        assert!(invalid.validate().is_err());
+        let mut invalid = self::parameter();
+        invalid.smoothing_window = 0;
+        assert!(invalid.validate().is_err());
+        let mut invalid = self::parameter();
+        invalid.buy_imbalance_trigger = 1.5;
+        assert!(invalid.validate().is_err());
+        let mut invalid = self::parameter();
+        invalid.sell_imbalance_trigger = 0.7;
+        assert!(invalid.validate().is_err());
+    }
+}
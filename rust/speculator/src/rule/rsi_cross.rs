@@ -1,16 +1,19 @@
 use super::*;
 use crate::indicator::*;
+use crate::rsi::*;
+use crate::Timestamp;
 use anyhow::Result;
 use database::model::*;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use ta::{indicators::RelativeStrengthIndex, Period};
-use validator::Validate;
+use ta::Period;
+use validator::{Validate, ValidationErrors};
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Validate)]
 #[serde(rename_all = "camelCase")]
 pub struct RsiCrossParameter {
     #[validate(range(min = 1))]
+    #[serde(alias = "candlestickTimespanMin")]
     candlestick_interval_min: i64,
     #[validate(range(min = 1))]
     candlestick_count: usize,
@@ -22,6 +25,32 @@ pub struct RsiCrossParameter {
     upper_pending_trigger: f64,
     #[validate(range(min = 0, max = 100))]
     lower_pending_trigger: f64,
+    /// Number of determined candlesticks after buy/sell recommendation, during which buy/sell is not recommended.
+    /// `cooldownCandlesticks` in rule JSON, 0 by default
+    #[serde(default)]
+    cooldown_candlesticks: usize,
+    /// Treatment of intervals without any price, like `"fillForward"` or `{"resetOnGap": 2}`.
+    /// `gapPolicy` in rule JSON, `"skipGaps"` by default
+    #[serde(default)]
+    gap_policy: GapPolicy,
+    /// Averaging of gains and losses, `"exponential"`, `"simple"` or `"wilder"`.
+    /// `method` in rule JSON, `"exponential"` by default
+    #[serde(default)]
+    method: RsiMethod,
+    /// If true, the latest RSI includes the candlestick partially built in the open interval,
+    /// so that price action within the interval is recommended before the interval finishes.
+    /// `useProvisionalCandle` in rule JSON, false by default
+    #[serde(default)]
+    use_provisional_candle: bool,
+    /// Beginning of an interval like `"2021-01-01T00:00:00"`, to which candlesticks are aligned.
+    /// `alignmentOrigin` in rule JSON, UNIX epoch by default
+    #[serde(default)]
+    alignment_origin: Option<Timestamp>,
+    /// If true, the first candlestick is dropped when the rule started mid-interval,
+    /// since it lacks prices before the start.
+    /// `discardFirstIncomplete` in rule JSON, false by default
+    #[serde(default)]
+    discard_first_incomplete: bool,
 }
 
 impl RsiCrossParameter {
@@ -35,76 +64,55 @@ impl RuleParameter for RsiCrossParameter {
     fn create_rule(&self, market: Market) -> Box<dyn Rule> {
         Box::from(RsiCrossRule::new(market, *self))
     }
+
+    fn validate_parameter(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
 }
 
 #[derive(Debug, Clone)]
 struct RsiCrossRule {
     market: Market,
     parameter: RsiCrossParameter,
-    market_states: Vec<MarketState>,
-    rsi_history: IndicatorHistory<RelativeStrengthIndex, f64>,
+    /// Only the last state is kept, since older ones are never read
+    last_market_state: Option<MarketState>,
+    rsi_history: IndicatorHistory<Rsi, f64>,
+    cooldown: Cooldown,
+    /// Whether buy/sell is recommended on the provisional candlestick of the open interval.
+    /// The interval starts cooldown when it is determined
+    provisional_fired: bool,
 }
 
 impl RsiCrossRule {
     fn new(market: Market, parameter: RsiCrossParameter) -> Self {
         // Parameter holds RsiHistory's constraint by RsiCrossParameter::new(),
         // so no panic occurs
-        let indicator = RelativeStrengthIndex::new(parameter.candlestick_count).unwrap();
-        let indicator_buffer = IndicatorBuffer::new(indicator, parameter.candlestick_interval());
-        let rsi_history = IndicatorHistory::new(indicator_buffer);
+        let indicator = Rsi::new(parameter.method, parameter.candlestick_count).unwrap();
+        let indicator_buffer = IndicatorBuffer::new(indicator, parameter.candlestick_interval())
+            .with_gap_policy(parameter.gap_policy)
+            .with_discard_first_incomplete(parameter.discard_first_incomplete);
+        let indicator_buffer = match parameter.alignment_origin {
+            Some(origin) => indicator_buffer.with_origin(origin),
+            None => indicator_buffer,
+        };
+        // Only the last two RSIs are read
+        let rsi_history = IndicatorHistory::with_capacity_limit(
+            indicator_buffer,
+            parameter.candlestick_count + 1,
+        );
 
         Self {
             market,
             parameter,
-            market_states: vec![],
+            last_market_state: None,
             rsi_history,
+            cooldown: Cooldown::new(parameter.cooldown_candlesticks),
+            provisional_fired: false,
         }
     }
-}
-
-impl Rule for RsiCrossRule {
-    fn market(&self) -> Market {
-        self.market.clone()
-    }
-
-    fn duration_requirement(&self) -> Option<Duration> {
-        let b = self.rsi_history.indicator_buffer();
-        let d = b.interval() * (b.indicator().period() as i32 + 1);
-        Some(d)
-    }
 
-    fn update_market_state(&mut self, mut market_state: MarketState) -> Result<(), RuleError> {
-        if !self.is_correct_market_state(&market_state) {
-            return Err(RuleError::MarketConstraint);
-        }
-
-        // Deny older timestamp data
-        if let Some(last_state) = self.market_states.last() {
-            if last_state.stamp.timestamp >= market_state.stamp.timestamp {
-                return Err(RuleError::StampConstraint);
-            }
-        }
-
-        let price_stamp = PriceStamp::new(
-            market_state.stamp.timestamp,
-            market_state.price.amount as f64,
-        );
-
-        self.rsi_history
-            .next(price_stamp)
-            .map_err(RuleError::Other)?;
-
-        // Drop needless myorder data for RSI-based speculation
-        market_state
-            .myorders
-            .retain(|m| m.state == OrderState::Opened);
-
-        self.market_states.push(market_state);
-
-        Ok(())
-    }
-
-    fn recommend(&self) -> Box<dyn Recommendation> {
+    /// Recommendation by RSI crossing between determined candlesticks, without cooldown
+    fn signal(&self) -> RsiCrossRecommendation {
         let p = self.parameter;
 
         //
@@ -114,7 +122,7 @@ impl Rule for RsiCrossRule {
             // Recommend only when candlestick is determined just now.
             // This condition prevents continuous recommendation by launch-by-launch this rule.
             if matches!(rsis.last(), Some(None)) {
-                return Box::from(RsiCrossRecommendation::RsiUndetermined(p));
+                return RsiCrossRecommendation::RsiUndetermined(p);
             }
 
             match rsis
@@ -125,11 +133,35 @@ impl Rule for RsiCrossRule {
                 .last()
             {
                 Some((prev, current)) => (prev, current),
-                None => return Box::from(RsiCrossRecommendation::RsiUndetermined(p)),
+                None => return RsiCrossRecommendation::RsiUndetermined(p),
             }
         };
 
-        let recommendation = match (prev, current) {
+        self.cross(prev, current)
+    }
+
+    /// Recommendation by RSI crossing from the last determined candlestick to the provisional one
+    /// of the open interval, without cooldown.
+    /// RSI indicator is cloned for the provisional candlestick, so the state of this rule is untouched
+    fn provisional_signal(&self) -> RsiCrossRecommendation {
+        let p = self.parameter;
+
+        let prev = match self.rsi_history.outputs().flatten().last() {
+            Some(&prev) => prev,
+            None => return RsiCrossRecommendation::RsiUndetermined(p),
+        };
+        let current = match self.rsi_history.provisional_output() {
+            Some((_, current)) => current,
+            None => return RsiCrossRecommendation::RsiUndetermined(p),
+        };
+
+        self.cross(prev, current)
+    }
+
+    fn cross(&self, prev: f64, current: f64) -> RsiCrossRecommendation {
+        let p = self.parameter;
+
+        match (prev, current) {
             (_, current) if current > p.upper_pending_trigger => {
                 RsiCrossRecommendation::Pending(current, p)
             }
@@ -143,9 +175,110 @@ impl Rule for RsiCrossRule {
                 RsiCrossRecommendation::Sell(prev, current, p)
             }
             _ => RsiCrossRecommendation::Neutral(p),
+        }
+    }
+
+    /// Whether the open interval is in cooldown, where provisional buy/sell is suppressed
+    fn is_provisional_suppressed(&self) -> bool {
+        self.cooldown.remaining() > 0
+    }
+
+    /// Recommendation by RSI crossing, suppressing buy/sell in cooldown
+    fn recommendation(&self) -> RsiCrossRecommendation {
+        // Remaining candlesticks are counted after the suppressed one, either determined or open
+        let (signal, suppressing, remaining) = if self.parameter.use_provisional_candle {
+            let suppressing = self.is_provisional_suppressed();
+            let remaining = self.cooldown.remaining().saturating_sub(1);
+            (self.provisional_signal(), suppressing, remaining)
+        } else {
+            let suppressing = self.cooldown.is_suppressing();
+            (self.signal(), suppressing, self.cooldown.remaining())
         };
 
-        Box::from(recommendation)
+        match signal {
+            RsiCrossRecommendation::Buy(..) | RsiCrossRecommendation::Sell(..) if suppressing => {
+                RsiCrossRecommendation::CoolingDown(remaining, self.parameter)
+            }
+            recommendation => recommendation,
+        }
+    }
+}
+
+impl Rule for RsiCrossRule {
+    fn name(&self) -> &'static str {
+        "rsiCross"
+    }
+
+    fn market(&self) -> Market {
+        self.market.clone()
+    }
+
+    fn duration_requirement(&self) -> Option<Duration> {
+        let b = self.rsi_history.indicator_buffer();
+        // Candlesticks in cooldown are also loaded to restore it,
+        // and so is the incomplete one to be discarded
+        let count = b.indicator().period()
+            + 1
+            + self.parameter.cooldown_candlesticks
+            + b.discard_first_incomplete() as usize;
+        let d = b.interval() * count as i32;
+        Some(d)
+    }
+
+    fn candlestick_interval(&self) -> Option<Duration> {
+        Some(self.rsi_history.indicator_buffer().interval())
+    }
+
+    fn update_market_state(&mut self, mut market_state: MarketState) -> Result<(), RuleError> {
+        if !self.is_correct_market_state(&market_state) {
+            return Err(RuleError::MarketConstraint);
+        }
+
+        // Deny older timestamp data
+        if let Some(last_state) = self.last_market_state.as_ref() {
+            if last_state.stamp.timestamp >= market_state.stamp.timestamp {
+                return Err(RuleError::StampConstraint);
+            }
+        }
+
+        let price_stamp = market_state.price_stamp();
+
+        let determined = self
+            .rsi_history
+            .next(price_stamp)
+            .map_err(RuleError::Other)?
+            .is_some();
+        // Cooldown is fed by the signal actually recommended
+        if self.parameter.use_provisional_candle {
+            if determined {
+                self.cooldown.next(self.provisional_fired);
+                self.provisional_fired = false;
+            }
+            let fires = matches!(
+                self.provisional_signal(),
+                RsiCrossRecommendation::Buy(..) | RsiCrossRecommendation::Sell(..)
+            );
+            self.provisional_fired |= fires && !self.is_provisional_suppressed();
+        } else if determined {
+            let fires = matches!(
+                self.signal(),
+                RsiCrossRecommendation::Buy(..) | RsiCrossRecommendation::Sell(..)
+            );
+            self.cooldown.next(fires);
+        }
+
+        // Drop needless myorder data for RSI-based speculation
+        market_state
+            .myorders
+            .retain(|m| m.state == OrderState::Opened);
+
+        self.last_market_state = Some(market_state);
+
+        Ok(())
+    }
+
+    fn recommend(&self) -> Box<dyn Recommendation> {
+        Box::from(self.recommendation())
     }
 }
 
@@ -156,6 +289,8 @@ pub enum RsiCrossRecommendation {
     Pending(f64, RsiCrossParameter),
     Neutral(RsiCrossParameter),
     RsiUndetermined(RsiCrossParameter),
+    /// Buy/sell is suppressed. Remaining candlesticks in cooldown
+    CoolingDown(usize, RsiCrossParameter),
 }
 
 impl Recommendation for RsiCrossRecommendation {
@@ -166,7 +301,7 @@ impl Recommendation for RsiCrossRecommendation {
             Buy(..) => RecommendationType::Buy,
             Sell(..) => RecommendationType::Sell,
             Pending(..) => RecommendationType::Pending,
-            Neutral(..) | RsiUndetermined(..) => RecommendationType::Neutral,
+            Neutral(..) | RsiUndetermined(..) | CoolingDown(..) => RecommendationType::Neutral,
         }
     }
 
@@ -174,12 +309,23 @@ impl Recommendation for RsiCrossRecommendation {
         use RsiCrossRecommendation::*;
 
         let parameter = match self {
-            Buy(_, _, p) | Sell(_, _, p) | Pending(_, p) | Neutral(p) | RsiUndetermined(p) => p,
+            Buy(_, _, p)
+            | Sell(_, _, p)
+            | Pending(_, p)
+            | Neutral(p)
+            | RsiUndetermined(p)
+            | CoolingDown(_, p) => p,
         };
         let mut header = format!(
-            "Rsi({}m {}x): ",
+            "Rsi({}m {}x {}{}): ",
             parameter.candlestick_interval().num_minutes(),
-            parameter.candlestick_count
+            parameter.candlestick_count,
+            parameter.method.name(),
+            if parameter.use_provisional_candle {
+                " provisional"
+            } else {
+                ""
+            }
         );
 
         let description = match self {
@@ -189,9 +335,325 @@ impl Recommendation for RsiCrossRecommendation {
             Pending(current, _) => format!("{}", current),
             Neutral(_) => String::from("trigger condition is not satisfied"),
             RsiUndetermined(_) => String::from("undetermined RSI"),
+            CoolingDown(remaining, _) => format!(
+                "cooling down after the last signal, {} candlesticks left",
+                remaining
+            ),
         };
 
         header.push_str(&description);
         header
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn market() -> Market {
+        Market::new(MarketId::new(1), CurrencyId::new(1), CurrencyId::new(2))
+    }
+
+    fn market_state(hour: u32, price: Amount) -> MarketState {
+        let timestamp = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(hour, 0, 0);
+        let stamp_id = StampId::new(hour as i32);
+        let stamp = Stamp::new(stamp_id, timestamp);
+        let price = Price::new(
+            PriceId::new(hour as i32),
+            market().market_id,
+            stamp_id,
+            price,
+        );
+        MarketState::new(stamp, price, vec![], vec![])
+    }
+
+    /// RSI crosses 50 on every candlestick, and pending is never recommended
+    fn parameter(cooldown_candlesticks: usize) -> RsiCrossParameter {
+        RsiCrossParameter {
+            candlestick_interval_min: 60,
+            candlestick_count: 2,
+            buy_trigger: 50.0,
+            sell_trigger: 50.0,
+            upper_pending_trigger: 100.0,
+            lower_pending_trigger: 0.0,
+            cooldown_candlesticks,
+            gap_policy: GapPolicy::SkipGaps,
+            method: RsiMethod::Exponential,
+            use_provisional_candle: false,
+            alignment_origin: None,
+            discard_first_incomplete: false,
+        }
+    }
+
+    /// Push a state at every hour with zigzag prices, and recommend after each push
+    fn recommendations(parameter: RsiCrossParameter) -> Vec<RecommendationType> {
+        let mut rule = parameter.create_rule(market());
+
+        (0..13)
+            .map(|hour| {
+                let price = if hour % 2 == 0 { 100.0 } else { 110.0 };
+                rule.update_market_state(market_state(hour, price)).unwrap();
+                rule.recommend().recommendation_type()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_recommend_without_cooldown() {
+        use RecommendationType::*;
+
+        let recommendations = recommendations(parameter(0));
+
+        let expected = vec![
+            Neutral, Neutral, Neutral, Sell, Buy, Sell, Buy, Sell, Buy, Sell, Buy, Sell, Buy,
+        ];
+        assert_eq!(expected, recommendations);
+    }
+
+    #[test]
+    fn test_recommend_with_cooldown() {
+        use RecommendationType::*;
+
+        let recommendations = recommendations(parameter(2));
+
+        // Two candlesticks are suppressed after each signal
+        let expected = vec![
+            Neutral, Neutral, Neutral, Sell, Neutral, Neutral, Buy, Neutral, Neutral, Sell,
+            Neutral, Neutral, Buy,
+        ];
+        assert_eq!(expected, recommendations);
+    }
+
+    #[test]
+    fn test_recommend_cooling_down_reason() {
+        let mut rule = RsiCrossRule::new(market(), parameter(2));
+        for hour in 0..5 {
+            let price = if hour % 2 == 0 { 100.0 } else { 110.0 };
+            rule.update_market_state(market_state(hour, price)).unwrap();
+        }
+
+        // Buy is suppressed on the first candlestick after sell
+        assert!(matches!(rule.signal(), RsiCrossRecommendation::Buy(..)));
+        let recommendation = rule.recommendation();
+        assert_eq!(
+            RsiCrossRecommendation::CoolingDown(1, parameter(2)),
+            recommendation
+        );
+        assert_eq!(
+            "Rsi(60m 2x exponential): cooling down after the last signal, 1 candlesticks left",
+            recommendation.reason()
+        );
+    }
+
+    #[test]
+    fn test_memory_bounded() {
+        let mut rule = RsiCrossRule::new(market(), parameter(0));
+        let origin = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 0, 0);
+
+        for i in 0..10000 {
+            let mut state = market_state(0, if i % 2 == 0 { 100.0 } else { 110.0 });
+            state.stamp.timestamp = origin + Duration::hours(i);
+            rule.update_market_state(state).unwrap();
+        }
+
+        // RSIs of the last 2 + 1 candlesticks are retained, which still cross on the last drop
+        assert_eq!(3, rule.rsi_history.history().len());
+        assert_eq!(3, rule.rsi_history.outputs().flatten().count());
+        assert!(matches!(
+            rule.recommendation(),
+            RsiCrossRecommendation::Sell(..)
+        ));
+    }
+
+    #[test]
+    fn test_recommend_by_method() {
+        use RecommendationType::*;
+
+        let parameter = RsiCrossParameter {
+            method: RsiMethod::Wilder,
+            ..parameter(0)
+        };
+
+        // Wilder's RSI of zigzag closes: 50, 100, 50, 75, 37.5, 68.75, ..
+        let recommendations = recommendations(parameter);
+        let expected = vec![Neutral, Neutral, Neutral, Sell, Neutral, Sell, Buy];
+        assert_eq!(expected, recommendations[..7].to_vec());
+
+        let reason = RsiCrossRecommendation::Neutral(parameter).reason();
+        assert_eq!(
+            "Rsi(60m 2x wilder): trigger condition is not satisfied",
+            reason
+        );
+    }
+
+    fn market_state_at(hour: u32, minute: u32, price: Amount) -> MarketState {
+        let mut state = market_state(hour, price);
+        state.stamp.timestamp = state.stamp.timestamp + Duration::minutes(minute as i64);
+        state
+    }
+
+    #[test]
+    fn test_recommend_provisional() {
+        let parameter = RsiCrossParameter {
+            use_provisional_candle: true,
+            ..parameter(0)
+        };
+        let mut rule = RsiCrossRule::new(market(), parameter);
+        let mut control = RsiCrossRule::new(market(), parameter(0));
+
+        // Determined closes 100, 110, 100, 110, so the last determined RSI is above 50
+        for hour in 0..5 {
+            let price = if hour % 2 == 0 { 100.0 } else { 110.0 };
+            rule.update_market_state(market_state(hour, price)).unwrap();
+            control
+                .update_market_state(market_state(hour, price))
+                .unwrap();
+        }
+        let rsis = rule.rsi_history.outputs().flatten().copied().collect_vec();
+
+        // A crash within the open interval is recommended before the interval finishes
+        let state = market_state_at(4, 5, 50.0);
+        rule.update_market_state(state.clone()).unwrap();
+        control.update_market_state(state).unwrap();
+        let recommendation = rule.recommendation();
+        assert!(matches!(recommendation, RsiCrossRecommendation::Sell(..)));
+        assert!(recommendation
+            .reason()
+            .starts_with("Rsi(60m 2x exponential provisional): "));
+        assert!(matches!(
+            control.recommendation(),
+            RsiCrossRecommendation::RsiUndetermined(..)
+        ));
+
+        // Provisional evaluation leaves the determined RSIs untouched
+        for _ in 0..3 {
+            rule.recommendation();
+        }
+        assert_eq!(
+            rsis,
+            rule.rsi_history.outputs().flatten().copied().collect_vec()
+        );
+
+        // Later determined RSIs are the same as the ones never evaluated provisionally
+        for hour in 5..8 {
+            let price = if hour % 2 == 0 { 100.0 } else { 110.0 };
+            rule.update_market_state(market_state(hour, price)).unwrap();
+            control
+                .update_market_state(market_state(hour, price))
+                .unwrap();
+            rule.recommendation();
+            assert_eq!(
+                control.rsi_history.outputs().flatten().collect_vec(),
+                rule.rsi_history.outputs().flatten().collect_vec()
+            );
+        }
+        assert_eq!(control.signal(), rule.signal());
+    }
+
+    #[test]
+    fn test_recommend_provisional_with_cooldown() {
+        let provisional = |cooldown_candlesticks| RsiCrossParameter {
+            use_provisional_candle: true,
+            ..parameter(cooldown_candlesticks)
+        };
+        let mut rule = RsiCrossRule::new(market(), provisional(2));
+        let mut control = RsiCrossRule::new(market(), provisional(0));
+        let update = |rule: &mut RsiCrossRule, control: &mut RsiCrossRule, state: MarketState| {
+            rule.update_market_state(state.clone()).unwrap();
+            control.update_market_state(state).unwrap();
+        };
+        // Rising prices never cross the triggers
+        for hour in 0..5 {
+            update(
+                &mut rule,
+                &mut control,
+                market_state(hour, 100.0 + hour as f64),
+            );
+        }
+
+        // A provisional crash is recommended, and starts cooldown once its interval is determined
+        update(&mut rule, &mut control, market_state_at(4, 5, 50.0));
+        assert!(matches!(
+            rule.recommendation(),
+            RsiCrossRecommendation::Sell(..)
+        ));
+
+        // The rebound is suppressed on the next interval
+        update(&mut rule, &mut control, market_state(5, 110.0));
+        assert!(matches!(
+            control.recommendation(),
+            RsiCrossRecommendation::Buy(..)
+        ));
+        assert_eq!(
+            RsiCrossRecommendation::CoolingDown(1, provisional(2)),
+            rule.recommendation()
+        );
+
+        update(&mut rule, &mut control, market_state(6, 100.0));
+        assert_eq!(
+            RecommendationType::Neutral,
+            rule.recommendation().recommendation_type()
+        );
+
+        // Cooldown is over after two intervals
+        for hour in 7..9 {
+            let price = if hour % 2 == 0 { 100.0 } else { 110.0 };
+            update(&mut rule, &mut control, market_state(hour, price));
+            assert_eq!(
+                control.recommendation().recommendation_type(),
+                rule.recommendation().recommendation_type()
+            );
+        }
+    }
+
+    #[test]
+    fn test_parameter() {
+        let json = r#"{
+            "algorithm": "rsiCross",
+            "candlestickTimespanMin": 60,
+            "candlestickCount": 14,
+            "buyTrigger": 30.0,
+            "sellTrigger": 70.0,
+            "upperPendingTrigger": 70.0,
+            "lowerPendingTrigger": 30.0
+        }"#;
+        let parameter = serde_json::from_str::<RsiCrossParameter>(json).unwrap();
+        assert_eq!(0, parameter.cooldown_candlesticks);
+        assert_eq!(GapPolicy::SkipGaps, parameter.gap_policy);
+        assert_eq!(RsiMethod::Exponential, parameter.method);
+        assert!(!parameter.use_provisional_candle);
+        assert_eq!(None, parameter.alignment_origin);
+        assert!(!parameter.discard_first_incomplete);
+        let rule = parameter.create_rule(market());
+        assert_eq!(Some(Duration::hours(15)), rule.duration_requirement());
+
+        let json = r#"{
+            "algorithm": "rsiCross",
+            "candlestickIntervalMin": 60,
+            "candlestickCount": 14,
+            "buyTrigger": 30.0,
+            "sellTrigger": 70.0,
+            "upperPendingTrigger": 70.0,
+            "lowerPendingTrigger": 30.0,
+            "cooldownCandlesticks": 3,
+            "gapPolicy": {"resetOnGap": 2},
+            "method": "wilder",
+            "useProvisionalCandle": true,
+            "alignmentOrigin": "2021-01-01T00:30:00",
+            "discardFirstIncomplete": true
+        }"#;
+        let parameter = serde_json::from_str::<RsiCrossParameter>(json).unwrap();
+        assert_eq!(3, parameter.cooldown_candlesticks);
+        assert!(parameter.use_provisional_candle);
+        assert_eq!(
+            Some(chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 30, 0)),
+            parameter.alignment_origin
+        );
+        assert!(parameter.discard_first_incomplete);
+        assert_eq!(GapPolicy::ResetOnGap(2), parameter.gap_policy);
+        assert_eq!(RsiMethod::Wilder, parameter.method);
+        // Candlesticks in cooldown and the discarded one are also loaded
+        let rule = parameter.create_rule(market());
+        assert_eq!(Some(Duration::hours(19)), rule.duration_requirement());
+    }
+}
@@ -1,32 +1,63 @@
 use super::*;
 use crate::indicator::*;
+use crate::rsi::*;
+use crate::Timestamp;
 use anyhow::Result;
 use database::model::*;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use std::ops::Range;
-use ta::{indicators::RelativeStrengthIndex, Close, Period};
-use validator::{Validate, ValidationError};
+use ta::{Close, Period};
+use validator::{Validate, ValidationError, ValidationErrors};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
 #[serde(rename_all = "camelCase")]
+#[validate(schema(function = "validate_maxima_interval"))]
 pub struct RsiDivergenceParameter {
     #[validate(range(min = 1))]
+    #[serde(alias = "candlestickTimespanMin")]
     candlestick_interval_min: i64,
     #[validate(range(min = 1))]
     candlestick_count: usize,
-    #[validate(custom = "validate_range")]
-    candlestick_maxima_interval: Range<usize>,
+    #[serde(alias = "candleStickMaximaIntervalMin")]
+    candlestick_maxima_interval_min: usize,
+    #[serde(alias = "candleStickMaximaIntervalMax")]
+    candlestick_maxima_interval_max: usize,
     #[validate(range(min = 0, max = 100))]
     upper_divergence_trigger: f64,
     #[validate(range(min = 0, max = 100))]
     lower_divergence_trigger: f64,
+    /// Number of determined candlesticks after buy/sell recommendation, during which buy/sell is not recommended.
+    /// `cooldownCandlesticks` in rule JSON, 0 by default
+    #[serde(default)]
+    cooldown_candlesticks: usize,
+    /// Treatment of intervals without any price, like `"fillForward"` or `{"resetOnGap": 2}`.
+    /// `gapPolicy` in rule JSON, `"skipGaps"` by default
+    #[serde(default)]
+    gap_policy: GapPolicy,
+    /// Averaging of gains and losses, `"exponential"`, `"simple"` or `"wilder"`.
+    /// `method` in rule JSON, `"exponential"` by default
+    #[serde(default)]
+    method: RsiMethod,
+    /// Beginning of an interval like `"2021-01-01T00:00:00"`, to which candlesticks are aligned.
+    /// `alignmentOrigin` in rule JSON, UNIX epoch by default
+    #[serde(default)]
+    alignment_origin: Option<Timestamp>,
+    /// If true, the first candlestick is dropped when the rule started mid-interval,
+    /// since it lacks prices before the start.
+    /// `discardFirstIncomplete` in rule JSON, false by default
+    #[serde(default)]
+    discard_first_incomplete: bool,
 }
 
 impl RsiDivergenceParameter {
     fn candlestick_interval(&self) -> Duration {
         Duration::minutes(self.candlestick_interval_min)
     }
+
+    fn candlestick_maxima_interval(&self) -> Range<usize> {
+        self.candlestick_maxima_interval_min..self.candlestick_maxima_interval_max
+    }
 }
 
 #[typetag::serde(name = "rsiDivergence")]
@@ -34,9 +65,14 @@ impl RuleParameter for RsiDivergenceParameter {
     fn create_rule(&self, market: Market) -> Box<dyn Rule> {
         Box::from(RsiDivergenceRule::new(market, self.clone()))
     }
+
+    fn validate_parameter(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
 }
 
-fn validate_range(range: &Range<usize>) -> Result<(), ValidationError> {
+fn validate_maxima_interval(parameter: &RsiDivergenceParameter) -> Result<(), ValidationError> {
+    let range = parameter.candlestick_maxima_interval();
     if range.start > 0 && range.start < range.end {
         Ok(())
     } else {
@@ -48,76 +84,40 @@ fn validate_range(range: &Range<usize>) -> Result<(), ValidationError> {
 struct RsiDivergenceRule {
     market: Market,
     parameter: RsiDivergenceParameter,
-    market_states: Vec<MarketState>,
-    rsi_history: IndicatorHistory<RelativeStrengthIndex, f64>,
+    /// Only the last state is kept, since older ones are never read
+    last_market_state: Option<MarketState>,
+    rsi_history: IndicatorHistory<Rsi, f64>,
+    cooldown: Cooldown,
 }
 
 impl RsiDivergenceRule {
     fn new(market: Market, parameter: RsiDivergenceParameter) -> Self {
         // Parameter holds RsiHistory's constraint by RsiDivergenceParameter::new(),
         // so no panic occurs
-        let indicator = RelativeStrengthIndex::new(parameter.candlestick_count).unwrap();
-        let indicator_buffer = IndicatorBuffer::new(indicator, parameter.candlestick_interval());
-        let rsi_history = IndicatorHistory::new(indicator_buffer);
+        let indicator = Rsi::new(parameter.method, parameter.candlestick_count).unwrap();
+        let indicator_buffer = IndicatorBuffer::new(indicator, parameter.candlestick_interval())
+            .with_gap_policy(parameter.gap_policy)
+            .with_discard_first_incomplete(parameter.discard_first_incomplete);
+        let indicator_buffer = match parameter.alignment_origin {
+            Some(origin) => indicator_buffer.with_origin(origin),
+            None => indicator_buffer,
+        };
+        // Peaks are searched within the retained candlesticks
+        let capacity_limit =
+            parameter.candlestick_count + parameter.candlestick_maxima_interval().end;
+        let rsi_history = IndicatorHistory::with_capacity_limit(indicator_buffer, capacity_limit);
+        let cooldown = Cooldown::new(parameter.cooldown_candlesticks);
         Self {
             market,
             parameter,
-            market_states: vec![],
+            last_market_state: None,
             rsi_history,
+            cooldown,
         }
     }
-}
-
-impl Rule for RsiDivergenceRule {
-    fn market(&self) -> Market {
-        self.market.clone()
-    }
-
-    /// Return the shortest duration required to generate recommendation
-    fn duration_requirement(&self) -> Option<Duration> {
-        let b = self.rsi_history.indicator_buffer();
-        let d = b.interval() * (b.indicator().period() as i32 + 1);
-        Some(d)
-    }
 
-    /// Push newer market state
-    /// # Returns
-    /// `Ok(())` if succeeds
-    ///
-    /// `Err(e)` if market/timestamp constraint fails
-    fn update_market_state(&mut self, mut market_state: MarketState) -> Result<(), RuleError> {
-        if !self.is_correct_market_state(&market_state) {
-            return Err(RuleError::MarketConstraint);
-        }
-
-        // Deny older timestamp data
-        if let Some(last_state) = self.market_states.last() {
-            if last_state.stamp.timestamp >= market_state.stamp.timestamp {
-                return Err(RuleError::StampConstraint);
-            }
-        }
-
-        let price_stamp = PriceStamp::new(
-            market_state.stamp.timestamp,
-            market_state.price.amount as f64,
-        );
-
-        self.rsi_history
-            .next(price_stamp)
-            .map_err(RuleError::Other)?;
-
-        // Drop needless myorder data for RSI-based speculation
-        market_state
-            .myorders
-            .retain(|m| m.state == OrderState::Opened);
-
-        self.market_states.push(market_state);
-
-        Ok(())
-    }
-
-    /// Gererate trade recommendation
-    fn recommend(&self) -> Box<dyn Recommendation> {
+    /// Recommendation by RSI divergence, without cooldown
+    fn signal(&self) -> RsiDivergenceRecommendation {
         use RsiDivergenceRecommendation::*;
 
         let peak_candidates = {
@@ -126,30 +126,30 @@ impl Rule for RsiDivergenceRule {
             // Recommend only when candlestick is determined just now.
             // This condition prevents continuous recommendation by launch-by-launch this rule.
             if matches!(history.last(), Some(None)) {
-                return Box::from(Neutral(self.parameter.clone()));
+                return Neutral(self.parameter.clone());
             }
 
-            let take_count = self.parameter.candlestick_maxima_interval.end
-                - self.parameter.candlestick_maxima_interval.start;
+            let maxima_interval = self.parameter.candlestick_maxima_interval();
+            let take_count = maxima_interval.end - maxima_interval.start;
 
             // Take determined candlesticks and its RSI
             history
                 .iter()
                 .flat_map(std::convert::identity)
-                .skip(self.parameter.candlestick_maxima_interval.start)
+                .skip(maxima_interval.start)
                 .take(take_count)
                 .cloned()
         };
 
         let (last_price, last_rsi) = match peak_candidates.clone().last() {
             Some((data, rsi)) => (data.close(), rsi),
-            None => return Box::from(Neutral(self.parameter.clone())),
+            None => return Neutral(self.parameter.clone()),
         };
 
         let (rsi_lower_peak, rsi_upper_peak) =
             match peak_candidates.minmax_by_key(|(_, rsi)| *rsi).into_option() {
                 Some(opt) => opt,
-                None => return Box::from(Neutral(self.parameter.clone())),
+                None => return Neutral(self.parameter.clone()),
             };
 
         // Check upper peak condition. It can make sell order recommendation
@@ -160,13 +160,13 @@ impl Rule for RsiDivergenceRule {
                 self.parameter.upper_divergence_trigger < last_rsi && last_rsi < peak_rsi;
             let price_cond = last_price > upper_peak_price;
             if rsi_cond && price_cond {
-                return Box::from(Sell(
+                return Sell(
                     self.parameter.clone(),
                     peak_rsi,
                     upper_peak_price,
                     last_rsi,
                     last_price,
-                ));
+                );
             }
         }
 
@@ -178,18 +178,104 @@ impl Rule for RsiDivergenceRule {
                 self.parameter.lower_divergence_trigger > last_rsi && last_rsi > peak_rsi;
             let price_cond = last_price < lower_peak_price;
             if rsi_cond && price_cond {
-                return Box::from(Buy(
+                return Buy(
                     self.parameter.clone(),
                     peak_rsi,
                     lower_peak_price,
                     last_rsi,
                     last_price,
-                ));
+                );
             }
         }
 
         // No buy/sell signal detected
-        Box::from(Neutral(self.parameter.clone()))
+        Neutral(self.parameter.clone())
+    }
+
+    /// Recommendation by RSI divergence, suppressing buy/sell in cooldown
+    fn recommendation(&self) -> RsiDivergenceRecommendation {
+        use RsiDivergenceRecommendation::*;
+
+        match self.signal() {
+            Buy(..) | Sell(..) if self.cooldown.is_suppressing() => {
+                CoolingDown(self.parameter.clone(), self.cooldown.remaining())
+            }
+            recommendation => recommendation,
+        }
+    }
+}
+
+impl Rule for RsiDivergenceRule {
+    fn name(&self) -> &'static str {
+        "rsiDivergence"
+    }
+
+    fn market(&self) -> Market {
+        self.market.clone()
+    }
+
+    /// Return the shortest duration required to generate recommendation
+    fn duration_requirement(&self) -> Option<Duration> {
+        let b = self.rsi_history.indicator_buffer();
+        // Candlesticks in cooldown are also loaded to restore it,
+        // and so is the incomplete one to be discarded
+        let count = b.indicator().period()
+            + 1
+            + self.parameter.cooldown_candlesticks
+            + b.discard_first_incomplete() as usize;
+        let d = b.interval() * count as i32;
+        Some(d)
+    }
+
+    fn candlestick_interval(&self) -> Option<Duration> {
+        Some(self.rsi_history.indicator_buffer().interval())
+    }
+
+    /// Push newer market state
+    /// # Returns
+    /// `Ok(())` if succeeds
+    ///
+    /// `Err(e)` if market/timestamp constraint fails
+    fn update_market_state(&mut self, mut market_state: MarketState) -> Result<(), RuleError> {
+        if !self.is_correct_market_state(&market_state) {
+            return Err(RuleError::MarketConstraint);
+        }
+
+        // Deny older timestamp data
+        if let Some(last_state) = self.last_market_state.as_ref() {
+            if last_state.stamp.timestamp >= market_state.stamp.timestamp {
+                return Err(RuleError::StampConstraint);
+            }
+        }
+
+        let price_stamp = market_state.price_stamp();
+
+        let determined = self
+            .rsi_history
+            .next(price_stamp)
+            .map_err(RuleError::Other)?
+            .is_some();
+        if determined {
+            let fires = matches!(
+                self.signal(),
+                RsiDivergenceRecommendation::Buy(..) | RsiDivergenceRecommendation::Sell(..)
+            );
+            self.cooldown.next(fires);
+        }
+
+        // Drop needless myorder data for RSI-based speculation
+        market_state
+            .myorders
+            .retain(|m| m.state == OrderState::Opened);
+
+        self.last_market_state = Some(market_state);
+
+        Ok(())
+    }
+
+    /// Gererate trade recommendation
+    fn recommend(&self) -> Box<dyn Recommendation> {
+        Box::from(self.recommendation())
     }
 }
 
@@ -200,6 +286,8 @@ pub enum RsiDivergenceRecommendation {
     /// peak_rsi, peak_price, last_rsi, last_price
     Sell(RsiDivergenceParameter, f64, f64, f64, f64),
     Neutral(RsiDivergenceParameter),
+    /// Buy/sell is suppressed. Remaining candlesticks in cooldown
+    CoolingDown(RsiDivergenceParameter, usize),
 }
 
 impl Recommendation for RsiDivergenceRecommendation {
@@ -209,7 +297,7 @@ impl Recommendation for RsiDivergenceRecommendation {
         match self {
             Buy(..) => RecommendationType::Buy,
             Sell(..) => RecommendationType::Sell,
-            Neutral(..) => RecommendationType::Neutral,
+            Neutral(..) | CoolingDown(..) => RecommendationType::Neutral,
         }
     }
 
@@ -217,12 +305,13 @@ impl Recommendation for RsiDivergenceRecommendation {
         use RsiDivergenceRecommendation::*;
 
         let parameter = match self {
-            Buy(p, ..) | Sell(p, ..) | Neutral(p) => p,
+            Buy(p, ..) | Sell(p, ..) | Neutral(p) | CoolingDown(p, _) => p,
         };
         let mut header = format!(
-            "Rsi divergence({}m {}x): ",
+            "Rsi divergence({}m {}x {}): ",
             parameter.candlestick_interval().num_minutes(),
-            parameter.candlestick_count
+            parameter.candlestick_count,
+            parameter.method.name()
         );
 
         let description = match self {
@@ -233,9 +322,166 @@ impl Recommendation for RsiDivergenceRecommendation {
             )
             .into(),
             Neutral(_) => String::from("trigger condition is not satisfied"),
+            CoolingDown(_, remaining) => format!(
+                "cooling down after the last signal, {} candlesticks left",
+                remaining
+            ),
         };
 
         header.push_str(&description);
         header
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn market() -> Market {
+        Market::new(MarketId::new(1), CurrencyId::new(1), CurrencyId::new(2))
+    }
+
+    fn market_state(hour: u32, price: Amount) -> MarketState {
+        let timestamp = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(hour, 0, 0);
+        let stamp_id = StampId::new(hour as i32);
+        let stamp = Stamp::new(stamp_id, timestamp);
+        let price = Price::new(
+            PriceId::new(hour as i32),
+            market().market_id,
+            stamp_id,
+            price,
+        );
+        MarketState::new(stamp, price, vec![], vec![])
+    }
+
+    /// Peaks are searched in the 2nd to 4th candlesticks
+    fn parameter(cooldown_candlesticks: usize) -> RsiDivergenceParameter {
+        RsiDivergenceParameter {
+            candlestick_interval_min: 60,
+            candlestick_count: 2,
+            candlestick_maxima_interval_min: 1,
+            candlestick_maxima_interval_max: 4,
+            upper_divergence_trigger: 70.0,
+            lower_divergence_trigger: 30.0,
+            cooldown_candlesticks,
+            gap_policy: GapPolicy::SkipGaps,
+            method: RsiMethod::Exponential,
+            alignment_origin: None,
+            discard_first_incomplete: false,
+        }
+    }
+
+    /// Push a state at every hour, and recommend after each push.
+    /// Price of the 4th candlestick exceeds the RSI peak of the 2nd one with lower RSI,
+    /// so that sell stays satisfied until the 2nd candlestick is dropped from the retained 6 ones
+    fn recommendations(parameter: RsiDivergenceParameter) -> Vec<RecommendationType> {
+        let mut rule = parameter.create_rule(market());
+        let prices = [100.0, 110.0, 105.0, 112.0];
+
+        (0..13)
+            .map(|hour| {
+                let price = prices.get(hour as usize).copied().unwrap_or(112.0);
+                rule.update_market_state(market_state(hour, price)).unwrap();
+                rule.recommend().recommendation_type()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_recommend_without_cooldown() {
+        use RecommendationType::*;
+
+        let recommendations = recommendations(parameter(0));
+
+        let mut expected = vec![Neutral; 4];
+        expected.extend(vec![Sell; 3]);
+        expected.extend(vec![Neutral; 6]);
+        assert_eq!(expected, recommendations);
+    }
+
+    #[test]
+    fn test_recommend_with_cooldown() {
+        use RecommendationType::*;
+
+        let recommendations = recommendations(parameter(2));
+
+        // Two candlesticks are suppressed after the signal
+        let mut expected = vec![Neutral; 4];
+        expected.push(Sell);
+        expected.extend(vec![Neutral; 8]);
+        assert_eq!(expected, recommendations);
+
+        // Sell is still satisfied on the suppressed candlesticks
+        let mut rule = RsiDivergenceRule::new(market(), parameter(2));
+        let prices = [100.0, 110.0, 105.0, 112.0, 112.0, 112.0];
+        for (hour, price) in prices.iter().enumerate() {
+            rule.update_market_state(market_state(hour as u32, *price))
+                .unwrap();
+        }
+        assert!(matches!(
+            rule.signal(),
+            RsiDivergenceRecommendation::Sell(..)
+        ));
+        assert!(matches!(
+            rule.recommendation(),
+            RsiDivergenceRecommendation::CoolingDown(_, 1)
+        ));
+    }
+
+    #[test]
+    fn test_memory_bounded() {
+        let mut rule = RsiDivergenceRule::new(market(), parameter(0));
+        let origin = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 0, 0);
+
+        for i in 0..10000 {
+            let mut state = market_state(0, 100.0 + (i % 5) as Amount);
+            state.stamp.timestamp = origin + Duration::minutes(30 * i);
+            rule.update_market_state(state).unwrap();
+        }
+
+        // 2 + 4 candlesticks with stamps of their intervals
+        let history = rule.rsi_history.history();
+        assert_eq!(6, history.iter().flatten().count());
+        assert!(history.len() <= 6 * 2);
+        assert!(rule.last_market_state.is_some());
+    }
+
+    #[test]
+    fn test_parameter() {
+        let json = r#"{
+            "algorithm": "rsiDivergence",
+            "candlestickIntervalMin": 60,
+            "candlestickCount": 14,
+            "candlestickMaximaIntervalMin": 2,
+            "candlestickMaximaIntervalMax": 10,
+            "upperDivergenceTrigger": 70.0,
+            "lowerDivergenceTrigger": 30.0,
+            "cooldownCandlesticks": 3
+        }"#;
+        let parameter = serde_json::from_str::<Box<dyn RuleParameter>>(json).unwrap();
+        assert!(parameter.validate_parameter().is_ok());
+
+        let rule = parameter.create_rule(market());
+        assert_eq!("rsiDivergence", rule.name());
+        // Candlesticks in cooldown are also loaded
+        assert_eq!(Some(Duration::hours(18)), rule.duration_requirement());
+    }
+
+    #[test]
+    fn test_parameter_gap_policy() {
+        let json = r#"{
+            "candlestickIntervalMin": 60,
+            "candlestickCount": 14,
+            "candlestickMaximaIntervalMin": 2,
+            "candlestickMaximaIntervalMax": 10,
+            "upperDivergenceTrigger": 70.0,
+            "lowerDivergenceTrigger": 30.0,
+            "gapPolicy": "fillForward"
+        }"#;
+        let parameter = serde_json::from_str::<RsiDivergenceParameter>(json).unwrap();
+        assert_eq!(GapPolicy::FillForward, parameter.gap_policy);
+
+        let json = json.replace(r#""fillForward""#, r#""unknown""#);
+        assert!(serde_json::from_str::<RsiDivergenceParameter>(&json).is_err());
+    }
+}
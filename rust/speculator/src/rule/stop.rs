@@ -0,0 +1,442 @@
+use super::*;
+use anyhow::Result;
+use database::custom_sql_type::OrderSide;
+use database::model::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use validator::{Validate, ValidationErrors};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct StopParameter {
+    /// Sell if price drops below entry price by this ratio
+    #[validate(range(min = 0.0, max = 1.0))]
+    stop_loss_ratio: f64,
+    /// Sell if price rises above entry price by this ratio
+    #[validate(range(min = 0.0))]
+    take_profit_ratio: f64,
+}
+
+#[typetag::serde(name = "stop")]
+impl RuleParameter for StopParameter {
+    fn create_rule(&self, market: Market) -> Box<dyn Rule> {
+        Box::from(StopRule::new(market, *self))
+    }
+
+    fn validate_parameter(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
+}
+
+/// Position held in a market, averaged over filled orders
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Position {
+    base_quantity: f64,
+    /// Total quote paid for `base_quantity`
+    cost: f64,
+}
+
+/// Positions smaller than this are regarded as closed
+const MIN_POSITION: f64 = 1e-9;
+
+impl Position {
+    /// Volume-weighted average price paid for the position. `None` if no position is held
+    fn entry_price(&self) -> Option<f64> {
+        if self.base_quantity > MIN_POSITION {
+            Some(self.cost / self.base_quantity)
+        } else {
+            None
+        }
+    }
+
+    /// Buys raise the position and its cost.
+    /// Sells reduce them at the average price, so the entry price of the rest doesn't change
+    fn fill(&mut self, side: OrderSide, price: f64, base_quantity: f64) {
+        match side {
+            OrderSide::Buy => {
+                self.base_quantity += base_quantity;
+                self.cost += price * base_quantity;
+            }
+            OrderSide::Sell => match self.entry_price() {
+                Some(entry) if base_quantity < self.base_quantity - MIN_POSITION => {
+                    self.base_quantity -= base_quantity;
+                    self.cost = entry * self.base_quantity;
+                }
+                // Sold all, or more than observed buys
+                _ => *self = Position::default(),
+            },
+        }
+    }
+}
+
+/// Price at which `myorder` was filled
+fn fill_price(myorder: &MyOrder) -> f64 {
+    if myorder.price > 0.0 {
+        myorder.price
+    } else if myorder.base_quantity > 0.0 {
+        // Market orders may have no price
+        myorder.quote_quantity / myorder.base_quantity
+    } else {
+        0.0
+    }
+}
+
+#[derive(Debug, Clone)]
+struct StopRule {
+    market: Market,
+    parameter: StopParameter,
+    last_state: Option<MarketState>,
+    position: Position,
+    /// Transactions already counted in `position`
+    filled_transactions: HashSet<String>,
+}
+
+impl StopRule {
+    fn new(market: Market, parameter: StopParameter) -> Self {
+        Self {
+            market,
+            parameter,
+            last_state: None,
+            position: Position::default(),
+            filled_transactions: HashSet::new(),
+        }
+    }
+}
+
+impl Rule for StopRule {
+    fn name(&self) -> &'static str {
+        "stop"
+    }
+
+    fn market(&self) -> Market {
+        self.market.clone()
+    }
+
+    /// Only the latest price is required.
+    /// The first market state loaded carries myorders modified before it, so the entry price covers fills since the position opened
+    fn duration_requirement(&self) -> Option<Duration> {
+        Some(Duration::zero())
+    }
+
+    fn requires_myorders(&self) -> bool {
+        true
+    }
+
+    fn update_market_state(&mut self, market_state: MarketState) -> Result<(), RuleError> {
+        if !self.is_correct_market_state(&market_state) {
+            return Err(RuleError::MarketConstraint);
+        }
+
+        // Deny older timestamp data
+        if let Some(last_state) = self.last_state.as_ref() {
+            if last_state.stamp.timestamp >= market_state.stamp.timestamp {
+                return Err(RuleError::StampConstraint);
+            }
+        }
+
+        // An order appears in every state modifying it, so a fill is counted once
+        for myorder in market_state
+            .myorders
+            .iter()
+            .filter(|m| m.state == OrderState::Filled)
+        {
+            if self
+                .filled_transactions
+                .insert(myorder.transaction_id.clone())
+            {
+                self.position
+                    .fill(myorder.side, fill_price(myorder), myorder.base_quantity);
+            }
+        }
+
+        self.last_state = Some(market_state);
+
+        Ok(())
+    }
+
+    fn recommend(&self) -> Box<dyn Recommendation> {
+        let p = self.parameter;
+
+        let (entry, current) = match (self.position.entry_price(), self.last_state.as_ref()) {
+            (Some(entry), Some(state)) => (entry, state.price.amount),
+            _ => return Box::from(StopRecommendation::NoPosition(p)),
+        };
+
+        let recommendation = if current < entry * (1.0 - p.stop_loss_ratio) {
+            StopRecommendation::StopLoss(entry, current, p)
+        } else if current > entry * (1.0 + p.take_profit_ratio) {
+            StopRecommendation::TakeProfit(entry, current, p)
+        } else {
+            StopRecommendation::Neutral(entry, current, p)
+        };
+
+        Box::from(recommendation)
+    }
+}
+
+/// While the price is between the stop-loss and take-profit levels, the position is protected by a sell stop
+/// at the stop-loss level. Sell is recommended directly if the price is already below it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopRecommendation {
+    /// entry price, current price
+    StopLoss(f64, f64, StopParameter),
+    /// entry price, current price
+    TakeProfit(f64, f64, StopParameter),
+    /// entry price, current price
+    Neutral(f64, f64, StopParameter),
+    NoPosition(StopParameter),
+}
+
+impl Recommendation for StopRecommendation {
+    fn recommendation_type(&self) -> RecommendationType {
+        use StopRecommendation::*;
+
+        match self {
+            StopLoss(..) | TakeProfit(..) => RecommendationType::Sell,
+            Neutral(..) | NoPosition(..) => RecommendationType::Neutral,
+        }
+    }
+
+    fn reason(&self) -> String {
+        use StopRecommendation::*;
+
+        let parameter = match self {
+            StopLoss(.., p) | TakeProfit(.., p) | Neutral(.., p) | NoPosition(p) => p,
+        };
+        let mut header = format!(
+            "Stop(-{} +{}): ",
+            parameter.stop_loss_ratio, parameter.take_profit_ratio
+        );
+
+        let description = match self {
+            StopLoss(entry, current, _) => format!("stop loss. entry {}, price {}", entry, current),
+            TakeProfit(entry, current, _) => {
+                format!("take profit. entry {}, price {}", entry, current)
+            }
+            Neutral(entry, current, _) => format!(
+                "trigger condition is not satisfied. entry {}, price {}",
+                entry, current
+            ),
+            NoPosition(_) => String::from("no position"),
+        };
+
+        header.push_str(&description);
+        header
+    }
+
+    fn stop_loss_trigger(&self) -> Option<f64> {
+        match self {
+            StopRecommendation::Neutral(entry, _, p) => Some(entry * (1.0 - p.stop_loss_ratio)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn market() -> Market {
+        Market::new(MarketId::new(1), CurrencyId::new(1), CurrencyId::new(2))
+    }
+
+    fn myorder(
+        id: &str,
+        side: OrderSide,
+        state: OrderState,
+        price: Amount,
+        base: Amount,
+    ) -> MyOrder {
+        MyOrder {
+            myorder_id: MyorderId::new(0),
+            transaction_id: id.into(),
+            market_id: market().market_id,
+            created_stamp_id: StampId::new(0),
+            modified_stamp_id: StampId::new(0),
+            price,
+            base_quantity: base,
+            quote_quantity: price * base,
+            order_type: OrderType::Limit,
+            side,
+            state,
+        }
+    }
+
+    fn market_state(hour: u32, price: Amount, myorders: Vec<MyOrder>) -> MarketState {
+        let timestamp = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(hour, 0, 0);
+        let stamp_id = StampId::new(hour as i32);
+        let stamp = Stamp::new(stamp_id, timestamp);
+        let price = Price::new(
+            PriceId::new(hour as i32),
+            market().market_id,
+            stamp_id,
+            price,
+        );
+        let myorders = myorders
+            .into_iter()
+            .map(|m| MyOrder {
+                modified_stamp_id: stamp_id,
+                ..m
+            })
+            .collect();
+        MarketState::new(stamp, price, vec![], myorders)
+    }
+
+    fn rule() -> StopRule {
+        let parameter = StopParameter {
+            stop_loss_ratio: 0.1,
+            take_profit_ratio: 0.2,
+        };
+        StopRule::new(market(), parameter)
+    }
+
+    #[test]
+    fn test_recommend() {
+        use OrderSide::*;
+        use OrderState::*;
+
+        let mut rule = rule();
+        rule.update_market_state(market_state(0, 100.0, vec![]))
+            .unwrap();
+        assert_eq!(
+            RecommendationType::Neutral,
+            rule.recommend().recommendation_type()
+        );
+
+        // Entry price is (100 * 1 + 130 * 3) / 4 = 122.5
+        let states = vec![
+            market_state(1, 100.0, vec![myorder("a", Buy, Opened, 100.0, 1.0)]),
+            market_state(2, 100.0, vec![myorder("a", Buy, Filled, 100.0, 1.0)]),
+            market_state(3, 130.0, vec![myorder("b", Buy, Filled, 130.0, 3.0)]),
+            // Cancelled orders and repeated fills don't change the position
+            market_state(
+                4,
+                120.0,
+                vec![
+                    myorder("a", Buy, Filled, 100.0, 1.0),
+                    myorder("c", Buy, Cancelled, 50.0, 10.0),
+                ],
+            ),
+        ];
+        for state in states.into_iter() {
+            rule.update_market_state(state).unwrap();
+        }
+        let recommendation = rule.recommend();
+        assert_eq!(
+            RecommendationType::Neutral,
+            recommendation.recommendation_type()
+        );
+        assert!(recommendation.reason().contains("entry 122.5, price 120"));
+
+        rule.update_market_state(market_state(5, 110.0, vec![]))
+            .unwrap();
+        let recommendation = rule.recommend();
+        assert_eq!(
+            RecommendationType::Sell,
+            recommendation.recommendation_type()
+        );
+        assert!(recommendation
+            .reason()
+            .contains("stop loss. entry 122.5, price 110"));
+
+        rule.update_market_state(market_state(6, 148.0, vec![]))
+            .unwrap();
+        let recommendation = rule.recommend();
+        assert_eq!(
+            RecommendationType::Sell,
+            recommendation.recommendation_type()
+        );
+        assert!(recommendation.reason().contains("take profit"));
+    }
+
+    #[test]
+    fn test_duration_requirement() {
+        // Market states are loaded even if no other rule requires them
+        assert_eq!(Some(Duration::zero()), rule().duration_requirement());
+    }
+
+    #[test]
+    fn test_stop_loss_trigger() {
+        let mut rule = rule();
+        rule.update_market_state(market_state(0, 100.0, vec![]))
+            .unwrap();
+        assert_eq!(None, rule.recommend().stop_loss_trigger());
+
+        rule.update_market_state(market_state(
+            1,
+            100.0,
+            vec![myorder("a", OrderSide::Buy, OrderState::Filled, 100.0, 1.0)],
+        ))
+        .unwrap();
+        let trigger = rule.recommend().stop_loss_trigger().unwrap();
+        assert!((trigger - 90.0).abs() < 1e-9);
+
+        // Already below the stop-loss level
+        rule.update_market_state(market_state(2, 89.0, vec![]))
+            .unwrap();
+        let recommendation = rule.recommend();
+        assert_eq!(
+            RecommendationType::Sell,
+            recommendation.recommendation_type()
+        );
+        assert_eq!(None, recommendation.stop_loss_trigger());
+    }
+
+    #[test]
+    fn test_recommend_after_sells() {
+        use OrderSide::*;
+        use OrderState::*;
+
+        let mut rule = rule();
+        rule.update_market_state(market_state(
+            0,
+            100.0,
+            vec![
+                myorder("a", Buy, Filled, 100.0, 1.0),
+                myorder("b", Buy, Filled, 200.0, 1.0),
+            ],
+        ))
+        .unwrap();
+
+        // A partial sell keeps the entry price of the rest
+        rule.update_market_state(market_state(
+            1,
+            100.0,
+            vec![myorder("c", Sell, Filled, 300.0, 1.5)],
+        ))
+        .unwrap();
+        assert_eq!(Some(150.0), rule.position.entry_price());
+        assert_eq!(
+            RecommendationType::Sell,
+            rule.recommend().recommendation_type()
+        );
+
+        // The position is closed
+        rule.update_market_state(market_state(
+            2,
+            100.0,
+            vec![myorder("d", Sell, Filled, 100.0, 0.5)],
+        ))
+        .unwrap();
+        assert_eq!(None, rule.position.entry_price());
+        assert_eq!(
+            RecommendationType::Neutral,
+            rule.recommend().recommendation_type()
+        );
+    }
+
+    #[test]
+    fn test_recommend_market_order() {
+        let mut rule = rule();
+        let mut order = myorder("a", OrderSide::Buy, OrderState::Filled, 0.0, 2.0);
+        order.quote_quantity = 200.0;
+        rule.update_market_state(market_state(0, 85.0, vec![order]))
+            .unwrap();
+
+        assert_eq!(Some(100.0), rule.position.entry_price());
+        assert_eq!(
+            RecommendationType::Sell,
+            rule.recommend().recommendation_type()
+        );
+    }
+}
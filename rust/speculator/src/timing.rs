@@ -0,0 +1,230 @@
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Measurement started by `Timings::start`. Holds nothing if timings are disabled
+#[must_use]
+pub struct Timer(Option<Instant>);
+
+impl Timer {
+    /// Return elapsed time since started, or `None` if timings are disabled
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.0.map(|started| started.elapsed())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TimingEntry {
+    pub total: Duration,
+    pub count: u32,
+}
+
+/// Accumulate elapsed time per (market, item).
+///
+/// Timers can be nested; the outer measurement includes the inner one.
+/// If disabled, timers do not even read the clock.
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    enabled: bool,
+    entries: HashMap<(String, String), TimingEntry>,
+}
+
+impl Timings {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self::new(false)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn start(&self) -> Timer {
+        if self.enabled {
+            Timer(Some(Instant::now()))
+        } else {
+            Timer(None)
+        }
+    }
+
+    /// Accumulate time elapsed since `timer` started into (`market`, `item`)
+    pub fn stop(&mut self, timer: Timer, market: &str, item: &str) {
+        if let Some(elapsed) = timer.elapsed() {
+            self.record(market, item, elapsed);
+        }
+    }
+
+    pub fn record(&mut self, market: &str, item: &str, elapsed: Duration) {
+        if !self.enabled {
+            return;
+        }
+
+        let entry = self
+            .entries
+            .entry((market.to_string(), item.to_string()))
+            .or_default();
+        entry.total += elapsed;
+        entry.count += 1;
+    }
+
+    /// Accumulate entries of `other`, like timings measured on another thread
+    pub fn merge(&mut self, other: Timings) {
+        if !self.enabled {
+            return;
+        }
+
+        for (key, entry) in other.entries.into_iter() {
+            let merged = self.entries.entry(key).or_default();
+            merged.total += entry.total;
+            merged.count += entry.count;
+        }
+    }
+
+    /// Return entries in descending order of total duration
+    pub fn entries(&self) -> Vec<(&str, &str, TimingEntry)> {
+        self.entries
+            .iter()
+            .map(|((market, item), &entry)| (market.as_str(), item.as_str(), entry))
+            .sorted_by(|(m1, i1, e1), (m2, i2, e2)| {
+                e2.total.cmp(&e1.total).then((m1, i1).cmp(&(m2, i2)))
+            })
+            .collect()
+    }
+
+    /// Format entries as a table in descending order of total duration
+    pub fn report(&self) -> String {
+        let entries = self.entries();
+        let market_width = entries
+            .iter()
+            .map(|(market, _, _)| market.len())
+            .chain(std::iter::once("market".len()))
+            .max()
+            .unwrap_or_default();
+        let item_width = entries
+            .iter()
+            .map(|(_, item, _)| item.len())
+            .chain(std::iter::once("item".len()))
+            .max()
+            .unwrap_or_default();
+
+        let mut lines = vec![format!(
+            "{:<mw$}  {:<iw$}  {:>10}  {:>6}",
+            "market",
+            "item",
+            "total[ms]",
+            "count",
+            mw = market_width,
+            iw = item_width
+        )];
+        for (market, item, entry) in entries.into_iter() {
+            lines.push(format!(
+                "{:<mw$}  {:<iw$}  {:>10.3}  {:>6}",
+                market,
+                item,
+                entry.total.as_secs_f64() * 1000.0,
+                entry.count,
+                mw = market_width,
+                iw = item_width
+            ));
+        }
+
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_accumulation() {
+        let mut timings = Timings::new(true);
+
+        for _ in 0..3 {
+            let outer = timings.start();
+            let inner = timings.start();
+            std::thread::sleep(Duration::from_millis(1));
+            timings.stop(inner, "BTC-USDT", "rsiCross/update");
+            timings.stop(outer, "BTC-USDT", "load");
+        }
+
+        let entries = timings.entries();
+        assert_eq!(2, entries.len());
+
+        let (_, _, outer) = entries.iter().find(|(_, item, _)| *item == "load").unwrap();
+        let (_, _, inner) = entries
+            .iter()
+            .find(|(_, item, _)| *item == "rsiCross/update")
+            .unwrap();
+        assert_eq!(3, outer.count);
+        assert_eq!(3, inner.count);
+        assert!(inner.total >= Duration::from_millis(3));
+        assert!(outer.total >= inner.total);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut timings = Timings::disabled();
+
+        let timer = timings.start();
+        timings.stop(timer, "BTC-USDT", "load");
+        timings.record("BTC-USDT", "load", Duration::from_millis(1));
+
+        assert!(timings.entries().is_empty());
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut timings = Timings::new(true);
+        timings.record("BTC-USDT", "load", Duration::from_millis(5));
+
+        let mut other = Timings::new(true);
+        other.record("BTC-USDT", "load", Duration::from_millis(3));
+        other.record("ETH-USDT", "load", Duration::from_millis(2));
+        timings.merge(other);
+
+        let entries = timings.entries();
+        assert_eq!(2, entries.len());
+        assert_eq!(
+            (
+                "BTC-USDT",
+                "load",
+                TimingEntry {
+                    total: Duration::from_millis(8),
+                    count: 2
+                }
+            ),
+            entries[0]
+        );
+
+        let mut disabled = Timings::disabled();
+        disabled.merge(timings);
+        assert!(disabled.entries().is_empty());
+    }
+
+    #[test]
+    fn test_report() {
+        let mut timings = Timings::new(true);
+        timings.record("BTC-USDT", "load", Duration::from_millis(5));
+        timings.record(
+            "ETH-USDT",
+            "rsiDivergence/update",
+            Duration::from_millis(12),
+        );
+        timings.record("BTC-USDT", "load", Duration::from_millis(5));
+
+        let expected = [
+            "market    item                   total[ms]   count",
+            "ETH-USDT  rsiDivergence/update      12.000       1",
+            "BTC-USDT  load                      10.000       2",
+        ]
+        .join("\n");
+        assert_eq!(expected, timings.report());
+    }
+}
@@ -1,12 +1,21 @@
+use crate::fee::FeeSchedule;
 use crate::rule::*;
+use crate::timing::Timings;
 use anyhow::{bail, Result};
-use chrono::Duration;
-use database::custom_sql_type::{MarketId, OrderSide, OrderType};
-use database::model::{Amount, Balance, Market};
+use chrono::{Duration, NaiveDateTime};
+use database::custom_sql_type::{CurrencyId, MarketId, OrderSide, OrderState, OrderType};
+use database::exchange::ExchangeGraph;
+use database::logic::CurrencyCollection;
+use database::model::{Amount, Balance, Currency, Market, MyOrder};
+use database::order::NewOrder;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use validator::Validate;
+use std::convert::TryFrom;
+use std::io::Read;
+use std::path::Path;
+use thiserror::Error as ThisError;
+use validator::{Validate, ValidationError, ValidationErrors};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct OrderRecommendation {
@@ -16,11 +25,234 @@ pub struct OrderRecommendation {
     pub base_quantity: Amount,
     /// Always non-negative
     pub quote_quantity: Amount,
+    /// Limit price, or expected fill price of market orders
     pub price: Amount,
+    /// Market price at which a stop order is placed. `None` unless `order_type` is `StopLimit` or `StopMarket`
+    pub trigger_price: Option<Amount>,
+    /// Change of quote balance when this order is filled, including fee.
+    /// Negative for buy orders, and positive for sell orders
+    pub expected_net_quote: Amount,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Validate)]
+impl OrderRecommendation {
+    /// Return (base, quote) balance changes when this order is filled
+    pub fn balance_diff(&self) -> (Amount, Amount) {
+        match self.side {
+            OrderSide::Buy => (self.base_quantity, self.expected_net_quote),
+            OrderSide::Sell => (-self.base_quantity, self.expected_net_quote),
+        }
+    }
+
+    /// Same order with quantities multiplied by `ratio`
+    pub fn scaled(&self, ratio: f64) -> Self {
+        Self {
+            base_quantity: self.base_quantity * ratio,
+            quote_quantity: self.quote_quantity * ratio,
+            expected_net_quote: self.expected_net_quote * ratio,
+            ..self.clone()
+        }
+    }
+
+    /// Order placed on `market_id`. Expected net quote is dropped
+    pub fn new_order(&self, market_id: MarketId) -> NewOrder {
+        NewOrder {
+            market_id,
+            side: self.side,
+            order_type: self.order_type,
+            price: self.price,
+            base_quantity: self.base_quantity,
+            quote_quantity: self.quote_quantity,
+            trigger_price: self.trigger_price,
+        }
+    }
+
+    /// Whether this order waits for its trigger price before being placed
+    pub fn is_stop(&self) -> bool {
+        self.trigger_price.is_some()
+    }
+
+    /// Whether this order is placed at market `price`.
+    /// Sell stops trigger when the price falls to the trigger or below, and buy stops when it rises to the trigger or above.
+    /// Orders other than stops are always placed
+    pub fn is_triggered(&self, price: Amount) -> bool {
+        match (self.trigger_price, self.side) {
+            (None, _) => true,
+            (Some(trigger), OrderSide::Sell) => price <= trigger,
+            (Some(trigger), OrderSide::Buy) => price >= trigger,
+        }
+    }
+
+    /// Order placed when this stop order is triggered, i.e. a limit order for stop limit and a market order for stop market
+    pub fn triggered(&self) -> Self {
+        let order_type = match self.order_type {
+            OrderType::StopLimit => OrderType::Limit,
+            OrderType::StopMarket => OrderType::Market,
+            order_type => order_type,
+        };
+        Self {
+            order_type,
+            trigger_price: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// Recommendation to cancel an open order
+#[derive(Debug, Clone, PartialEq)]
+pub struct CancelRecommendation {
+    pub transaction_id: String,
+    pub market_id: MarketId,
+    pub side: OrderSide,
+}
+
+/// Orders after applying an allocation cap
+#[derive(Debug, Clone, PartialEq)]
+pub struct CappedOrders {
+    pub orders: Vec<OrderRecommendation>,
+    /// Why buy orders were reduced or skipped. `None` if orders are unchanged
+    pub reason: Option<String>,
+}
+
+/// Reduce or skip buy `orders` so that the share of the base currency in the portfolio stays within `cap` after they are filled.
+/// `base_value` and `total_value` are values of the base currency and the whole portfolio in the same currency,
+/// and `base_rate` converts base quantity into that currency.
+/// Sell orders are kept as they are.
+pub fn cap_buy_orders(
+    orders: Vec<OrderRecommendation>,
+    base_value: f64,
+    total_value: f64,
+    base_rate: f64,
+    cap: f64,
+) -> CappedOrders {
+    let buy_value = orders
+        .iter()
+        .filter(|order| order.side == OrderSide::Buy)
+        .map(|order| order.base_quantity * base_rate)
+        .sum::<f64>();
+    let allowed_value = common::allocation::max_buy_value(base_value, total_value, cap);
+
+    if buy_value <= allowed_value {
+        return CappedOrders {
+            orders,
+            reason: None,
+        };
+    }
+
+    let share = base_value / total_value;
+    if allowed_value <= 0.0 {
+        let orders = orders
+            .into_iter()
+            .filter(|order| order.side != OrderSide::Buy)
+            .collect();
+        let reason = format!(
+            "Skip buy: allocation {:.2}% reaches cap {:.2}%",
+            share * 100.0,
+            cap * 100.0
+        );
+        return CappedOrders {
+            orders,
+            reason: Some(reason),
+        };
+    }
+
+    let ratio = allowed_value / buy_value;
+    let orders = orders
+        .into_iter()
+        .map(|order| match order.side {
+            OrderSide::Buy => order.scaled(ratio),
+            OrderSide::Sell => order,
+        })
+        .collect();
+    let reason = format!(
+        "Reduce buy to {:.2}%: allocation {:.2}% would exceed cap {:.2}%",
+        ratio * 100.0,
+        (base_value + buy_value) / total_value * 100.0,
+        cap * 100.0
+    );
+    CappedOrders {
+        orders,
+        reason: Some(reason),
+    }
+}
+
+/// Available amounts of a market's currencies, used to size recommended orders
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Holdings {
+    pub base_available: f64,
+    pub quote_available: f64,
+}
+
+impl Holdings {
+    pub fn new(base_available: f64, quote_available: f64) -> Self {
+        Self {
+            base_available,
+            quote_available,
+        }
+    }
+
+    /// # Returns
+    /// `Err(e)` if currencies of balances differ from base/quote of `market`
+    pub fn from_balances(
+        base_balance: &Balance,
+        quote_balance: &Balance,
+        market: &Market,
+    ) -> Result<Self, HoldingsError> {
+        if base_balance.currency_id != market.base_id {
+            return Err(HoldingsError::BaseMismatch {
+                market_id: market.market_id,
+                expected: market.base_id,
+                actual: base_balance.currency_id,
+            });
+        }
+        if quote_balance.currency_id != market.quote_id {
+            return Err(HoldingsError::QuoteMismatch {
+                market_id: market.market_id,
+                expected: market.quote_id,
+                actual: quote_balance.currency_id,
+            });
+        }
+
+        Ok(Self::new(base_balance.available, quote_balance.available))
+    }
+}
+
+#[derive(Debug, ThisError)]
+pub enum HoldingsError {
+    #[error("Base of market {market_id} is currency {expected}, but balance of {actual} is given")]
+    BaseMismatch {
+        market_id: MarketId,
+        expected: CurrencyId,
+        actual: CurrencyId,
+    },
+    #[error(
+        "Quote of market {market_id} is currency {expected}, but balance of {actual} is given"
+    )]
+    QuoteMismatch {
+        market_id: MarketId,
+        expected: CurrencyId,
+        actual: CurrencyId,
+    },
+}
+
+/// How much of a market an order targets
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
+pub enum Sizing {
+    /// Ratio of available balances, corrected by quantity ratios
+    BalanceRatio,
+    /// Fixed notional in `currency`, converted into the quote currency of the market
+    FixedQuote { amount: f64, currency: String },
+}
+
+impl Default for Sizing {
+    fn default() -> Self {
+        Sizing::BalanceRatio
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", try_from = "TradeParameterJson")]
+#[validate(schema(function = "validate_trade_parameter"))]
 pub struct TradeParameter {
     /// Buy order if weighted average of rules is above this
     #[validate(range(min = 0, max = 1.0))]
@@ -44,30 +276,250 @@ pub struct TradeParameter {
     sell_market_allowable_diff_ratio: f64,
     buy_limit_diff_ratio: f64,
     sell_limit_diff_ratio: f64,
+    sizing: Sizing,
+}
+
+/// Fields of `TradeParameter` before validation
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TradeParameterJson {
+    buy_trigger: f64,
+    sell_trigger: f64,
+    buy_quantity_ratio: f64,
+    sell_quantity_ratio: f64,
+    market_ratio: f64,
+    limit_ratio: f64,
+    buy_market_allowable_diff_ratio: f64,
+    sell_market_allowable_diff_ratio: f64,
+    buy_limit_diff_ratio: f64,
+    sell_limit_diff_ratio: f64,
+    #[serde(default)]
+    sizing: Sizing,
+}
+
+impl TryFrom<TradeParameterJson> for TradeParameter {
+    type Error = ValidationErrors;
+
+    fn try_from(json: TradeParameterJson) -> Result<Self, Self::Error> {
+        TradeParameter::new(
+            json.buy_trigger,
+            json.sell_trigger,
+            json.buy_quantity_ratio,
+            json.sell_quantity_ratio,
+            json.market_ratio,
+            json.limit_ratio,
+            json.buy_market_allowable_diff_ratio,
+            json.sell_market_allowable_diff_ratio,
+            json.buy_limit_diff_ratio,
+            json.sell_limit_diff_ratio,
+        )?
+        .with_sizing(json.sizing)
+    }
+}
+
+fn validate_trade_parameter(parameter: &TradeParameter) -> Result<(), ValidationError> {
+    let p = parameter;
+    let all_finite = [
+        p.buy_trigger,
+        p.sell_trigger,
+        p.buy_quantity_ratio,
+        p.sell_quantity_ratio,
+        p.market_ratio,
+        p.limit_ratio,
+        p.buy_market_allowable_diff_ratio,
+        p.sell_market_allowable_diff_ratio,
+        p.buy_limit_diff_ratio,
+        p.sell_limit_diff_ratio,
+    ]
+    .iter()
+    .all(|value| value.is_finite());
+    if !all_finite {
+        return Err(ValidationError::new("Parameters must be finite"));
+    }
+
+    // Quantity is split into market and limit orders by these ratios
+    if p.market_ratio + p.limit_ratio <= 0.0 {
+        return Err(ValidationError::new(
+            "Sum of marketRatio and limitRatio must be positive",
+        ));
+    }
+
+    let diff_ratios_positive = [
+        p.buy_market_allowable_diff_ratio,
+        p.sell_market_allowable_diff_ratio,
+        p.buy_limit_diff_ratio,
+        p.sell_limit_diff_ratio,
+    ]
+    .iter()
+    .all(|ratio| *ratio > 0.0);
+    if !diff_ratios_positive {
+        return Err(ValidationError::new("Diff ratios must be positive"));
+    }
+
+    if let Sizing::FixedQuote { amount, .. } = p.sizing {
+        if !(amount.is_finite() && amount > 0.0) {
+            return Err(ValidationError::new(
+                "Amount of fixedQuote sizing must be positive",
+            ));
+        }
+    }
+
+    Ok(())
 }
 
 impl TradeParameter {
+    /// Orders are sized by `Sizing::BalanceRatio`.
+    /// # Returns
+    /// `Err(e)` if triggers or ratios are out of 0..=1, both of `market_ratio` and `limit_ratio` are 0,
+    /// or any of diff ratios is not positive
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        buy_trigger: f64,
+        sell_trigger: f64,
+        buy_quantity_ratio: f64,
+        sell_quantity_ratio: f64,
+        market_ratio: f64,
+        limit_ratio: f64,
+        buy_market_allowable_diff_ratio: f64,
+        sell_market_allowable_diff_ratio: f64,
+        buy_limit_diff_ratio: f64,
+        sell_limit_diff_ratio: f64,
+    ) -> Result<Self, ValidationErrors> {
+        let parameter = Self {
+            buy_trigger,
+            sell_trigger,
+            buy_quantity_ratio,
+            sell_quantity_ratio,
+            market_ratio,
+            limit_ratio,
+            buy_market_allowable_diff_ratio,
+            sell_market_allowable_diff_ratio,
+            buy_limit_diff_ratio,
+            sell_limit_diff_ratio,
+            sizing: Sizing::BalanceRatio,
+        };
+        parameter.validate()?;
+        Ok(parameter)
+    }
+
+    /// # Returns
+    /// `Err(e)` if amount of `Sizing::FixedQuote` is not positive
+    pub fn with_sizing(self, sizing: Sizing) -> Result<Self, ValidationErrors> {
+        let parameter = Self { sizing, ..self };
+        parameter.validate()?;
+        Ok(parameter)
+    }
+
+    pub fn sizing(&self) -> &Sizing {
+        &self.sizing
+    }
+
+    /// Deserialize JSON from `reader`. Invalid parameters are rejected while deserializing
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        serde_json::from_reader(reader).map_err(Into::into)
+    }
+
+    /// Sum of the ratios is positive by validation, so no division by zero occurs
     fn market_limit_ratio(&self) -> (f64, f64) {
         let sum = self.market_ratio + self.limit_ratio;
         (self.market_ratio / sum, self.limit_ratio / sum)
     }
 }
 
-struct WeightedRule {
+/// Market with its resolved base/quote currencies
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarketInfo {
+    pub market: Market,
+    pub base: Currency,
+    pub quote: Currency,
+}
+
+impl MarketInfo {
+    /// # Panics
+    /// Panics if `base` or `quote` does not match the currencies of `market`
+    pub fn new(market: Market, base: Currency, quote: Currency) -> Self {
+        assert_eq!(market.base_id, base.currency_id);
+        assert_eq!(market.quote_id, quote.currency_id);
+
+        Self {
+            market,
+            base,
+            quote,
+        }
+    }
+
+    /// Return (base symbol, quote symbol) of this market
+    pub fn market_symbols(&self) -> (&str, &str) {
+        (&self.base.symbol, &self.quote.symbol)
+    }
+}
+
+pub struct WeightedRule {
     rule: Box<dyn Rule>,
     weight: f64,
+    /// Hash of the rule's JSON parameters
+    parameter_hash: String,
+}
+
+impl WeightedRule {
+    /// Rules constructed without their JSON parameters have an empty parameter hash.
+    /// # Returns
+    /// `None` if `weight` is negative or not finite
+    pub fn new(rule: Box<dyn Rule>, weight: f64) -> Option<Self> {
+        if weight >= 0.0 && weight.is_finite() {
+            Some(Self {
+                rule,
+                weight,
+                parameter_hash: String::new(),
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn rule(&self) -> &dyn Rule {
+        self.rule.as_ref()
+    }
+
+    pub fn weight(&self) -> f64 {
+        self.weight
+    }
+}
+
+/// Identity of a rule in an aggregation
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleDescription {
+    /// Same as `algorithm` of rule JSON
+    pub name: &'static str,
+    /// Hash of the rule's JSON parameters, which distinguishes rules of the same algorithm
+    pub parameter_hash: String,
+    pub candlestick_interval: Option<Duration>,
+}
+
+/// Hash JSON of `parameter` by FNV-1a, so that the hash is stable between builds
+fn parameter_hash(parameter: &dyn RuleParameter) -> Result<String> {
+    let json = serde_json::to_string(parameter)?;
+    let hash = json.bytes().fold(0xcbf29ce484222325u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+    Ok(format!("{:016x}", hash))
 }
 
 #[derive(Serialize, Deserialize, Validate)]
 #[serde(rename_all = "camelCase")]
 struct RuleComponent {
+    /// Parameters of the rule are placed next to `algorithm`, `weight` and `markets`
+    #[serde(flatten)]
     rule: Box<dyn RuleParameter>,
     #[validate(range(min = 0))]
     weight: f64,
-    #[serde(default)]
+    #[serde(default, alias = "pairs")]
     markets: Vec<String>,
 }
 
+/// Rules and their markets, given by `RULE_JSON`.
+/// Each rule is named by `algorithm` like `fixed`, `rsiCross`, `rsiDivergence` and `orderbookImbalance`.
+/// Rules without `markets` are applied to `defaultMarkets`, as in `testdata/rule_default_markets.json`
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TradeAggregationParameter {
@@ -77,13 +529,34 @@ pub struct TradeAggregationParameter {
 }
 
 impl TradeAggregationParameter {
+    /// Deserialize JSON from `reader`, then validate weights and parameters of all rules
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let parameter: Self = serde_json::from_reader(reader)?;
+        for rule_component in parameter.rules.iter() {
+            rule_component.validate()?;
+            rule_component.rule.validate_parameter()?;
+        }
+        Ok(parameter)
+    }
+
+    /// Read JSON file at `path` like `from_reader`
+    pub fn from_json_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        match std::fs::File::open(path) {
+            Ok(file) => Self::from_reader(file),
+            Err(e) => bail!("Can't open rule JSON {}: {}", path.display(), e),
+        }
+    }
+
+    /// Create aggregations of rules for each market.
+    /// `f` resolves a market like `BTC-USDT`, and `None` fails the whole finalization
     pub fn finalize<F>(
         self,
         trade_parameter: TradeParameter,
         mut f: F,
     ) -> Result<HashMap<MarketId, TradeAggregation>>
     where
-        F: FnMut(&str) -> Option<Market>,
+        F: FnMut(&str) -> Option<MarketInfo>,
     {
         let mut market_map = HashMap::new();
         let mut map = HashMap::new();
@@ -95,15 +568,21 @@ impl TradeAggregationParameter {
                 &rule_component.markets
             };
             for market_str in market_strs.iter() {
-                let market = match f(&market_str) {
-                    Some(market) => market,
+                let market_info = match f(&market_str) {
+                    Some(market_info) => market_info,
                     None => bail!("{} is invalid market", market_str),
                 };
-                market_map.entry(market.market_id).or_insert(market.clone());
+                let market = market_info.market.clone();
+                market_map.entry(market.market_id).or_insert(market_info);
 
                 let rule = rule_component.rule.create_rule(market.clone());
                 let weight = rule_component.weight;
-                let weighted_rule = WeightedRule { rule, weight };
+                let parameter_hash = parameter_hash(rule_component.rule.as_ref())?;
+                let weighted_rule = WeightedRule {
+                    rule,
+                    weight,
+                    parameter_hash,
+                };
                 map.entry(market.market_id)
                     .or_insert(vec![])
                     .push(weighted_rule);
@@ -112,8 +591,9 @@ impl TradeAggregationParameter {
 
         let mut aggregation_map = HashMap::new();
         for (market_id, weighted_rules) in map.into_iter() {
-            let market = market_map[&market_id].clone();
-            let aggregation = TradeAggregation::new(market, trade_parameter, weighted_rules);
+            let market_info = market_map[&market_id].clone();
+            let aggregation =
+                TradeAggregation::new(market_info, trade_parameter.clone(), weighted_rules);
             let ret = aggregation_map.insert(market_id, aggregation);
             assert!(ret.is_none());
         }
@@ -123,16 +603,20 @@ impl TradeAggregationParameter {
 }
 
 pub struct TradeAggregation {
-    market: Market,
+    market_info: MarketInfo,
     parameter: TradeParameter,
     weighted_rules: Vec<WeightedRule>,
     last_market_state: Option<MarketState>,
 }
 
 impl TradeAggregation {
-    fn new(market: Market, parameter: TradeParameter, weighted_rules: Vec<WeightedRule>) -> Self {
+    pub fn new(
+        market_info: MarketInfo,
+        parameter: TradeParameter,
+        weighted_rules: Vec<WeightedRule>,
+    ) -> Self {
         Self {
-            market,
+            market_info,
             parameter,
             weighted_rules,
             last_market_state: None,
@@ -140,7 +624,38 @@ impl TradeAggregation {
     }
 
     pub fn market(&self) -> &Market {
-        &self.market
+        &self.market_info.market
+    }
+
+    pub fn weighted_rules(&self) -> &[WeightedRule] {
+        &self.weighted_rules
+    }
+
+    pub fn market_info(&self) -> &MarketInfo {
+        &self.market_info
+    }
+
+    /// Return (base symbol, quote symbol) of target market
+    pub fn market_symbols(&self) -> (&str, &str) {
+        self.market_info.market_symbols()
+    }
+
+    /// Return `base-quote` label of target market
+    pub fn market_label(&self) -> String {
+        let (base_symbol, quote_symbol) = self.market_symbols();
+        format!("{}-{}", base_symbol, quote_symbol)
+    }
+
+    /// Return descriptions of rules, in the same order as source recommendations of `recommend`
+    pub fn rule_descriptions(&self) -> Vec<RuleDescription> {
+        self.weighted_rules
+            .iter()
+            .map(|weighted_rule| RuleDescription {
+                name: weighted_rule.rule.name(),
+                parameter_hash: weighted_rule.parameter_hash.clone(),
+                candlestick_interval: weighted_rule.rule.candlestick_interval(),
+            })
+            .collect()
     }
 
     pub fn duration_requirement(&self) -> Option<Duration> {
@@ -150,14 +665,76 @@ impl TradeAggregation {
             .max()
     }
 
+    /// Return the largest number of the latest market states required by rules
+    pub fn stamp_count_requirement(&self) -> usize {
+        self.weighted_rules
+            .iter()
+            .map(|weighted_rule| weighted_rule.rule.stamp_count_requirement())
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Return `true` if any rule reads myorders of market states
+    pub fn requires_myorders(&self) -> bool {
+        self.weighted_rules
+            .iter()
+            .any(|weighted_rule| weighted_rule.rule.requires_myorders())
+    }
+
+    pub fn shortest_candlestick_interval(&self) -> Option<Duration> {
+        self.weighted_rules
+            .iter()
+            .flat_map(|weighted_rule| weighted_rule.rule.candlestick_interval())
+            .min()
+    }
+
+    /// Check that candlesticks are built from enough stamps.
+    /// # Returns
+    /// `Err(message)` if the shortest candlestick interval is less than `min_ratio` times `cadence`
+    pub fn check_cadence(&self, cadence: Duration, min_ratio: i32) -> Result<(), String> {
+        match self.shortest_candlestick_interval() {
+            Some(interval) if interval < cadence * min_ratio => {
+                let (base_symbol, quote_symbol) = self.market_symbols();
+                Err(format!(
+                    "[{}-{}] Candlestick interval {} min is less than {} times scraper cadence {} sec",
+                    base_symbol,
+                    quote_symbol,
+                    interval.num_minutes(),
+                    min_ratio,
+                    cadence.num_seconds()
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub fn update_market_state(&mut self, market_state: MarketState) -> Result<(), Vec<RuleError>> {
-        let errors = self
+        self.update_market_state_timed(market_state, &mut Timings::disabled())
+    }
+
+    /// Same as `update_market_state`, but accumulate elapsed time of each rule into `timings`
+    pub fn update_market_state_timed(
+        &mut self,
+        market_state: MarketState,
+        timings: &mut Timings,
+    ) -> Result<(), Vec<RuleError>> {
+        let market_label = self.market_label();
+        let mut errors = vec![];
+        for rule in self
             .weighted_rules
             .iter_mut()
             .map(|weighted_rule| &mut weighted_rule.rule)
-            .map(|rule| rule.update_market_state(market_state.clone()))
-            .filter_map(Result::err)
-            .collect_vec();
+        {
+            let timer = timings.start();
+            let result = rule.update_market_state(market_state.clone());
+            if let Some(elapsed) = timer.elapsed() {
+                timings.record(&market_label, &format!("{}/update", rule.name()), elapsed);
+            }
+
+            if let Err(e) = result {
+                errors.push(e);
+            }
+        }
 
         self.last_market_state = Some(market_state);
 
@@ -169,30 +746,35 @@ impl TradeAggregation {
     }
 
     pub fn recommend(&self) -> AggregatedRecommendation {
-        let (mean, recommendations) = {
-            let mut weight_sum = 0.0;
-            let mut sum = 0.0;
-            let mut recommendations = vec![];
-
-            for WeightedRule { rule, weight } in self.weighted_rules.iter() {
-                let recommendation = rule.recommend();
-                let evaluation = match recommendation.recommendation_type() {
-                    RecommendationType::Buy => Some(1.0),
-                    RecommendationType::Sell => Some(-1.0),
-                    RecommendationType::Pending => Some(0.0),
-                    RecommendationType::Neutral => None,
-                };
+        self.recommend_timed(&mut Timings::disabled())
+    }
 
-                if let Some(evaluation) = evaluation {
-                    weight_sum += weight;
-                    sum += evaluation * weight;
-                }
-                recommendations.push(recommendation);
+    /// Same as `recommend`, but accumulate elapsed time of each rule into `timings`
+    pub fn recommend_timed(&self, timings: &mut Timings) -> AggregatedRecommendation {
+        let market_label = self.market_label();
+        let mut recommendations = vec![];
+        let mut rule_evaluations = vec![];
+
+        for WeightedRule { rule, weight, .. } in self.weighted_rules.iter() {
+            let timer = timings.start();
+            let recommendation = rule.recommend();
+            if let Some(elapsed) = timer.elapsed() {
+                timings.record(
+                    &market_label,
+                    &format!("{}/recommend", rule.name()),
+                    elapsed,
+                );
             }
 
-            let mean = sum / weight_sum;
-            (mean, recommendations)
-        };
+            rule_evaluations.push(RuleEvaluation::new(
+                rule.name(),
+                *weight,
+                recommendation.recommendation_type(),
+            ));
+            recommendations.push(recommendation);
+        }
+
+        let mean = weighted_mean(&rule_evaluations);
 
         let recommendation_type = match mean {
             m if m > self.parameter.buy_trigger => RecommendationType::Buy,
@@ -206,78 +788,297 @@ impl TradeAggregation {
         };
 
         AggregatedRecommendation {
-            parameter: self.parameter,
+            market_info: self.market_info.clone(),
+            parameter: self.parameter.clone(),
             recommendation_type,
             quantity_ratio,
+            mean,
             source_recommendations: recommendations,
+            rule_evaluations,
             last_market_state: self.last_market_state.clone(),
+            reference_rate: None,
+        }
+    }
+}
+
+/// Contribution of a rule to the weighted mean of `TradeAggregation::recommend`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleEvaluation {
+    pub name: &'static str,
+    pub weight: f64,
+    pub recommendation_type: RecommendationType,
+    /// 1 for buy, -1 for sell and 0 for pending. `None` for neutral, which is excluded from the mean
+    pub score: Option<f64>,
+}
+
+impl RuleEvaluation {
+    pub fn new(name: &'static str, weight: f64, recommendation_type: RecommendationType) -> Self {
+        let score = match recommendation_type {
+            RecommendationType::Buy => Some(1.0),
+            RecommendationType::Sell => Some(-1.0),
+            RecommendationType::Pending => Some(0.0),
+            RecommendationType::Neutral => None,
+        };
+        Self {
+            name,
+            weight,
+            recommendation_type,
+            score,
         }
     }
 }
 
+/// Mean of scores weighted by weights of rules. NaN if all rules are neutral
+fn weighted_mean(rule_evaluations: &[RuleEvaluation]) -> f64 {
+    let (sum, weight_sum) = rule_evaluations
+        .iter()
+        .filter_map(|e| e.score.map(|score| (score * e.weight, e.weight)))
+        .fold((0.0, 0.0), |(sum, weight_sum), (s, w)| {
+            (sum + s, weight_sum + w)
+        });
+    sum / weight_sum
+}
+
+/// Breakdown of an aggregated recommendation, printed in explain mode
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Explanation<'a> {
+    pub market: String,
+    pub rules: &'a [RuleEvaluation],
+    pub mean: f64,
+    pub buy_trigger: f64,
+    pub sell_trigger: f64,
+    pub recommendation_type: RecommendationType,
+}
+
 pub struct AggregatedRecommendation {
+    market_info: MarketInfo,
     parameter: TradeParameter,
     recommendation_type: RecommendationType,
     quantity_ratio: f64,
+    /// Weighted mean of scores of rules
+    mean: f64,
     source_recommendations: Vec<Box<dyn Recommendation>>,
+    rule_evaluations: Vec<RuleEvaluation>,
     last_market_state: Option<MarketState>,
+    /// Rate from the reference currency of `Sizing::FixedQuote` to the quote currency of the market
+    reference_rate: Option<f64>,
 }
 
 impl AggregatedRecommendation {
+    pub fn market_info(&self) -> &MarketInfo {
+        &self.market_info
+    }
+
+    /// Return (base symbol, quote symbol) of target market
+    pub fn market_symbols(&self) -> (&str, &str) {
+        self.market_info.market_symbols()
+    }
+
     pub fn recommendation_type(&self) -> RecommendationType {
         self.recommendation_type
     }
 
+    /// Resolve the rate converting the reference currency of `Sizing::FixedQuote` into the quote currency of the market.
+    /// Nothing is resolved for `Sizing::BalanceRatio`
+    pub fn with_exchange_graph(
+        mut self,
+        graph: &ExchangeGraph<CurrencyId>,
+        currencies: &CurrencyCollection,
+    ) -> Self {
+        if let Sizing::FixedQuote { currency, .. } = &self.parameter.sizing {
+            self.reference_rate = currencies.by_symbol(currency).and_then(|reference| {
+                graph.rate_between(reference.currency_id, self.market_info.quote.currency_id)
+            });
+        }
+        self
+    }
+
+    /// Quote amount of `Sizing::FixedQuote` in the quote currency of the market.
+    /// `None` if the rate is not resolved by `with_exchange_graph`
+    fn fixed_quote_amount(&self, amount: f64) -> Option<f64> {
+        match self.reference_rate {
+            Some(rate) => Some(amount * rate),
+            None => {
+                let (base_symbol, quote_symbol) = self.market_symbols();
+                warn!(
+                    "Skip orders of {}-{}, since rate of the sizing currency to {} is unavailable",
+                    base_symbol, quote_symbol, quote_symbol
+                );
+                None
+            }
+        }
+    }
+
+    /// Buy orders spend up to the quote budget including their fees
     pub fn recommend_orders(
         &self,
-        base_balance: &Balance,
-        quote_balance: &Balance,
+        holdings: &Holdings,
+        fees: &FeeSchedule,
     ) -> Vec<OrderRecommendation> {
         let market_state = match &self.last_market_state {
             Some(state) => state,
             None => return vec![],
         };
-        let p = self.parameter;
+        let p = &self.parameter;
         let (market_ratio, limit_ratio) = p.market_limit_ratio();
-        match self.recommendation_type {
+        let mut orders = match self.recommendation_type {
             RecommendationType::Buy => {
-                let quote_quantity = quote_balance.available
-                    * self.quantity_ratio as Amount
-                    * p.buy_quantity_ratio as Amount;
-                let market_quantity = quote_quantity * market_ratio as Amount;
-                let limit_quantity = quote_quantity * limit_ratio as Amount;
-                let market_order = market_buy_order(&self.parameter, market_state, market_quantity);
-                let limit_order = limit_buy_order(&self.parameter, market_state, limit_quantity);
+                let quote_quantity = match &p.sizing {
+                    Sizing::BalanceRatio => {
+                        holdings.quote_available * self.quantity_ratio * p.buy_quantity_ratio
+                    }
+                    Sizing::FixedQuote { amount, .. } => match self.fixed_quote_amount(*amount) {
+                        Some(quote) => quote.min(holdings.quote_available),
+                        None => return vec![],
+                    },
+                };
+                let market_fee = fees.fee_ratio(OrderType::Market);
+                let limit_fee = fees.fee_ratio(OrderType::Limit);
+                let market_quantity = quote_quantity * (market_ratio / (1.0 + market_fee));
+                let limit_quantity = quote_quantity * (limit_ratio / (1.0 + limit_fee));
+                let market_order =
+                    market_buy_order(&self.parameter, market_state, market_quantity, market_fee);
+                let limit_order =
+                    limit_buy_order(&self.parameter, market_state, limit_quantity, limit_fee);
                 vec![market_order, limit_order]
             }
             RecommendationType::Sell => {
-                let base_quantity = base_balance.available
-                    * self.quantity_ratio as Amount
-                    * p.sell_quantity_ratio as Amount;
-                let market_quantity = base_quantity * market_ratio as Amount;
-                let limit_quantity = base_quantity * limit_ratio as Amount;
-                let market_order =
-                    market_sell_order(&self.parameter, market_state, market_quantity);
-                let limit_order = limit_sell_order(&self.parameter, market_state, limit_quantity);
+                let base_quantity = match &p.sizing {
+                    Sizing::BalanceRatio => {
+                        holdings.base_available * self.quantity_ratio * p.sell_quantity_ratio
+                    }
+                    Sizing::FixedQuote { amount, .. } => match self.fixed_quote_amount(*amount) {
+                        Some(quote) => {
+                            (quote / market_state.price.amount).min(holdings.base_available)
+                        }
+                        None => return vec![],
+                    },
+                };
+                let market_quantity = base_quantity * market_ratio;
+                let limit_quantity = base_quantity * limit_ratio;
+                let market_order = market_sell_order(
+                    &self.parameter,
+                    market_state,
+                    market_quantity,
+                    fees.fee_ratio(OrderType::Market),
+                );
+                let limit_order = limit_sell_order(
+                    &self.parameter,
+                    market_state,
+                    limit_quantity,
+                    fees.fee_ratio(OrderType::Limit),
+                );
                 vec![market_order, limit_order]
             }
             RecommendationType::Pending | RecommendationType::Neutral => vec![],
+        };
+        orders.extend(self.protective_stop(market_state, holdings, fees));
+
+        orders
+    }
+
+    /// Sell stop of the whole base balance at the highest stop-loss trigger of source recommendations.
+    /// No stop is recommended along with sells, which already reduce the position
+    fn protective_stop(
+        &self,
+        market_state: &MarketState,
+        holdings: &Holdings,
+        fees: &FeeSchedule,
+    ) -> Option<OrderRecommendation> {
+        if self.recommendation_type == RecommendationType::Sell || holdings.base_available <= 0.0 {
+            return None;
+        }
+        let trigger = self
+            .source_recommendations
+            .iter()
+            .filter_map(|r| r.stop_loss_trigger())
+            .fold(None, |highest: Option<f64>, trigger| {
+                Some(highest.map_or(trigger, |highest| highest.max(trigger)))
+            })?;
+
+        match stop_loss_order(market_state, holdings.base_available, trigger, None, fees) {
+            Ok(order) => Some(order),
+            Err(e) => {
+                let (base_symbol, quote_symbol) = self.market_symbols();
+                debug!("No stop loss of {}-{}: {}", base_symbol, quote_symbol, e);
+                None
+            }
         }
     }
 
+    /// Cancel `open_myorders` on the opposite side of the recommended direction,
+    /// since they would work against the new orders.
+    /// Cancels should be executed before orders of `recommend_orders` are placed.
+    ///
+    /// Open orders are given by the caller, because states of myorders appear only in market states at which they change
+    pub fn recommend_cancels(&self, open_myorders: &[MyOrder]) -> Vec<CancelRecommendation> {
+        let opposite_side = match self.recommendation_type {
+            RecommendationType::Buy => OrderSide::Sell,
+            RecommendationType::Sell => OrderSide::Buy,
+            RecommendationType::Pending | RecommendationType::Neutral => return vec![],
+        };
+        let market_id = self.market_info.market.market_id;
+
+        open_myorders
+            .iter()
+            .filter(|m| m.market_id == market_id)
+            .filter(|m| m.state == OrderState::Opened)
+            .filter(|m| m.side == opposite_side)
+            .map(|m| CancelRecommendation {
+                transaction_id: m.transaction_id.clone(),
+                market_id,
+                side: m.side,
+            })
+            .collect()
+    }
+
     pub fn source_recommendations(&self) -> &[Box<dyn Recommendation>] {
         &self.source_recommendations
     }
+
+    /// Evaluations of rules, in the same order as `source_recommendations`
+    pub fn rule_evaluations(&self) -> &[RuleEvaluation] {
+        &self.rule_evaluations
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Return how rules led to the recommendation type.
+    /// Sell is recommended if the mean is below negative of `sell_trigger`
+    pub fn explain(&self) -> Explanation {
+        let (base_symbol, quote_symbol) = self.market_symbols();
+        Explanation {
+            market: format!("{}-{}", base_symbol, quote_symbol),
+            rules: &self.rule_evaluations,
+            mean: self.mean,
+            buy_trigger: self.parameter.buy_trigger,
+            sell_trigger: self.parameter.sell_trigger,
+            recommendation_type: self.recommendation_type,
+        }
+    }
+
+    /// Return reasons of source recommendations, each prefixed by its market symbol
+    pub fn reasons(&self) -> Vec<String> {
+        let (base_symbol, quote_symbol) = self.market_symbols();
+        self.source_recommendations
+            .iter()
+            .map(|r| format!("[{}-{}] {}", base_symbol, quote_symbol, r.reason()))
+            .collect()
+    }
 }
 
 fn market_buy_order(
     parameter: &TradeParameter,
     market_state: &MarketState,
     quote_quantity: Amount,
+    fee_ratio: f64,
 ) -> OrderRecommendation {
     let price = market_state.price.amount;
-    let base_quantity =
-        quote_quantity / price * parameter.buy_market_allowable_diff_ratio as Amount;
+    let base_quantity = quote_quantity / price * parameter.buy_market_allowable_diff_ratio;
 
     OrderRecommendation {
         side: OrderSide::Buy,
@@ -285,6 +1086,8 @@ fn market_buy_order(
         base_quantity,
         quote_quantity,
         price,
+        trigger_price: None,
+        expected_net_quote: -quote_quantity * (1.0 + fee_ratio),
     }
 }
 
@@ -292,10 +1095,10 @@ fn market_sell_order(
     parameter: &TradeParameter,
     market_state: &MarketState,
     base_quantity: Amount,
+    fee_ratio: f64,
 ) -> OrderRecommendation {
     let price = market_state.price.amount;
-    let quote_quantity =
-        base_quantity * price * parameter.sell_market_allowable_diff_ratio as Amount;
+    let quote_quantity = base_quantity * price * parameter.sell_market_allowable_diff_ratio;
 
     OrderRecommendation {
         side: OrderSide::Sell,
@@ -303,6 +1106,8 @@ fn market_sell_order(
         base_quantity,
         quote_quantity,
         price,
+        trigger_price: None,
+        expected_net_quote: quote_quantity * (1.0 - fee_ratio),
     }
 }
 
@@ -310,16 +1115,19 @@ fn limit_buy_order(
     parameter: &TradeParameter,
     market_state: &MarketState,
     quote_quantity: Amount,
+    fee_ratio: f64,
 ) -> OrderRecommendation {
-    let price = market_state.price.amount * parameter.buy_limit_diff_ratio as Amount;
+    let price = market_state.price.amount * parameter.buy_limit_diff_ratio;
     let base_quantity = quote_quantity / price;
 
     OrderRecommendation {
         side: OrderSide::Buy,
         order_type: OrderType::Limit,
         price,
+        trigger_price: None,
         base_quantity,
         quote_quantity,
+        expected_net_quote: -quote_quantity * (1.0 + fee_ratio),
     }
 }
 
@@ -327,15 +1135,1235 @@ fn limit_sell_order(
     parameter: &TradeParameter,
     market_state: &MarketState,
     base_quantity: Amount,
+    fee_ratio: f64,
 ) -> OrderRecommendation {
-    let price = market_state.price.amount * parameter.sell_limit_diff_ratio as Amount;
+    let price = market_state.price.amount * parameter.sell_limit_diff_ratio;
     let quote_quantity = base_quantity * price;
 
     OrderRecommendation {
         side: OrderSide::Sell,
         order_type: OrderType::Limit,
         price,
+        trigger_price: None,
         base_quantity,
         quote_quantity,
+        expected_net_quote: quote_quantity * (1.0 - fee_ratio),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, ThisError)]
+pub enum StopOrderError {
+    #[error("Trigger {trigger} of a sell stop must be below the current price {price}")]
+    SellTriggerNotBelowPrice { trigger: Amount, price: Amount },
+    #[error("Trigger {trigger} of a buy stop must be above the current price {price}")]
+    BuyTriggerNotAbovePrice { trigger: Amount, price: Amount },
+    #[error("Stop order needs positive quantity and prices. base quantity: {base_quantity}, trigger: {trigger}, limit: {limit:?}")]
+    NonPositive {
+        base_quantity: Amount,
+        trigger: Amount,
+        limit: Option<Amount>,
+    },
+}
+
+/// Stop order of `base_quantity`, placed when the market price reaches `trigger`.
+/// It becomes a limit order at `limit` if given, otherwise a market order expected to fill at `trigger`.
+///
+/// The trigger must be below the current price for sell stops and above it for buy stops,
+/// since the order would be placed immediately otherwise.
+pub fn stop_order(
+    side: OrderSide,
+    market_state: &MarketState,
+    base_quantity: Amount,
+    trigger: Amount,
+    limit: Option<Amount>,
+    fees: &FeeSchedule,
+) -> Result<OrderRecommendation, StopOrderError> {
+    if base_quantity <= 0.0 || trigger <= 0.0 || limit.map_or(false, |limit| limit <= 0.0) {
+        return Err(StopOrderError::NonPositive {
+            base_quantity,
+            trigger,
+            limit,
+        });
+    }
+
+    let current_price = market_state.price.amount;
+    match side {
+        OrderSide::Sell if trigger >= current_price => {
+            return Err(StopOrderError::SellTriggerNotBelowPrice {
+                trigger,
+                price: current_price,
+            })
+        }
+        OrderSide::Buy if trigger <= current_price => {
+            return Err(StopOrderError::BuyTriggerNotAbovePrice {
+                trigger,
+                price: current_price,
+            })
+        }
+        _ => {}
+    }
+
+    let (order_type, price) = match limit {
+        Some(limit) => (OrderType::StopLimit, limit),
+        None => (OrderType::StopMarket, trigger),
+    };
+    let quote_quantity = base_quantity * price;
+    let fee_ratio = fees.fee_ratio(order_type);
+    let expected_net_quote = match side {
+        OrderSide::Buy => -quote_quantity * (1.0 + fee_ratio),
+        OrderSide::Sell => quote_quantity * (1.0 - fee_ratio),
+    };
+
+    Ok(OrderRecommendation {
+        side,
+        order_type,
+        base_quantity,
+        quote_quantity,
+        price,
+        trigger_price: Some(trigger),
+        expected_net_quote,
+    })
+}
+
+/// Sell stop of `base_quantity` limiting loss when the market price falls to `trigger`.
+/// See `stop_order` for `limit`
+pub fn stop_loss_order(
+    market_state: &MarketState,
+    base_quantity: Amount,
+    trigger: Amount,
+    limit: Option<Amount>,
+    fees: &FeeSchedule,
+) -> Result<OrderRecommendation, StopOrderError> {
+    stop_order(
+        OrderSide::Sell,
+        market_state,
+        base_quantity,
+        trigger,
+        limit,
+        fees,
+    )
+}
+
+/// Stop order waiting for its trigger
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeldStopOrder {
+    pub order: OrderRecommendation,
+    /// The order is not triggered after this time
+    pub expiry: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StopOrderUpdate {
+    /// Orders to place, already converted into limit or market orders
+    pub triggered: Vec<OrderRecommendation>,
+    /// Orders still waiting for their trigger
+    pub held: Vec<HeldStopOrder>,
+    pub expired: Vec<HeldStopOrder>,
+}
+
+/// Check `held` stop orders against market `price` at `now`.
+/// An order is still triggered at its expiry, and expires after it
+pub fn trigger_stop_orders(
+    held: Vec<HeldStopOrder>,
+    price: Amount,
+    now: NaiveDateTime,
+) -> StopOrderUpdate {
+    let mut update = StopOrderUpdate::default();
+    for stop in held.into_iter() {
+        if stop.expiry < now {
+            update.expired.push(stop);
+        } else if stop.order.is_triggered(price) {
+            update.triggered.push(stop.order.triggered());
+        } else {
+            update.held.push(stop);
+        }
+    }
+
+    update
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use chrono::NaiveDate;
+
+    struct BuyRule(Market);
+
+    impl Rule for BuyRule {
+        fn name(&self) -> &'static str {
+            "buy"
+        }
+
+        fn market(&self) -> Market {
+            self.0.clone()
+        }
+
+        fn duration_requirement(&self) -> Option<Duration> {
+            None
+        }
+
+        fn update_market_state(&mut self, _: MarketState) -> Result<(), RuleError> {
+            Ok(())
+        }
+
+        fn recommend(&self) -> Box<dyn Recommendation> {
+            Box::from(BuyRecommendation)
+        }
+    }
+
+    struct BuyRecommendation;
+
+    impl Recommendation for BuyRecommendation {
+        fn recommendation_type(&self) -> RecommendationType {
+            RecommendationType::Buy
+        }
+
+        fn reason(&self) -> String {
+            String::from("always buy")
+        }
+    }
+
+    fn trade_parameter() -> TradeParameter {
+        TradeParameter {
+            buy_trigger: 0.5,
+            sell_trigger: 0.5,
+            buy_quantity_ratio: 0.5,
+            sell_quantity_ratio: 0.5,
+            market_ratio: 0.5,
+            limit_ratio: 0.5,
+            buy_market_allowable_diff_ratio: 1.005,
+            sell_market_allowable_diff_ratio: 0.995,
+            buy_limit_diff_ratio: 1.005,
+            sell_limit_diff_ratio: 0.995,
+            sizing: Sizing::BalanceRatio,
+        }
+    }
+
+    #[test]
+    fn test_trade_parameter_new() {
+        let valid = TradeParameter::new(0.5, 0.5, 0.5, 0.5, 0.0, 1.0, 1.005, 0.995, 1.005, 0.995);
+        assert!(valid.is_ok());
+        assert_eq!((0.0, 1.0), valid.unwrap().market_limit_ratio());
+    }
+
+    #[test]
+    fn test_trade_parameter_new_invalid() {
+        let new = |f: fn(&mut TradeParameter)| {
+            let mut p = trade_parameter();
+            f(&mut p);
+            TradeParameter::new(
+                p.buy_trigger,
+                p.sell_trigger,
+                p.buy_quantity_ratio,
+                p.sell_quantity_ratio,
+                p.market_ratio,
+                p.limit_ratio,
+                p.buy_market_allowable_diff_ratio,
+                p.sell_market_allowable_diff_ratio,
+                p.buy_limit_diff_ratio,
+                p.sell_limit_diff_ratio,
+            )
+        };
+
+        assert!(new(|_| {}).is_ok());
+        // Triggers and ratios out of 0..=1
+        assert!(new(|p| p.buy_trigger = 1.1).is_err());
+        assert!(new(|p| p.sell_trigger = -0.1).is_err());
+        assert!(new(|p| p.buy_quantity_ratio = 2.0).is_err());
+        assert!(new(|p| p.sell_quantity_ratio = -1.0).is_err());
+        assert!(new(|p| p.market_ratio = 1.5).is_err());
+        assert!(new(|p| p.limit_ratio = -0.5).is_err());
+        // Quantity can't be split
+        assert!(new(|p| {
+            p.market_ratio = 0.0;
+            p.limit_ratio = 0.0;
+        })
+        .is_err());
+        // Non-positive diff ratios
+        assert!(new(|p| p.buy_market_allowable_diff_ratio = 0.0).is_err());
+        assert!(new(|p| p.sell_market_allowable_diff_ratio = -0.995).is_err());
+        assert!(new(|p| p.buy_limit_diff_ratio = 0.0).is_err());
+        assert!(new(|p| p.sell_limit_diff_ratio = -1.0).is_err());
+        // NaN
+        assert!(new(|p| p.buy_trigger = f64::NAN).is_err());
+        assert!(new(|p| p.buy_limit_diff_ratio = f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_trade_parameter_deserialize() {
+        let json = |market_ratio: f64, limit_ratio: f64| {
+            format!(
+                r#"{{
+                    "buyTrigger": 0.5,
+                    "sellTrigger": 0.5,
+                    "buyQuantityRatio": 0.5,
+                    "sellQuantityRatio": 0.5,
+                    "marketRatio": {},
+                    "limitRatio": {},
+                    "buyMarketAllowableDiffRatio": 1.005,
+                    "sellMarketAllowableDiffRatio": 0.995,
+                    "buyLimitDiffRatio": 1.005,
+                    "sellLimitDiffRatio": 0.995
+                }}"#,
+                market_ratio, limit_ratio
+            )
+        };
+
+        let parameter = TradeParameter::from_reader(json(0.5, 0.5).as_bytes()).unwrap();
+        assert_eq!(trade_parameter(), parameter);
+
+        // Rejected by deserialization itself, not only by from_reader
+        assert!(serde_json::from_str::<TradeParameter>(&json(0.0, 0.0)).is_err());
+        assert!(TradeParameter::from_reader(json(0.0, 0.0).as_bytes()).is_err());
+    }
+
+    fn market_info() -> MarketInfo {
+        let base = Currency::new(CurrencyId::new(1), "DOGE".into(), "Dogecoin".into());
+        let quote = Currency::new(CurrencyId::new(2), "USDT".into(), "Tether".into());
+        let market = Market::new(MarketId::new(3), base.currency_id, quote.currency_id);
+        MarketInfo::new(market, base, quote)
+    }
+
+    #[test]
+    fn test_weighted_rule_new() {
+        let rule = || Box::new(BuyRule(market_info().market));
+
+        let weighted_rule = WeightedRule::new(rule(), 0.5).unwrap();
+        assert_eq!(0.5, weighted_rule.weight());
+        assert_eq!("buy", weighted_rule.rule().name());
+        assert!(WeightedRule::new(rule(), 0.0).is_some());
+
+        assert!(WeightedRule::new(rule(), -0.1).is_none());
+        assert!(WeightedRule::new(rule(), f64::NAN).is_none());
+        assert!(WeightedRule::new(rule(), f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn test_market_symbols() {
+        let market_info = market_info();
+        let rule = BuyRule(market_info.market.clone());
+        let weighted_rules = vec![WeightedRule::new(Box::from(rule), 1.0).unwrap()];
+        let aggregation = TradeAggregation::new(market_info, trade_parameter(), weighted_rules);
+
+        assert_eq!(("DOGE", "USDT"), aggregation.market_symbols());
+
+        let recommendation = aggregation.recommend();
+        assert_eq!(("DOGE", "USDT"), recommendation.market_symbols());
+        assert_eq!(
+            vec![String::from("[DOGE-USDT] always buy")],
+            recommendation.reasons()
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_market_info_mismatch() {
+        let MarketInfo {
+            market,
+            base,
+            quote,
+        } = market_info();
+        let _ = MarketInfo::new(market, quote, base);
+    }
+
+    /// Resolve any `BASE-QUOTE` string, numbering currencies in order of appearance
+    fn resolve_any_market() -> impl FnMut(&str) -> Option<MarketInfo> {
+        let mut currencies: HashMap<String, Currency> = HashMap::new();
+        let mut markets: HashMap<String, MarketInfo> = HashMap::new();
+
+        move |market_str| {
+            // Same market is resolved to the same ID, like the resolver of `nicehash_speculator`
+            if let Some(market_info) = markets.get(market_str) {
+                return Some(market_info.clone());
+            }
+            let (base_symbol, quote_symbol) = market_str.split('-').collect_tuple::<(_, _)>()?;
+            let mut currency = |symbol: &str| {
+                let id = CurrencyId::new(currencies.len() as i32);
+                currencies
+                    .entry(symbol.to_string())
+                    .or_insert_with(|| Currency::new(id, symbol.into(), symbol.into()))
+                    .clone()
+            };
+            let base = currency(base_symbol);
+            let quote = currency(quote_symbol);
+            let market = Market::new(
+                MarketId::new(markets.len() as i32 + 1),
+                base.currency_id,
+                quote.currency_id,
+            );
+            let market_info = MarketInfo::new(market, base, quote);
+            markets.insert(market_str.to_string(), market_info.clone());
+            Some(market_info)
+        }
+    }
+
+    #[test]
+    fn test_trade_parameter_golden() {
+        let json = include_str!("../testdata/trade.json");
+        let parameter = TradeParameter::from_reader(json.as_bytes()).unwrap();
+
+        assert_eq!(trade_parameter(), parameter);
+    }
+
+    #[test]
+    fn test_trade_parameter_invalid() {
+        let json = include_str!("../testdata/trade.json")
+            .replace("\"buyTrigger\": 0.5", "\"buyTrigger\": 1.5");
+
+        assert!(TradeParameter::from_reader(json.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_trade_parameter_sizing() {
+        let json = include_str!("../testdata/trade.json");
+        assert_eq!(
+            &Sizing::BalanceRatio,
+            TradeParameter::from_reader(json.as_bytes())
+                .unwrap()
+                .sizing()
+        );
+
+        let fixed_quote = |amount: &str| {
+            json.replace(
+                "\"sellLimitDiffRatio\": 0.995",
+                &format!(
+                    r#""sellLimitDiffRatio": 0.995, "sizing": {{"fixedQuote": {{"amount": {}, "currency": "BTC"}}}}"#,
+                    amount
+                ),
+            )
+        };
+        let parameter = TradeParameter::from_reader(fixed_quote("0.005").as_bytes()).unwrap();
+        let expected = Sizing::FixedQuote {
+            amount: 0.005,
+            currency: "BTC".into(),
+        };
+        assert_eq!(&expected, parameter.sizing());
+        assert!(TradeParameter::from_reader(fixed_quote("0.0").as_bytes()).is_err());
+        assert!(TradeParameter::from_reader(fixed_quote("-1.0").as_bytes()).is_err());
+
+        assert!(trade_parameter().with_sizing(expected).is_ok());
+    }
+
+    #[test]
+    fn test_trade_aggregation_parameter_golden() {
+        let json = include_str!("../testdata/rule.json");
+        let parameter = TradeAggregationParameter::from_reader(json.as_bytes()).unwrap();
+
+        assert_eq!(3, parameter.rules.len());
+        assert_eq!(
+            vec![0.5, 0.5, 0.0],
+            parameter.rules.iter().map(|r| r.weight).collect_vec()
+        );
+        assert_eq!(15, parameter.rules[0].markets.len());
+        assert!(parameter.rules[2].markets.is_empty());
+
+        let aggregations = parameter
+            .finalize(trade_parameter(), resolve_any_market())
+            .unwrap();
+
+        // The fixed rule has no market, so only the 2 RSI rules are applied to each market
+        assert_eq!(15, aggregations.len());
+        for aggregation in aggregations.values() {
+            assert_eq!(2, aggregation.weighted_rules.len());
+            // Longer RSI: 240 minutes * (14 + 1) candlesticks
+            assert_eq!(
+                Some(Duration::minutes(240 * 15)),
+                aggregation.duration_requirement()
+            );
+        }
+    }
+
+    #[test]
+    fn test_trade_aggregation_parameter_round_trip() {
+        let json = include_str!("../testdata/rule.json");
+        let parameter = TradeAggregationParameter::from_reader(json.as_bytes()).unwrap();
+
+        let serialized = serde_json::to_string(&parameter).unwrap();
+        let restored = TradeAggregationParameter::from_reader(serialized.as_bytes()).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&parameter).unwrap(),
+            serde_json::to_value(&restored).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_trade_aggregation_parameter_default_markets() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/rule_default_markets.json"
+        );
+        let parameter = TradeAggregationParameter::from_json_file(path).unwrap();
+
+        assert_eq!(4, parameter.rules.len());
+        assert_eq!(3, parameter.default_markets.len());
+
+        let serialized = serde_json::to_string(&parameter).unwrap();
+        let restored = TradeAggregationParameter::from_reader(serialized.as_bytes()).unwrap();
+        assert_eq!(
+            serde_json::to_value(&parameter).unwrap(),
+            serde_json::to_value(&restored).unwrap()
+        );
+
+        let aggregations = restored
+            .finalize(trade_parameter(), resolve_any_market())
+            .unwrap();
+
+        // fixed, rsiCross and rsiDivergence for each default market, and rsiCross only for DOGE-USDT
+        assert_eq!(4, aggregations.len());
+        let mut rule_counts = aggregations
+            .values()
+            .map(|aggregation| aggregation.weighted_rules.len())
+            .collect_vec();
+        rule_counts.sort_unstable();
+        assert_eq!(vec![1, 3, 3, 3], rule_counts);
+
+        assert!(TradeAggregationParameter::from_json_file("testdata/missing.json").is_err());
+    }
+
+    #[test]
+    fn test_trade_aggregation_parameter_invalid() {
+        let negative_weight =
+            include_str!("../testdata/rule.json").replace("\"weight\": 0.5", "\"weight\": -0.5");
+        assert!(TradeAggregationParameter::from_reader(negative_weight.as_bytes()).is_err());
+
+        let invalid_trigger = include_str!("../testdata/rule.json")
+            .replace("\"buyTrigger\": 30.0", "\"buyTrigger\": 130.0");
+        assert!(TradeAggregationParameter::from_reader(invalid_trigger.as_bytes()).is_err());
+    }
+
+    /// Recommendation to buy DOGE at 100 USDT
+    fn buy_recommendation() -> AggregatedRecommendation {
+        let market_info = market_info();
+        let market_id = market_info.market.market_id;
+        let rule = BuyRule(market_info.market.clone());
+        let weighted_rules = vec![WeightedRule::new(Box::from(rule), 1.0).unwrap()];
+        let mut aggregation = TradeAggregation::new(market_info, trade_parameter(), weighted_rules);
+
+        let stamp = Stamp::new(
+            StampId::new(1),
+            NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 0, 0),
+        );
+        let price = Price::new(PriceId::new(1), market_id, stamp.stamp_id, 100.0);
+        aggregation
+            .update_market_state(MarketState {
+                stamp,
+                price,
+                orderbooks: vec![],
+                myorders: vec![],
+            })
+            .unwrap();
+
+        aggregation.recommend()
+    }
+
+    /// Rule always recommending the given type
+    struct TypedRule(Market, RecommendationType);
+
+    impl Rule for TypedRule {
+        fn name(&self) -> &'static str {
+            "typed"
+        }
+
+        fn market(&self) -> Market {
+            self.0.clone()
+        }
+
+        fn duration_requirement(&self) -> Option<Duration> {
+            None
+        }
+
+        fn update_market_state(&mut self, _: MarketState) -> Result<(), RuleError> {
+            Ok(())
+        }
+
+        fn recommend(&self) -> Box<dyn Recommendation> {
+            Box::from(TypedRecommendation(self.1))
+        }
+    }
+
+    struct TypedRecommendation(RecommendationType);
+
+    impl Recommendation for TypedRecommendation {
+        fn recommendation_type(&self) -> RecommendationType {
+            self.0
+        }
+
+        fn reason(&self) -> String {
+            format!("always {:?}", self.0)
+        }
+    }
+
+    fn typed_aggregation(rules: &[(RecommendationType, f64)]) -> TradeAggregation {
+        let market_info = market_info();
+        let weighted_rules = rules
+            .iter()
+            .map(|&(recommendation_type, weight)| {
+                let rule = TypedRule(market_info.market.clone(), recommendation_type);
+                WeightedRule::new(Box::from(rule), weight).unwrap()
+            })
+            .collect();
+        TradeAggregation::new(market_info, trade_parameter(), weighted_rules)
+    }
+
+    #[test]
+    fn test_rule_evaluations() {
+        use RecommendationType::*;
+
+        let aggregation = typed_aggregation(&[(Buy, 3.0), (Sell, 1.0)]);
+        let recommendation = aggregation.recommend();
+
+        let expected = vec![
+            RuleEvaluation {
+                name: "typed",
+                weight: 3.0,
+                recommendation_type: Buy,
+                score: Some(1.0),
+            },
+            RuleEvaluation {
+                name: "typed",
+                weight: 1.0,
+                recommendation_type: Sell,
+                score: Some(-1.0),
+            },
+        ];
+        assert_eq!(expected.as_slice(), recommendation.rule_evaluations());
+        // (1 * 3 - 1 * 1) / (3 + 1), which doesn't exceed buy trigger 0.5
+        assert_approx_eq!(0.5, recommendation.mean());
+        assert_eq!(Pending, recommendation.recommendation_type());
+
+        // Neutral rules are excluded from the mean, while pending rules pull it toward 0
+        let aggregation = typed_aggregation(&[(Buy, 3.0), (Neutral, 10.0), (Pending, 1.0)]);
+        let recommendation = aggregation.recommend();
+        assert_eq!(None, recommendation.rule_evaluations()[1].score);
+        assert_approx_eq!(0.75, recommendation.mean());
+        assert_eq!(Buy, recommendation.recommendation_type());
+    }
+
+    #[test]
+    fn test_explain() {
+        use RecommendationType::*;
+
+        let recommendation = typed_aggregation(&[(Sell, 1.0), (Pending, 1.0)]).recommend();
+        let json = serde_json::to_value(recommendation.explain()).unwrap();
+
+        assert_eq!("DOGE-USDT", json["market"]);
+        assert_eq!(-0.5, json["mean"]);
+        assert_eq!(0.5, json["sellTrigger"]);
+        assert_eq!("Pending", json["recommendationType"]);
+        assert_eq!("Sell", json["rules"][0]["recommendationType"]);
+        assert_eq!(0.0, json["rules"][1]["score"]);
+    }
+
+    /// Fill `orders` and return resulting (base, quote) balances
+    fn fill(orders: &[OrderRecommendation], base: Amount, quote: Amount) -> (Amount, Amount) {
+        orders.iter().fold((base, quote), |(base, quote), order| {
+            let (base_diff, quote_diff) = order.balance_diff();
+            (base + base_diff, quote + quote_diff)
+        })
+    }
+
+    #[test]
+    fn test_recommend_orders_maker_taker_fee() {
+        let recommendation = buy_recommendation();
+        let holdings = Holdings::new(0.0, 1000.0);
+
+        let flat = recommendation.recommend_orders(&holdings, &FeeSchedule::flat(0.005));
+        let tiered = recommendation.recommend_orders(&holdings, &FeeSchedule::new(0.001, 0.005));
+
+        // Budget: 1000 * quantity ratio 0.5 * buy quantity ratio 0.5 = 250, split into market and limit.
+        // Expected cost including fee never exceeds the budget
+        for orders in [&flat, &tiered].iter() {
+            assert_eq!(2, orders.len());
+            assert_approx_eq!(-125.0, orders[0].expected_net_quote, 1e-3);
+            assert_approx_eq!(-125.0, orders[1].expected_net_quote, 1e-3);
+        }
+
+        // Market orders pay the same taker fee
+        assert_eq!(flat[0], tiered[0]);
+        // Limit order pays less fee, so buys more
+        assert_approx_eq!(125.0 / 1.005, flat[1].quote_quantity, 1e-3);
+        assert_approx_eq!(125.0 / 1.001, tiered[1].quote_quantity, 1e-3);
+
+        let (flat_base, flat_quote) = fill(&flat, 0.0, 1000.0);
+        let (tiered_base, tiered_quote) = fill(&tiered, 0.0, 1000.0);
+        assert_approx_eq!(flat_quote, tiered_quote, 1e-3);
+        assert!(tiered_base - flat_base > 1e-3);
+    }
+
+    /// Recommendation of `recommendation_type` on DOGE-USDT at 100 USDT, sized by `sizing`
+    fn sized_recommendation(
+        recommendation_type: RecommendationType,
+        sizing: Sizing,
+    ) -> AggregatedRecommendation {
+        let market_info = market_info();
+        let rule = TypedRule(market_info.market.clone(), recommendation_type);
+        let weighted_rules = vec![WeightedRule::new(Box::from(rule), 1.0).unwrap()];
+        let parameter = trade_parameter().with_sizing(sizing).unwrap();
+        let mut aggregation = TradeAggregation::new(market_info, parameter, weighted_rules);
+        aggregation
+            .update_market_state(market_state(100.0))
+            .unwrap();
+        aggregation.recommend()
+    }
+
+    /// 0.005 BTC, which is 200 USDT
+    fn fixed_quote_sizing() -> (Sizing, ExchangeGraph<CurrencyId>, CurrencyCollection) {
+        let btc = Currency::new(CurrencyId::new(4), "BTC".into(), "Bitcoin".into());
+        let market_info = market_info();
+        let graph = ExchangeGraph::from_rates(vec![(
+            btc.currency_id,
+            market_info.quote.currency_id,
+            40000.0,
+        )]);
+        let currencies = CurrencyCollection::new(vec![market_info.base, market_info.quote, btc]);
+        let sizing = Sizing::FixedQuote {
+            amount: 0.005,
+            currency: "BTC".into(),
+        };
+        (sizing, graph, currencies)
+    }
+
+    #[test]
+    fn test_recommend_orders_sizing_buy() {
+        let holdings = Holdings::new(0.0, 1000.0);
+        let fees = FeeSchedule::flat(0.0);
+        let (sizing, graph, currencies) = fixed_quote_sizing();
+
+        // 1000 * quantity ratio 0.5 * buy quantity ratio 0.5 = 250 USDT
+        let orders = sized_recommendation(RecommendationType::Buy, Sizing::BalanceRatio)
+            .with_exchange_graph(&graph, &currencies)
+            .recommend_orders(&holdings, &fees);
+        assert_eq!(2, orders.len());
+        assert_approx_eq!(125.0, orders[0].quote_quantity);
+        assert_approx_eq!(125.0 / 100.0 * 1.005, orders[0].base_quantity);
+        assert_approx_eq!(125.0, orders[1].quote_quantity);
+        assert_approx_eq!(125.0 / 100.5, orders[1].base_quantity);
+
+        // 0.005 BTC * 40000 = 200 USDT regardless of the balance
+        let orders = sized_recommendation(RecommendationType::Buy, sizing)
+            .with_exchange_graph(&graph, &currencies)
+            .recommend_orders(&holdings, &fees);
+        assert_eq!(2, orders.len());
+        assert_approx_eq!(100.0, orders[0].quote_quantity);
+        assert_approx_eq!(100.0 / 100.0 * 1.005, orders[0].base_quantity);
+        assert_approx_eq!(100.0, orders[1].quote_quantity);
+        assert_approx_eq!(100.0 / 100.5, orders[1].base_quantity);
+    }
+
+    #[test]
+    fn test_recommend_orders_sizing_sell() {
+        let holdings = Holdings::new(10.0, 0.0);
+        let fees = FeeSchedule::flat(0.0);
+        let (sizing, graph, currencies) = fixed_quote_sizing();
+
+        // 10 * quantity ratio 0.5 * sell quantity ratio 0.5 = 2.5 DOGE
+        let orders = sized_recommendation(RecommendationType::Sell, Sizing::BalanceRatio)
+            .recommend_orders(&holdings, &fees);
+        assert_eq!(2, orders.len());
+        assert_approx_eq!(1.25, orders[0].base_quantity);
+        assert_approx_eq!(1.25 * 100.0 * 0.995, orders[0].quote_quantity);
+        assert_approx_eq!(1.25, orders[1].base_quantity);
+        assert_approx_eq!(1.25 * 99.5, orders[1].quote_quantity);
+
+        // 200 USDT / 100 = 2 DOGE
+        let orders = sized_recommendation(RecommendationType::Sell, sizing)
+            .with_exchange_graph(&graph, &currencies)
+            .recommend_orders(&holdings, &fees);
+        assert_eq!(2, orders.len());
+        assert_approx_eq!(1.0, orders[0].base_quantity);
+        assert_approx_eq!(99.5, orders[0].quote_quantity);
+        assert_approx_eq!(1.0, orders[1].base_quantity);
+        assert_approx_eq!(99.5, orders[1].quote_quantity);
+
+        // Never sells more than the balance
+        let orders = sized_recommendation(RecommendationType::Sell, fixed_quote_sizing().0)
+            .with_exchange_graph(&graph, &currencies)
+            .recommend_orders(&Holdings::new(1.0, 0.0), &fees);
+        assert_approx_eq!(0.5, orders[0].base_quantity);
+        assert_approx_eq!(0.5, orders[1].base_quantity);
+    }
+
+    #[test]
+    fn test_recommend_orders_sizing_rate_unavailable() {
+        let holdings = Holdings::new(10.0, 1000.0);
+        let fees = FeeSchedule::flat(0.0);
+        let (sizing, _, currencies) = fixed_quote_sizing();
+        let market_info = market_info();
+        let unrelated = ExchangeGraph::from_rates(vec![(
+            market_info.base.currency_id,
+            market_info.quote.currency_id,
+            100.0,
+        )]);
+
+        // Exchange graph is not given
+        let orders = sized_recommendation(RecommendationType::Buy, sizing.clone())
+            .recommend_orders(&holdings, &fees);
+        assert!(orders.is_empty());
+
+        // BTC is not connected to USDT
+        let orders = sized_recommendation(RecommendationType::Sell, sizing)
+            .with_exchange_graph(&unrelated, &currencies)
+            .recommend_orders(&holdings, &fees);
+        assert!(orders.is_empty());
+    }
+
+    fn open_myorder(id: &str, market_id: MarketId, side: OrderSide, state: OrderState) -> MyOrder {
+        MyOrder {
+            myorder_id: MyorderId::new(1),
+            transaction_id: id.into(),
+            market_id,
+            created_stamp_id: StampId::new(1),
+            modified_stamp_id: StampId::new(1),
+            price: 110.0,
+            base_quantity: 1.0,
+            quote_quantity: 110.0,
+            order_type: OrderType::Limit,
+            side,
+            state,
+        }
+    }
+
+    #[test]
+    fn test_recommend_cancels() {
+        let recommendation = buy_recommendation();
+        let market_id = recommendation.market_info().market.market_id;
+        let open_myorders = vec![
+            open_myorder("sell", market_id, OrderSide::Sell, OrderState::Opened),
+            // Same side as the recommendation
+            open_myorder("buy", market_id, OrderSide::Buy, OrderState::Opened),
+            // Already closed
+            open_myorder("filled", market_id, OrderSide::Sell, OrderState::Filled),
+            // Another market
+            open_myorder(
+                "other",
+                MarketId::new(99),
+                OrderSide::Sell,
+                OrderState::Opened,
+            ),
+        ];
+
+        let cancels = recommendation.recommend_cancels(&open_myorders);
+        let expected = vec![CancelRecommendation {
+            transaction_id: "sell".into(),
+            market_id,
+            side: OrderSide::Sell,
+        }];
+        assert_eq!(expected, cancels);
+    }
+
+    #[test]
+    fn test_recommend_cancels_no_open_orders() {
+        let recommendation = buy_recommendation();
+        assert!(recommendation.recommend_cancels(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_holdings_from_balances() {
+        let market = market_info().market;
+        let balance = |currency_id, available| {
+            Balance::new(
+                BalanceId::new(1),
+                CurrencyId::new(currency_id),
+                StampId::new(1),
+                available,
+                0.0,
+            )
+        };
+        let doge = balance(1, 10.0);
+        let usdt = balance(2, 1000.0);
+
+        let holdings = Holdings::from_balances(&doge, &usdt, &market).unwrap();
+        assert_eq!(Holdings::new(10.0, 1000.0), holdings);
+
+        // Swapped balances
+        assert!(matches!(
+            Holdings::from_balances(&usdt, &doge, &market),
+            Err(HoldingsError::BaseMismatch { .. })
+        ));
+        // Balance of another currency
+        assert!(matches!(
+            Holdings::from_balances(&doge, &balance(3, 1000.0), &market),
+            Err(HoldingsError::QuoteMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_balance_diff_sell() {
+        let order = OrderRecommendation {
+            side: OrderSide::Sell,
+            order_type: OrderType::Limit,
+            base_quantity: 2.0,
+            quote_quantity: 200.0,
+            price: 100.0,
+            trigger_price: None,
+            expected_net_quote: 199.8,
+        };
+
+        assert_eq!((-2.0, 199.8), order.balance_diff());
+    }
+
+    #[test]
+    fn test_new_order() {
+        let order = OrderRecommendation {
+            side: OrderSide::Buy,
+            order_type: OrderType::StopLimit,
+            base_quantity: 2.0,
+            quote_quantity: 200.0,
+            price: 100.0,
+            trigger_price: Some(105.0),
+            expected_net_quote: -200.2,
+        };
+
+        assert_eq!(
+            NewOrder {
+                market_id: MarketId::new(3),
+                side: OrderSide::Buy,
+                order_type: OrderType::StopLimit,
+                price: 100.0,
+                base_quantity: 2.0,
+                quote_quantity: 200.0,
+                trigger_price: Some(105.0),
+            },
+            order.new_order(MarketId::new(3))
+        );
+    }
+
+    #[test]
+    fn test_check_cadence() {
+        let json = include_str!("../testdata/rule.json");
+        let aggregations = TradeAggregationParameter::from_reader(json.as_bytes())
+            .unwrap()
+            .finalize(trade_parameter(), resolve_any_market())
+            .unwrap();
+        let aggregation = aggregations.values().next().unwrap();
+
+        // The shortest candlestick is 60 minutes
+        assert_eq!(
+            Some(Duration::minutes(60)),
+            aggregation.shortest_candlestick_interval()
+        );
+        assert!(aggregation.check_cadence(Duration::minutes(20), 3).is_ok());
+        assert!(aggregation.check_cadence(Duration::minutes(21), 3).is_err());
+    }
+
+    #[test]
+    fn test_rule_descriptions() {
+        let json = include_str!("../testdata/rule.json");
+        let aggregations = TradeAggregationParameter::from_reader(json.as_bytes())
+            .unwrap()
+            .finalize(trade_parameter(), resolve_any_market())
+            .unwrap();
+        let aggregation = aggregations.values().next().unwrap();
+
+        let descriptions = aggregation.rule_descriptions();
+        assert_eq!(2, descriptions.len());
+        assert_eq!(
+            descriptions.len(),
+            aggregation.recommend().source_recommendations().len()
+        );
+        // The 2 RSI rules have different parameters
+        assert_ne!(
+            descriptions[0].parameter_hash,
+            descriptions[1].parameter_hash
+        );
+
+        // Hashes are same between markets
+        for other in aggregations.values() {
+            assert_eq!(descriptions, other.rule_descriptions());
+        }
+    }
+
+    fn order(side: OrderSide, base_quantity: Amount, price: Amount) -> OrderRecommendation {
+        let quote_quantity = base_quantity * price;
+        OrderRecommendation {
+            side,
+            order_type: OrderType::Limit,
+            base_quantity,
+            quote_quantity,
+            price,
+            trigger_price: None,
+            expected_net_quote: match side {
+                OrderSide::Buy => -quote_quantity,
+                OrderSide::Sell => quote_quantity,
+            },
+        }
+    }
+
+    #[test]
+    fn test_cap_buy_orders_unchanged() {
+        let orders = vec![order(OrderSide::Buy, 100.0, 0.1)];
+
+        // 10 of 1000 is held, and buying 10 more keeps 2% within 5%
+        let capped = cap_buy_orders(orders.clone(), 10.0, 1000.0, 0.1, 0.05);
+
+        assert_eq!(orders, capped.orders);
+        assert_eq!(None, capped.reason);
+    }
+
+    #[test]
+    fn test_cap_buy_orders_reduced() {
+        let orders = vec![
+            order(OrderSide::Buy, 400.0, 0.1),
+            order(OrderSide::Buy, 400.0, 0.1),
+        ];
+
+        // 30 of 1000 is held, so only 20 of 80 can be bought within 5%
+        let capped = cap_buy_orders(orders, 30.0, 1000.0, 0.1, 0.05);
+
+        assert_eq!(2, capped.orders.len());
+        for order in capped.orders.iter() {
+            assert_approx_eq!(100.0, order.base_quantity);
+            assert_approx_eq!(10.0, order.quote_quantity);
+            assert_approx_eq!(-10.0, order.expected_net_quote);
+        }
+        assert!(capped.reason.unwrap().starts_with("Reduce buy"));
+    }
+
+    #[test]
+    fn test_cap_buy_orders_skipped() {
+        let orders = vec![
+            order(OrderSide::Buy, 100.0, 0.1),
+            order(OrderSide::Sell, 100.0, 0.1),
+        ];
+
+        let capped = cap_buy_orders(orders, 60.0, 1000.0, 0.1, 0.05);
+
+        // Sell orders are kept
+        assert_eq!(vec![order(OrderSide::Sell, 100.0, 0.1)], capped.orders);
+        assert!(capped.reason.unwrap().starts_with("Skip buy"));
+    }
+
+    /// Market state of DOGE-USDT at `price`
+    fn market_state(price: Amount) -> MarketState {
+        let stamp = Stamp::new(
+            StampId::new(1),
+            NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 0, 0),
+        );
+        let price = Price::new(
+            PriceId::new(1),
+            market_info().market.market_id,
+            stamp.stamp_id,
+            price,
+        );
+        MarketState {
+            stamp,
+            price,
+            orderbooks: vec![],
+            myorders: vec![],
+        }
+    }
+
+    #[test]
+    fn test_stop_loss_order() {
+        let fees = FeeSchedule::new(0.001, 0.005);
+        let state = market_state(100.0);
+
+        let order = stop_loss_order(&state, 2.0, 90.0, None, &fees).unwrap();
+        assert_eq!(OrderSide::Sell, order.side);
+        assert_eq!(OrderType::StopMarket, order.order_type);
+        assert_eq!(Some(90.0), order.trigger_price);
+        assert_approx_eq!(180.0 * 0.995, order.expected_net_quote, 1e-3);
+
+        let order = stop_loss_order(&state, 2.0, 90.0, Some(89.0), &fees).unwrap();
+        assert_eq!(OrderType::StopLimit, order.order_type);
+        assert_approx_eq!(89.0, order.price);
+        assert_approx_eq!(178.0 * 0.999, order.expected_net_quote, 1e-3);
+    }
+
+    #[test]
+    fn test_stop_order_trigger_validation() {
+        let fees = FeeSchedule::flat(0.001);
+        let state = market_state(100.0);
+
+        // A sell stop at or above the current price would be placed immediately
+        for &trigger in [100.0, 110.0].iter() {
+            assert_eq!(
+                Err(StopOrderError::SellTriggerNotBelowPrice {
+                    trigger,
+                    price: 100.0
+                }),
+                stop_loss_order(&state, 1.0, trigger, None, &fees)
+            );
+        }
+        for &trigger in [90.0, 100.0].iter() {
+            assert_eq!(
+                Err(StopOrderError::BuyTriggerNotAbovePrice {
+                    trigger,
+                    price: 100.0
+                }),
+                stop_order(OrderSide::Buy, &state, 1.0, trigger, None, &fees)
+            );
+        }
+        assert!(stop_order(OrderSide::Buy, &state, 1.0, 110.0, None, &fees).is_ok());
+
+        assert!(matches!(
+            stop_loss_order(&state, 0.0, 90.0, None, &fees),
+            Err(StopOrderError::NonPositive { .. })
+        ));
+        assert!(matches!(
+            stop_loss_order(&state, 1.0, 90.0, Some(-1.0), &fees),
+            Err(StopOrderError::NonPositive { .. })
+        ));
+    }
+
+    /// Rule holding a position protected by a sell stop at the given trigger
+    struct ProtectingRule(Market, Amount);
+
+    impl Rule for ProtectingRule {
+        fn name(&self) -> &'static str {
+            "protecting"
+        }
+
+        fn market(&self) -> Market {
+            self.0.clone()
+        }
+
+        fn duration_requirement(&self) -> Option<Duration> {
+            None
+        }
+
+        fn update_market_state(&mut self, _: MarketState) -> Result<(), RuleError> {
+            Ok(())
+        }
+
+        fn recommend(&self) -> Box<dyn Recommendation> {
+            Box::from(ProtectingRecommendation(self.1))
+        }
+    }
+
+    struct ProtectingRecommendation(Amount);
+
+    impl Recommendation for ProtectingRecommendation {
+        fn recommendation_type(&self) -> RecommendationType {
+            RecommendationType::Neutral
+        }
+
+        fn reason(&self) -> String {
+            format!("protect at {}", self.0)
+        }
+
+        fn stop_loss_trigger(&self) -> Option<f64> {
+            Some(self.0)
+        }
+    }
+
+    fn protected_recommendation(
+        triggers: &[Amount],
+        recommendation_type: RecommendationType,
+    ) -> AggregatedRecommendation {
+        let market_info = market_info();
+        let mut weighted_rules = triggers
+            .iter()
+            .map(|&trigger| {
+                let rule = ProtectingRule(market_info.market.clone(), trigger);
+                WeightedRule::new(Box::from(rule), 1.0).unwrap()
+            })
+            .collect::<Vec<_>>();
+        let rule = TypedRule(market_info.market.clone(), recommendation_type);
+        weighted_rules.push(WeightedRule::new(Box::from(rule), 1.0).unwrap());
+        let mut aggregation = TradeAggregation::new(market_info, trade_parameter(), weighted_rules);
+        aggregation
+            .update_market_state(market_state(100.0))
+            .unwrap();
+        aggregation.recommend()
+    }
+
+    #[test]
+    fn test_recommend_orders_protective_stop() {
+        let fees = FeeSchedule::flat(0.001);
+        let holdings = Holdings::new(2.0, 1000.0);
+
+        // The highest trigger protects the whole base balance
+        let orders = protected_recommendation(&[85.0, 90.0], RecommendationType::Neutral)
+            .recommend_orders(&holdings, &fees);
+        assert_eq!(
+            vec![stop_loss_order(&market_state(100.0), 2.0, 90.0, None, &fees).unwrap()],
+            orders
+        );
+
+        // Along with buys
+        let orders = protected_recommendation(&[90.0], RecommendationType::Buy)
+            .recommend_orders(&holdings, &fees);
+        assert_eq!(3, orders.len());
+        assert!(orders[2].is_stop());
+
+        // Not along with sells, nor without position or above the price
+        let orders = protected_recommendation(&[90.0], RecommendationType::Sell)
+            .recommend_orders(&holdings, &fees);
+        assert!(orders.iter().all(|order| !order.is_stop()));
+        let orders = protected_recommendation(&[90.0], RecommendationType::Neutral)
+            .recommend_orders(&Holdings::new(0.0, 1000.0), &fees);
+        assert!(orders.is_empty());
+        let orders = protected_recommendation(&[110.0], RecommendationType::Neutral)
+            .recommend_orders(&holdings, &fees);
+        assert!(orders.is_empty());
+    }
+
+    #[test]
+    fn test_trigger_stop_orders_at_boundary() {
+        let fees = FeeSchedule::flat(0.001);
+        let state = market_state(100.0);
+        let expiry = NaiveDate::from_ymd(2021, 1, 2).and_hms(0, 0, 0);
+        let now = NaiveDate::from_ymd(2021, 1, 1).and_hms(12, 0, 0);
+        let held = vec![
+            HeldStopOrder {
+                order: stop_loss_order(&state, 1.0, 90.0, None, &fees).unwrap(),
+                expiry,
+            },
+            HeldStopOrder {
+                order: stop_order(OrderSide::Buy, &state, 1.0, 110.0, Some(111.0), &fees).unwrap(),
+                expiry,
+            },
+        ];
+
+        // Neither is crossed yet
+        let update = trigger_stop_orders(held.clone(), 90.01, now);
+        assert!(update.triggered.is_empty());
+        assert_eq!(held, update.held);
+
+        // Exactly at the sell trigger
+        let update = trigger_stop_orders(held.clone(), 90.0, now);
+        assert_eq!(1, update.triggered.len());
+        assert_eq!(OrderType::Market, update.triggered[0].order_type);
+        assert_eq!(None, update.triggered[0].trigger_price);
+        assert_eq!(vec![held[1].clone()], update.held);
+
+        // Exactly at the buy trigger
+        let update = trigger_stop_orders(held.clone(), 110.0, now);
+        assert_eq!(1, update.triggered.len());
+        assert_eq!(OrderType::Limit, update.triggered[0].order_type);
+        assert_approx_eq!(111.0, update.triggered[0].price);
+        assert_eq!(vec![held[0].clone()], update.held);
+
+        // Still triggered at the expiry
+        let update = trigger_stop_orders(held, 90.0, expiry);
+        assert_eq!(1, update.triggered.len());
+        assert!(update.expired.is_empty());
+    }
+
+    #[test]
+    fn test_trigger_stop_orders_expire_untriggered() {
+        let state = market_state(100.0);
+        let order = stop_loss_order(&state, 1.0, 90.0, None, &FeeSchedule::flat(0.001)).unwrap();
+        let expiry = NaiveDate::from_ymd(2021, 1, 1).and_hms(3, 0, 0);
+        let mut held = vec![HeldStopOrder { order, expiry }];
+
+        // Prices stay above the trigger until the expiry
+        for (hour, &price) in [95.0, 93.0, 91.0, 90.5].iter().enumerate() {
+            let now = NaiveDate::from_ymd(2021, 1, 1).and_hms(hour as u32, 0, 0);
+            let update = trigger_stop_orders(held, price, now);
+            assert!(update.triggered.is_empty());
+            assert!(update.expired.is_empty());
+            held = update.held;
+        }
+
+        // Crossing after the expiry doesn't trigger
+        let now = NaiveDate::from_ymd(2021, 1, 1).and_hms(3, 0, 1);
+        let update = trigger_stop_orders(held, 80.0, now);
+        assert!(update.triggered.is_empty());
+        assert!(update.held.is_empty());
+        assert_eq!(1, update.expired.len());
     }
 }